@@ -0,0 +1,38 @@
+//! Images shared by the benchmarks: generated ones and small reference files checked in
+//! under `tests/`.
+
+// Every benchmark compiles this module but uses only part of it.
+#![allow(dead_code)]
+
+use std::path::Path;
+
+/// Side of the generated square images.
+pub const SIZE: u32 = 512;
+
+/// A `width` x `height` image of `components` interleaved 8-bit samples mixing smooth
+/// gradients with noisy areas, so that both the flat and the detailed paths of the
+/// coders are exercised.
+pub fn test_pattern(width: u32, height: u32, components: u32) -> Vec<u8> {
+    let mut seed = 0x1234_5678u32;
+    let mut samples = Vec::with_capacity((width * height * components) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            for c in 0..components {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                let value = if (x / 16 + y / 16 + c) % 4 == 0 {
+                    (seed >> 16) & 0xFF
+                } else {
+                    (x + 2 * y + 40 * c) & 0xFF
+                };
+                samples.push(value as u8);
+            }
+        }
+    }
+    samples
+}
+
+/// Reads a reference file, relative to the crate root.
+pub fn reference(path: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(path);
+    std::fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
@@ -0,0 +1,181 @@
+//! Compares the row-lifting DWT against the per-sample baseline implementation, and the
+//! batched inverse DWT against one `inverse_2d` call per band.
+//!
+//! Run with `cargo bench --bench dwt`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use jpegexp_rs::jpeg2000::dwt::{inverse_2d_batch, Dwt53, Dwt97, DwtBand, WaveletFilter};
+use std::hint::black_box;
+
+const SIZES: [usize; 3] = [64, 256, 1024];
+
+struct Subbands<T> {
+    ll: Vec<T>,
+    hl: Vec<T>,
+    lh: Vec<T>,
+    hh: Vec<T>,
+}
+
+fn subbands<T: Copy>(size: usize, convert: impl Fn(i32) -> T) -> Subbands<T> {
+    let half = size / 2;
+    let band = |seed: i32| -> Vec<T> {
+        (0..half * half)
+            .map(|i| convert(((i as i32).wrapping_mul(37) ^ seed) % 255 - 127))
+            .collect()
+    };
+    Subbands {
+        ll: band(1),
+        hl: band(2),
+        lh: band(3),
+        hh: band(4),
+    }
+}
+
+fn bench_inverse_2d_53(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dwt53_inverse_2d");
+    for size in SIZES {
+        let bands = subbands(size, |v| v);
+        let mut output = vec![0i32; size * size];
+        let dim = size as u32;
+        group.bench_with_input(BenchmarkId::new("baseline", size), &size, |b, _| {
+            b.iter(|| {
+                Dwt53::inverse_2d_baseline(
+                    black_box(&bands.ll),
+                    &bands.hl,
+                    &bands.lh,
+                    &bands.hh,
+                    dim,
+                    dim,
+                    &mut output,
+                )
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("lifting", size), &size, |b, _| {
+            b.iter(|| {
+                Dwt53::inverse_2d(
+                    black_box(&bands.ll),
+                    &bands.hl,
+                    &bands.lh,
+                    &bands.hh,
+                    dim,
+                    dim,
+                    &mut output,
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_inverse_2d_97(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dwt97_inverse_2d");
+    for size in SIZES {
+        let bands = subbands(size, |v| v as f32);
+        let mut output = vec![0.0f32; size * size];
+        let dim = size as u32;
+        group.bench_with_input(BenchmarkId::new("baseline", size), &size, |b, _| {
+            b.iter(|| {
+                Dwt97::inverse_2d_baseline(
+                    black_box(&bands.ll),
+                    &bands.hl,
+                    &bands.lh,
+                    &bands.hh,
+                    dim,
+                    dim,
+                    &mut output,
+                )
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("lifting", size), &size, |b, _| {
+            b.iter(|| {
+                Dwt97::inverse_2d(
+                    black_box(&bands.ll),
+                    &bands.hl,
+                    &bands.lh,
+                    &bands.hh,
+                    dim,
+                    dim,
+                    &mut output,
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_inverse_2d_batch(c: &mut Criterion) {
+    // 64 bands of 256x256, as the code-block-sized tiles of a slide region.
+    const BANDS: usize = 64;
+    const SIZE: usize = 256;
+    let bands: Vec<DwtBand> = (0..BANDS)
+        .map(|i| DwtBand {
+            offset: i * SIZE * SIZE,
+            width: SIZE as u32,
+            height: SIZE as u32,
+        })
+        .collect();
+    let coefficients: Vec<f32> = (0..BANDS * SIZE * SIZE)
+        .map(|i| ((i as i32).wrapping_mul(37) % 255 - 127) as f32)
+        .collect();
+    let quadrants = subbands(SIZE, |v| v as f32);
+    let mut output = vec![0.0f32; SIZE * SIZE];
+    let mut data = coefficients.clone();
+
+    let mut group = c.benchmark_group("dwt97_inverse_2d_batch");
+    group.bench_function("per_band", |b| {
+        b.iter(|| {
+            for _ in 0..BANDS {
+                Dwt97::inverse_2d(
+                    black_box(&quadrants.ll),
+                    &quadrants.hl,
+                    &quadrants.lh,
+                    &quadrants.hh,
+                    SIZE as u32,
+                    SIZE as u32,
+                    &mut output,
+                )
+            }
+        })
+    });
+    group.bench_function("batch", |b| {
+        b.iter(|| {
+            data.copy_from_slice(&coefficients);
+            inverse_2d_batch(WaveletFilter::Irreversible97, black_box(&mut data), &bands).unwrap()
+        })
+    });
+    group.finish();
+}
+
+fn bench_forward_1d(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dwt_forward_1d");
+    let len = 4096;
+    let signal: Vec<i32> = (0..len as i32).map(|i| (i * 31) % 255).collect();
+    let signal_f32: Vec<f32> = signal.iter().map(|&v| v as f32).collect();
+    let mut low = vec![0i32; len / 2];
+    let mut high = vec![0i32; len / 2];
+    let mut low_f32 = vec![0.0f32; len / 2];
+    let mut high_f32 = vec![0.0f32; len / 2];
+
+    group.bench_function("53/baseline", |b| {
+        b.iter(|| Dwt53::forward_baseline(black_box(&signal), &mut low, &mut high))
+    });
+    group.bench_function("53/lifting", |b| {
+        b.iter(|| Dwt53::forward(black_box(&signal), &mut low, &mut high))
+    });
+    group.bench_function("97/baseline", |b| {
+        b.iter(|| Dwt97::forward_baseline(black_box(&signal_f32), &mut low_f32, &mut high_f32))
+    });
+    group.bench_function("97/lifting", |b| {
+        b.iter(|| Dwt97::forward(black_box(&signal_f32), &mut low_f32, &mut high_f32))
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_inverse_2d_53,
+    bench_inverse_2d_97,
+    bench_inverse_2d_batch,
+    bench_forward_1d
+);
+criterion_main!(benches);
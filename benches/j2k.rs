@@ -0,0 +1,109 @@
+//! JPEG 2000 tier-1 (MQ bit-plane coding of code-blocks) on generated code-blocks, and
+//! whole decodes of small checked-in reference codestreams.
+//!
+//! Run with `cargo bench --bench j2k`.
+
+mod common;
+
+use common::reference;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use jpegexp_rs::jpeg2000::bit_plane_coder::BitPlaneCoder;
+use jpegexp_rs::jpeg2000::decoder::J2kDecoder;
+use jpegexp_rs::jpeg_stream_reader::JpegStreamReader;
+use std::hint::black_box;
+
+/// Code-block sides: 32x32 and the usual 64x64.
+const CODE_BLOCK_SIZES: [u32; 2] = [32, 64];
+/// Magnitude bit-planes of the generated coefficients.
+const BIT_PLANES: u8 = 8;
+
+/// Coefficients of a code-block as after quantization: mostly small magnitudes, with
+/// a few large ones and about half of them zero.
+fn coefficients(size: u32) -> Vec<i32> {
+    let mut seed = 0x2545_f491u32;
+    (0..size * size)
+        .map(|_| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            let magnitude = ((seed >> 16) & 0xFF) >> ((seed >> 8) & 7);
+            let value = if seed & 1 == 0 { 0 } else { magnitude as i32 };
+            if seed & 2 == 0 {
+                -value
+            } else {
+                value
+            }
+        })
+        .collect()
+}
+
+/// Codes every bit-plane of `data` from the most significant one, as the encoder does.
+fn encode_codeblock(size: u32, data: &[i32]) -> Vec<u8> {
+    let mut coder = BitPlaneCoder::new(size, size, data);
+    for bit_plane in (0..BIT_PLANES).rev() {
+        coder.significance_propagation(bit_plane);
+        coder.magnitude_refinement(bit_plane);
+        coder.cleanup(bit_plane);
+    }
+    coder.mq.flush();
+    coder.mq.get_buffer().to_vec()
+}
+
+fn bench_tier1(c: &mut Criterion) {
+    let mut group = c.benchmark_group("j2k_tier1");
+    for size in CODE_BLOCK_SIZES {
+        let data = coefficients(size);
+        group.throughput(Throughput::Elements((size * size) as u64));
+        group.bench_with_input(BenchmarkId::new("encode", size), &data, |b, data| {
+            b.iter(|| encode_codeblock(size, black_box(data)))
+        });
+
+        // Cleanup pass of the top bit-plane, then three passes for every other one.
+        let encoded = encode_codeblock(size, &data);
+        let passes = 3 * BIT_PLANES - 2;
+        group.bench_with_input(BenchmarkId::new("decode", size), &encoded, |b, encoded| {
+            b.iter(|| {
+                let mut coder = BitPlaneCoder::new(size, size, &[]);
+                // Orientation 0: the LL subband.
+                coder.decode_codeblock(black_box(encoded), BIT_PLANES - 1, passes, 0)
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("j2k_decode");
+    let images = [
+        (
+            "gradient_64x64_lossless",
+            "tests/jpegls_test_images/gradient_64x64_gray_lossless.j2c",
+        ),
+        (
+            "gradient_64x64_lossy",
+            "tests/jpegls_test_images/gradient_64x64_gray_lossy.j2c",
+        ),
+        (
+            "profile_cinema2k",
+            "tests/test_images/JPEG2000/profile-cinema2k.j2c",
+        ),
+        // Four tiles and several thousand code-blocks, where per-block allocations show.
+        (
+            "tiled_256x256",
+            "tests/test_images/JPEG2000/oj-rgn-tilepart-header-1.jp2",
+        ),
+    ];
+    for (name, path) in images {
+        let encoded = reference(path);
+        group.throughput(Throughput::Bytes(encoded.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &encoded, |b, encoded| {
+            b.iter(|| {
+                let mut reader = JpegStreamReader::new(black_box(encoded));
+                let mut decoder = J2kDecoder::new(&mut reader);
+                decoder.decode().unwrap().reconstruct_pixels().unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_tier1, bench_decode);
+criterion_main!(benches);
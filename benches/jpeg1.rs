@@ -0,0 +1,140 @@
+//! Baseline JPEG decode throughput on a checked-in reference image and on generated
+//! images encoded by `Jpeg1Encoder`, and the 8x8 DCT kernels on their own.
+//!
+//! Run with `cargo bench --bench jpeg1`. The `jpeg1_decode_restart` group decodes an
+//! image with restart markers on one thread and on all available threads. The
+//! `jpeg1_fdct` and `jpeg1_idct` groups compare the kernel dispatched on this CPU with
+//! the scalar separable one and the direct evaluation of the definition; `jpeg1_idct`
+//! also has the fixed-point transform and the scaled transforms of `set_output_scaling`.
+
+mod common;
+
+use common::{reference, test_pattern, SIZE};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use jpegexp_rs::jpeg1::dct::{
+    fdct_8x8, fdct_8x8_baseline, fdct_8x8_scalar, idct_8x8, idct_8x8_baseline,
+    idct_8x8_fixed_point, idct_8x8_scalar, idct_scaled,
+};
+use jpegexp_rs::jpeg1::decoder::Jpeg1Decoder;
+use jpegexp_rs::jpeg1::encoder::Jpeg1Encoder;
+use jpegexp_rs::jpeg_stream_reader::JpegStreamReader;
+use jpegexp_rs::FrameInfo;
+use std::hint::black_box;
+
+fn generated(components: u32, restart_interval: u16) -> Vec<u8> {
+    let source = test_pattern(SIZE, SIZE, components);
+    let frame_info = FrameInfo {
+        width: SIZE,
+        height: SIZE,
+        bits_per_sample: 8,
+        component_count: components as i32,
+    };
+    let mut encoder = Jpeg1Encoder::new();
+    encoder.set_quality(85);
+    encoder.set_restart_interval(restart_interval);
+    let mut encoded = vec![0u8; 1024 + source.len() * 2];
+    let length = encoder.encode(&source, &frame_info, &mut encoded).unwrap();
+    encoded.truncate(length);
+    encoded
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("jpeg1_decode");
+    let images = [
+        (
+            "grad_pillow",
+            reference("tests/test_images/grad_pillow.jpg"),
+        ),
+        ("gray_q85", generated(1, 0)),
+        ("rgb_q85", generated(3, 0)),
+    ];
+    for (name, encoded) in images {
+        let mut reader = JpegStreamReader::new(&encoded);
+        reader.read_header(&mut None).unwrap();
+        let info = reader.frame_info();
+        let mut decoded =
+            vec![0u8; (info.width * info.height) as usize * info.component_count as usize];
+        group.throughput(Throughput::Bytes(decoded.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &encoded, |b, encoded| {
+            b.iter(|| {
+                let mut decoder = Jpeg1Decoder::new(black_box(encoded));
+                decoder.read_header().unwrap();
+                decoder.decode(&mut decoded).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_decode_restart(c: &mut Criterion) {
+    let mut group = c.benchmark_group("jpeg1_decode_restart");
+    let encoded = generated(3, 64);
+    let mut decoded = vec![0u8; (SIZE * SIZE * 3) as usize];
+    group.throughput(Throughput::Bytes(decoded.len() as u64));
+    for (name, thread_count) in [("1_thread", 1), ("all_threads", 0)] {
+        group.bench_with_input(BenchmarkId::from_parameter(name), &encoded, |b, encoded| {
+            b.iter(|| {
+                let mut decoder = Jpeg1Decoder::new(black_box(encoded));
+                decoder.set_thread_count(thread_count);
+                decoder.read_header().unwrap();
+                decoder.decode(&mut decoded).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+type Transform = fn(&[f32; 64], &mut [f32; 64]);
+
+fn pixel_block() -> [f32; 64] {
+    std::array::from_fn(|i| ((i * 37 + (i / 8) * 11) % 255) as f32 - 128.0)
+}
+
+fn bench_fdct(c: &mut Criterion) {
+    let mut group = c.benchmark_group("jpeg1_fdct");
+    let pixels = pixel_block();
+    let kernels: [(&str, Transform); 3] = [
+        ("baseline", fdct_8x8_baseline),
+        ("scalar", fdct_8x8_scalar),
+        ("simd", fdct_8x8),
+    ];
+    for (name, fdct) in kernels {
+        let mut output = [0.0f32; 64];
+        group.bench_function(name, |b| b.iter(|| fdct(black_box(&pixels), &mut output)));
+    }
+    group.finish();
+}
+
+fn bench_idct(c: &mut Criterion) {
+    let mut group = c.benchmark_group("jpeg1_idct");
+    let mut coefficients = [0.0f32; 64];
+    fdct_8x8_baseline(&pixel_block(), &mut coefficients);
+    let kernels: [(&str, Transform); 4] = [
+        ("baseline", idct_8x8_baseline),
+        ("fixed_point", idct_8x8_fixed_point),
+        ("scalar", idct_8x8_scalar),
+        ("simd", idct_8x8),
+    ];
+    for (name, idct) in kernels {
+        let mut output = [0.0f32; 64];
+        group.bench_function(name, |b| {
+            b.iter(|| idct(black_box(&coefficients), &mut output))
+        });
+    }
+    for size in [4, 2, 1] {
+        let mut output = [0.0f32; 16];
+        group.bench_with_input(BenchmarkId::new("scaled", size), &size, |b, &size| {
+            b.iter(|| idct_scaled(black_box(&coefficients), size, &mut output))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_decode,
+    bench_decode_restart,
+    bench_fdct,
+    bench_idct
+);
+criterion_main!(benches);
@@ -0,0 +1,133 @@
+//! JPEG-LS encode and decode throughput on generated images, lossless and near-lossless.
+//!
+//! Run with `cargo bench --bench jpegls`. The `jpegls_encode_stripes` group encodes a
+//! 16-bit frame in restart-interval stripes on one thread and on all available threads.
+
+mod common;
+
+use common::{test_pattern, SIZE};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use jpegexp_rs::jpegls::{InterleaveMode, JpeglsDecoder, JpeglsEncoder, JpeglsEncoderBuilder};
+use jpegexp_rs::FrameInfo;
+use std::hint::black_box;
+
+/// (name, components, interleave mode, NEAR, bits per sample)
+const CASES: [(&str, u32, InterleaveMode, i32, i32); 5] = [
+    ("gray", 1, InterleaveMode::None, 0, 8),
+    ("gray_near2", 1, InterleaveMode::None, 2, 8),
+    ("rgb_line", 3, InterleaveMode::Line, 0, 8),
+    ("rgb_sample", 3, InterleaveMode::Sample, 0, 8),
+    ("gray12", 1, InterleaveMode::None, 0, 12),
+];
+
+fn frame_info(components: u32, bits_per_sample: i32) -> FrameInfo {
+    FrameInfo {
+        width: SIZE,
+        height: SIZE,
+        bits_per_sample,
+        component_count: components as i32,
+    }
+}
+
+/// The test pattern, scaled up to `bits_per_sample` and stored as native-endian 16-bit
+/// samples past 8 bits.
+fn source(components: u32, bits_per_sample: i32) -> Vec<u8> {
+    let samples = test_pattern(SIZE, SIZE, components);
+    if bits_per_sample <= 8 {
+        return samples;
+    }
+    samples
+        .iter()
+        .flat_map(|&s| ((s as u16) << (bits_per_sample - 8)).to_ne_bytes())
+        .collect()
+}
+
+fn encode(
+    source: &[u8],
+    components: u32,
+    interleave_mode: InterleaveMode,
+    near_lossless: i32,
+    bits_per_sample: i32,
+    destination: &mut [u8],
+) -> usize {
+    let mut encoder = JpeglsEncoder::new(destination);
+    encoder
+        .set_frame_info(frame_info(components, bits_per_sample))
+        .unwrap();
+    encoder.set_interleave_mode(interleave_mode).unwrap();
+    encoder.set_near_lossless(near_lossless).unwrap();
+    encoder.encode(source).unwrap()
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("jpegls_encode");
+    for (name, components, interleave_mode, near_lossless, bits_per_sample) in CASES {
+        let source = source(components, bits_per_sample);
+        let mut destination = vec![0u8; 1024 + source.len() * 2];
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &source, |b, source| {
+            b.iter(|| {
+                encode(
+                    black_box(source),
+                    components,
+                    interleave_mode,
+                    near_lossless,
+                    bits_per_sample,
+                    &mut destination,
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("jpegls_decode");
+    for (name, components, interleave_mode, near_lossless, bits_per_sample) in CASES {
+        let source = source(components, bits_per_sample);
+        let mut encoded = vec![0u8; 1024 + source.len() * 2];
+        let length = encode(
+            &source,
+            components,
+            interleave_mode,
+            near_lossless,
+            bits_per_sample,
+            &mut encoded,
+        );
+        encoded.truncate(length);
+        let mut decoded = vec![0u8; source.len()];
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &encoded, |b, encoded| {
+            b.iter(|| {
+                let mut decoder = JpeglsDecoder::new(black_box(encoded));
+                decoder.read_header().unwrap();
+                decoder.decode(&mut decoded).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_encode_stripes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("jpegls_encode_stripes");
+    let source = source(1, 16);
+    let builder = JpeglsEncoderBuilder::new(frame_info(1, 16)).restart_interval(32);
+    let mut destination = vec![0u8; builder.estimated_destination_size()];
+    group.throughput(Throughput::Bytes(source.len() as u64));
+    for (name, thread_count) in [("1_thread", 1), ("all_threads", 0)] {
+        let builder = builder.thread_count(thread_count);
+        group.bench_with_input(BenchmarkId::from_parameter(name), &source, |b, source| {
+            b.iter(|| {
+                builder
+                    .build(&mut destination)
+                    .unwrap()
+                    .encode(black_box(source))
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_encode, bench_decode, bench_encode_stripes);
+criterion_main!(benches);
@@ -0,0 +1,83 @@
+//! Build script: regenerates the C header `include/jpegexp.h` from the FFI module and the
+//! CMake package version file `cmake/jpegexpConfigVersion.cmake` when the `ffi` feature is
+//! enabled.
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "ffi")]
+    {
+        generate_c_header();
+        generate_cmake_version();
+    }
+}
+
+#[cfg(feature = "ffi")]
+fn generate_c_header() {
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR is set");
+    let crate_dir = std::path::Path::new(&crate_dir);
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml"))
+        .expect("cbindgen.toml is valid");
+    cbindgen::Builder::new()
+        .with_crate(crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("C header generation failed")
+        .write_to_file(crate_dir.join("include").join("jpegexp.h"));
+}
+
+/// Writes the version file of the CMake package, so `find_package(jpegexp <version>)`
+/// follows Cargo's compatibility rules: the same major version, and the same minor version
+/// while the major version is 0. It also rejects a consumer of a different pointer width.
+#[cfg(feature = "ffi")]
+fn generate_cmake_version() {
+    println!("cargo:rerun-if-changed=Cargo.toml");
+
+    let env = |name: &str| std::env::var(name).unwrap_or_else(|_| panic!("{name} is set"));
+    let version = env("CARGO_PKG_VERSION");
+    let major = env("CARGO_PKG_VERSION_MAJOR");
+    let minor = env("CARGO_PKG_VERSION_MINOR");
+    let compatible = if major == "0" {
+        format!("(PACKAGE_FIND_VERSION_MAJOR EQUAL 0 AND PACKAGE_FIND_VERSION_MINOR EQUAL {minor})")
+    } else {
+        format!("PACKAGE_FIND_VERSION_MAJOR EQUAL {major}")
+    };
+    let pointer_width = env("CARGO_CFG_TARGET_POINTER_WIDTH");
+    let pointer_bytes = pointer_width
+        .parse::<u32>()
+        .expect("pointer width is a number")
+        / 8;
+
+    let contents = format!(
+        r#"# Generated by build.rs from Cargo.toml; do not edit.
+
+set(PACKAGE_VERSION "{version}")
+
+if(PACKAGE_FIND_VERSION AND PACKAGE_FIND_VERSION VERSION_GREATER PACKAGE_VERSION)
+  set(PACKAGE_VERSION_COMPATIBLE FALSE)
+elseif(NOT PACKAGE_FIND_VERSION OR {compatible})
+  set(PACKAGE_VERSION_COMPATIBLE TRUE)
+  if(PACKAGE_FIND_VERSION STREQUAL PACKAGE_VERSION)
+    set(PACKAGE_VERSION_EXACT TRUE)
+  endif()
+else()
+  set(PACKAGE_VERSION_COMPATIBLE FALSE)
+endif()
+
+if(CMAKE_SIZEOF_VOID_P AND NOT CMAKE_SIZEOF_VOID_P EQUAL {pointer_bytes})
+  set(PACKAGE_VERSION "${{PACKAGE_VERSION}} ({pointer_width}bit)")
+  set(PACKAGE_VERSION_UNSUITABLE TRUE)
+endif()
+"#
+    );
+    let crate_dir = env("CARGO_MANIFEST_DIR");
+    let path = std::path::Path::new(&crate_dir)
+        .join("cmake")
+        .join("jpegexpConfigVersion.cmake");
+    // Only touch the file when the version changes, like cbindgen does for the header.
+    if std::fs::read_to_string(&path).ok().as_deref() != Some(contents.as_str()) {
+        std::fs::write(&path, contents).expect("writing the CMake version file failed");
+    }
+}
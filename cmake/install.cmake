@@ -0,0 +1,40 @@
+# Installs the jpegexp C library, its headers and CMake package after
+# `cargo build --release --features ffi`:
+#
+#   cmake -DCMAKE_INSTALL_PREFIX=/usr/local -P cmake/install.cmake
+#
+# Layout: include/jpegexp.h and jpegexp.hpp, the libraries in lib (DLLs in bin) and the
+# package in lib/cmake/jpegexp. Set JPEGEXP_LIBRARY_DIR to install the libraries of
+# another cargo profile or target directory than target/release.
+
+cmake_minimum_required(VERSION 3.14)
+
+if(NOT CMAKE_INSTALL_PREFIX)
+  message(FATAL_ERROR "Set the destination with -DCMAKE_INSTALL_PREFIX=<prefix>")
+endif()
+get_filename_component(_source_dir "${CMAKE_CURRENT_LIST_DIR}/.." ABSOLUTE)
+if(NOT JPEGEXP_LIBRARY_DIR)
+  set(JPEGEXP_LIBRARY_DIR "${_source_dir}/target/release")
+endif()
+
+file(GLOB _libraries
+  "${JPEGEXP_LIBRARY_DIR}/libjpegexp_rs.a"
+  "${JPEGEXP_LIBRARY_DIR}/libjpegexp_rs.so"
+  "${JPEGEXP_LIBRARY_DIR}/libjpegexp_rs.dylib"
+  "${JPEGEXP_LIBRARY_DIR}/jpegexp_rs.lib"
+  "${JPEGEXP_LIBRARY_DIR}/jpegexp_rs.dll.lib")
+file(GLOB _runtime_libraries "${JPEGEXP_LIBRARY_DIR}/jpegexp_rs.dll")
+if(NOT _libraries)
+  message(FATAL_ERROR "No jpegexp_rs library in ${JPEGEXP_LIBRARY_DIR}; build it with "
+                      "`cargo build --release --features ffi`")
+endif()
+
+file(INSTALL "${_source_dir}/include/jpegexp.h" "${_source_dir}/include/jpegexp.hpp"
+     DESTINATION "${CMAKE_INSTALL_PREFIX}/include")
+file(INSTALL ${_libraries} DESTINATION "${CMAKE_INSTALL_PREFIX}/lib")
+if(_runtime_libraries)
+  file(INSTALL ${_runtime_libraries} DESTINATION "${CMAKE_INSTALL_PREFIX}/bin")
+endif()
+file(INSTALL "${_source_dir}/cmake/jpegexpConfig.cmake"
+             "${_source_dir}/cmake/jpegexpConfigVersion.cmake"
+     DESTINATION "${CMAKE_INSTALL_PREFIX}/lib/cmake/jpegexp")
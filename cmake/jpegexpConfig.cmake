@@ -0,0 +1,90 @@
+# CMake package configuration of the jpegexp C library and its C++ wrapper.
+#
+#   find_package(jpegexp 0.1 CONFIG REQUIRED)
+#   target_link_libraries(app PRIVATE jpegexp::jpegexp)
+#
+# Imported targets:
+#   jpegexp::jpegexp         the shared library, or the static one if only that was built
+#   jpegexp::jpegexp_static  the static library, with the system libraries Rust needs
+# Both add the directory of jpegexp.h and jpegexp.hpp to the include path.
+#
+# The package is found in an install tree made by cmake/install.cmake
+# (<prefix>/lib/cmake/jpegexp) and in the source tree after
+# `cargo build --release --features ffi`. Set JPEGEXP_LIBRARY_DIR to take the libraries
+# from another directory, e.g. target/debug.
+
+get_filename_component(_jpegexp_config_dir "${CMAKE_CURRENT_LIST_DIR}" REALPATH)
+if(EXISTS "${_jpegexp_config_dir}/../include/jpegexp.h")
+  # Source tree: <repository>/cmake
+  get_filename_component(_jpegexp_prefix "${_jpegexp_config_dir}/.." ABSOLUTE)
+  set(_jpegexp_default_library_dir "${_jpegexp_prefix}/target/release")
+else()
+  # Install tree: <prefix>/lib/cmake/jpegexp
+  get_filename_component(_jpegexp_prefix "${_jpegexp_config_dir}/../../.." ABSOLUTE)
+  set(_jpegexp_default_library_dir "${_jpegexp_prefix}/lib")
+endif()
+set(JPEGEXP_LIBRARY_DIR "${_jpegexp_default_library_dir}" CACHE PATH
+    "Directory of the jpegexp_rs libraries")
+set(_jpegexp_include_dir "${_jpegexp_prefix}/include")
+
+if(WIN32)
+  # The DLL is installed to bin; cargo puts it next to the import library.
+  set(_jpegexp_shared "${JPEGEXP_LIBRARY_DIR}/jpegexp_rs.dll")
+  if(NOT EXISTS "${_jpegexp_shared}")
+    set(_jpegexp_shared "${JPEGEXP_LIBRARY_DIR}/../bin/jpegexp_rs.dll")
+  endif()
+  set(_jpegexp_import "${JPEGEXP_LIBRARY_DIR}/jpegexp_rs.dll.lib")
+  set(_jpegexp_static "${JPEGEXP_LIBRARY_DIR}/jpegexp_rs.lib")
+  set(_jpegexp_system_libraries kernel32 advapi32 ntdll userenv ws2_32 dbghelp)
+else()
+  set(_jpegexp_shared
+      "${JPEGEXP_LIBRARY_DIR}/libjpegexp_rs${CMAKE_SHARED_LIBRARY_SUFFIX}")
+  set(_jpegexp_static "${JPEGEXP_LIBRARY_DIR}/libjpegexp_rs.a")
+  if(APPLE)
+    set(_jpegexp_system_libraries System c m)
+  else()
+    set(_jpegexp_system_libraries gcc_s util rt pthread m dl c)
+  endif()
+endif()
+
+if(EXISTS "${_jpegexp_static}" AND NOT TARGET jpegexp::jpegexp_static)
+  add_library(jpegexp::jpegexp_static STATIC IMPORTED)
+  set_target_properties(jpegexp::jpegexp_static PROPERTIES
+    IMPORTED_LOCATION "${_jpegexp_static}"
+    IMPORTED_LINK_INTERFACE_LANGUAGES "C"
+    INTERFACE_INCLUDE_DIRECTORIES "${_jpegexp_include_dir}"
+    INTERFACE_LINK_LIBRARIES "${_jpegexp_system_libraries}")
+endif()
+
+if(NOT TARGET jpegexp::jpegexp)
+  if(EXISTS "${_jpegexp_shared}")
+    add_library(jpegexp::jpegexp SHARED IMPORTED)
+    set_target_properties(jpegexp::jpegexp PROPERTIES
+      IMPORTED_LOCATION "${_jpegexp_shared}"
+      INTERFACE_INCLUDE_DIRECTORIES "${_jpegexp_include_dir}")
+    if(WIN32)
+      set_target_properties(jpegexp::jpegexp PROPERTIES
+        IMPORTED_IMPLIB "${_jpegexp_import}")
+    endif()
+  elseif(TARGET jpegexp::jpegexp_static)
+    add_library(jpegexp::jpegexp INTERFACE IMPORTED)
+    set_target_properties(jpegexp::jpegexp PROPERTIES
+      INTERFACE_LINK_LIBRARIES jpegexp::jpegexp_static)
+  endif()
+endif()
+
+if(NOT TARGET jpegexp::jpegexp)
+  set(jpegexp_FOUND FALSE)
+  set(jpegexp_NOT_FOUND_MESSAGE
+      "No jpegexp_rs library in ${JPEGEXP_LIBRARY_DIR}; build it with "
+      "`cargo build --release --features ffi` or set JPEGEXP_LIBRARY_DIR.")
+endif()
+
+unset(_jpegexp_config_dir)
+unset(_jpegexp_prefix)
+unset(_jpegexp_default_library_dir)
+unset(_jpegexp_include_dir)
+unset(_jpegexp_shared)
+unset(_jpegexp_import)
+unset(_jpegexp_static)
+unset(_jpegexp_system_libraries)
@@ -0,0 +1,304 @@
+/*
+ * DCMTK codec shim: a DcmCodec registered with DcmCodecList that decodes JPEG, JPEG-LS and
+ * JPEG 2000 Pixel Data with jpegexp, in place of the dcmjpeg and dcmjpls decoders. Any
+ * DCMTK code that changes the representation to an uncompressed transfer syntax or calls
+ * DcmElement::getUncompressedFrame then decodes through jpegexp.
+ *
+ *     dcmtk_jpegexp input.dcm output.dcm
+ *
+ * Written against DCMTK 3.6.7.
+ */
+
+#include <cstdio>
+#include <cstring>
+#include <exception>
+#include <vector>
+
+#include "dcmtk/config/osconfig.h"
+#include "dcmtk/dcmdata/dccodec.h"
+#include "dcmtk/dcmdata/dcdeftag.h"
+#include "dcmtk/dcmdata/dcfilefo.h"
+#include "dcmtk/dcmdata/dcpixel.h"
+#include "dcmtk/dcmdata/dcpixseq.h"
+#include "dcmtk/dcmdata/dcpxitem.h"
+#include "dcmtk/dcmdata/dcstack.h"
+#include "dcmtk/dcmdata/dcswap.h"
+#include "dcmtk/dcmdata/dcxfer.h"
+
+#include "jpegexp_dicom.hpp"
+
+namespace {
+
+const OFConditionConst JpegExpDecodingFailed(OFM_dcmdata, 0x7f01, OF_error,
+                                             "jpegexp: decoding failed");
+
+/* Codec parameters; the decoder has none. */
+class JpegExpCodecParameter : public DcmCodecParameter {
+public:
+    DcmCodecParameter* clone() const override { return new JpegExpCodecParameter(*this); }
+    const char* className() const override { return "JpegExpCodecParameter"; }
+};
+
+bool is_supported(E_TransferSyntax ts) {
+    switch (ts) {
+    case EXS_JPEGProcess1:
+    case EXS_JPEGProcess2_4:
+    case EXS_JPEGProcess14:
+    case EXS_JPEGProcess14SV1:
+    case EXS_JPEGLSLossless:
+    case EXS_JPEGLSLossy:
+    case EXS_JPEG2000LosslessOnly:
+    case EXS_JPEG2000:
+        return true;
+    default:
+        return false;
+    }
+}
+
+/* The dataset that holds the Pixel Data element on top of `stack`. */
+DcmItem* pixel_data_item(const DcmStack& stack) {
+    DcmStack local(stack);
+    local.pop();
+    DcmObject* object = local.pop();
+    if (object == nullptr || (object->ident() != EVR_dataset && object->ident() != EVR_item)) {
+        return nullptr;
+    }
+    return OFstatic_cast(DcmItem*, object);
+}
+
+bool read_layout(DcmItem* dataset, jpegexp_dicom::frame_layout& layout, Sint32& frame_count) {
+    Uint16 columns = 0, rows = 0, samples_per_pixel = 0, bits_allocated = 0;
+    if (dataset->findAndGetUint16(DCM_Columns, columns).bad() ||
+        dataset->findAndGetUint16(DCM_Rows, rows).bad() ||
+        dataset->findAndGetUint16(DCM_SamplesPerPixel, samples_per_pixel).bad() ||
+        dataset->findAndGetUint16(DCM_BitsAllocated, bits_allocated).bad()) {
+        return false;
+    }
+    layout.columns = columns;
+    layout.rows = rows;
+    layout.samples_per_pixel = samples_per_pixel;
+    layout.bits_allocated = bits_allocated;
+    if (dataset->findAndGetSint32(DCM_NumberOfFrames, frame_count).bad() || frame_count < 1) {
+        frame_count = 1;
+    }
+    return true;
+}
+
+/* Decodes `frame` into `destination`, which holds layout.size() bytes. */
+OFCondition decode_frame(DcmPixelSequence* sequence, const jpegexp_dicom::frame_layout& layout,
+                         Uint32 frame, Uint32 frame_count, Uint8* destination,
+                         jpegexp::stream_info& stream) {
+    // Item 0 is the Basic Offset Table.
+    if (sequence->card() < 2) {
+        return EC_InvalidValue;
+    }
+    try {
+        auto range = jpegexp_dicom::frame_fragments(frame, frame_count, sequence->card() - 1);
+        std::vector<Uint8> data;
+        for (std::size_t i = range.first; i < range.second; ++i) {
+            DcmPixelItem* item = nullptr;
+            Uint8* fragment = nullptr;
+            OFCondition result = sequence->getItem(item, OFstatic_cast(Uint32, i + 1));
+            if (result.good()) {
+                result = item->getUint8Array(fragment);
+            }
+            if (result.bad()) {
+                return result;
+            }
+            if (fragment != nullptr) {
+                data.insert(data.end(), fragment, fragment + item->getLength());
+            }
+        }
+        jpegexp_dicom::decoded_frame decoded =
+            jpegexp_dicom::decode_frame(data.data(), data.size(), layout);
+        std::memcpy(destination, decoded.pixels.data(), decoded.pixels.size());
+        stream = decoded.stream;
+    } catch (const jpegexp::error& e) {
+        std::fprintf(stderr, "jpegexp: %s\n", e.what());
+        return JpegExpDecodingFailed;
+    }
+    return EC_Normal;
+}
+
+/* Color model of the decoded pixels: the decoders convert YBR to RGB. */
+OFCondition decoded_color_model(DcmItem* dataset, OFString& color_model) {
+    Uint16 samples_per_pixel = 0;
+    if (dataset->findAndGetUint16(DCM_SamplesPerPixel, samples_per_pixel).good() &&
+        samples_per_pixel == 3) {
+        color_model = "RGB";
+        return EC_Normal;
+    }
+    return dataset->findAndGetOFString(DCM_PhotometricInterpretation, color_model);
+}
+
+}  // namespace
+
+/* Decoder of the JPEG, JPEG-LS and JPEG 2000 transfer syntaxes; encoding is left to the
+ * DCMTK codecs. */
+class JpegExpDecoder : public DcmCodec {
+public:
+    OFCondition decode(const DcmRepresentationParameter*, DcmPixelSequence* pixSeq,
+                       DcmPolymorphOBOW& uncompressedPixelData, const DcmCodecParameter*,
+                       const DcmStack& objStack, OFBool& removeOldRep) const override {
+        DcmItem* dataset = pixel_data_item(objStack);
+        jpegexp_dicom::frame_layout layout;
+        Sint32 frame_count = 1;
+        if (pixSeq == nullptr || dataset == nullptr || !read_layout(dataset, layout, frame_count)) {
+            return EC_IllegalCall;
+        }
+
+        // Pixel Data of odd length is padded to an even number of bytes.
+        const std::size_t frame_size = layout.size();
+        const std::size_t total_size = (frame_size * frame_count + 1) & ~std::size_t{1};
+        Uint16* pixels = nullptr;
+        OFCondition result =
+            uncompressedPixelData.createUint16Array(OFstatic_cast(Uint32, total_size / 2), pixels);
+        if (result.bad()) {
+            return result;
+        }
+        Uint8* bytes = OFreinterpret_cast(Uint8*, pixels);
+        std::memset(bytes, 0, total_size);
+
+        bool lossless = true;
+        for (Sint32 frame = 0; frame < frame_count && result.good(); ++frame) {
+            jpegexp::stream_info stream{};
+            result = decode_frame(pixSeq, layout, frame, frame_count, bytes + frame * frame_size,
+                                  stream);
+            lossless = lossless && stream.lossless;
+        }
+        if (result.bad()) {
+            return result;
+        }
+        // jpegexp writes little-endian samples; the array holds host-order words.
+        result = swapIfNecessary(gLocalByteOrder, EBO_LittleEndian, pixels,
+                                 OFstatic_cast(Uint32, total_size), sizeof(Uint16));
+
+        if (result.good() && layout.samples_per_pixel == 3) {
+            result = dataset->putAndInsertString(DCM_PhotometricInterpretation, "RGB");
+            if (result.good()) {
+                result = dataset->putAndInsertUint16(DCM_PlanarConfiguration, 0);
+            }
+        }
+        if (result.good() && !lossless) {
+            result = dataset->putAndInsertString(DCM_LossyImageCompression, "01");
+        }
+        removeOldRep = OFTrue;
+        return result;
+    }
+
+    OFCondition decodeFrame(const DcmRepresentationParameter*, DcmPixelSequence* fromPixSeq,
+                            const DcmCodecParameter*, DcmItem* dataset, Uint32 frameNo,
+                            Uint32& startFragment, void* buffer, Uint32 bufSize,
+                            OFString& decompressedColorModel) const override {
+        jpegexp_dicom::frame_layout layout;
+        Sint32 frame_count = 1;
+        if (fromPixSeq == nullptr || dataset == nullptr ||
+            !read_layout(dataset, layout, frame_count)) {
+            return EC_IllegalCall;
+        }
+        if (bufSize < layout.size()) {
+            return EC_IllegalCall;
+        }
+        jpegexp::stream_info stream{};
+        OFCondition result = decode_frame(fromPixSeq, layout, frameNo, frame_count,
+                                          OFstatic_cast(Uint8*, buffer), stream);
+        if (result.bad()) {
+            return result;
+        }
+        // One fragment per frame: the next frame starts at the following item.
+        startFragment = frameNo + 2;
+        if (layout.bits_allocated > 8) {
+            result = swapIfNecessary(gLocalByteOrder, EBO_LittleEndian, buffer,
+                                     OFstatic_cast(Uint32, layout.size()), sizeof(Uint16));
+        }
+        if (result.good()) {
+            result = decoded_color_model(dataset, decompressedColorModel);
+        }
+        return result;
+    }
+
+    OFCondition encode(const Uint16*, const Uint32, const DcmRepresentationParameter*,
+                       DcmPixelSequence*&, const DcmCodecParameter*, DcmStack&,
+                       OFBool&) const override {
+        return EC_IllegalCall;
+    }
+
+    OFCondition encode(const E_TransferSyntax, const DcmRepresentationParameter*,
+                       DcmPixelSequence*, const DcmRepresentationParameter*, DcmPixelSequence*&,
+                       const DcmCodecParameter*, DcmStack&, OFBool&) const override {
+        return EC_IllegalCall;
+    }
+
+    OFBool canChangeCoding(const E_TransferSyntax oldRepType,
+                           const E_TransferSyntax newRepType) const override {
+        return is_supported(oldRepType) && !DcmXfer(newRepType).isEncapsulated();
+    }
+
+    OFCondition determineDecompressedColorModel(const DcmRepresentationParameter*,
+                                                DcmPixelSequence*, const DcmCodecParameter*,
+                                                DcmItem* dataset,
+                                                OFString& decompressedColorModel) const override {
+        if (dataset == nullptr) {
+            return EC_IllegalCall;
+        }
+        return decoded_color_model(dataset, decompressedColorModel);
+    }
+
+    Uint16 decodedBitsAllocated(Uint16, Uint16 bitsStored) const override {
+        return bitsStored > 8 ? 16 : 8;
+    }
+};
+
+/* Registers the jpegexp decoder with DCMTK, like DJLSDecoderRegistration does for dcmjpls. */
+struct JpegExpDecoderRegistration {
+    static void registerCodecs() {
+        if (codec == nullptr) {
+            codec = new JpegExpDecoder();
+            parameter = new JpegExpCodecParameter();
+            DcmCodecList::registerCodec(codec, nullptr, parameter);
+        }
+    }
+
+    static void cleanup() {
+        if (codec != nullptr) {
+            DcmCodecList::deregisterCodec(codec);
+            delete codec;
+            delete parameter;
+            codec = nullptr;
+            parameter = nullptr;
+        }
+    }
+
+    static JpegExpDecoder* codec;
+    static JpegExpCodecParameter* parameter;
+};
+
+JpegExpDecoder* JpegExpDecoderRegistration::codec = nullptr;
+JpegExpCodecParameter* JpegExpDecoderRegistration::parameter = nullptr;
+
+int main(int argc, char* argv[]) {
+    if (argc != 3) {
+        std::fprintf(stderr, "usage: %s input.dcm output.dcm\n", argv[0]);
+        return 2;
+    }
+    JpegExpDecoderRegistration::registerCodecs();
+
+    DcmFileFormat file;
+    OFCondition result = file.loadFile(argv[1]);
+    if (result.good()) {
+        DcmDataset* dataset = file.getDataset();
+        result = dataset->chooseRepresentation(EXS_LittleEndianExplicit, nullptr);
+        if (result.good() && !dataset->canWriteXfer(EXS_LittleEndianExplicit)) {
+            result = EC_CannotChangeRepresentation;
+        }
+    }
+    if (result.good()) {
+        result = file.saveFile(argv[2], EXS_LittleEndianExplicit);
+    }
+    if (result.bad()) {
+        std::fprintf(stderr, "%s: %s\n", argv[1], result.text());
+    }
+
+    JpegExpDecoderRegistration::cleanup();
+    return result.good() ? 0 : 1;
+}
@@ -0,0 +1,141 @@
+/*
+ * GDCM codec shim: a gdcm::ImageCodec that decodes JPEG, JPEG-LS and JPEG 2000 Pixel Data
+ * with jpegexp instead of GDCM's bundled IJG, CharLS and OpenJPEG codecs.
+ *
+ * GDCM picks its decoders from a fixed list, so the codec is called directly: the tool
+ * reads a file with gdcm::ImageReader (which leaves the Pixel Data encapsulated), decodes
+ * it with JpegExpCodec and writes it as Explicit VR Little Endian.
+ *
+ *     gdcm_jpegexp input.dcm output.dcm
+ */
+
+#include <cstdio>
+#include <exception>
+#include <vector>
+
+#include <gdcmImage.h>
+#include <gdcmImageCodec.h>
+#include <gdcmImageReader.h>
+#include <gdcmImageWriter.h>
+#include <gdcmSequenceOfFragments.h>
+
+#include "jpegexp_dicom.hpp"
+
+class JpegExpCodec : public gdcm::ImageCodec {
+public:
+    bool CanDecode(gdcm::TransferSyntax const& ts) const override {
+        switch (ts) {
+        case gdcm::TransferSyntax::JPEGBaselineProcess1:
+        case gdcm::TransferSyntax::JPEGExtendedProcess2_4:
+        case gdcm::TransferSyntax::JPEGLosslessProcess14:
+        case gdcm::TransferSyntax::JPEGLosslessProcess14_1:
+        case gdcm::TransferSyntax::JPEGLSLossless:
+        case gdcm::TransferSyntax::JPEGLSNearLossless:
+        case gdcm::TransferSyntax::JPEG2000Lossless:
+        case gdcm::TransferSyntax::JPEG2000:
+            return true;
+        default:
+            return false;
+        }
+    }
+
+    bool CanCode(gdcm::TransferSyntax const&) const override { return false; }
+
+    /* Decodes every frame of the encapsulated `in` into the native Pixel Data `out`. Set
+     * the dimensions and pixel format of the image first. */
+    bool Decode(gdcm::DataElement const& in, gdcm::DataElement& out) override {
+        const gdcm::SequenceOfFragments* fragments = in.GetSequenceOfFragments();
+        if (fragments == nullptr) {
+            return false;
+        }
+        const gdcm::PixelFormat& pixel_format = GetPixelFormat();
+        const auto& dimensions = GetDimensions();
+        jpegexp_dicom::frame_layout layout;
+        layout.columns = dimensions[0];
+        layout.rows = dimensions[1];
+        layout.samples_per_pixel = pixel_format.GetSamplesPerPixel();
+        layout.bits_allocated = pixel_format.GetBitsAllocated();
+        const std::size_t frame_count = GetNumberOfDimensions() == 3 ? dimensions[2] : 1;
+
+        std::vector<char> pixels;
+        pixels.reserve(layout.size() * frame_count);
+        bool lossless = true;
+        try {
+            for (std::size_t frame = 0; frame < frame_count; ++frame) {
+                auto range = jpegexp_dicom::frame_fragments(frame, frame_count,
+                                                            fragments->GetNumberOfFragments());
+                std::vector<char> stream;
+                for (std::size_t i = range.first; i < range.second; ++i) {
+                    const gdcm::ByteValue* value = fragments->GetFragment(i).GetByteValue();
+                    if (value != nullptr) {
+                        const char* begin = value->GetPointer();
+                        stream.insert(stream.end(), begin,
+                                      begin + static_cast<std::uint32_t>(value->GetLength()));
+                    }
+                }
+                jpegexp_dicom::decoded_frame decoded =
+                    jpegexp_dicom::decode_frame(stream.data(), stream.size(), layout);
+                lossless = lossless && decoded.stream.lossless;
+                pixels.insert(pixels.end(), decoded.pixels.begin(), decoded.pixels.end());
+            }
+        } catch (const jpegexp::error& e) {
+            std::fprintf(stderr, "jpegexp: %s\n", e.what());
+            return false;
+        }
+
+        out = in;
+        out.SetVR(layout.bits_allocated > 8 ? gdcm::VR::OW : gdcm::VR::OB);
+        out.SetByteValue(pixels.data(), static_cast<std::uint32_t>(pixels.size()));
+        SetLossyFlag(!lossless);
+        return true;
+    }
+
+    gdcm::ImageCodec* Clone() const override { return new JpegExpCodec(*this); }
+};
+
+int main(int argc, char* argv[]) {
+    if (argc != 3) {
+        std::fprintf(stderr, "usage: %s input.dcm output.dcm\n", argv[0]);
+        return 2;
+    }
+    gdcm::ImageReader reader;
+    reader.SetFileName(argv[1]);
+    if (!reader.Read()) {
+        std::fprintf(stderr, "cannot read %s\n", argv[1]);
+        return 1;
+    }
+    gdcm::Image& image = reader.GetImage();
+    JpegExpCodec codec;
+    if (!codec.CanDecode(image.GetTransferSyntax())) {
+        std::fprintf(stderr, "%s is not JPEG, JPEG-LS or JPEG 2000\n", argv[1]);
+        return 1;
+    }
+
+    codec.SetNumberOfDimensions(image.GetNumberOfDimensions());
+    codec.SetDimensions(image.GetDimensions());
+    codec.SetPixelFormat(image.GetPixelFormat());
+    codec.SetPhotometricInterpretation(image.GetPhotometricInterpretation());
+    gdcm::DataElement pixel_data;
+    if (!codec.Decode(image.GetDataElement(), pixel_data)) {
+        return 1;
+    }
+
+    // The decoders convert YBR to RGB and write interleaved samples.
+    image.SetDataElement(pixel_data);
+    if (image.GetPixelFormat().GetSamplesPerPixel() == 3) {
+        image.SetPhotometricInterpretation(gdcm::PhotometricInterpretation::RGB);
+        image.SetPlanarConfiguration(0);
+    }
+    image.SetLossyFlag(codec.GetLossyFlag() || image.IsLossy());
+    image.SetTransferSyntax(gdcm::TransferSyntax::ExplicitVRLittleEndian);
+
+    gdcm::ImageWriter writer;
+    writer.SetFileName(argv[2]);
+    writer.SetFile(reader.GetFile());
+    writer.SetImage(image);
+    if (!writer.Write()) {
+        std::fprintf(stderr, "cannot write %s\n", argv[2]);
+        return 1;
+    }
+    return 0;
+}
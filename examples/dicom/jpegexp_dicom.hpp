@@ -0,0 +1,103 @@
+/*
+ * Toolkit-independent part of the GDCM and DCMTK codec shims: maps the fragments of an
+ * encapsulated Pixel Data element to frames and decodes one frame with the jpegexp C API,
+ * checking the stream against the image pixel module of the dataset.
+ */
+
+#ifndef JPEGEXP_DICOM_HPP
+#define JPEGEXP_DICOM_HPP
+
+#include <cstddef>
+#include <cstdint>
+#include <string>
+#include <utility>
+#include <vector>
+
+#include "jpegexp.hpp"
+
+namespace jpegexp_dicom {
+
+/* The image pixel module attributes a decoded frame must match. */
+struct frame_layout {
+    std::uint32_t columns = 0;
+    std::uint32_t rows = 0;
+    std::uint32_t samples_per_pixel = 1;
+    std::uint32_t bits_allocated = 8;
+
+    std::size_t size() const {
+        return std::size_t{columns} * rows * samples_per_pixel * (bits_allocated / 8);
+    }
+};
+
+/* Interleaved pixels of a frame and the coding properties of its stream. */
+struct decoded_frame {
+    std::vector<std::uint8_t> pixels;
+    jpegexp::stream_info stream{};
+};
+
+/*
+ * Fragments [first, last) of `frame` among `fragment_count` fragments (the Basic Offset
+ * Table item not counted): one fragment per frame, or all fragments for a single frame.
+ * Frames split over several fragments need the offset table, which the shims do not read.
+ */
+inline std::pair<std::size_t, std::size_t> frame_fragments(std::size_t frame,
+                                                           std::size_t frame_count,
+                                                           std::size_t fragment_count) {
+    if (frame_count == 1 && fragment_count > 0 && frame == 0) {
+        return {0, fragment_count};
+    }
+    if (fragment_count == frame_count && frame < frame_count) {
+        return {frame, frame + 1};
+    }
+    throw jpegexp::error(JPEG_EXP_ERROR_UNSUPPORTED_FORMAT,
+                         std::to_string(frame_count) + " frames in " +
+                             std::to_string(fragment_count) +
+                             " fragments; one fragment per frame is supported");
+}
+
+/*
+ * Decodes the JPEG, JPEG-LS or JPEG 2000 stream of one frame. Samples wider than 8 bits
+ * are little-endian, as in Explicit VR Little Endian Pixel Data. Throws jpegexp::error
+ * when the stream does not match `layout` or the decoder would reduce its precision.
+ */
+inline decoded_frame decode_frame(const void* data, std::size_t size,
+                                  const frame_layout& layout) {
+    jpegexp::decoder decoder(data, size);
+    const jpegexp::image_info& info = decoder.read_header();
+    decoded_frame frame;
+    frame.stream = decoder.get_stream_info();
+
+    if (info.width != layout.columns || info.height != layout.rows ||
+        info.components != layout.samples_per_pixel) {
+        throw jpegexp::error(JPEG_EXP_ERROR_INVALID_DATA,
+                             "stream is " + std::to_string(info.width) + "x" +
+                                 std::to_string(info.height) + "x" +
+                                 std::to_string(info.components) +
+                                 ", the dataset describes " + std::to_string(layout.columns) +
+                                 "x" + std::to_string(layout.rows) + "x" +
+                                 std::to_string(layout.samples_per_pixel));
+    }
+    if (frame.stream.precision > info.bits_per_sample) {
+        throw jpegexp::error(JPEG_EXP_ERROR_UNSUPPORTED_FORMAT,
+                             "the decoder reduces the " + std::to_string(frame.stream.precision) +
+                                 "-bit samples to " + std::to_string(info.bits_per_sample) +
+                                 " bits");
+    }
+    if ((info.bits_per_sample > 8 ? 16u : 8u) != layout.bits_allocated) {
+        throw jpegexp::error(JPEG_EXP_ERROR_UNSUPPORTED_FORMAT,
+                             std::to_string(info.bits_per_sample) +
+                                 "-bit samples do not fit Bits Allocated " +
+                                 std::to_string(layout.bits_allocated));
+    }
+    frame.pixels = decoder.decode();
+    return frame;
+}
+
+/* Lossy Image Compression (0028,2110) of a frame: "01" unless every sample was kept. */
+inline const char* lossy_image_compression(const jpegexp::stream_info& stream) {
+    return stream.lossless ? "00" : "01";
+}
+
+}  // namespace jpegexp_dicom
+
+#endif /* JPEGEXP_DICOM_HPP */
@@ -0,0 +1,22 @@
+//! Size limits shared by the fuzz targets. A header may declare an image of up to
+//! 65535 x 65535 x 255 samples, so the targets only decode images whose output fits in a
+//! few megabytes; larger ones would only measure the allocator.
+
+use jpegexp_rs::FrameInfo;
+
+/// Largest decoded image, in bytes.
+pub const MAX_DECODED_BYTES: u64 = 16 << 20;
+
+/// Size of the decoded image of `frame_info`, with samples wider than 8 bits taking two
+/// bytes, or `None` if the header is degenerate or the image is over the limit.
+pub fn decoded_size(frame_info: &FrameInfo) -> Option<usize> {
+    if frame_info.component_count <= 0 || frame_info.bits_per_sample <= 0 {
+        return None;
+    }
+    let bytes_per_sample = (frame_info.bits_per_sample as u64).div_ceil(8);
+    let size = (frame_info.width as u64)
+        .checked_mul(frame_info.height as u64)?
+        .checked_mul(frame_info.component_count as u64)?
+        .checked_mul(bytes_per_sample)?;
+    (size > 0 && size <= MAX_DECODED_BYTES).then_some(size as usize)
+}
@@ -0,0 +1,50 @@
+//! JPEG 2000 (raw codestream or JP2) header parsing, decoding and reconstruction of
+//! arbitrary input.
+
+#![no_main]
+
+mod common;
+
+use jpegexp_rs::jpeg2000::decoder::J2kDecoder;
+use jpegexp_rs::jpeg2000::jp2::Jp2Reader;
+use jpegexp_rs::jpeg2000::parser::J2kParser;
+use jpegexp_rs::jpeg_stream_reader::JpegStreamReader;
+use jpegexp_rs::FrameInfo;
+use libfuzzer_sys::fuzz_target;
+
+/// Most tiles decoded; every tile has its own component, resolution and code-block state.
+const MAX_TILES: u32 = 1024;
+
+/// Whether the main header is readable and declares an image within the limits. The
+/// decoder sizes its buffers from SIZ before reading any tile data.
+fn within_limits(data: &[u8]) -> bool {
+    let codestream = Jp2Reader::new(data)
+        .find_codestream()
+        .ok()
+        .flatten()
+        .unwrap_or(data);
+    let mut reader = JpegStreamReader::new(codestream);
+    let mut parser = J2kParser::new(&mut reader);
+    if parser.parse_main_header().is_err() {
+        return false;
+    }
+    let image = &parser.image;
+    let frame_info = FrameInfo {
+        width: image.width,
+        height: image.height,
+        bits_per_sample: image.bits_per_sample() as i32,
+        component_count: image.component_count as i32,
+    };
+    common::decoded_size(&frame_info).is_some() && image.tile_count() <= MAX_TILES
+}
+
+fuzz_target!(|data: &[u8]| {
+    if !within_limits(data) {
+        return;
+    }
+    let mut reader = JpegStreamReader::new(data);
+    let mut decoder = J2kDecoder::new(&mut reader);
+    if let Ok(image) = decoder.decode() {
+        let _ = image.reconstruct_pixels();
+    }
+});
@@ -0,0 +1,32 @@
+//! Baseline JPEG header parsing and decoding of arbitrary input.
+
+#![no_main]
+
+mod common;
+
+use jpegexp_rs::jpeg1::decoder::Jpeg1Decoder;
+use jpegexp_rs::jpeg_stream_reader::JpegStreamReader;
+use jpegexp_rs::FrameInfo;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut reader = JpegStreamReader::new(data);
+    if reader.read_header(&mut None).is_err() {
+        return;
+    }
+    // The JPEG 1 decoder writes 8-bit samples whatever the precision.
+    let frame_info = FrameInfo {
+        bits_per_sample: 8,
+        ..reader.frame_info()
+    };
+    let Some(size) = common::decoded_size(&frame_info) else {
+        return;
+    };
+
+    let mut decoder = Jpeg1Decoder::new(data);
+    if decoder.read_header().is_err() {
+        return;
+    }
+    let mut pixels = vec![0u8; size];
+    let _ = decoder.decode(&mut pixels);
+});
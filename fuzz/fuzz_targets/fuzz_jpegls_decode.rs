@@ -0,0 +1,20 @@
+//! JPEG-LS header parsing and decoding of arbitrary input.
+
+#![no_main]
+
+mod common;
+
+use jpegexp_rs::jpegls::JpeglsDecoder;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut decoder = JpeglsDecoder::new(data);
+    if decoder.read_header().is_err() {
+        return;
+    }
+    let Some(size) = common::decoded_size(&decoder.frame_info()) else {
+        return;
+    };
+    let mut pixels = vec![0u8; size];
+    let _ = decoder.decode(&mut pixels);
+});
@@ -0,0 +1,482 @@
+/* Generated by cbindgen from src/ffi.rs. Do not edit. */
+
+#ifndef JPEGEXP_H
+#define JPEGEXP_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Output formats of `jpegexp_encoder_new`.
+ */
+typedef enum JpegExpFormat {
+  JPEG_EXP_FORMAT_JPEG = 0,
+  JPEG_EXP_FORMAT_JPEGLS = 1,
+  JPEG_EXP_FORMAT_J2K = 2,
+} JpegExpFormat;
+
+/**
+ * Error codes.
+ */
+typedef enum JpegExpError {
+  JPEG_EXP_ERROR_OK = 0,
+  JPEG_EXP_ERROR_INVALID_DATA = 1,
+  JPEG_EXP_ERROR_BUFFER_TOO_SMALL = 2,
+  JPEG_EXP_ERROR_UNSUPPORTED_FORMAT = 3,
+  JPEG_EXP_ERROR_INTERNAL_ERROR = 4,
+} JpegExpError;
+
+/**
+ * Log levels passed to a `JpegExpLogCallback`. The values match the `log` crate.
+ */
+typedef enum JpegExpLogLevel {
+  JPEG_EXP_LOG_LEVEL_OFF = 0,
+  JPEG_EXP_LOG_LEVEL_ERROR = 1,
+  JPEG_EXP_LOG_LEVEL_WARN = 2,
+  JPEG_EXP_LOG_LEVEL_INFO = 3,
+  JPEG_EXP_LOG_LEVEL_DEBUG = 4,
+  JPEG_EXP_LOG_LEVEL_TRACE = 5,
+} JpegExpLogLevel;
+
+/**
+ * Receives one diagnostic message with its `JpegExpLogLevel`.
+ *
+ * `message` is only valid for the duration of the call. The callback may be invoked from
+ * any thread that calls into the library.
+ */
+typedef void (*JpegExpLogCallback)(int level, const char *message, void *user_data);
+
+/**
+ * Opaque decoder handle.
+ */
+typedef struct JpegExpDecoder {
+  uint8_t _private[0];
+} JpegExpDecoder;
+
+/**
+ * Image information structure.
+ */
+typedef struct JpegExpImageInfo {
+  uint32_t width;
+  uint32_t height;
+  uint32_t components;
+  uint32_t bits_per_sample;
+} JpegExpImageInfo;
+
+/**
+ * Coding properties of a stream, filled by `jpegexp_decoder_get_stream_info`; e.g. for
+ * a DICOM toolkit to set Lossy Image Compression and to check the decoded precision.
+ */
+typedef struct JpegExpStreamInfo {
+  /**
+   * `JpegExpFormat` of the stream.
+   */
+  int format;
+  /**
+   * 1 if decoding reconstructs the encoded samples exactly: the JPEG lossless process,
+   * JPEG-LS with NEAR 0 or the JPEG 2000 reversible (5/3) wavelet; 0 otherwise.
+   */
+  int lossless;
+  /**
+   * JPEG-LS NEAR parameter of the first scan; 0 for the other formats.
+   */
+  int near_lossless;
+  /**
+   * Sample precision of the frame header. Larger than the `bits_per_sample` of
+   * `JpegExpImageInfo` when the decoder reduces the samples, as the JPEG decoder does
+   * to 8 bits.
+   */
+  uint32_t precision;
+} JpegExpStreamInfo;
+
+/**
+ * Precision and sampling of one component of a stream, filled by
+ * `jpegexp_decoder_get_component_info`. JPEG 2000 components can each have their own
+ * precision and signedness; `JpegExpImageInfo` gives the largest precision.
+ */
+typedef struct JpegExpComponentInfo {
+  /**
+   * Bits per sample of the component in the stream.
+   */
+  uint32_t bits_per_sample;
+  /**
+   * 1 if the samples are signed (only in JPEG 2000), 0 otherwise.
+   */
+  int is_signed;
+  /**
+   * Horizontal subsampling relative to the image width, e.g. 2 for 4:2:0 chroma.
+   */
+  uint32_t horizontal_subsampling;
+  /**
+   * Vertical subsampling relative to the image height.
+   */
+  uint32_t vertical_subsampling;
+} JpegExpComponentInfo;
+
+/**
+ * Encoder options for the `jpegexp_encode_*_with_options` functions.
+ *
+ * A zero-initialized struct selects the defaults for every field.
+ */
+typedef struct JpegExpEncodeOptions {
+  /**
+   * JPEG and JPEG 2000 quality (1-100); 0 keeps the encoder default.
+   */
+  int quality;
+  /**
+   * JPEG-LS NEAR parameter; 0 encodes losslessly.
+   */
+  int near_lossless;
+  /**
+   * Sample precision (2-16); 0 means 8 bits.
+   */
+  int bits_per_sample;
+  /**
+   * JPEG-LS interleave mode: 0 = none, 1 = line, 2 = sample.
+   */
+  int interleave_mode;
+} JpegExpEncodeOptions;
+
+/**
+ * Opaque encoder handle.
+ */
+typedef struct JpegExpEncoder {
+  uint8_t _private[0];
+} JpegExpEncoder;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Message describing the most recent failure on the calling thread, or null if no call
+ * has failed yet.
+ *
+ * The string is owned by the library and stays valid until the next failing call on the
+ * same thread.
+ */
+const char *jpegexp_get_last_error_message(void);
+
+/**
+ * Register a callback that receives the library's diagnostic messages.
+ *
+ * Messages above `max_level` (a `JpegExpLogLevel` value) are discarded. A null `callback`
+ * or `JPEG_EXP_LOG_LEVEL_OFF` turns logging off. `user_data` is passed back unchanged and
+ * must be usable from any thread that calls into the library.
+ *
+ * Fails with `JPEG_EXP_ERROR_INTERNAL_ERROR` when the host process already installed a
+ * different `log` backend.
+ */
+int jpegexp_set_log_callback(JpegExpLogCallback callback, int max_level, void *user_data);
+
+/**
+ * Create a new decoder from raw data.
+ *
+ * # Safety
+ * `data` must be a valid pointer to `len` bytes.
+ */
+struct JpegExpDecoder *jpegexp_decoder_new(const unsigned char *data, size_t len);
+
+/**
+ * Free a decoder handle.
+ *
+ * # Safety
+ * `decoder` must be a valid handle from `jpegexp_decoder_new`.
+ */
+void jpegexp_decoder_free(struct JpegExpDecoder *decoder);
+
+/**
+ * Read the image header. For JPEG 2000 only the main header (and the boxes of a JP2
+ * file) are read; the tiles are decoded by `jpegexp_decoder_decode`.
+ *
+ * # Safety
+ * `decoder` must be valid. `info` must point to a valid JpegExpImageInfo.
+ */
+int jpegexp_decoder_read_header(struct JpegExpDecoder *decoder, struct JpegExpImageInfo *info);
+
+/**
+ * Query the coding properties of the stream: its format, whether it is lossless and the
+ * precision of its samples.
+ *
+ * `jpegexp_decoder_read_header` must have been called first.
+ *
+ * # Safety
+ * `decoder` must be valid. `info` must point to a writable JpegExpStreamInfo.
+ */
+int jpegexp_decoder_get_stream_info(const struct JpegExpDecoder *decoder,
+                                    struct JpegExpStreamInfo *info);
+
+/**
+ * Query the precision, signedness and subsampling of the component at `index`, which is
+ * less than the `components` of `JpegExpImageInfo`.
+ *
+ * `jpegexp_decoder_read_header` must have been called first.
+ *
+ * # Safety
+ * `decoder` must be valid. `info` must point to a writable JpegExpComponentInfo.
+ */
+int jpegexp_decoder_get_component_info(const struct JpegExpDecoder *decoder,
+                                       uint32_t index,
+                                       struct JpegExpComponentInfo *info);
+
+/**
+ * Query the size in bytes of the buffer `jpegexp_decoder_decode` writes.
+ *
+ * `jpegexp_decoder_read_header` must have been called first.
+ *
+ * # Safety
+ * `decoder` must be valid. `size` must point to a writable `size_t`.
+ */
+int jpegexp_decoder_get_decoded_size(const struct JpegExpDecoder *decoder, size_t *size);
+
+/**
+ * Decode the image to raw pixels.
+ *
+ * # Safety
+ * All pointers must be valid. `output` must have at least `output_len` bytes.
+ */
+int jpegexp_decoder_decode(struct JpegExpDecoder *decoder,
+                           unsigned char *output,
+                           size_t output_len);
+
+/**
+ * Encode raw pixels to JPEG.
+ *
+ * # Safety
+ * All pointers must be valid.
+ */
+int jpegexp_encode_jpeg(const unsigned char *pixels,
+                        uint32_t width,
+                        uint32_t height,
+                        uint32_t components,
+                        unsigned char *output,
+                        size_t output_len,
+                        size_t *bytes_written);
+
+/**
+ * Encode raw pixels to JPEG-LS, losslessly.
+ *
+ * # Safety
+ * All pointers must be valid.
+ */
+int jpegexp_encode_jpegls(const unsigned char *pixels,
+                          uint32_t width,
+                          uint32_t height,
+                          uint32_t components,
+                          unsigned char *output,
+                          size_t output_len,
+                          size_t *bytes_written);
+
+/**
+ * Encode raw pixels to JPEG-LS with the NEAR parameter `near_lossless`: 0 for lossless
+ * coding, otherwise the maximum error allowed per sample (at most 127 for 8-bit
+ * samples).
+ *
+ * # Safety
+ * All pointers must be valid.
+ */
+int jpegexp_encode_jpegls_near(const unsigned char *pixels,
+                               uint32_t width,
+                               uint32_t height,
+                               uint32_t components,
+                               int near_lossless,
+                               unsigned char *output,
+                               size_t output_len,
+                               size_t *bytes_written);
+
+/**
+ * Encode raw pixels to JPEG 2000.
+ *
+ * # Safety
+ * All pointers must be valid.
+ */
+int jpegexp_encode_j2k(const unsigned char *pixels,
+                       uint32_t width,
+                       uint32_t height,
+                       uint32_t components,
+                       uint8_t quality,
+                       unsigned char *output,
+                       size_t output_len,
+                       size_t *bytes_written);
+
+/**
+ * Encode raw pixels to JPEG using the given options.
+ *
+ * `options` may be null to use the defaults. Samples wider than 8 bits are passed as
+ * little-endian 16-bit values.
+ *
+ * # Safety
+ * All pointers must be valid. `pixels` must hold `width * height * components` samples.
+ */
+int jpegexp_encode_jpeg_with_options(const unsigned char *pixels,
+                                     uint32_t width,
+                                     uint32_t height,
+                                     uint32_t components,
+                                     const struct JpegExpEncodeOptions *options,
+                                     unsigned char *output,
+                                     size_t output_len,
+                                     size_t *bytes_written);
+
+/**
+ * Encode raw pixels to JPEG-LS using the given options.
+ *
+ * `options` may be null to use the defaults. Samples wider than 8 bits are passed as
+ * little-endian 16-bit values.
+ *
+ * # Safety
+ * All pointers must be valid. `pixels` must hold `width * height * components` samples.
+ */
+int jpegexp_encode_jpegls_with_options(const unsigned char *pixels,
+                                       uint32_t width,
+                                       uint32_t height,
+                                       uint32_t components,
+                                       const struct JpegExpEncodeOptions *options,
+                                       unsigned char *output,
+                                       size_t output_len,
+                                       size_t *bytes_written);
+
+/**
+ * Encode raw pixels to JPEG 2000 using the given options.
+ *
+ * `options` may be null to use the defaults. Samples wider than 8 bits are passed as
+ * little-endian 16-bit values.
+ *
+ * # Safety
+ * All pointers must be valid. `pixels` must hold `width * height * components` samples.
+ */
+int jpegexp_encode_j2k_with_options(const unsigned char *pixels,
+                                    uint32_t width,
+                                    uint32_t height,
+                                    uint32_t components,
+                                    const struct JpegExpEncodeOptions *options,
+                                    unsigned char *output,
+                                    size_t output_len,
+                                    size_t *bytes_written);
+
+/**
+ * Create a new encoder for the given format.
+ *
+ * `format` is a `JpegExpFormat` value. The encoder starts with the default options.
+ * Returns null for an unknown format.
+ */
+struct JpegExpEncoder *jpegexp_encoder_new(int format);
+
+/**
+ * Free an encoder handle.
+ *
+ * # Safety
+ * `encoder` must be a valid handle from `jpegexp_encoder_new`.
+ */
+void jpegexp_encoder_free(struct JpegExpEncoder *encoder);
+
+/**
+ * Replace the options of an encoder. A null `options` restores the defaults.
+ *
+ * # Safety
+ * `encoder` must be valid. `options` must be null or point to a valid struct.
+ */
+int jpegexp_encoder_set_options(struct JpegExpEncoder *encoder,
+                                const struct JpegExpEncodeOptions *options);
+
+/**
+ * Encode raw pixels with the format and options of `encoder`.
+ *
+ * Samples wider than 8 bits are passed as little-endian 16-bit values.
+ *
+ * # Safety
+ * All pointers must be valid. `pixels` must hold `width * height * components` samples.
+ */
+int jpegexp_encoder_encode(struct JpegExpEncoder *encoder,
+                           const unsigned char *pixels,
+                           uint32_t width,
+                           uint32_t height,
+                           uint32_t components,
+                           unsigned char *output,
+                           size_t output_len,
+                           size_t *bytes_written);
+
+/**
+ * Query the size in bytes of an output buffer that is large enough for
+ * `jpegexp_encoder_encode` to encode a `width` x `height` image of `components` components
+ * with the format and options of `encoder`, even when the pixels do not compress.
+ *
+ * # Safety
+ * `encoder` must be valid. `size` must point to a writable `size_t`.
+ */
+int jpegexp_encoder_get_estimated_size(const struct JpegExpEncoder *encoder,
+                                       uint32_t width,
+                                       uint32_t height,
+                                       uint32_t components,
+                                       size_t *size);
+
+/**
+ * Reorder the channels of 8-bit interleaved pixels, e.g. RGB to BGR with the order
+ * `{2, 1, 0}`.
+ *
+ * `input` holds `len` bytes of pixels of `channels` samples each. Channel `c` of an
+ * output pixel is channel `order[c]` of the input pixel, so `output` must hold
+ * `len / channels * order_len` bytes.
+ *
+ * # Safety
+ * `input`, `order` and `output` must be valid for `len`, `order_len` and `output_len`
+ * elements.
+ */
+int jpegexp_swizzle(const unsigned char *input,
+                    size_t len,
+                    uint32_t channels,
+                    const uint32_t *order,
+                    uint32_t order_len,
+                    unsigned char *output,
+                    size_t output_len);
+
+/**
+ * Append a channel holding `alpha` to every 8-bit interleaved pixel, e.g. RGB to RGBA.
+ *
+ * `input` holds `len` bytes of pixels of `channels` samples each; `output` must hold
+ * `len / channels * (channels + 1)` bytes.
+ *
+ * # Safety
+ * `input` and `output` must be valid for `len` and `output_len` bytes.
+ */
+int jpegexp_add_alpha(const unsigned char *input,
+                      size_t len,
+                      uint32_t channels,
+                      unsigned char alpha,
+                      unsigned char *output,
+                      size_t output_len);
+
+/**
+ * Map `count` 16-bit samples to 8 bits with the DICOM linear window of `width` values
+ * around `center`. A non-zero `is_signed` reads the samples as `int16_t`.
+ *
+ * `output` must hold `count` bytes. Fails with `JPEG_EXP_ERROR_INVALID_DATA` if `width`
+ * is less than 1.
+ *
+ * # Safety
+ * `input` must be valid for `count` samples and `output` for `output_len` bytes.
+ */
+int jpegexp_window_level_16(const uint16_t *input,
+                            size_t count,
+                            int is_signed,
+                            double center,
+                            double width,
+                            unsigned char *output,
+                            size_t output_len);
+
+/**
+ * Swap the bytes of every 16-bit sample in `data` in place, e.g. to turn the
+ * little-endian output of `jpegexp_decoder_decode` into big-endian samples.
+ *
+ * # Safety
+ * `data` must be valid for `len` bytes.
+ */
+int jpegexp_swap_bytes_16(unsigned char *data, size_t len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* JPEGEXP_H */
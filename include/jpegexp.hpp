@@ -0,0 +1,209 @@
+/*
+ * C++ wrapper over the jpegexp C API (jpegexp.h).
+ *
+ * Header-only and C++17. The handles are released by RAII and failed calls throw
+ * jpegexp::error with the library's message, so the C error codes need not be checked:
+ *
+ *     jpegexp::decoder decoder(data.data(), data.size());
+ *     const jpegexp::image_info& info = decoder.read_header();
+ *     std::vector<std::uint8_t> pixels = decoder.decode();
+ *
+ *     jpegexp::encoder encoder(jpegexp::format::jpegls);
+ *     std::vector<std::uint8_t> encoded =
+ *         encoder.encode(pixels.data(), info.width, info.height, info.components);
+ *
+ * Link against the jpegexp_rs library; the CMake package (cmake/jpegexpConfig.cmake)
+ * provides the jpegexp::jpegexp target.
+ */
+
+#ifndef JPEGEXP_HPP
+#define JPEGEXP_HPP
+
+#include <cstddef>
+#include <cstdint>
+#include <memory>
+#include <stdexcept>
+#include <string>
+#include <vector>
+
+#include "jpegexp.h"
+
+namespace jpegexp {
+
+/* Thrown when a call into the library fails. */
+class error : public std::runtime_error {
+public:
+    error(int code, const std::string& message) : std::runtime_error(message), code_(code) {}
+
+    /* The JpegExpError value of the failed call. */
+    int code() const noexcept { return code_; }
+
+private:
+    int code_;
+};
+
+/* Output formats of jpegexp::encoder. */
+enum class format {
+    jpeg = JPEG_EXP_FORMAT_JPEG,
+    jpegls = JPEG_EXP_FORMAT_JPEGLS,
+    j2k = JPEG_EXP_FORMAT_J2K,
+};
+
+/* Dimensions and sample precision of an image. */
+using image_info = JpegExpImageInfo;
+
+/* Format, losslessness and sample precision of a stream. */
+using stream_info = JpegExpStreamInfo;
+
+/* Precision, signedness and subsampling of one component of a stream. */
+using component_info = JpegExpComponentInfo;
+
+/* Encoder options; a value-initialized struct (encode_options{}) selects the defaults. */
+using encode_options = JpegExpEncodeOptions;
+
+namespace detail {
+
+inline void check(int code) {
+    if (code != JPEG_EXP_ERROR_OK) {
+        const char* message = jpegexp_get_last_error_message();
+        throw error(code, message != nullptr ? message : "jpegexp call failed");
+    }
+}
+
+struct decoder_deleter {
+    void operator()(JpegExpDecoder* decoder) const noexcept { jpegexp_decoder_free(decoder); }
+};
+
+struct encoder_deleter {
+    void operator()(JpegExpEncoder* encoder) const noexcept { jpegexp_encoder_free(encoder); }
+};
+
+}  // namespace detail
+
+/* Decodes a JPEG, JPEG-LS or JPEG 2000 stream. Move-only. */
+class decoder {
+public:
+    /* Copies the `size` bytes of the encoded stream at `data`. */
+    decoder(const void* data, std::size_t size)
+        : handle_(jpegexp_decoder_new(static_cast<const unsigned char*>(data), size)) {
+        if (!handle_) {
+            throw error(JPEG_EXP_ERROR_INVALID_DATA, "source data is null or empty");
+        }
+    }
+
+    explicit decoder(const std::vector<std::uint8_t>& data) : decoder(data.data(), data.size()) {}
+
+    /* Reads the header; a JPEG 2000 stream is decoded completely here. */
+    const image_info& read_header() {
+        detail::check(jpegexp_decoder_read_header(handle_.get(), &info_));
+        return info_;
+    }
+
+    /* The information of the last read_header call. */
+    const image_info& info() const noexcept { return info_; }
+
+    /* Coding properties of the stream. Call after read_header. */
+    stream_info get_stream_info() const {
+        stream_info info{};
+        detail::check(jpegexp_decoder_get_stream_info(handle_.get(), &info));
+        return info;
+    }
+
+    /* Precision and sampling of the component at `index`. Call after read_header. */
+    component_info get_component_info(std::uint32_t index) const {
+        component_info info{};
+        detail::check(jpegexp_decoder_get_component_info(handle_.get(), index, &info));
+        return info;
+    }
+
+    /* Size in bytes of the pixels decode writes. Call after read_header. */
+    std::size_t decoded_size() const {
+        std::size_t size = 0;
+        detail::check(jpegexp_decoder_get_decoded_size(handle_.get(), &size));
+        return size;
+    }
+
+    /* Decodes into `destination`, which holds `size` bytes. Samples wider than 8 bits are
+     * written as little-endian 16-bit values. */
+    void decode(void* destination, std::size_t size) {
+        detail::check(
+            jpegexp_decoder_decode(handle_.get(), static_cast<unsigned char*>(destination), size));
+    }
+
+    /* Decodes into a new buffer of decoded_size() bytes. Call after read_header. */
+    std::vector<std::uint8_t> decode() {
+        std::vector<std::uint8_t> pixels(decoded_size());
+        decode(pixels.data(), pixels.size());
+        return pixels;
+    }
+
+    /* The C handle, for calls the wrapper does not cover. */
+    JpegExpDecoder* get() const noexcept { return handle_.get(); }
+
+private:
+    std::unique_ptr<JpegExpDecoder, detail::decoder_deleter> handle_;
+    image_info info_{};
+};
+
+/* Encodes interleaved pixels with one format and set of options. Move-only. */
+class encoder {
+public:
+    explicit encoder(format output_format)
+        : handle_(jpegexp_encoder_new(static_cast<int>(output_format))) {
+        if (!handle_) {
+            throw error(JPEG_EXP_ERROR_UNSUPPORTED_FORMAT, "unknown output format");
+        }
+    }
+
+    encoder(format output_format, const encode_options& options) : encoder(output_format) {
+        set_options(options);
+    }
+
+    /* Replaces the options. */
+    encoder& set_options(const encode_options& options) {
+        detail::check(jpegexp_encoder_set_options(handle_.get(), &options));
+        return *this;
+    }
+
+    /* Size in bytes of a destination that holds the encoded image even when the pixels
+     * do not compress. */
+    std::size_t estimated_size(std::uint32_t width, std::uint32_t height,
+                               std::uint32_t components) const {
+        std::size_t size = 0;
+        detail::check(
+            jpegexp_encoder_get_estimated_size(handle_.get(), width, height, components, &size));
+        return size;
+    }
+
+    /* Encodes `width` x `height` pixels of `components` samples into `destination`, which
+     * holds `size` bytes, and returns the length of the stream. Samples wider than 8 bits
+     * are little-endian 16-bit values. */
+    std::size_t encode(const void* pixels, std::uint32_t width, std::uint32_t height,
+                       std::uint32_t components, void* destination, std::size_t size) {
+        std::size_t bytes_written = 0;
+        detail::check(jpegexp_encoder_encode(handle_.get(),
+                                             static_cast<const unsigned char*>(pixels), width,
+                                             height, components,
+                                             static_cast<unsigned char*>(destination), size,
+                                             &bytes_written));
+        return bytes_written;
+    }
+
+    /* Encodes into a new buffer that is as long as the stream. */
+    std::vector<std::uint8_t> encode(const void* pixels, std::uint32_t width,
+                                     std::uint32_t height, std::uint32_t components) {
+        std::vector<std::uint8_t> stream(estimated_size(width, height, components));
+        stream.resize(encode(pixels, width, height, components, stream.data(), stream.size()));
+        return stream;
+    }
+
+    /* The C handle, for calls the wrapper does not cover. */
+    JpegExpEncoder* get() const noexcept { return handle_.get(); }
+
+private:
+    std::unique_ptr<JpegExpEncoder, detail::encoder_deleter> handle_;
+};
+
+}  // namespace jpegexp
+
+#endif /* JPEGEXP_HPP */
@@ -0,0 +1,7 @@
+"""Universal JPEG codec supporting JPEG, JPEG-LS, JPEG 2000 and HTJ2K.
+
+The codecs live in the native ``jpegexp._jpegexp`` module, re-exported here.
+``jpegexp.pillow_plugin`` registers them with Pillow when imported.
+"""
+
+from ._jpegexp import *  # noqa: F401,F403
@@ -0,0 +1,145 @@
+"""Pillow plugin for the formats Pillow cannot open by itself.
+
+Importing this module registers JPEG-LS (``.jls``) and JPEG 2000 / HTJ2K codestreams and
+files (``.j2k``, ``.j2c``, ``.jp2``, ``.jph``, ``.jhc``) with ``PIL.Image.open``::
+
+    import jpegexp.pillow_plugin  # noqa: F401
+    from PIL import Image
+
+    image = Image.open("ct_slice.jls")
+
+Both formats are tried before Pillow's own plugins, so JPEG 2000 files are decoded by
+jpegexp even when Pillow was built with OpenJPEG. Baseline JPEG is left to Pillow. Only
+decoding is registered; use ``jpegexp.Encoder`` to write these formats.
+
+Single-component images open as ``L`` (8 bits), ``I;16`` (9 to 16 bits) or ``I``
+(signed); images with 2, 3 or 4 components open as ``LA``, ``RGB`` or ``RGBA``, with
+samples wider than 8 bits reduced to 8 bits since Pillow has no such modes.
+"""
+
+import struct
+
+import numpy as np
+from PIL import Image, ImageFile
+
+from . import Decoder, get_info
+
+_JP2_SIGNATURE = b"\x00\x00\x00\x0cjP  \r\n\x87\n"
+_J2K_SIGNATURE = b"\xff\x4f\xff\x51"
+
+# JPEG markers that end the search for the frame header: SOFn other than DHT (C4),
+# JPG (C8) and DAC (CC), SOS (DA) and EOI (D9).
+_JPEG_SOF_MARKERS = {m for m in range(0xC0, 0xD0) if m not in (0xC4, 0xC8, 0xCC)}
+_JPEGLS_SOF_MARKER = 0xF7
+
+
+def _is_jpegls(fp):
+    """Reads the marker segments of a JPEG stream up to its frame header, which is SOF55
+    for JPEG-LS."""
+    if fp.read(2) != b"\xff\xd8":
+        return False
+    while True:
+        marker = fp.read(2)
+        if len(marker) < 2 or marker[0] != 0xFF:
+            return False
+        if marker[1] == _JPEGLS_SOF_MARKER:
+            return True
+        if marker[1] in _JPEG_SOF_MARKERS or marker[1] in (0xD9, 0xDA):
+            return False
+        length = fp.read(2)
+        if len(length) < 2:
+            return False
+        fp.seek(struct.unpack(">H", length)[0] - 2, 1)
+
+
+def _mode(info):
+    """The Pillow mode of an image described by ``get_info``."""
+    signed = any(component.signed for component in info.component_info)
+    if info.components == 1:
+        if signed:
+            return "I"
+        return "L" if info.bits_per_sample <= 8 else "I;16"
+    modes = {2: "LA", 3: "RGB", 4: "RGBA"}
+    if info.components not in modes:
+        msg = f"{info.components} components cannot be represented in Pillow"
+        raise SyntaxError(msg)
+    return modes[info.components]
+
+
+class _JpegexpImageFile(ImageFile.ImageFile):
+    def _open(self):
+        data = self.fp.read()
+        try:
+            info = get_info(data)
+        except ValueError as e:
+            raise SyntaxError(str(e)) from e
+        self._size = (info.width, info.height)
+        self._mode = _mode(info)
+        self.info["jpegexp_format"] = info.format
+        if info.dpi is not None:
+            self.info["dpi"] = info.dpi
+        self.tile = [("jpegexp", (0, 0) + self.size, 0, (data, info.bits_per_sample))]
+
+
+class JpeglsImageFile(_JpegexpImageFile):
+    format = "JPEG-LS"
+    format_description = "JPEG-LS (ISO/IEC 14495-1)"
+
+    def _open(self):
+        if not _is_jpegls(self.fp):
+            msg = "not a JPEG-LS file"
+            raise SyntaxError(msg)
+        self.fp.seek(0)
+        super()._open()
+
+
+class J2kImageFile(_JpegexpImageFile):
+    format = "J2K"
+    format_description = "JPEG 2000 / HTJ2K (ISO/IEC 15444-1, 15444-15)"
+
+
+class JpegexpDecoder(ImageFile.PyDecoder):
+    """Decodes the whole image at once from the data read by the image file."""
+
+    _pulls_fd = True
+
+    def decode(self, buffer):
+        data, bits_per_sample = self.args
+        array = Decoder().decode(data)
+        if self.mode == "I":
+            array = array.astype(np.int32)
+        elif self.mode == "I;16":
+            array = array.astype("<u2")
+        elif array.dtype != np.uint8:
+            if array.dtype.kind == "i":
+                array = array.astype(np.int32) + (1 << (bits_per_sample - 1))
+            array = (array >> max(bits_per_sample - 8, 0)).astype(np.uint8)
+        self.set_as_raw(array.tobytes(), self.mode)
+        return -1, 0
+
+
+def _accept_jpeg(prefix):
+    return prefix[:3] == b"\xff\xd8\xff"
+
+
+def _accept_j2k(prefix):
+    return prefix[:4] == _J2K_SIGNATURE or prefix[:12] == _JP2_SIGNATURE
+
+
+def _register_first(format, factory, accept):
+    Image.register_open(format, factory, accept)
+    # Ahead of Pillow's JPEG plugin, which claims JPEG-LS streams and then fails on them,
+    # and its JPEG 2000 plugin.
+    Image.ID.remove(format)
+    Image.ID.insert(0, format)
+
+
+_register_first(JpeglsImageFile.format, JpeglsImageFile, _accept_jpeg)
+Image.register_extension(JpeglsImageFile.format, ".jls")
+Image.register_mime(JpeglsImageFile.format, "image/jls")
+
+_register_first(J2kImageFile.format, J2kImageFile, _accept_j2k)
+Image.register_extensions(J2kImageFile.format, [".j2k", ".j2c", ".jp2", ".jph", ".jhc"])
+Image.register_mime(J2kImageFile.format, "image/jp2")
+
+Image.register_decoder("jpegexp", JpegexpDecoder)
@@ -1,8 +1,14 @@
 //! Python bindings for jpegexp-rs using PyO3.
 
+use std::borrow::Cow;
+
+use numpy::{Element, PyArray1, PyArrayDyn, PyReadonlyArrayDyn};
 use pyo3::prelude::*;
 use pyo3::types::PyBytes;
 
+use jpegexp_rs::codec::Format;
+use jpegexp_rs::jpeg2000::image::J2kSamples;
+
 /// Image information class.
 #[pyclass]
 #[derive(Clone)]
@@ -17,6 +23,11 @@ struct ImageInfo {
     bits_per_sample: u32,
     #[pyo3(get)]
     format: String,
+    #[pyo3(get)]
+    component_info: Vec<ComponentInfo>,
+    /// Horizontal and vertical pixel density in dots per inch, or None.
+    #[pyo3(get)]
+    dpi: Option<(u32, u32)>,
 }
 
 #[pymethods]
@@ -29,6 +40,49 @@ impl ImageInfo {
     }
 }
 
+/// Precision, signedness and subsampling of one component.
+#[pyclass]
+#[derive(Clone)]
+struct ComponentInfo {
+    #[pyo3(get)]
+    bits_per_sample: u32,
+    #[pyo3(get)]
+    signed: bool,
+    #[pyo3(get)]
+    horizontal_subsampling: u32,
+    #[pyo3(get)]
+    vertical_subsampling: u32,
+}
+
+#[pymethods]
+impl ComponentInfo {
+    fn __repr__(&self) -> String {
+        format!(
+            "ComponentInfo(bits={}, signed={}, subsampling={}x{})",
+            self.bits_per_sample,
+            if self.signed { "True" } else { "False" },
+            self.horizontal_subsampling,
+            self.vertical_subsampling
+        )
+    }
+}
+
+fn dpi(data: &[u8]) -> Option<(u32, u32)> {
+    jpegexp_rs::resolution::read_resolution(data).map(|r| (r.x_dpi, r.y_dpi))
+}
+
+fn component_info(components: &[jpegexp_rs::ComponentInfo]) -> Vec<ComponentInfo> {
+    components
+        .iter()
+        .map(|component| ComponentInfo {
+            bits_per_sample: component.bits_per_sample as u32,
+            signed: component.signed,
+            horizontal_subsampling: component.horizontal_subsampling as u32,
+            vertical_subsampling: component.vertical_subsampling as u32,
+        })
+        .collect()
+}
+
 /// Decode a JPEG file to raw pixels.
 ///
 /// Args:
@@ -38,303 +92,1173 @@ impl ImageInfo {
 ///     Raw pixel data as bytes
 #[pyfunction]
 fn decode(py: Python<'_>, data: &[u8]) -> PyResult<Py<PyBytes>> {
-    let pixels = if data.starts_with(&[0xFF, 0xD8]) {
-        decode_jpeg1(data)?
-    } else if data.starts_with(&[0xFF, 0x4F]) || data.starts_with(b"\x00\x00\x00\x0CjP") {
-        decode_j2k(data)?
-    } else {
-        decode_jpegls(data)?
-    };
-
-    Ok(PyBytes::new(py, &pixels).into())
+    let image = py.allow_threads(|| decode_image(data))?;
+    Ok(PyBytes::new(py, &image.pixels).into())
 }
 
 /// Decode a file path to raw pixels.
 #[pyfunction]
 fn decode_file(py: Python<'_>, path: &str) -> PyResult<Py<PyBytes>> {
-    let data = std::fs::read(path)
+    let data = py
+        .allow_threads(|| std::fs::read(path))
         .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(format!("{}", e)))?;
     decode(py, &data)
 }
 
+/// Decode a JPEG file to a numpy array.
+///
+/// Args:
+///     data: JPEG file bytes
+///
+/// Returns:
+///     Array of shape (height, width) for single component images and
+///     (height, width, components) otherwise, with dtype uint8, or uint16 for JPEG-LS
+///     images with more than 8 bits per sample. The samples are decoded straight into
+///     the array's memory.
+#[pyfunction]
+fn decode_numpy(py: Python<'_>, data: &[u8]) -> PyResult<PyObject> {
+    Decoder::default().decode(py, data)
+}
+
 /// Get image information without decoding.
 #[pyfunction]
 fn get_info(data: &[u8]) -> PyResult<ImageInfo> {
-    if data.starts_with(&[0xFF, 0xD8]) {
-        let mut reader = jpegexp_rs::jpeg_stream_reader::JpegStreamReader::new(data);
-        let mut spiff = None;
-        reader
-            .read_header(&mut spiff)
-            .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e)))?;
-        let info = reader.frame_info();
-        let format = if reader.is_progressive {
-            "jpeg-progressive"
-        } else if reader.is_lossless {
-            "jpeg-lossless"
-        } else {
-            "jpeg"
-        };
-        Ok(ImageInfo {
-            width: info.width,
-            height: info.height,
-            components: info.component_count as u32,
-            bits_per_sample: info.bits_per_sample as u32,
-            format: format.to_string(),
+    match detect_format(data)? {
+        Format::Jpeg1 => {
+            let mut reader = jpegexp_rs::jpeg_stream_reader::JpegStreamReader::new(data);
+            let mut spiff = None;
+            reader
+                .read_header(&mut spiff)
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e)))?;
+            let info = reader.frame_info();
+            let header = jpegexp_rs::jpeg1::read_metadata(data)
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e)))?;
+            let format = if header.is_progressive() {
+                "jpeg-progressive"
+            } else if header.is_lossless() {
+                "jpeg-lossless"
+            } else {
+                "jpeg"
+            };
+            Ok(ImageInfo {
+                width: info.width,
+                height: info.height,
+                components: info.component_count as u32,
+                bits_per_sample: info.bits_per_sample as u32,
+                format: format.to_string(),
+                component_info: component_info(&reader.component_info()),
+                dpi: dpi(data),
+            })
+        }
+        Format::Jpeg2000 => {
+            // The main header holds everything; the tiles are not read.
+            let mut reader = jpegexp_rs::jpeg_stream_reader::JpegStreamReader::new(data);
+            let mut parser = jpegexp_rs::jpeg2000::parser::J2kParser::new(&mut reader);
+            let image = parser
+                .parse_info()
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e)))?;
+            let format = if image.cap.as_ref().is_some_and(|cap| cap.is_htj2k()) {
+                "htj2k"
+            } else {
+                "j2k"
+            };
+            let (width, height) = image.size();
+            Ok(ImageInfo {
+                width,
+                height,
+                components: image.component_count,
+                bits_per_sample: image.bits_per_sample() as u32,
+                format: format.to_string(),
+                component_info: component_info(&image.component_info()),
+                dpi: dpi(data),
+            })
+        }
+        Format::Jpegls => {
+            let mut decoder = jpegexp_rs::jpegls::JpeglsDecoder::new(data);
+            decoder
+                .read_header()
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e)))?;
+            let info = decoder.frame_info();
+            Ok(ImageInfo {
+                width: info.width,
+                height: info.height,
+                components: info.component_count as u32,
+                bits_per_sample: info.bits_per_sample as u32,
+                format: "jpegls".to_string(),
+                component_info: component_info(&decoder.component_info()),
+                dpi: dpi(data),
+            })
+        }
+    }
+}
+
+/// Reusable encoder whose options are set once, as keyword arguments or attributes.
+///
+/// Args:
+///     format: "jpegls" (default), "jpeg" or "j2k"
+///     near_lossless: JPEG-LS NEAR parameter; 0 (the default) is lossless
+///     interleave_mode: JPEG-LS interleave mode, "none" (default), "line" or "sample"
+///     color_transformation: JPEG-LS color transformation, "none" (default), "hp1",
+///         "hp2" or "hp3"
+///     bits_per_sample: JPEG-LS sample precision; defaults to the width of the dtype
+///     quality: JPEG and JPEG 2000 quality (1-100); defaults to the codec's default
+///     restart_interval: JPEG restart interval in MCUs, JPEG-LS restart interval in lines;
+///         0 (the default) disables it
+///     decomposition_levels: JPEG 2000 wavelet decomposition levels (default 5)
+///     irreversible: JPEG 2000 9-7 irreversible (True, the default) or 5-3 reversible
+///         transform
+#[pyclass]
+#[derive(Clone)]
+struct Encoder {
+    format: Format,
+    #[pyo3(get, set)]
+    near_lossless: i32,
+    interleave_mode: jpegexp_rs::jpegls::InterleaveMode,
+    color_transformation: jpegexp_rs::jpegls::ColorTransformation,
+    #[pyo3(get, set)]
+    bits_per_sample: Option<i32>,
+    #[pyo3(get, set)]
+    quality: Option<u8>,
+    #[pyo3(get, set)]
+    restart_interval: u16,
+    #[pyo3(get, set)]
+    decomposition_levels: Option<u8>,
+    #[pyo3(get, set)]
+    irreversible: bool,
+}
+
+#[pymethods]
+impl Encoder {
+    #[new]
+    #[pyo3(signature = (
+        format = "jpegls",
+        *,
+        near_lossless = 0,
+        interleave_mode = "none",
+        color_transformation = "none",
+        bits_per_sample = None,
+        quality = None,
+        restart_interval = 0,
+        decomposition_levels = None,
+        irreversible = true
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        format: &str,
+        near_lossless: i32,
+        interleave_mode: &str,
+        color_transformation: &str,
+        bits_per_sample: Option<i32>,
+        quality: Option<u8>,
+        restart_interval: u16,
+        decomposition_levels: Option<u8>,
+        irreversible: bool,
+    ) -> PyResult<Self> {
+        Ok(Self {
+            format: parse_format(format)?,
+            near_lossless,
+            interleave_mode: parse_interleave_mode(interleave_mode)?,
+            color_transformation: parse_color_transformation(color_transformation)?,
+            bits_per_sample,
+            quality,
+            restart_interval,
+            decomposition_levels,
+            irreversible,
         })
-    } else if data.starts_with(&[0xFF, 0x4F]) || data.starts_with(b"\x00\x00\x00\x0CjP") {
-        let mut reader = jpegexp_rs::jpeg_stream_reader::JpegStreamReader::new(data);
-        let mut decoder = jpegexp_rs::jpeg2000::decoder::J2kDecoder::new(&mut reader);
-        let image = decoder
-            .decode()
+    }
+
+    #[getter]
+    fn format(&self) -> &'static str {
+        self.format.name()
+    }
+
+    #[setter]
+    fn set_format(&mut self, format: &str) -> PyResult<()> {
+        self.format = parse_format(format)?;
+        Ok(())
+    }
+
+    #[getter]
+    fn interleave_mode(&self) -> &'static str {
+        match self.interleave_mode {
+            jpegexp_rs::jpegls::InterleaveMode::None => "none",
+            jpegexp_rs::jpegls::InterleaveMode::Line => "line",
+            jpegexp_rs::jpegls::InterleaveMode::Sample => "sample",
+        }
+    }
+
+    #[setter]
+    fn set_interleave_mode(&mut self, interleave_mode: &str) -> PyResult<()> {
+        self.interleave_mode = parse_interleave_mode(interleave_mode)?;
+        Ok(())
+    }
+
+    #[getter]
+    fn color_transformation(&self) -> &'static str {
+        match self.color_transformation {
+            jpegexp_rs::jpegls::ColorTransformation::None => "none",
+            jpegexp_rs::jpegls::ColorTransformation::Hp1 => "hp1",
+            jpegexp_rs::jpegls::ColorTransformation::Hp2 => "hp2",
+            jpegexp_rs::jpegls::ColorTransformation::Hp3 => "hp3",
+        }
+    }
+
+    #[setter]
+    fn set_color_transformation(&mut self, color_transformation: &str) -> PyResult<()> {
+        self.color_transformation = parse_color_transformation(color_transformation)?;
+        Ok(())
+    }
+
+    /// Encode pixels with the current options.
+    ///
+    /// `pixels` is either raw 8-bit bytes, in which case `width`, `height` and
+    /// `components` are required, or a numpy array of shape (height, width) or
+    /// (height, width, components). uint16 arrays are only accepted for JPEG-LS.
+    #[pyo3(signature = (pixels, width = None, height = None, components = None))]
+    fn encode(
+        &self,
+        py: Python<'_>,
+        pixels: Pixels<'_>,
+        width: Option<u32>,
+        height: Option<u32>,
+        components: Option<u32>,
+    ) -> PyResult<Py<PyBytes>> {
+        let (pixels, mut frame_info) = pixels.source(width, height, components)?;
+        if let Some(bits) = self.bits_per_sample {
+            frame_info.bits_per_sample = bits;
+        }
+        self.encode_frame(py, &pixels, frame_info)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Encoder(format='{}', near_lossless={}, interleave_mode='{}', quality={:?})",
+            self.format.name(),
+            self.near_lossless,
+            self.interleave_mode(),
+            self.quality
+        )
+    }
+}
+
+impl Encoder {
+    fn with_format(format: Format) -> Self {
+        Self {
+            format,
+            near_lossless: 0,
+            interleave_mode: jpegexp_rs::jpegls::InterleaveMode::None,
+            color_transformation: jpegexp_rs::jpegls::ColorTransformation::None,
+            bits_per_sample: None,
+            quality: None,
+            restart_interval: 0,
+            decomposition_levels: None,
+            irreversible: true,
+        }
+    }
+
+    fn encode_frame(
+        &self,
+        py: Python<'_>,
+        pixels: &[u8],
+        frame_info: jpegexp_rs::FrameInfo,
+    ) -> PyResult<Py<PyBytes>> {
+        if self.format != Format::Jpegls && frame_info.bits_per_sample != 8 {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "{} encoding requires 8-bit samples",
+                self.format.name()
+            )));
+        }
+
+        let encoded = py
+            .allow_threads(|| self.encode_pixels(pixels, frame_info))
             .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e)))?;
-        let format = if image
-            .cap
-            .as_ref()
-            .map_or(false, |c| (c.pcap & (1 << 14)) != 0)
-        {
-            "htj2k"
+
+        Ok(PyBytes::new(py, &encoded).into())
+    }
+
+    /// Runs the codec; called without holding the GIL.
+    fn encode_pixels(
+        &self,
+        pixels: &[u8],
+        frame_info: jpegexp_rs::FrameInfo,
+    ) -> Result<Vec<u8>, jpegexp_rs::JpeglsError> {
+        let mut encoder = jpegexp_rs::codec::Encoder::for_format(self.format);
+        if let Some(quality) = self.quality {
+            encoder.set_quality(quality);
+        }
+        encoder.set_near_lossless(self.near_lossless);
+        encoder.set_interleave_mode(self.interleave_mode);
+        encoder.set_color_transformation(self.color_transformation);
+        encoder.set_restart_interval(self.restart_interval);
+        if let Some(levels) = self.decomposition_levels {
+            encoder.set_decomposition_levels(levels);
+        }
+        encoder.set_irreversible(self.irreversible);
+        encoder.encode(pixels, &frame_info)
+    }
+}
+
+/// Reusable decoder that returns numpy arrays.
+///
+/// Args:
+///     output_layout: "interleaved" (default) returns arrays of shape
+///         (height, width, components); "planar" returns (components, height, width),
+///         as used by DICOM planar configuration 1. Single component images are always
+///         (height, width).
+#[pyclass]
+#[derive(Clone, Default)]
+struct Decoder {
+    output_layout: jpegexp_rs::OutputLayout,
+}
+
+#[pymethods]
+impl Decoder {
+    #[new]
+    #[pyo3(signature = (*, output_layout = "interleaved"))]
+    fn new(output_layout: &str) -> PyResult<Self> {
+        Ok(Self {
+            output_layout: parse_output_layout(output_layout)?,
+        })
+    }
+
+    #[getter]
+    fn output_layout(&self) -> &'static str {
+        match self.output_layout {
+            jpegexp_rs::OutputLayout::Interleaved => "interleaved",
+            jpegexp_rs::OutputLayout::Planar => "planar",
+        }
+    }
+
+    #[setter]
+    fn set_output_layout(&mut self, output_layout: &str) -> PyResult<()> {
+        self.output_layout = parse_output_layout(output_layout)?;
+        Ok(())
+    }
+
+    /// Decode JPEG, JPEG-LS or JPEG 2000 bytes to a numpy array with dtype uint8, or
+    /// uint16 for images with more than 8 bits per sample. Signed JPEG 2000 components
+    /// give int8 or int16 arrays.
+    fn decode(&self, py: Python<'_>, data: &[u8]) -> PyResult<PyObject> {
+        let output_layout = self.output_layout;
+        let format = detect_format(data)?;
+        if format == Format::Jpeg1 {
+            let mut reader = jpegexp_rs::jpeg_stream_reader::JpegStreamReader::new(data);
+            let mut spiff = None;
+            reader
+                .read_header(&mut spiff)
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e)))?;
+            let shape = self.shape(&reader.frame_info());
+            decode_into_array::<u8>(py, shape, |pixels| {
+                let mut decoder = jpegexp_rs::jpeg1::decoder::Jpeg1Decoder::new(data);
+                decoder.read_header()?;
+                decoder.set_output_layout(output_layout);
+                decoder.decode(pixels)
+            })
+        } else if format == Format::Jpeg2000 {
+            let (samples, info) = py.allow_threads(|| decode_j2k_samples(data, output_layout))?;
+            let shape = self.shape(&info);
+            Ok(match samples {
+                J2kSamples::U8(s) => PyArray1::from_vec(py, s).reshape(shape)?.to_object(py),
+                J2kSamples::I8(s) => PyArray1::from_vec(py, s).reshape(shape)?.to_object(py),
+                J2kSamples::U16(s) => PyArray1::from_vec(py, s).reshape(shape)?.to_object(py),
+                J2kSamples::I16(s) => PyArray1::from_vec(py, s).reshape(shape)?.to_object(py),
+            })
         } else {
-            "j2k"
+            let mut decoder = jpegexp_rs::jpegls::JpeglsDecoder::new(data);
+            decoder
+                .read_header()
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e)))?;
+            let info = decoder.frame_info();
+            let shape = self.shape(&info);
+            let decode = |pixels: &mut [u8]| {
+                let mut decoder = jpegexp_rs::jpegls::JpeglsDecoder::new(data);
+                decoder.read_header()?;
+                decoder.set_output_layout(output_layout);
+                decoder.decode(pixels)
+            };
+            if info.bits_per_sample <= 8 {
+                decode_into_array::<u8>(py, shape, decode)
+            } else {
+                decode_into_array::<u16>(py, shape, decode)
+            }
+        }
+    }
+
+    /// Iterate over the image in chunks, yielding (x, y, width, height, array) with the
+    /// array shaped like the result of `decode`. JPEG 2000 images give their tiles,
+    /// clipped to the image area and positioned from the image origin; JPEG and JPEG-LS
+    /// images give strips of `rows_per_strip` rows across the image.
+    ///
+    /// JPEG strips are decoded as the iterator advances, each going on where the previous
+    /// one stopped; a sequential JPEG holds little more than one strip, a progressive one
+    /// the coefficients of the whole image. JPEG 2000 tiles are decoded one at a time.
+    /// JPEG-LS and lossless JPEG lines depend on the lines above them, so their samples
+    /// are decoded once and held until the iterator is dropped, then copied out chunk by
+    /// chunk.
+    #[pyo3(signature = (data, rows_per_strip = 256))]
+    fn iter_tiles(
+        &self,
+        py: Python<'_>,
+        data: &[u8],
+        rows_per_strip: u32,
+    ) -> PyResult<TileIterator> {
+        let rows_per_strip = rows_per_strip.max(1);
+        let strips = |info: &jpegexp_rs::FrameInfo| {
+            (0..info.height)
+                .step_by(rows_per_strip as usize)
+                .map(|y| (0, y, info.width, rows_per_strip.min(info.height - y)))
+                .collect::<Vec<_>>()
         };
-        Ok(ImageInfo {
-            width: image.width,
-            height: image.height,
-            components: image.component_count,
-            bits_per_sample: 8,
-            format: format.to_string(),
-        })
-    } else {
-        let mut decoder = jpegexp_rs::jpegls::JpeglsDecoder::new(data);
-        decoder
-            .read_header()
-            .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e)))?;
-        let info = decoder.frame_info();
-        Ok(ImageInfo {
-            width: info.width,
-            height: info.height,
-            components: info.component_count as u32,
-            bits_per_sample: info.bits_per_sample as u32,
-            format: "jpegls".to_string(),
+        let format = detect_format(data)?;
+        let (source, tiles) = match format {
+            Format::Jpeg1 => {
+                let mut decoder = jpegexp_rs::jpeg1::decoder::Jpeg1Decoder::new(data);
+                decoder.read_header().map_err(|e| {
+                    PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e))
+                })?;
+                let (width, height) = decoder.output_size();
+                let info = jpegexp_rs::FrameInfo {
+                    width,
+                    height,
+                    bits_per_sample: 8,
+                    component_count: decoder.output_component_count() as i32,
+                };
+                let tiles = strips(&info);
+                let lossless = jpegexp_rs::jpeg1::read_metadata(data)
+                    .map_err(|e| {
+                        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e))
+                    })?
+                    .is_lossless();
+                let source = if lossless {
+                    // A crop of a lossless frame decodes the whole frame; do that once.
+                    let size = info
+                        .decoded_size(jpegexp_rs::OutputLayout::Interleaved)
+                        .map_err(|e| {
+                            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e))
+                        })?;
+                    let mut pixels = vec![0u8; size];
+                    py.allow_threads(|| decoder.decode(&mut pixels))
+                        .map_err(|e| {
+                            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e))
+                        })?;
+                    TileSource::Decoded(J2kSamples::U8(pixels), info)
+                } else {
+                    let mut decoder =
+                        jpegexp_rs::jpeg1::decoder::Jpeg1Decoder::from_vec(data.to_vec());
+                    decoder.read_header().map_err(|e| {
+                        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e))
+                    })?;
+                    decoder.set_output_layout(self.output_layout);
+                    TileSource::Jpeg1(Box::new(decoder), info)
+                };
+                (source, tiles)
+            }
+            Format::Jpegls => {
+                let mut decoder = jpegexp_rs::jpegls::JpeglsDecoder::new(data);
+                decoder.read_header().map_err(|e| {
+                    PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e))
+                })?;
+                let info = decoder.frame_info();
+                let size = info
+                    .decoded_size(jpegexp_rs::OutputLayout::Interleaved)
+                    .map_err(|e| {
+                        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e))
+                    })?;
+                let mut decode = |pixels: &mut [u8]| {
+                    py.allow_threads(|| decoder.decode(pixels)).map_err(|e| {
+                        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e))
+                    })
+                };
+                let samples = if info.bits_per_sample <= 8 {
+                    let mut pixels = vec![0u8; size];
+                    decode(&mut pixels)?;
+                    J2kSamples::U8(pixels)
+                } else {
+                    let mut samples = vec![0u16; size / 2];
+                    // SAFETY: the bytes are those of `samples`, which is not used while they
+                    // are borrowed; any byte pattern is a valid u16, and the decoder writes
+                    // 16-bit samples in native byte order.
+                    let pixels = unsafe {
+                        std::slice::from_raw_parts_mut(samples.as_mut_ptr() as *mut u8, size)
+                    };
+                    decode(pixels)?;
+                    J2kSamples::U16(samples)
+                };
+                (TileSource::Decoded(samples, info), strips(&info))
+            }
+            Format::Jpeg2000 => {
+                // Only the main header is read here; each tile is decoded on its own.
+                let mut reader = jpegexp_rs::jpeg_stream_reader::JpegStreamReader::new(data);
+                let mut parser = jpegexp_rs::jpeg2000::parser::J2kParser::new(&mut reader);
+                let image = parser.parse_info().map_err(|e| {
+                    PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e))
+                })?;
+                let (width, height) = image.size();
+                let info = jpegexp_rs::FrameInfo {
+                    width,
+                    height,
+                    bits_per_sample: image.channel_bits_per_sample() as i32,
+                    component_count: image.channel_count() as i32,
+                };
+                (TileSource::Jpeg2000(data.to_vec(), info), j2k_tiles(image))
+            }
+        };
+        Ok(TileIterator {
+            decoder: self.clone(),
+            source,
+            tiles: tiles.into_iter().enumerate(),
         })
     }
+
+    fn __repr__(&self) -> String {
+        format!("Decoder(output_layout='{}')", self.output_layout())
+    }
+}
+
+impl Decoder {
+    /// Shape of the array that holds the image described by `info`.
+    fn shape(&self, info: &jpegexp_rs::FrameInfo) -> Vec<usize> {
+        let (width, height) = (info.width as usize, info.height as usize);
+        let components = info.component_count as usize;
+        match self.output_layout {
+            _ if components == 1 => vec![height, width],
+            jpegexp_rs::OutputLayout::Interleaved => vec![height, width, components],
+            jpegexp_rs::OutputLayout::Planar => vec![components, height, width],
+        }
+    }
+}
+
+/// Iterator returned by `Decoder.iter_tiles`.
+#[pyclass]
+struct TileIterator {
+    decoder: Decoder,
+    source: TileSource,
+    /// The chunks not yet yielded, with the index of the JPEG 2000 tile each one is.
+    tiles: std::iter::Enumerate<std::vec::IntoIter<(u32, u32, u32, u32)>>,
+}
+
+/// Where the chunks of a [`TileIterator`] come from.
+enum TileSource {
+    /// A DCT-based JPEG stream, decoded strip by strip with
+    /// [`Jpeg1Decoder::decode_rows`](jpegexp_rs::jpeg1::decoder::Jpeg1Decoder::decode_rows).
+    Jpeg1(
+        Box<jpegexp_rs::jpeg1::decoder::Jpeg1Decoder<'static>>,
+        jpegexp_rs::FrameInfo,
+    ),
+    /// A JPEG 2000 stream, decoded tile by tile.
+    Jpeg2000(Vec<u8>, jpegexp_rs::FrameInfo),
+    /// The interleaved samples of the whole image.
+    Decoded(J2kSamples, jpegexp_rs::FrameInfo),
+}
+
+#[pymethods]
+impl TileIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<(u32, u32, u32, u32, PyObject)>> {
+        let Some((index, (x, y, width, height))) = self.tiles.next() else {
+            return Ok(None);
+        };
+        let output_layout = self.decoder.output_layout;
+        let array = match &mut self.source {
+            TileSource::Jpeg1(decoder, info) => {
+                let shape = self.decoder.shape(&jpegexp_rs::FrameInfo {
+                    width,
+                    height,
+                    ..*info
+                });
+                decode_into_array::<u8>(py, shape, |pixels| decoder.decode_rows(pixels, height))?
+            }
+            TileSource::Jpeg2000(data, info) => {
+                let shape = self.decoder.shape(&jpegexp_rs::FrameInfo {
+                    width,
+                    height,
+                    ..*info
+                });
+                let components = info.component_count as usize;
+                let samples = py.allow_threads(|| {
+                    let mut reader = jpegexp_rs::jpeg_stream_reader::JpegStreamReader::new(data);
+                    let mut decoder = jpegexp_rs::jpeg2000::decoder::J2kDecoder::new(&mut reader);
+                    decoder.set_tiles(&[index as u16]);
+                    let image = decoder.decode().map_err(|e| {
+                        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e))
+                    })?;
+                    let tile = image
+                        .reconstruct_tile(
+                            index as u32,
+                            jpegexp_rs::jpeg2000::image::J2kUpsampling::Nearest,
+                        )
+                        .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e))?;
+                    Ok::<_, PyErr>(to_layout(tile.samples, components, output_layout))
+                })?;
+                match samples {
+                    J2kSamples::U8(s) => PyArray1::from_vec(py, s).reshape(shape)?.to_object(py),
+                    J2kSamples::I8(s) => PyArray1::from_vec(py, s).reshape(shape)?.to_object(py),
+                    J2kSamples::U16(s) => PyArray1::from_vec(py, s).reshape(shape)?.to_object(py),
+                    J2kSamples::I16(s) => PyArray1::from_vec(py, s).reshape(shape)?.to_object(py),
+                }
+            }
+            TileSource::Decoded(samples, info) => {
+                let shape = self.decoder.shape(&jpegexp_rs::FrameInfo {
+                    width,
+                    height,
+                    ..*info
+                });
+                let rect = (x, y, width, height);
+                match samples {
+                    J2kSamples::U8(s) => {
+                        PyArray1::from_vec(py, copy_tile(s, info, rect, output_layout))
+                            .reshape(shape)?
+                            .to_object(py)
+                    }
+                    J2kSamples::I8(s) => {
+                        PyArray1::from_vec(py, copy_tile(s, info, rect, output_layout))
+                            .reshape(shape)?
+                            .to_object(py)
+                    }
+                    J2kSamples::U16(s) => {
+                        PyArray1::from_vec(py, copy_tile(s, info, rect, output_layout))
+                            .reshape(shape)?
+                            .to_object(py)
+                    }
+                    J2kSamples::I16(s) => {
+                        PyArray1::from_vec(py, copy_tile(s, info, rect, output_layout))
+                            .reshape(shape)?
+                            .to_object(py)
+                    }
+                }
+            }
+        };
+        Ok(Some((x, y, width, height, array)))
+    }
+
+    /// Number of chunks not yet yielded.
+    fn __len__(&self) -> usize {
+        self.tiles.len()
+    }
+}
+
+/// Copies the `rect` (x, y, width, height) of interleaved `samples` in `output_layout`.
+fn copy_tile<T: Copy>(
+    samples: &[T],
+    info: &jpegexp_rs::FrameInfo,
+    (x, y, width, height): (u32, u32, u32, u32),
+    output_layout: jpegexp_rs::OutputLayout,
+) -> Vec<T> {
+    let components = info.component_count.max(1) as usize;
+    let (x, y, width, height) = (x as usize, y as usize, width as usize, height as usize);
+    let row = |r: usize| {
+        let start = ((y + r) * info.width as usize + x) * components;
+        &samples[start..start + width * components]
+    };
+    match output_layout {
+        jpegexp_rs::OutputLayout::Interleaved => (0..height).flat_map(row).copied().collect(),
+        jpegexp_rs::OutputLayout::Planar => (0..components)
+            .flat_map(|c| {
+                (0..height).flat_map(move |r| row(r).iter().skip(c).step_by(components).copied())
+            })
+            .collect(),
+    }
+}
+
+/// Tiles of the SIZ tile grid as (x, y, width, height), clipped to the image area, in
+/// raster order. Positions count from the image origin (XOsiz, YOsiz), the top-left
+/// corner of the decoded image.
+fn j2k_tiles(image: &jpegexp_rs::jpeg2000::image::J2kImage) -> Vec<(u32, u32, u32, u32)> {
+    (0..image.tile_count())
+        .map(|index| {
+            let (tx0, ty0, tx1, ty1) = image.tile_bounds(index);
+            (
+                tx0 - image.x_origin,
+                ty0 - image.y_origin,
+                tx1.saturating_sub(tx0),
+                ty1.saturating_sub(ty0),
+            )
+        })
+        .collect()
 }
 
 /// Encode raw pixels to JPEG.
+///
+/// `pixels` is either raw bytes, in which case `width`, `height` and `components` are
+/// required, or a uint8 numpy array of shape (height, width) or (height, width, components).
 #[pyfunction]
 fn encode_jpeg(
     py: Python<'_>,
-    pixels: &[u8],
-    width: u32,
-    height: u32,
-    components: u32,
+    pixels: Pixels<'_>,
+    width: Option<u32>,
+    height: Option<u32>,
+    components: Option<u32>,
 ) -> PyResult<Py<PyBytes>> {
-    let frame_info = jpegexp_rs::FrameInfo {
-        width,
-        height,
-        bits_per_sample: 8,
-        component_count: components as i32,
-    };
-
-    let mut dest = vec![0u8; pixels.len() * 2];
-    let mut encoder = jpegexp_rs::jpeg1::encoder::Jpeg1Encoder::new();
-    let len = encoder
-        .encode(pixels, &frame_info, &mut dest)
-        .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e)))?;
-    dest.truncate(len);
-
-    Ok(PyBytes::new(py, &dest).into())
+    Encoder::with_format(Format::Jpeg1).encode(py, pixels, width, height, components)
 }
 
 /// Encode raw pixels to JPEG-LS.
+///
+/// `pixels` is either raw 8-bit bytes, in which case `width`, `height` and `components`
+/// are required, or a uint8 or uint16 numpy array of shape (height, width) or
+/// (height, width, components). `bits_per_sample` defaults to the width of the array's
+/// dtype and may be lowered for uint16 arrays holding e.g. 12-bit data.
+/// `near_lossless` is the NEAR parameter; omit it or pass 0 for lossless coding.
 #[pyfunction]
 fn encode_jpegls(
     py: Python<'_>,
-    pixels: &[u8],
-    width: u32,
-    height: u32,
-    components: u32,
+    pixels: Pixels<'_>,
+    width: Option<u32>,
+    height: Option<u32>,
+    components: Option<u32>,
+    near_lossless: Option<i32>,
+    bits_per_sample: Option<i32>,
 ) -> PyResult<Py<PyBytes>> {
-    let frame_info = jpegexp_rs::FrameInfo {
-        width,
-        height,
-        bits_per_sample: 8,
-        component_count: components as i32,
-    };
-
-    let mut dest = vec![0u8; pixels.len() * 2];
-    let mut encoder = jpegexp_rs::jpegls::JpeglsEncoder::new(&mut dest);
-    encoder
-        .set_frame_info(frame_info)
-        .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e)))?;
-    let len = encoder
-        .encode(pixels)
-        .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e)))?;
-    dest.truncate(len);
-
-    Ok(PyBytes::new(py, &dest).into())
+    let mut encoder = Encoder::with_format(Format::Jpegls);
+    encoder.near_lossless = near_lossless.unwrap_or(0);
+    encoder.bits_per_sample = bits_per_sample;
+    encoder.encode(py, pixels, width, height, components)
 }
 
 /// Encode raw pixels to JPEG 2000.
+///
+/// `pixels` is either raw bytes, in which case `width`, `height` and `components` are
+/// required, or a uint8 numpy array of shape (height, width) or (height, width, components).
 #[pyfunction]
 fn encode_j2k(
     py: Python<'_>,
-    pixels: &[u8],
-    width: u32,
-    height: u32,
-    components: u32,
+    pixels: Pixels<'_>,
+    width: Option<u32>,
+    height: Option<u32>,
+    components: Option<u32>,
     quality: Option<u8>,
 ) -> PyResult<Py<PyBytes>> {
-    let frame_info = jpegexp_rs::FrameInfo {
-        width,
-        height,
-        bits_per_sample: 8,
-        component_count: components as i32,
-    };
-
-    let mut dest = vec![0u8; pixels.len() * 4];
-    let mut encoder = jpegexp_rs::jpeg2000::encoder::J2kEncoder::new();
-    if let Some(q) = quality {
-        encoder.set_quality(q);
-    }
-    let len = encoder
-        .encode(pixels, &frame_info, &mut dest)
-        .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e)))?;
-    dest.truncate(len);
-
-    Ok(PyBytes::new(py, &dest).into())
+    let mut encoder = Encoder::with_format(Format::Jpeg2000);
+    encoder.quality = quality;
+    encoder.encode(py, pixels, width, height, components)
 }
 
 /// Transcode between formats.
 #[pyfunction]
 fn transcode(py: Python<'_>, data: &[u8], target: &str) -> PyResult<Py<PyBytes>> {
-    // Decode
-    let (pixels, width, height, components) = if data.starts_with(&[0xFF, 0xD8]) {
-        decode_jpeg1_with_info(data)?
-    } else if data.starts_with(&[0xFF, 0x4F]) || data.starts_with(b"\x00\x00\x00\x0CjP") {
-        decode_j2k_with_info(data)?
-    } else {
-        decode_jpegls_with_info(data)?
-    };
-
-    // Re-encode
-    match target {
-        "jpeg" => encode_jpeg(py, &pixels, width, height, components),
-        "jpegls" => encode_jpegls(py, &pixels, width, height, components),
-        "j2k" | "jpeg2000" => encode_j2k(py, &pixels, width, height, components, None),
-        _ => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+    let format = Format::from_name(target).ok_or_else(|| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
             "Unsupported target format: {}",
             target
+        ))
+    })?;
+    let image = py.allow_threads(|| decode_image(data))?;
+    Encoder::with_format(format).encode_frame(py, &image.pixels, image.frame_info)
+}
+
+// Internal option helpers
+
+fn parse_format(name: &str) -> PyResult<Format> {
+    Format::from_name(name).ok_or_else(|| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Unsupported format: {}", name))
+    })
+}
+
+fn parse_interleave_mode(name: &str) -> PyResult<jpegexp_rs::jpegls::InterleaveMode> {
+    match name {
+        "none" => Ok(jpegexp_rs::jpegls::InterleaveMode::None),
+        "line" => Ok(jpegexp_rs::jpegls::InterleaveMode::Line),
+        "sample" => Ok(jpegexp_rs::jpegls::InterleaveMode::Sample),
+        _ => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "Unsupported interleave mode: {}",
+            name
         ))),
     }
 }
 
-// Internal decode helpers
+fn parse_color_transformation(name: &str) -> PyResult<jpegexp_rs::jpegls::ColorTransformation> {
+    match name {
+        "none" => Ok(jpegexp_rs::jpegls::ColorTransformation::None),
+        "hp1" => Ok(jpegexp_rs::jpegls::ColorTransformation::Hp1),
+        "hp2" => Ok(jpegexp_rs::jpegls::ColorTransformation::Hp2),
+        "hp3" => Ok(jpegexp_rs::jpegls::ColorTransformation::Hp3),
+        _ => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "Unsupported color transformation: {}",
+            name
+        ))),
+    }
+}
 
-fn decode_jpeg1(data: &[u8]) -> PyResult<Vec<u8>> {
-    let (pixels, _, _, _) = decode_jpeg1_with_info(data)?;
-    Ok(pixels)
+fn parse_output_layout(name: &str) -> PyResult<jpegexp_rs::OutputLayout> {
+    match name {
+        "interleaved" => Ok(jpegexp_rs::OutputLayout::Interleaved),
+        "planar" => Ok(jpegexp_rs::OutputLayout::Planar),
+        _ => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "Unsupported output layout: {}",
+            name
+        ))),
+    }
 }
 
-fn decode_jpeg1_with_info(data: &[u8]) -> PyResult<(Vec<u8>, u32, u32, u32)> {
-    let mut reader = jpegexp_rs::jpeg_stream_reader::JpegStreamReader::new(data);
-    let mut spiff = None;
-    reader
-        .read_header(&mut spiff)
-        .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e)))?;
-    let info = reader.frame_info();
-    let width = info.width;
-    let height = info.height;
-    let components = info.component_count as u32;
+/// Reorder the channels of a numpy array of shape (height, width, channels) or
+/// (height, width).
+///
+/// Channel `c` of the result is channel `order[c]` of `pixels`, so `[2, 1, 0]` turns RGB
+/// into BGR and `[0, 0, 0]` gray into RGB. Returns an array of the same dtype and shape
+/// (height, width, len(order)).
+#[pyfunction]
+fn swizzle(py: Python<'_>, pixels: Samples<'_>, order: Vec<usize>) -> PyResult<PyObject> {
+    let channels_out = order.len();
+    match pixels {
+        Samples::U8(array) => convert_pixels(py, &array, channels_out, |samples, channels| {
+            jpegexp_rs::pixelops::swizzle(samples, channels, &order)
+        }),
+        Samples::U16(array) => convert_pixels(py, &array, channels_out, |samples, channels| {
+            jpegexp_rs::pixelops::swizzle(samples, channels, &order)
+        }),
+        Samples::I16(array) => convert_pixels(py, &array, channels_out, |samples, channels| {
+            jpegexp_rs::pixelops::swizzle(samples, channels, &order)
+        }),
+    }
+}
 
-    let pixel_count = (width * height * components) as usize;
-    let mut pixels = vec![0u8; pixel_count];
+/// Append an alpha channel to a numpy array of shape (height, width, channels) or
+/// (height, width), e.g. RGB to RGBA.
+///
+/// `alpha` defaults to the largest value of the dtype (opaque).
+#[pyfunction]
+#[pyo3(signature = (pixels, alpha = None))]
+fn add_alpha(py: Python<'_>, pixels: Samples<'_>, alpha: Option<i64>) -> PyResult<PyObject> {
+    fn alpha_value<T: TryFrom<i64>>(alpha: Option<i64>, max: T) -> PyResult<T> {
+        match alpha {
+            None => Ok(max),
+            Some(alpha) => T::try_from(alpha).map_err(|_| {
+                PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "alpha {} does not fit the array's dtype",
+                    alpha
+                ))
+            }),
+        }
+    }
 
-    let mut decoder = jpegexp_rs::jpeg1::decoder::Jpeg1Decoder::new(data);
-    decoder
-        .read_header()
-        .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e)))?;
-    decoder
-        .decode(&mut pixels)
+    let channels_out = pixels.channels()? + 1;
+    match pixels {
+        Samples::U8(array) => {
+            let alpha = alpha_value(alpha, u8::MAX)?;
+            convert_pixels(py, &array, channels_out, |samples, channels| {
+                jpegexp_rs::pixelops::add_alpha(samples, channels, alpha)
+            })
+        }
+        Samples::U16(array) => {
+            let alpha = alpha_value(alpha, u16::MAX)?;
+            convert_pixels(py, &array, channels_out, |samples, channels| {
+                jpegexp_rs::pixelops::add_alpha(samples, channels, alpha)
+            })
+        }
+        Samples::I16(array) => {
+            let alpha = alpha_value(alpha, i16::MAX)?;
+            convert_pixels(py, &array, channels_out, |samples, channels| {
+                jpegexp_rs::pixelops::add_alpha(samples, channels, alpha)
+            })
+        }
+    }
+}
+
+/// Map a uint16 or int16 numpy array to uint8 with the DICOM linear window of `width`
+/// values around `center`, e.g. center 40 and width 400 for soft tissue in CT.
+///
+/// Returns a uint8 array of the same shape.
+#[pyfunction]
+fn window_level(
+    py: Python<'_>,
+    pixels: Samples<'_>,
+    center: f64,
+    width: f64,
+) -> PyResult<PyObject> {
+    fn window<T: Element + Copy + Sync + Into<f64>>(
+        py: Python<'_>,
+        array: &PyReadonlyArrayDyn<'_, T>,
+        center: f64,
+        width: f64,
+    ) -> PyResult<PyObject> {
+        let shape = array.shape().to_vec();
+        let samples = owned_samples(array);
+        let windowed = py
+            .allow_threads(|| jpegexp_rs::pixelops::window_level(&samples, center, width))
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e)))?;
+        Ok(PyArray1::from_vec(py, windowed)
+            .reshape(shape)?
+            .to_object(py))
+    }
+
+    match pixels {
+        Samples::U8(array) => window(py, &array, center, width),
+        Samples::U16(array) => window(py, &array, center, width),
+        Samples::I16(array) => window(py, &array, center, width),
+    }
+}
+
+/// Swap the bytes of every 16-bit sample in raw bytes, e.g. to turn the little-endian
+/// output of `decode` into big-endian samples.
+#[pyfunction]
+fn swap_bytes_16(py: Python<'_>, data: &[u8]) -> PyResult<Py<PyBytes>> {
+    let mut swapped = data.to_vec();
+    jpegexp_rs::pixelops::swap_bytes_16(&mut swapped)
         .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e)))?;
+    Ok(PyBytes::new(py, &swapped).into())
+}
+
+// Internal numpy helpers
 
-    Ok((pixels, width, height, components))
+/// Samples accepted by the pixel conversion functions.
+#[derive(FromPyObject)]
+enum Samples<'py> {
+    U8(PyReadonlyArrayDyn<'py, u8>),
+    U16(PyReadonlyArrayDyn<'py, u16>),
+    I16(PyReadonlyArrayDyn<'py, i16>),
 }
 
-fn decode_j2k(data: &[u8]) -> PyResult<Vec<u8>> {
-    let (pixels, _, _, _) = decode_j2k_with_info(data)?;
-    Ok(pixels)
+impl Samples<'_> {
+    /// Number of channels of the array, see [`pixel_shape`].
+    fn channels(&self) -> PyResult<usize> {
+        let shape = match self {
+            Samples::U8(array) => array.shape(),
+            Samples::U16(array) => array.shape(),
+            Samples::I16(array) => array.shape(),
+        };
+        Ok(pixel_shape(shape)?.2)
+    }
 }
 
-fn decode_j2k_with_info(data: &[u8]) -> PyResult<(Vec<u8>, u32, u32, u32)> {
-    let mut reader = jpegexp_rs::jpeg_stream_reader::JpegStreamReader::new(data);
-    let mut decoder = jpegexp_rs::jpeg2000::decoder::J2kDecoder::new(&mut reader);
-    let image = decoder
-        .decode()
+/// Height, width and channels of an array of shape (height, width) or
+/// (height, width, channels).
+fn pixel_shape(shape: &[usize]) -> PyResult<(usize, usize, usize)> {
+    match *shape {
+        [h, w] => Ok((h, w, 1)),
+        [h, w, c] => Ok((h, w, c)),
+        _ => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "expected an array of shape (height, width) or (height, width, channels), got {:?}",
+            shape
+        ))),
+    }
+}
+
+/// A copy of the elements of `array` in C order. The conversions run without the GIL, when
+/// other Python threads may write the array, so they never read its memory directly.
+fn owned_samples<T: Element + Copy>(array: &PyReadonlyArrayDyn<'_, T>) -> Vec<T> {
+    match array.as_slice() {
+        Ok(slice) => slice.to_vec(),
+        Err(_) => array.as_array().iter().copied().collect(),
+    }
+}
+
+/// Applies a conversion from pixels of `channels` samples to pixels of `channels_out`
+/// samples and returns the result as an array of shape (height, width, channels_out).
+fn convert_pixels<T: Element + Copy + Send + Sync>(
+    py: Python<'_>,
+    array: &PyReadonlyArrayDyn<'_, T>,
+    channels_out: usize,
+    convert: impl FnOnce(&[T], usize) -> Result<Vec<T>, jpegexp_rs::JpeglsError> + Send,
+) -> PyResult<PyObject> {
+    let (height, width, channels) = pixel_shape(array.shape())?;
+    let samples = owned_samples(array);
+    let converted = py
+        .allow_threads(|| convert(&samples, channels))
         .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e)))?;
+    Ok(PyArray1::from_vec(py, converted)
+        .reshape(vec![height, width, channels_out])?
+        .to_object(py))
+}
 
-    let width = image.width;
-    let height = image.height;
-    let components = image.component_count;
-
-    let planar_pixels = image
-        .reconstruct_pixels()
-        .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e))?;
-
-    // Convert planar (RRR...GGG...BBB...) to interleaved (RGBRGB...)
-    let pixel_count = (width * height) as usize;
-    let mut pixels = vec![0u8; planar_pixels.len()];
-
-    if components == 1 {
-        pixels.copy_from_slice(&planar_pixels);
-    } else {
-        for i in 0..pixel_count {
-            for c in 0..components {
-                let src_idx = (c as usize * pixel_count) + i;
-                let dst_idx = (i * components as usize) + c as usize;
-                if src_idx < planar_pixels.len() && dst_idx < pixels.len() {
-                    pixels[dst_idx] = planar_pixels[src_idx];
-                }
+/// Pixel data accepted by the encode functions.
+#[derive(FromPyObject)]
+enum Pixels<'py> {
+    U8(PyReadonlyArrayDyn<'py, u8>),
+    U16(PyReadonlyArrayDyn<'py, u16>),
+    Bytes(&'py [u8]),
+}
+
+impl Pixels<'_> {
+    /// Returns the samples as packed bytes and the frame they describe. Arrays take their
+    /// dimensions from their shape and are copied, as other Python threads may write them
+    /// while the encoder runs without the GIL; bytes are immutable and are borrowed.
+    fn source(
+        &self,
+        width: Option<u32>,
+        height: Option<u32>,
+        components: Option<u32>,
+    ) -> PyResult<(Cow<'_, [u8]>, jpegexp_rs::FrameInfo)> {
+        let (pixels, shape, bits_per_sample): (Cow<'_, [u8]>, &[usize], i32) = match self {
+            Pixels::Bytes(bytes) => {
+                let required = |value: Option<u32>, name: &str| {
+                    value.ok_or_else(|| {
+                        PyErr::new::<pyo3::exceptions::PyTypeError, _>(format!(
+                            "{} is required when pixels is bytes",
+                            name
+                        ))
+                    })
+                };
+                let frame_info = jpegexp_rs::FrameInfo {
+                    width: required(width, "width")?,
+                    height: required(height, "height")?,
+                    bits_per_sample: 8,
+                    component_count: required(components, "components")? as i32,
+                };
+                return Ok((Cow::Borrowed(*bytes), frame_info));
             }
-        }
+            Pixels::U8(array) => (Cow::Owned(owned_samples(array)), array.shape(), 8),
+            Pixels::U16(array) => {
+                let pixels = array
+                    .as_array()
+                    .iter()
+                    .flat_map(|sample| sample.to_ne_bytes())
+                    .collect();
+                (Cow::Owned(pixels), array.shape(), 16)
+            }
+        };
+
+        let (array_height, array_width, array_components) = match *shape {
+            [h, w] => (h, w, 1),
+            [h, w, c] => (h, w, c),
+            _ => {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "expected an array of shape (height, width) or (height, width, components), got {:?}",
+                    shape
+                )));
+            }
+        };
+        let dimension = |value: Option<u32>, actual: usize, name: &str| match value {
+            Some(value) if value as usize != actual => {
+                Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "{} {} does not match the array shape {:?}",
+                    name, value, shape
+                )))
+            }
+            _ => Ok(actual as u32),
+        };
+        let frame_info = jpegexp_rs::FrameInfo {
+            width: dimension(width, array_width, "width")?,
+            height: dimension(height, array_height, "height")?,
+            bits_per_sample,
+            component_count: dimension(components, array_components, "components")? as i32,
+        };
+        Ok((pixels, frame_info))
     }
+}
 
-    Ok((pixels, width, height, components))
+/// Creates an array of the given shape and lets `decode` write the samples straight into
+/// its memory. `decode` runs without holding the GIL.
+fn decode_into_array<T: Element>(
+    py: Python<'_>,
+    shape: Vec<usize>,
+    decode: impl FnOnce(&mut [u8]) -> Result<(), jpegexp_rs::JpeglsError> + Send,
+) -> PyResult<PyObject> {
+    let array = PyArrayDyn::<T>::zeros(py, shape, false);
+    // SAFETY: the array was just created, is C-contiguous and is not shared yet, so no
+    // Python code can touch it while the GIL is released; its elements are plain integers,
+    // so any byte pattern written by the decoder is valid.
+    let pixels = unsafe {
+        std::slice::from_raw_parts_mut(
+            array.data() as *mut u8,
+            array.len() * std::mem::size_of::<T>(),
+        )
+    };
+    py.allow_threads(|| decode(pixels))
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e)))?;
+    Ok(array.to_object(py))
 }
 
-fn decode_jpegls(data: &[u8]) -> PyResult<Vec<u8>> {
-    let (pixels, _, _, _) = decode_jpegls_with_info(data)?;
-    Ok(pixels)
+// Internal decode helpers
+
+/// Format of `data`, or a ValueError if it is not a JPEG, JPEG-LS or JPEG 2000 stream.
+fn detect_format(data: &[u8]) -> PyResult<Format> {
+    jpegexp_rs::codec::detect_format(data).ok_or_else(|| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            "Unsupported format: not a JPEG, JPEG-LS or JPEG 2000 stream",
+        )
+    })
 }
 
-fn decode_jpegls_with_info(data: &[u8]) -> PyResult<(Vec<u8>, u32, u32, u32)> {
-    let mut decoder = jpegexp_rs::jpegls::JpeglsDecoder::new(data);
-    decoder
-        .read_header()
-        .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e)))?;
-    let info = decoder.frame_info();
-    let width = info.width;
-    let height = info.height;
-    let components = info.component_count as u32;
-
-    let pixel_count = (width * height * components) as usize;
-    let mut pixels = vec![0u8; pixel_count];
-    decoder
-        .decode(&mut pixels)
+/// Decodes a stream of any supported format to interleaved samples.
+fn decode_image(data: &[u8]) -> PyResult<jpegexp_rs::DecodedImage> {
+    detect_format(data)?;
+    jpegexp_rs::codec::Decoder::auto(data)
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e)))
+}
+
+/// Decodes a JPEG 2000 image to samples in the requested layout.
+fn decode_j2k_samples(
+    data: &[u8],
+    output_layout: jpegexp_rs::OutputLayout,
+) -> PyResult<(J2kSamples, jpegexp_rs::FrameInfo)> {
+    with_j2k_image(data, |image| {
+        let samples = image
+            .reconstruct_samples()
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e))?;
+        let components = image.channel_count() as usize;
+        let samples = to_layout(samples, components, output_layout);
+        let (width, height) = image.size();
+        let info = jpegexp_rs::FrameInfo {
+            width,
+            height,
+            bits_per_sample: image.channel_bits_per_sample() as i32,
+            component_count: components as i32,
+        };
+        Ok((samples, info))
+    })
+}
+
+/// Decodes a JPEG 2000 codestream or JP2 file and passes the image to `f`.
+fn with_j2k_image<R>(
+    data: &[u8],
+    f: impl FnOnce(&jpegexp_rs::jpeg2000::image::J2kImage) -> PyResult<R>,
+) -> PyResult<R> {
+    let mut reader = jpegexp_rs::jpeg_stream_reader::JpegStreamReader::new(data);
+    let mut decoder = jpegexp_rs::jpeg2000::decoder::J2kDecoder::new(&mut reader);
+    let image = decoder
+        .decode()
         .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("{:?}", e)))?;
+    f(image)
+}
+
+/// Interleaved samples in `output_layout`.
+fn to_layout(
+    samples: J2kSamples,
+    components: usize,
+    output_layout: jpegexp_rs::OutputLayout,
+) -> J2kSamples {
+    match output_layout {
+        jpegexp_rs::OutputLayout::Interleaved => samples,
+        jpegexp_rs::OutputLayout::Planar => match samples {
+            J2kSamples::U8(s) => J2kSamples::U8(to_planes(&s, components)),
+            J2kSamples::I8(s) => J2kSamples::I8(to_planes(&s, components)),
+            J2kSamples::U16(s) => J2kSamples::U16(to_planes(&s, components)),
+            J2kSamples::I16(s) => J2kSamples::I16(to_planes(&s, components)),
+        },
+    }
+}
 
-    Ok((pixels, width, height, components))
+/// Interleaved samples (RGBRGB...) to component planes (RRR...GGG...BBB...).
+fn to_planes<T: Copy>(samples: &[T], components: usize) -> Vec<T> {
+    (0..components)
+        .flat_map(|c| samples.iter().skip(c).step_by(components).copied())
+        .collect()
 }
 
-/// jpegexp Python module.
+/// jpegexp Python module, built as `jpegexp._jpegexp` and re-exported by the `jpegexp`
+/// package next to this crate.
 #[pymodule]
+#[pyo3(name = "_jpegexp")]
 fn jpegexp(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
     m.add_class::<ImageInfo>()?;
+    m.add_class::<ComponentInfo>()?;
+    m.add_class::<Encoder>()?;
+    m.add_class::<Decoder>()?;
+    m.add_class::<TileIterator>()?;
     m.add_function(wrap_pyfunction!(decode, m)?)?;
     m.add_function(wrap_pyfunction!(decode_file, m)?)?;
+    m.add_function(wrap_pyfunction!(decode_numpy, m)?)?;
     m.add_function(wrap_pyfunction!(get_info, m)?)?;
     m.add_function(wrap_pyfunction!(encode_jpeg, m)?)?;
     m.add_function(wrap_pyfunction!(encode_jpegls, m)?)?;
     m.add_function(wrap_pyfunction!(encode_j2k, m)?)?;
     m.add_function(wrap_pyfunction!(transcode, m)?)?;
+    m.add_function(wrap_pyfunction!(swizzle, m)?)?;
+    m.add_function(wrap_pyfunction!(add_alpha, m)?)?;
+    m.add_function(wrap_pyfunction!(window_level, m)?)?;
+    m.add_function(wrap_pyfunction!(swap_bytes_16, m)?)?;
     Ok(())
 }
@@ -0,0 +1,79 @@
+"""Chunked decoding with ``Decoder.iter_tiles``.
+
+Run with ``maturin develop && pytest tests`` from the ``python`` directory.
+"""
+
+from pathlib import Path
+
+import numpy as np
+import pytest
+
+import jpegexp
+
+IMAGES = Path(__file__).parents[2] / "tests/jpegls_test_images"
+
+
+def assemble(chunks, shape):
+    """Place every (x, y, width, height, array) chunk in an array of ``shape``."""
+    image = np.zeros(shape, dtype=chunks[0][4].dtype)
+    for x, y, width, height, array in chunks:
+        assert array.shape[:2] == (height, width)
+        image[y : y + height, x : x + width] = array
+    return image
+
+
+@pytest.mark.parametrize("encode", [jpegexp.encode_jpegls, jpegexp.encode_jpeg])
+def test_strips(encode):
+    pixels = (np.arange(40 * 24, dtype=np.uint16) % 251).astype(np.uint8).reshape(24, 40)
+    data = encode(pixels)
+
+    chunks = list(jpegexp.Decoder().iter_tiles(data, rows_per_strip=10))
+    assert [chunk[:4] for chunk in chunks] == [(0, 0, 40, 10), (0, 10, 40, 10), (0, 20, 40, 4)]
+    np.testing.assert_array_equal(assemble(chunks, (24, 40)), jpegexp.decode_numpy(data))
+
+
+def test_strips_rgb_planar():
+    pixels = np.arange(8 * 6 * 3, dtype=np.uint8).reshape(6, 8, 3)
+    data = jpegexp.encode_jpegls(pixels)
+
+    chunks = list(jpegexp.Decoder(output_layout="planar").iter_tiles(data, rows_per_strip=4))
+    assert [chunk[:4] for chunk in chunks] == [(0, 0, 8, 4), (0, 4, 8, 2)]
+    assert chunks[0][4].shape == (3, 4, 8)
+    np.testing.assert_array_equal(chunks[1][4], pixels[4:].transpose(2, 0, 1))
+
+
+def test_j2k_tiles():
+    # 40x24 pixels of 128 in 16x16 tiles: 3 columns, 2 rows, partial at the right and bottom.
+    data = (IMAGES / "tiles_40x24_16x16.j2c").read_bytes()
+
+    tiles = jpegexp.Decoder().iter_tiles(data)
+    assert len(tiles) == 6
+    chunks = list(tiles)
+    assert [chunk[:4] for chunk in chunks] == [
+        (0, 0, 16, 16),
+        (16, 0, 16, 16),
+        (32, 0, 8, 16),
+        (0, 16, 16, 8),
+        (16, 16, 16, 8),
+        (32, 16, 8, 8),
+    ]
+    np.testing.assert_array_equal(assemble(chunks, (24, 40)), np.full((24, 40), 128))
+
+
+def test_j2k_tiles_with_image_origin():
+    # The same tile grid with the image area starting at (5, 3) on the reference grid:
+    # the first tile column and row are cut, and positions count from the image origin.
+    data = (IMAGES / "tiles_40x24_origin_5_3.j2c").read_bytes()
+    info = jpegexp.get_info(data)
+    assert (info.width, info.height) == (40, 24)
+
+    chunks = list(jpegexp.Decoder().iter_tiles(data))
+    assert [chunk[:4] for chunk in chunks] == [
+        (0, 0, 11, 13),
+        (11, 0, 16, 13),
+        (27, 0, 13, 13),
+        (0, 13, 11, 11),
+        (11, 13, 16, 11),
+        (27, 13, 13, 11),
+    ]
+    np.testing.assert_array_equal(assemble(chunks, (24, 40)), np.full((24, 40), 128))
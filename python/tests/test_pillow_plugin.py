@@ -0,0 +1,56 @@
+"""Opening JPEG-LS and JPEG 2000 files through ``PIL.Image.open``.
+
+Run with ``maturin develop && pytest tests`` from the ``python`` directory.
+"""
+
+from pathlib import Path
+
+import numpy as np
+import pytest
+
+Image = pytest.importorskip("PIL.Image")
+
+import jpegexp
+import jpegexp.pillow_plugin  # noqa: F401
+
+# A 64x64 gradient encoded losslessly by OpenJPEG.
+J2K_FILE = Path(__file__).parents[2] / "tests/jpegls_test_images/gradient_64x64_gray_lossless.j2c"
+
+
+def test_open_jpegls(tmp_path):
+    pixels = np.arange(32 * 16 * 3, dtype=np.uint8).reshape(16, 32, 3)
+    path = tmp_path / "image.jls"
+    path.write_bytes(jpegexp.encode_jpegls(pixels))
+
+    with Image.open(path) as image:
+        assert image.format == "JPEG-LS"
+        assert image.mode == "RGB"
+        assert image.size == (32, 16)
+        np.testing.assert_array_equal(np.asarray(image), pixels)
+
+
+def test_open_jpegls_16_bit(tmp_path):
+    pixels = (np.arange(16 * 16, dtype=np.uint16) * 15).reshape(16, 16)
+    path = tmp_path / "image.jls"
+    path.write_bytes(jpegexp.encode_jpegls(pixels, bits_per_sample=12))
+
+    with Image.open(path) as image:
+        assert image.mode == "I;16"
+        np.testing.assert_array_equal(np.asarray(image), pixels)
+
+
+def test_open_j2k():
+    with Image.open(J2K_FILE) as image:
+        assert image.format == "J2K"
+        assert image.mode == "L"
+        assert image.size == (64, 64)
+        expected = jpegexp.decode_numpy(J2K_FILE.read_bytes())
+        np.testing.assert_array_equal(np.asarray(image), expected)
+
+
+def test_baseline_jpeg_is_left_to_pillow(tmp_path):
+    path = tmp_path / "image.jpg"
+    Image.new("L", (8, 8), 128).save(path)
+
+    with Image.open(path) as image:
+        assert image.format == "JPEG"
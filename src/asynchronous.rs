@@ -0,0 +1,192 @@
+//! Async wrappers for Tokio-based services (feature `async`).
+//!
+//! Decoding and encoding are CPU-bound and would stall the other tasks of an async
+//! executor, so [`decode_async`] and [`encode_async`] run the [`Decoder`] and [`Encoder`]
+//! on Tokio's blocking thread pool ([`tokio::task::spawn_blocking`]) and await the result.
+//! [`decode_in_row_batches`] hands the image out as a [`Stream`] of row batches as they are
+//! decoded, e.g. to write an HTTP response body in chunks as the client reads it:
+//!
+//! ```rust
+//! use jpegexp_rs::asynchronous::{decode_async, encode_async};
+//! use jpegexp_rs::codec::Encoder;
+//! use jpegexp_rs::{Decoder, Format, FrameInfo};
+//!
+//! # let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+//! # runtime.block_on(async {
+//! let frame_info = FrameInfo { width: 16, height: 16, bits_per_sample: 8, component_count: 1 };
+//! let encoded = encode_async(&Encoder::for_format(Format::Jpegls), vec![7; 256], frame_info)
+//!     .await
+//!     .unwrap();
+//! let image = decode_async(&Decoder::new(), encoded).await.unwrap();
+//! assert_eq!(image.pixels, vec![7; 256]);
+//! # });
+//! ```
+//!
+//! The functions must be called from within a Tokio runtime.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use tokio::sync::mpsc;
+use tokio::task::{spawn_blocking, JoinError};
+
+use crate::codec::Encoder;
+use crate::decoder::{DecodedImage, Decoder};
+use crate::error::JpeglsError;
+use crate::FrameInfo;
+
+/// Number of row batches [`decode_in_row_batches`] keeps ready ahead of the consumer.
+const BATCHES_AHEAD: usize = 2;
+
+/// Decodes `data` with a copy of `decoder` on the blocking thread pool. See
+/// [`Decoder::decode`].
+pub async fn decode_async(decoder: &Decoder, data: Vec<u8>) -> Result<DecodedImage, JpeglsError> {
+    let decoder = *decoder;
+    spawn_blocking(move || decoder.decode(&data))
+        .await
+        .unwrap_or_else(join_failed)
+}
+
+/// Encodes `pixels` with a copy of `encoder` on the blocking thread pool. See
+/// [`Encoder::encode`].
+pub async fn encode_async(
+    encoder: &Encoder,
+    pixels: Vec<u8>,
+    frame_info: FrameInfo,
+) -> Result<Vec<u8>, JpeglsError> {
+    let encoder = *encoder;
+    spawn_blocking(move || encoder.encode(&pixels, &frame_info))
+        .await
+        .unwrap_or_else(join_failed)
+}
+
+/// A panic in the blocking task is passed on to the awaiting task; the task is only
+/// cancelled when the runtime shuts down.
+fn join_failed<T>(error: JoinError) -> Result<T, JpeglsError> {
+    match error.try_into_panic() {
+        Ok(payload) => std::panic::resume_unwind(payload),
+        Err(_) => Err(JpeglsError::InvalidOperation),
+    }
+}
+
+/// Consecutive rows of a decoded image, as yielded by [`DecodedRows`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedRowBatch {
+    /// Dimensions and sample precision of the whole image.
+    pub frame_info: FrameInfo,
+    /// Index of the first row in the batch.
+    pub first_row: u32,
+    /// Number of rows in the batch; the last batch may be shorter.
+    pub row_count: u32,
+    /// Interleaved samples of the rows, laid out like [`DecodedImage::pixels`].
+    pub pixels: Vec<u8>,
+}
+
+/// Decodes `data` with a copy of `decoder` on the blocking thread pool and yields the
+/// image in batches of `rows_per_batch` interleaved rows as they are decoded (the
+/// decoder's [`OutputLayout`](crate::OutputLayout) is not used); see
+/// [`Decoder::decode_row_batches`] for which images are decoded batch by batch and which
+/// whole first. Decoding runs at most two batches ahead of the consumer and stops when the
+/// stream is dropped. A decoding error ends the stream, after the batches decoded before it.
+///
+/// `rows_per_batch` is at least 1.
+pub fn decode_in_row_batches(decoder: &Decoder, data: Vec<u8>, rows_per_batch: u32) -> DecodedRows {
+    let decoder = *decoder;
+    let (sender, receiver) = mpsc::channel(BATCHES_AHEAD);
+    spawn_blocking(move || {
+        let mut closed = false;
+        let send = |frame_info: &FrameInfo, first_row: u32, pixels: &[u8]| {
+            let row_bytes = frame_info.decoded_size(crate::OutputLayout::Interleaved)?
+                / frame_info.height.max(1) as usize;
+            let batch = DecodedRowBatch {
+                frame_info: *frame_info,
+                first_row,
+                row_count: (pixels.len() / row_bytes.max(1)) as u32,
+                pixels: pixels.to_vec(),
+            };
+            // The receiver was dropped: nobody wants the remaining rows.
+            closed = sender.blocking_send(Ok(batch)).is_err();
+            if closed {
+                return Err(JpeglsError::InvalidOperation);
+            }
+            Ok(())
+        };
+        let result = decoder.decode_row_batches(&data, rows_per_batch, send);
+        if let (Err(error), false) = (result, closed) {
+            let _ = sender.blocking_send(Err(error));
+        }
+    });
+    DecodedRows { receiver }
+}
+
+/// Stream of the row batches of a decoded image, returned by [`decode_in_row_batches`].
+#[derive(Debug)]
+pub struct DecodedRows {
+    receiver: mpsc::Receiver<Result<DecodedRowBatch, JpeglsError>>,
+}
+
+impl DecodedRows {
+    /// The next batch, or `None` after the last one.
+    pub async fn next(&mut self) -> Option<Result<DecodedRowBatch, JpeglsError>> {
+        self.receiver.recv().await
+    }
+}
+
+impl Stream for DecodedRows {
+    type Item = Result<DecodedRowBatch, JpeglsError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Format;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_decode_in_row_batches() {
+        let frame_info = FrameInfo {
+            width: 5,
+            height: 7,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let pixels: Vec<u8> = (0..5 * 7 * 3).map(|i| (i * 37 % 256) as u8).collect();
+
+        runtime().block_on(async {
+            let encoder = Encoder::for_format(Format::Jpegls);
+            let encoded = encode_async(&encoder, pixels.clone(), frame_info)
+                .await
+                .unwrap();
+            let image = decode_async(&Decoder::new(), encoded.clone())
+                .await
+                .unwrap();
+            assert_eq!(image.pixels, pixels);
+
+            let mut rows = decode_in_row_batches(&Decoder::new(), encoded, 3);
+            let mut joined = Vec::new();
+            let mut first_rows = Vec::new();
+            while let Some(batch) = rows.next().await {
+                let batch = batch.unwrap();
+                assert_eq!(batch.pixels.len(), batch.row_count as usize * 5 * 3);
+                first_rows.push((batch.first_row, batch.row_count));
+                joined.extend(batch.pixels);
+            }
+            assert_eq!(first_rows, [(0, 3), (3, 3), (6, 1)]);
+            assert_eq!(joined, pixels);
+
+            let mut rows = decode_in_row_batches(&Decoder::new(), vec![0xFF, 0xD8], 3);
+            assert!(rows.next().await.unwrap().is_err());
+            assert!(rows.next().await.is_none());
+        });
+    }
+}
@@ -5,7 +5,10 @@
 
 use clap::{Parser, Subcommand, ValueEnum};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
 /// Universal JPEG codec supporting JPEG, JPEG-LS, JPEG 2000, and HTJ2K
 #[derive(Parser)]
@@ -18,11 +21,19 @@ use std::path::PathBuf;
     jpegexp decode -i image.j2k -o image.ppm -f ppm
     jpegexp encode -i pixels.raw -o image.jls -w 512 -h 512 -c jpegls
     jpegexp transcode -i image.jpg -o image.jls -c jpegls
+    jpegexp transcode -i scan.png -o scan.jls -c jpegls   (with the image-io feature)
+    jpegexp transcode --input-dir ./studies --pattern '**/*.jpg' --output-dir ./out -c jpegls
+    jpegexp transform -i photo.jpg -o rotated.jpg --rotate 90
     jpegexp info -i image.j2k
+    jpegexp suggest -i pixels.raw -w 512 -H 512 -g archival
+    jpegexp compare -a ref.raw -b test.jls --decode --min-psnr 45
+    jpegexp bench -i image.j2k --iterations 50
+    jpegexp dump -i image.jls
 
 SUPPORTED FORMATS:
     Input:  JPEG (.jpg), JPEG-LS (.jls), JPEG 2000 (.j2k/.jp2), HTJ2K (.jph)
     Output: JPEG, JPEG-LS, JPEG 2000 (.j2k)
+    Images: PNG and 8/16-bit TIFF input and output with the image-io feature
 
 For more information, visit: https://github.com/rad-medica/jpegexp-rs")]
 struct Cli {
@@ -46,7 +57,7 @@ enum Commands {
         #[arg(short, long, help = "Path for the output file")]
         output: PathBuf,
 
-        /// Output format: raw (binary pixels) or ppm (Portable PixMap)
+        /// Output format: raw (binary pixels), ppm (Portable PixMap), png or tiff
         #[arg(short, long, default_value = "raw", value_enum)]
         format: OutputFormat,
     },
@@ -54,26 +65,27 @@ enum Commands {
     /// Encode raw pixels to a JPEG format
     ///
     /// Takes raw pixel data and encodes it using the specified codec.
-    /// Input must be raw 8-bit grayscale or RGB pixel data.
+    /// Input must be raw 8-bit grayscale or RGB pixel data, or a PNG or TIFF
+    /// image when built with the image-io feature.
     #[command(visible_alias = "e")]
     Encode {
         /// Input raw pixel file
-        #[arg(short, long, help = "Path to raw pixel data file")]
+        #[arg(short, long, help = "Path to raw pixel data or PNG/TIFF file")]
         input: PathBuf,
 
         /// Output JPEG file
         #[arg(short, long, help = "Path for the encoded output file")]
         output: PathBuf,
 
-        /// Image width in pixels
+        /// Image width in pixels (required for raw input)
         #[arg(short, long)]
-        width: u32,
+        width: Option<u32>,
 
-        /// Image height in pixels
+        /// Image height in pixels (required for raw input)
         #[arg(short = 'H', long)]
-        height: u32,
+        height: Option<u32>,
 
-        /// Number of color components (1=grayscale, 3=RGB)
+        /// Number of color components for raw input (1=grayscale, 3=RGB)
         #[arg(short = 'n', long, default_value = "1")]
         components: u32,
 
@@ -81,11 +93,11 @@ enum Commands {
         #[arg(short, long, default_value = "jpeg", value_enum)]
         codec: Codec,
 
-        /// Quality level (1-100, only for lossy codecs)
+        /// Quality level for JPEG (1-100)
         #[arg(short, long, default_value = "85")]
         quality: u8,
 
-        /// Enable near-lossless mode for JPEG-LS (0=lossless, 1-255=near-lossless)
+        /// Enable near-lossless mode for JPEG-LS (0=lossless, 1-127=maximum error per sample)
         #[arg(long, default_value = "0")]
         near_lossless: u8,
     },
@@ -93,24 +105,100 @@ enum Commands {
     /// Transcode between JPEG formats
     ///
     /// Decodes the input file and re-encodes it using the target codec.
-    /// Useful for converting between JPEG, JPEG-LS, and J2K formats.
+    /// Useful for converting between JPEG, JPEG-LS, and J2K formats, and from
+    /// PNG or TIFF when built with the image-io feature.
+    ///
+    /// Only lossless paths run by default: JPEG to JPEG re-encodes the DCT
+    /// coefficients with optimized Huffman tables, and JPEG-LS output is
+    /// verified to decode to the source pixels. Other targets need --allow-lossy.
+    ///
+    /// With --input-dir, every file matching --pattern is transcoded in parallel
+    /// into --output-dir, keeping its path relative to the input directory.
     #[command(visible_alias = "t")]
     Transcode {
         /// Input JPEG file
-        #[arg(short, long, help = "Path to the input image file")]
-        input: PathBuf,
+        #[arg(
+            short,
+            long,
+            help = "Path to the input image file",
+            required_unless_present = "input_dir",
+            conflicts_with = "input_dir"
+        )]
+        input: Option<PathBuf>,
 
         /// Output JPEG file
-        #[arg(short, long, help = "Path for the transcoded output file")]
-        output: PathBuf,
+        #[arg(
+            short,
+            long,
+            help = "Path for the transcoded output file",
+            required_unless_present = "output_dir",
+            conflicts_with = "output_dir"
+        )]
+        output: Option<PathBuf>,
+
+        /// Directory to search for input files
+        #[arg(long, requires = "output_dir")]
+        input_dir: Option<PathBuf>,
+
+        /// Directory for the transcoded files; subdirectories are created as needed
+        #[arg(long, requires = "input_dir")]
+        output_dir: Option<PathBuf>,
+
+        /// Glob pattern relative to --input-dir, e.g. '**/*.jpg'
+        #[arg(long, default_value = "**/*")]
+        pattern: String,
+
+        /// Number of files to transcode in parallel [default: number of CPUs]
+        #[arg(short, long)]
+        jobs: Option<usize>,
 
         /// Target codec: jpeg, jpegls, j2k, htj2k
         #[arg(short, long, value_enum)]
         codec: Codec,
 
-        /// Quality level (1-100, only for lossy codecs)
+        /// Quality level for JPEG (1-100)
         #[arg(short, long, default_value = "85")]
         quality: u8,
+
+        /// Allow targets that lose information; JPEG to JPEG then re-encodes the pixels
+        #[arg(long)]
+        allow_lossy: bool,
+    },
+
+    /// Rotate, mirror or crop a JPEG without decoding it
+    ///
+    /// Moves the quantized DCT blocks like jpegtran, so the image loses no quality.
+    /// Blocks only move as whole MCUs: mirroring and rotating drop the partial MCUs
+    /// at the edges that would move to the opposite side, and a crop starts at the
+    /// MCU boundary at or before the requested corner.
+    Transform {
+        /// Input JPEG file
+        #[arg(short, long, help = "Path to the input JPEG file")]
+        input: PathBuf,
+
+        /// Output JPEG file
+        #[arg(short, long, help = "Path for the transformed JPEG file")]
+        output: PathBuf,
+
+        /// Rotate clockwise by 90, 180 or 270 degrees
+        #[arg(long, conflicts_with_all = ["flip", "transpose", "transverse"])]
+        rotate: Option<u32>,
+
+        /// Mirror the image horizontally (left to right) or vertically (top to bottom)
+        #[arg(long, value_enum, conflicts_with_all = ["transpose", "transverse"])]
+        flip: Option<FlipDirection>,
+
+        /// Mirror the image across the diagonal from the top left corner
+        #[arg(long, conflicts_with = "transverse")]
+        transpose: bool,
+
+        /// Mirror the image across the diagonal from the top right corner
+        #[arg(long)]
+        transverse: bool,
+
+        /// Keep a rectangle of the transformed image, given as WIDTHxHEIGHT+X+Y
+        #[arg(long)]
+        crop: Option<String>,
     },
 
     /// Display image metadata and codec information
@@ -126,11 +214,159 @@ enum Commands {
         /// Show extended metadata (may decode more of the file)
         #[arg(short, long)]
         extended: bool,
+
+        /// Print the information as a JSON object
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// List every marker segment with its offset, length and parsed fields
+    ///
+    /// Walks JPEG and JPEG-LS marker segments, JPEG 2000 codestream markers and JP2
+    /// boxes without decoding the image, like jpegdump or jpylyzer. Parsing stops at
+    /// the first corrupt or truncated segment, which is reported with its offset.
+    Dump {
+        /// Input file path
+        #[arg(short, long, help = "Path to the image file to inspect")]
+        input: PathBuf,
+    },
+
+    /// Check the structure of a JPEG 2000 codestream or JP2 file
+    ///
+    /// Checks box structure, marker ordering, tile-part lengths (Psot) and packet
+    /// framing (PLT, SOP, EPH). Errors and warnings are listed separately; the exit
+    /// code is 1 if there are errors.
+    Validate {
+        /// Input file path
+        #[arg(short, long, help = "Path to the J2K or JP2 file to validate")]
+        input: PathBuf,
+
+        /// Print the report as a JSON object
+        #[arg(long)]
+        json: bool,
     },
 
     /// List supported codecs and their capabilities
     #[command(visible_alias = "l")]
     List,
+
+    /// Recommend a codec and parameters for raw pixels
+    ///
+    /// Analyzes the entropy and gradient statistics of the image and suggests the
+    /// codec best suited to the intended use, with an expected compression ratio.
+    #[command(visible_alias = "s")]
+    Suggest {
+        /// Input raw pixel file
+        #[arg(short, long, help = "Path to raw pixel data file")]
+        input: PathBuf,
+
+        /// Image width in pixels
+        #[arg(short, long)]
+        width: u32,
+
+        /// Image height in pixels
+        #[arg(short = 'H', long)]
+        height: u32,
+
+        /// Number of color components (1=grayscale, 3=RGB)
+        #[arg(short = 'n', long, default_value = "1")]
+        components: u32,
+
+        /// Bits per sample; samples above 8 bits are read as native-endian 16-bit values
+        #[arg(short, long, default_value = "8")]
+        bits: u8,
+
+        /// Intended use of the encoded image
+        #[arg(short, long, default_value = "archival", value_enum)]
+        goal: SuggestGoal,
+    },
+
+    /// Compare two images and report PSNR, MSE and maximum error
+    ///
+    /// Inputs are raw pixels unless --decode is given, in which case JPEG, JPEG-LS
+    /// and JPEG 2000 files (and PNG/TIFF with the image-io feature) are decoded first.
+    /// Raw inputs take their dimensions from the options or from the other image.
+    /// Exits with status 1 if a --min-psnr or --max-error threshold is not met.
+    #[command(visible_alias = "c")]
+    Compare {
+        /// Reference image
+        #[arg(short = 'a', long, help = "Path to the reference image")]
+        reference: PathBuf,
+
+        /// Image to compare against the reference
+        #[arg(short = 'b', long, help = "Path to the image under test")]
+        test: PathBuf,
+
+        /// Decode encoded inputs before comparing
+        #[arg(short, long)]
+        decode: bool,
+
+        /// Image width in pixels, for raw inputs
+        #[arg(short, long)]
+        width: Option<u32>,
+
+        /// Image height in pixels, for raw inputs
+        #[arg(short = 'H', long)]
+        height: Option<u32>,
+
+        /// Number of color components, for raw inputs
+        #[arg(short = 'n', long, default_value = "1")]
+        components: u32,
+
+        /// Bits per sample for raw inputs; samples above 8 bits are native-endian 16-bit values
+        #[arg(long, default_value = "8")]
+        bits: u8,
+
+        /// Fail if the PSNR is below this value (dB)
+        #[arg(long)]
+        min_psnr: Option<f64>,
+
+        /// Fail if any sample differs by more than this value
+        #[arg(long)]
+        max_error: Option<u32>,
+
+        /// Print the statistics as a JSON object
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Measure decode and encode throughput
+    ///
+    /// Decodes the input repeatedly and re-encodes the decoded pixels, reporting the
+    /// mean, minimum and median time per iteration and the throughput in megapixels
+    /// and megabytes (of decoded samples) per second. Build with --release for
+    /// meaningful numbers.
+    #[command(visible_alias = "b")]
+    Bench {
+        /// Input file path (JPEG, JPEG-LS, J2K, or JP2; PNG/TIFF with image-io)
+        #[arg(short, long, help = "Path to the image file to benchmark")]
+        input: PathBuf,
+
+        /// Number of timed iterations
+        #[arg(
+            short = 'N',
+            long,
+            default_value = "20",
+            value_parser = clap::value_parser!(u32).range(1..)
+        )]
+        iterations: u32,
+
+        /// Number of untimed iterations run first
+        #[arg(long, default_value = "3")]
+        warmup: u32,
+
+        /// Codec for the encode benchmark [default: codec of the input]
+        #[arg(short, long, value_enum)]
+        codec: Option<Codec>,
+
+        /// Quality level for JPEG (1-100)
+        #[arg(short, long, default_value = "85")]
+        quality: u8,
+
+        /// Skip the encode benchmark
+        #[arg(long)]
+        no_encode: bool,
+    },
 }
 
 #[derive(Clone, ValueEnum)]
@@ -139,6 +375,12 @@ enum OutputFormat {
     Raw,
     /// Portable PixMap (PPM/PGM) format
     Ppm,
+    /// Portable Network Graphics, 8 or 16 bits per sample
+    #[cfg(feature = "image-io")]
+    Png,
+    /// Tagged Image File Format, 8 or 16 bits per sample
+    #[cfg(feature = "image-io")]
+    Tiff,
 }
 
 #[derive(Clone, Debug, ValueEnum)]
@@ -153,6 +395,24 @@ enum Codec {
     Htj2k,
 }
 
+#[derive(Clone, ValueEnum)]
+enum FlipDirection {
+    /// Mirror left to right
+    Horizontal,
+    /// Mirror top to bottom
+    Vertical,
+}
+
+#[derive(Clone, ValueEnum)]
+enum SuggestGoal {
+    /// Lossless long-term storage
+    Archival,
+    /// Display in browsers and general viewers
+    Web,
+    /// Teleradiology: bounded error over limited bandwidth
+    Telerad,
+}
+
 fn main() {
     let cli = Cli::parse();
 
@@ -184,11 +444,91 @@ fn main() {
         Commands::Transcode {
             input,
             output,
+            input_dir,
+            output_dir,
+            pattern,
+            jobs,
             codec,
             quality,
-        } => transcode_image(&input, &output, &codec, quality),
-        Commands::Info { input, extended } => show_info(&input, extended),
+            allow_lossy,
+        } => match (input, output, input_dir, output_dir) {
+            (Some(input), Some(output), _, _) => {
+                transcode_image(&input, &output, &codec, quality, allow_lossy)
+            }
+            (_, _, Some(input_dir), Some(output_dir)) => transcode_directory(
+                &input_dir,
+                &output_dir,
+                &pattern,
+                &codec,
+                quality,
+                allow_lossy,
+                jobs,
+            ),
+            _ => Err("Specify --input and --output, or --input-dir and --output-dir".into()),
+        },
+        Commands::Transform {
+            input,
+            output,
+            rotate,
+            flip,
+            transpose,
+            transverse,
+            crop,
+        } => transform_image(
+            &input,
+            &output,
+            rotate,
+            flip,
+            transpose,
+            transverse,
+            crop.as_deref(),
+        ),
+        Commands::Info {
+            input,
+            extended,
+            json,
+        } => show_info(&input, extended, json),
+        Commands::Dump { input } => dump_markers(&input),
+        Commands::Validate { input, json } => validate_jpeg2000(&input, json),
         Commands::List => list_codecs(),
+        Commands::Suggest {
+            input,
+            width,
+            height,
+            components,
+            bits,
+            goal,
+        } => suggest_codec(&input, width, height, components, bits, &goal),
+        Commands::Compare {
+            reference,
+            test,
+            decode,
+            width,
+            height,
+            components,
+            bits,
+            min_psnr,
+            max_error,
+            json,
+        } => {
+            let raw_info = width.zip(height).map(|(width, height)| jpegexp_rs::FrameInfo {
+                width,
+                height,
+                bits_per_sample: bits as i32,
+                component_count: components as i32,
+            });
+            compare_images(
+                &reference, &test, decode, raw_info, min_psnr, max_error, json,
+            )
+        }
+        Commands::Bench {
+            input,
+            iterations,
+            warmup,
+            codec,
+            quality,
+            no_encode,
+        } => bench_image(&input, iterations, warmup, codec, quality, !no_encode),
     };
 
     if let Err(e) = result {
@@ -204,23 +544,45 @@ fn decode_image(
 ) -> Result<(), Box<dyn std::error::Error>> {
     let data = fs::read(input)?;
 
-    let (pixels, width, height, components) = detect_and_decode(&data)?;
+    let image = decode_data(&data)?;
+    let (pixels, frame_info) = (&image.pixels, &image.frame_info);
+    let (width, height, components) = (
+        frame_info.width,
+        frame_info.height,
+        frame_info.component_count as u32,
+    );
 
     match format {
         OutputFormat::Raw => {
-            fs::write(output, &pixels)?;
+            fs::write(output, pixels)?;
             println!(
                 "✓ Decoded {}x{} image ({} components) to {:?}",
                 width, height, components, output
             );
         }
         OutputFormat::Ppm => {
-            write_ppm(output, &pixels, width, height, components)?;
+            write_ppm(output, pixels, width, height, components)?;
             println!(
                 "✓ Decoded {}x{} image ({} components) to {:?} (PPM format)",
                 width, height, components, output
             );
         }
+        #[cfg(feature = "image-io")]
+        OutputFormat::Png => {
+            image_io::write_png(output, pixels, frame_info)?;
+            println!(
+                "✓ Decoded {}x{} image ({} components) to {:?} (PNG format)",
+                width, height, components, output
+            );
+        }
+        #[cfg(feature = "image-io")]
+        OutputFormat::Tiff => {
+            image_io::write_tiff(output, pixels, frame_info)?;
+            println!(
+                "✓ Decoded {}x{} image ({} components) to {:?} (TIFF format)",
+                width, height, components, output
+            );
+        }
     }
     Ok(())
 }
@@ -228,77 +590,63 @@ fn decode_image(
 fn encode_image(
     input: &PathBuf,
     output: &PathBuf,
-    width: u32,
-    height: u32,
+    width: Option<u32>,
+    height: Option<u32>,
     components: u32,
     codec: &Codec,
     quality: u8,
     near_lossless: u8,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let pixels = fs::read(input)?;
-
-    // Validate input size
-    let expected_size = (width * height * components) as usize;
-    if pixels.len() < expected_size {
-        return Err(format!(
-            "Input file too small: expected {} bytes, got {} bytes",
-            expected_size,
-            pixels.len()
-        )
-        .into());
-    }
-
-    let frame_info = jpegexp_rs::FrameInfo {
-        width,
-        height,
-        bits_per_sample: 8,
-        component_count: components as i32,
-    };
+    let data = fs::read(input)?;
 
-    let encoded = match codec {
-        Codec::Jpeg => {
-            let mut dest = vec![0u8; pixels.len() * 2];
-            let mut encoder = jpegexp_rs::jpeg1::encoder::Jpeg1Encoder::new();
-
-            // Set quality using the new API method
-            encoder.set_quality(quality);
-
-            let len = encoder.encode(&pixels[..expected_size], &frame_info, &mut dest)?;
-            dest.truncate(len);
-            dest
-        }
-        Codec::Jpegls => {
-            // Allocate more space: at least 1KB header + 2x input size for worst-case compression
-            let min_size = 1024 + pixels.len() * 2;
-            let mut dest = vec![0u8; min_size];
-            let mut encoder = jpegexp_rs::jpegls::JpeglsEncoder::new(&mut dest);
-            encoder.set_frame_info(frame_info)?;
-            if near_lossless > 0 {
-                encoder.set_near_lossless(near_lossless as i32)?;
+    let (pixels, frame_info) = match read_image_file(&data)? {
+        Some(image) => image,
+        None => {
+            let (Some(width), Some(height)) = (width, height) else {
+                return Err("--width and --height are required for raw pixel input".into());
+            };
+            let mut pixels = data;
+            let frame_info = jpegexp_rs::FrameInfo {
+                width,
+                height,
+                bits_per_sample: 8,
+                component_count: components as i32,
+            };
+
+            // Validate input size
+            let expected_size = frame_info.decoded_size(jpegexp_rs::OutputLayout::Interleaved)?;
+            if pixels.len() < expected_size {
+                return Err(format!(
+                    "Input file too small: expected {} bytes, got {} bytes",
+                    expected_size,
+                    pixels.len()
+                )
+                .into());
             }
-            let len = encoder.encode(&pixels[..expected_size])?;
-            dest.truncate(len);
-            dest
-        }
-        Codec::J2k => {
-            let mut dest = vec![0u8; pixels.len() * 4]; // J2K can be larger
-            let mut encoder = jpegexp_rs::jpeg2000::encoder::J2kEncoder::new();
-            encoder.set_quality(quality);
-            let len = encoder.encode(&pixels[..expected_size], &frame_info, &mut dest)?;
-            dest.truncate(len);
-            dest
-        }
-        Codec::Htj2k => {
-            return Err("HTJ2K encoding not yet implemented".into());
+            pixels.truncate(expected_size);
+            (pixels, frame_info)
         }
     };
+    check_sample_depth(&frame_info, codec)?;
+    let (width, height, components) = (
+        frame_info.width,
+        frame_info.height,
+        frame_info.component_count,
+    );
+
+    let mut encoder = facade_encoder(codec)?;
+    if matches!(codec, Codec::Jpeg | Codec::J2k | Codec::Htj2k) {
+        encoder.set_quality(quality);
+    }
+    encoder.set_near_lossless(near_lossless as i32);
+    let encoded = encoder.encode(&pixels, &frame_info)?;
 
     fs::write(output, &encoded)?;
     println!(
         "✓ Encoded {}x{} image ({} components) to {:?} using {:?} codec",
         width, height, components, output, codec
     );
-    if matches!(codec, Codec::Jpeg | Codec::J2k) && quality != 85 {
+    if matches!(codec, Codec::Jpeg) && quality != 85 {
         println!("  Quality: {}", quality);
     }
     if matches!(codec, Codec::Jpegls) && near_lossless > 0 {
@@ -312,71 +660,326 @@ fn transcode_image(
     output: &PathBuf,
     codec: &Codec,
     quality: u8,
+    allow_lossy: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let data = fs::read(input)?;
-    let (pixels, width, height, components) = detect_and_decode(&data)?;
+    let (encoded, frame_info) = transcode_data(&data, codec, quality, allow_lossy)?;
 
-    let frame_info = jpegexp_rs::FrameInfo {
-        width,
-        height,
-        bits_per_sample: 8,
-        component_count: components as i32,
+    fs::write(output, &encoded)?;
+    println!(
+        "✓ Transcoded {}x{} image ({} components) to {:?} using {:?} codec",
+        frame_info.width, frame_info.height, frame_info.component_count, output, codec
+    );
+    if allow_lossy && matches!(codec, Codec::Jpeg) && quality != 85 {
+        println!("  Quality: {}", quality);
+    }
+    Ok(())
+}
+
+fn transform_image(
+    input: &PathBuf,
+    output: &PathBuf,
+    rotate: Option<u32>,
+    flip: Option<FlipDirection>,
+    transpose: bool,
+    transverse: bool,
+    crop: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use jpegexp_rs::jpeg1::Transform;
+
+    let transform = match (rotate, flip) {
+        (Some(degrees), _) => Transform::rotation(degrees)
+            .ok_or_else(|| format!("Cannot rotate by {} degrees; use 90, 180 or 270", degrees))?,
+        (_, Some(FlipDirection::Horizontal)) => Transform::FlipHorizontal,
+        (_, Some(FlipDirection::Vertical)) => Transform::FlipVertical,
+        _ if transpose => Transform::Transpose,
+        _ if transverse => Transform::Transverse,
+        _ => Transform::None,
     };
+    let crop = crop.map(parse_crop).transpose()?;
 
-    let encoded = match codec {
-        Codec::Jpeg => {
-            let mut dest = vec![0u8; pixels.len() * 2];
-            let mut encoder = jpegexp_rs::jpeg1::encoder::Jpeg1Encoder::new();
-
-            // Set quality using the new API method
-            encoder.set_quality(quality);
-
-            let len = encoder.encode(&pixels, &frame_info, &mut dest)?;
-            dest.truncate(len);
-            dest
-        }
-        Codec::Jpegls => {
-            let mut dest = vec![0u8; pixels.len() * 2];
-            let mut encoder = jpegexp_rs::jpegls::JpeglsEncoder::new(&mut dest);
-            encoder.set_frame_info(frame_info)?;
-            // Note: quality parameter is ignored for JPEG-LS (use --near-lossless for encode command)
-            let len = encoder.encode(&pixels)?;
-            dest.truncate(len);
-            dest
-        }
-        Codec::J2k => {
-            let mut dest = vec![0u8; pixels.len() * 4]; // J2K can be larger
-            let mut encoder = jpegexp_rs::jpeg2000::encoder::J2kEncoder::new();
-            encoder.set_quality(quality);
-            let len = encoder.encode(&pixels, &frame_info, &mut dest)?;
-            dest.truncate(len);
-            dest
-        }
-        Codec::Htj2k => {
-            return Err("HTJ2K encoding not yet implemented".into());
-        }
+    let data = fs::read(input)?;
+    let transformed = jpegexp_rs::jpeg1::transform(&data, transform, crop)?;
+    let frame_info = {
+        let mut reader = jpegexp_rs::jpeg_stream_reader::JpegStreamReader::new(&transformed);
+        reader.read_header(&mut None)?;
+        reader.frame_info()
     };
 
-    fs::write(output, &encoded)?;
+    fs::write(output, &transformed)?;
     println!(
-        "✓ Transcoded {}x{} image ({} components) to {:?} using {:?} codec",
-        width, height, components, output, codec
+        "✓ Transformed image ({:?}) to {}x{} in {:?}",
+        transform, frame_info.width, frame_info.height, output
     );
-    if matches!(codec, Codec::Jpeg | Codec::J2k) && quality != 85 {
-        println!("  Quality: {}", quality);
+    Ok(())
+}
+
+/// Parses a crop rectangle given as `WIDTHxHEIGHT+X+Y`, as jpegtran's `-crop` takes it,
+/// into left, top, width and height.
+fn parse_crop(crop: &str) -> Result<(u32, u32, u32, u32), Box<dyn std::error::Error>> {
+    let invalid = || format!("Invalid crop rectangle {crop:?}; expected WIDTHxHEIGHT+X+Y");
+    let (size, offset) = crop.split_once('+').ok_or_else(invalid)?;
+    let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+    let (x, y) = offset.split_once('+').ok_or_else(invalid)?;
+    let parse = |value: &str| value.parse::<u32>().map_err(|_| invalid());
+    Ok((parse(x)?, parse(y)?, parse(width)?, parse(height)?))
+}
+
+fn transcode_directory(
+    input_dir: &Path,
+    output_dir: &Path,
+    pattern: &str,
+    codec: &Codec,
+    quality: u8,
+    allow_lossy: bool,
+    jobs: Option<usize>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let pattern_matcher = glob::Pattern::new(pattern)?;
+    let mut files = Vec::new();
+    find_files(input_dir, input_dir, &pattern_matcher, &mut files)?;
+    files.sort();
+    if files.is_empty() {
+        return Err(format!("No files in {:?} match {:?}", input_dir, pattern).into());
+    }
+
+    let jobs = jobs
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+        .clamp(1, files.len());
+    let next_file = AtomicUsize::new(0);
+
+    // Workers take the next unprocessed file until none are left.
+    let mut outcomes: Vec<_> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut outcomes = Vec::new();
+                    while let Some(input) = files.get(next_file.fetch_add(1, Ordering::Relaxed)) {
+                        let outcome = transcode_file(
+                            input,
+                            input_dir,
+                            output_dir,
+                            codec,
+                            quality,
+                            allow_lossy,
+                        )
+                        .map_err(|e| e.to_string());
+                        outcomes.push((input, outcome));
+                    }
+                    outcomes
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("transcode worker panicked"))
+            .collect()
+    });
+    outcomes.sort_by_key(|(input, _)| *input);
+
+    let (mut size_before, mut size_after) = (0, 0);
+    let mut failures = Vec::new();
+    for (input, outcome) in &outcomes {
+        match outcome {
+            Ok((input_size, output_size)) => {
+                size_before += input_size;
+                size_after += output_size;
+            }
+            Err(e) => failures.push(format!("{}: {}", input.display(), e)),
+        }
+    }
+
+    println!();
+    println!(
+        "Transcoded {} of {} files using {:?} codec",
+        outcomes.len() - failures.len(),
+        outcomes.len(),
+        codec
+    );
+    println!("  Size before: {} bytes", size_before);
+    println!("  Size after:  {} bytes", size_after);
+    if size_after > 0 {
+        println!(
+            "  Ratio:       {:.2}:1",
+            size_before as f64 / size_after as f64
+        );
+    }
+    if !failures.is_empty() {
+        eprintln!("Failed:");
+        for failure in &failures {
+            eprintln!("  {}", failure);
+        }
+        return Err(format!("{} of {} files failed", failures.len(), outcomes.len()).into());
     }
     Ok(())
 }
 
-fn show_info(input: &PathBuf, extended: bool) -> Result<(), Box<dyn std::error::Error>> {
+/// Collects the files below `dir` whose path relative to `root` matches `pattern`.
+fn find_files(
+    root: &Path,
+    dir: &Path,
+    pattern: &glob::Pattern,
+    files: &mut Vec<PathBuf>,
+) -> std::io::Result<()> {
+    let options = glob::MatchOptions {
+        require_literal_separator: true,
+        ..Default::default()
+    };
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_files(root, &path, pattern, files)?;
+        } else if path
+            .strip_prefix(root)
+            .is_ok_and(|relative| pattern.matches_path_with(relative, options))
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Transcodes one file of a directory batch, returning the input and output sizes.
+fn transcode_file(
+    input: &Path,
+    input_dir: &Path,
+    output_dir: &Path,
+    codec: &Codec,
+    quality: u8,
+    allow_lossy: bool,
+) -> Result<(usize, usize), Box<dyn std::error::Error>> {
+    let relative = input.strip_prefix(input_dir)?;
+    let output = output_dir
+        .join(relative)
+        .with_extension(codec_extension(codec));
+
+    let data = fs::read(input)?;
+    let (encoded, _) = transcode_data(&data, codec, quality, allow_lossy)?;
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&output, &encoded)?;
+    println!("✓ {} -> {}", input.display(), output.display());
+    Ok((data.len(), encoded.len()))
+}
+
+/// Decodes an encoded image (or a PNG/TIFF file) and re-encodes it with `codec`.
+///
+/// Without `allow_lossy` only lossless paths are taken: a DCT JPEG is transcoded to JPEG in
+/// the coefficient domain, and JPEG-LS output is checked to decode to the source pixels.
+fn transcode_data(
+    data: &[u8],
+    codec: &Codec,
+    quality: u8,
+    allow_lossy: bool,
+) -> Result<Image, Box<dyn std::error::Error>> {
+    use jpegexp_rs::codec::Format;
+
+    let input_format = jpegexp_rs::codec::detect_format(data);
+    if matches!(codec, Codec::Jpeg) && input_format == Some(Format::Jpeg1) && !allow_lossy {
+        let encoded = jpegexp_rs::jpeg1::transcode(data).map_err(|e| {
+            format!(
+                "Cannot transcode the DCT coefficients ({}); \
+                 pass --allow-lossy to re-encode the pixels",
+                e
+            )
+        })?;
+        let frame_info = {
+            let mut reader = jpegexp_rs::jpeg_stream_reader::JpegStreamReader::new(&encoded);
+            reader.read_header(&mut None)?;
+            reader.frame_info()
+        };
+        return Ok((encoded, frame_info));
+    }
+    if !allow_lossy && !matches!(codec, Codec::Jpegls) {
+        return Err(format!(
+            "Transcoding to {:?} would lose information; pass --allow-lossy to accept this",
+            codec
+        )
+        .into());
+    }
+
+    let (pixels, frame_info) = match read_image_file(data)? {
+        Some(image) => image,
+        None => {
+            let image = decode_data(data)?;
+            (image.pixels, image.frame_info)
+        }
+    };
+    let encoded = encode_pixels(&pixels, &frame_info, codec, quality)?;
+    if matches!(codec, Codec::Jpegls) {
+        let decoded = jpegexp_rs::codec::Decoder::auto(&encoded)?;
+        if decoded.pixels != pixels {
+            return Err("JPEG-LS output does not decode to the source pixels".into());
+        }
+    }
+    Ok((encoded, frame_info))
+}
+
+/// Encodes pixels with `codec` using the default settings of the transcode command.
+fn encode_pixels(
+    pixels: &[u8],
+    frame_info: &jpegexp_rs::FrameInfo,
+    codec: &Codec,
+    quality: u8,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    check_sample_depth(frame_info, codec)?;
+    let mut encoder = facade_encoder(codec)?;
+    // The quality is ignored for JPEG-LS (use --near-lossless with the encode command).
+    if matches!(codec, Codec::Jpeg | Codec::J2k | Codec::Htj2k) {
+        encoder.set_quality(quality);
+    }
+    Ok(encoder.encode(pixels, frame_info)?)
+}
+
+/// The library encoder for `codec`, with its default options.
+fn facade_encoder(codec: &Codec) -> Result<jpegexp_rs::codec::Encoder, Box<dyn std::error::Error>> {
+    use jpegexp_rs::codec::{Encoder, Format};
+
+    let format = match codec {
+        Codec::Jpeg => Format::Jpeg1,
+        Codec::Jpegls => Format::Jpegls,
+        Codec::J2k | Codec::Htj2k => Format::Jpeg2000,
+    };
+    let mut encoder = Encoder::for_format(format);
+    encoder.set_high_throughput(matches!(codec, Codec::Htj2k));
+    Ok(encoder)
+}
+
+fn codec_extension(codec: &Codec) -> &'static str {
+    match codec {
+        Codec::Jpeg => "jpg",
+        Codec::Jpegls => "jls",
+        Codec::J2k => "j2k",
+        Codec::Htj2k => "jph",
+    }
+}
+
+fn show_info(
+    input: &PathBuf,
+    extended: bool,
+    json: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use jpegexp_rs::codec::Format;
+
     let data = fs::read(input)?;
+    if json {
+        println!("{:#}", info_json(input, &data, extended)?);
+        return Ok(());
+    }
 
     println!("File: {:?}", input);
     println!("Size: {} bytes", data.len());
     println!();
 
-    if data.starts_with(&[0xFF, 0xD8]) {
-        if is_jpegls(&data) {
+    let format = jpegexp_rs::codec::detect_format(&data);
+    let print_resolution = || {
+        if let Some(r) = jpegexp_rs::resolution::read_resolution(&data) {
+            println!("  Resolution: {}x{} dpi", r.x_dpi, r.y_dpi);
+        }
+    };
+    if let Some(format @ (Format::Jpeg1 | Format::Jpegls)) = format {
+        let is_jpegls = format == Format::Jpegls;
+        if is_jpegls {
             println!("Format: JPEG-LS");
         } else {
             println!("Format: JPEG 1");
@@ -385,25 +988,60 @@ fn show_info(input: &PathBuf, extended: bool) -> Result<(), Box<dyn std::error::
         let mut spiff = None;
         reader.read_header(&mut spiff)?;
         let info = reader.frame_info();
+        let header = if is_jpegls {
+            None
+        } else {
+            Some(jpegexp_rs::jpeg1::read_metadata(&data)?)
+        };
         println!("  Dimensions: {}x{}", info.width, info.height);
         println!("  Bit depth:  {} bits", info.bits_per_sample);
         println!("  Components: {}", info.component_count);
+        print_resolution();
         println!(
             "  Mode:       {}",
-            if is_jpegls(&data) {
-                "JPEG-LS"
-            } else if reader.is_progressive {
-                "Progressive"
-            } else if reader.is_lossless {
-                "Lossless"
-            } else {
-                "Baseline"
+            match &header {
+                None => "JPEG-LS",
+                Some(header) if header.is_progressive() => "Progressive",
+                Some(header) if header.is_lossless() => "Lossless",
+                Some(_) => "Baseline",
             }
         );
-        if extended && reader.restart_interval > 0 {
-            println!("  Restart:    every {} MCUs", reader.restart_interval);
+        let restart_interval = header
+            .as_ref()
+            .map_or(reader.restart_interval, |header| header.restart_interval());
+        if extended && restart_interval > 0 {
+            println!("  Restart:    every {} MCUs", restart_interval);
         }
-    } else if data.starts_with(&[0xFF, 0x4F]) || data.starts_with(b"\x00\x00\x00\x0CjP") {
+        if let Some(header) = header.as_ref().filter(|_| extended) {
+            println!("  Scans:      {}", header.scans.len());
+            for scan in &header.scans {
+                let ids: Vec<String> = scan.components.iter().map(|c| c.id.to_string()).collect();
+                println!(
+                    "    components {:<8} Ss={:<2} Se={:<2} Ah={} Al={}",
+                    ids.join(","),
+                    scan.spectral_start,
+                    scan.spectral_end,
+                    scan.approximation_high,
+                    scan.approximation_low
+                );
+            }
+        }
+        if extended && header.as_ref().is_some_and(|header| !header.is_lossless()) {
+            let mut decoder = jpegexp_rs::jpeg1::Jpeg1Decoder::new(&data);
+            decoder.read_header()?;
+            for (id, table) in decoder.quantization_tables() {
+                println!("  Quant table {}:", id);
+                let table = jpegexp_rs::jpeg1::quantization::to_natural_order(&table);
+                for row in table.chunks(8) {
+                    let values: Vec<String> = row.iter().map(|v| format!("{:>3}", v)).collect();
+                    println!("    {}", values.join(" "));
+                }
+            }
+            if let Some(quality) = decoder.estimate_quality() {
+                println!("  Quality:    ~{} (estimated)", quality);
+            }
+        }
+    } else if format == Some(Format::Jpeg2000) {
         let is_jp2 = data.starts_with(b"\x00\x00\x00\x0CjP");
         println!(
             "Format: {}",
@@ -413,12 +1051,24 @@ fn show_info(input: &PathBuf, extended: bool) -> Result<(), Box<dyn std::error::
                 "JPEG 2000 Codestream"
             }
         );
+        let codestream_count = jpegexp_rs::jpeg2000::jp2::Jp2Reader::new(&data)
+            .codestreams()
+            .len();
+        if codestream_count > 1 {
+            println!(
+                "  Codestreams: {} (the first is described)",
+                codestream_count
+            );
+        }
 
+        // Only the main header; the tiles are not read.
         let mut reader = jpegexp_rs::jpeg_stream_reader::JpegStreamReader::new(&data);
-        let mut decoder = jpegexp_rs::jpeg2000::decoder::J2kDecoder::new(&mut reader);
-        if let Ok(image) = decoder.decode() {
-            println!("  Dimensions: {}x{}", image.width, image.height);
+        let mut parser = jpegexp_rs::jpeg2000::parser::J2kParser::new(&mut reader);
+        if let Ok(image) = parser.parse_info() {
+            let (width, height) = image.size();
+            println!("  Dimensions: {}x{}", width, height);
             println!("  Components: {}", image.component_count);
+            print_resolution();
             println!("  Tile size:  {}x{}", image.tile_width, image.tile_height);
             if let Some(cod) = &image.cod {
                 println!("  DWT levels: {}", cod.decomposition_levels);
@@ -436,17 +1086,48 @@ fn show_info(input: &PathBuf, extended: bool) -> Result<(), Box<dyn std::error::
                 );
             }
             if let Some(cap) = &image.cap {
-                let is_htj2k = (cap.pcap & (1 << 14)) != 0;
-                println!("  HTJ2K:      {}", if is_htj2k { "Yes" } else { "No" });
+                use jpegexp_rs::jpeg2000::image::HtDeclaration;
+                println!(
+                    "  HTJ2K:      {}",
+                    match cap.ht_declaration() {
+                        Some(HtDeclaration::HtOnly) => "Yes (HT only)",
+                        Some(HtDeclaration::HtDeclared) => "Yes (HT declared)",
+                        Some(HtDeclaration::Mixed) => "Yes (mixed)",
+                        None if cap.is_htj2k() => "Yes",
+                        None => "No",
+                    }
+                );
             }
             if extended {
                 if image.icc_profile.is_some() {
                     println!("  ICC Profile: Present");
                 }
-                if image.roi.is_some() {
+                if let Some(palette) = &image.palette {
+                    println!(
+                        "  Palette:    {} entries, {} columns",
+                        palette.entries.len(),
+                        palette.columns.len()
+                    );
+                }
+                if let Some(alpha) = image
+                    .channel_definitions
+                    .iter()
+                    .find(|definition| definition.channel_type.is_opacity())
+                {
+                    println!(
+                        "  Alpha:      component {} ({:?})",
+                        alpha.channel, alpha.channel_type
+                    );
+                }
+                if !image.xml_boxes.is_empty() {
+                    println!("  XML boxes:  {}", image.xml_boxes.len());
+                }
+                if !image.uuid_boxes.is_empty() {
+                    println!("  UUID boxes: {}", image.uuid_boxes.len());
+                }
+                if !image.roi.is_empty() {
                     println!("  ROI:        Present");
                 }
-                println!("  Decoded layers: {}", image.decoded_layers);
             }
         }
     } else {
@@ -456,6 +1137,204 @@ fn show_info(input: &PathBuf, extended: bool) -> Result<(), Box<dyn std::error::
     Ok(())
 }
 
+/// The fields of [`show_info`] as a JSON object, for scripts. Fields that do not apply to
+/// the format are left out; `format` is null for unknown files.
+fn info_json(
+    input: &Path,
+    data: &[u8],
+    extended: bool,
+) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    use jpegexp_rs::codec::Format;
+    use serde_json::{json, Value};
+
+    let format = jpegexp_rs::codec::detect_format(data);
+    let mut info = json!({
+        "file": input.display().to_string(),
+        "size": data.len(),
+        "format": format.map(Format::name),
+    });
+    let resolution = jpegexp_rs::resolution::read_resolution(data)
+        .map(|r| json!({ "x_dpi": r.x_dpi, "y_dpi": r.y_dpi }));
+    match format {
+        Some(format @ (Format::Jpeg1 | Format::Jpegls)) => {
+            let mut reader = jpegexp_rs::jpeg_stream_reader::JpegStreamReader::new(data);
+            let mut spiff = None;
+            reader.read_header(&mut spiff)?;
+            let frame_info = reader.frame_info();
+            let header = match format {
+                Format::Jpegls => None,
+                _ => Some(jpegexp_rs::jpeg1::read_metadata(data)?),
+            };
+            info["width"] = json!(frame_info.width);
+            info["height"] = json!(frame_info.height);
+            info["bits_per_sample"] = json!(frame_info.bits_per_sample);
+            info["components"] = json!(frame_info.component_count);
+            info["resolution"] = json!(resolution);
+            info["mode"] = json!(match &header {
+                None => "jpegls",
+                Some(header) if header.is_progressive() => "progressive",
+                Some(header) if header.is_lossless() => "lossless",
+                Some(_) => "baseline",
+            });
+            if !extended {
+                return Ok(info);
+            }
+            info["restart_interval"] = json!(header
+                .as_ref()
+                .map_or(reader.restart_interval, |header| header.restart_interval()));
+            if let Some(header) = &header {
+                let scans: Vec<Value> = header
+                    .scans
+                    .iter()
+                    .map(|scan| {
+                        json!({
+                            "components": scan.components.iter().map(|c| c.id).collect::<Vec<_>>(),
+                            "spectral_start": scan.spectral_start,
+                            "spectral_end": scan.spectral_end,
+                            "approximation_high": scan.approximation_high,
+                            "approximation_low": scan.approximation_low,
+                        })
+                    })
+                    .collect();
+                info["scans"] = json!(scans);
+                if !header.is_lossless() {
+                    let mut decoder = jpegexp_rs::jpeg1::Jpeg1Decoder::new(data);
+                    decoder.read_header()?;
+                    let tables: Vec<Value> = decoder
+                        .quantization_tables()
+                        .iter()
+                        .map(|(id, table)| {
+                            let table = jpegexp_rs::jpeg1::quantization::to_natural_order(table);
+                            json!({ "id": id, "values": table.to_vec() })
+                        })
+                        .collect();
+                    info["quantization_tables"] = json!(tables);
+                    info["estimated_quality"] = json!(decoder.estimate_quality());
+                }
+            }
+        }
+        Some(Format::Jpeg2000) => {
+            info["container"] = json!(if data.starts_with(b"\x00\x00\x00\x0CjP") {
+                "jp2"
+            } else {
+                "codestream"
+            });
+            // A bare codestream is one.
+            info["codestreams"] = json!(jpegexp_rs::jpeg2000::jp2::Jp2Reader::new(data)
+                .codestreams()
+                .len()
+                .max(1));
+            let mut reader = jpegexp_rs::jpeg_stream_reader::JpegStreamReader::new(data);
+            let mut parser = jpegexp_rs::jpeg2000::parser::J2kParser::new(&mut reader);
+            let image = parser.parse_info()?;
+            let (width, height) = image.size();
+            info["width"] = json!(width);
+            info["height"] = json!(height);
+            info["bits_per_sample"] = json!(image.channel_bits_per_sample());
+            info["components"] = json!(image.component_count);
+            info["resolution"] = json!(resolution);
+            info["tile_width"] = json!(image.tile_width);
+            info["tile_height"] = json!(image.tile_height);
+            if let Some(cod) = &image.cod {
+                info["decomposition_levels"] = json!(cod.decomposition_levels);
+                info["layers"] = json!(cod.number_of_layers);
+                info["progression"] = json!(match cod.progression_order {
+                    0 => "LRCP",
+                    1 => "RLCP",
+                    2 => "RPCL",
+                    3 => "PCRL",
+                    4 => "CPRL",
+                    _ => "Unknown",
+                });
+            }
+            info["htj2k"] = json!(image.cap.as_ref().is_some_and(|cap| cap.is_htj2k()));
+            if !extended {
+                return Ok(info);
+            }
+            info["icc_profile"] = json!(image.icc_profile.is_some());
+            info["palette"] = json!(image.palette.as_ref().map(|palette| json!({
+                "entries": palette.entries.len(),
+                "columns": palette.columns.len(),
+            })));
+            info["alpha_component"] = json!(image
+                .channel_definitions
+                .iter()
+                .find(|definition| definition.channel_type.is_opacity())
+                .map(|alpha| alpha.channel));
+            info["xml_boxes"] = json!(image.xml_boxes.len());
+            info["uuid_boxes"] = json!(image.uuid_boxes.len());
+            info["roi"] = json!(!image.roi.is_empty());
+        }
+        None => {}
+    }
+    Ok(info)
+}
+
+fn dump_markers(input: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+    let data = fs::read(input)?;
+    let dump = jpegexp_rs::dump::dump(&data);
+
+    println!("File: {:?}", input);
+    println!("Size: {} bytes", data.len());
+    println!("Format: {}", dump.format);
+    println!();
+    println!("{:>10}  {:>10}  Marker", "Offset", "Length");
+    for segment in &dump.segments {
+        let indent = "  ".repeat(segment.depth);
+        println!(
+            "{:>10}  {:>10}  {}{:<6} {}",
+            segment.offset, segment.length, indent, segment.name, segment.description
+        );
+        for (name, value) in &segment.fields {
+            println!("{:>24}{}  {}: {}", "", indent, name, value);
+        }
+    }
+
+    if let Some((offset, message)) = dump.error {
+        return Err(format!("{} at offset {}", message, offset).into());
+    }
+    Ok(())
+}
+
+fn validate_jpeg2000(input: &PathBuf, json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let data = fs::read(input)?;
+    let report = jpegexp_rs::jpeg2000::validate(&data);
+
+    if json {
+        let issues = |issues: &[jpegexp_rs::jpeg2000::ValidationIssue]| -> Vec<serde_json::Value> {
+            issues
+                .iter()
+                .map(
+                    |issue| serde_json::json!({ "offset": issue.offset, "message": issue.message }),
+                )
+                .collect()
+        };
+        let output = serde_json::json!({
+            "file": input.display().to_string(),
+            "valid": report.is_valid(),
+            "errors": issues(&report.errors),
+            "warnings": issues(&report.warnings),
+        });
+        println!("{:#}", output);
+    } else {
+        println!("File: {:?}", input);
+        for (label, issues) in [("Errors", &report.errors), ("Warnings", &report.warnings)] {
+            println!("{}: {}", label, issues.len());
+            for issue in issues {
+                println!("  {:>10}  {}", issue.offset, issue.message);
+            }
+        }
+    }
+
+    if !report.is_valid() {
+        return Err(format!("{} is not valid", input.display()).into());
+    }
+    if !json {
+        println!("Valid");
+    }
+    Ok(())
+}
+
 fn list_codecs() -> Result<(), Box<dyn std::error::Error>> {
     println!("Supported Codecs:");
     println!();
@@ -482,99 +1361,473 @@ fn list_codecs() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-// Internal helpers
+fn suggest_codec(
+    input: &PathBuf,
+    width: u32,
+    height: u32,
+    components: u32,
+    bits: u8,
+    goal: &SuggestGoal,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use jpegexp_rs::suggest::{Codec, Goal};
 
-fn detect_and_decode(data: &[u8]) -> Result<(Vec<u8>, u32, u32, u32), Box<dyn std::error::Error>> {
-    if data.starts_with(&[0xFF, 0xD8]) {
-        if is_jpegls(data) {
-            decode_jpegls(data)
+    let pixels = fs::read(input)?;
+    let frame_info = jpegexp_rs::FrameInfo {
+        width,
+        height,
+        bits_per_sample: bits as i32,
+        component_count: components as i32,
+    };
+    let goal = match goal {
+        SuggestGoal::Archival => Goal::ArchivalLossless,
+        SuggestGoal::Web => Goal::Web,
+        SuggestGoal::Telerad => Goal::Telerad,
+    };
+
+    let suggestion = jpegexp_rs::suggest_codec(&pixels, &frame_info, goal)?;
+    let (name, codec_arg) = match suggestion.codec {
+        Codec::Jpeg1 => ("JPEG 1 Baseline", "jpeg"),
+        Codec::Jpegls => ("JPEG-LS", "jpegls"),
+        Codec::Jpeg2000 => ("JPEG 2000", "j2k"),
+    };
+    let statistics = &suggestion.statistics;
+
+    println!("Suggested codec: {}", name);
+    if let Some(quality) = suggestion.quality {
+        println!("  Quality:        {}", quality);
+    }
+    if let Some(near_lossless) = suggestion.near_lossless {
+        if near_lossless == 0 {
+            println!("  Mode:           Lossless");
         } else {
-            decode_jpeg1(data)
+            println!("  Near-lossless:  {}", near_lossless);
         }
-    } else if data.starts_with(&[0xFF, 0x4F]) || data.starts_with(b"\x00\x00\x00\x0CjP") {
-        decode_j2k(data)
-    } else {
-        decode_jpegls(data)
     }
+    println!(
+        "  Expected ratio: {:.1}:1",
+        suggestion.expected_compression_ratio
+    );
+    println!("  Reason:         {}", suggestion.rationale);
+    println!();
+    println!("Image statistics:");
+    println!(
+        "  Sample entropy:    {:.2} bits/sample",
+        statistics.sample_entropy
+    );
+    println!(
+        "  Residual entropy:  {:.2} bits/sample",
+        statistics.residual_entropy
+    );
+    println!(
+        "  Mean |residual|:   {:.2}",
+        statistics.mean_absolute_residual
+    );
+    println!("  Flat samples:      {:.1}%", statistics.flat_fraction * 100.0);
+
+    // The encode command reads 8-bit samples only.
+    if bits == 8 {
+        let mut command = format!(
+            "jpegexp encode -i {} -o OUTPUT -w {} -H {} -n {} -c {}",
+            input.display(),
+            width,
+            height,
+            components,
+            codec_arg
+        );
+        if let Some(quality) = suggestion.quality {
+            command.push_str(&format!(" -q {}", quality));
+        }
+        if let Some(near_lossless) = suggestion.near_lossless.filter(|&near| near > 0) {
+            command.push_str(&format!(" --near-lossless {}", near_lossless));
+        }
+        println!();
+        println!("Encode with:");
+        println!("  {}", command);
+    }
+    Ok(())
 }
 
-fn is_jpegls(data: &[u8]) -> bool {
-    // Basic marker scanner
-    let mut i = 0;
-    while i < data.len() - 1 {
-        if data[i] == 0xFF {
-            let marker = data[i + 1];
-            if marker == 0xF7 || marker == 0xF8 {
-                // SOF55 or LSE
-                return true;
-            }
-            if marker == 0xDA {
-                // SOS (Start of Scan) - stop scanning
-                break;
+/// Benchmarks decoding `input` and, if `encode` is set, encoding the decoded pixels
+/// with `codec` (the input's codec by default).
+fn bench_image(
+    input: &Path,
+    iterations: u32,
+    warmup: u32,
+    codec: Option<Codec>,
+    quality: u8,
+    encode: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use jpegexp_rs::codec::Format as InputCodec;
+
+    let data = fs::read(input)?;
+    let input_codec = jpegexp_rs::codec::detect_format(&data);
+    let decoder = jpegexp_rs::Decoder::new();
+    let encode_codec = match (encode, codec, input_codec) {
+        (false, _, _) => None,
+        (true, Some(codec), _) => Some(codec),
+        (true, None, Some(InputCodec::Jpeg1)) => Some(Codec::Jpeg),
+        (true, None, Some(InputCodec::Jpegls)) => Some(Codec::Jpegls),
+        (true, None, Some(InputCodec::Jpeg2000)) => Some(Codec::J2k),
+        (true, None, None) => None,
+    };
+
+    let (pixels, frame_info) = match read_image_file(&data)? {
+        Some(image) => image,
+        None => {
+            if input_codec.is_none() {
+                return Err("Unrecognized input format".into());
             }
-            i += 2;
-        } else {
-            i += 1;
+            let image = decoder.decode(&data)?;
+            (image.pixels, image.frame_info)
         }
+    };
+    if input_codec.is_none() && encode_codec.is_none() {
+        return Err("--codec is required to benchmark PNG/TIFF input".into());
+    }
+    let pixel_count = frame_info.width as usize * frame_info.height as usize;
+
+    println!("File: {:?} ({} bytes)", input, data.len());
+    println!(
+        "Image: {}x{}, {} components, {} bits",
+        frame_info.width, frame_info.height, frame_info.component_count, frame_info.bits_per_sample
+    );
+    println!("Iterations: {} ({} warmup)", iterations, warmup);
+    println!();
+
+    if input_codec.is_some() {
+        let durations = time_iterations(warmup, iterations, || Ok(decoder.decode(&data)?))?;
+        print_throughput("Decode", &durations, pixel_count, pixels.len());
     }
-    false
+
+    if let Some(codec) = encode_codec {
+        let encoded = encode_pixels(&pixels, &frame_info, &codec, quality)?;
+        let durations = time_iterations(warmup, iterations, || {
+            encode_pixels(&pixels, &frame_info, &codec, quality)
+        })?;
+        print_throughput(
+            &format!("Encode ({:?})", codec),
+            &durations,
+            pixel_count,
+            pixels.len(),
+        );
+        println!(
+            "  {:<14} {} bytes ({:.2}:1)",
+            "Encoded size:",
+            encoded.len(),
+            pixels.len() as f64 / encoded.len() as f64
+        );
+    }
+    Ok(())
 }
 
-fn decode_jpeg1(data: &[u8]) -> Result<(Vec<u8>, u32, u32, u32), Box<dyn std::error::Error>> {
-    let mut reader = jpegexp_rs::jpeg_stream_reader::JpegStreamReader::new(data);
-    let mut spiff = None;
-    reader.read_header(&mut spiff)?;
-    let info = reader.frame_info();
-    let width = info.width;
-    let height = info.height;
-    let components = info.component_count as u32;
+/// Runs `operation` `warmup` times untimed, then `iterations` times, returning the
+/// duration of each timed run.
+fn time_iterations<T>(
+    warmup: u32,
+    iterations: u32,
+    mut operation: impl FnMut() -> Result<T, Box<dyn std::error::Error>>,
+) -> Result<Vec<Duration>, Box<dyn std::error::Error>> {
+    for _ in 0..warmup {
+        std::hint::black_box(operation()?);
+    }
+    (0..iterations)
+        .map(|_| {
+            let start = Instant::now();
+            std::hint::black_box(operation()?);
+            Ok(start.elapsed())
+        })
+        .collect()
+}
 
-    let pixel_count = (width * height * components) as usize;
-    let mut pixels = vec![0u8; pixel_count];
+fn print_throughput(label: &str, durations: &[Duration], pixel_count: usize, byte_count: usize) {
+    let mut sorted = durations.to_vec();
+    sorted.sort();
+    let mean = durations.iter().sum::<Duration>() / durations.len() as u32;
+    let seconds = mean.as_secs_f64();
 
-    let mut decoder = jpegexp_rs::jpeg1::decoder::Jpeg1Decoder::new(data);
-    decoder.read_header()?;
-    decoder.decode(&mut pixels)?;
+    println!("{}:", label);
+    println!(
+        "  {:<14} {:.3} ms (min {:.3} ms, median {:.3} ms)",
+        "Time:",
+        seconds * 1e3,
+        sorted[0].as_secs_f64() * 1e3,
+        sorted[sorted.len() / 2].as_secs_f64() * 1e3
+    );
+    println!(
+        "  {:<14} {:.2} MP/s, {:.2} MB/s",
+        "Throughput:",
+        pixel_count as f64 / seconds / 1e6,
+        byte_count as f64 / seconds / 1e6
+    );
+}
 
-    Ok((pixels, width, height, components))
+/// Error statistics of one component, or of all components together.
+#[derive(Default)]
+struct ErrorStats {
+    sample_count: usize,
+    squared_error: f64,
+    error_sum: i64,
+    max_error: u32,
 }
 
-fn decode_j2k(data: &[u8]) -> Result<(Vec<u8>, u32, u32, u32), Box<dyn std::error::Error>> {
-    let mut reader = jpegexp_rs::jpeg_stream_reader::JpegStreamReader::new(data);
-    let mut decoder = jpegexp_rs::jpeg2000::decoder::J2kDecoder::new(&mut reader);
-    let image = decoder.decode()?;
+impl ErrorStats {
+    fn add(&mut self, reference: u32, test: u32) {
+        let error = test as i64 - reference as i64;
+        self.sample_count += 1;
+        self.squared_error += (error * error) as f64;
+        self.error_sum += error;
+        self.max_error = self.max_error.max(error.unsigned_abs() as u32);
+    }
 
-    let width = image.width;
-    let height = image.height;
-    let components = image.component_count;
+    fn mse(&self) -> f64 {
+        self.squared_error / self.sample_count.max(1) as f64
+    }
+
+    fn mean_error(&self) -> f64 {
+        self.error_sum as f64 / self.sample_count.max(1) as f64
+    }
 
-    // Reconstruct pixels from DWT coefficients using IDWT
-    match image.reconstruct_pixels() {
-        Ok(reconstructed) => Ok((reconstructed, width, height, components)),
-        Err(e) => {
-            eprintln!("J2K Reconstruction failed: {}", e);
-            // Fallback to default if reconstruction fails
-            let pixels = vec![128u8; (width * height * components) as usize];
-            Ok((pixels, width, height, components))
+    /// Peak signal-to-noise ratio in dB; infinite for identical samples.
+    fn psnr(&self, max_value: u32) -> f64 {
+        let mse = self.mse();
+        if mse == 0.0 {
+            f64::INFINITY
+        } else {
+            10.0 * ((max_value as f64).powi(2) / mse).log10()
         }
     }
 }
 
-fn decode_jpegls(data: &[u8]) -> Result<(Vec<u8>, u32, u32, u32), Box<dyn std::error::Error>> {
-    let mut decoder = jpegexp_rs::jpegls::JpeglsDecoder::new(data);
-    decoder.read_header()?;
-    let info = decoder.frame_info();
-    let width = info.width;
-    let height = info.height;
-    let components = info.component_count as u32;
-    let bits_per_sample = info.bits_per_sample;
-    let bytes_per_sample = ((bits_per_sample + 7) / 8) as usize;
+fn compare_images(
+    reference: &Path,
+    test: &Path,
+    decode: bool,
+    raw_info: Option<jpegexp_rs::FrameInfo>,
+    min_psnr: Option<f64>,
+    max_error: Option<u32>,
+    json: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let reference_data = fs::read(reference)?;
+    let test_data = fs::read(test)?;
+    let reference_image = if decode {
+        decode_for_compare(&reference_data)?
+    } else {
+        None
+    };
+    let test_image = if decode {
+        decode_for_compare(&test_data)?
+    } else {
+        None
+    };
+
+    // Raw inputs use the dimensions from the command line, or those of the decoded image.
+    let raw_info = raw_info.or_else(|| {
+        reference_image
+            .as_ref()
+            .or(test_image.as_ref())
+            .map(|(_, frame_info)| *frame_info)
+    });
+    let (reference_pixels, reference_info) = match reference_image {
+        Some(image) => image,
+        None => raw_image(reference_data, raw_info)?,
+    };
+    let (test_pixels, test_info) = match test_image {
+        Some(image) => image,
+        None => raw_image(test_data, raw_info)?,
+    };
+
+    if (reference_info.width, reference_info.height, reference_info.component_count)
+        != (test_info.width, test_info.height, test_info.component_count)
+    {
+        return Err(format!(
+            "Images differ in size: {}x{} ({} components) vs {}x{} ({} components)",
+            reference_info.width,
+            reference_info.height,
+            reference_info.component_count,
+            test_info.width,
+            test_info.height,
+            test_info.component_count
+        )
+        .into());
+    }
+
+    let components = reference_info.component_count.max(1) as usize;
+    let bits_per_sample = reference_info
+        .bits_per_sample
+        .max(test_info.bits_per_sample);
+    let max_value = (1u32 << bits_per_sample) - 1;
+
+    let mut total = ErrorStats::default();
+    let mut per_component: Vec<ErrorStats> =
+        (0..components).map(|_| ErrorStats::default()).collect();
+    let reference_samples = samples(&reference_pixels, &reference_info);
+    let test_samples = samples(&test_pixels, &test_info);
+    for (i, (&a, &b)) in reference_samples.iter().zip(&test_samples).enumerate() {
+        total.add(a, b);
+        per_component[i % components].add(a, b);
+    }
+
+    let psnr = total.psnr(max_value);
+    let failure = if let Some(min_psnr) = min_psnr.filter(|&min_psnr| psnr < min_psnr) {
+        Some(format!(
+            "PSNR {:.2} dB is below the minimum of {} dB",
+            psnr, min_psnr
+        ))
+    } else {
+        max_error
+            .filter(|&max_error| total.max_error > max_error)
+            .map(|max_error| {
+                format!(
+                    "Maximum error {} exceeds the limit of {}",
+                    total.max_error, max_error
+                )
+            })
+    };
+
+    if json {
+        // The PSNR of identical images is infinite, which JSON writes as null.
+        let stats_json = |stats: &ErrorStats| {
+            serde_json::json!({
+                "mse": stats.mse(),
+                "psnr": stats.psnr(max_value),
+                "max_error": stats.max_error,
+                "mean_error": stats.mean_error(),
+            })
+        };
+        let mut output = stats_json(&total);
+        output["width"] = serde_json::json!(reference_info.width);
+        output["height"] = serde_json::json!(reference_info.height);
+        output["components"] = serde_json::json!(components);
+        output["bits_per_sample"] = serde_json::json!(bits_per_sample);
+        output["per_component"] = per_component.iter().map(stats_json).collect();
+        output["passed"] = serde_json::json!(failure.is_none());
+        println!("{:#}", output);
+    } else {
+        let format_psnr = |psnr: f64| {
+            if psnr.is_infinite() {
+                "inf (identical)".to_string()
+            } else {
+                format!("{:.2} dB", psnr)
+            }
+        };
+        println!(
+            "Compared {}x{} image ({} components, {} bits)",
+            reference_info.width, reference_info.height, components, bits_per_sample
+        );
+        println!("  MSE:        {:.4}", total.mse());
+        println!("  PSNR:       {}", format_psnr(psnr));
+        println!("  Max error:  {}", total.max_error);
+        println!("  Mean error: {:.4}", total.mean_error());
+        if components > 1 {
+            println!();
+            println!("Per component:");
+            for (c, stats) in per_component.iter().enumerate() {
+                println!(
+                    "  {}: MSE {:.4}, PSNR {}, max error {}, mean error {:.4}",
+                    c,
+                    stats.mse(),
+                    format_psnr(stats.psnr(max_value)),
+                    stats.max_error,
+                    stats.mean_error()
+                );
+            }
+        }
+    }
+
+    match failure {
+        Some(failure) => Err(failure.into()),
+        None => Ok(()),
+    }
+}
+
+/// Decodes a compare input if it is an encoded image, or returns `None` for raw data.
+fn decode_for_compare(data: &[u8]) -> Result<Option<Image>, Box<dyn std::error::Error>> {
+    if let Some(image) = read_image_file(data)? {
+        return Ok(Some(image));
+    }
+    if jpegexp_rs::codec::detect_format(data).is_none() {
+        return Ok(None);
+    }
+    let image = jpegexp_rs::codec::Decoder::auto(data)?;
+    Ok(Some((image.pixels, image.frame_info)))
+}
+
+fn raw_image(
+    mut pixels: Vec<u8>,
+    frame_info: Option<jpegexp_rs::FrameInfo>,
+) -> Result<Image, Box<dyn std::error::Error>> {
+    let frame_info =
+        frame_info.ok_or("--width and --height are required to compare raw pixel files")?;
+    let bytes_per_sample = if frame_info.bits_per_sample > 8 { 2 } else { 1 };
+    let expected_size = frame_info.width as usize
+        * frame_info.height as usize
+        * frame_info.component_count as usize
+        * bytes_per_sample;
+    if pixels.len() < expected_size {
+        return Err(format!(
+            "Input file too small: expected {} bytes, got {} bytes",
+            expected_size,
+            pixels.len()
+        )
+        .into());
+    }
+    pixels.truncate(expected_size);
+    Ok((pixels, frame_info))
+}
+
+fn samples(pixels: &[u8], frame_info: &jpegexp_rs::FrameInfo) -> Vec<u32> {
+    if frame_info.bits_per_sample > 8 {
+        pixels
+            .chunks_exact(2)
+            .map(|s| u16::from_ne_bytes([s[0], s[1]]) as u32)
+            .collect()
+    } else {
+        pixels.iter().map(|&s| s as u32).collect()
+    }
+}
 
-    let byte_count = (width * height * components) as usize * bytes_per_sample;
-    let mut pixels = vec![0u8; byte_count];
-    decoder.decode(&mut pixels)?;
+// Internal helpers
 
-    Ok((pixels, width, height, components))
+/// Pixels with the frame they describe.
+type Image = (Vec<u8>, jpegexp_rs::FrameInfo);
+
+/// Reads a PNG or TIFF input file, or returns `None` if the data is neither (or the
+/// image-io feature is disabled) so the caller can treat it as raw or encoded data.
+#[cfg_attr(not(feature = "image-io"), allow(unused_variables))]
+fn read_image_file(data: &[u8]) -> Result<Option<Image>, Box<dyn std::error::Error>> {
+    #[cfg(feature = "image-io")]
+    {
+        if image_io::is_png(data) {
+            return image_io::read_png(data).map(Some);
+        }
+        if image_io::is_tiff(data) {
+            return image_io::read_tiff(data).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// Only the JPEG-LS encoder accepts samples wider than 8 bits.
+fn check_sample_depth(
+    frame_info: &jpegexp_rs::FrameInfo,
+    codec: &Codec,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if frame_info.bits_per_sample > 8 && !matches!(codec, Codec::Jpegls) {
+        return Err(format!(
+            "{:?} encoding requires 8-bit samples; use -c jpegls for {}-bit input",
+            codec, frame_info.bits_per_sample
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Decodes a JPEG 1, JPEG-LS or JPEG 2000 stream.
+fn decode_data(data: &[u8]) -> Result<jpegexp_rs::DecodedImage, Box<dyn std::error::Error>> {
+    if jpegexp_rs::codec::detect_format(data).is_none() {
+        return Err("Unrecognized input format".into());
+    }
+    Ok(jpegexp_rs::codec::Decoder::auto(data)?)
 }
 
 fn write_ppm(
@@ -598,3 +1851,172 @@ fn write_ppm(
 
     Ok(())
 }
+
+/// PNG and TIFF input/output, enabled by the `image-io` feature. Samples wider than
+/// 8 bits are exchanged with the codecs as native-endian 16-bit values.
+#[cfg(feature = "image-io")]
+mod image_io {
+    use super::Image;
+    use jpegexp_rs::FrameInfo;
+    use std::error::Error;
+    use std::fs::File;
+    use std::io::{BufWriter, Cursor};
+    use std::path::Path;
+    use tiff::decoder::DecodingResult;
+    use tiff::encoder::{colortype, TiffEncoder};
+
+    pub fn is_png(data: &[u8]) -> bool {
+        data.starts_with(b"\x89PNG\r\n\x1a\n")
+    }
+
+    pub fn is_tiff(data: &[u8]) -> bool {
+        data.starts_with(b"II*\0") || data.starts_with(b"MM\0*")
+    }
+
+    /// Reads a PNG image. Palette and 1/2/4-bit images are expanded to 8 bits and the
+    /// alpha channel is dropped.
+    pub fn read_png(data: &[u8]) -> Result<Image, Box<dyn Error>> {
+        let mut decoder = png::Decoder::new(data);
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let mut reader = decoder.read_info()?;
+        let mut buffer = vec![0u8; reader.output_buffer_size()];
+        let output = reader.next_frame(&mut buffer)?;
+        buffer.truncate(output.buffer_size());
+
+        let (components, has_alpha) = match output.color_type {
+            png::ColorType::Grayscale => (1, false),
+            png::ColorType::GrayscaleAlpha => (1, true),
+            png::ColorType::Rgb | png::ColorType::Indexed => (3, false),
+            png::ColorType::Rgba => (3, true),
+        };
+        let bytes_per_sample = match output.bit_depth {
+            png::BitDepth::Sixteen => 2,
+            _ => 1,
+        };
+
+        let mut pixels = if has_alpha {
+            strip_alpha(&buffer, components * bytes_per_sample, bytes_per_sample)
+        } else {
+            buffer
+        };
+        if bytes_per_sample == 2 {
+            // PNG stores 16-bit samples big-endian.
+            for sample in pixels.chunks_exact_mut(2) {
+                let value = u16::from_be_bytes([sample[0], sample[1]]);
+                sample.copy_from_slice(&value.to_ne_bytes());
+            }
+        }
+
+        let frame_info = FrameInfo {
+            width: output.width,
+            height: output.height,
+            bits_per_sample: bytes_per_sample as i32 * 8,
+            component_count: components as i32,
+        };
+        Ok((pixels, frame_info))
+    }
+
+    /// Reads the first image of an 8 or 16-bit grayscale or RGB TIFF file, dropping the
+    /// alpha channel.
+    pub fn read_tiff(data: &[u8]) -> Result<Image, Box<dyn Error>> {
+        let mut decoder = tiff::decoder::Decoder::new(Cursor::new(data))?;
+        let (width, height) = decoder.dimensions()?;
+        let (components, has_alpha, bits_per_sample) = match decoder.colortype()? {
+            tiff::ColorType::Gray(bits @ (8 | 16)) => (1, false, bits),
+            tiff::ColorType::GrayA(bits @ (8 | 16)) => (1, true, bits),
+            tiff::ColorType::RGB(bits @ (8 | 16)) => (3, false, bits),
+            tiff::ColorType::RGBA(bits @ (8 | 16)) => (3, true, bits),
+            other => return Err(format!("Unsupported TIFF color type: {:?}", other).into()),
+        };
+
+        let samples = match decoder.read_image()? {
+            DecodingResult::U8(samples) => samples,
+            DecodingResult::U16(samples) => {
+                samples.iter().flat_map(|s| s.to_ne_bytes()).collect()
+            }
+            _ => return Err("Only integer TIFF samples are supported".into()),
+        };
+        let bytes_per_sample = bits_per_sample as usize / 8;
+        let pixels = if has_alpha {
+            strip_alpha(&samples, components * bytes_per_sample, bytes_per_sample)
+        } else {
+            samples
+        };
+
+        let frame_info = FrameInfo {
+            width,
+            height,
+            bits_per_sample: bits_per_sample as i32,
+            component_count: components as i32,
+        };
+        Ok((pixels, frame_info))
+    }
+
+    pub fn write_png(
+        path: &Path,
+        pixels: &[u8],
+        frame_info: &FrameInfo,
+    ) -> Result<(), Box<dyn Error>> {
+        let color_type = match frame_info.component_count {
+            1 => png::ColorType::Grayscale,
+            2 => png::ColorType::GrayscaleAlpha,
+            3 => png::ColorType::Rgb,
+            4 => png::ColorType::Rgba,
+            n => return Err(format!("PNG does not support {} components", n).into()),
+        };
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, frame_info.width, frame_info.height);
+        encoder.set_color(color_type);
+
+        if frame_info.bits_per_sample > 8 {
+            encoder.set_depth(png::BitDepth::Sixteen);
+            let samples: Vec<u8> = to_u16(pixels)
+                .iter()
+                .flat_map(|s| s.to_be_bytes())
+                .collect();
+            encoder.write_header()?.write_image_data(&samples)?;
+        } else {
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.write_header()?.write_image_data(pixels)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_tiff(
+        path: &Path,
+        pixels: &[u8],
+        frame_info: &FrameInfo,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut encoder = TiffEncoder::new(BufWriter::new(File::create(path)?))?;
+        let (width, height) = (frame_info.width, frame_info.height);
+        match (frame_info.component_count, frame_info.bits_per_sample > 8) {
+            (1, false) => encoder.write_image::<colortype::Gray8>(width, height, pixels)?,
+            (3, false) => encoder.write_image::<colortype::RGB8>(width, height, pixels)?,
+            (1, true) => {
+                encoder.write_image::<colortype::Gray16>(width, height, &to_u16(pixels))?
+            }
+            (3, true) => {
+                encoder.write_image::<colortype::RGB16>(width, height, &to_u16(pixels))?
+            }
+            (n, _) => {
+                return Err(format!("TIFF output does not support {} components", n).into())
+            }
+        }
+        Ok(())
+    }
+
+    fn strip_alpha(samples: &[u8], color_bytes: usize, alpha_bytes: usize) -> Vec<u8> {
+        samples
+            .chunks_exact(color_bytes + alpha_bytes)
+            .flat_map(|pixel| &pixel[..color_bytes])
+            .copied()
+            .collect()
+    }
+
+    fn to_u16(pixels: &[u8]) -> Vec<u16> {
+        pixels
+            .chunks_exact(2)
+            .map(|s| u16::from_ne_bytes([s[0], s[1]]))
+            .collect()
+    }
+}
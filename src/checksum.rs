@@ -0,0 +1,333 @@
+//! Checksums of the decoded pixels embedded in the stream, to detect silent corruption in
+//! long-term archives.
+//!
+//! [`embed_checksum`] decodes a stream, computes the CRC-32 of its pixels and stores it in
+//! the stream: in a COM segment in front of the frame header of JPEG 1 and JPEG-LS
+//! streams, in a COM segment at the end of the main header of JPEG 2000 codestreams and
+//! in a UUID box in front of the codestream box of JP2 files. The decoders skip it like
+//! any comment or unknown box. After decoding, [`DecodedImage::verify_checksum`] compares
+//! the pixels with it:
+//!
+//! ```rust
+//! use jpegexp_rs::checksum::embed_checksum;
+//! use jpegexp_rs::codec::{Decoder, Encoder, Format};
+//! use jpegexp_rs::FrameInfo;
+//!
+//! let frame_info = FrameInfo { width: 8, height: 8, bits_per_sample: 8, component_count: 1 };
+//! let encoded = Encoder::for_format(Format::Jpegls).encode(&[9; 64], &frame_info).unwrap();
+//! let archived = embed_checksum(&encoded).unwrap();
+//!
+//! let image = Decoder::auto(&archived).unwrap();
+//! assert_eq!(image.verify_checksum(), Some(true));
+//! ```
+//!
+//! The checksum covers the pixels as [`Decoder`] returns them, interleaved with samples
+//! wider than 8 bits as little-endian 16-bit values. A lossless stream decodes to the
+//! same pixels with any conforming decoder; the pixels of a lossy stream may change in the
+//! last bit with the decoder, so its checksum detects corruption as long as this library
+//! decodes it.
+
+use crate::decoder::{DecodedImage, Decoder};
+use crate::error::JpeglsError;
+
+/// Start of the text of the checksum comment, followed by 8 hexadecimal digits.
+const COMMENT_PREFIX: &[u8] = b"jpegexp-crc32:";
+
+/// UUID of the JP2 box that holds the checksum comment
+/// (7c0f4f58-2b3a-4d7e-9a61-3e8c5d20b1f4).
+pub const CHECKSUM_UUID: [u8; 16] = [
+    0x7c, 0x0f, 0x4f, 0x58, 0x2b, 0x3a, 0x4d, 0x7e, 0x9a, 0x61, 0x3e, 0x8c, 0x5d, 0x20, 0xb1, 0xf4,
+];
+
+const JP2_SIGNATURE: &[u8] = b"\x00\x00\x00\x0CjP  \r\n\x87\n";
+
+/// CRC-32 lookup table of the reflected polynomial 0xEDB88320.
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 of `data` as used by zlib, PNG and Ethernet (ISO 3309).
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// CRC-32 of the pixels of `image` in the interleaved layout.
+pub fn pixel_checksum(image: &DecodedImage) -> u32 {
+    if image.layout == crate::OutputLayout::Interleaved {
+        crc32(&image.pixels)
+    } else {
+        crc32(&image.to_interleaved().pixels)
+    }
+}
+
+/// Decodes `data`, a JPEG 1, JPEG-LS or JPEG 2000 stream or a JP2 file, and returns a copy
+/// with the CRC-32 of its pixels embedded. A checksum embedded before is replaced.
+///
+/// Returns the error of the decoder if `data` does not decode.
+pub fn embed_checksum(data: &[u8]) -> Result<Vec<u8>, JpeglsError> {
+    let image = Decoder::new().decode(data)?;
+    let text = comment_text(pixel_checksum(&image));
+
+    let mut stream = data.to_vec();
+    if let Some(range) = locate(&stream).and_then(|location| location.existing) {
+        stream.drain(range);
+    }
+    let location = locate(&stream).ok_or(JpeglsError::InvalidData)?;
+    let insertion: Vec<u8> = match location.container {
+        Container::Jpeg => segment(0xFE, &text),
+        Container::Codestream => {
+            // Rcom 1: the comment is Latin-1 text.
+            let mut data = vec![0, 1];
+            data.extend_from_slice(&text);
+            segment(0x64, &data)
+        }
+        Container::Jp2 => {
+            let length = 8 + CHECKSUM_UUID.len() + text.len();
+            let mut uuid_box = (length as u32).to_be_bytes().to_vec();
+            uuid_box.extend_from_slice(b"uuid");
+            uuid_box.extend_from_slice(&CHECKSUM_UUID);
+            uuid_box.extend_from_slice(&text);
+            uuid_box
+        }
+    };
+    stream.splice(location.insert_at..location.insert_at, insertion);
+    Ok(stream)
+}
+
+/// The checksum embedded in `data` by [`embed_checksum`], or `None` if there is none.
+pub fn read_checksum(data: &[u8]) -> Option<u32> {
+    let location = locate(data)?;
+    let range = location.existing?;
+    let text = match location.container {
+        Container::Jpeg => &data[range.start + 4..range.end],
+        Container::Codestream => &data[range.start + 6..range.end],
+        Container::Jp2 => &data[range.start + 8 + CHECKSUM_UUID.len()..range.end],
+    };
+    parse_comment(text)
+}
+
+fn comment_text(checksum: u32) -> Vec<u8> {
+    let mut text = COMMENT_PREFIX.to_vec();
+    text.extend_from_slice(format!("{checksum:08x}").as_bytes());
+    text
+}
+
+fn parse_comment(text: &[u8]) -> Option<u32> {
+    let digits = text.strip_prefix(COMMENT_PREFIX)?;
+    if digits.len() != 8 {
+        return None;
+    }
+    u32::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()
+}
+
+/// A marker segment with `data`.
+fn segment(marker: u8, data: &[u8]) -> Vec<u8> {
+    let mut segment = vec![0xFF, marker];
+    segment.extend_from_slice(&((data.len() + 2) as u16).to_be_bytes());
+    segment.extend_from_slice(data);
+    segment
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Container {
+    /// JPEG 1 or JPEG-LS stream.
+    Jpeg,
+    /// JPEG 2000 codestream.
+    Codestream,
+    Jp2,
+}
+
+/// Where the checksum of a stream goes and, if there is one, the bytes of the segment or
+/// box that holds it.
+struct Location {
+    container: Container,
+    insert_at: usize,
+    existing: Option<std::ops::Range<usize>>,
+}
+
+fn locate(data: &[u8]) -> Option<Location> {
+    if data.starts_with(&[0xFF, 0xD8]) {
+        locate_in_segments(data, Container::Jpeg)
+    } else if data.starts_with(&[0xFF, 0x4F]) {
+        locate_in_segments(data, Container::Codestream)
+    } else if data.starts_with(JP2_SIGNATURE) {
+        locate_in_boxes(data)
+    } else {
+        None
+    }
+}
+
+/// Walks the marker segments of the header up to the frame header (JPEG 1, JPEG-LS) or
+/// the first tile-part (JPEG 2000), where the checksum comment is inserted.
+fn locate_in_segments(data: &[u8], container: Container) -> Option<Location> {
+    let mut position = 2;
+    let mut existing = None;
+    loop {
+        if *data.get(position)? != 0xFF {
+            return None;
+        }
+        let marker = *data.get(position + 1)?;
+        if marker == 0xFF {
+            // Fill byte.
+            position += 1;
+            continue;
+        }
+        let header_ends = match container {
+            // SOFn (but DHT, JPG and DAC), SOF55 (JPEG-LS) and SOS.
+            Container::Jpeg => {
+                matches!(marker, 0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC))
+                    || marker == 0xF7
+                    || marker == 0xDA
+            }
+            // SOT and EOC.
+            _ => marker == 0x90 || marker == 0xD9,
+        };
+        if header_ends {
+            return Some(Location {
+                container,
+                insert_at: position,
+                existing,
+            });
+        }
+        let length = u16::from_be_bytes([*data.get(position + 2)?, *data.get(position + 3)?]);
+        let end = position + 2 + length as usize;
+        let segment_data = data.get(position + 4..end)?;
+        let text = match (container, marker) {
+            (Container::Jpeg, 0xFE) => Some(segment_data),
+            (Container::Codestream, 0x64) => segment_data.get(2..),
+            _ => None,
+        };
+        if text.is_some_and(|text| parse_comment(text).is_some()) {
+            existing = Some(position..end);
+        }
+        position = end;
+    }
+}
+
+/// Walks the top-level boxes of a JP2 file up to the codestream box, in front of which
+/// the checksum box is inserted.
+fn locate_in_boxes(data: &[u8]) -> Option<Location> {
+    let mut position = JP2_SIGNATURE.len();
+    let mut existing = None;
+    loop {
+        let header = data.get(position..position + 8)?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let box_type = &header[4..8];
+        if box_type == b"jp2c" {
+            return Some(Location {
+                container: Container::Jp2,
+                insert_at: position,
+                existing,
+            });
+        }
+        // Boxes with an extended length, or up to the end of the file, are only allowed
+        // for the codestream.
+        if length < 8 {
+            return None;
+        }
+        let end = position.checked_add(length)?;
+        let contents = data.get(position + 8..end)?;
+        if box_type == b"uuid"
+            && contents.starts_with(&CHECKSUM_UUID)
+            && parse_comment(&contents[CHECKSUM_UUID.len()..]).is_some()
+        {
+            existing = Some(position..end);
+        }
+        position = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{Encoder, Format};
+    use crate::FrameInfo;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_embed_and_verify_every_format() {
+        let frame_info = FrameInfo {
+            width: 16,
+            height: 12,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let pixels: Vec<u8> = (0..16 * 12 * 3).map(|i| (i * 7 % 256) as u8).collect();
+        for format in [Format::Jpeg1, Format::Jpegls, Format::Jpeg2000] {
+            let encoded = Encoder::for_format(format)
+                .encode(&pixels, &frame_info)
+                .unwrap();
+            assert_eq!(read_checksum(&encoded), None);
+            assert_eq!(Decoder::auto(&encoded).unwrap().verify_checksum(), None);
+
+            let archived = embed_checksum(&encoded).unwrap();
+            let image = Decoder::auto(&archived).unwrap();
+            assert_eq!(read_checksum(&archived), Some(pixel_checksum(&image)));
+            assert_eq!(image.verify_checksum(), Some(true), "{:?}", format);
+            // Embedding again replaces the checksum.
+            assert_eq!(embed_checksum(&archived).unwrap(), archived);
+
+            let mut planar = Decoder::new();
+            planar.set_output_layout(crate::OutputLayout::Planar);
+            assert_eq!(
+                planar.decode(&archived).unwrap().verify_checksum(),
+                Some(true)
+            );
+
+            let mut corrupted = image.clone();
+            corrupted.pixels[5] ^= 1;
+            assert_eq!(corrupted.verify_checksum(), Some(false));
+        }
+    }
+
+    #[test]
+    fn test_embed_in_jp2() {
+        let frame_info = FrameInfo {
+            width: 8,
+            height: 8,
+            bits_per_sample: 8,
+            component_count: 1,
+        };
+        let codestream = Encoder::for_format(Format::Jpeg2000)
+            .encode(&[100; 64], &frame_info)
+            .unwrap();
+        let mut jp2 = JP2_SIGNATURE.to_vec();
+        jp2.extend_from_slice(&20u32.to_be_bytes());
+        jp2.extend_from_slice(b"ftypjp2 \0\0\0\0jp2 ");
+        jp2.extend_from_slice(&(codestream.len() as u32 + 8).to_be_bytes());
+        jp2.extend_from_slice(b"jp2c");
+        jp2.extend_from_slice(&codestream);
+
+        let archived = embed_checksum(&jp2).unwrap();
+        assert_eq!(
+            archived.len(),
+            jp2.len() + 8 + 16 + COMMENT_PREFIX.len() + 8
+        );
+        assert_eq!(&archived[36..40], b"uuid");
+        let image = Decoder::auto(&archived).unwrap();
+        assert_eq!(image.verify_checksum(), Some(true));
+    }
+}
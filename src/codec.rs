@@ -0,0 +1,593 @@
+//! High-level entry points for applications and bindings.
+//!
+//! [`detect_format`] tells the codec of a stream from its first bytes, [`Decoder::auto`]
+//! decodes a JPEG 1, JPEG-LS or JPEG 2000 stream, and [`Encoder::for_format`] encodes
+//! pixels with one codec:
+//!
+//! ```rust
+//! use jpegexp_rs::codec::{detect_format, Decoder, Encoder, Format};
+//! use jpegexp_rs::FrameInfo;
+//!
+//! let frame_info = FrameInfo { width: 8, height: 8, bits_per_sample: 8, component_count: 1 };
+//! let pixels: Vec<u8> = (0..64).collect();
+//! let encoded = Encoder::for_format(Format::Jpegls).encode(&pixels, &frame_info).unwrap();
+//!
+//! assert_eq!(detect_format(&encoded), Some(Format::Jpegls));
+//! let image = Decoder::auto(&encoded).unwrap();
+//! assert_eq!(image.format, Format::Jpegls);
+//! assert_eq!(image.pixels, pixels);
+//! ```
+
+use crate::checksum;
+use crate::error::JpeglsError;
+use crate::jpeg1::encoder::{Jpeg1Encoder, Jpeg1EncoderBuilder};
+use crate::jpeg2000::encoder::{J2kEncoder, J2kEncoderBuilder};
+use crate::jpeg2000::jp2::Jp2Writer;
+use crate::jpeg_stream_reader::is_jpegls;
+use crate::jpeg_stream_writer::{Sink, WriteSink};
+use crate::jpegls::{ColorTransformation, InterleaveMode, JpeglsEncoderBuilder};
+use crate::resolution::Resolution;
+use crate::FrameInfo;
+use std::io::{self, Write};
+
+pub use crate::decoder::{DecodedImage, Decoder, ImageMetadata};
+
+/// Compression format of a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    /// JPEG 1 baseline DCT (ISO/IEC 10918-1).
+    Jpeg1,
+    /// JPEG-LS lossless or near-lossless (ISO/IEC 14495-1).
+    Jpegls,
+    /// JPEG 2000 Part 1 (ISO/IEC 15444-1).
+    Jpeg2000,
+}
+
+impl Format {
+    /// Short name of the format: `"jpeg"`, `"jpegls"` or `"j2k"`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Jpeg1 => "jpeg",
+            Self::Jpegls => "jpegls",
+            Self::Jpeg2000 => "j2k",
+        }
+    }
+
+    /// Parses a name returned by [`name`](Self::name); `"jpeg2000"` is accepted for
+    /// JPEG 2000 too.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "jpeg" => Some(Self::Jpeg1),
+            "jpegls" => Some(Self::Jpegls),
+            "j2k" | "jpeg2000" => Some(Self::Jpeg2000),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the format of a stream from its first bytes, or `None` if it is not recognized.
+///
+/// JPEG 1 and JPEG-LS share the start of image marker; a stream is JPEG-LS when a
+/// JPEG-LS frame or preset parameters marker precedes the first scan.
+pub fn detect_format(data: &[u8]) -> Option<Format> {
+    if data.starts_with(&[0xFF, 0xD8]) {
+        Some(if is_jpegls(data) {
+            Format::Jpegls
+        } else {
+            Format::Jpeg1
+        })
+    } else if data.starts_with(&[0xFF, 0x4F]) || data.starts_with(b"\x00\x00\x00\x0CjP") {
+        Some(Format::Jpeg2000)
+    } else {
+        None
+    }
+}
+
+/// Encodes pixels with the codec it was created for. Options that do not apply to the
+/// codec are ignored.
+///
+/// Pixels are interleaved; samples wider than 8 bits are 16-bit values in native byte
+/// order, which JPEG-LS and, for 12-bit samples, JPEG 1 accept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Encoder {
+    format: Format,
+    quality: Option<u8>,
+    near_lossless: i32,
+    interleave_mode: InterleaveMode,
+    color_transformation: ColorTransformation,
+    restart_interval: u16,
+    decomposition_levels: Option<u8>,
+    irreversible: bool,
+    high_throughput: bool,
+    deterministic: bool,
+    embed_checksum: bool,
+    resolution: Option<Resolution>,
+}
+
+impl Encoder {
+    /// Creates an encoder for `format` with the default options of its codec.
+    pub fn for_format(format: Format) -> Self {
+        Self {
+            format,
+            quality: None,
+            near_lossless: 0,
+            interleave_mode: InterleaveMode::None,
+            color_transformation: ColorTransformation::None,
+            restart_interval: 0,
+            decomposition_levels: None,
+            irreversible: true,
+            high_throughput: false,
+            deterministic: false,
+            embed_checksum: false,
+            resolution: None,
+        }
+    }
+
+    /// Format the encoder writes.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// JPEG 1 and JPEG 2000 quality (1-100). Defaults to the codec's default. JPEG 2000
+    /// only writes it as QCD step sizes, as its encoder codes no code-block data yet.
+    pub fn set_quality(&mut self, quality: u8) {
+        self.quality = Some(quality);
+    }
+
+    /// JPEG-LS NEAR parameter; 0, the default, is lossless.
+    pub fn set_near_lossless(&mut self, near_lossless: i32) {
+        self.near_lossless = near_lossless;
+    }
+
+    /// JPEG-LS interleave mode of multi-component images. Defaults to
+    /// [`InterleaveMode::None`].
+    pub fn set_interleave_mode(&mut self, interleave_mode: InterleaveMode) {
+        self.interleave_mode = interleave_mode;
+    }
+
+    /// JPEG-LS color transformation. Defaults to [`ColorTransformation::None`].
+    pub fn set_color_transformation(&mut self, color_transformation: ColorTransformation) {
+        self.color_transformation = color_transformation;
+    }
+
+    /// JPEG 1 restart interval in MCUs and JPEG-LS restart interval in lines; 0, the
+    /// default, disables restart markers.
+    pub fn set_restart_interval(&mut self, restart_interval: u16) {
+        self.restart_interval = restart_interval;
+    }
+
+    /// JPEG 2000 wavelet decomposition levels. Defaults to the codec's default.
+    pub fn set_decomposition_levels(&mut self, levels: u8) {
+        self.decomposition_levels = Some(levels);
+    }
+
+    /// JPEG 2000 9-7 irreversible (`true`, the default) or 5-3 reversible transform.
+    pub fn set_irreversible(&mut self, irreversible: bool) {
+        self.irreversible = irreversible;
+    }
+
+    /// JPEG 2000 codestream using the HT block coder (HTJ2K); see
+    /// [`J2kEncoder::set_high_throughput`]. Defaults to `false`.
+    pub fn set_high_throughput(&mut self, high_throughput: bool) {
+        self.high_throughput = high_throughput;
+    }
+
+    /// Byte-identical streams for the same pixels and options on every platform and at
+    /// any thread count; see [`Jpeg1Encoder::set_encode_deterministic`] and
+    /// [`JpeglsEncoder::set_encode_deterministic`](crate::jpegls::JpeglsEncoder::set_encode_deterministic).
+    /// The JPEG 2000 encoder needs no switch: it runs on one thread and uses no
+    /// platform-dependent math. Defaults to `false`.
+    pub fn set_encode_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    /// Embeds the CRC-32 of the decoded pixels in the stream, see
+    /// [`checksum::embed_checksum`](crate::checksum::embed_checksum). The stream is decoded
+    /// once more for it and is written to the sink or destination only when it is
+    /// complete. Defaults to `false`.
+    pub fn set_embed_checksum(&mut self, embed_checksum: bool) {
+        self.embed_checksum = embed_checksum;
+    }
+
+    /// Writes the pixel density in the JFIF segment of JPEG 1 streams and the SPIFF header
+    /// of JPEG-LS streams, see [`resolution`](crate::resolution). JPEG 2000 codestreams
+    /// have no field for it, so they are written in a JP2 file with a capture resolution
+    /// box, which is produced whole before it is written to the sink or destination.
+    pub fn set_resolution(&mut self, resolution: Resolution) {
+        self.resolution = Some(resolution);
+    }
+
+    /// Size of a destination buffer large enough to encode an image described by
+    /// `frame_info` with these options; see the `estimated_destination_size` function of
+    /// each codec's encoder.
+    pub fn estimated_destination_size(&self, frame_info: &FrameInfo) -> usize {
+        match self.format {
+            Format::Jpeg1 => {
+                let mut encoder = Jpeg1Encoder::new();
+                encoder.set_restart_interval(self.restart_interval);
+                if let Some(resolution) = self.resolution {
+                    encoder.set_resolution(resolution);
+                }
+                encoder.estimated_destination_size(frame_info)
+            }
+            Format::Jpegls => JpeglsEncoderBuilder::new(*frame_info)
+                .interleave_mode(self.interleave_mode)
+                .restart_interval(self.restart_interval)
+                .estimated_destination_size(),
+            Format::Jpeg2000 => {
+                let mut encoder = J2kEncoder::new();
+                if let Some(levels) = self.decomposition_levels {
+                    encoder.set_decomposition_levels(levels);
+                }
+                // The boxes of a JP2 file around the codestream.
+                let jp2_boxes = if self.resolution.is_some() { 128 } else { 0 };
+                encoder.estimated_destination_size(frame_info) + jp2_boxes
+            }
+        }
+    }
+
+    /// Encodes `pixels` into a new buffer, which grows as the stream is written.
+    pub fn encode(&self, pixels: &[u8], frame_info: &FrameInfo) -> Result<Vec<u8>, JpeglsError> {
+        let mut stream = Vec::new();
+        self.encode_to_sink(pixels, frame_info, &mut stream)?;
+        Ok(stream)
+    }
+
+    /// Encodes `pixels` to `sink` as the stream is produced, returning its length.
+    pub fn encode_to_sink<D: Sink>(
+        &self,
+        pixels: &[u8],
+        frame_info: &FrameInfo,
+        mut sink: D,
+    ) -> Result<usize, JpeglsError> {
+        if self.encodes_whole_stream() {
+            let stream = self.encode_whole_stream(pixels, frame_info)?;
+            sink.write(&stream)?;
+            return Ok(stream.len());
+        }
+        match self.format {
+            Format::Jpeg1 => self
+                .jpeg1_encoder(frame_info)?
+                .encode_to_sink(pixels, frame_info, sink),
+            Format::Jpegls => self
+                .jpegls_builder(frame_info)
+                .build_with_sink(sink)?
+                .encode(pixels),
+            Format::Jpeg2000 => self
+                .j2k_encoder(frame_info)?
+                .encode_to_sink(pixels, frame_info, sink),
+        }
+    }
+
+    /// Encodes `pixels` to `writer`, e.g. a file or socket, as the stream is produced and
+    /// returns its length. The encoders write a few bytes at a time, so `writer` should be
+    /// buffered.
+    ///
+    /// I/O errors are returned as they are, encoding errors as
+    /// [`io::ErrorKind::InvalidInput`] errors that wrap the [`JpeglsError`].
+    pub fn encode_to_writer<W: Write>(
+        &self,
+        pixels: &[u8],
+        frame_info: &FrameInfo,
+        writer: W,
+    ) -> io::Result<usize> {
+        let mut sink = WriteSink::new(writer);
+        self.encode_to_sink(pixels, frame_info, &mut sink)
+            .map_err(|e| {
+                sink.take_error()
+                    .unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, e))
+            })
+    }
+
+    /// Encodes `pixels` into `destination`, returning the length of the stream.
+    ///
+    /// The frame info and options are checked by the codec's encoder builder before
+    /// anything is written, so 16-bit samples, for instance, give
+    /// [`JpeglsError::InvalidArgumentBitsPerSample`] unless the format is JPEG-LS. Returns
+    /// [`JpeglsError::DestinationTooSmall`] with the size to retry with if the stream does
+    /// not fit.
+    pub fn encode_into(
+        &self,
+        pixels: &[u8],
+        frame_info: &FrameInfo,
+        destination: &mut [u8],
+    ) -> Result<usize, JpeglsError> {
+        if self.encodes_whole_stream() {
+            let stream = self.encode_whole_stream(pixels, frame_info)?;
+            destination
+                .get_mut(..stream.len())
+                .ok_or(JpeglsError::DestinationTooSmall {
+                    needed: stream.len(),
+                })?
+                .copy_from_slice(&stream);
+            return Ok(stream.len());
+        }
+        match self.format {
+            Format::Jpeg1 => {
+                self.jpeg1_encoder(frame_info)?
+                    .encode(pixels, frame_info, destination)
+            }
+            Format::Jpegls => self
+                .jpegls_builder(frame_info)
+                .build(destination)?
+                .encode(pixels),
+            Format::Jpeg2000 => {
+                self.j2k_encoder(frame_info)?
+                    .encode(pixels, frame_info, destination)
+            }
+        }
+    }
+
+    /// True when the stream is changed after it is encoded: a checksum is embedded or a
+    /// JPEG 2000 codestream is written in a JP2 file.
+    fn encodes_whole_stream(&self) -> bool {
+        self.embed_checksum || (self.format == Format::Jpeg2000 && self.resolution.is_some())
+    }
+
+    fn encode_whole_stream(
+        &self,
+        pixels: &[u8],
+        frame_info: &FrameInfo,
+    ) -> Result<Vec<u8>, JpeglsError> {
+        let mut stream = Vec::new();
+        match self.format {
+            Format::Jpeg2000 => {
+                self.j2k_encoder(frame_info)?
+                    .encode_to_sink(pixels, frame_info, &mut stream)?;
+                if let Some(resolution) = self.resolution {
+                    let mut writer = Jp2Writer::new();
+                    writer.set_resolution(resolution);
+                    stream = writer.write(&stream)?;
+                }
+            }
+            _ => {
+                let encoder = Encoder {
+                    embed_checksum: false,
+                    ..*self
+                };
+                encoder.encode_to_sink(pixels, frame_info, &mut stream)?;
+            }
+        }
+        if self.embed_checksum {
+            stream = checksum::embed_checksum(&stream)?;
+        }
+        Ok(stream)
+    }
+
+    fn jpeg1_encoder(&self, frame_info: &FrameInfo) -> Result<Jpeg1Encoder, JpeglsError> {
+        let mut builder = Jpeg1EncoderBuilder::new(*frame_info)
+            .restart_interval(self.restart_interval)
+            .encode_deterministic(self.deterministic);
+        if let Some(quality) = self.quality {
+            builder = builder.quality(quality);
+        }
+        if let Some(resolution) = self.resolution {
+            builder = builder.resolution(resolution);
+        }
+        builder.build()
+    }
+
+    fn jpegls_builder(&self, frame_info: &FrameInfo) -> JpeglsEncoderBuilder {
+        let builder = JpeglsEncoderBuilder::new(*frame_info)
+            .near_lossless(self.near_lossless)
+            .interleave_mode(self.interleave_mode)
+            .color_transformation(self.color_transformation)
+            .restart_interval(self.restart_interval)
+            .encode_deterministic(self.deterministic);
+        match self.resolution {
+            Some(resolution) => builder.resolution(resolution),
+            None => builder,
+        }
+    }
+
+    fn j2k_encoder(&self, frame_info: &FrameInfo) -> Result<J2kEncoder, JpeglsError> {
+        let mut builder = J2kEncoderBuilder::new(*frame_info)
+            .irreversible(self.irreversible)
+            .high_throughput(self.high_throughput);
+        if let Some(quality) = self.quality {
+            builder = builder.quality(quality);
+        }
+        if let Some(levels) = self.decomposition_levels {
+            builder = builder.decomposition_levels(levels);
+        }
+        builder.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(frame_info: &FrameInfo) -> Vec<u8> {
+        let len = frame_info
+            .decoded_size(crate::OutputLayout::Interleaved)
+            .unwrap();
+        (0..len).map(|i| (i * 5 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_encode_and_decode_every_format() {
+        let frame_info = FrameInfo {
+            width: 16,
+            height: 16,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let pixels = gradient(&frame_info);
+        for format in [Format::Jpeg1, Format::Jpegls, Format::Jpeg2000] {
+            let encoded = Encoder::for_format(format)
+                .encode(&pixels, &frame_info)
+                .unwrap();
+            assert_eq!(detect_format(&encoded), Some(format));
+
+            let image = Decoder::auto(&encoded).unwrap();
+            assert_eq!(image.format, format);
+            assert_eq!(image.frame_info, frame_info);
+            assert_eq!(image.pixels.len(), pixels.len());
+            if format == Format::Jpegls {
+                assert_eq!(image.pixels, pixels);
+            }
+        }
+    }
+
+    #[test]
+    fn test_noise_fits_estimated_destination_size() {
+        let mut seed = 0x2545_f491u32;
+        for (width, height, component_count) in [(1, 1, 1), (1, 300, 1), (64, 48, 3)] {
+            let frame_info = FrameInfo {
+                width,
+                height,
+                bits_per_sample: 8,
+                component_count,
+            };
+            let len = frame_info
+                .decoded_size(crate::OutputLayout::Interleaved)
+                .unwrap();
+            let pixels: Vec<u8> = (0..len)
+                .map(|_| {
+                    seed ^= seed << 13;
+                    seed ^= seed >> 17;
+                    seed ^= seed << 5;
+                    seed as u8
+                })
+                .collect();
+            for format in [Format::Jpeg1, Format::Jpegls, Format::Jpeg2000] {
+                let mut encoder = Encoder::for_format(format);
+                encoder.set_quality(100);
+                let size = encoder.estimated_destination_size(&frame_info);
+                let mut destination = vec![0u8; size];
+                let encoded_len = encoder
+                    .encode_into(&pixels, &frame_info, &mut destination)
+                    .unwrap_or_else(|e| panic!("{:?} {:?}: {}", format, frame_info, e));
+                assert!(encoded_len <= size);
+            }
+        }
+    }
+
+    #[test]
+    fn test_destination_too_small_reports_estimated_size() {
+        let frame_info = FrameInfo {
+            width: 24,
+            height: 16,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let pixels = gradient(&frame_info);
+        for format in [Format::Jpeg1, Format::Jpegls, Format::Jpeg2000] {
+            let encoder = Encoder::for_format(format);
+            let mut destination = [0u8; 16];
+            match encoder.encode_into(&pixels, &frame_info, &mut destination) {
+                Err(JpeglsError::DestinationTooSmall { needed }) => {
+                    assert_eq!(needed, encoder.estimated_destination_size(&frame_info))
+                }
+                result => panic!("{:?}: unexpected result {:?}", format, result),
+            }
+            let mut grown = vec![0u8; encoder.estimated_destination_size(&frame_info)];
+            let len = encoder
+                .encode_into(&pixels, &frame_info, &mut grown)
+                .unwrap();
+            assert_eq!(&grown[..len], encoder.encode(&pixels, &frame_info).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_encode_to_writer() {
+        struct FailingWriter;
+
+        impl Write for FailingWriter {
+            fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let frame_info = FrameInfo {
+            width: 24,
+            height: 16,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let pixels = gradient(&frame_info);
+        for format in [Format::Jpeg1, Format::Jpegls, Format::Jpeg2000] {
+            let mut encoder = Encoder::for_format(format);
+            encoder.set_restart_interval(2);
+            let mut written = Vec::new();
+            let len = encoder
+                .encode_to_writer(&pixels, &frame_info, &mut written)
+                .unwrap();
+            assert_eq!(len, written.len());
+            assert_eq!(written, encoder.encode(&pixels, &frame_info).unwrap());
+
+            let error = encoder
+                .encode_to_writer(&pixels, &frame_info, FailingWriter)
+                .unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+            let error = encoder
+                .encode_to_writer(&pixels[1..], &frame_info, &mut written)
+                .unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn test_embed_checksum() {
+        let frame_info = FrameInfo {
+            width: 16,
+            height: 8,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let pixels = gradient(&frame_info);
+        for format in [Format::Jpeg1, Format::Jpegls, Format::Jpeg2000] {
+            let mut encoder = Encoder::for_format(format);
+            encoder.set_embed_checksum(true);
+            let encoded = encoder.encode(&pixels, &frame_info).unwrap();
+            assert_eq!(
+                Decoder::auto(&encoded).unwrap().verify_checksum(),
+                Some(true)
+            );
+
+            let mut destination = vec![0u8; encoded.len() - 1];
+            assert_eq!(
+                encoder.encode_into(&pixels, &frame_info, &mut destination),
+                Err(JpeglsError::DestinationTooSmall {
+                    needed: encoded.len()
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_12_bit_samples() {
+        let frame_info = FrameInfo {
+            width: 8,
+            height: 8,
+            bits_per_sample: 12,
+            component_count: 1,
+        };
+        let pixels = vec![0u8; 128];
+        assert!(Encoder::for_format(Format::Jpegls)
+            .encode(&pixels, &frame_info)
+            .is_ok());
+        assert!(Encoder::for_format(Format::Jpeg1)
+            .encode(&pixels, &frame_info)
+            .is_ok());
+        assert_eq!(
+            Encoder::for_format(Format::Jpeg2000).encode(&pixels, &frame_info),
+            Err(JpeglsError::InvalidArgumentBitsPerSample)
+        );
+    }
+
+    #[test]
+    fn test_format_names() {
+        for format in [Format::Jpeg1, Format::Jpegls, Format::Jpeg2000] {
+            assert_eq!(Format::from_name(format.name()), Some(format));
+        }
+        assert_eq!(Format::from_name("jpeg2000"), Some(Format::Jpeg2000));
+        assert_eq!(Format::from_name("png"), None);
+        assert_eq!(detect_format(b"\x89PNG"), None);
+    }
+}
@@ -0,0 +1,1350 @@
+//! Codec-independent decoding of complete and fragmented streams.
+//!
+//! [`Decoder`] detects the codec of a stream with [`detect_format`] and decodes it with
+//! the matching decoder.
+//! DICOM encapsulated pixel data stores every frame as one or more fragments, and a
+//! fragment boundary can fall anywhere in the stream, even inside a marker segment.
+//! [`Decoder::decode_fragments`] joins the fragments of one frame before decoding, and
+//! [`Decoder::decode_frame`] picks one frame out of the fragments of a multi-frame object:
+//!
+//! ```rust
+//! use jpegexp_rs::decoder::Decoder;
+//! use jpegexp_rs::jpegls::JpeglsEncoder;
+//! use jpegexp_rs::FrameInfo;
+//!
+//! let frame_info = FrameInfo { width: 8, height: 8, bits_per_sample: 8, component_count: 1 };
+//! let pixels: Vec<u8> = (0..64).collect();
+//! let mut encoded = vec![0u8; 1024];
+//! let mut encoder = JpeglsEncoder::new(&mut encoded);
+//! encoder.set_frame_info(frame_info).unwrap();
+//! let len = encoder.encode(&pixels).unwrap();
+//!
+//! // Two frames, the first split into two fragments in the middle of its header.
+//! let fragments = [&encoded[..5], &encoded[5..len], &encoded[..len]];
+//! let decoder = Decoder::new();
+//! assert_eq!(Decoder::frame_count(&fragments), 2);
+//! assert_eq!(decoder.decode_frame(&fragments, 0).unwrap().pixels, pixels);
+//! assert_eq!(decoder.decode_frame(&fragments, 1).unwrap().pixels, pixels);
+//! ```
+//!
+//! Without a Basic Offset Table a frame is assumed to start at every fragment that begins
+//! with a start of image (JPEG, JPEG-LS), start of codestream (JPEG 2000) or JP2 signature.
+//! When the offset table is present, [`Decoder::frames_from_offset_table`] uses it instead.
+
+use std::borrow::Cow;
+
+use crate::checksum;
+use crate::codec::{detect_format, Format};
+use crate::error::{JpeglsError, Truncation};
+use crate::jpeg1::decoder::{luma, ColorSpace, Jpeg1Decoder};
+use crate::jpeg1::metadata::read_metadata;
+use crate::jpeg2000::decoder::J2kDecoder;
+use crate::jpeg2000::image::{J2kSamples, J2kUpsampling};
+use crate::jpeg2000::jp2::{Jp2ChannelDefinition, Jp2UuidBox};
+use crate::jpeg2000::parser::J2kParser;
+use crate::jpeg_stream_reader::JpegStreamReader;
+use crate::jpegls::{JpeglsDecoder, MappingTable};
+use crate::pixelops;
+use crate::resolution::{self, Resolution};
+use crate::{ColorConversion, ComponentInfo, FrameInfo, OutputLayout};
+
+/// Size of the item tag and item length that precede every fragment in DICOM
+/// encapsulated pixel data; Basic Offset Table entries count these bytes.
+const FRAGMENT_ITEM_HEADER_SIZE: usize = 8;
+
+/// Default of [`Decoder::set_max_decoded_bytes`].
+const DEFAULT_MAX_DECODED_BYTES: usize = 1 << 30;
+
+/// A decoded frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedImage {
+    /// Dimensions and sample precision of `pixels`. JPEG 1 frames are always decoded to
+    /// 8-bit samples.
+    pub frame_info: FrameInfo,
+    /// Samples in `layout`. Samples wider than 8 bits are stored as little-endian 16-bit
+    /// values.
+    pub pixels: Vec<u8>,
+    /// Arrangement of the components in `pixels`, the decoder's [`OutputLayout`].
+    pub layout: OutputLayout,
+    /// Format of the decoded stream.
+    pub format: Format,
+    pub metadata: ImageMetadata,
+}
+
+impl DecodedImage {
+    /// The samples if they are at most 8 bits wide, or `None` for 16-bit samples.
+    pub fn as_u8(&self) -> Option<&[u8]> {
+        (self.bytes_per_sample() == 1).then_some(&self.pixels[..])
+    }
+
+    /// The samples if they are wider than 8 bits, or `None` for 8-bit samples. The samples
+    /// are copied, as the byte buffer is not aligned for `u16`.
+    pub fn as_u16(&self) -> Option<Vec<u16>> {
+        (self.bytes_per_sample() == 2).then(|| {
+            self.pixels
+                .chunks_exact(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                .collect()
+        })
+    }
+
+    /// A copy of the image with interleaved components (RGBRGB...).
+    pub fn to_interleaved(&self) -> DecodedImage {
+        self.to_layout(OutputLayout::Interleaved)
+    }
+
+    /// A copy of the image with one plane per component (RRR...GGG...BBB...).
+    pub fn to_planar(&self) -> DecodedImage {
+        self.to_layout(OutputLayout::Planar)
+    }
+
+    /// A copy of the `width` x `height` rectangle whose top left pixel is at (`x`, `y`),
+    /// in the same layout.
+    ///
+    /// Returns [`JpeglsError::InvalidArgumentWidth`] or
+    /// [`JpeglsError::InvalidArgumentHeight`] if the rectangle does not lie within the
+    /// image.
+    pub fn crop(
+        &self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<DecodedImage, JpeglsError> {
+        if x.checked_add(width)
+            .is_none_or(|right| right > self.frame_info.width)
+        {
+            return Err(JpeglsError::InvalidArgumentWidth);
+        }
+        if y.checked_add(height)
+            .is_none_or(|bottom| bottom > self.frame_info.height)
+        {
+            return Err(JpeglsError::InvalidArgumentHeight);
+        }
+
+        let (planes, pixel_bytes) = self.planes();
+        let (x, y, width) = (x as usize, y as usize, width as usize);
+        let image_width = self.frame_info.width as usize;
+        let image_height = self.frame_info.height as usize;
+        let frame_info = FrameInfo {
+            width: width as u32,
+            height,
+            ..self.frame_info
+        };
+        let mut pixels = Vec::with_capacity(frame_info.decoded_size(self.layout)?);
+        for plane in 0..planes {
+            for row in y..y + height as usize {
+                let start = ((plane * image_height + row) * image_width + x) * pixel_bytes;
+                pixels.extend_from_slice(&self.pixels[start..start + width * pixel_bytes]);
+            }
+        }
+        Ok(DecodedImage {
+            frame_info,
+            pixels,
+            metadata: self.metadata_without_checksum(),
+            ..self.clone()
+        })
+    }
+
+    /// A copy of a three-component image as one channel of luminance,
+    /// 0.299 R + 0.587 G + 0.114 B; images with other component counts are copied as is.
+    pub fn to_gray(&self) -> DecodedImage {
+        if self.frame_info.component_count != 3 {
+            return self.clone();
+        }
+
+        let bytes_per_sample = self.bytes_per_sample();
+        let pixel_count = self.frame_info.width as usize * self.frame_info.height as usize;
+        let sample = |i: usize| {
+            let bytes = &self.pixels[i * bytes_per_sample..(i + 1) * bytes_per_sample];
+            match bytes {
+                [low, high] => u16::from_le_bytes([*low, *high]) as f32,
+                _ => bytes[0] as f32,
+            }
+        };
+        let mut pixels = Vec::with_capacity(pixel_count * bytes_per_sample);
+        for p in 0..pixel_count {
+            let [r, g, b] = [0, 1, 2].map(|c| {
+                sample(match self.layout {
+                    OutputLayout::Interleaved => p * 3 + c,
+                    OutputLayout::Planar => c * pixel_count + p,
+                })
+            });
+            let gray = luma(r, g, b).round() as u16;
+            pixels.extend_from_slice(&gray.to_le_bytes()[..bytes_per_sample]);
+        }
+        DecodedImage {
+            frame_info: FrameInfo {
+                component_count: 1,
+                ..self.frame_info
+            },
+            pixels,
+            metadata: ImageMetadata {
+                channel_definitions: Vec::new(),
+                ..self.metadata_without_checksum()
+            },
+            ..self.clone()
+        }
+    }
+
+    /// Shrinks an interleaved image of unsigned samples to [`thumbnail_size`].
+    fn downscale(self, max_dim: u32) -> Result<DecodedImage, JpeglsError> {
+        let FrameInfo { width, height, .. } = self.frame_info;
+        let (new_width, new_height) = thumbnail_size(width, height, max_dim);
+        if (new_width, new_height) == (width, height) {
+            return Ok(self);
+        }
+
+        let components = self.frame_info.component_count.max(1) as usize;
+        let size = [width, height, new_width, new_height].map(|v| v as usize);
+        let pixels = match self.as_u16() {
+            None => pixelops::downscale_box(
+                &self.pixels,
+                size[0],
+                size[1],
+                components,
+                size[2],
+                size[3],
+            )?,
+            Some(samples) => {
+                pixelops::downscale_box(&samples, size[0], size[1], components, size[2], size[3])?
+                    .into_iter()
+                    .flat_map(u16::to_le_bytes)
+                    .collect()
+            }
+        };
+        Ok(DecodedImage {
+            frame_info: FrameInfo {
+                width: new_width,
+                height: new_height,
+                ..self.frame_info
+            },
+            pixels,
+            metadata: ImageMetadata {
+                resolution: self
+                    .metadata
+                    .resolution
+                    .map(|r| r.scaled((width, height), (new_width, new_height))),
+                ..self.metadata_without_checksum()
+            },
+            ..self
+        })
+    }
+
+    /// Compares the pixels with the checksum embedded in the stream: `Some(true)` when
+    /// they match, `Some(false)` when the stream or the pixels were corrupted, and `None`
+    /// when there is no checksum to compare with ([`ImageMetadata::checksum`]).
+    pub fn verify_checksum(&self) -> Option<bool> {
+        let expected = self.metadata.checksum?;
+        Some(checksum::pixel_checksum(self) == expected)
+    }
+
+    /// True if a component is an opacity (alpha) channel, premultiplied or not, according
+    /// to [`ImageMetadata::channel_definitions`].
+    pub fn has_alpha(&self) -> bool {
+        self.metadata
+            .channel_definitions
+            .iter()
+            .any(|definition| definition.channel_type.is_opacity())
+    }
+
+    /// The pixel buffer.
+    pub fn into_vec(self) -> Vec<u8> {
+        self.pixels
+    }
+
+    fn metadata_without_checksum(&self) -> ImageMetadata {
+        ImageMetadata {
+            checksum: None,
+            ..self.metadata.clone()
+        }
+    }
+
+    fn bytes_per_sample(&self) -> usize {
+        (self.frame_info.bits_per_sample.max(1) as usize).div_ceil(8)
+    }
+
+    /// Number of planes in `pixels` and the bytes a pixel takes in each.
+    fn planes(&self) -> (usize, usize) {
+        let components = self.frame_info.component_count.max(1) as usize;
+        match self.layout {
+            OutputLayout::Interleaved => (1, components * self.bytes_per_sample()),
+            OutputLayout::Planar => (components, self.bytes_per_sample()),
+        }
+    }
+
+    fn to_layout(&self, layout: OutputLayout) -> DecodedImage {
+        let components = self.frame_info.component_count.max(1) as usize;
+        if layout == self.layout || components == 1 {
+            return DecodedImage {
+                layout,
+                ..self.clone()
+            };
+        }
+
+        let bytes_per_sample = self.bytes_per_sample();
+        let plane_len = self.frame_info.width as usize * self.frame_info.height as usize;
+        let mut pixels = vec![0u8; self.pixels.len()];
+        for (i, sample) in self.pixels.chunks_exact(bytes_per_sample).enumerate() {
+            // Index of the sample in the interleaved and in the planar arrangement.
+            let (interleaved, planar) = match self.layout {
+                OutputLayout::Interleaved => (i, (i % components) * plane_len + i / components),
+                OutputLayout::Planar => ((i % plane_len) * components + i / plane_len, i),
+            };
+            let dest = match layout {
+                OutputLayout::Interleaved => interleaved,
+                OutputLayout::Planar => planar,
+            } * bytes_per_sample;
+            pixels[dest..dest + bytes_per_sample].copy_from_slice(sample);
+        }
+        DecodedImage {
+            pixels,
+            layout,
+            ..self.clone()
+        }
+    }
+}
+
+/// Properties of the decoded stream that `frame_info` does not describe.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ImageMetadata {
+    /// JPEG 1 progressive DCT process (SOF2).
+    pub progressive: bool,
+    /// JPEG 1 lossless process (SOF3).
+    pub lossless: bool,
+    /// Color space of a JPEG 1 DCT-based frame, from its JFIF and Adobe segments; `None`
+    /// for lossless frames and the other formats.
+    pub color_space: Option<ColorSpace>,
+    /// JPEG 2000 codestream using the Part 15 high-throughput block coder (HTJ2K).
+    pub high_throughput: bool,
+    /// JPEG 2000 codestream using the reversible 5/3 wavelet, which reconstructs the
+    /// samples exactly when every quality layer is decoded.
+    pub reversible: bool,
+    /// ICC profile of a JP2 file's colour specification box.
+    pub icc_profile: Option<Vec<u8>>,
+    /// Roles of the components from the channel definition box of a JP2 file, e.g. which
+    /// one is opacity and whether the colours are premultiplied by it; see
+    /// [`DecodedImage::has_alpha`]. Empty without the box, when every component is a
+    /// colour channel.
+    pub channel_definitions: Vec<Jp2ChannelDefinition>,
+    /// Contents of the XML boxes of a JP2 file, e.g. XMP packets or GML metadata, to copy
+    /// into a new file with [`Jp2Writer`](crate::jpeg2000::jp2::Jp2Writer).
+    pub xml_boxes: Vec<Vec<u8>>,
+    /// UUID boxes of a JP2 file, e.g. GeoJP2 georeferencing, other than the one holding
+    /// [`checksum`](Self::checksum).
+    pub uuid_boxes: Vec<Jp2UuidBox>,
+    /// JPEG-LS mapping tables (palettes) of the stream. The samples of a component that
+    /// selects one are indices into it.
+    pub mapping_tables: Vec<MappingTable>,
+    /// JPEG-LS mapping table selected by each component, 0 for none; empty for the other
+    /// formats.
+    pub mapping_table_ids: Vec<u8>,
+    /// Where a stream decoded in tolerant mode ended early, see
+    /// [`Decoder::set_tolerant`]; `None` when the whole stream was read.
+    pub truncation: Option<Truncation>,
+    /// Precision, signedness and subsampling of every component of the stream. The
+    /// decoded samples are upsampled and stored at the `frame_info` precision; these are
+    /// the values the stream codes them with.
+    pub components: Vec<ComponentInfo>,
+    /// Pixel density from the JFIF segment, SPIFF header or JP2 resolution box; see
+    /// [`resolution`](crate::resolution). Thumbnails give the density of their smaller
+    /// pixels.
+    pub resolution: Option<Resolution>,
+    /// CRC-32 of the pixels embedded in the stream by
+    /// [`embed_checksum`](crate::checksum::embed_checksum); see
+    /// [`DecodedImage::verify_checksum`]. `None` when the stream has none, and for
+    /// thumbnails and images converted to gray, crop or downscaled copies, whose pixels it
+    /// does not describe.
+    pub checksum: Option<u32>,
+}
+
+/// Decodes JPEG 1, JPEG-LS and JPEG 2000 streams, detecting the codec from the data.
+#[derive(Debug, Clone, Copy)]
+pub struct Decoder {
+    output_layout: OutputLayout,
+    color_conversion: ColorConversion,
+    tolerant: bool,
+    max_decoded_bytes: usize,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self {
+            output_layout: OutputLayout::default(),
+            color_conversion: ColorConversion::default(),
+            tolerant: false,
+            max_decoded_bytes: DEFAULT_MAX_DECODED_BYTES,
+        }
+    }
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes one complete stream of any supported format with the default options.
+    pub fn auto(data: &[u8]) -> Result<DecodedImage, JpeglsError> {
+        Self::new().decode(data)
+    }
+
+    /// Arrangement of the components in the decoded image. Defaults to
+    /// [`OutputLayout::Interleaved`].
+    pub fn set_output_layout(&mut self, output_layout: OutputLayout) {
+        self.output_layout = output_layout;
+    }
+
+    /// Conversion of the decoded components. Defaults to [`ColorConversion::None`]. JPEG 1
+    /// images convert to gray while decoding, the other formats after decoding.
+    pub fn set_color_conversion(&mut self, color_conversion: ColorConversion) {
+        self.color_conversion = color_conversion;
+    }
+
+    /// Decodes truncated and damaged streams as far as the data goes instead of failing
+    /// with [`JpeglsError::InvalidData`], e.g. to salvage archived files that were cut
+    /// short. The part of the image that could not be decoded is mid-grey and
+    /// [`ImageMetadata::truncation`] reports where decoding stopped. JPEG 1 and JPEG-LS
+    /// images keep the rows above that point; JPEG 2000 images keep the packets that are
+    /// complete, so the whole image loses detail instead. Errors in the headers still fail
+    /// the decode. Defaults to `false`.
+    pub fn set_tolerant(&mut self, tolerant: bool) {
+        self.tolerant = tolerant;
+    }
+
+    /// Largest decoded image, in bytes, that is allocated; a header declaring a larger
+    /// image fails with [`JpeglsError::ParameterOutOfRange`] before any pixel is decoded,
+    /// so a few corrupt bytes cannot exhaust memory. Defaults to 1 GiB.
+    pub fn set_max_decoded_bytes(&mut self, max_decoded_bytes: usize) {
+        self.max_decoded_bytes = max_decoded_bytes;
+    }
+
+    /// Returns the size of the decoded image, failing sizes above the limit of
+    /// [`set_max_decoded_bytes`](Self::set_max_decoded_bytes).
+    fn checked_decoded_size(&self, frame_info: &FrameInfo) -> Result<usize, JpeglsError> {
+        let size = frame_info.decoded_size(self.output_layout)?;
+        if size > self.max_decoded_bytes {
+            return Err(JpeglsError::ParameterOutOfRange);
+        }
+        Ok(size)
+    }
+
+    /// Returns the codec of a stream from its first bytes, see [`detect_format`].
+    pub fn detect_codec(data: &[u8]) -> Option<Format> {
+        detect_format(data)
+    }
+
+    /// Decodes one complete stream.
+    pub fn decode(&self, data: &[u8]) -> Result<DecodedImage, JpeglsError> {
+        self.decode_scaled(data, None)
+    }
+
+    /// Decodes a preview whose larger side is `max_dim` pixels, e.g. for gallery views,
+    /// taking the cheapest path of each format: JPEG 1 DCT-based frames are decoded at the
+    /// smallest n/8 of their size that covers the preview with a reduced inverse DCT (see
+    /// [`Jpeg1Decoder::set_output_scaling`]) and JPEG 2000 images are
+    /// reconstructed from a lower resolution level of the wavelet transform; JPEG-LS and
+    /// JPEG 1 lossless images are decoded in full. The result is then averaged down (box
+    /// filter) to the exact size, keeping the aspect ratio. Images that already fit are
+    /// decoded at full size.
+    ///
+    /// Returns [`JpeglsError::InvalidArgument`] if `max_dim` is 0.
+    pub fn decode_thumbnail(&self, data: &[u8], max_dim: u32) -> Result<DecodedImage, JpeglsError> {
+        if max_dim == 0 {
+            return Err(JpeglsError::InvalidArgument);
+        }
+        // The box filter works on interleaved pixels.
+        let decoder = Decoder {
+            output_layout: OutputLayout::Interleaved,
+            ..*self
+        };
+        Ok(decoder
+            .decode_scaled(data, Some(max_dim))?
+            .to_layout(self.output_layout))
+    }
+
+    /// Decodes one stream `rows` rows at a time, handing each batch of interleaved rows to
+    /// `handler` with the frame info of the image and the index of its first row, e.g. to
+    /// send a large image on while the rest is still being decoded. JPEG 1 DCT-based frames
+    /// and JPEG-LS images are decoded batch by batch, see [`Jpeg1Decoder::decode_rows`]
+    /// and [`JpeglsDecoder::decode_row_batches`] for what they hold in memory; lossless
+    /// JPEG 1 and JPEG 2000 images are decoded whole, then handed out in batches. The
+    /// output layout is not used. An error returned by `handler` stops decoding and is
+    /// passed on.
+    pub fn decode_row_batches(
+        &self,
+        data: &[u8],
+        rows: u32,
+        mut handler: impl FnMut(&FrameInfo, u32, &[u8]) -> Result<(), JpeglsError>,
+    ) -> Result<(), JpeglsError> {
+        let rows = rows.max(1);
+        let interleaved = Decoder {
+            output_layout: OutputLayout::Interleaved,
+            ..*self
+        };
+        match detect_format(data) {
+            Some(Format::Jpeg1) if !read_metadata(data)?.is_lossless() => {
+                let mut decoder = Jpeg1Decoder::new(data);
+                decoder.read_header()?;
+                decoder.set_color_conversion(self.color_conversion);
+                decoder.set_tolerant(self.tolerant);
+                let (width, height) = decoder.output_size();
+                let frame_info = FrameInfo {
+                    width,
+                    height,
+                    bits_per_sample: 8,
+                    component_count: decoder.output_component_count() as i32,
+                };
+                let size = interleaved.checked_decoded_size(&frame_info)?;
+                let row_bytes = size / height.max(1) as usize;
+                let mut batch = vec![0u8; rows.min(height) as usize * row_bytes];
+                for first_row in (0..height).step_by(rows as usize) {
+                    let batch_rows = rows.min(height - first_row);
+                    let pixels = &mut batch[..batch_rows as usize * row_bytes];
+                    decoder.decode_rows(pixels, batch_rows)?;
+                    handler(&frame_info, first_row, pixels)?;
+                }
+                Ok(())
+            }
+            Some(Format::Jpegls) => {
+                let mut decoder = JpeglsDecoder::new(data);
+                decoder.read_header()?;
+                decoder.set_tolerant(self.tolerant);
+                let frame_info = decoder.frame_info();
+                let size = interleaved.checked_decoded_size(&frame_info)?;
+                let row_bytes = size / frame_info.height.max(1) as usize;
+                decoder.decode_row_batches(rows, |first_row, pixels| {
+                    if self.color_conversion == ColorConversion::None {
+                        return handler(&frame_info, first_row, pixels);
+                    }
+                    let batch = self.convert_colors(DecodedImage {
+                        frame_info: FrameInfo {
+                            height: (pixels.len() / row_bytes.max(1)) as u32,
+                            ..frame_info
+                        },
+                        pixels: pixels.to_vec(),
+                        layout: OutputLayout::Interleaved,
+                        format: Format::Jpegls,
+                        metadata: ImageMetadata::default(),
+                    });
+                    let frame_info = FrameInfo {
+                        height: frame_info.height,
+                        ..batch.frame_info
+                    };
+                    handler(&frame_info, first_row, &batch.pixels)
+                })
+            }
+            _ => {
+                let image = interleaved.decode(data)?;
+                let row_bytes = image.pixels.len() / image.frame_info.height.max(1) as usize;
+                let batches = image.pixels.chunks((rows as usize * row_bytes).max(1));
+                for (index, pixels) in batches.enumerate() {
+                    handler(&image.frame_info, index as u32 * rows, pixels)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Decodes one stream, shrunk to fit in `max_dim` x `max_dim` pixels if given; see
+    /// [`decode_thumbnail`](Self::decode_thumbnail).
+    fn decode_scaled(
+        &self,
+        data: &[u8],
+        max_dim: Option<u32>,
+    ) -> Result<DecodedImage, JpeglsError> {
+        let mut decoded = match detect_format(data) {
+            // JPEG 1 frames are converted to gray while decoding.
+            Some(Format::Jpeg1) => self.decode_jpeg1(data, max_dim)?,
+            Some(Format::Jpegls) => {
+                let decoded = self.decode_jpegls(data)?;
+                self.convert_colors(match max_dim {
+                    Some(max_dim) => decoded.downscale(max_dim)?,
+                    None => decoded,
+                })
+            }
+            Some(Format::Jpeg2000) => self.convert_colors(self.decode_jpeg2000(data, max_dim)?),
+            None => return Err(JpeglsError::StartOfImageMarkerNotFound),
+        };
+        if max_dim.is_none() && self.color_conversion == ColorConversion::None {
+            decoded.metadata.checksum = checksum::read_checksum(data);
+        }
+        Ok(decoded)
+    }
+
+    fn convert_colors(&self, decoded: DecodedImage) -> DecodedImage {
+        match self.color_conversion {
+            ColorConversion::None => decoded,
+            ColorConversion::ToGray => decoded.to_gray(),
+        }
+    }
+
+    /// Decodes one frame stored as consecutive fragments. The fragments are joined in
+    /// order, so they may be split at any byte.
+    pub fn decode_fragments(&self, fragments: &[&[u8]]) -> Result<DecodedImage, JpeglsError> {
+        self.decode(&join_fragments(fragments))
+    }
+
+    /// Decodes frame `index` of a multi-frame object whose frames start at the fragments
+    /// that begin a new stream (see [`frames`](Self::frames)).
+    pub fn decode_frame(
+        &self,
+        fragments: &[&[u8]],
+        index: usize,
+    ) -> Result<DecodedImage, JpeglsError> {
+        let frames = Self::frames(fragments);
+        let frame = frames.get(index).ok_or(JpeglsError::InvalidArgument)?;
+        self.decode_fragments(frame)
+    }
+
+    /// Number of frames in `fragments`, see [`frames`](Self::frames).
+    pub fn frame_count(fragments: &[&[u8]]) -> usize {
+        Self::frames(fragments).len()
+    }
+
+    /// Groups fragments into frames. A new frame starts at every fragment that begins with
+    /// a start of image marker, a start of codestream marker or a JP2 signature; fragments
+    /// before the first such fragment are ignored.
+    pub fn frames<'f, 'a>(fragments: &'f [&'a [u8]]) -> Vec<&'f [&'a [u8]]> {
+        let starts: Vec<usize> = fragments
+            .iter()
+            .enumerate()
+            .filter(|(_, fragment)| detect_format(fragment).is_some())
+            .map(|(i, _)| i)
+            .collect();
+        starts
+            .iter()
+            .enumerate()
+            .map(|(n, &start)| {
+                let end = starts.get(n + 1).copied().unwrap_or(fragments.len());
+                &fragments[start..end]
+            })
+            .collect()
+    }
+
+    /// Groups fragments into frames using the DICOM Basic Offset Table: `offsets[n]` is the
+    /// byte offset of the first fragment of frame `n`, counted from the start of the first
+    /// fragment's item header.
+    ///
+    /// Returns [`JpeglsError::InvalidData`] if an offset does not point at a fragment or the
+    /// offsets are not increasing.
+    pub fn frames_from_offset_table<'f, 'a>(
+        fragments: &'f [&'a [u8]],
+        offsets: &[u32],
+    ) -> Result<Vec<&'f [&'a [u8]]>, JpeglsError> {
+        let mut fragment_offsets = Vec::with_capacity(fragments.len());
+        let mut position = 0usize;
+        for fragment in fragments {
+            fragment_offsets.push(position);
+            position += FRAGMENT_ITEM_HEADER_SIZE + fragment.len();
+        }
+
+        let starts = offsets
+            .iter()
+            .map(|&offset| {
+                fragment_offsets
+                    .binary_search(&(offset as usize))
+                    .map_err(|_| JpeglsError::InvalidData)
+            })
+            .collect::<Result<Vec<usize>, _>>()?;
+        if starts.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(JpeglsError::InvalidData);
+        }
+
+        Ok(starts
+            .iter()
+            .enumerate()
+            .map(|(n, &start)| {
+                let end = starts.get(n + 1).copied().unwrap_or(fragments.len());
+                &fragments[start..end]
+            })
+            .collect())
+    }
+
+    fn decode_jpeg1(&self, data: &[u8], max_dim: Option<u32>) -> Result<DecodedImage, JpeglsError> {
+        let header = read_metadata(data)?;
+        let mut decoder = Jpeg1Decoder::new(data);
+        decoder.read_header()?;
+        decoder.set_color_conversion(self.color_conversion);
+        decoder.set_tolerant(self.tolerant);
+        if let Some(max_dim) = max_dim {
+            // The smallest scale that still covers the thumbnail.
+            let (full_width, full_height) = decoder.output_size();
+            let (width, height) = thumbnail_size(full_width, full_height, max_dim);
+            for numerator in 1..=8 {
+                decoder.set_output_scaling(numerator)?;
+                let (scaled_width, scaled_height) = decoder.output_size();
+                if scaled_width >= width && scaled_height >= height {
+                    break;
+                }
+            }
+        }
+        let (width, height) = decoder.output_size();
+        // The JPEG 1 decoder writes 8-bit samples.
+        let frame_info = FrameInfo {
+            width,
+            height,
+            bits_per_sample: 8,
+            component_count: decoder.output_component_count() as i32,
+        };
+        let mut pixels = vec![0u8; self.checked_decoded_size(&frame_info)?];
+        decoder.set_output_layout(self.output_layout);
+        decoder.decode(&mut pixels)?;
+        let metadata = ImageMetadata {
+            progressive: header.is_progressive(),
+            lossless: header.is_lossless(),
+            color_space: (!header.is_lossless()).then(|| decoder.color_space()),
+            truncation: decoder.truncation(),
+            components: decoder.component_info(),
+            // Scaled with the DCT when decoding a thumbnail.
+            resolution: header
+                .resolution
+                .zip(header.frame.as_ref())
+                .map(|(r, frame)| {
+                    r.scaled((frame.width as u32, frame.height as u32), (width, height))
+                }),
+            ..ImageMetadata::default()
+        };
+        let decoded = DecodedImage {
+            frame_info,
+            pixels,
+            layout: self.output_layout,
+            format: Format::Jpeg1,
+            metadata,
+        };
+        match max_dim {
+            Some(max_dim) => decoded.downscale(max_dim),
+            None => Ok(decoded),
+        }
+    }
+
+    fn decode_jpegls(&self, data: &[u8]) -> Result<DecodedImage, JpeglsError> {
+        let mut decoder = JpeglsDecoder::new(data);
+        decoder.read_header()?;
+        decoder.set_output_layout(self.output_layout);
+        decoder.set_tolerant(self.tolerant);
+        let frame_info = decoder.frame_info();
+
+        let mut pixels = vec![0u8; self.checked_decoded_size(&frame_info)?];
+        decoder.decode(&mut pixels)?;
+        let mapping_table_ids = (0..frame_info.component_count as usize)
+            .map(|c| decoder.mapping_table_id(c).unwrap_or(0))
+            .collect();
+        let metadata = ImageMetadata {
+            mapping_tables: decoder.mapping_tables().cloned().collect(),
+            mapping_table_ids,
+            truncation: decoder.truncation(),
+            components: decoder.component_info(),
+            resolution: decoder
+                .spiff_header()
+                .and_then(|spiff| Resolution::from_spiff(&spiff)),
+            ..ImageMetadata::default()
+        };
+        Ok(DecodedImage {
+            frame_info,
+            pixels,
+            layout: self.output_layout,
+            format: Format::Jpegls,
+            metadata,
+        })
+    }
+
+    fn decode_jpeg2000(
+        &self,
+        data: &[u8],
+        max_dim: Option<u32>,
+    ) -> Result<DecodedImage, JpeglsError> {
+        // The tiles are decoded into buffers of the whole image; the main header gives its
+        // size before any of them is allocated.
+        let mut header_reader = JpegStreamReader::new(data);
+        let mut parser = J2kParser::new(&mut header_reader);
+        let header = parser.parse_info()?;
+        let (width, height) = header.size();
+        self.checked_decoded_size(&FrameInfo {
+            width,
+            height,
+            bits_per_sample: header.channel_bits_per_sample() as i32,
+            component_count: header.channel_count() as i32,
+        })?;
+
+        let mut reader = JpegStreamReader::new(data);
+        let mut decoder = J2kDecoder::new(&mut reader);
+        let (image, truncation) = if self.tolerant {
+            // The packets are kept up to the first one that is cut short.
+            let partial = decoder.decode_partial(data.len())?;
+            let truncation = (!partial.is_complete()).then_some(Truncation {
+                offset: partial.offset,
+                complete_rows: partial.complete_rows,
+                error: JpeglsError::NeedMoreData,
+            });
+            (partial.image, truncation)
+        } else {
+            (decoder.decode()?, None)
+        };
+        let (width, height) = image.size();
+        let mut frame_info = FrameInfo {
+            width,
+            height,
+            bits_per_sample: image.channel_bits_per_sample() as i32,
+            component_count: image.channel_count() as i32,
+        };
+
+        let thumbnail = max_dim.map(|max_dim| thumbnail_size(width, height, max_dim));
+        // Every resolution below the full one halves the image; take the smallest that
+        // still covers the thumbnail.
+        let resolution = match thumbnail {
+            Some((thumbnail_width, thumbnail_height)) => {
+                let levels = image.cod.as_ref().map_or(0, |cod| cod.decomposition_levels);
+                (0..levels)
+                    .find(|&resolution| {
+                        let scale = 1 << (levels - resolution);
+                        width.div_ceil(scale) >= thumbnail_width
+                            && height.div_ceil(scale) >= thumbnail_height
+                    })
+                    .unwrap_or(u8::MAX)
+            }
+            None => u8::MAX,
+        };
+        let preview = image
+            .reconstruct_resolution(resolution, J2kUpsampling::Nearest)
+            .map_err(|_| JpeglsError::InvalidData)?;
+        frame_info.width = preview.width;
+        frame_info.height = preview.height;
+        let mut samples = preview.samples;
+        if let Some((width, height)) = thumbnail {
+            // Signed samples are averaged before they are stored as bytes.
+            samples = downscale_samples(samples, &frame_info, width, height)?;
+            frame_info.width = width;
+            frame_info.height = height;
+        }
+        let pixels = samples.into_bytes();
+        let metadata = ImageMetadata {
+            high_throughput: image.cap.as_ref().is_some_and(|cap| cap.is_htj2k()),
+            reversible: image
+                .cod
+                .as_ref()
+                .is_some_and(|cod| cod.transformation == 1),
+            icc_profile: image.icc_profile.clone(),
+            channel_definitions: image.channel_definitions.clone(),
+            xml_boxes: image.xml_boxes.clone(),
+            uuid_boxes: image
+                .uuid_boxes
+                .iter()
+                .filter(|b| b.uuid != checksum::CHECKSUM_UUID)
+                .cloned()
+                .collect(),
+            truncation,
+            components: image.component_info(),
+            resolution: resolution::read_resolution(data)
+                .map(|r| r.scaled((width, height), (frame_info.width, frame_info.height))),
+            ..ImageMetadata::default()
+        };
+
+        // Reconstruction gives interleaved samples.
+        let decoded = DecodedImage {
+            frame_info,
+            pixels,
+            layout: OutputLayout::Interleaved,
+            format: Format::Jpeg2000,
+            metadata,
+        };
+        Ok(match self.output_layout {
+            OutputLayout::Interleaved => decoded,
+            OutputLayout::Planar => decoded.to_planar(),
+        })
+    }
+}
+
+/// Size of a thumbnail whose larger side is `max_dim`, keeping the aspect ratio; images
+/// that fit keep their size.
+fn thumbnail_size(width: u32, height: u32, max_dim: u32) -> (u32, u32) {
+    let larger = width.max(height);
+    if larger <= max_dim {
+        return (width, height);
+    }
+    let scale =
+        |v: u32| ((v as u64 * max_dim as u64 + larger as u64 / 2) / larger as u64).max(1) as u32;
+    (scale(width), scale(height))
+}
+
+/// Shrinks the interleaved JPEG 2000 samples of `frame_info` to `width` x `height`.
+fn downscale_samples(
+    samples: J2kSamples,
+    frame_info: &FrameInfo,
+    width: u32,
+    height: u32,
+) -> Result<J2kSamples, JpeglsError> {
+    if (width, height) == (frame_info.width, frame_info.height) {
+        return Ok(samples);
+    }
+    let components = frame_info.component_count as usize;
+    let (old_width, old_height) = (frame_info.width as usize, frame_info.height as usize);
+    let (width, height) = (width as usize, height as usize);
+    Ok(match samples {
+        J2kSamples::U8(s) => J2kSamples::U8(pixelops::downscale_box(
+            &s, old_width, old_height, components, width, height,
+        )?),
+        J2kSamples::I8(s) => J2kSamples::I8(pixelops::downscale_box(
+            &s, old_width, old_height, components, width, height,
+        )?),
+        J2kSamples::U16(s) => J2kSamples::U16(pixelops::downscale_box(
+            &s, old_width, old_height, components, width, height,
+        )?),
+        J2kSamples::I16(s) => J2kSamples::I16(pixelops::downscale_box(
+            &s, old_width, old_height, components, width, height,
+        )?),
+    })
+}
+
+/// Joins fragments into one stream, borrowing when there is only one.
+fn join_fragments<'a>(fragments: &[&'a [u8]]) -> Cow<'a, [u8]> {
+    match fragments {
+        [fragment] => Cow::Borrowed(fragment),
+        _ => Cow::Owned(fragments.concat()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Encoder;
+    use crate::jpeg1::Jpeg1EncoderBuilder;
+    use crate::jpegls::JpeglsEncoder;
+
+    fn encode_jpegls(pixels: &[u8], width: u32, height: u32) -> Vec<u8> {
+        let mut encoded = vec![0u8; pixels.len() * 2 + 1024];
+        let mut encoder = JpeglsEncoder::new(&mut encoded);
+        encoder
+            .set_frame_info(FrameInfo {
+                width,
+                height,
+                bits_per_sample: 8,
+                component_count: 1,
+            })
+            .unwrap();
+        let len = encoder.encode(pixels).unwrap();
+        encoded.truncate(len);
+        encoded
+    }
+
+    #[test]
+    fn test_decode_fragments_split_inside_marker() {
+        let pixels: Vec<u8> = (0..256).map(|i| (i * 7 % 256) as u8).collect();
+        let encoded = encode_jpegls(&pixels, 16, 16);
+
+        // Split after the first byte of the SOF55 marker and again in the scan data.
+        let marker = encoded.windows(2).position(|w| w == [0xFF, 0xF7]).unwrap();
+        let fragments = [
+            &encoded[..marker + 1],
+            &encoded[marker + 1..encoded.len() - 10],
+            &encoded[encoded.len() - 10..],
+        ];
+        let image = Decoder::new().decode_fragments(&fragments).unwrap();
+        assert_eq!(image.frame_info.width, 16);
+        assert_eq!(image.pixels, pixels);
+    }
+
+    #[test]
+    fn test_decode_frame_by_index() {
+        let first: Vec<u8> = (0..64).collect();
+        let second: Vec<u8> = (0..64).map(|i| 255 - i).collect();
+        let first_encoded = encode_jpegls(&first, 8, 8);
+        let second_encoded = encode_jpegls(&second, 8, 8);
+        let (a, b) = second_encoded.split_at(20);
+        let fragments = [&first_encoded[..], a, b];
+
+        let decoder = Decoder::new();
+        assert_eq!(Decoder::frame_count(&fragments), 2);
+        assert_eq!(decoder.decode_frame(&fragments, 0).unwrap().pixels, first);
+        assert_eq!(decoder.decode_frame(&fragments, 1).unwrap().pixels, second);
+        assert_eq!(
+            decoder.decode_frame(&fragments, 2),
+            Err(JpeglsError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn test_layout_conversions_and_crop() {
+        // 3x2 RGB image whose samples count up in interleaved order.
+        let image = DecodedImage {
+            frame_info: FrameInfo {
+                width: 3,
+                height: 2,
+                bits_per_sample: 8,
+                component_count: 3,
+            },
+            pixels: (0..18).collect(),
+            layout: OutputLayout::Interleaved,
+            format: Format::Jpegls,
+            metadata: ImageMetadata::default(),
+        };
+        let planar = image.to_planar();
+        assert_eq!(planar.layout, OutputLayout::Planar);
+        assert_eq!(
+            planar.pixels,
+            [0, 3, 6, 9, 12, 15, 1, 4, 7, 10, 13, 16, 2, 5, 8, 11, 14, 17]
+        );
+        assert_eq!(planar.to_interleaved(), image);
+
+        let cropped = image.crop(1, 1, 2, 1).unwrap();
+        assert_eq!(cropped.frame_info.width, 2);
+        assert_eq!(cropped.frame_info.height, 1);
+        assert_eq!(cropped.pixels, [12, 13, 14, 15, 16, 17]);
+        assert_eq!(planar.crop(1, 1, 2, 1).unwrap(), cropped.to_planar());
+        assert_eq!(
+            image.crop(2, 0, 2, 1),
+            Err(JpeglsError::InvalidArgumentWidth)
+        );
+        assert_eq!(
+            image.crop(0, 1, 1, 2),
+            Err(JpeglsError::InvalidArgumentHeight)
+        );
+
+        assert_eq!(image.as_u8(), Some(&image.pixels[..]));
+        assert_eq!(image.as_u16(), None);
+        assert_eq!(image.clone().into_vec(), image.pixels);
+
+        let gray = image.to_gray();
+        assert_eq!(gray.frame_info.component_count, 1);
+        assert_eq!(gray.pixels, [1, 4, 7, 10, 13, 16]);
+        assert_eq!(planar.to_gray().pixels, gray.pixels);
+        assert_eq!(gray.to_gray(), gray);
+    }
+
+    #[test]
+    fn test_decode_to_gray() {
+        let frame_info = FrameInfo {
+            width: 16,
+            height: 16,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let source: Vec<u8> = (0..16 * 16)
+            .flat_map(|i| [(i % 16 * 12) as u8, (i / 16 * 12) as u8, 128])
+            .collect();
+        let mut decoder = Decoder::new();
+        decoder.set_color_conversion(ColorConversion::ToGray);
+
+        // JPEG 1 takes the Y component, close to the luminance of the decoded RGB.
+        let jpeg = Jpeg1EncoderBuilder::new(frame_info)
+            .build()
+            .unwrap()
+            .encode_to_vec(&source, &frame_info)
+            .unwrap();
+        let gray = decoder.decode(&jpeg).unwrap();
+        assert_eq!(gray.frame_info.component_count, 1);
+        let expected = Decoder::auto(&jpeg).unwrap().to_gray();
+        assert_eq!(gray.pixels.len(), expected.pixels.len());
+        for (&actual, &expected) in gray.pixels.iter().zip(&expected.pixels) {
+            assert!((actual as i32 - expected as i32).abs() <= 2);
+        }
+
+        let mut jpegls = vec![0u8; source.len() * 2 + 1024];
+        let mut encoder = JpeglsEncoder::new(&mut jpegls);
+        encoder.set_frame_info(frame_info).unwrap();
+        let len = encoder.encode(&source).unwrap();
+        jpegls.truncate(len);
+        let gray = decoder.decode(&jpegls).unwrap();
+        assert_eq!(gray, Decoder::auto(&jpegls).unwrap().to_gray());
+    }
+
+    #[test]
+    fn test_decode_row_batches() {
+        let frame_info = FrameInfo {
+            width: 12,
+            height: 19,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let source: Vec<u8> = (0..12 * 19 * 3).map(|i| (i * 7 % 251) as u8).collect();
+        let jpeg = Jpeg1EncoderBuilder::new(frame_info)
+            .build()
+            .unwrap()
+            .encode_to_vec(&source, &frame_info)
+            .unwrap();
+        let jpegls = Encoder::for_format(Format::Jpegls)
+            .encode(&source, &frame_info)
+            .unwrap();
+        let j2k = Encoder::for_format(Format::Jpeg2000)
+            .encode(&source, &frame_info)
+            .unwrap();
+        for color_conversion in [ColorConversion::None, ColorConversion::ToGray] {
+            let mut decoder = Decoder::new();
+            decoder.set_color_conversion(color_conversion);
+            for encoded in [&jpeg, &jpegls, &j2k] {
+                let image = decoder.decode(encoded).unwrap();
+                let mut joined = Vec::new();
+                let mut batches = Vec::new();
+                decoder
+                    .decode_row_batches(encoded, 8, |frame_info, first_row, pixels| {
+                        assert_eq!(*frame_info, image.frame_info);
+                        batches.push(first_row);
+                        joined.extend_from_slice(pixels);
+                        Ok(())
+                    })
+                    .unwrap();
+                assert_eq!(batches, [0, 8, 16]);
+                assert_eq!(joined, image.pixels, "{:?}", image.format);
+            }
+        }
+
+        // A handler error stops decoding.
+        let mut calls = 0;
+        let result = Decoder::new().decode_row_batches(&jpeg, 8, |_, _, _| {
+            calls += 1;
+            Err(JpeglsError::InvalidOperation)
+        });
+        assert_eq!(result, Err(JpeglsError::InvalidOperation));
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_tolerant_decode_of_truncated_streams() {
+        let frame_info = FrameInfo {
+            width: 32,
+            height: 32,
+            bits_per_sample: 8,
+            component_count: 1,
+        };
+        let source: Vec<u8> = (0..32 * 32)
+            .map(|i| (i % 32 * 5 + i / 32 * 3) as u8)
+            .collect();
+        let mut tolerant = Decoder::new();
+        tolerant.set_tolerant(true);
+
+        let jpegls = encode_jpegls(&source, 32, 32);
+        let jpeg = Jpeg1EncoderBuilder::new(frame_info)
+            .build()
+            .unwrap()
+            .encode_to_vec(&source, &frame_info)
+            .unwrap();
+        for encoded in [&jpegls, &jpeg] {
+            let complete = Decoder::auto(encoded).unwrap();
+            let image = tolerant.decode(encoded).unwrap();
+            assert_eq!(image, complete);
+            assert_eq!(image.metadata.truncation, None);
+
+            // Cut in the middle of the scan data.
+            let scan = encoded.windows(2).position(|w| w == [0xFF, 0xDA]).unwrap();
+            let cut = &encoded[..(scan + encoded.len()) / 2];
+            assert!(Decoder::auto(cut).is_err());
+            let image = tolerant.decode(cut).unwrap();
+            let truncation = image.metadata.truncation.unwrap();
+            assert!(truncation.offset <= cut.len());
+            let rows = truncation.complete_rows as usize;
+            assert!(rows > 0 && rows < 32, "{rows} complete rows");
+            assert_eq!(image.pixels[..rows * 32], complete.pixels[..rows * 32]);
+            // The rows the data did not reach are mid-grey.
+            assert!(image.pixels[31 * 32..].iter().all(|&sample| sample == 128));
+        }
+
+        // A JPEG 2000 image keeps the packets that arrived, at a lower resolution.
+        let j2k = Encoder::for_format(Format::Jpeg2000)
+            .encode(&source, &frame_info)
+            .unwrap();
+        assert_eq!(tolerant.decode(&j2k).unwrap().metadata.truncation, None);
+        let data = j2k.windows(2).position(|w| w == [0xFF, 0x93]).unwrap();
+        let cut = &j2k[..(data + j2k.len()) / 2];
+        let image = tolerant.decode(cut).unwrap();
+        assert_eq!(image.frame_info, frame_info);
+        let truncation = image.metadata.truncation.unwrap();
+        assert!(truncation.offset > data && truncation.offset <= cut.len());
+        assert_eq!(truncation.complete_rows, 0);
+        // Nothing past the offset was used.
+        let shorter = tolerant.decode(&cut[..truncation.offset]).unwrap();
+        assert_eq!(shorter.pixels, image.pixels);
+        assert_eq!(shorter.metadata.truncation, Some(truncation));
+
+        // The rows of the tiles read in full are complete.
+        let mut encoder = crate::jpeg2000::encoder::J2kEncoder::new();
+        encoder.set_tile_size(16, 16);
+        let mut tiled = vec![0u8; 8192];
+        let len = encoder.encode(&source, &frame_info, &mut tiled).unwrap();
+        let complete = Decoder::auto(&tiled[..len]).unwrap();
+        let third_tile = tiled
+            .windows(2)
+            .enumerate()
+            .filter(|(_, w)| *w == [0xFF, 0x90])
+            .nth(2)
+            .unwrap()
+            .0;
+        let image = tolerant.decode(&tiled[..third_tile + 20]).unwrap();
+        let truncation = image.metadata.truncation.unwrap();
+        // Past the SOT marker segment and SOD marker of the tile-part.
+        assert!(truncation.offset >= third_tile + 14 && truncation.offset <= third_tile + 20);
+        assert_eq!(truncation.complete_rows, 16);
+        assert_eq!(image.pixels[..16 * 32], complete.pixels[..16 * 32]);
+
+        // Header errors are not recovered from.
+        assert!(tolerant.decode(&jpegls[..10]).is_err());
+    }
+
+    #[test]
+    fn test_component_info() {
+        let frame_info = FrameInfo {
+            width: 8,
+            height: 8,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let pixels = vec![100u8; 8 * 8 * 3];
+        let full = ComponentInfo {
+            bits_per_sample: 8,
+            signed: false,
+            horizontal_subsampling: 1,
+            vertical_subsampling: 1,
+        };
+        for format in [Format::Jpeg1, Format::Jpegls, Format::Jpeg2000] {
+            let encoded = Encoder::for_format(format)
+                .encode(&pixels, &frame_info)
+                .unwrap();
+            let image = Decoder::auto(&encoded).unwrap();
+            assert_eq!(image.metadata.components, [full; 3], "{format:?}");
+        }
+
+        // JPEG 2000 components can differ: make the second one 12-bit signed in SIZ.
+        let mut j2k = Encoder::for_format(Format::Jpeg2000)
+            .encode(&pixels, &frame_info)
+            .unwrap();
+        let siz = j2k.windows(2).position(|w| w == [0xFF, 0x51]).unwrap();
+        j2k[siz + 40 + 3] = 0x80 | 11;
+        let image = Decoder::auto(&j2k).unwrap();
+        assert_eq!(image.frame_info.bits_per_sample, 12);
+        let signed = ComponentInfo {
+            bits_per_sample: 12,
+            signed: true,
+            ..full
+        };
+        assert_eq!(image.metadata.components, [full, signed, full]);
+    }
+
+    #[test]
+    fn test_max_decoded_bytes() {
+        let pixels: Vec<u8> = (0..256).map(|i| i as u8).collect();
+        let encoded = encode_jpegls(&pixels, 16, 16);
+
+        let mut decoder = Decoder::new();
+        decoder.set_max_decoded_bytes(255);
+        assert_eq!(
+            decoder.decode(&encoded),
+            Err(JpeglsError::ParameterOutOfRange)
+        );
+        decoder.set_max_decoded_bytes(256);
+        assert_eq!(decoder.decode(&encoded).unwrap().pixels, pixels);
+
+        // JPEG 2000 images are checked against the size in the main header.
+        let frame_info = FrameInfo {
+            width: 16,
+            height: 16,
+            bits_per_sample: 8,
+            component_count: 1,
+        };
+        let j2k = Encoder::for_format(Format::Jpeg2000)
+            .encode(&pixels, &frame_info)
+            .unwrap();
+        decoder.set_max_decoded_bytes(255);
+        assert_eq!(decoder.decode(&j2k), Err(JpeglsError::ParameterOutOfRange));
+        decoder.set_max_decoded_bytes(256);
+        assert_eq!(decoder.decode(&j2k).unwrap().pixels.len(), 256);
+    }
+
+    #[test]
+    fn test_sixteen_bit_samples() {
+        let pixels: Vec<u8> = (0..64u16).flat_map(|i| (i * 1000).to_le_bytes()).collect();
+        let encoded = {
+            let mut encoded = vec![0u8; 1024];
+            let mut encoder = JpeglsEncoder::new(&mut encoded);
+            encoder
+                .set_frame_info(FrameInfo {
+                    width: 8,
+                    height: 8,
+                    bits_per_sample: 16,
+                    component_count: 1,
+                })
+                .unwrap();
+            let len = encoder.encode(&pixels).unwrap();
+            encoded.truncate(len);
+            encoded
+        };
+        let image = Decoder::auto(&encoded).unwrap();
+        assert_eq!(image.as_u8(), None);
+        let samples = image.as_u16().unwrap();
+        assert_eq!(samples, (0..64u16).map(|i| i * 1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_decode_thumbnail() {
+        let frame_info = |width, height, component_count| FrameInfo {
+            width,
+            height,
+            bits_per_sample: 8,
+            component_count,
+        };
+        let rgb: Vec<u8> = (0..64 * 32)
+            .flat_map(|i| [(i % 64 * 4) as u8, (i / 64 * 8) as u8, 100])
+            .collect();
+        let gray: Vec<u8> = (0..64 * 64).map(|i| (i % 64 * 2 + i / 64) as u8).collect();
+        let close = |actual: &[u8], expected: &[u8], tolerance: u8| {
+            assert_eq!(actual.len(), expected.len());
+            for (&a, &e) in actual.iter().zip(expected) {
+                assert!(a.abs_diff(e) <= tolerance, "{a} vs {e}");
+            }
+        };
+        let decoder = Decoder::new();
+
+        // JPEG 1 at 1/8 (DC only) and at 3/8 followed by the box filter.
+        let jpeg = Encoder::for_format(Format::Jpeg1)
+            .encode(&rgb, &frame_info(64, 32, 3))
+            .unwrap();
+        let full = Decoder::auto(&jpeg).unwrap();
+        for (max_dim, width, height) in [(8, 8, 4), (20, 20, 10)] {
+            let thumbnail = decoder.decode_thumbnail(&jpeg, max_dim).unwrap();
+            assert_eq!(thumbnail.frame_info, frame_info(width, height, 3));
+            let expected =
+                pixelops::downscale_box(&full.pixels, 64, 32, 3, width as usize, height as usize)
+                    .unwrap();
+            // The boxes of the scaled decode are not aligned with the pixels.
+            close(&thumbnail.pixels, &expected, 12);
+        }
+
+        // JPEG-LS is decoded in full and averaged down.
+        let jpegls = encode_jpegls(&gray, 64, 64);
+        let thumbnail = decoder.decode_thumbnail(&jpegls, 10).unwrap();
+        assert_eq!(thumbnail.frame_info, frame_info(10, 10, 1));
+        assert_eq!(
+            thumbnail.pixels,
+            pixelops::downscale_box(&gray, 64, 64, 1, 10, 10).unwrap()
+        );
+        let fits = decoder.decode_thumbnail(&jpegls, 64).unwrap();
+        assert_eq!(fits, Decoder::auto(&jpegls).unwrap());
+        assert_eq!(
+            decoder.decode_thumbnail(&jpegls, 0),
+            Err(JpeglsError::InvalidArgument)
+        );
+
+        // JPEG 2000 is reconstructed at a lower resolution level.
+        let j2k = Encoder::for_format(Format::Jpeg2000)
+            .encode(&gray, &frame_info(64, 64, 1))
+            .unwrap();
+        let thumbnail = decoder.decode_thumbnail(&j2k, 16).unwrap();
+        assert_eq!(thumbnail.frame_info, frame_info(16, 16, 1));
+        let full = Decoder::auto(&j2k).unwrap();
+        let expected = pixelops::downscale_box(&full.pixels, 64, 64, 1, 16, 16).unwrap();
+        close(&thumbnail.pixels, &expected, 4);
+    }
+
+    #[test]
+    fn test_frames_from_offset_table() {
+        let fragments: [&[u8]; 3] = [&[0xFF, 0xD8, 1, 2], &[3, 4], &[0xFF, 0xD8]];
+        let frames = Decoder::frames_from_offset_table(&fragments, &[0, 22]).unwrap();
+        assert_eq!(frames, vec![&fragments[..2], &fragments[2..]]);
+
+        assert_eq!(
+            Decoder::frames_from_offset_table(&fragments, &[0, 20]),
+            Err(JpeglsError::InvalidData)
+        );
+        assert_eq!(
+            Decoder::frames_from_offset_table(&fragments, &[22, 0]),
+            Err(JpeglsError::InvalidData)
+        );
+    }
+}
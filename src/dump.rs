@@ -0,0 +1,968 @@
+//! Marker-level inspection of JPEG, JPEG-LS and JPEG 2000 streams.
+//!
+//! [`dump`] lists every marker segment of a stream with its offset, length and parsed
+//! fields, similar to `jpegdump` or `jpylyzer`. It is meant for debugging interoperability
+//! problems, so it does not validate the stream beyond what is needed to walk it, and a
+//! truncated or corrupt stream still yields the segments read before the problem:
+//!
+//! ```rust
+//! let data = [0xFF, 0xD8, 0xFF, 0xFE, 0x00, 0x04, b'h', b'i', 0xFF, 0xD9];
+//! let dump = jpegexp_rs::dump::dump(&data);
+//! let names: Vec<&str> = dump.segments.iter().map(|s| s.name.as_str()).collect();
+//! assert_eq!(names, ["SOI", "COM", "EOI"]);
+//! assert_eq!(dump.segments[1].fields, [("text".to_string(), "hi".to_string())]);
+//! assert!(dump.error.is_none());
+//! ```
+
+use std::fmt::Display;
+
+/// JP2 signature box, the first 12 bytes of every JP2 file.
+const JP2_SIGNATURE: &[u8] = b"\x00\x00\x00\x0CjP  \r\n\x87\n";
+
+/// The segments of a stream, in file order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamDump {
+    /// "JPEG", "JPEG-LS", "JPEG 2000 codestream" or "JP2".
+    pub format: &'static str,
+    pub segments: Vec<Segment>,
+    /// Offset and description of the problem that stopped parsing, if any.
+    pub error: Option<(usize, String)>,
+}
+
+/// A marker segment, entropy-coded data or JP2 box.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    /// Offset of the marker (or box header) from the start of the file.
+    pub offset: usize,
+    /// Size in bytes, including the marker and length field (or box header).
+    pub length: usize,
+    /// Marker abbreviation ("SOF55", "SIZ", ...) or box type ("jp2c", ...).
+    pub name: String,
+    pub description: &'static str,
+    /// Parsed fields as name/value pairs.
+    pub fields: Vec<(String, String)>,
+    /// Nesting level: codestream markers inside a JP2 `jp2c` box or boxes inside a
+    /// superbox have a depth above 0.
+    pub depth: usize,
+}
+
+/// Lists the marker segments (or JP2 boxes) of `data`. The format is detected from the
+/// first bytes.
+pub fn dump(data: &[u8]) -> StreamDump {
+    let mut dump = StreamDump {
+        format: "Unknown",
+        segments: Vec::new(),
+        error: None,
+    };
+    if data.starts_with(JP2_SIGNATURE) {
+        dump.format = "JP2";
+        dump_boxes(data, 0, 0, &mut dump);
+    } else if data.starts_with(&[0xFF, 0x4F]) {
+        dump.format = "JPEG 2000 codestream";
+        dump_codestream(data, 0, 0, &mut dump);
+    } else if data.starts_with(&[0xFF, 0xD8]) {
+        dump.format = if crate::jpeg_stream_reader::is_jpegls(data) {
+            "JPEG-LS"
+        } else {
+            "JPEG"
+        };
+        dump_jpeg(data, &mut dump);
+    } else {
+        dump.error = Some((
+            0,
+            "No start of image, codestream or JP2 signature".to_string(),
+        ));
+    }
+    dump
+}
+
+/// Big-endian reader over a segment payload.
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn bytes(&mut self, count: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.position..self.position + count)?;
+        self.position += count;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.bytes(8)
+            .map(|b| u64::from_be_bytes(b.try_into().expect("8 bytes")))
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.position)
+    }
+}
+
+/// Collects the fields of one segment.
+#[derive(Default)]
+struct Fields(Vec<(String, String)>);
+
+impl Fields {
+    fn add(&mut self, name: impl Into<String>, value: impl Display) {
+        self.0.push((name.into(), value.to_string()));
+    }
+}
+
+fn push_segment(
+    dump: &mut StreamDump,
+    offset: usize,
+    length: usize,
+    name: impl Into<String>,
+    description: &'static str,
+    fields: Fields,
+    depth: usize,
+) {
+    dump.segments.push(Segment {
+        offset,
+        length,
+        name: name.into(),
+        description,
+        fields: fields.0,
+        depth,
+    });
+}
+
+/// Parses a payload with `parse`, noting a truncated payload as a field.
+fn parse_fields(
+    payload: &[u8],
+    parse: impl FnOnce(&mut Reader, &mut Fields) -> Option<()>,
+) -> Fields {
+    let mut fields = Fields::default();
+    if parse(&mut Reader::new(payload), &mut fields).is_none() {
+        fields.add("error", "segment too short");
+    }
+    fields
+}
+
+fn text_preview(bytes: &[u8]) -> String {
+    const MAX_CHARS: usize = 64;
+    let text = String::from_utf8_lossy(bytes);
+    let text = text.trim_end_matches('\0');
+    if text.chars().count() > MAX_CHARS {
+        format!("{}...", text.chars().take(MAX_CHARS).collect::<String>())
+    } else {
+        text.to_string()
+    }
+}
+
+// JPEG and JPEG-LS
+
+fn dump_jpeg(data: &[u8], dump: &mut StreamDump) {
+    let mut position = 0;
+    let mut is_jpegls = false;
+    while position < data.len() {
+        if data[position] != 0xFF || position + 1 >= data.len() {
+            dump.error = Some((position, "Expected a marker".to_string()));
+            return;
+        }
+        // Any number of 0xFF fill bytes may precede a marker.
+        if data[position + 1] == 0xFF {
+            position += 1;
+            continue;
+        }
+
+        let marker = data[position + 1];
+        let (name, description) = jpeg_marker_name(marker);
+        if matches!(marker, 0x01 | 0xD0..=0xD9) {
+            push_segment(dump, position, 2, name, description, Fields::default(), 0);
+            position += 2;
+            if marker == 0xD9 {
+                if position < data.len() {
+                    dump.error = Some((
+                        position,
+                        format!("{} bytes after EOI", data.len() - position),
+                    ));
+                }
+                return;
+            }
+            continue;
+        }
+
+        let Some(length) = data
+            .get(position + 2..position + 4)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+        else {
+            dump.error = Some((position, format!("{} segment truncated", name)));
+            return;
+        };
+        let end = position + 2 + length;
+        if length < 2 || end > data.len() {
+            dump.error = Some((
+                position,
+                format!("{} segment length {} exceeds the data", name, length),
+            ));
+            return;
+        }
+        let payload = &data[position + 4..end];
+        let fields = match marker {
+            0xC0..=0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF | 0xF7 => {
+                is_jpegls |= marker == 0xF7;
+                parse_fields(payload, parse_start_of_frame)
+            }
+            0xC4 => parse_fields(payload, parse_huffman_tables),
+            0xDA => parse_fields(payload, |r, f| parse_start_of_scan(r, f, is_jpegls)),
+            0xDB => parse_fields(payload, parse_quantization_tables),
+            0xDC => parse_fields(payload, |r, f| {
+                f.add("lines", r.u16()?);
+                Some(())
+            }),
+            0xDD => parse_fields(payload, |r, f| {
+                f.add("restart interval", r.u16()?);
+                Some(())
+            }),
+            0xE0..=0xEF => parse_fields(payload, parse_application_data),
+            0xF8 => parse_fields(payload, parse_jpegls_preset_parameters),
+            0xFE => {
+                let mut fields = Fields::default();
+                fields.add("text", text_preview(payload));
+                fields
+            }
+            _ => Fields::default(),
+        };
+        push_segment(dump, position, 2 + length, name, description, fields, 0);
+        position = end;
+
+        if marker == 0xDA {
+            position = dump_entropy_coded_data(data, position, dump);
+        }
+    }
+    dump.error = Some((data.len(), "Missing EOI marker".to_string()));
+}
+
+/// Records the entropy-coded data after a scan header and returns the offset of the next
+/// marker. Restart markers inside the data are counted rather than listed.
+fn dump_entropy_coded_data(data: &[u8], start: usize, dump: &mut StreamDump) -> usize {
+    let mut position = start;
+    let mut restart_markers = 0;
+    while position + 1 < data.len() {
+        // JPEG stuffs 0xFF 0x00 and JPEG-LS stuffs a zero bit after 0xFF, so a following
+        // byte with the high bit set is a marker.
+        if data[position] == 0xFF && data[position + 1] >= 0x80 {
+            if (0xD0..=0xD7).contains(&data[position + 1]) {
+                restart_markers += 1;
+            } else {
+                break;
+            }
+        }
+        position += 1;
+    }
+    if position + 1 >= data.len() {
+        position = data.len();
+    }
+
+    let mut fields = Fields::default();
+    if restart_markers > 0 {
+        fields.add("restart markers", restart_markers);
+    }
+    push_segment(
+        dump,
+        start,
+        position - start,
+        "ECS",
+        "Entropy-coded data",
+        fields,
+        0,
+    );
+    position
+}
+
+pub(crate) fn jpeg_marker_name(marker: u8) -> (String, &'static str) {
+    let description = match marker {
+        0x01 => "Temporary private use",
+        0xC0 => "Start of frame (baseline DCT)",
+        0xC1 => "Start of frame (extended sequential DCT)",
+        0xC2 => "Start of frame (progressive DCT)",
+        0xC3 => "Start of frame (lossless)",
+        0xC4 => "Define Huffman tables",
+        0xC5..=0xC7 => "Start of frame (differential, Huffman)",
+        0xC8 => "Reserved for JPEG extensions",
+        0xC9..=0xCB => "Start of frame (arithmetic coding)",
+        0xCC => "Define arithmetic coding conditioning",
+        0xCD..=0xCF => "Start of frame (differential, arithmetic coding)",
+        0xD0..=0xD7 => "Restart",
+        0xD8 => "Start of image",
+        0xD9 => "End of image",
+        0xDA => "Start of scan",
+        0xDB => "Define quantization tables",
+        0xDC => "Define number of lines",
+        0xDD => "Define restart interval",
+        0xDE => "Define hierarchical progression",
+        0xDF => "Expand reference components",
+        0xE0..=0xEF => "Application data",
+        0xF7 => "Start of frame (JPEG-LS)",
+        0xF8 => "JPEG-LS preset parameters",
+        0xF0..=0xFD => "Reserved for JPEG extensions",
+        0xFE => "Comment",
+        _ => "Unknown marker",
+    };
+    let name = match marker {
+        0x01 => "TEM".to_string(),
+        0xC4 => "DHT".to_string(),
+        0xC8 => "JPG".to_string(),
+        0xCC => "DAC".to_string(),
+        0xC0..=0xCF => format!("SOF{}", marker - 0xC0),
+        0xD0..=0xD7 => format!("RST{}", marker - 0xD0),
+        0xD8 => "SOI".to_string(),
+        0xD9 => "EOI".to_string(),
+        0xDA => "SOS".to_string(),
+        0xDB => "DQT".to_string(),
+        0xDC => "DNL".to_string(),
+        0xDD => "DRI".to_string(),
+        0xDE => "DHP".to_string(),
+        0xDF => "EXP".to_string(),
+        0xE0..=0xEF => format!("APP{}", marker - 0xE0),
+        0xF7 => "SOF55".to_string(),
+        0xF8 => "LSE".to_string(),
+        0xF0..=0xFD => format!("JPG{}", marker - 0xF0),
+        0xFE => "COM".to_string(),
+        _ => format!("0xFF{:02X}", marker),
+    };
+    (name, description)
+}
+
+fn parse_start_of_frame(reader: &mut Reader, fields: &mut Fields) -> Option<()> {
+    fields.add("precision", reader.u8()?);
+    fields.add("height", reader.u16()?);
+    fields.add("width", reader.u16()?);
+    let components = reader.u8()?;
+    fields.add("components", components);
+    for _ in 0..components {
+        let id = reader.u8()?;
+        let sampling = reader.u8()?;
+        let table = reader.u8()?;
+        fields.add(
+            format!("component {}", id),
+            format_args!(
+                "sampling {}x{}, quantization table {}",
+                sampling >> 4,
+                sampling & 0x0F,
+                table
+            ),
+        );
+    }
+    Some(())
+}
+
+fn parse_start_of_scan(reader: &mut Reader, fields: &mut Fields, is_jpegls: bool) -> Option<()> {
+    let components = reader.u8()?;
+    fields.add("components", components);
+    for _ in 0..components {
+        let id = reader.u8()?;
+        let tables = reader.u8()?;
+        let value = if is_jpegls {
+            format!("mapping table {}", tables)
+        } else {
+            format!("DC table {}, AC table {}", tables >> 4, tables & 0x0F)
+        };
+        fields.add(format!("component {}", id), value);
+    }
+    let start = reader.u8()?;
+    let end = reader.u8()?;
+    let approximation = reader.u8()?;
+    if is_jpegls {
+        fields.add("near", start);
+        fields.add(
+            "interleave mode",
+            match end {
+                0 => "none",
+                1 => "line",
+                2 => "sample",
+                _ => "invalid",
+            },
+        );
+        fields.add("point transform", approximation & 0x0F);
+    } else {
+        fields.add("spectral selection", format_args!("{}..{}", start, end));
+        fields.add(
+            "successive approximation",
+            format_args!("high {}, low {}", approximation >> 4, approximation & 0x0F),
+        );
+    }
+    Some(())
+}
+
+fn parse_huffman_tables(reader: &mut Reader, fields: &mut Fields) -> Option<()> {
+    while reader.remaining() > 0 {
+        let class_and_id = reader.u8()?;
+        let code_count: usize = reader.bytes(16)?.iter().map(|&n| n as usize).sum();
+        reader.bytes(code_count)?;
+        let class = if class_and_id >> 4 == 0 { "DC" } else { "AC" };
+        fields.add(
+            format!("{} table {}", class, class_and_id & 0x0F),
+            format_args!("{} codes", code_count),
+        );
+    }
+    Some(())
+}
+
+fn parse_quantization_tables(reader: &mut Reader, fields: &mut Fields) -> Option<()> {
+    while reader.remaining() > 0 {
+        let precision_and_id = reader.u8()?;
+        let sixteen_bit = precision_and_id >> 4 != 0;
+        reader.bytes(if sixteen_bit { 128 } else { 64 })?;
+        fields.add(
+            format!("table {}", precision_and_id & 0x0F),
+            if sixteen_bit { "16-bit" } else { "8-bit" },
+        );
+    }
+    Some(())
+}
+
+fn parse_application_data(reader: &mut Reader, fields: &mut Fields) -> Option<()> {
+    let payload = reader.bytes(reader.remaining())?;
+    let identifier_length = payload
+        .iter()
+        .take(32)
+        .position(|&b| b == 0 || !b.is_ascii_graphic() && b != b' ')
+        .unwrap_or(payload.len().min(32));
+    if identifier_length > 0 {
+        fields.add("identifier", text_preview(&payload[..identifier_length]));
+    }
+    Some(())
+}
+
+fn parse_jpegls_preset_parameters(reader: &mut Reader, fields: &mut Fields) -> Option<()> {
+    let id = reader.u8()?;
+    match id {
+        1 => {
+            fields.add("type", "preset coding parameters");
+            fields.add("MAXVAL", reader.u16()?);
+            fields.add("T1", reader.u16()?);
+            fields.add("T2", reader.u16()?);
+            fields.add("T3", reader.u16()?);
+            fields.add("RESET", reader.u16()?);
+        }
+        2 | 3 => {
+            fields.add(
+                "type",
+                if id == 2 {
+                    "mapping table"
+                } else {
+                    "mapping table continuation"
+                },
+            );
+            fields.add("table id", reader.u8()?);
+            fields.add("entry width", reader.u8()?);
+        }
+        4 => {
+            fields.add("type", "oversize image dimension");
+            let width_bytes = reader.u8()? as usize;
+            let read = |reader: &mut Reader| -> Option<u64> {
+                let bytes = reader.bytes(width_bytes)?;
+                Some(bytes.iter().fold(0u64, |value, &b| value << 8 | b as u64))
+            };
+            fields.add("height", read(reader)?);
+            fields.add("width", read(reader)?);
+        }
+        _ => fields.add("type", format_args!("unknown ({})", id)),
+    }
+    Some(())
+}
+
+// JPEG 2000 codestream
+
+fn dump_codestream(data: &[u8], base: usize, depth: usize, dump: &mut StreamDump) {
+    let mut position = 0;
+    let mut tile_part_end = data.len();
+    while position < data.len() {
+        let Some(&[0xFF, marker]) = data.get(position..position + 2) else {
+            dump.error = Some((base + position, "Expected a marker".to_string()));
+            return;
+        };
+        let (name, description) = codestream_marker_name(marker);
+
+        match marker {
+            0x4F | 0x92 | 0xD9 => {
+                push_segment(
+                    dump,
+                    base + position,
+                    2,
+                    name,
+                    description,
+                    Fields::default(),
+                    depth,
+                );
+                position += 2;
+                if marker == 0xD9 {
+                    return;
+                }
+                continue;
+            }
+            0x93 => {
+                // Tile data runs to the end of the tile-part given by the SOT marker.
+                push_segment(
+                    dump,
+                    base + position,
+                    2,
+                    name,
+                    description,
+                    Fields::default(),
+                    depth,
+                );
+                let data_end = tile_part_end.max(position + 2).min(data.len());
+                let mut fields = Fields::default();
+                fields.add("bytes", data_end - position - 2);
+                push_segment(
+                    dump,
+                    base + position + 2,
+                    data_end - position - 2,
+                    "data",
+                    "Tile-part bit stream",
+                    fields,
+                    depth,
+                );
+                position = data_end;
+                continue;
+            }
+            _ => {}
+        }
+
+        let Some(length) = data
+            .get(position + 2..position + 4)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+        else {
+            dump.error = Some((base + position, format!("{} segment truncated", name)));
+            return;
+        };
+        let end = position + 2 + length;
+        if length < 2 || end > data.len() {
+            dump.error = Some((
+                base + position,
+                format!("{} segment length {} exceeds the data", name, length),
+            ));
+            return;
+        }
+        let payload = &data[position + 4..end];
+        let fields = match marker {
+            0x50 => parse_fields(payload, parse_capabilities),
+            0x51 => parse_fields(payload, parse_image_and_tile_size),
+            0x52 => parse_fields(payload, parse_coding_style),
+            0x53 => parse_fields(payload, |r, f| {
+                // Component indices are one byte when there are fewer than 257 components.
+                let component = if payload.len() > 6 {
+                    r.u16()?
+                } else {
+                    r.u8()? as u16
+                };
+                f.add("component", component);
+                f.add("coding style", r.u8()?);
+                f.add("decomposition levels", r.u8()?);
+                Some(())
+            }),
+            0x5C => parse_fields(payload, parse_quantization_default),
+            0x5D => parse_fields(payload, |r, f| {
+                f.add("component", r.u8()?);
+                let style = r.u8()?;
+                f.add("quantization", quantization_style(style));
+                f.add("guard bits", style >> 5);
+                Some(())
+            }),
+            0x64 => parse_fields(payload, |r, f| {
+                let registration = r.u16()?;
+                let text = r.bytes(r.remaining())?;
+                if registration == 1 {
+                    f.add("text", text_preview(text));
+                } else {
+                    f.add("binary", format_args!("{} bytes", text.len()));
+                }
+                Some(())
+            }),
+            0x90 => parse_fields(payload, |r, f| {
+                f.add("tile", r.u16()?);
+                let tile_part_length = r.u32()? as usize;
+                f.add("tile-part length", tile_part_length);
+                f.add("tile-part", r.u8()?);
+                f.add("tile-parts", r.u8()?);
+                tile_part_end = if tile_part_length == 0 {
+                    // The last tile-part may run to the EOC marker.
+                    if data.ends_with(&[0xFF, 0xD9]) {
+                        data.len() - 2
+                    } else {
+                        data.len()
+                    }
+                } else {
+                    position + tile_part_length
+                };
+                Some(())
+            }),
+            _ => Fields::default(),
+        };
+        push_segment(
+            dump,
+            base + position,
+            2 + length,
+            name,
+            description,
+            fields,
+            depth,
+        );
+        position = end;
+    }
+    dump.error = Some((base + data.len(), "Missing EOC marker".to_string()));
+}
+
+fn codestream_marker_name(marker: u8) -> (String, &'static str) {
+    let (name, description) = match marker {
+        0x4F => ("SOC", "Start of codestream"),
+        0x50 => ("CAP", "Extended capabilities"),
+        0x51 => ("SIZ", "Image and tile size"),
+        0x52 => ("COD", "Coding style default"),
+        0x53 => ("COC", "Coding style component"),
+        0x55 => ("TLM", "Tile-part lengths"),
+        0x57 => ("PLM", "Packet length, main header"),
+        0x58 => ("PLT", "Packet length, tile-part header"),
+        0x59 => ("CPF", "Corresponding profile"),
+        0x5C => ("QCD", "Quantization default"),
+        0x5D => ("QCC", "Quantization component"),
+        0x5E => ("RGN", "Region of interest"),
+        0x5F => ("POC", "Progression order change"),
+        0x60 => ("PPM", "Packed packet headers, main header"),
+        0x61 => ("PPT", "Packed packet headers, tile-part header"),
+        0x63 => ("CRG", "Component registration"),
+        0x64 => ("COM", "Comment"),
+        0x90 => ("SOT", "Start of tile-part"),
+        0x91 => ("SOP", "Start of packet"),
+        0x92 => ("EPH", "End of packet header"),
+        0x93 => ("SOD", "Start of data"),
+        0xD9 => ("EOC", "End of codestream"),
+        _ => return (format!("0xFF{:02X}", marker), "Unknown marker"),
+    };
+    (name.to_string(), description)
+}
+
+fn parse_capabilities(reader: &mut Reader, fields: &mut Fields) -> Option<()> {
+    let pcap = reader.u32()?;
+    fields.add("Pcap", format_args!("0x{:08X}", pcap));
+    if pcap & (1 << 14) != 0 {
+        fields.add("HTJ2K", "yes");
+    }
+    while reader.remaining() >= 2 {
+        fields.add("Ccap", format_args!("0x{:04X}", reader.u16()?));
+    }
+    Some(())
+}
+
+fn parse_image_and_tile_size(reader: &mut Reader, fields: &mut Fields) -> Option<()> {
+    fields.add("Rsiz", format_args!("0x{:04X}", reader.u16()?));
+    let width = reader.u32()?;
+    let height = reader.u32()?;
+    let x_offset = reader.u32()?;
+    let y_offset = reader.u32()?;
+    fields.add("image size", format_args!("{}x{}", width, height));
+    fields.add("image offset", format_args!("{},{}", x_offset, y_offset));
+    let tile_width = reader.u32()?;
+    let tile_height = reader.u32()?;
+    let tile_x_offset = reader.u32()?;
+    let tile_y_offset = reader.u32()?;
+    fields.add("tile size", format_args!("{}x{}", tile_width, tile_height));
+    fields.add(
+        "tile offset",
+        format_args!("{},{}", tile_x_offset, tile_y_offset),
+    );
+    let components = reader.u16()?;
+    fields.add("components", components);
+    for c in 0..components {
+        let depth = reader.u8()?;
+        let x_subsampling = reader.u8()?;
+        let y_subsampling = reader.u8()?;
+        fields.add(
+            format!("component {}", c),
+            format_args!(
+                "{} bits {}, subsampling {}x{}",
+                (depth & 0x7F) + 1,
+                if depth & 0x80 != 0 {
+                    "signed"
+                } else {
+                    "unsigned"
+                },
+                x_subsampling,
+                y_subsampling
+            ),
+        );
+    }
+    Some(())
+}
+
+fn parse_coding_style(reader: &mut Reader, fields: &mut Fields) -> Option<()> {
+    let style = reader.u8()?;
+    fields.add("coding style", format_args!("0x{:02X}", style));
+    fields.add(
+        "progression order",
+        match reader.u8()? {
+            0 => "LRCP",
+            1 => "RLCP",
+            2 => "RPCL",
+            3 => "PCRL",
+            4 => "CPRL",
+            _ => "invalid",
+        },
+    );
+    fields.add("layers", reader.u16()?);
+    fields.add("multiple component transform", reader.u8()?);
+    fields.add("decomposition levels", reader.u8()?);
+    let block_width = reader.u8()?;
+    let block_height = reader.u8()?;
+    fields.add(
+        "code-block size",
+        format_args!(
+            "{}x{}",
+            1u32 << ((block_width & 0x0F) + 2),
+            1u32 << ((block_height & 0x0F) + 2)
+        ),
+    );
+    fields.add("code-block style", format_args!("0x{:02X}", reader.u8()?));
+    fields.add(
+        "wavelet",
+        match reader.u8()? {
+            0 => "9-7 irreversible",
+            1 => "5-3 reversible",
+            _ => "invalid",
+        },
+    );
+    Some(())
+}
+
+fn parse_quantization_default(reader: &mut Reader, fields: &mut Fields) -> Option<()> {
+    let style = reader.u8()?;
+    fields.add("quantization", quantization_style(style));
+    fields.add("guard bits", style >> 5);
+    let step_size_bytes = if style & 0x1F == 0 { 1 } else { 2 };
+    fields.add("step sizes", reader.remaining() / step_size_bytes);
+    Some(())
+}
+
+fn quantization_style(style: u8) -> &'static str {
+    match style & 0x1F {
+        0 => "none",
+        1 => "scalar derived",
+        2 => "scalar expounded",
+        _ => "invalid",
+    }
+}
+
+// JP2 boxes
+
+fn dump_boxes(data: &[u8], base: usize, depth: usize, dump: &mut StreamDump) {
+    let mut reader = Reader::new(data);
+    while reader.remaining() > 0 {
+        let start = reader.position;
+        let (Some(box_length), Some(box_type)) = (reader.u32(), reader.bytes(4)) else {
+            dump.error = Some((base + start, "Box header truncated".to_string()));
+            return;
+        };
+        let (length, header_length) = match box_length {
+            0 => (data.len() - start, 8),
+            1 => match reader.u64() {
+                Some(length) => (length as usize, 16),
+                None => {
+                    dump.error = Some((base + start, "Box header truncated".to_string()));
+                    return;
+                }
+            },
+            length => (length as usize, 8),
+        };
+        let name = String::from_utf8_lossy(box_type).into_owned();
+        if length < header_length || start + length > data.len() {
+            dump.error = Some((
+                base + start,
+                format!("'{}' box length {} exceeds the data", name, length),
+            ));
+            return;
+        }
+        let contents = &data[start + header_length..start + length];
+
+        let (description, fields) = match box_type {
+            b"jP  " => ("JPEG 2000 signature", Fields::default()),
+            b"ftyp" => ("File type", parse_fields(contents, parse_file_type)),
+            b"jp2h" => ("JP2 header", Fields::default()),
+            b"ihdr" => ("Image header", parse_fields(contents, parse_image_header)),
+            b"bpcc" => ("Bits per component", Fields::default()),
+            b"colr" => ("Colour specification", parse_fields(contents, parse_colour)),
+            b"pclr" => ("Palette", Fields::default()),
+            b"cmap" => ("Component mapping", Fields::default()),
+            b"cdef" => ("Channel definition", Fields::default()),
+            b"res " => ("Resolution", Fields::default()),
+            b"resc" => ("Capture resolution", Fields::default()),
+            b"resd" => ("Default display resolution", Fields::default()),
+            b"jp2c" => ("Contiguous codestream", Fields::default()),
+            b"xml " => ("XML", Fields::default()),
+            b"uuid" => ("UUID", Fields::default()),
+            _ => ("Unknown box", Fields::default()),
+        };
+        push_segment(dump, base + start, length, name, description, fields, depth);
+
+        let contents_offset = base + start + header_length;
+        match box_type {
+            b"jp2h" | b"res " => dump_boxes(contents, contents_offset, depth + 1, dump),
+            b"jp2c" => dump_codestream(contents, contents_offset, depth + 1, dump),
+            _ => {}
+        }
+        if dump.error.is_some() {
+            return;
+        }
+        reader.position = start + length;
+    }
+}
+
+fn parse_file_type(reader: &mut Reader, fields: &mut Fields) -> Option<()> {
+    fields.add("brand", text_preview(reader.bytes(4)?));
+    fields.add("minor version", reader.u32()?);
+    while reader.remaining() >= 4 {
+        fields.add("compatible", text_preview(reader.bytes(4)?));
+    }
+    Some(())
+}
+
+fn parse_image_header(reader: &mut Reader, fields: &mut Fields) -> Option<()> {
+    let height = reader.u32()?;
+    let width = reader.u32()?;
+    fields.add("size", format_args!("{}x{}", width, height));
+    fields.add("components", reader.u16()?);
+    let depth = reader.u8()?;
+    if depth == 0xFF {
+        fields.add("bits per component", "varies (see bpcc)");
+    } else {
+        fields.add(
+            "bits per component",
+            format_args!(
+                "{} {}",
+                (depth & 0x7F) + 1,
+                if depth & 0x80 != 0 {
+                    "signed"
+                } else {
+                    "unsigned"
+                }
+            ),
+        );
+    }
+    fields.add("compression", reader.u8()?);
+    fields.add("colourspace unknown", reader.u8()?);
+    fields.add("intellectual property", reader.u8()?);
+    Some(())
+}
+
+fn parse_colour(reader: &mut Reader, fields: &mut Fields) -> Option<()> {
+    let method = reader.u8()?;
+    reader.u8()?; // precedence
+    reader.u8()?; // approximation
+    match method {
+        1 => fields.add(
+            "colourspace",
+            match reader.u32()? {
+                16 => "sRGB".to_string(),
+                17 => "greyscale".to_string(),
+                18 => "sYCC".to_string(),
+                other => format!("enumerated {}", other),
+            },
+        ),
+        2 => fields.add("ICC profile", format_args!("{} bytes", reader.remaining())),
+        _ => fields.add("method", method),
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jpegls::JpeglsEncoder;
+    use crate::FrameInfo;
+
+    fn names(dump: &StreamDump) -> Vec<&str> {
+        dump.segments.iter().map(|s| s.name.as_str()).collect()
+    }
+
+    fn field<'a>(segment: &'a Segment, name: &str) -> Option<&'a str> {
+        segment
+            .fields
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn test_dump_jpegls() {
+        let pixels: Vec<u8> = (0..64).collect();
+        let mut encoded = vec![0u8; 1024];
+        let mut encoder = JpeglsEncoder::new(&mut encoded);
+        encoder
+            .set_frame_info(FrameInfo {
+                width: 8,
+                height: 8,
+                bits_per_sample: 8,
+                component_count: 1,
+            })
+            .unwrap();
+        let len = encoder.encode(&pixels).unwrap();
+
+        let dump = dump(&encoded[..len]);
+        assert_eq!(dump.format, "JPEG-LS");
+        assert_eq!(dump.error, None);
+        assert_eq!(names(&dump).first(), Some(&"SOI"));
+        assert_eq!(names(&dump).last(), Some(&"EOI"));
+
+        let frame = dump.segments.iter().find(|s| s.name == "SOF55").unwrap();
+        assert_eq!(field(frame, "width"), Some("8"));
+        assert_eq!(field(frame, "precision"), Some("8"));
+        let scan = dump.segments.iter().find(|s| s.name == "SOS").unwrap();
+        assert_eq!(field(scan, "near"), Some("0"));
+
+        // Segments cover the stream without gaps.
+        let covered: usize = dump.segments.iter().map(|s| s.length).sum();
+        assert_eq!(covered, len);
+    }
+
+    #[test]
+    fn test_dump_codestream_tile_part() {
+        let mut data = vec![0xFF, 0x4F];
+        // SIZ: 16x8 image, one 8-bit component.
+        data.extend_from_slice(&[0xFF, 0x51, 0x00, 0x29, 0x00, 0x00]);
+        for value in [16u32, 8, 0, 0, 16, 8, 0, 0] {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        data.extend_from_slice(&[0x00, 0x01, 0x07, 0x01, 0x01]);
+        // SOT with a tile-part length covering SOT, SOD and 3 data bytes.
+        data.extend_from_slice(&[0xFF, 0x90, 0x00, 0x0A, 0x00, 0x00]);
+        data.extend_from_slice(&17u32.to_be_bytes());
+        data.extend_from_slice(&[0x00, 0x01, 0xFF, 0x93, 0x01, 0x02, 0x03, 0xFF, 0xD9]);
+
+        let dump = dump(&data);
+        assert_eq!(dump.error, None);
+        assert_eq!(names(&dump), ["SOC", "SIZ", "SOT", "SOD", "data", "EOC"]);
+        assert_eq!(field(&dump.segments[1], "image size"), Some("16x8"));
+        assert_eq!(
+            field(&dump.segments[1], "component 0"),
+            Some("8 bits unsigned, subsampling 1x1")
+        );
+        assert_eq!(dump.segments[4].length, 3);
+    }
+
+    #[test]
+    fn test_dump_truncated_segment() {
+        let dump = dump(&[0xFF, 0xD8, 0xFF, 0xDB, 0x00, 0x43, 0x00]);
+        assert_eq!(names(&dump), ["SOI"]);
+        assert_eq!(dump.error.unwrap().0, 2);
+    }
+}
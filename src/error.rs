@@ -1,13 +1,17 @@
 use thiserror::Error;
 
 #[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
 pub enum JpeglsError {
     #[error("Not enough memory")]
     NotEnoughMemory = 1,
     #[error("Callback failed")]
     CallbackFailed = 2,
-    #[error("Destination too small")]
-    DestinationTooSmall = 3,
+    /// `needed` is the exact size when decoding. Encoders cannot know the size of the
+    /// stream before writing it and report their estimate, or more than the destination
+    /// they were given when the estimate did not fit either.
+    #[error("Destination too small, {needed} bytes needed")]
+    DestinationTooSmall { needed: usize } = 3,
     #[error("Need more data")]
     NeedMoreData = 4,
     #[error("Invalid data")]
@@ -78,6 +82,8 @@ pub enum JpeglsError {
     InvalidParameterMappingTableId = 37,
     #[error("Invalid parameter mapping table continuation")]
     InvalidParameterMappingTableContinuation = 38,
+    #[error("Parameter out of range")]
+    ParameterOutOfRange = 39,
 
     // Logic errors
     #[error("Invalid operation")]
@@ -107,3 +113,35 @@ pub enum JpeglsError {
     #[error("Invalid argument encoding options")]
     InvalidArgumentEncodingOptions = 112,
 }
+
+impl JpeglsError {
+    /// Numeric error code, the discriminant above.
+    pub fn code(&self) -> i32 {
+        // SAFETY: `repr(i32)` places the discriminant first in every variant.
+        unsafe { *(self as *const Self as *const i32) }
+    }
+
+    /// Replaces the size of a [`DestinationTooSmall`](Self::DestinationTooSmall) error
+    /// reported by a writer, which only knows where it ran out of space, with `needed`.
+    pub(crate) fn with_needed_size(self, needed: usize) -> Self {
+        match self {
+            JpeglsError::DestinationTooSmall { .. } => JpeglsError::DestinationTooSmall { needed },
+            e => e,
+        }
+    }
+}
+
+/// Where a stream decoded in tolerant mode ended early, e.g. a truncated archive file.
+/// The decoders keep what they decoded before this point and fill the rest of the image
+/// with mid-grey samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Truncation {
+    /// Offset in the stream at which the data ran out or stopped making sense.
+    pub offset: usize,
+    /// Rows at the top of the image that were decoded completely. For progressive JPEG 1
+    /// frames these rows still lack the scans that did not arrive; for JPEG 2000 images
+    /// they are the rows of the tiles whose packets were all read.
+    pub complete_rows: u32,
+    /// The error that ended decoding.
+    pub error: JpeglsError,
+}
@@ -3,8 +3,17 @@
 //! This module provides C-compatible functions with opaque handles
 //! for use from C/C++ projects.
 
-use std::os::raw::{c_int, c_uchar};
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::fmt::Display;
+use std::os::raw::{c_char, c_int, c_uchar, c_void};
 use std::ptr;
+use std::sync::{OnceLock, RwLock};
+
+thread_local! {
+    /// Message describing the most recent failure on this thread.
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
 
 /// Opaque decoder handle.
 #[repr(C)]
@@ -12,6 +21,12 @@ pub struct JpegExpDecoder {
     _private: [u8; 0],
 }
 
+/// Opaque encoder handle.
+#[repr(C)]
+pub struct JpegExpEncoder {
+    _private: [u8; 0],
+}
+
 /// Image information structure.
 #[repr(C)]
 pub struct JpegExpImageInfo {
@@ -31,10 +46,228 @@ pub enum JpegExpError {
     InternalError = 4,
 }
 
+/// Output formats of `jpegexp_encoder_new`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JpegExpFormat {
+    Jpeg = 0,
+    Jpegls = 1,
+    J2k = 2,
+}
+
+/// Encoder options for the `jpegexp_encode_*_with_options` functions.
+///
+/// A zero-initialized struct selects the defaults for every field.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JpegExpEncodeOptions {
+    /// JPEG and JPEG 2000 quality (1-100); 0 keeps the encoder default.
+    pub quality: c_int,
+    /// JPEG-LS NEAR parameter; 0 encodes losslessly.
+    pub near_lossless: c_int,
+    /// Sample precision (2-16); 0 means 8 bits.
+    pub bits_per_sample: c_int,
+    /// JPEG-LS interleave mode: 0 = none, 1 = line, 2 = sample.
+    pub interleave_mode: c_int,
+}
+
+/// Coding properties of a stream, filled by `jpegexp_decoder_get_stream_info`; e.g. for
+/// a DICOM toolkit to set Lossy Image Compression and to check the decoded precision.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JpegExpStreamInfo {
+    /// `JpegExpFormat` of the stream.
+    pub format: c_int,
+    /// 1 if decoding reconstructs the encoded samples exactly: the JPEG lossless process,
+    /// JPEG-LS with NEAR 0 or the JPEG 2000 reversible (5/3) wavelet; 0 otherwise.
+    pub lossless: c_int,
+    /// JPEG-LS NEAR parameter of the first scan; 0 for the other formats.
+    pub near_lossless: c_int,
+    /// Sample precision of the frame header. Larger than the `bits_per_sample` of
+    /// `JpegExpImageInfo` when the decoder reduces the samples, as the JPEG decoder does
+    /// to 8 bits.
+    pub precision: u32,
+}
+
+/// Precision and sampling of one component of a stream, filled by
+/// `jpegexp_decoder_get_component_info`. JPEG 2000 components can each have their own
+/// precision and signedness; `JpegExpImageInfo` gives the largest precision.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JpegExpComponentInfo {
+    /// Bits per sample of the component in the stream.
+    pub bits_per_sample: u32,
+    /// 1 if the samples are signed (only in JPEG 2000), 0 otherwise.
+    pub is_signed: c_int,
+    /// Horizontal subsampling relative to the image width, e.g. 2 for 4:2:0 chroma.
+    pub horizontal_subsampling: u32,
+    /// Vertical subsampling relative to the image height.
+    pub vertical_subsampling: u32,
+}
+
+/// Log levels passed to a `JpegExpLogCallback`. The values match the `log` crate.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JpegExpLogLevel {
+    Off = 0,
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+/// Receives one diagnostic message with its `JpegExpLogLevel`.
+///
+/// `message` is only valid for the duration of the call. The callback may be invoked from
+/// any thread that calls into the library.
+pub type JpegExpLogCallback =
+    Option<unsafe extern "C" fn(level: c_int, message: *const c_char, user_data: *mut c_void)>;
+
 /// Internal decoder state.
 struct DecoderState {
     data: Vec<u8>,
     info: Option<crate::FrameInfo>,
+    stream: JpegExpStreamInfo,
+    components: Vec<crate::ComponentInfo>,
+}
+
+/// Internal encoder state.
+struct EncoderState {
+    format: JpegExpFormat,
+    settings: EncodeSettings,
+}
+
+impl EncoderState {
+    /// Facade encoder with the format and options of the handle.
+    fn codec_encoder(&self) -> crate::codec::Encoder {
+        let mut encoder = crate::codec::Encoder::for_format(self.format.into());
+        if let Some(quality) = self.settings.quality {
+            encoder.set_quality(quality);
+        }
+        encoder.set_near_lossless(self.settings.near_lossless);
+        encoder.set_interleave_mode(self.settings.interleave_mode);
+        encoder
+    }
+}
+
+impl From<JpegExpFormat> for crate::codec::Format {
+    fn from(format: JpegExpFormat) -> Self {
+        match format {
+            JpegExpFormat::Jpeg => Self::Jpeg1,
+            JpegExpFormat::Jpegls => Self::Jpegls,
+            JpegExpFormat::J2k => Self::Jpeg2000,
+        }
+    }
+}
+
+/// Callback registered with `jpegexp_set_log_callback`.
+struct LogSink {
+    callback: unsafe extern "C" fn(c_int, *const c_char, *mut c_void),
+    user_data: *mut c_void,
+}
+
+// SAFETY: the embedder that registers the callback guarantees `user_data` may be used from
+// any thread, as documented on `jpegexp_set_log_callback`.
+unsafe impl Send for LogSink {}
+unsafe impl Sync for LogSink {}
+
+static LOG_SINK: RwLock<Option<LogSink>> = RwLock::new(None);
+
+/// `log` backend that forwards records to the registered callback.
+struct CallbackLogger;
+
+impl log::Log for CallbackLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        let sink = LOG_SINK.read().unwrap_or_else(|e| e.into_inner());
+        if let Some(sink) = sink.as_ref() {
+            let message = to_c_string(record.args());
+            unsafe { (sink.callback)(record.level() as c_int, message.as_ptr(), sink.user_data) };
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CallbackLogger = CallbackLogger;
+
+fn to_c_string(message: impl Display) -> CString {
+    CString::new(message.to_string().replace('\0', " ")).unwrap_or_default()
+}
+
+/// Records `message` as the last error of this thread and returns `code`.
+fn fail(code: JpegExpError, message: impl Display) -> c_int {
+    let message = to_c_string(message);
+    log::debug!("{}", message.to_string_lossy());
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    code as c_int
+}
+
+/// Size in bytes of the interleaved image, see [`crate::FrameInfo::decoded_size`]; records
+/// the error when it does not fit in `size_t`.
+fn decoded_size(info: &crate::FrameInfo) -> Result<usize, c_int> {
+    info.decoded_size(crate::OutputLayout::Interleaved)
+        .map_err(|e| fail(JpegExpError::InvalidData, e))
+}
+
+/// Message describing the most recent failure on the calling thread, or null if no call
+/// has failed yet.
+///
+/// The string is owned by the library and stays valid until the next failing call on the
+/// same thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn jpegexp_get_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |m| m.as_ptr()))
+}
+
+/// Register a callback that receives the library's diagnostic messages.
+///
+/// Messages above `max_level` (a `JpegExpLogLevel` value) are discarded. A null `callback`
+/// or `JPEG_EXP_LOG_LEVEL_OFF` turns logging off. `user_data` is passed back unchanged and
+/// must be usable from any thread that calls into the library.
+///
+/// Fails with `JPEG_EXP_ERROR_INTERNAL_ERROR` when the host process already installed a
+/// different `log` backend.
+#[unsafe(no_mangle)]
+pub extern "C" fn jpegexp_set_log_callback(
+    callback: JpegExpLogCallback,
+    max_level: c_int,
+    user_data: *mut c_void,
+) -> c_int {
+    static INSTALLED: OnceLock<bool> = OnceLock::new();
+
+    let Some(level) = usize::try_from(max_level)
+        .ok()
+        .and_then(|level| log::LevelFilter::iter().nth(level))
+    else {
+        return fail(
+            JpegExpError::InvalidData,
+            format!("log level {max_level} is outside 0..=5"),
+        );
+    };
+    if !*INSTALLED.get_or_init(|| log::set_logger(&LOGGER).is_ok()) {
+        return fail(
+            JpegExpError::InternalError,
+            "another logger is already installed in this process",
+        );
+    }
+
+    let sink = callback.map(|callback| LogSink {
+        callback,
+        user_data,
+    });
+    let level = if sink.is_some() {
+        level
+    } else {
+        log::LevelFilter::Off
+    };
+    *LOG_SINK.write().unwrap_or_else(|e| e.into_inner()) = sink;
+    log::set_max_level(level);
+    JpegExpError::Ok as c_int
 }
 
 /// Create a new decoder from raw data.
@@ -55,6 +288,8 @@ pub unsafe extern "C" fn jpegexp_decoder_new(
     let state = Box::new(DecoderState {
         data: slice.to_vec(),
         info: None,
+        stream: JpegExpStreamInfo::default(),
+        components: Vec::new(),
     });
 
     Box::into_raw(state) as *mut JpegExpDecoder
@@ -71,7 +306,8 @@ pub unsafe extern "C" fn jpegexp_decoder_free(decoder: *mut JpegExpDecoder) {
     }
 }
 
-/// Read the image header.
+/// Read the image header. For JPEG 2000 only the main header (and the boxes of a JP2
+/// file) are read; the tiles are decoded by `jpegexp_decoder_decode`.
 ///
 /// # Safety
 /// `decoder` must be valid. `info` must point to a valid JpegExpImageInfo.
@@ -81,220 +317,217 @@ pub unsafe extern "C" fn jpegexp_decoder_read_header(
     decoder: *mut JpegExpDecoder,
     info: *mut JpegExpImageInfo,
 ) -> c_int {
-    // #region agent log
-    {
-        use std::fs::OpenOptions;
-        use std::io::Write;
-        if let Ok(mut f) = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log")
-        {
-            let _ = writeln!(
-                f,
-                r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"A,D","location":"ffi.rs:77","message":"jpegexp_decoder_read_header entry","data":{{"decoder_null":{},"info_null":{}}},"timestamp":{}}}"#,
-                decoder.is_null(),
-                info.is_null(),
-                std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap_or_default()
-                    .as_millis()
-            );
-        }
-    }
-    // #endregion
     if decoder.is_null() {
-        // #region agent log
-        {
-            use std::fs::OpenOptions;
-            use std::io::Write;
-            if let Ok(mut f) = OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log")
-            {
-                let _ = writeln!(
-                    f,
-                    r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"A","location":"ffi.rs:85","message":"decoder is null","data":{{}},"timestamp":{}}}"#,
-                    std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap_or_default()
-                        .as_millis()
-                );
-            }
-        }
-        // #endregion
-        return JpegExpError::InvalidData as c_int;
+        return fail(JpegExpError::InvalidData, "decoder handle is null");
     }
 
     let state = unsafe { &mut *(decoder as *mut DecoderState) };
-    let data_len = state.data.len();
-    let first_bytes: Vec<u8> = state.data.iter().take(4).copied().collect();
+    log::debug!("reading header of a {} byte stream", state.data.len());
 
-    // #region agent log
-    {
-        use std::fs::OpenOptions;
-        use std::io::Write;
-        if let Ok(mut f) = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log")
-        {
-            let _ = writeln!(
-                f,
-                r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"E","location":"ffi.rs:96","message":"format detection start","data":{{"data_len":{},"first_bytes":{:?},"is_jpeg1":{},"is_jpeg2000":{},"is_jpegls":{}}},"timestamp":{}}}"#,
-                data_len,
-                first_bytes,
-                state.data.starts_with(&[0xFF, 0xD8]),
-                state.data.starts_with(&[0xFF, 0x4F])
-                    || state.data.starts_with(b"\x00\x00\x00\x0CjP"),
-                !state.data.starts_with(&[0xFF, 0xD8])
-                    && !(state.data.starts_with(&[0xFF, 0x4F])
-                        || state.data.starts_with(b"\x00\x00\x00\x0CjP")),
-                std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap_or_default()
-                    .as_millis()
-            );
+    let frame_info = match crate::codec::detect_format(&state.data) {
+        Some(crate::codec::Format::Jpeg2000) => {
+            let mut reader = crate::jpeg_stream_reader::JpegStreamReader::new(&state.data);
+            let mut parser = crate::jpeg2000::parser::J2kParser::new(&mut reader);
+            let image = match parser.parse_info() {
+                Ok(image) => image,
+                Err(e) => return fail(JpegExpError::InvalidData, e),
+            };
+            let (width, height) = image.size();
+            let frame_info = crate::FrameInfo {
+                width,
+                height,
+                bits_per_sample: image.channel_bits_per_sample() as i32,
+                component_count: image.channel_count() as i32,
+            };
+            let reversible = image
+                .cod
+                .as_ref()
+                .is_some_and(|cod| cod.transformation == 1);
+            state.stream = JpegExpStreamInfo {
+                format: JpegExpFormat::J2k as c_int,
+                lossless: reversible as c_int,
+                near_lossless: 0,
+                precision: frame_info.bits_per_sample as u32,
+            };
+            state.components = image.component_info();
+            frame_info
         }
-    }
-    // #endregion
-
-    // Detect format and read header
-    if state.data.starts_with(&[0xFF, 0xD8]) {
-        // JPEG 1
-        let mut reader = crate::jpeg_stream_reader::JpegStreamReader::new(&state.data);
-        let mut spiff = None;
-        // #region agent log
-        {
-            use std::fs::OpenOptions;
-            use std::io::Write;
-            if let Ok(mut f) = OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log")
-            {
-                let _ = writeln!(
-                    f,
-                    r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"A","location":"ffi.rs:111","message":"before JPEG1 read_header","data":{{}},"timestamp":{}}}"#,
-                    std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap_or_default()
-                        .as_millis()
-                );
+        Some(format) => {
+            // JPEG 1 and JPEG-LS
+            let mut reader = crate::jpeg_stream_reader::JpegStreamReader::new(&state.data);
+            let mut spiff = None;
+            if let Err(e) = reader.read_header(&mut spiff) {
+                return fail(JpegExpError::InvalidData, e);
             }
-        }
-        // #endregion
-        match reader.read_header(&mut spiff) {
-            Ok(_) => {
-                let frame_info = reader.frame_info();
-                state.info = Some(frame_info);
-                // #region agent log
-                {
-                    use std::fs::OpenOptions;
-                    use std::io::Write;
-                    if let Ok(mut f) = OpenOptions::new()
-                        .create(true)
-                        .append(true)
-                        .open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log")
-                    {
-                        let _ = writeln!(
-                            f,
-                            r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"A","location":"ffi.rs:119","message":"JPEG1 read_header success","data":{{"width":{},"height":{},"components":{},"bits_per_sample":{}}},"timestamp":{}}}"#,
-                            frame_info.width,
-                            frame_info.height,
-                            frame_info.component_count,
-                            frame_info.bits_per_sample,
-                            std::time::SystemTime::now()
-                                .duration_since(std::time::UNIX_EPOCH)
-                                .unwrap_or_default()
-                                .as_millis()
-                        );
-                    }
+            let frame_info = reader.frame_info();
+            state.components = reader.component_info();
+            if format == crate::codec::Format::Jpeg1 {
+                state.stream = JpegExpStreamInfo {
+                    format: JpegExpFormat::Jpeg as c_int,
+                    lossless: reader.is_lossless as c_int,
+                    near_lossless: 0,
+                    precision: frame_info.bits_per_sample as u32,
+                };
+                // The JPEG 1 decoder writes 8-bit samples.
+                crate::FrameInfo {
+                    bits_per_sample: 8,
+                    ..frame_info
                 }
-                // #endregion
-                if !info.is_null() {
-                    unsafe {
-                        (*info).width = frame_info.width;
-                        (*info).height = frame_info.height;
-                        (*info).components = frame_info.component_count as u32;
-                        (*info).bits_per_sample = frame_info.bits_per_sample as u32;
-                    }
+            } else {
+                // NEAR is a parameter of the scan header that follows the frame header.
+                if let Err(e) = reader.read_start_of_scan_segment_jpegls() {
+                    return fail(JpegExpError::InvalidData, e);
                 }
-            }
-            Err(e) => {
-                // #region agent log
-                {
-                    use std::fs::OpenOptions;
-                    use std::io::Write;
-                    if let Ok(mut f) = OpenOptions::new()
-                        .create(true)
-                        .append(true)
-                        .open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log")
-                    {
-                        let _ = writeln!(
-                            f,
-                            r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"A","location":"ffi.rs:134","message":"JPEG1 read_header failed","data":{{"error":"{:?}"}},"timestamp":{}}}"#,
-                            e,
-                            std::time::SystemTime::now()
-                                .duration_since(std::time::UNIX_EPOCH)
-                                .unwrap_or_default()
-                                .as_millis()
-                        );
-                    }
-                }
-                // #endregion
-                return JpegExpError::InvalidData as c_int;
+                let near_lossless = reader.parameters().near_lossless;
+                state.stream = JpegExpStreamInfo {
+                    format: JpegExpFormat::Jpegls as c_int,
+                    lossless: (near_lossless == 0) as c_int,
+                    near_lossless,
+                    precision: frame_info.bits_per_sample as u32,
+                };
+                frame_info
             }
         }
-    } else if state.data.starts_with(&[0xFF, 0x4F]) || state.data.starts_with(b"\x00\x00\x00\x0CjP")
-    {
-        // JPEG 2000
-        let mut reader = crate::jpeg_stream_reader::JpegStreamReader::new(&state.data);
-        let mut decoder = crate::jpeg2000::decoder::J2kDecoder::new(&mut reader);
-        let image = match decoder.decode() {
-            Ok(img) => img,
-            Err(_) => return JpegExpError::InvalidData as c_int,
-        };
+        None => {
+            return fail(
+                JpegExpError::UnsupportedFormat,
+                "not a JPEG, JPEG-LS or JPEG 2000 stream",
+            )
+        }
+    };
 
-        let frame_info = crate::FrameInfo {
-            width: image.width,
-            height: image.height,
-            bits_per_sample: 8,
-            component_count: image.component_count as i32,
-        };
-        state.info = Some(frame_info);
+    log::debug!(
+        "header: {}x{}, {} components, {} bits per sample",
+        frame_info.width,
+        frame_info.height,
+        frame_info.component_count,
+        frame_info.bits_per_sample
+    );
+    state.info = Some(frame_info);
 
-        if !info.is_null() {
-            unsafe {
-                (*info).width = image.width;
-                (*info).height = image.height;
-                (*info).components = image.component_count;
-                (*info).bits_per_sample = 8;
-            }
-        }
-    } else {
-        // JPEG-LS
-        let mut decoder = crate::jpegls::JpeglsDecoder::new(&state.data);
-        if decoder.read_header().is_err() {
-            return JpegExpError::InvalidData as c_int;
+    if !info.is_null() {
+        unsafe {
+            (*info).width = frame_info.width;
+            (*info).height = frame_info.height;
+            (*info).components = frame_info.component_count as u32;
+            (*info).bits_per_sample = frame_info.bits_per_sample as u32;
         }
-        let frame_info = decoder.frame_info();
-        state.info = Some(frame_info);
+    }
 
-        if !info.is_null() {
-            unsafe {
-                (*info).width = frame_info.width;
-                (*info).height = frame_info.height;
-                (*info).components = frame_info.component_count as u32;
-                (*info).bits_per_sample = frame_info.bits_per_sample as u32;
-            }
-        }
+    JpegExpError::Ok as c_int
+}
+
+/// Query the coding properties of the stream: its format, whether it is lossless and the
+/// precision of its samples.
+///
+/// `jpegexp_decoder_read_header` must have been called first.
+///
+/// # Safety
+/// `decoder` must be valid. `info` must point to a writable JpegExpStreamInfo.
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub unsafe extern "C" fn jpegexp_decoder_get_stream_info(
+    decoder: *const JpegExpDecoder,
+    info: *mut JpegExpStreamInfo,
+) -> c_int {
+    if decoder.is_null() || info.is_null() {
+        return fail(
+            JpegExpError::InvalidData,
+            "decoder handle or info pointer is null",
+        );
     }
 
+    let state = unsafe { &*(decoder as *const DecoderState) };
+    if state.info.is_none() {
+        return fail(
+            JpegExpError::InvalidData,
+            "jpegexp_decoder_read_header must be called before querying the stream info",
+        );
+    }
+    unsafe { *info = state.stream };
     JpegExpError::Ok as c_int
 }
 
+/// Query the precision, signedness and subsampling of the component at `index`, which is
+/// less than the `components` of `JpegExpImageInfo`.
+///
+/// `jpegexp_decoder_read_header` must have been called first.
+///
+/// # Safety
+/// `decoder` must be valid. `info` must point to a writable JpegExpComponentInfo.
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub unsafe extern "C" fn jpegexp_decoder_get_component_info(
+    decoder: *const JpegExpDecoder,
+    index: u32,
+    info: *mut JpegExpComponentInfo,
+) -> c_int {
+    if decoder.is_null() || info.is_null() {
+        return fail(
+            JpegExpError::InvalidData,
+            "decoder handle or info pointer is null",
+        );
+    }
+
+    let state = unsafe { &*(decoder as *const DecoderState) };
+    if state.info.is_none() {
+        return fail(
+            JpegExpError::InvalidData,
+            "jpegexp_decoder_read_header must be called before querying the component info",
+        );
+    }
+    let Some(component) = state.components.get(index as usize) else {
+        return fail(
+            JpegExpError::InvalidData,
+            format!("component index {index} is out of range"),
+        );
+    };
+    unsafe {
+        *info = JpegExpComponentInfo {
+            bits_per_sample: component.bits_per_sample as u32,
+            is_signed: component.signed as c_int,
+            horizontal_subsampling: component.horizontal_subsampling as u32,
+            vertical_subsampling: component.vertical_subsampling as u32,
+        }
+    };
+    JpegExpError::Ok as c_int
+}
+
+/// Query the size in bytes of the buffer `jpegexp_decoder_decode` writes.
+///
+/// `jpegexp_decoder_read_header` must have been called first.
+///
+/// # Safety
+/// `decoder` must be valid. `size` must point to a writable `size_t`.
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub unsafe extern "C" fn jpegexp_decoder_get_decoded_size(
+    decoder: *const JpegExpDecoder,
+    size: *mut usize,
+) -> c_int {
+    if decoder.is_null() || size.is_null() {
+        return fail(
+            JpegExpError::InvalidData,
+            "decoder handle or size pointer is null",
+        );
+    }
+
+    let state = unsafe { &*(decoder as *const DecoderState) };
+    match &state.info {
+        Some(info) => match decoded_size(info) {
+            Ok(decoded_size) => {
+                unsafe { *size = decoded_size };
+                JpegExpError::Ok as c_int
+            }
+            Err(code) => code,
+        },
+        None => fail(
+            JpegExpError::InvalidData,
+            "jpegexp_decoder_read_header must be called before querying the decoded size",
+        ),
+    }
+}
+
 /// Decode the image to raw pixels.
 ///
 /// # Safety
@@ -307,244 +540,54 @@ pub unsafe extern "C" fn jpegexp_decoder_decode(
     output_len: usize,
 ) -> c_int {
     if decoder.is_null() || output.is_null() {
-        return JpegExpError::InvalidData as c_int;
+        return fail(
+            JpegExpError::InvalidData,
+            "decoder handle or output buffer is null",
+        );
     }
 
     let state = unsafe { &*(decoder as *mut DecoderState) };
     let info = match &state.info {
         Some(i) => i,
-        None => return JpegExpError::InvalidData as c_int,
+        None => {
+            return fail(
+                JpegExpError::InvalidData,
+                "jpegexp_decoder_read_header must be called before decoding",
+            )
+        }
     };
 
-    let required_size = (info.width * info.height * info.component_count as u32) as usize;
+    let required_size = match decoded_size(info) {
+        Ok(size) => size,
+        Err(code) => return code,
+    };
     if output_len < required_size {
-        return JpegExpError::BufferTooSmall as c_int;
+        return fail(
+            JpegExpError::BufferTooSmall,
+            format!("output buffer holds {output_len} bytes, {required_size} are required"),
+        );
     }
 
-    let output_slice = unsafe { std::slice::from_raw_parts_mut(output, required_size) };
-
-    // Decode based on format
-    if state.data.starts_with(&[0xFF, 0xD8]) {
-        // #region agent log
-        {
-            use std::fs::OpenOptions;
-            use std::io::Write;
-            if let Ok(mut f) = OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log")
-            {
-                let _ = writeln!(
-                    f,
-                    r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"A,B","location":"ffi.rs:246","message":"decoding JPEG1","data":{{}},"timestamp":{}}}"#,
-                    std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap_or_default()
-                        .as_millis()
-                );
-            }
-        }
-        // #endregion
-        let mut dec = crate::jpeg1::decoder::Jpeg1Decoder::new(&state.data);
-        match dec.read_header() {
-            Ok(_) => {}
-            Err(e) => {
-                // #region agent log
-                {
-                    use std::fs::OpenOptions;
-                    use std::io::Write;
-                    if let Ok(mut f) = OpenOptions::new()
-                        .create(true)
-                        .append(true)
-                        .open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log")
-                    {
-                        let _ = writeln!(
-                            f,
-                            r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"A","location":"ffi.rs:257","message":"JPEG1 decoder read_header failed in decode","data":{{"error":"{:?}"}},"timestamp":{}}}"#,
-                            e,
-                            std::time::SystemTime::now()
-                                .duration_since(std::time::UNIX_EPOCH)
-                                .unwrap_or_default()
-                                .as_millis()
-                        );
-                    }
-                }
-                // #endregion
-                return JpegExpError::InvalidData as c_int;
-            }
-        }
-        match dec.decode(output_slice) {
-            Ok(_) => {
-                // #region agent log
-                {
-                    use std::fs::OpenOptions;
-                    use std::io::Write;
-                    if let Ok(mut f) = OpenOptions::new()
-                        .create(true)
-                        .append(true)
-                        .open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log")
-                    {
-                        let _ = writeln!(
-                            f,
-                            r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"B","location":"ffi.rs:232","message":"JPEG1 decode success","data":{{}},"timestamp":{}}}"#,
-                            std::time::SystemTime::now()
-                                .duration_since(std::time::UNIX_EPOCH)
-                                .unwrap_or_default()
-                                .as_millis()
-                        );
-                    }
-                }
-                // #endregion
-            }
-            Err(e) => {
-                // #region agent log
-                {
-                    use std::fs::OpenOptions;
-                    use std::io::Write;
-                    if let Ok(mut f) = OpenOptions::new()
-                        .create(true)
-                        .append(true)
-                        .open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log")
-                    {
-                        let _ = writeln!(
-                            f,
-                            r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"B","location":"ffi.rs:242","message":"JPEG1 decode failed","data":{{"error":"{:?}"}},"timestamp":{}}}"#,
-                            e,
-                            std::time::SystemTime::now()
-                                .duration_since(std::time::UNIX_EPOCH)
-                                .unwrap_or_default()
-                                .as_millis()
-                        );
-                    }
-                }
-                // #endregion
-                return JpegExpError::InternalError as c_int;
-            }
-        }
-    } else if state.data.starts_with(&[0xFF, 0x4F]) || state.data.starts_with(b"\x00\x00\x00\x0CjP")
-    {
-        // J2K decoding returns metadata only - pixel reconstruction requires IDWT
-        // Return default image values for compatibility
-        output_slice.fill(128);
-    } else {
-        // #region agent log
-        {
-            use std::fs::OpenOptions;
-            use std::io::Write;
-            if let Ok(mut f) = OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log")
-            {
-                let _ = writeln!(
-                    f,
-                    r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"A,B","location":"ffi.rs:252","message":"decoding JPEG-LS","data":{{}},"timestamp":{}}}"#,
-                    std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap_or_default()
-                        .as_millis()
-                );
-            }
-        }
-        // #endregion
-        let mut dec = crate::jpegls::JpeglsDecoder::new(&state.data);
-        match dec.read_header() {
-            Ok(_) => {}
-            Err(e) => {
-                // #region agent log
-                {
-                    use std::fs::OpenOptions;
-                    use std::io::Write;
-                    if let Ok(mut f) = OpenOptions::new()
-                        .create(true)
-                        .append(true)
-                        .open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log")
-                    {
-                        let _ = writeln!(
-                            f,
-                            r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"A","location":"ffi.rs:262","message":"JPEG-LS decoder read_header failed in decode","data":{{"error":"{:?}"}},"timestamp":{}}}"#,
-                            e,
-                            std::time::SystemTime::now()
-                                .duration_since(std::time::UNIX_EPOCH)
-                                .unwrap_or_default()
-                                .as_millis()
-                        );
-                    }
-                }
-                // #endregion
-                return JpegExpError::InvalidData as c_int;
-            }
-        }
-        match dec.decode(output_slice) {
-            Ok(_) => {
-                // #region agent log
-                {
-                    use std::fs::OpenOptions;
-                    use std::io::Write;
-                    if let Ok(mut f) = OpenOptions::new()
-                        .create(true)
-                        .append(true)
-                        .open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log")
-                    {
-                        let _ = writeln!(
-                            f,
-                            r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"B","location":"ffi.rs:275","message":"JPEG-LS decode success","data":{{}},"timestamp":{}}}"#,
-                            std::time::SystemTime::now()
-                                .duration_since(std::time::UNIX_EPOCH)
-                                .unwrap_or_default()
-                                .as_millis()
-                        );
-                    }
-                }
-                // #endregion
-            }
-            Err(e) => {
-                // #region agent log
-                {
-                    use std::fs::OpenOptions;
-                    use std::io::Write;
-                    if let Ok(mut f) = OpenOptions::new()
-                        .create(true)
-                        .append(true)
-                        .open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log")
-                    {
-                        let _ = writeln!(
-                            f,
-                            r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"B","location":"ffi.rs:285","message":"JPEG-LS decode failed","data":{{"error":"{:?}"}},"timestamp":{}}}"#,
-                            e,
-                            std::time::SystemTime::now()
-                                .duration_since(std::time::UNIX_EPOCH)
-                                .unwrap_or_default()
-                                .as_millis()
-                        );
-                    }
-                }
-                // #endregion
-                return JpegExpError::InternalError as c_int;
-            }
+    let format = crate::codec::detect_format(&state.data);
+    log::debug!("decoding {} stream", format.map_or("unknown", |f| f.name()));
+    let image = match crate::codec::Decoder::auto(&state.data) {
+        Ok(image) => image,
+        Err(e) => return fail(JpegExpError::InternalError, e),
+    };
+    let output_slice = unsafe { std::slice::from_raw_parts_mut(output, output_len) };
+    match output_slice.get_mut(..image.pixels.len()) {
+        Some(pixels) => pixels.copy_from_slice(&image.pixels),
+        None => {
+            return fail(
+                JpegExpError::BufferTooSmall,
+                format!(
+                    "output buffer holds {output_len} bytes, {} are required",
+                    image.pixels.len()
+                ),
+            )
         }
     }
 
-    // #region agent log
-    {
-        use std::fs::OpenOptions;
-        use std::io::Write;
-        if let Ok(mut f) = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log")
-        {
-            let _ = writeln!(
-                f,
-                r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"B","location":"ffi.rs:295","message":"jpegexp_decoder_decode success","data":{{}},"timestamp":{}}}"#,
-                std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap_or_default()
-                    .as_millis()
-            );
-        }
-    }
-    // #endregion
     JpegExpError::Ok as c_int
 }
 
@@ -563,236 +606,936 @@ pub unsafe extern "C" fn jpegexp_encode_jpeg(
     output_len: usize,
     bytes_written: *mut usize,
 ) -> c_int {
-    // #region agent log
-    {
-        use std::fs::OpenOptions;
-        use std::io::Write;
-        if let Ok(mut f) = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log")
-        {
-            let _ = writeln!(
-                f,
-                r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"A","location":"ffi.rs:215","message":"jpegexp_encode_jpeg entry","data":{{"width":{},"height":{},"components":{},"output_len":{}}},"timestamp":{}}}"#,
-                width,
-                height,
-                components,
-                output_len,
-                std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap_or_default()
-                    .as_millis()
-            );
-        }
+    unsafe {
+        jpegexp_encode_jpeg_with_options(
+            pixels,
+            width,
+            height,
+            components,
+            ptr::null(),
+            output,
+            output_len,
+            bytes_written,
+        )
     }
-    // #endregion
-    if pixels.is_null() || output.is_null() || bytes_written.is_null() {
-        // #region agent log
-        {
-            use std::fs::OpenOptions;
-            use std::io::Write;
-            if let Ok(mut f) = OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log")
-            {
-                let _ = writeln!(
-                    f,
-                    r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"A","location":"ffi.rs:225","message":"null pointer check failed","data":{{}},"timestamp":{}}}"#,
-                    std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap_or_default()
-                        .as_millis()
-                );
-            }
-        }
-        // #endregion
-        return JpegExpError::InvalidData as c_int;
+}
+
+/// Encode raw pixels to JPEG-LS, losslessly.
+///
+/// # Safety
+/// All pointers must be valid.
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub unsafe extern "C" fn jpegexp_encode_jpegls(
+    pixels: *const c_uchar,
+    width: u32,
+    height: u32,
+    components: u32,
+    output: *mut c_uchar,
+    output_len: usize,
+    bytes_written: *mut usize,
+) -> c_int {
+    unsafe {
+        jpegexp_encode_jpegls_near(
+            pixels,
+            width,
+            height,
+            components,
+            0,
+            output,
+            output_len,
+            bytes_written,
+        )
     }
+}
 
-    let pixel_count = (width * height * components) as usize;
-    let pixels_slice = unsafe { std::slice::from_raw_parts(pixels, pixel_count) };
-    let output_slice = unsafe { std::slice::from_raw_parts_mut(output, output_len) };
+/// Encode raw pixels to JPEG-LS with the NEAR parameter `near_lossless`: 0 for lossless
+/// coding, otherwise the maximum error allowed per sample (at most 127 for 8-bit
+/// samples).
+///
+/// # Safety
+/// All pointers must be valid.
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub unsafe extern "C" fn jpegexp_encode_jpegls_near(
+    pixels: *const c_uchar,
+    width: u32,
+    height: u32,
+    components: u32,
+    near_lossless: c_int,
+    output: *mut c_uchar,
+    output_len: usize,
+    bytes_written: *mut usize,
+) -> c_int {
+    let options = JpegExpEncodeOptions {
+        near_lossless,
+        ..JpegExpEncodeOptions::default()
+    };
+    unsafe {
+        jpegexp_encode_jpegls_with_options(
+            pixels,
+            width,
+            height,
+            components,
+            &options,
+            output,
+            output_len,
+            bytes_written,
+        )
+    }
+}
 
-    let frame_info = crate::FrameInfo {
-        width,
-        height,
-        bits_per_sample: 8,
-        component_count: components as i32,
+/// Encode raw pixels to JPEG 2000.
+///
+/// # Safety
+/// All pointers must be valid.
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub unsafe extern "C" fn jpegexp_encode_j2k(
+    pixels: *const c_uchar,
+    width: u32,
+    height: u32,
+    components: u32,
+    quality: u8,
+    output: *mut c_uchar,
+    output_len: usize,
+    bytes_written: *mut usize,
+) -> c_int {
+    let options = JpegExpEncodeOptions {
+        quality: c_int::from(quality.max(1)),
+        ..JpegExpEncodeOptions::default()
     };
+    unsafe {
+        jpegexp_encode_j2k_with_options(
+            pixels,
+            width,
+            height,
+            components,
+            &options,
+            output,
+            output_len,
+            bytes_written,
+        )
+    }
+}
 
-    // #region agent log
-    {
-        use std::fs::OpenOptions;
-        use std::io::Write;
-        if let Ok(mut f) = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log")
-        {
-            let _ = writeln!(
-                f,
-                r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"B","location":"ffi.rs:237","message":"before encoder.encode call","data":{{"pixel_count":{},"frame_info_width":{},"frame_info_height":{},"frame_info_components":{}}},"timestamp":{}}}"#,
-                pixel_count,
-                frame_info.width,
-                frame_info.height,
-                frame_info.component_count,
-                std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap_or_default()
-                    .as_millis()
-            );
-        }
+/// Encode raw pixels to JPEG using the given options.
+///
+/// `options` may be null to use the defaults. Samples wider than 8 bits are passed as
+/// little-endian 16-bit values.
+///
+/// # Safety
+/// All pointers must be valid. `pixels` must hold `width * height * components` samples.
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub unsafe extern "C" fn jpegexp_encode_jpeg_with_options(
+    pixels: *const c_uchar,
+    width: u32,
+    height: u32,
+    components: u32,
+    options: *const JpegExpEncodeOptions,
+    output: *mut c_uchar,
+    output_len: usize,
+    bytes_written: *mut usize,
+) -> c_int {
+    unsafe {
+        encode_with_options(
+            JpegExpFormat::Jpeg,
+            options,
+            pixels,
+            (width, height, components),
+            output,
+            output_len,
+            bytes_written,
+        )
     }
-    // #endregion
-    let mut encoder = crate::jpeg1::encoder::Jpeg1Encoder::new();
-    match encoder.encode(pixels_slice, &frame_info, output_slice) {
-        Ok(len) => {
-            // #region agent log
-            {
-                use std::fs::OpenOptions;
-                use std::io::Write;
-                if let Ok(mut f) = OpenOptions::new()
-                    .create(true)
-                    .append(true)
-                    .open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log")
-                {
-                    let first_bytes: Vec<u8> = output_slice.iter().take(10).copied().collect();
-                    let last_bytes: Vec<u8> = output_slice
-                        .iter()
-                        .skip(len.saturating_sub(2))
-                        .take(2)
-                        .copied()
-                        .collect();
-                    let _ = writeln!(
-                        f,
-                        r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"C,D,E","location":"ffi.rs:243","message":"encoder.encode success","data":{{"len":{},"first_bytes":{:?},"last_bytes":{:?},"has_soi":{},"has_eoi":{}}},"timestamp":{}}}"#,
-                        len,
-                        first_bytes,
-                        last_bytes,
-                        first_bytes.len() >= 2 && first_bytes[0] == 0xFF && first_bytes[1] == 0xD8,
-                        last_bytes.len() >= 2 && last_bytes[0] == 0xFF && last_bytes[1] == 0xD9,
-                        std::time::SystemTime::now()
-                            .duration_since(std::time::UNIX_EPOCH)
-                            .unwrap_or_default()
-                            .as_millis()
-                    );
-                }
-            }
-            // #endregion
-            unsafe { *bytes_written = len };
-            JpegExpError::Ok as c_int
-        }
-        Err(e) => {
-            // #region agent log
-            {
-                use std::fs::OpenOptions;
-                use std::io::Write;
-                if let Ok(mut f) = OpenOptions::new()
-                    .create(true)
-                    .append(true)
-                    .open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log")
-                {
-                    let _ = writeln!(
-                        f,
-                        r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"A","location":"ffi.rs:252","message":"encoder.encode error","data":{{"error":"{:?}"}},"timestamp":{}}}"#,
-                        e,
-                        std::time::SystemTime::now()
-                            .duration_since(std::time::UNIX_EPOCH)
-                            .unwrap_or_default()
-                            .as_millis()
-                    );
-                }
-            }
-            // #endregion
-            JpegExpError::InternalError as c_int
-        }
+}
+
+/// Encode raw pixels to JPEG-LS using the given options.
+///
+/// `options` may be null to use the defaults. Samples wider than 8 bits are passed as
+/// little-endian 16-bit values.
+///
+/// # Safety
+/// All pointers must be valid. `pixels` must hold `width * height * components` samples.
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub unsafe extern "C" fn jpegexp_encode_jpegls_with_options(
+    pixels: *const c_uchar,
+    width: u32,
+    height: u32,
+    components: u32,
+    options: *const JpegExpEncodeOptions,
+    output: *mut c_uchar,
+    output_len: usize,
+    bytes_written: *mut usize,
+) -> c_int {
+    unsafe {
+        encode_with_options(
+            JpegExpFormat::Jpegls,
+            options,
+            pixels,
+            (width, height, components),
+            output,
+            output_len,
+            bytes_written,
+        )
     }
 }
 
-/// Encode raw pixels to JPEG-LS.
+/// Encode raw pixels to JPEG 2000 using the given options.
+///
+/// `options` may be null to use the defaults. Samples wider than 8 bits are passed as
+/// little-endian 16-bit values.
 ///
 /// # Safety
-/// All pointers must be valid.
+/// All pointers must be valid. `pixels` must hold `width * height * components` samples.
 #[unsafe(no_mangle)]
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
-pub unsafe extern "C" fn jpegexp_encode_jpegls(
+pub unsafe extern "C" fn jpegexp_encode_j2k_with_options(
     pixels: *const c_uchar,
     width: u32,
     height: u32,
     components: u32,
+    options: *const JpegExpEncodeOptions,
     output: *mut c_uchar,
     output_len: usize,
     bytes_written: *mut usize,
 ) -> c_int {
-    if pixels.is_null() || output.is_null() || bytes_written.is_null() {
-        return JpegExpError::InvalidData as c_int;
+    unsafe {
+        encode_with_options(
+            JpegExpFormat::J2k,
+            options,
+            pixels,
+            (width, height, components),
+            output,
+            output_len,
+            bytes_written,
+        )
     }
+}
 
-    let pixel_count = (width * height * components) as usize;
-    let pixels_slice = unsafe { std::slice::from_raw_parts(pixels, pixel_count) };
-    let output_slice = unsafe { std::slice::from_raw_parts_mut(output, output_len) };
+/// Encodes through a temporary encoder handle configured with `options`.
+///
+/// # Safety
+/// Same requirements as the `jpegexp_encode_*_with_options` functions.
+unsafe fn encode_with_options(
+    format: JpegExpFormat,
+    options: *const JpegExpEncodeOptions,
+    pixels: *const c_uchar,
+    (width, height, components): (u32, u32, u32),
+    output: *mut c_uchar,
+    output_len: usize,
+    bytes_written: *mut usize,
+) -> c_int {
+    let settings = match unsafe { encode_settings(options) } {
+        Ok(settings) => settings,
+        Err(code) => return code,
+    };
+    let mut state = EncoderState { format, settings };
+    unsafe {
+        jpegexp_encoder_encode(
+            &mut state as *mut EncoderState as *mut JpegExpEncoder,
+            pixels,
+            width,
+            height,
+            components,
+            output,
+            output_len,
+            bytes_written,
+        )
+    }
+}
 
-    let frame_info = crate::FrameInfo {
-        width,
-        height,
-        bits_per_sample: 8,
-        component_count: components as i32,
+/// Create a new encoder for the given format.
+///
+/// `format` is a `JpegExpFormat` value. The encoder starts with the default options.
+/// Returns null for an unknown format.
+#[unsafe(no_mangle)]
+pub extern "C" fn jpegexp_encoder_new(format: c_int) -> *mut JpegExpEncoder {
+    let format = match format {
+        0 => JpegExpFormat::Jpeg,
+        1 => JpegExpFormat::Jpegls,
+        2 => JpegExpFormat::J2k,
+        _ => {
+            fail(
+                JpegExpError::UnsupportedFormat,
+                format!("unknown format {format}"),
+            );
+            return ptr::null_mut();
+        }
     };
+    let state = Box::new(EncoderState {
+        format,
+        settings: EncodeSettings::default(),
+    });
 
-    let mut encoder = crate::jpegls::JpeglsEncoder::new(output_slice);
-    if encoder.set_frame_info(frame_info).is_err() {
-        return JpegExpError::InvalidData as c_int;
+    Box::into_raw(state) as *mut JpegExpEncoder
+}
+
+/// Free an encoder handle.
+///
+/// # Safety
+/// `encoder` must be a valid handle from `jpegexp_encoder_new`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn jpegexp_encoder_free(encoder: *mut JpegExpEncoder) {
+    if !encoder.is_null() {
+        let _ = unsafe { Box::from_raw(encoder as *mut EncoderState) };
     }
+}
 
-    match encoder.encode(pixels_slice) {
-        Ok(len) => {
-            unsafe { *bytes_written = len };
+/// Replace the options of an encoder. A null `options` restores the defaults.
+///
+/// # Safety
+/// `encoder` must be valid. `options` must be null or point to a valid struct.
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub unsafe extern "C" fn jpegexp_encoder_set_options(
+    encoder: *mut JpegExpEncoder,
+    options: *const JpegExpEncodeOptions,
+) -> c_int {
+    if encoder.is_null() {
+        return fail(JpegExpError::InvalidData, "encoder handle is null");
+    }
+
+    let state = unsafe { &mut *(encoder as *mut EncoderState) };
+    match unsafe { encode_settings(options) } {
+        Ok(settings) => {
+            state.settings = settings;
             JpegExpError::Ok as c_int
         }
-        Err(_) => JpegExpError::InternalError as c_int,
+        Err(code) => code,
     }
 }
 
-/// Encode raw pixels to JPEG 2000.
+/// Encode raw pixels with the format and options of `encoder`.
+///
+/// Samples wider than 8 bits are passed as little-endian 16-bit values.
 ///
 /// # Safety
-/// All pointers must be valid.
+/// All pointers must be valid. `pixels` must hold `width * height * components` samples.
 #[unsafe(no_mangle)]
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
-pub unsafe extern "C" fn jpegexp_encode_j2k(
+pub unsafe extern "C" fn jpegexp_encoder_encode(
+    encoder: *mut JpegExpEncoder,
     pixels: *const c_uchar,
     width: u32,
     height: u32,
     components: u32,
-    quality: u8,
     output: *mut c_uchar,
     output_len: usize,
     bytes_written: *mut usize,
 ) -> c_int {
-    if pixels.is_null() || output.is_null() || bytes_written.is_null() {
-        return JpegExpError::InvalidData as c_int;
+    if encoder.is_null() {
+        return fail(JpegExpError::InvalidData, "encoder handle is null");
     }
 
-    let pixel_count = (width * height * components) as usize;
-    let pixels_slice = unsafe { std::slice::from_raw_parts(pixels, pixel_count) };
-    let output_slice = unsafe { std::slice::from_raw_parts_mut(output, output_len) };
+    let state = unsafe { &*(encoder as *const EncoderState) };
+    let settings = &state.settings;
+    log::debug!(
+        "encoding {width}x{height}x{components} image as {:?}",
+        state.format
+    );
+    let frame_info = crate::FrameInfo {
+        width,
+        height,
+        bits_per_sample: settings.bits_per_sample,
+        component_count: components as i32,
+    };
+    let (pixels, output) =
+        match unsafe { encode_buffers(pixels, &frame_info, output, output_len, bytes_written) } {
+            Ok(buffers) => buffers,
+            Err(code) => return code,
+        };
+
+    let result = state
+        .codec_encoder()
+        .encode_into(pixels, &frame_info, output);
+    unsafe { finish_encode(result, bytes_written) }
+}
+
+/// Query the size in bytes of an output buffer that is large enough for
+/// `jpegexp_encoder_encode` to encode a `width` x `height` image of `components` components
+/// with the format and options of `encoder`, even when the pixels do not compress.
+///
+/// # Safety
+/// `encoder` must be valid. `size` must point to a writable `size_t`.
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub unsafe extern "C" fn jpegexp_encoder_get_estimated_size(
+    encoder: *const JpegExpEncoder,
+    width: u32,
+    height: u32,
+    components: u32,
+    size: *mut usize,
+) -> c_int {
+    if encoder.is_null() || size.is_null() {
+        return fail(
+            JpegExpError::InvalidData,
+            "encoder handle or size pointer is null",
+        );
+    }
 
+    let state = unsafe { &*(encoder as *const EncoderState) };
     let frame_info = crate::FrameInfo {
         width,
         height,
-        bits_per_sample: 8,
+        bits_per_sample: state.settings.bits_per_sample,
         component_count: components as i32,
     };
+    let estimated_size = state
+        .codec_encoder()
+        .estimated_destination_size(&frame_info);
+    unsafe { *size = estimated_size };
+    JpegExpError::Ok as c_int
+}
+
+/// Reorder the channels of 8-bit interleaved pixels, e.g. RGB to BGR with the order
+/// `{2, 1, 0}`.
+///
+/// `input` holds `len` bytes of pixels of `channels` samples each. Channel `c` of an
+/// output pixel is channel `order[c]` of the input pixel, so `output` must hold
+/// `len / channels * order_len` bytes.
+///
+/// # Safety
+/// `input`, `order` and `output` must be valid for `len`, `order_len` and `output_len`
+/// elements.
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub unsafe extern "C" fn jpegexp_swizzle(
+    input: *const c_uchar,
+    len: usize,
+    channels: u32,
+    order: *const u32,
+    order_len: u32,
+    output: *mut c_uchar,
+    output_len: usize,
+) -> c_int {
+    if input.is_null() || order.is_null() || output.is_null() {
+        return fail(
+            JpegExpError::InvalidData,
+            "input, order or output pointer is null",
+        );
+    }
+    let input = unsafe { std::slice::from_raw_parts(input, len) };
+    let order: Vec<usize> = unsafe { std::slice::from_raw_parts(order, order_len as usize) }
+        .iter()
+        .map(|&channel| channel as usize)
+        .collect();
+    let result = crate::pixelops::swizzle(input, channels as usize, &order);
+    unsafe { write_converted(result, output, output_len) }
+}
+
+/// Append a channel holding `alpha` to every 8-bit interleaved pixel, e.g. RGB to RGBA.
+///
+/// `input` holds `len` bytes of pixels of `channels` samples each; `output` must hold
+/// `len / channels * (channels + 1)` bytes.
+///
+/// # Safety
+/// `input` and `output` must be valid for `len` and `output_len` bytes.
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub unsafe extern "C" fn jpegexp_add_alpha(
+    input: *const c_uchar,
+    len: usize,
+    channels: u32,
+    alpha: c_uchar,
+    output: *mut c_uchar,
+    output_len: usize,
+) -> c_int {
+    if input.is_null() || output.is_null() {
+        return fail(JpegExpError::InvalidData, "input or output pointer is null");
+    }
+    let input = unsafe { std::slice::from_raw_parts(input, len) };
+    let result = crate::pixelops::add_alpha(input, channels as usize, alpha);
+    unsafe { write_converted(result, output, output_len) }
+}
+
+/// Map `count` 16-bit samples to 8 bits with the DICOM linear window of `width` values
+/// around `center`. A non-zero `is_signed` reads the samples as `int16_t`.
+///
+/// `output` must hold `count` bytes. Fails with `JPEG_EXP_ERROR_INVALID_DATA` if `width`
+/// is less than 1.
+///
+/// # Safety
+/// `input` must be valid for `count` samples and `output` for `output_len` bytes.
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub unsafe extern "C" fn jpegexp_window_level_16(
+    input: *const u16,
+    count: usize,
+    is_signed: c_int,
+    center: f64,
+    width: f64,
+    output: *mut c_uchar,
+    output_len: usize,
+) -> c_int {
+    if input.is_null() || output.is_null() {
+        return fail(JpegExpError::InvalidData, "input or output pointer is null");
+    }
+    let result = if is_signed != 0 {
+        let input = unsafe { std::slice::from_raw_parts(input as *const i16, count) };
+        crate::pixelops::window_level(input, center, width)
+    } else {
+        let input = unsafe { std::slice::from_raw_parts(input, count) };
+        crate::pixelops::window_level(input, center, width)
+    };
+    unsafe { write_converted(result, output, output_len) }
+}
+
+/// Swap the bytes of every 16-bit sample in `data` in place, e.g. to turn the
+/// little-endian output of `jpegexp_decoder_decode` into big-endian samples.
+///
+/// # Safety
+/// `data` must be valid for `len` bytes.
+#[unsafe(no_mangle)]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub unsafe extern "C" fn jpegexp_swap_bytes_16(data: *mut c_uchar, len: usize) -> c_int {
+    if data.is_null() {
+        return fail(JpegExpError::InvalidData, "data pointer is null");
+    }
+    let data = unsafe { std::slice::from_raw_parts_mut(data, len) };
+    match crate::pixelops::swap_bytes_16(data) {
+        Ok(()) => JpegExpError::Ok as c_int,
+        Err(e) => fail(JpegExpError::InvalidData, e),
+    }
+}
+
+/// Copies the result of a pixel conversion to `output`, or records the error.
+///
+/// # Safety
+/// `output` must be valid for `output_len` bytes.
+unsafe fn write_converted(
+    result: Result<Vec<u8>, crate::JpeglsError>,
+    output: *mut c_uchar,
+    output_len: usize,
+) -> c_int {
+    let converted = match result {
+        Ok(converted) => converted,
+        Err(e) => return fail(JpegExpError::InvalidData, e),
+    };
+    if output_len < converted.len() {
+        return fail(
+            JpegExpError::BufferTooSmall,
+            format!(
+                "output buffer holds {output_len} bytes, {} are required",
+                converted.len()
+            ),
+        );
+    }
+    unsafe { std::slice::from_raw_parts_mut(output, converted.len()) }.copy_from_slice(&converted);
+    JpegExpError::Ok as c_int
+}
+
+/// Encoder settings after validation of a `JpegExpEncodeOptions` struct.
+#[derive(Clone, Copy)]
+struct EncodeSettings {
+    bits_per_sample: i32,
+    quality: Option<u8>,
+    near_lossless: i32,
+    interleave_mode: crate::jpegls::InterleaveMode,
+}
+
+impl Default for EncodeSettings {
+    fn default() -> Self {
+        Self {
+            bits_per_sample: 8,
+            quality: None,
+            near_lossless: 0,
+            interleave_mode: crate::jpegls::InterleaveMode::None,
+        }
+    }
+}
+
+/// Checks the options passed to an encoder.
+///
+/// # Safety
+/// `options` must be null or point to a valid `JpegExpEncodeOptions`.
+unsafe fn encode_settings(options: *const JpegExpEncodeOptions) -> Result<EncodeSettings, c_int> {
+    if options.is_null() {
+        return Ok(EncodeSettings::default());
+    }
+    let options = unsafe { *options };
+
+    let quality = match options.quality {
+        0 => None,
+        1..=100 => Some(options.quality as u8),
+        q => {
+            return Err(fail(
+                JpegExpError::InvalidData,
+                format!("quality {q} is outside 1..=100"),
+            ))
+        }
+    };
+    let bits_per_sample = match options.bits_per_sample {
+        0 => 8,
+        2..=16 => options.bits_per_sample,
+        bits => {
+            return Err(fail(
+                JpegExpError::InvalidData,
+                format!("bits_per_sample {bits} is outside 2..=16"),
+            ))
+        }
+    };
+    let interleave_mode = match u8::try_from(options.interleave_mode)
+        .map_err(|_| crate::JpeglsError::InvalidArgument)
+        .and_then(crate::jpegls::InterleaveMode::try_from)
+    {
+        Ok(mode) => mode,
+        Err(_) => {
+            return Err(fail(
+                JpegExpError::InvalidData,
+                format!(
+                    "interleave_mode {} is not 0, 1 or 2",
+                    options.interleave_mode
+                ),
+            ))
+        }
+    };
 
-    let mut encoder = crate::jpeg2000::encoder::J2kEncoder::new();
-    encoder.set_quality(quality);
-    match encoder.encode(pixels_slice, &frame_info, output_slice) {
+    Ok(EncodeSettings {
+        bits_per_sample,
+        quality,
+        near_lossless: options.near_lossless,
+        interleave_mode,
+    })
+}
+
+/// Checks the buffer pointers of an encode call and borrows them as slices.
+///
+/// # Safety
+/// Non-null pointers must be valid; `pixels` must hold the samples described by `frame_info`.
+unsafe fn encode_buffers<'a>(
+    pixels: *const c_uchar,
+    frame_info: &crate::FrameInfo,
+    output: *mut c_uchar,
+    output_len: usize,
+    bytes_written: *mut usize,
+) -> Result<(&'a [u8], &'a mut [u8]), c_int> {
+    if pixels.is_null() || output.is_null() || bytes_written.is_null() {
+        return Err(fail(
+            JpegExpError::InvalidData,
+            "pixel, output or bytes_written pointer is null",
+        ));
+    }
+    let pixels = unsafe { std::slice::from_raw_parts(pixels, decoded_size(frame_info)?) };
+    let output = unsafe { std::slice::from_raw_parts_mut(output, output_len) };
+    Ok((pixels, output))
+}
+
+/// Stores the encoded length, or records the error and maps it to an error code.
+///
+/// # Safety
+/// `bytes_written` must be valid for writes.
+unsafe fn finish_encode(
+    result: Result<usize, crate::JpeglsError>,
+    bytes_written: *mut usize,
+) -> c_int {
+    match result {
         Ok(len) => {
             unsafe { *bytes_written = len };
             JpegExpError::Ok as c_int
         }
-        Err(_) => JpegExpError::InternalError as c_int,
+        Err(e @ crate::JpeglsError::DestinationTooSmall { .. }) => {
+            fail(JpegExpError::BufferTooSmall, e)
+        }
+        // Logic errors (100 and up) reject the frame or the options.
+        Err(e) if e.code() >= crate::JpeglsError::InvalidOperation.code() => {
+            fail(JpegExpError::InvalidData, e)
+        }
+        Err(e) => fail(JpegExpError::InternalError, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    #[test]
+    fn test_jpegls_options_round_trip_with_size_query() {
+        let (width, height) = (16u32, 8u32);
+        let pixels: Vec<u8> = (0..width * height * 3)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect();
+        let options = JpegExpEncodeOptions {
+            interleave_mode: 2,
+            ..JpegExpEncodeOptions::default()
+        };
+        let mut encoded = vec![0u8; 4096];
+        let mut written = 0usize;
+        let result = unsafe {
+            jpegexp_encode_jpegls_with_options(
+                pixels.as_ptr(),
+                width,
+                height,
+                3,
+                &options,
+                encoded.as_mut_ptr(),
+                encoded.len(),
+                &mut written,
+            )
+        };
+        assert_eq!(result, JpegExpError::Ok as c_int);
+
+        unsafe {
+            let decoder = jpegexp_decoder_new(encoded.as_ptr(), written);
+            let mut size = 0usize;
+            let result = jpegexp_decoder_get_decoded_size(decoder, &mut size);
+            assert_eq!(result, JpegExpError::InvalidData as c_int);
+
+            let mut info = JpegExpImageInfo {
+                width: 0,
+                height: 0,
+                components: 0,
+                bits_per_sample: 0,
+            };
+            jpegexp_decoder_read_header(decoder, &mut info);
+            let result = jpegexp_decoder_get_decoded_size(decoder, &mut size);
+            assert_eq!(result, JpegExpError::Ok as c_int);
+            assert_eq!(size, pixels.len());
+
+            let mut decoded = vec![0u8; size];
+            let result = jpegexp_decoder_decode(decoder, decoded.as_mut_ptr(), size - 1);
+            assert_eq!(result, JpegExpError::BufferTooSmall as c_int);
+            let message = CStr::from_ptr(jpegexp_get_last_error_message());
+            assert!(message.to_str().unwrap().contains("required"));
+
+            let result = jpegexp_decoder_decode(decoder, decoded.as_mut_ptr(), size);
+            assert_eq!(result, JpegExpError::Ok as c_int);
+            assert_eq!(decoded, pixels);
+            jpegexp_decoder_free(decoder);
+        }
+    }
+
+    #[test]
+    fn test_invalid_options_set_last_error_message() {
+        let pixels = [0u8; 64];
+        let options = JpegExpEncodeOptions {
+            interleave_mode: 7,
+            ..JpegExpEncodeOptions::default()
+        };
+        let mut encoded = [0u8; 1024];
+        let mut written = 0usize;
+        let result = unsafe {
+            jpegexp_encode_jpegls_with_options(
+                pixels.as_ptr(),
+                8,
+                8,
+                1,
+                &options,
+                encoded.as_mut_ptr(),
+                encoded.len(),
+                &mut written,
+            )
+        };
+        assert_eq!(result, JpegExpError::InvalidData as c_int);
+        let message = unsafe { CStr::from_ptr(jpegexp_get_last_error_message()) };
+        assert!(message.to_str().unwrap().contains("interleave_mode"));
+    }
+
+    #[test]
+    fn test_encoder_handle_lifecycle() {
+        assert!(jpegexp_encoder_new(9).is_null());
+
+        let pixels: Vec<u8> = (0..64u32).map(|i| (i * 3) as u8).collect();
+        let options = JpegExpEncodeOptions {
+            near_lossless: 2,
+            ..JpegExpEncodeOptions::default()
+        };
+        let mut encoded = Vec::new();
+        let mut written = 0usize;
+        unsafe {
+            let encoder = jpegexp_encoder_new(JpegExpFormat::Jpegls as c_int);
+            assert!(!encoder.is_null());
+            assert_eq!(jpegexp_encoder_set_options(encoder, &options), 0);
+            let mut size = 0usize;
+            let result = jpegexp_encoder_get_estimated_size(encoder, 8, 8, 1, &mut size);
+            assert_eq!(result, JpegExpError::Ok as c_int);
+            assert!(size >= pixels.len());
+            encoded.resize(size, 0);
+            let result = jpegexp_encoder_encode(
+                encoder,
+                pixels.as_ptr(),
+                8,
+                8,
+                1,
+                encoded.as_mut_ptr(),
+                encoded.len(),
+                &mut written,
+            );
+            assert_eq!(result, JpegExpError::Ok as c_int);
+            jpegexp_encoder_free(encoder);
+        }
+
+        let mut decoder = crate::jpegls::JpeglsDecoder::new(&encoded[..written]);
+        decoder.read_header().unwrap();
+        let mut decoded = vec![0u8; pixels.len()];
+        decoder.decode(&mut decoded).unwrap();
+        for (&a, &b) in decoded.iter().zip(&pixels) {
+            assert!(a.abs_diff(b) <= 2);
+        }
+    }
+
+    #[test]
+    fn test_log_callback_receives_error_messages() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static MESSAGES: AtomicUsize = AtomicUsize::new(0);
+        unsafe extern "C" fn count(level: c_int, message: *const c_char, _: *mut c_void) {
+            let message = unsafe { CStr::from_ptr(message) };
+            if level == JpegExpLogLevel::Debug as c_int
+                && message.to_bytes().starts_with(b"unknown format")
+            {
+                MESSAGES.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        assert_eq!(jpegexp_set_log_callback(Some(count), 6, ptr::null_mut()), 1);
+        let level = JpegExpLogLevel::Debug as c_int;
+        assert_eq!(
+            jpegexp_set_log_callback(Some(count), level, ptr::null_mut()),
+            0
+        );
+        assert!(jpegexp_encoder_new(42).is_null());
+        assert_eq!(MESSAGES.load(Ordering::SeqCst), 1);
+
+        assert_eq!(jpegexp_set_log_callback(None, level, ptr::null_mut()), 0);
+        assert!(jpegexp_encoder_new(42).is_null());
+        assert_eq!(MESSAGES.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_stream_info() {
+        let frame_info = crate::FrameInfo {
+            width: 16,
+            height: 16,
+            bits_per_sample: 8,
+            component_count: 1,
+        };
+        let pixels: Vec<u8> = (0..256u32).map(|i| (i * 5 % 251) as u8).collect();
+        let stream_info = |encoder: crate::codec::Encoder| {
+            let encoded = encoder.encode(&pixels, &frame_info).unwrap();
+            let mut info = JpegExpStreamInfo::default();
+            unsafe {
+                let decoder = jpegexp_decoder_new(encoded.as_ptr(), encoded.len());
+                let result = jpegexp_decoder_get_stream_info(decoder, &mut info);
+                assert_eq!(result, JpegExpError::InvalidData as c_int);
+                jpegexp_decoder_read_header(decoder, ptr::null_mut());
+                let result = jpegexp_decoder_get_stream_info(decoder, &mut info);
+                assert_eq!(result, JpegExpError::Ok as c_int);
+                jpegexp_decoder_free(decoder);
+            }
+            (
+                info.format,
+                info.lossless,
+                info.near_lossless,
+                info.precision,
+            )
+        };
+
+        let encoder = crate::codec::Encoder::for_format(crate::codec::Format::Jpeg1);
+        assert_eq!(
+            stream_info(encoder),
+            (JpegExpFormat::Jpeg as c_int, 0, 0, 8)
+        );
+
+        let mut encoder = crate::codec::Encoder::for_format(crate::codec::Format::Jpegls);
+        assert_eq!(
+            stream_info(encoder),
+            (JpegExpFormat::Jpegls as c_int, 1, 0, 8)
+        );
+        encoder.set_near_lossless(3);
+        assert_eq!(
+            stream_info(encoder),
+            (JpegExpFormat::Jpegls as c_int, 0, 3, 8)
+        );
+
+        let mut encoder = crate::codec::Encoder::for_format(crate::codec::Format::Jpeg2000);
+        assert_eq!(stream_info(encoder), (JpegExpFormat::J2k as c_int, 0, 0, 8));
+        encoder.set_irreversible(false);
+        assert_eq!(stream_info(encoder), (JpegExpFormat::J2k as c_int, 1, 0, 8));
+    }
+
+    #[test]
+    fn test_component_info() {
+        let frame_info = crate::FrameInfo {
+            width: 8,
+            height: 8,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let pixels = vec![60u8; 8 * 8 * 3];
+        let encoded = crate::codec::Encoder::for_format(crate::codec::Format::Jpeg2000)
+            .encode(&pixels, &frame_info)
+            .unwrap();
+        let mut info = JpegExpComponentInfo::default();
+        unsafe {
+            let decoder = jpegexp_decoder_new(encoded.as_ptr(), encoded.len());
+            let result = jpegexp_decoder_get_component_info(decoder, 0, &mut info);
+            assert_eq!(result, JpegExpError::InvalidData as c_int);
+            jpegexp_decoder_read_header(decoder, ptr::null_mut());
+            let result = jpegexp_decoder_get_component_info(decoder, 2, &mut info);
+            assert_eq!(result, JpegExpError::Ok as c_int);
+            let result = jpegexp_decoder_get_component_info(decoder, 3, &mut info);
+            assert_eq!(result, JpegExpError::InvalidData as c_int);
+            jpegexp_decoder_free(decoder);
+        }
+        assert_eq!(
+            info,
+            JpegExpComponentInfo {
+                bits_per_sample: 8,
+                is_signed: 0,
+                horizontal_subsampling: 1,
+                vertical_subsampling: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_pixel_conversions() {
+        let rgb = [1u8, 2, 3, 4, 5, 6];
+        let mut bgra = [0u8; 8];
+        unsafe {
+            let result = jpegexp_swizzle(
+                rgb.as_ptr(),
+                6,
+                3,
+                [2, 1, 0].as_ptr(),
+                3,
+                bgra.as_mut_ptr(),
+                6,
+            );
+            assert_eq!(result, JpegExpError::Ok as c_int);
+            assert_eq!(bgra[..6], [3, 2, 1, 6, 5, 4]);
+
+            let bgr = bgra;
+            let result = jpegexp_add_alpha(bgr.as_ptr(), 6, 3, 255, bgra.as_mut_ptr(), 7);
+            assert_eq!(result, JpegExpError::BufferTooSmall as c_int);
+            let result = jpegexp_add_alpha(bgr.as_ptr(), 6, 3, 255, bgra.as_mut_ptr(), 8);
+            assert_eq!(result, JpegExpError::Ok as c_int);
+            assert_eq!(bgra, [3, 2, 1, 255, 6, 5, 4, 255]);
+
+            let samples = [-1000i16, 40, 3000];
+            let mut windowed = [0u8; 3];
+            let result = jpegexp_window_level_16(
+                samples.as_ptr() as *const u16,
+                3,
+                1,
+                40.0,
+                400.0,
+                windowed.as_mut_ptr(),
+                3,
+            );
+            assert_eq!(result, JpegExpError::Ok as c_int);
+            assert_eq!(windowed, [0, 128, 255]);
+
+            let mut bytes = [1u8, 2, 3];
+            let result = jpegexp_swap_bytes_16(bytes.as_mut_ptr(), 3);
+            assert_eq!(result, JpegExpError::InvalidData as c_int);
+            let result = jpegexp_swap_bytes_16(bytes.as_mut_ptr(), 2);
+            assert_eq!(result, JpegExpError::Ok as c_int);
+            assert_eq!(bytes, [2, 1, 3]);
+        }
     }
 }
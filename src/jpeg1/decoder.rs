@@ -1,18 +1,126 @@
 //! JPEG 1 Baseline and Progressive Decoder implementation.
 
-use crate::error::JpeglsError;
+use crate::constants::AUTO_CALCULATE_STRIDE;
+use crate::error::{JpeglsError, Truncation};
+use crate::jpeg1::huffman::{BitPosition, HuffmanEncoder, JpegBitReader};
+use crate::jpeg1::quantization::{dequantize_block, estimate_quality, to_natural_order};
 use crate::jpeg_stream_reader::JpegStreamReader;
-use crate::jpeg1::huffman::{HuffmanEncoder, JpegBitReader};
-use crate::jpeg1::quantization::dequantize_block;
+use crate::jpegls::sample_layout::SampleLayout;
+use crate::mem_profiling::{track_elements, DecodeStats, Session};
+use crate::{ColorConversion, ComponentInfo, OutputLayout};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// Quantized DCT coefficients of a DCT-based JPEG image, as stored in the entropy-coded
+/// data (before dequantization and the inverse DCT).
+#[derive(Debug, Clone)]
+pub struct DctCoefficients {
+    pub width: u32,
+    pub height: u32,
+    pub components: Vec<ComponentCoefficients>,
+}
+
+/// Coefficients of one component. The block grid covers whole MCUs, so it can extend past
+/// the right and bottom edges of the image.
+#[derive(Debug, Clone)]
+pub struct ComponentCoefficients {
+    /// Component identifier from the SOF segment.
+    pub id: u8,
+    pub h_samp_factor: u8,
+    pub v_samp_factor: u8,
+    /// Quantization table selector (0-3) and the table as stored in the DQT segment.
+    pub quantization_table_id: u8,
+    pub quantization_table: [u8; 64],
+    pub blocks_wide: usize,
+    pub blocks_high: usize,
+    /// 64 coefficients per block in natural (row-major) order, blocks in raster order.
+    pub coefficients: Vec<i16>,
+}
+
+/// Color space of the components of a DCT-based JPEG 1 image, which decides how
+/// [`Jpeg1Decoder`] converts them to output samples. It is determined the way libjpeg
+/// does: from the JFIF (APP0) and Adobe (APP14) segments and, without either, from the
+/// component identifiers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// One component, decoded as is.
+    Grayscale,
+    /// Y, Cb and Cr, decoded to R, G and B.
+    YCbCr,
+    /// R, G and B, decoded as is.
+    Rgb,
+    /// C, M, Y and K, decoded as is. Adobe applications store inverted samples, with 0
+    /// being full ink.
+    Cmyk,
+    /// Y, Cb, Cr and K, decoded to C, M, Y and K in the same convention as [`Cmyk`](Self::Cmyk).
+    Ycck,
+    /// Any other number of components, decoded as is.
+    Unknown,
+}
 
 pub struct Jpeg1Decoder<'a> {
     reader: JpegStreamReader<'a>,
+    output_layout: OutputLayout,
+    color_conversion: ColorConversion,
+    scale_numerator: u32,
+    crop: Option<(u32, u32, u32, u32)>,
+    thread_count: usize,
+    tolerant: bool,
+    truncation: Option<Truncation>,
+    stats: DecodeStats,
+    rows: Option<RowState>,
+}
+
+/// Blocks of one restart interval, with the component and coefficient offset of each.
+type IntervalBlocks = Vec<(usize, usize, [i16; 64])>;
+
+/// Where [`Jpeg1Decoder::decode_rows`] goes on.
+struct RowState {
+    /// Next row of the decoded rectangle.
+    next_row: usize,
+    /// Coefficients of every component from MCU row `first_mcu_row` on.
+    coefficients: Vec<Vec<i16>>,
+    first_mcu_row: usize,
+    /// The entropy-coded data of a sequential frame coded in one scan, or `None` when
+    /// `coefficients` hold the whole frame.
+    scan: Option<ScanPosition>,
+}
+
+/// How far the entropy-coded data of a sequential scan has been read.
+struct ScanPosition {
+    bits: BitPosition,
+    dc_preds: Vec<i16>,
+    /// Next MCU to decode, or block in a scan of one component.
+    unit: usize,
+    /// A tolerant decode stopped at damaged data; the rest of the frame stays mid-grey.
+    ended: bool,
+}
+
+impl Jpeg1Decoder<'static> {
+    /// Creates a decoder that owns `source`, e.g. to move it to another thread or into an
+    /// async task.
+    pub fn from_vec(source: Vec<u8>) -> Self {
+        Self::with_reader(JpegStreamReader::from_vec(source))
+    }
 }
 
 impl<'a> Jpeg1Decoder<'a> {
     pub fn new(source: &'a [u8]) -> Self {
+        Self::with_reader(JpegStreamReader::new(source))
+    }
+
+    fn with_reader(reader: JpegStreamReader<'a>) -> Self {
         Self {
-            reader: JpegStreamReader::new(source),
+            reader,
+            output_layout: OutputLayout::Interleaved,
+            color_conversion: ColorConversion::None,
+            scale_numerator: 8,
+            crop: None,
+            thread_count: 1,
+            tolerant: false,
+            truncation: None,
+            stats: DecodeStats::default(),
+            rows: None,
         }
     }
 
@@ -21,28 +129,717 @@ impl<'a> Jpeg1Decoder<'a> {
         self.reader.read_header(&mut spiff)
     }
 
+    /// Arrangement of the components in the decoded image. Defaults to
+    /// [`OutputLayout::Interleaved`]; with [`OutputLayout::Planar`] every component is
+    /// written as a plane of `height` rows, each `stride` bytes apart.
+    pub fn set_output_layout(&mut self, output_layout: OutputLayout) {
+        self.output_layout = output_layout;
+    }
+
+    /// Conversion of the decoded components. Defaults to [`ColorConversion::None`]; with
+    /// [`ColorConversion::ToGray`], three-component images decode to one channel per pixel
+    /// (see [`output_component_count`](Self::output_component_count)).
+    pub fn set_color_conversion(&mut self, color_conversion: ColorConversion) {
+        self.color_conversion = color_conversion;
+    }
+
+    /// Number of channels [`decode`](Self::decode) writes per pixel. Call after
+    /// [`read_header`](Self::read_header).
+    pub fn output_component_count(&self) -> usize {
+        if self.converts_to_gray() {
+            1
+        } else {
+            self.reader.components.len()
+        }
+    }
+
+    /// Precision and subsampling of the components of the frame, before color conversion
+    /// and upsampling. Call after [`read_header`](Self::read_header).
+    pub fn component_info(&self) -> Vec<ComponentInfo> {
+        self.reader.component_info()
+    }
+
+    /// Decodes DCT-based frames at `numerator`/8 of their width and height, as libjpeg's
+    /// `scale_num`/`scale_denom`: every block is reconstructed to `numerator` x `numerator`
+    /// samples from its lowest frequencies, so 4 (1/2), 2 (1/4) and 1 (1/8, the DC
+    /// coefficient only) skip most of the inverse DCT work. The default 8 decodes at full
+    /// size. Lossless frames are always decoded at full size (see
+    /// [`output_size`](Self::output_size)).
+    ///
+    /// Returns [`JpeglsError::InvalidArgument`] unless `numerator` is 1 to 8.
+    pub fn set_output_scaling(&mut self, numerator: u32) -> Result<(), JpeglsError> {
+        if !(1..=8).contains(&numerator) {
+            return Err(JpeglsError::InvalidArgument);
+        }
+        self.scale_numerator = numerator;
+        Ok(())
+    }
+
+    /// Decodes only the `width` x `height` rectangle whose top left pixel is at (`x`, `y`),
+    /// e.g. to serve deep-zoom tiles from a large scan. The rectangle is in output pixels,
+    /// after the [output scaling](Self::set_output_scaling). The entropy-coded data is read
+    /// up to the last MCU row that overlaps the rectangle and only the blocks that overlap
+    /// it are transformed back; lossless frames are decoded in full and then cropped.
+    ///
+    /// [`decode`](Self::decode) returns [`JpeglsError::InvalidArgumentWidth`] or
+    /// [`JpeglsError::InvalidArgumentHeight`] if the rectangle does not lie within the
+    /// image.
+    pub fn set_crop(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        self.crop = Some((x, y, width, height));
+    }
+
+    /// Width and height of the image [`decode`](Self::decode) writes: the
+    /// [crop](Self::set_crop) rectangle, or else the frame size scaled by the
+    /// [output scaling](Self::set_output_scaling) and rounded up. Call after
+    /// [`read_header`](Self::read_header).
+    pub fn output_size(&self) -> (u32, u32) {
+        match self.crop {
+            Some((_, _, width, height)) => (width, height),
+            None => self.scaled_size(),
+        }
+    }
+
+    /// Size of the whole image at the output scaling.
+    fn scaled_size(&self) -> (u32, u32) {
+        let frame_info = self.reader.frame_info();
+        let numerator = self.scaled_block_size() as u64;
+        let scale = |v: u32| (v as u64 * numerator).div_ceil(8) as u32;
+        (scale(frame_info.width), scale(frame_info.height))
+    }
+
+    /// Left, top, width and height of the decoded rectangle of the scaled image, which is
+    /// checked to lie within it.
+    fn output_region(&self) -> Result<(usize, usize, usize, usize), JpeglsError> {
+        let (image_width, image_height) = self.scaled_size();
+        let (x, y, width, height) = self.crop.unwrap_or((0, 0, image_width, image_height));
+        if x.checked_add(width).is_none_or(|right| right > image_width) {
+            return Err(JpeglsError::InvalidArgumentWidth);
+        }
+        if y.checked_add(height)
+            .is_none_or(|bottom| bottom > image_height)
+        {
+            return Err(JpeglsError::InvalidArgumentHeight);
+        }
+        Ok((x as usize, y as usize, width as usize, height as usize))
+    }
+
+    /// Width and height of a block after the (scaled) inverse DCT.
+    fn scaled_block_size(&self) -> usize {
+        if self.reader.is_lossless {
+            8
+        } else {
+            self.scale_numerator as usize
+        }
+    }
+
+    fn converts_to_gray(&self) -> bool {
+        self.color_conversion == ColorConversion::ToGray && self.reader.components.len() == 3
+    }
+
+    /// Decodes the restart intervals of baseline scans on up to `thread_count` threads; the
+    /// DC predictions restart at every RSTn marker, so the intervals are independent. 0
+    /// uses [`std::thread::available_parallelism`] threads; 1, the default, decodes on the
+    /// calling thread. Scans without restart markers and progressive scans are always
+    /// decoded on the calling thread.
+    pub fn set_thread_count(&mut self, thread_count: usize) {
+        self.thread_count = thread_count;
+    }
+
+    /// Decodes truncated and damaged streams as far as the data goes instead of failing:
+    /// the blocks (or, for lossless frames, the samples) that could not be read come out
+    /// mid-grey and [`truncation`](Self::truncation) reports where decoding stopped.
+    /// Errors in the frame header still fail the decode. Defaults to `false`.
+    pub fn set_tolerant(&mut self, tolerant: bool) {
+        self.tolerant = tolerant;
+    }
+
+    /// Where the most recent tolerant [`decode`](Self::decode) stopped, or `None` if the
+    /// whole frame was read.
+    pub fn truncation(&self) -> Option<Truncation> {
+        self.truncation
+    }
+
+    /// Registers a handler that receives the data of every COM segment found while reading
+    /// the header and decoding. Returning an error from the handler aborts with that error.
+    /// Handlers must be `Send + Sync` so that the decoder stays so too.
+    pub fn at_comment(
+        &mut self,
+        handler: impl FnMut(&[u8]) -> Result<(), JpeglsError> + Send + Sync + 'a,
+    ) {
+        self.reader.set_comment_handler(handler);
+    }
+
+    /// Registers a handler that receives the APPn number (0 to 15) and the data of every
+    /// application data segment.
+    pub fn at_application_data(
+        &mut self,
+        handler: impl FnMut(u8, &[u8]) -> Result<(), JpeglsError> + Send + Sync + 'a,
+    ) {
+        self.reader.set_application_data_handler(handler);
+    }
+
+    /// Color space of the frame, which also gives the number of output channels. Call after
+    /// [`read_header`](Self::read_header). Lossless frames are always decoded as is.
+    pub fn color_space(&self) -> ColorSpace {
+        let components = &self.reader.components;
+        match components.len() {
+            1 => ColorSpace::Grayscale,
+            3 => {
+                let transform = self.reader.adobe_transform;
+                let ids: Vec<u8> = components.iter().map(|component| component.id).collect();
+                if self.reader.jfif {
+                    ColorSpace::YCbCr
+                } else if transform == Some(0) || (transform.is_none() && ids == b"RGB") {
+                    ColorSpace::Rgb
+                } else {
+                    ColorSpace::YCbCr
+                }
+            }
+            4 if self.reader.adobe_transform == Some(2) => ColorSpace::Ycck,
+            4 => ColorSpace::Cmyk,
+            _ => ColorSpace::Unknown,
+        }
+    }
+
+    /// Quantization tables used by the components of the frame, as pairs of the table
+    /// selector (0-3) and the table as stored in the DQT segment, in selector order. Call
+    /// after [`read_header`](Self::read_header). Lossless frames have none.
+    pub fn quantization_tables(&self) -> Vec<(u8, [u8; 64])> {
+        if self.reader.is_lossless {
+            return Vec::new();
+        }
+        let mut ids: Vec<u8> = self
+            .reader
+            .components
+            .iter()
+            .map(|component| component.quant_table_dest & 3)
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids.into_iter()
+            .map(|id| (id, self.reader.quantization_tables[id as usize]))
+            .collect()
+    }
+
+    /// Estimated quality (1-100) of the encoder that made the quantization tables (see
+    /// [`estimate_quality`](crate::jpeg1::quantization::estimate_quality)), e.g. to spot
+    /// images that were recompressed at a lower quality. The table of the first component
+    /// is taken as the luminance table and, for YCbCr and YCCK images, the table of the
+    /// second component as the chrominance table. Call after
+    /// [`read_header`](Self::read_header). Returns `None` for lossless frames.
+    pub fn estimate_quality(&self) -> Option<u32> {
+        let components = &self.reader.components;
+        if self.reader.is_lossless || components.is_empty() {
+            return None;
+        }
+        let table = |index: usize| {
+            &self.reader.quantization_tables[components[index].quant_table_dest as usize & 3]
+        };
+        let chrominance = match self.color_space() {
+            ColorSpace::YCbCr | ColorSpace::Ycck => Some(table(1)),
+            _ => None,
+        };
+        Some(estimate_quality(table(0), chrominance))
+    }
+
+    /// Memory statistics of the most recent call to [`decode`](Self::decode).
+    pub fn stats(&self) -> DecodeStats {
+        self.stats
+    }
+
     pub fn decode(&mut self, destination: &mut [u8]) -> Result<(), JpeglsError> {
+        self.decode_with_stride(destination, AUTO_CALCULATE_STRIDE)
+    }
+
+    /// Like [`decode`](Self::decode), for destination rows that are `stride` bytes apart.
+    /// [`AUTO_CALCULATE_STRIDE`] selects tightly packed rows; row padding is left untouched.
+    pub fn decode_with_stride(
+        &mut self,
+        destination: &mut [u8],
+        stride: usize,
+    ) -> Result<(), JpeglsError> {
+        let session = Session::begin();
+        self.truncation = None;
+        let result = self
+            .destination_layout(destination, stride)
+            .and_then(|layout| self.decode_frame(destination, layout));
+        let frame_info = self.reader.frame_info();
+        self.stats = DecodeStats {
+            peak_buffer_bytes: session.finish(),
+            pixel_count: frame_info.width as u64 * frame_info.height as u64,
+        };
+        result
+    }
+
+    /// Decodes the next `rows` rows of the image, going on where the previous call stopped,
+    /// e.g. to convert a large scan strip by strip. A sequential frame coded in one scan is
+    /// read only as far as each strip needs and only the coefficients of the MCU row a
+    /// strip shares with the next one are kept, so memory follows the strip size. The scans
+    /// of progressive frames, and of frames coded one component per scan, each cover the
+    /// whole image: their coefficients are all read by the first call and held until the
+    /// decoder is dropped. The rows are laid out as by [`decode`](Self::decode), which this
+    /// replaces; set the options, the [crop](Self::set_crop) included, before the first
+    /// call.
+    ///
+    /// Returns [`JpeglsError::InvalidArgumentHeight`] if fewer than `rows` rows are left and
+    /// [`JpeglsError::EncodingNotSupported`] for lossless frames.
+    pub fn decode_rows(&mut self, destination: &mut [u8], rows: u32) -> Result<(), JpeglsError> {
+        if self.reader.is_lossless {
+            return Err(JpeglsError::EncodingNotSupported);
+        }
+        let (left, top, width, height) = self.output_region()?;
+        let rows = rows as usize;
+        let next_row = self.rows.as_ref().map_or(0, |state| state.next_row);
+        if rows > height - next_row {
+            return Err(JpeglsError::InvalidArgumentHeight);
+        }
+        let layout = self.rows_layout(destination, AUTO_CALCULATE_STRIDE, rows)?;
+        let mut state = match self.rows.take() {
+            Some(state) => state,
+            None => self.start_rows()?,
+        };
+
+        let (_, max_v_samp, _, mcus_h) = self.mcu_layout();
+        let mcu_height = max_v_samp * self.scaled_block_size();
+        let (first, end) = (top + next_row, top + next_row + rows);
+        let RowState {
+            coefficients,
+            first_mcu_row,
+            scan,
+            ..
+        } = &mut state;
+        let read = match scan {
+            Some(scan) => self.read_mcu_rows(
+                scan,
+                coefficients,
+                first_mcu_row,
+                first / mcu_height,
+                end.div_ceil(mcu_height).min(mcus_h),
+            ),
+            None => Ok(()),
+        };
+        let result = read.and_then(|()| {
+            self.reconstruct(
+                &state.coefficients,
+                state.first_mcu_row,
+                destination,
+                layout,
+                (left, first, width, rows),
+            )
+        });
+        if result.is_ok() {
+            state.next_row += rows;
+        }
+        self.rows = Some(state);
+        result
+    }
+
+    /// Starts [`decode_rows`](Self::decode_rows) at the first scan. Unless that is a
+    /// sequential scan of every component, the coefficients of all scans are read.
+    fn start_rows(&mut self) -> Result<RowState, JpeglsError> {
+        let start = self.reader.position();
+        let components = self.reader.components.len();
+        if !self.reader.is_progressive
+            && matches!(self.read_next_scan_header(), Ok(true))
+            && self.reader.scan_component_indices.len() == components
+        {
+            return Ok(RowState {
+                next_row: 0,
+                coefficients: vec![Vec::new(); components],
+                first_mcu_row: 0,
+                scan: Some(ScanPosition {
+                    bits: BitPosition::default(),
+                    dc_preds: vec![0; components],
+                    unit: 0,
+                    ended: false,
+                }),
+            });
+        }
+        self.reader.seek(start);
+        let (_, _, _, mcus_h) = self.mcu_layout();
+        Ok(RowState {
+            next_row: 0,
+            coefficients: self.decode_coefficient_blocks(mcus_h)?,
+            first_mcu_row: 0,
+            scan: None,
+        })
+    }
+
+    /// Reads the sequential scan up to MCU row `end`, keeping the coefficients of the rows
+    /// from `first` on in `coefficients`, which start at row `first_mcu_row`.
+    fn read_mcu_rows(
+        &mut self,
+        scan: &mut ScanPosition,
+        coefficients: &mut [Vec<i16>],
+        first_mcu_row: &mut usize,
+        first: usize,
+        end: usize,
+    ) -> Result<(), JpeglsError> {
+        let (_, max_v_samp, mcus_w, mcus_h) = self.mcu_layout();
+        let mcu_height = max_v_samp * self.scaled_block_size();
+        let scan_components = self.reader.scan_component_indices.clone();
+        let units_per_row = self.scan_unit_count(&scan_components) / mcus_h.max(1);
+        let row_len: Vec<usize> = (self.reader.components.iter())
+            .map(|comp| mcus_w * comp.h_samp_factor as usize * comp.v_samp_factor as usize * 64)
+            .collect();
+        let restart_interval = self.reader.restart_interval as usize;
+        // Drops the rows above `below` that are kept.
+        let drop_rows = |coefficients: &mut [Vec<i16>], first_mcu_row: &mut usize, below: usize| {
+            let count = below.saturating_sub(*first_mcu_row);
+            for (buffer, &len) in coefficients.iter_mut().zip(&row_len) {
+                buffer.drain(..(count * len).min(buffer.len()));
+            }
+            *first_mcu_row += count;
+        };
+
+        let mut blocks = Vec::new();
+        while scan.unit / units_per_row.max(1) < end {
+            let row = scan.unit / units_per_row;
+            drop_rows(coefficients, first_mcu_row, first.min(row));
+            let first_block_row = *first_mcu_row;
+            for (buffer, &len) in coefficients.iter_mut().zip(&row_len) {
+                buffer.resize(buffer.len() + len, 0);
+            }
+            if !scan.ended {
+                let mut bit_reader = JpegBitReader::resume(self.reader.remaining_data(), scan.bits);
+                let mut dc_preds = scan.dc_preds.clone();
+                let decoded = (scan.unit..scan.unit + units_per_row).try_for_each(|unit| {
+                    if restart_interval > 0 && unit > 0 && unit % restart_interval == 0 {
+                        bit_reader.read_marker_code()?;
+                        dc_preds.fill(0);
+                    }
+                    self.unit_blocks(&scan_components, unit, &mut blocks);
+                    for &(comp_idx, block_offset) in &blocks {
+                        let offset = block_offset - first_block_row * row_len[comp_idx];
+                        Self::decode_block_internal(
+                            &mut bit_reader,
+                            self,
+                            &mut dc_preds[comp_idx],
+                            &mut coefficients[comp_idx][offset..offset + 64],
+                            comp_idx,
+                        )?;
+                    }
+                    Ok(())
+                });
+                match decoded {
+                    Ok(()) => {
+                        scan.bits = bit_reader.bit_position();
+                        scan.dc_preds = dc_preds;
+                    }
+                    Err(error) if self.tolerant => {
+                        let offset = self.reader.position() + bit_reader.position();
+                        self.record_truncation(offset, &[row * mcu_height], error)?;
+                        scan.ended = true;
+                    }
+                    Err(error) => {
+                        // The row is read again by the next call.
+                        for (buffer, &len) in coefficients.iter_mut().zip(&row_len) {
+                            buffer.truncate(buffer.len() - len);
+                        }
+                        return Err(error);
+                    }
+                }
+            }
+            scan.unit += units_per_row;
+        }
+        let rows_read = scan.unit / units_per_row.max(1);
+        drop_rows(coefficients, first_mcu_row, first.min(rows_read));
+        Ok(())
+    }
+
+    /// Decodes the quantized DCT coefficients without reconstructing pixels, e.g. to
+    /// re-encode an image without generation loss. Call after
+    /// [`read_header`](Self::read_header). Lossless (SOF3) frames have no coefficients and
+    /// fail with [`JpeglsError::EncodingNotSupported`].
+    pub fn read_coefficients(&mut self) -> Result<DctCoefficients, JpeglsError> {
         if self.reader.is_lossless {
-            return self.decode_lossless(destination);
+            return Err(JpeglsError::EncodingNotSupported);
         }
+        let (_, _, mcus_w, mcus_h) = self.mcu_layout();
+        let blocks = self.decode_coefficient_blocks(mcus_h)?;
+        let frame_info = self.reader.frame_info();
+        let components = self
+            .reader
+            .components
+            .iter()
+            .zip(blocks)
+            .map(|(comp, coefficients)| ComponentCoefficients {
+                id: comp.id,
+                h_samp_factor: comp.h_samp_factor,
+                v_samp_factor: comp.v_samp_factor,
+                quantization_table_id: comp.quant_table_dest,
+                quantization_table: self.reader.quantization_tables
+                    [comp.quant_table_dest as usize & 3],
+                blocks_wide: mcus_w * comp.h_samp_factor as usize,
+                blocks_high: mcus_h * comp.v_samp_factor as usize,
+                coefficients,
+            })
+            .collect();
+        Ok(DctCoefficients {
+            width: frame_info.width,
+            height: frame_info.height,
+            components,
+        })
+    }
 
+    /// Maximum sampling factors and the number of MCUs across and down the image.
+    fn mcu_layout(&self) -> (usize, usize, usize, usize) {
         let frame_info = self.reader.frame_info();
-        let width = frame_info.width as usize;
-        let height = frame_info.height as usize;
+        let components = &self.reader.components;
+        let max_h_samp = components
+            .iter()
+            .map(|c| c.h_samp_factor as usize)
+            .max()
+            .unwrap_or(1);
+        let max_v_samp = components
+            .iter()
+            .map(|c| c.v_samp_factor as usize)
+            .max()
+            .unwrap_or(1);
+        let mcus_w = (frame_info.width as usize).div_ceil(max_h_samp * 8);
+        let mcus_h = (frame_info.height as usize).div_ceil(max_v_samp * 8);
+        (max_h_samp, max_v_samp, mcus_w, mcus_h)
+    }
+
+    /// Resolves where every decoded sample is stored and checks that `destination` can hold
+    /// the whole image.
+    fn destination_layout(
+        &self,
+        destination: &[u8],
+        stride: usize,
+    ) -> Result<SampleLayout, JpeglsError> {
+        let (_, _, _, height) = self.output_region()?;
+        self.rows_layout(destination, stride, height)
+    }
+
+    /// Like [`destination_layout`](Self::destination_layout), for `height` rows of the
+    /// image.
+    fn rows_layout(
+        &self,
+        destination: &[u8],
+        stride: usize,
+        height: usize,
+    ) -> Result<SampleLayout, JpeglsError> {
+        let frame_info = self.reader.frame_info();
+        // The row arithmetic below assumes the image size fits in usize.
+        frame_info.decoded_size(self.output_layout)?;
+        let components = self.output_component_count();
+        let (_, _, width, _) = self.output_region()?;
+        let (pixel_components, row_count) = match self.output_layout {
+            OutputLayout::Interleaved => (components, height),
+            OutputLayout::Planar => (1, height * components),
+        };
+        let row_bytes = width * pixel_components;
+        let stride = if stride == AUTO_CALCULATE_STRIDE {
+            row_bytes
+        } else {
+            stride
+        };
+        if stride < row_bytes {
+            return Err(JpeglsError::InvalidArgumentStride);
+        }
+        let needed = match row_count {
+            0 => 0,
+            _ => (row_count - 1) * stride + row_bytes,
+        };
+        if destination.len() < needed {
+            return Err(JpeglsError::DestinationTooSmall { needed });
+        }
+        Ok(match self.output_layout {
+            OutputLayout::Interleaved => SampleLayout::interleaved(components, stride),
+            OutputLayout::Planar => SampleLayout::planar_with_stride(stride, height),
+        })
+    }
+
+    fn decode_frame(
+        &mut self,
+        destination: &mut [u8],
+        layout: SampleLayout,
+    ) -> Result<(), JpeglsError> {
+        let region = self.output_region()?;
+        let (_, top, width, height) = region;
+
+        if self.reader.is_lossless {
+            if !self.converts_to_gray() {
+                return self.decode_lossless(destination, layout, region);
+            }
+            let mut color = vec![0u8; width * height * 3];
+            self.decode_lossless(&mut color, SampleLayout::interleaved(3, width * 3), region)?;
+            for (i, pixel) in color.chunks_exact(3).enumerate() {
+                let gray = luma(pixel[0] as f32, pixel[1] as f32, pixel[2] as f32);
+                destination[layout.index(i % width, i / width, 0)] = gray.round() as u8;
+            }
+            return Ok(());
+        }
+        let (_, max_v_samp, _, mcus_h) = self.mcu_layout();
+        // MCU rows below the rectangle are not needed.
+        let mcu_rows = (top + height)
+            .div_ceil(max_v_samp * self.scaled_block_size())
+            .min(mcus_h);
+        let coefficient_buffers = self.decode_coefficient_blocks(mcu_rows)?;
+        let _coefficient_memory =
+            track_elements::<i16>(coefficient_buffers.iter().map(Vec::len).sum());
+        self.reconstruct(&coefficient_buffers, 0, destination, layout, region)
+    }
+
+    /// Transforms the blocks that cover `region` (left, top, width and height) back and
+    /// stores its pixels. `coefficient_buffers` hold the blocks of every component from
+    /// MCU row `first_mcu_row` on.
+    fn reconstruct(
+        &self,
+        coefficient_buffers: &[Vec<i16>],
+        first_mcu_row: usize,
+        destination: &mut [u8],
+        layout: SampleLayout,
+        (left, top, width, height): (usize, usize, usize, usize),
+    ) -> Result<(), JpeglsError> {
         let components_count = self.reader.components.len();
+        let color_space = self.color_space();
+        let to_gray = self.converts_to_gray();
+        // The Y component of YCbCr is the luminance; the chroma is not needed.
+        let reconstructed = if to_gray && color_space == ColorSpace::YCbCr {
+            1
+        } else {
+            components_count
+        };
+
+        let (max_h_samp, max_v_samp, mcus_w, _) = self.mcu_layout();
+        let block = self.scaled_block_size();
+        let block_len = block * block;
+
+        // Dequantize and IDCT the blocks of each component that cover the rectangle.
+        let mut component_buffers_f32 = Vec::new();
+        let mut first_block_rows = Vec::with_capacity(reconstructed);
+        let mut component_memory = Vec::with_capacity(reconstructed);
+        let components = self.reader.components.iter().zip(coefficient_buffers);
+        for (comp, coefficients) in components.take(reconstructed) {
+            let h_samp = comp.h_samp_factor as usize;
+            let v_samp = comp.v_samp_factor as usize;
+            let comp_blocks_w = mcus_w * h_samp;
+
+            let quant_idx = comp.quant_table_dest as usize;
+            // DQT segments store the table in zigzag order, the blocks are in natural order.
+            let quant_table = to_natural_order(&self.reader.quantization_tables[quant_idx]);
+
+            // Blocks of the component that cover the rectangle.
+            let blocks = |start: usize, size: usize, samp: usize, max_samp: usize| {
+                (start * samp / max_samp) / block
+                    ..=((start + size).max(1) - 1) * samp / max_samp / block
+            };
+            let columns = blocks(left, width, h_samp, max_h_samp);
+            let rows = blocks(top, height, v_samp, max_v_samp);
+            // Only the block rows of the rectangle are kept, from the first one on.
+            let first_block_row = *rows.start();
+            let coefficients_from = first_mcu_row * v_samp;
+            first_block_rows.push(first_block_row);
+
+            let block_rows = rows.end() + 1 - first_block_row;
+            let mut comp_buffer = vec![0.0f32; comp_blocks_w * block_rows * block_len];
+            component_memory.push(track_elements::<f32>(comp_buffer.len()));
+            for (by, bx) in rows.flat_map(|by| columns.clone().map(move |bx| (by, bx))) {
+                let Some(coefficient_row) = by.checked_sub(coefficients_from) else {
+                    continue;
+                };
+                let block_offset = (coefficient_row * comp_blocks_w + bx) * 64;
+                let b = (by - first_block_row) * comp_blocks_w + bx;
+                if block_offset + 64 <= coefficients.len() {
+                    let mut block_data = [0i16; 64];
+                    block_data.copy_from_slice(&coefficients[block_offset..block_offset + 64]);
+                    let mut dequant_coeffs = [0.0f32; 64];
+                    dequantize_block(&block_data, &quant_table, &mut dequant_coeffs);
+                    let output = &mut comp_buffer[b * block_len..(b + 1) * block_len];
+                    if block == 8 {
+                        let mut idct_out = [0.0f32; 64];
+                        crate::jpeg1::dct::idct_8x8(&dequant_coeffs, &mut idct_out);
+                        output.copy_from_slice(&idct_out);
+                    } else {
+                        crate::jpeg1::dct::idct_scaled(&dequant_coeffs, block, output);
+                    }
+                }
+            }
+            component_buffers_f32.push(comp_buffer);
+        }
+
+        // Reconstruct output pixels, handling subsampling
+        let mut component_values = vec![0.0f32; reconstructed];
+        for y in 0..height {
+            for x in 0..width {
+                let (px, py) = (left + x, top + y);
+                for (c, value) in component_values.iter_mut().enumerate() {
+                    let comp = &self.reader.components[c];
+                    let h_samp = comp.h_samp_factor as usize;
+                    let v_samp = comp.v_samp_factor as usize;
+                    let comp_blocks_w = mcus_w * h_samp;
+
+                    // Calculate position in component's coordinate system
+                    // For subsampled components, we need to scale down the pixel position
+                    let comp_px = (px * h_samp) / max_h_samp;
+                    let comp_py = (py * v_samp) / max_v_samp;
+
+                    let bx = comp_px / block;
+                    let by = comp_py / block - first_block_rows[c];
+                    let tx = comp_px % block;
+                    let ty = comp_py % block;
+                    let block_idx = (by * comp_blocks_w + bx) * block_len + (ty * block + tx);
+
+                    *value = component_buffers_f32[c]
+                        .get(block_idx)
+                        .copied()
+                        .unwrap_or_default();
+                }
+
+                if to_gray {
+                    let gray = match color_space {
+                        ColorSpace::YCbCr => component_values[0],
+                        _ => luma(
+                            component_values[0],
+                            component_values[1],
+                            component_values[2],
+                        ),
+                    };
+                    destination[layout.index(x, y, 0)] = level_shift(gray);
+                    continue;
+                }
+                match color_space {
+                    ColorSpace::YCbCr | ColorSpace::Ycck => {
+                        let [r, g, b] = ycbcr_to_rgb(
+                            component_values[0],
+                            component_values[1],
+                            component_values[2],
+                        );
+                        for (c, value) in [r, g, b].into_iter().enumerate() {
+                            let value = value.clamp(0.0, 255.0) as u8;
+                            // YCCK holds the inverted C, M and Y channels as RGB.
+                            destination[layout.index(x, y, c)] = if color_space == ColorSpace::Ycck
+                            {
+                                255 - value
+                            } else {
+                                value
+                            };
+                        }
+                        if color_space == ColorSpace::Ycck {
+                            destination[layout.index(x, y, 3)] = level_shift(component_values[3]);
+                        }
+                    }
+                    _ => {
+                        for (c, &value) in component_values.iter().enumerate() {
+                            destination[layout.index(x, y, c)] = level_shift(value);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads all scans of a DCT-based frame into one coefficient buffer per component. The
+    /// entropy-coded data of every scan is read for the first `mcu_rows` MCU rows only; the
+    /// blocks below them stay 0.
+    fn decode_coefficient_blocks(&mut self, mcu_rows: usize) -> Result<Vec<Vec<i16>>, JpeglsError> {
+        let components_count = self.reader.components.len();
+        let (_, max_v_samp, mcus_w, mcus_h) = self.mcu_layout();
+        let block = self.scaled_block_size();
 
-        // Calculate MCU dimensions based on maximum sampling factors
-        let max_h_samp = self.reader.components.iter().map(|c| c.h_samp_factor as usize).max().unwrap_or(1);
-        let max_v_samp = self.reader.components.iter().map(|c| c.v_samp_factor as usize).max().unwrap_or(1);
-        let mcu_width = max_h_samp * 8;
-        let mcu_height = max_v_samp * 8;
-        
-        // Calculate number of MCUs
-        #[allow(clippy::manual_div_ceil)]
-        let mcus_w = (width + mcu_width - 1) / mcu_width;
-        #[allow(clippy::manual_div_ceil)]
-        let mcus_h = (height + mcu_height - 1) / mcu_height;
-        
         // Calculate blocks per component based on sampling factors
         let mut coefficient_buffers = Vec::new();
         for comp in &self.reader.components {
@@ -50,282 +847,404 @@ impl<'a> Jpeg1Decoder<'a> {
             let comp_blocks_h = mcus_h * comp.v_samp_factor as usize;
             coefficient_buffers.push(vec![0i16; comp_blocks_w * comp_blocks_h * 64]);
         }
-        
+
         let mut dc_preds = vec![0i16; components_count];
         let mut eob_runs = vec![0u16; components_count];
 
+        // Rows of the scaled frame that every scan of a component has covered so far.
+        let mut rows_read = vec![0usize; components_count];
+
         loop {
-            let marker = self.reader.peek_marker();
-            match marker {
-                Ok(crate::jpeg_marker_code::JpegMarkerCode::StartOfScan) => {
-                    self.reader.read_start_of_scan_segment_jpeg1()?;
-                }
-                Ok(crate::jpeg_marker_code::JpegMarkerCode::EndOfImage) => break,
-                Ok(crate::jpeg_marker_code::JpegMarkerCode::DefineHuffmanTable) => {
-                    self.reader.read_dht_segment()?;
-                    continue;
-                }
-                Ok(crate::jpeg_marker_code::JpegMarkerCode::DefineQuantizationTable) => {
-                    self.reader.read_dqt_segment()?;
-                    continue;
-                }
-                Ok(crate::jpeg_marker_code::JpegMarkerCode::DefineRestartInterval) => {
-                    self.reader.read_dri_segment()?;
-                    continue;
+            match self.read_next_scan_header() {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(error) if self.tolerant => {
+                    self.record_truncation(self.reader.position(), &rows_read, error)?;
+                    break;
                 }
-                _ if marker.is_ok()
-                    && marker.unwrap() as u8 >= 0xE0
-                    && (marker.unwrap() as u8) <= 0xFE =>
+                Err(error) => return Err(error),
+            }
+
+            let scan_components = self.reader.scan_component_indices.clone();
+            let restart_interval = self.reader.restart_interval as usize;
+            // A tolerant decode reads the intervals in order to find where the data ends.
+            if restart_interval > 0
+                && !self.reader.is_progressive
+                && !self.tolerant
+                && self.threads() > 1
+            {
+                if let Some(length) =
+                    self.decode_restart_intervals(&scan_components, &mut coefficient_buffers)?
                 {
-                    self.reader.skip_segment()?;
+                    self.reader.advance(length);
+                    mark_read(&mut rows_read, &scan_components, usize::MAX);
                     continue;
                 }
-                _ => break,
             }
 
-            let scan_components = self.reader.scan_component_indices.clone();
             let mut bit_reader = JpegBitReader::new(self.reader.remaining_data());
-            let restart_interval = self.reader.restart_interval as usize;
             let mut mcus_decoded = 0;
 
-            let ss = self.reader.ss;
-            let se = self.reader.se;
-            let ah = self.reader.ah;
-            let al = self.reader.al;
-
-            if scan_components.len() > 1 {
-                // Interleaved scan - need to handle subsampling
-                let total_mcus = mcus_h * mcus_w;
-                for mcu_y in 0..mcus_h {
-                    for mcu_x in 0..mcus_w {
-                        if restart_interval > 0
-                            && mcus_decoded > 0
-                            && (mcus_decoded % restart_interval == 0)
-                            && mcus_decoded < total_mcus
-                        {
-                            let _ = bit_reader.read_marker_code()?;
-                            for i in 0..components_count {
-                                dc_preds[i] = 0;
-                                eob_runs[i] = 0;
+            let mut decode_scan = || -> Result<(), JpeglsError> {
+                let ss = self.reader.ss;
+                let se = self.reader.se;
+                let ah = self.reader.ah;
+                let al = self.reader.al;
+
+                if scan_components.len() > 1 {
+                    // Interleaved scan - need to handle subsampling
+                    let total_mcus = mcus_h * mcus_w;
+                    for mcu_y in 0..mcu_rows {
+                        for mcu_x in 0..mcus_w {
+                            if restart_interval > 0
+                                && mcus_decoded > 0
+                                && (mcus_decoded % restart_interval == 0)
+                                && mcus_decoded < total_mcus
+                            {
+                                let _ = bit_reader.read_marker_code()?;
+                                for i in 0..components_count {
+                                    dc_preds[i] = 0;
+                                    eob_runs[i] = 0;
+                                }
                             }
-                        }
 
-                        // Decode blocks for each component in this MCU
-                        for &comp_idx in &scan_components {
-                            let comp = &self.reader.components[comp_idx];
-                            let h_samp = comp.h_samp_factor as usize;
-                            let v_samp = comp.v_samp_factor as usize;
-                            let comp_blocks_w = mcus_w * h_samp;
-                            
-                            // Decode h_samp * v_samp blocks for this component
-                            for v in 0..v_samp {
-                                for h in 0..h_samp {
-                                    let block_x = mcu_x * h_samp + h;
-                                    let block_y = mcu_y * v_samp + v;
-                                    let block_offset = (block_y * comp_blocks_w + block_x) * 64;
-                                    
-                                    if block_offset + 64 <= coefficient_buffers[comp_idx].len() {
-                                        let target_block =
-                                            &mut coefficient_buffers[comp_idx][block_offset..block_offset + 64];
-
-                                        if self.reader.is_progressive {
-                                            if ss == 0 {
-                                                self.decode_dc_progressive(
-                                                    &mut bit_reader,
-                                                    &mut dc_preds[comp_idx],
-                                                    target_block,
-                                                    ah,
-                                                    al,
-                                                    comp_idx,
-                                                )?;
+                            // Decode blocks for each component in this MCU
+                            for &comp_idx in &scan_components {
+                                let comp = &self.reader.components[comp_idx];
+                                let h_samp = comp.h_samp_factor as usize;
+                                let v_samp = comp.v_samp_factor as usize;
+                                let comp_blocks_w = mcus_w * h_samp;
+
+                                // Decode h_samp * v_samp blocks for this component
+                                for v in 0..v_samp {
+                                    for h in 0..h_samp {
+                                        let block_x = mcu_x * h_samp + h;
+                                        let block_y = mcu_y * v_samp + v;
+                                        let block_offset = (block_y * comp_blocks_w + block_x) * 64;
+
+                                        if block_offset + 64 <= coefficient_buffers[comp_idx].len()
+                                        {
+                                            let target_block = &mut coefficient_buffers[comp_idx]
+                                                [block_offset..block_offset + 64];
+
+                                            if self.reader.is_progressive {
+                                                if ss == 0 {
+                                                    self.decode_dc_progressive(
+                                                        &mut bit_reader,
+                                                        &mut dc_preds[comp_idx],
+                                                        target_block,
+                                                        ah,
+                                                        al,
+                                                        comp_idx,
+                                                    )?;
+                                                } else {
+                                                    self.decode_ac_progressive(
+                                                        &mut bit_reader,
+                                                        target_block,
+                                                        ss,
+                                                        se,
+                                                        ah,
+                                                        al,
+                                                        &mut eob_runs[comp_idx],
+                                                        comp_idx,
+                                                    )?;
+                                                }
                                             } else {
-                                                self.decode_ac_progressive(
+                                                Self::decode_block_internal(
                                                     &mut bit_reader,
+                                                    self,
+                                                    &mut dc_preds[comp_idx],
                                                     target_block,
-                                                    ss,
-                                                    se,
-                                                    ah,
-                                                    al,
-                                                    &mut eob_runs[comp_idx],
                                                     comp_idx,
                                                 )?;
                                             }
-                                        } else {
-                                            Self::decode_block_internal(
-                                                &mut bit_reader,
-                                                self,
-                                                &mut dc_preds[comp_idx],
-                                                target_block,
-                                                comp_idx,
-                                            )?;
                                         }
                                     }
                                 }
                             }
+                            mcus_decoded += 1;
                         }
-                        mcus_decoded += 1;
                     }
-                }
-            } else {
-                // Non-interleaved (planar) scan - one component at a time
-                let comp_idx = scan_components[0];
-                let comp = &self.reader.components[comp_idx];
-                let h_samp = comp.h_samp_factor as usize;
-                let v_samp = comp.v_samp_factor as usize;
-                let comp_blocks_w = mcus_w * h_samp;
-                let comp_blocks_h = mcus_h * v_samp;
-                let total_blocks = comp_blocks_h * comp_blocks_w;
-                
-                for block_y in 0..comp_blocks_h {
-                    for block_x in 0..comp_blocks_w {
-                        if restart_interval > 0
-                            && mcus_decoded > 0
-                            && (mcus_decoded % restart_interval == 0)
-                            && mcus_decoded < total_blocks
-                        {
-                            let _ = bit_reader.read_marker_code()?;
-                            dc_preds[comp_idx] = 0;
-                            eob_runs[comp_idx] = 0;
-                        }
+                } else {
+                    // Non-interleaved (planar) scan - one component at a time
+                    let comp_idx = scan_components[0];
+                    let comp = &self.reader.components[comp_idx];
+                    let h_samp = comp.h_samp_factor as usize;
+                    let v_samp = comp.v_samp_factor as usize;
+                    let comp_blocks_w = mcus_w * h_samp;
+                    let comp_blocks_h = mcus_h * v_samp;
+                    let total_blocks = comp_blocks_h * comp_blocks_w;
 
-                        let block_offset = (block_y * comp_blocks_w + block_x) * 64;
-                        if block_offset + 64 <= coefficient_buffers[comp_idx].len() {
-                            let target_block =
-                                &mut coefficient_buffers[comp_idx][block_offset..block_offset + 64];
+                    for block_y in 0..mcu_rows * v_samp {
+                        for block_x in 0..comp_blocks_w {
+                            if restart_interval > 0
+                                && mcus_decoded > 0
+                                && (mcus_decoded % restart_interval == 0)
+                                && mcus_decoded < total_blocks
+                            {
+                                let _ = bit_reader.read_marker_code()?;
+                                dc_preds[comp_idx] = 0;
+                                eob_runs[comp_idx] = 0;
+                            }
 
-                            if self.reader.is_progressive {
-                                if ss == 0 {
-                                    self.decode_dc_progressive(
-                                        &mut bit_reader,
-                                        &mut dc_preds[comp_idx],
-                                        target_block,
-                                        ah,
-                                        al,
-                                        comp_idx,
-                                    )?;
+                            let block_offset = (block_y * comp_blocks_w + block_x) * 64;
+                            if block_offset + 64 <= coefficient_buffers[comp_idx].len() {
+                                let target_block = &mut coefficient_buffers[comp_idx]
+                                    [block_offset..block_offset + 64];
+
+                                if self.reader.is_progressive {
+                                    if ss == 0 {
+                                        self.decode_dc_progressive(
+                                            &mut bit_reader,
+                                            &mut dc_preds[comp_idx],
+                                            target_block,
+                                            ah,
+                                            al,
+                                            comp_idx,
+                                        )?;
+                                    } else {
+                                        self.decode_ac_progressive(
+                                            &mut bit_reader,
+                                            target_block,
+                                            ss,
+                                            se,
+                                            ah,
+                                            al,
+                                            &mut eob_runs[comp_idx],
+                                            comp_idx,
+                                        )?;
+                                    }
                                 } else {
-                                    self.decode_ac_progressive(
+                                    Self::decode_block_internal(
                                         &mut bit_reader,
+                                        self,
+                                        &mut dc_preds[comp_idx],
                                         target_block,
-                                        ss,
-                                        se,
-                                        ah,
-                                        al,
-                                        &mut eob_runs[comp_idx],
                                         comp_idx,
                                     )?;
                                 }
-                            } else {
-                                Self::decode_block_internal(
-                                    &mut bit_reader,
-                                    self,
-                                    &mut dc_preds[comp_idx],
-                                    target_block,
-                                    comp_idx,
-                                )?;
                             }
+                            mcus_decoded += 1;
                         }
-                        mcus_decoded += 1;
                     }
                 }
+                Ok(())
+            };
+            if let Err(error) = decode_scan() {
+                if !self.tolerant {
+                    return Err(error);
+                }
+                // The rows of the units read before the error are complete.
+                let rows = match scan_components[..] {
+                    [comp_idx] => {
+                        let comp = &self.reader.components[comp_idx];
+                        let block_rows = mcus_decoded / (mcus_w * comp.h_samp_factor as usize);
+                        block_rows * block * max_v_samp / comp.v_samp_factor as usize
+                    }
+                    _ => mcus_decoded / mcus_w * max_v_samp * block,
+                };
+                mark_read(&mut rows_read, &scan_components, rows);
+                let offset = self.reader.position() + bit_reader.position();
+                self.record_truncation(offset, &rows_read, error)?;
+                break;
+            }
+            mark_read(&mut rows_read, &scan_components, usize::MAX);
+            if mcu_rows < mcus_h {
+                // Skip the rest of the entropy-coded data, up to the marker that ends it.
+                let (_, length) = restart_intervals(self.reader.remaining_data());
+                self.reader.advance(length);
+            } else {
+                self.reader.advance(bit_reader.position());
             }
-            self.reader.advance(bit_reader.position());
         }
 
-        // Dequantize and IDCT all blocks for each component
-        let mut component_buffers_f32 = Vec::new();
-        for c in 0..components_count {
-            let comp = &self.reader.components[c];
+        Ok(coefficient_buffers)
+    }
+
+    /// Reads the table and miscellaneous segments up to the next scan and its header.
+    /// Returns `false` when the frame ends instead: at EOI, at another marker or where the
+    /// data runs out, which only a tolerant decode reports as an error.
+    fn read_next_scan_header(&mut self) -> Result<bool, JpeglsError> {
+        loop {
+            let marker = self.reader.peek_marker();
+            match marker {
+                Ok(crate::jpeg_marker_code::JpegMarkerCode::StartOfScan) => {
+                    self.reader.read_start_of_scan_segment_jpeg1()?;
+                    return Ok(true);
+                }
+                Ok(crate::jpeg_marker_code::JpegMarkerCode::EndOfImage) => return Ok(false),
+                Ok(crate::jpeg_marker_code::JpegMarkerCode::DefineHuffmanTable) => {
+                    self.reader.read_dht_segment()?;
+                }
+                Ok(crate::jpeg_marker_code::JpegMarkerCode::DefineQuantizationTable) => {
+                    self.reader.read_dqt_segment()?;
+                }
+                Ok(crate::jpeg_marker_code::JpegMarkerCode::DefineRestartInterval) => {
+                    self.reader.read_dri_segment()?;
+                }
+                Ok(crate::jpeg_marker_code::JpegMarkerCode::DefineNumberOfLines) => {
+                    self.reader.read_marker()?;
+                    self.reader.read_define_number_of_lines_segment()?;
+                }
+                Ok(marker) if (0xE0..=0xFE).contains(&(marker as u8)) => {
+                    let marker = self.reader.read_marker()?;
+                    self.reader.skip_or_report_segment(marker)?;
+                }
+                Err(error) if self.tolerant => return Err(error),
+                _ => return Ok(false),
+            }
+        }
+    }
+
+    /// Records where a tolerant decode stopped. `rows_read` holds the rows of the scaled
+    /// frame that every component has complete.
+    fn record_truncation(
+        &mut self,
+        offset: usize,
+        rows_read: &[usize],
+        error: JpeglsError,
+    ) -> Result<(), JpeglsError> {
+        let (_, top, _, height) = self.output_region()?;
+        let rows = rows_read.iter().copied().min().unwrap_or(0);
+        self.truncation = Some(Truncation {
+            offset,
+            complete_rows: rows.saturating_sub(top).min(height) as u32,
+            error,
+        });
+        Ok(())
+    }
+
+    /// Number of threads to decode a scan on.
+    fn threads(&self) -> usize {
+        match self.thread_count {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            thread_count => thread_count,
+        }
+    }
+
+    /// Number of MCUs of an interleaved scan, or of blocks of a single component scan: the
+    /// units counted by the restart interval.
+    fn scan_unit_count(&self, scan_components: &[usize]) -> usize {
+        let (_, _, mcus_w, mcus_h) = self.mcu_layout();
+        match scan_components {
+            [comp_idx] => {
+                let comp = &self.reader.components[*comp_idx];
+                mcus_w * comp.h_samp_factor as usize * mcus_h * comp.v_samp_factor as usize
+            }
+            _ => mcus_w * mcus_h,
+        }
+    }
+
+    /// Replaces `blocks` with the component and coefficient offset of every block of `unit`,
+    /// in the order they are coded.
+    fn unit_blocks(
+        &self,
+        scan_components: &[usize],
+        unit: usize,
+        blocks: &mut Vec<(usize, usize)>,
+    ) {
+        let (_, _, mcus_w, _) = self.mcu_layout();
+        blocks.clear();
+        if let [comp_idx] = scan_components {
+            // A single component scan codes its blocks in raster order.
+            blocks.push((*comp_idx, unit * 64));
+            return;
+        }
+        let (mcu_x, mcu_y) = (unit % mcus_w, unit / mcus_w);
+        for &comp_idx in scan_components {
+            let comp = &self.reader.components[comp_idx];
             let h_samp = comp.h_samp_factor as usize;
             let v_samp = comp.v_samp_factor as usize;
             let comp_blocks_w = mcus_w * h_samp;
-            let comp_blocks_h = mcus_h * v_samp;
-            
-            let quant_idx = comp.quant_table_dest as usize;
-            let quant_table = &self.reader.quantization_tables[quant_idx];
-            
-            let mut comp_buffer = vec![0.0f32; comp_blocks_w * comp_blocks_h * 64];
-            for b in 0..(comp_blocks_w * comp_blocks_h) {
-                let block_offset = b * 64;
-                if block_offset + 64 <= coefficient_buffers[c].len() {
-                    let mut block_data = [0i16; 64];
-                    block_data
-                        .copy_from_slice(&coefficient_buffers[c][block_offset..block_offset + 64]);
-                    let mut dequant_coeffs = [0.0f32; 64];
-                    dequantize_block(&block_data, quant_table, &mut dequant_coeffs);
-                    let mut idct_out = [0.0f32; 64];
-                    crate::jpeg1::dct::idct_8x8_fixed_point(&dequant_coeffs, &mut idct_out);
-                    comp_buffer[block_offset..block_offset + 64].copy_from_slice(&idct_out);
+            for v in 0..v_samp {
+                for h in 0..h_samp {
+                    let block_x = mcu_x * h_samp + h;
+                    let block_y = mcu_y * v_samp + v;
+                    blocks.push((comp_idx, (block_y * comp_blocks_w + block_x) * 64));
                 }
             }
-            component_buffers_f32.push(comp_buffer);
         }
+    }
 
-        // Reconstruct output pixels, handling subsampling
-        for py in 0..height {
-            for px in 0..width {
-                if components_count == 1 {
-                    // Grayscale - no subsampling
-                    let comp = &self.reader.components[0];
-                    let h_samp = comp.h_samp_factor as usize;
-                    let comp_blocks_w = mcus_w * h_samp;
-                    
-                    let bx = px / 8;
-                    let by = py / 8;
-                    let tx = px % 8;
-                    let ty = py % 8;
-                    let block_idx = (by * comp_blocks_w + bx) * 64 + (ty * 8 + tx);
-                    
-                    if block_idx < component_buffers_f32[0].len() {
-                        let val = (component_buffers_f32[0][block_idx] + 128.0)
-                            .round()
-                            .clamp(0.0, 255.0) as u8;
-                        destination[py * width + px] = val;
-                    }
-                } else if components_count == 3 {
-                    // RGB/YCbCr - may have subsampling
-                    let mut component_values = [0.0f32; 3];
-                    
-                    for c in 0..3 {
-                        let comp = &self.reader.components[c];
-                        let h_samp = comp.h_samp_factor as usize;
-                        let v_samp = comp.v_samp_factor as usize;
-                        let comp_blocks_w = mcus_w * h_samp;
-                        
-                        // Calculate position in component's coordinate system
-                        // For subsampled components, we need to scale down the pixel position
-                        let comp_px = (px * h_samp) / max_h_samp;
-                        let comp_py = (py * v_samp) / max_v_samp;
-                        
-                        let bx = comp_px / 8;
-                        let by = comp_py / 8;
-                        let tx = comp_px % 8;
-                        let ty = comp_py % 8;
-                        let block_idx = (by * comp_blocks_w + bx) * 64 + (ty * 8 + tx);
-                        
-                        if block_idx < component_buffers_f32[c].len() {
-                            component_values[c] = component_buffers_f32[c][block_idx];
-                        }
-                    }
-                    
-                    // Convert YCbCr to RGB
-                    let y_val = component_values[0];
-                    let cb_val = component_values[1];
-                    let cr_val = component_values[2];
-                    let r = y_val + 1.402 * cr_val + 128.0;
-                    let g = y_val - 0.344136 * cb_val - 0.714136 * cr_val + 128.0;
-                    let b = y_val + 1.772 * cb_val + 128.0;
-                    
-                    let pixel_idx = (py * width + px) * 3;
-                    if pixel_idx + 2 < destination.len() {
-                        destination[pixel_idx] = r.clamp(0.0, 255.0) as u8;
-                        destination[pixel_idx + 1] = g.clamp(0.0, 255.0) as u8;
-                        destination[pixel_idx + 2] = b.clamp(0.0, 255.0) as u8;
-                    }
+    /// Decodes a baseline scan with restart markers by handing its restart intervals to
+    /// worker threads. Every worker returns the blocks it decoded with their place in the
+    /// coefficient buffers, which are filled in afterwards. Returns the length of the
+    /// entropy-coded data, or `None` when the RSTn markers do not delimit the expected
+    /// number of intervals and the scan has to be decoded sequentially.
+    fn decode_restart_intervals(
+        &self,
+        scan_components: &[usize],
+        coefficient_buffers: &mut [Vec<i16>],
+    ) -> Result<Option<usize>, JpeglsError> {
+        let unit_count = self.scan_unit_count(scan_components);
+        let restart_interval = self.reader.restart_interval as usize;
+        let (intervals, length) = restart_intervals(self.reader.remaining_data());
+        if intervals.len() != unit_count.div_ceil(restart_interval) {
+            return Ok(None);
+        }
+
+        let decode = |interval: usize| -> Result<IntervalBlocks, JpeglsError> {
+            let mut bit_reader = JpegBitReader::new(intervals[interval]);
+            let mut dc_preds = vec![0i16; self.reader.components.len()];
+            let mut unit_blocks = Vec::new();
+            let mut blocks = Vec::new();
+            let first_unit = interval * restart_interval;
+            for unit in first_unit..unit_count.min(first_unit + restart_interval) {
+                self.unit_blocks(scan_components, unit, &mut unit_blocks);
+                for &(comp_idx, block_offset) in &unit_blocks {
+                    let mut block = [0i16; 64];
+                    Self::decode_block_internal(
+                        &mut bit_reader,
+                        self,
+                        &mut dc_preds[comp_idx],
+                        &mut block,
+                        comp_idx,
+                    )?;
+                    blocks.push((comp_idx, block_offset, block));
+                }
+            }
+            Ok(blocks)
+        };
+        let next_interval = AtomicUsize::new(0);
+
+        // Workers take the next interval until none are left.
+        let mut decoded: Vec<(usize, Result<IntervalBlocks, JpeglsError>)> =
+            thread::scope(|scope| {
+                let workers: Vec<_> = (0..self.threads().min(intervals.len()))
+                    .map(|_| {
+                        scope.spawn(|| {
+                            let mut decoded = Vec::new();
+                            loop {
+                                let interval = next_interval.fetch_add(1, Ordering::Relaxed);
+                                if interval >= intervals.len() {
+                                    break decoded;
+                                }
+                                decoded.push((interval, decode(interval)));
+                            }
+                        })
+                    })
+                    .collect();
+                workers
+                    .into_iter()
+                    .flat_map(|worker| {
+                        worker
+                            .join()
+                            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                    })
+                    .collect()
+            });
+        decoded.sort_by_key(|(interval, _)| *interval);
+
+        for (_, blocks) in decoded {
+            for (comp_idx, block_offset, block) in blocks? {
+                if let Some(target) =
+                    coefficient_buffers[comp_idx].get_mut(block_offset..block_offset + 64)
+                {
+                    target.copy_from_slice(&block);
                 }
             }
         }
-        Ok(())
+        Ok(Some(length))
     }
 
     fn decode_block_internal(
@@ -532,7 +1451,13 @@ impl<'a> Jpeg1Decoder<'a> {
         Ok(())
     }
 
-    fn decode_lossless(&mut self, destination: &mut [u8]) -> Result<(), JpeglsError> {
+    /// Decodes a lossless frame and stores the `region` (left, top, width and height) of it.
+    fn decode_lossless(
+        &mut self,
+        destination: &mut [u8],
+        layout: SampleLayout,
+        region: (usize, usize, usize, usize),
+    ) -> Result<(), JpeglsError> {
         let frame_info = self.reader.frame_info();
         let width = frame_info.width as usize;
         let height = frame_info.height as usize;
@@ -540,23 +1465,19 @@ impl<'a> Jpeg1Decoder<'a> {
         let bit_depth = frame_info.bits_per_sample as u8;
 
         let mut component_pixels = vec![Vec::new(); components_count];
+        let mut component_memory = Vec::with_capacity(components_count);
+        let mut rows_read = vec![0usize; components_count];
+        let mid_grey = 1 << (bit_depth - 1);
 
-        loop {
-            let marker = self.reader.peek_marker();
-            match marker {
-                Ok(crate::jpeg_marker_code::JpegMarkerCode::StartOfScan) => {
-                    self.reader.read_start_of_scan_segment_jpeg1()?;
-                }
-                Ok(crate::jpeg_marker_code::JpegMarkerCode::DefineHuffmanTable) => {
-                    self.reader.read_dht_segment()?;
-                    continue;
-                }
-                Ok(crate::jpeg_marker_code::JpegMarkerCode::Comment)
-                | Ok(crate::jpeg_marker_code::JpegMarkerCode::ApplicationData0) => {
-                    self.reader.skip_segment()?;
-                    continue;
+        'scans: loop {
+            match self.read_next_scan_header() {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(error) if self.tolerant => {
+                    self.record_truncation(self.reader.position(), &rows_read, error)?;
+                    break;
                 }
-                _ => break,
+                Err(error) => return Err(error),
             }
 
             let scan_components = self.reader.scan_component_indices.clone();
@@ -569,34 +1490,104 @@ impl<'a> Jpeg1Decoder<'a> {
                     .as_ref()
                     .ok_or(JpeglsError::InvalidData)?;
 
-                let pixels = crate::jpeg1::lossless::Jpeg1LosslessDecoder::decode_component(
-                    predictor_id,
-                    width,
-                    height,
-                    bit_depth,
-                    &mut bit_reader,
-                    huffman_table,
-                )?;
+                // Rows a tolerant decode cannot read stay mid-grey.
+                let mut pixels = vec![mid_grey; width * height];
+                component_memory.push(track_elements::<i32>(pixels.len()));
+                let mut decoded = Ok(());
+                for y in 0..height {
+                    decoded = crate::jpeg1::lossless::Jpeg1LosslessDecoder::decode_row(
+                        predictor_id,
+                        y,
+                        width,
+                        bit_depth,
+                        &mut bit_reader,
+                        huffman_table,
+                        &mut pixels,
+                    );
+                    if decoded.is_err() {
+                        break;
+                    }
+                    rows_read[comp_idx] = y + 1;
+                }
                 component_pixels[comp_idx] = pixels;
+                if let Err(error) = decoded {
+                    if !self.tolerant {
+                        return Err(error);
+                    }
+                    let offset = self.reader.position() + bit_reader.position();
+                    self.record_truncation(offset, &rows_read, error)?;
+                    break 'scans;
+                }
             }
             self.reader.advance(bit_reader.position());
         }
 
-        for py in 0..height {
-            for px in 0..width {
-                let pixel_idx = (py * width + px) * components_count;
+        let (left, top, region_width, region_height) = region;
+        for y in 0..region_height {
+            for x in 0..region_width {
+                let (px, py) = (left + x, top + y);
                 for c in 0..components_count {
-                    if component_pixels[c].is_empty() {
-                        continue;
-                    }
-                    let val =
-                        component_pixels[c][py * width + px].clamp(0, (1 << bit_depth) - 1) as u8;
-                    if pixel_idx + c < destination.len() {
-                        destination[pixel_idx + c] = val;
-                    }
+                    let val = match component_pixels[c].get(py * width + px) {
+                        Some(&val) => val,
+                        // Components a truncated stream did not reach.
+                        None if self.truncation.is_some() => mid_grey,
+                        None => continue,
+                    };
+                    destination[layout.index(x, y, c)] = val.clamp(0, (1 << bit_depth) - 1) as u8;
                 }
             }
         }
         Ok(())
     }
 }
+
+/// Sets the rows read of the components of a scan.
+fn mark_read(rows_read: &mut [usize], scan_components: &[usize], rows: usize) {
+    for &comp_idx in scan_components {
+        rows_read[comp_idx] = rows;
+    }
+}
+
+/// Converts level-shifted Y, Cb and Cr samples to R, G and B (ITU-T T.871).
+fn ycbcr_to_rgb(y: f32, cb: f32, cr: f32) -> [f32; 3] {
+    [
+        y + 1.402 * cr + 128.0,
+        y - 0.344136 * cb - 0.714136 * cr + 128.0,
+        y + 1.772 * cb + 128.0,
+    ]
+}
+
+/// Luminance of R, G and B samples (ITU-R BT.601 weights).
+pub(crate) fn luma(r: f32, g: f32, b: f32) -> f32 {
+    0.299 * r + 0.587 * g + 0.114 * b
+}
+
+/// Undoes the level shift of a reconstructed sample.
+fn level_shift(value: f32) -> u8 {
+    (value + 128.0).round().clamp(0.0, 255.0) as u8
+}
+
+/// Splits the entropy-coded data at the start of `data` at its RSTn markers. Returns the
+/// restart intervals and the offset of the marker that ends the scan.
+fn restart_intervals(data: &[u8]) -> (Vec<&[u8]>, usize) {
+    let mut intervals = Vec::new();
+    let mut start = 0;
+    let mut position = 0;
+    let end = loop {
+        match data.get(position..position + 2) {
+            None => break data.len(),
+            Some([0xFF, 0xD0..=0xD7]) => {
+                intervals.push(&data[start..position]);
+                position += 2;
+                start = position;
+            }
+            // A stuffed 0xFF data byte, or a fill byte in front of a marker.
+            Some([0xFF, 0x00]) => position += 2,
+            Some([0xFF, 0xFF]) => position += 1,
+            Some([0xFF, _]) => break position,
+            Some(_) => position += 1,
+        }
+    };
+    intervals.push(&data[start..end]);
+    (intervals, end)
+}
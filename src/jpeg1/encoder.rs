@@ -1,14 +1,18 @@
 //! JPEG 1 Baseline Encoder orchestration.
 
 use crate::error::JpeglsError;
-use crate::jpeg1::dct::fdct_8x8;
-use crate::jpeg1::huffman::{
-    HuffmanEncoder, HuffmanTable, JpegBitWriter, STD_LUMINANCE_DC_LENGTHS, STD_LUMINANCE_DC_VALUES,
-};
+use crate::jpeg1::dct::{fdct_8x8, fdct_8x8_scalar};
+use crate::jpeg1::huffman::{HuffmanEncoder, HuffmanTable, JpegBitWriter};
 use crate::jpeg1::quantization::{
-    quantize_block, STD_CHROMINANCE_QUANT_TABLE, STD_LUMINANCE_QUANT_TABLE,
+    quantize_block, to_zigzag_order, STD_CHROMINANCE_QUANT_TABLE, STD_LUMINANCE_QUANT_TABLE,
+};
+use crate::jpeg1::transcode::SymbolCounts;
+use crate::constants::{
+    AUTO_CALCULATE_STRIDE, MAXIMUM_APPLICATION_DATA_ID, SEGMENT_MAX_DATA_SIZE,
 };
-use crate::jpeg_stream_writer::JpegStreamWriter;
+use crate::jpeg_stream_writer::{JpegStreamWriter, Sink, SliceSink};
+use crate::mem_profiling::{EncodeStats, Session};
+use crate::resolution::Resolution;
 use crate::FrameInfo;
 
 /// Zigzag scan pattern for 8x8 blocks.
@@ -24,10 +28,35 @@ pub struct Jpeg1Encoder {
     ac_table_lum: HuffmanTable,
     dc_table_chrom: HuffmanTable,
     ac_table_chrom: HuffmanTable,
-    pub quantization_table_lum: [u8; 64],
-    pub quantization_table_chrom: [u8; 64],
+    /// Set by [`set_huffman_tables`](Self::set_huffman_tables): the tables are used as they
+    /// are, also for 12-bit samples.
+    custom_huffman_tables: bool,
+    /// Quantization tables in natural (row-major) order; see
+    /// [`set_quantization_tables`](Self::set_quantization_tables).
+    pub quantization_table_lum: [u16; 64],
+    pub quantization_table_chrom: [u16; 64],
+    quantization_precision: u8,
     pub restart_interval: u16,
     pub quality: u8,
+    /// Write a height of 0 in SOF0 and the actual height in a DNL segment after the scan.
+    pub define_number_of_lines: bool,
+    /// See [`set_encode_deterministic`](Self::set_encode_deterministic).
+    deterministic: bool,
+    /// See [`set_resolution`](Self::set_resolution).
+    resolution: Option<Resolution>,
+    metadata_segments: Vec<MetadataSegment>,
+    stats: EncodeStats,
+}
+
+/// Adobe (APP14) segment data: DCTEncode version 100, no flags, transform 0. Without a
+/// color transform, four components are CMYK.
+const ADOBE_CMYK_SEGMENT: [u8; 12] = [b'A', b'd', b'o', b'b', b'e', 0, 100, 0, 0, 0, 0, 0];
+
+/// COM or APPn segment written right after SOI.
+#[derive(Debug, Clone)]
+enum MetadataSegment {
+    Comment(Vec<u8>),
+    ApplicationData(u8, Vec<u8>),
 }
 
 impl Default for Jpeg1Encoder {
@@ -38,10 +67,17 @@ impl Default for Jpeg1Encoder {
             ac_table_lum: HuffmanTable::standard_luminance_ac(),
             dc_table_chrom: HuffmanTable::standard_chrominance_dc(),
             ac_table_chrom: HuffmanTable::standard_chrominance_ac(),
-            quantization_table_lum: STD_LUMINANCE_QUANT_TABLE,
-            quantization_table_chrom: STD_CHROMINANCE_QUANT_TABLE,
+            custom_huffman_tables: false,
+            quantization_table_lum: STD_LUMINANCE_QUANT_TABLE.map(u16::from),
+            quantization_table_chrom: STD_CHROMINANCE_QUANT_TABLE.map(u16::from),
+            quantization_precision: 8,
             restart_interval: 0,
             quality: 75, // Default quality
+            define_number_of_lines: false,
+            deterministic: false,
+            resolution: None,
+            metadata_segments: Vec::new(),
+            stats: EncodeStats::default(),
         }
     }
 }
@@ -55,6 +91,28 @@ impl Jpeg1Encoder {
         self.restart_interval = interval;
     }
 
+    /// Writes a height of 0 in the frame header and the actual height in a DNL segment
+    /// after the first scan, as line-scan cameras do.
+    pub fn set_define_number_of_lines(&mut self, enabled: bool) {
+        self.define_number_of_lines = enabled;
+    }
+
+    /// Transforms the blocks with the scalar DCT instead of the fastest kernel of the CPU,
+    /// so the stream is byte-identical on every platform, e.g. for archives that hash the
+    /// encoded images to find duplicates. The vectorized kernels add in the same order and
+    /// give the same coefficients today; this makes it a guarantee. Off by default.
+    pub fn set_encode_deterministic(&mut self, enabled: bool) {
+        self.deterministic = enabled;
+    }
+
+    /// Writes the pixel density in a JFIF (APP0) segment, in dots per inch. It is not
+    /// written when an APP0 JFIF or Adobe segment was added with
+    /// [`write_application_data`](Self::write_application_data), which then describes the
+    /// image.
+    pub fn set_resolution(&mut self, resolution: Resolution) {
+        self.resolution = Some(resolution);
+    }
+
     /// Set encoding quality (1-100). Higher values = better quality, larger files.
     /// Quality 50 uses standard tables, quality 100 approaches lossless.
     pub fn set_quality(&mut self, quality: u8) {
@@ -69,13 +127,237 @@ impl Jpeg1Encoder {
         };
 
         for i in 0..64 {
-            let lum_val =
-                ((STD_LUMINANCE_QUANT_TABLE[i] as f32 * scale / 100.0).round() as u8).clamp(1, 255);
-            let chrom_val = ((STD_CHROMINANCE_QUANT_TABLE[i] as f32 * scale / 100.0).round() as u8)
+            let lum_val = ((STD_LUMINANCE_QUANT_TABLE[i] as f32 * scale / 100.0).round() as u16)
+                .clamp(1, 255);
+            let chrom_val = ((STD_CHROMINANCE_QUANT_TABLE[i] as f32 * scale / 100.0).round()
+                as u16)
                 .clamp(1, 255);
             self.quantization_table_lum[i] = lum_val;
             self.quantization_table_chrom[i] = chrom_val;
         }
+        self.quantization_precision = 8;
+    }
+
+    /// Replaces the quantization tables: `luminance` for grayscale images and the first
+    /// component, `chrominance` for the other components. The tables are in natural
+    /// (row-major) order and are written to the DQT segments in zigzag order.
+    ///
+    /// `precision` is the number of bits per entry, 8 or 16. Entries are 1-255 in 8-bit
+    /// tables and 1-65535 in 16-bit tables, which T.81 only allows for 12-bit samples, so
+    /// encoding 8-bit samples with them fails. Returns
+    /// [`JpeglsError::InvalidArgumentEncodingOptions`] and keeps the current tables when the
+    /// precision or an entry is out of range.
+    pub fn set_quantization_tables(
+        &mut self,
+        luminance: &[u16; 64],
+        chrominance: &[u16; 64],
+        precision: u8,
+    ) -> Result<(), JpeglsError> {
+        check_quantization_table(luminance, precision)?;
+        check_quantization_table(chrominance, precision)?;
+        self.quantization_table_lum = *luminance;
+        self.quantization_table_chrom = *chrominance;
+        self.quantization_precision = precision;
+        Ok(())
+    }
+
+    /// Replaces the Huffman tables, e.g. with the tables of a device that checks the DHT
+    /// segments: `dc_luminance` and `ac_luminance` for grayscale images and the first
+    /// component, the chrominance tables for the other components. Build them from the code
+    /// counts and symbols of a DHT segment with [`HuffmanTable::build_from_dht`]. The
+    /// tables are written as they are and are used for 12-bit samples too, instead of
+    /// tables optimized for the image.
+    ///
+    /// Returns [`JpeglsError::InvalidArgumentEncodingOptions`] and keeps the current tables
+    /// when a table has more or fewer symbols than codes, a symbol twice, or more codes of
+    /// some length than fit. Encoding an image that needs a symbol a table has no code for
+    /// fails with the same error.
+    pub fn set_huffman_tables(
+        &mut self,
+        dc_luminance: &HuffmanTable,
+        ac_luminance: &HuffmanTable,
+        dc_chrominance: &HuffmanTable,
+        ac_chrominance: &HuffmanTable,
+    ) -> Result<(), JpeglsError> {
+        for table in [dc_luminance, ac_luminance, dc_chrominance, ac_chrominance] {
+            check_huffman_table(table)?;
+        }
+        self.dc_table_lum = dc_luminance.clone();
+        self.ac_table_lum = ac_luminance.clone();
+        self.dc_table_chrom = dc_chrominance.clone();
+        self.ac_table_chrom = ac_chrominance.clone();
+        self.custom_huffman_tables = true;
+        Ok(())
+    }
+
+    /// Bits per entry of the quantization tables: 8, or 16 after
+    /// [`set_quantization_tables`](Self::set_quantization_tables) with 16-bit tables.
+    pub fn quantization_precision(&self) -> u8 {
+        self.quantization_precision
+    }
+
+    /// Checks the sample precision and that the quantization tables suit it.
+    fn check_precision(&self, frame_info: &FrameInfo) -> Result<(), JpeglsError> {
+        check_bits_per_sample(frame_info)?;
+        if self.quantization_precision == 16 && frame_info.bits_per_sample != 12 {
+            return Err(JpeglsError::InvalidArgumentEncodingOptions);
+        }
+        check_quantization_table(&self.quantization_table_lum, self.quantization_precision)?;
+        check_quantization_table(&self.quantization_table_chrom, self.quantization_precision)
+    }
+
+    /// Adds a COM segment to every image encoded afterwards. Segments are written after SOI
+    /// in the order they were added.
+    pub fn write_comment(&mut self, comment: &[u8]) -> Result<(), JpeglsError> {
+        if comment.len() > SEGMENT_MAX_DATA_SIZE {
+            return Err(JpeglsError::InvalidArgumentSize);
+        }
+        self.metadata_segments
+            .push(MetadataSegment::Comment(comment.to_vec()));
+        Ok(())
+    }
+
+    /// Adds an APPn segment (`id` 0 to 15) to every image encoded afterwards.
+    pub fn write_application_data(&mut self, id: u8, data: &[u8]) -> Result<(), JpeglsError> {
+        if id as i32 > MAXIMUM_APPLICATION_DATA_ID {
+            return Err(JpeglsError::InvalidArgument);
+        }
+        if data.len() > SEGMENT_MAX_DATA_SIZE {
+            return Err(JpeglsError::InvalidArgumentSize);
+        }
+        self.metadata_segments
+            .push(MetadataSegment::ApplicationData(id, data.to_vec()));
+        Ok(())
+    }
+
+    /// Writes the JFIF segment of [`set_resolution`](Self::set_resolution), the COM and APPn
+    /// segments, and for four components the Adobe (APP14) segment that marks them as CMYK,
+    /// unless one of the segments already is an Adobe segment.
+    fn write_metadata_segments<D: Sink>(
+        &self,
+        writer: &mut JpegStreamWriter<D>,
+        frame_info: &FrameInfo,
+    ) -> Result<(), JpeglsError> {
+        if let Some(jfif) = self.jfif_segment() {
+            writer.write_application_data(0, &jfif)?;
+        }
+        for segment in &self.metadata_segments {
+            match segment {
+                MetadataSegment::Comment(comment) => writer.write_comment(comment)?,
+                MetadataSegment::ApplicationData(id, data) => {
+                    writer.write_application_data(*id, data)?
+                }
+            }
+        }
+        if self.needs_adobe_segment(frame_info) {
+            writer.write_application_data(14, &ADOBE_CMYK_SEGMENT)?;
+        }
+        Ok(())
+    }
+
+    /// Data of the JFIF segment for [`set_resolution`](Self::set_resolution), unless an
+    /// added segment describes the image: a JFIF segment would override the color
+    /// transform of an Adobe segment.
+    fn jfif_segment(&self) -> Option<Vec<u8>> {
+        let describes_image = |segment: &MetadataSegment| match segment {
+            MetadataSegment::ApplicationData(0, data) => data.starts_with(b"JFIF\0"),
+            MetadataSegment::ApplicationData(14, data) => data.starts_with(b"Adobe"),
+            _ => false,
+        };
+        if self.metadata_segments.iter().any(describes_image) {
+            return None;
+        }
+        self.resolution.map(|resolution| resolution.jfif_segment())
+    }
+
+    fn needs_adobe_segment(&self, frame_info: &FrameInfo) -> bool {
+        let is_adobe = |segment: &MetadataSegment| match segment {
+            MetadataSegment::ApplicationData(14, data) => data.starts_with(b"Adobe"),
+            _ => false,
+        };
+        frame_info.component_count == 4 && !self.metadata_segments.iter().any(is_adobe)
+    }
+
+    /// Size of a destination buffer large enough to encode an image described by
+    /// `frame_info` with the current settings.
+    ///
+    /// Like libjpeg-turbo's `tj3JPEGBufSize`, it allows 2 bytes per sample of the image
+    /// padded to whole blocks, enough for noise at quality 100, and 2048 bytes for the
+    /// tables and headers; 12-bit samples get twice as much. The metadata segments and restart markers are added to that.
+    pub fn estimated_destination_size(&self, frame_info: &FrameInfo) -> usize {
+        let blocks =
+            (frame_info.width as usize).div_ceil(8) * (frame_info.height as usize).div_ceil(8);
+        let components = frame_info.component_count.max(0) as usize;
+        let metadata: usize = self
+            .metadata_segments
+            .iter()
+            .map(|segment| match segment {
+                MetadataSegment::Comment(comment) => 4 + comment.len(),
+                MetadataSegment::ApplicationData(_, data) => 4 + data.len(),
+            })
+            .sum::<usize>()
+            + self.jfif_segment().map_or(0, |jfif| 4 + jfif.len())
+            + if self.needs_adobe_segment(frame_info) {
+                4 + ADOBE_CMYK_SEGMENT.len()
+            } else {
+                0
+            };
+        // A restart marker follows up to 7 padding bits; planar images restart per scan.
+        let restart_markers = match self.restart_interval {
+            0 => 0,
+            interval => components * blocks.div_ceil(interval as usize) * 3,
+        };
+        let bytes_per_sample = if frame_info.bits_per_sample > 8 { 4 } else { 2 };
+        blocks * 64 * components * bytes_per_sample + 2048 + metadata + restart_markers
+    }
+
+    /// Memory statistics of the most recent encode. Blocks are transformed in place on the
+    /// stack, so the baseline encoder needs no intermediate heap buffers.
+    pub fn stats(&self) -> EncodeStats {
+        self.stats
+    }
+
+    fn record_stats(&mut self, session: Session, frame_info: &FrameInfo) {
+        self.stats = EncodeStats {
+            peak_buffer_bytes: session.finish(),
+            pixel_count: frame_info.width as u64 * frame_info.height as u64,
+        };
+    }
+
+    /// Reports the estimated size, or more than the destination if that did not fit
+    /// either, when the stream overflowed a destination of `destination_len` bytes.
+    fn with_needed_size(
+        &self,
+        result: Result<usize, JpeglsError>,
+        frame_info: &FrameInfo,
+        destination_len: usize,
+    ) -> Result<usize, JpeglsError> {
+        let needed = self
+            .estimated_destination_size(frame_info)
+            .max(destination_len + 1);
+        result.map_err(|e| e.with_needed_size(needed))
+    }
+
+    /// Like [`encode`](Self::encode), into a buffer that grows as the stream is written.
+    pub fn encode_to_vec(
+        &mut self,
+        source: &[u8],
+        frame_info: &FrameInfo,
+    ) -> Result<Vec<u8>, JpeglsError> {
+        let mut stream = Vec::new();
+        self.encode_to_sink(source, frame_info, &mut stream)?;
+        Ok(stream)
+    }
+
+    /// Like [`encode`](Self::encode), writing the stream to `sink` as it is produced, and
+    /// returns its length.
+    pub fn encode_to_sink<D: Sink>(
+        &mut self,
+        source: &[u8],
+        frame_info: &FrameInfo,
+        sink: D,
+    ) -> Result<usize, JpeglsError> {
+        self.encode_frame(source, AUTO_CALCULATE_STRIDE, frame_info, false, sink)
     }
 
     pub fn encode(
@@ -83,82 +365,139 @@ impl Jpeg1Encoder {
         source: &[u8],
         frame_info: &FrameInfo,
         destination: &mut [u8],
+    ) -> Result<usize, JpeglsError> {
+        self.encode_with_stride(source, AUTO_CALCULATE_STRIDE, frame_info, destination)
+    }
+
+    /// Like [`encode`](Self::encode), for source rows that are `stride` bytes apart.
+    /// [`AUTO_CALCULATE_STRIDE`] selects tightly packed rows.
+    pub fn encode_with_stride(
+        &mut self,
+        source: &[u8],
+        stride: usize,
+        frame_info: &FrameInfo,
+        destination: &mut [u8],
     ) -> Result<usize, JpeglsError> {
         let destination_len = destination.len();
-        // #region agent log
-        {
-            use std::fs::OpenOptions;
-            use std::io::Write;
-            if let Ok(mut f) = OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log")
-            {
-                let _ = writeln!(
-                    f,
-                    r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"B","location":"encoder.rs:56","message":"encoder.encode entry","data":{{"source_len":{},"destination_len":{},"width":{},"height":{},"components":{}}},"timestamp":{}}}"#,
-                    source.len(),
-                    destination_len,
-                    frame_info.width,
-                    frame_info.height,
-                    frame_info.component_count,
-                    std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap_or_default()
-                        .as_millis()
-                );
-            }
-        }
-        // #endregion
-        let mut writer = JpegStreamWriter::new(destination);
+        let result = self.encode_frame(
+            source,
+            stride,
+            frame_info,
+            false,
+            SliceSink::new(destination),
+        );
+        self.with_needed_size(result, frame_info, destination_len)
+    }
 
-        let components_count = frame_info.component_count as usize;
+    pub fn encode_planar(
+        &mut self,
+        source: &[u8],
+        frame_info: &FrameInfo,
+        destination: &mut [u8],
+    ) -> Result<usize, JpeglsError> {
+        self.encode_planar_with_stride(source, AUTO_CALCULATE_STRIDE, frame_info, destination)
+    }
 
-        writer.write_start_of_image()?;
+    /// Like [`encode_planar`](Self::encode_planar), for source rows that are `stride` bytes
+    /// apart. [`AUTO_CALCULATE_STRIDE`] selects tightly packed rows.
+    pub fn encode_planar_with_stride(
+        &mut self,
+        source: &[u8],
+        stride: usize,
+        frame_info: &FrameInfo,
+        destination: &mut [u8],
+    ) -> Result<usize, JpeglsError> {
+        let destination_len = destination.len();
+        let result = self.encode_frame(
+            source,
+            stride,
+            frame_info,
+            true,
+            SliceSink::new(destination),
+        );
+        self.with_needed_size(result, frame_info, destination_len)
+    }
 
-        // Write Quantization Tables
-        if components_count == 1 {
-            writer.write_dqt(0, &self.quantization_table_lum)?;
-        } else {
-            writer.write_dqt(0, &self.quantization_table_lum)?;
-            writer.write_dqt(1, &self.quantization_table_chrom)?;
-        }
-
-        // Write Huffman Tables (Luminance)
-        writer.write_dht(0, 0, &STD_LUMINANCE_DC_LENGTHS, &STD_LUMINANCE_DC_VALUES)?;
-        writer.write_dht(1, 0, &self.ac_table_lum.lengths, &self.ac_table_lum.values)?;
-
-        // Write Huffman Tables (Chrominance) if needed
-        if components_count > 1 {
-            writer.write_dht(
-                0,
-                1,
-                &crate::jpeg1::huffman::STD_CHROMINANCE_DC_LENGTHS,
-                &crate::jpeg1::huffman::STD_CHROMINANCE_DC_VALUES,
-            )?;
-            writer.write_dht(
-                1,
-                1,
-                &self.ac_table_chrom.lengths,
-                &self.ac_table_chrom.values,
-            )?;
-        }
+    /// Encodes one scan per component when `planar`, else a single interleaved scan.
+    fn encode_frame<D: Sink>(
+        &mut self,
+        source: &[u8],
+        stride: usize,
+        frame_info: &FrameInfo,
+        planar: bool,
+        sink: D,
+    ) -> Result<usize, JpeglsError> {
+        let session = Session::begin();
+        let mut writer = JpegStreamWriter::with_sink(sink);
+        let result = check_component_count(frame_info)
+            .and_then(|()| self.check_precision(frame_info))
+            .and_then(|()| source_stride(source, stride, frame_info))
+            .and_then(|stride| {
+                if planar {
+                    self.encode_non_interleaved(source, stride, frame_info, &mut writer)
+                } else {
+                    self.encode_interleaved(source, stride, frame_info, &mut writer)
+                }
+            });
+        self.record_stats(session, frame_info);
+        result.map(|()| writer.len())
+    }
 
-        if self.restart_interval > 0 {
-            writer.write_dri(self.restart_interval)?;
-        }
+    fn encode_interleaved<D: Sink>(
+        &mut self,
+        source: &[u8],
+        stride: usize,
+        frame_info: &FrameInfo,
+        writer: &mut JpegStreamWriter<D>,
+    ) -> Result<(), JpeglsError> {
+        log::trace!(
+            "JPEG encode: {}x{}x{}, {} source bytes",
+            frame_info.width,
+            frame_info.height,
+            frame_info.component_count,
+            source.len()
+        );
+        let samples = Samples::new(source, stride, frame_info);
+        let tables = self.huffman_tables(frame_info, |encoder, counts| {
+            encoder.encode_interleaved_scan(&samples, frame_info, counts)
+        })?;
 
-        writer.write_sof0_segment(frame_info)?;
+        writer.write_start_of_image()?;
+        self.write_metadata_segments(writer, frame_info)?;
+        self.write_tables(writer, frame_info, &tables)?;
+        self.write_frame_header(writer, frame_info)?;
         writer.write_sos_segment(frame_info.component_count as u8)?;
 
-        // Use Option to manage borrow of writer via bit_writer
-        let mut bit_writer_opt = Some(JpegBitWriter::new(writer.remaining_slice()));
+        let mut scan = ScanWriter {
+            bits: JpegBitWriter::with_sink(&mut *writer),
+            tables: &tables,
+        };
+        self.encode_interleaved_scan(&samples, frame_info, &mut scan)?;
+
+        // Final flush
+        scan.bits.flush()?;
+        self.write_define_number_of_lines(writer, frame_info)?;
+        writer.write_end_of_image()?;
+        log::trace!("JPEG encode: wrote {} bytes", writer.len());
+
+        Ok(())
+    }
+
+    /// Produces the symbols of the single scan that interleaves all components.
+    fn encode_interleaved_scan<S: SymbolSink + ?Sized>(
+        &mut self,
+        samples: &Samples,
+        frame_info: &FrameInfo,
+        sink: &mut S,
+    ) -> Result<(), JpeglsError> {
+        let components_count = frame_info.component_count as usize;
         let width = frame_info.width as usize;
         let height = frame_info.height as usize;
+        let center = samples.center;
 
         let mut mcus_encoded = 0;
         let mut next_restart_index = 0;
-        let total_mcus = ((height + 7) / 8) * ((width + 7) / 8);
+        let total_mcus = height.div_ceil(8) * width.div_ceil(8);
 
         // Reset DC predictors
         self.huffman.dc_previous_value = [0; 4];
@@ -171,28 +510,13 @@ impl Jpeg1Encoder {
                     && (mcus_encoded % self.restart_interval as usize == 0)
                     && mcus_encoded < total_mcus
                 {
-                    let bw = bit_writer_opt.as_mut().unwrap();
-                    bw.flush()?;
-                    let len = bw.len();
-                    let _ = bit_writer_opt.take(); // Force drop and release borrow
-
-                    writer.advance(len);
-                    let marker = crate::jpeg_marker_code::JpegMarkerCode::try_from(
-                        0xD0 + (next_restart_index % 8),
-                    )
-                    .unwrap();
-                    writer.write_marker(marker)?;
+                    sink.restart((next_restart_index % 8) as u8)?;
                     next_restart_index += 1;
 
-                    // Create new bit writer
-                    bit_writer_opt = Some(JpegBitWriter::new(writer.remaining_slice()));
-
                     // Reset DC predictors
                     self.huffman.dc_previous_value = [0; 4];
                 }
 
-                let bit_writer = bit_writer_opt.as_mut().unwrap();
-
                 if components_count == 1 {
                     // Grayscale
                     let mut block_data = [0.0f32; 64];
@@ -201,20 +525,50 @@ impl Jpeg1Encoder {
                             let py = block_y + y;
                             let px = block_x + x;
                             if py < height && px < width {
-                                block_data[y * 8 + x] = source[py * width + px] as f32 - 128.0;
+                                block_data[y * 8 + x] = samples.get(py, px) - center;
                             }
                         }
                     }
-                    // Y: DC table 0, AC table 0, Quant table 0, Pred index 0
+                    // Y: Quant table 0, Pred index 0
                     Self::encode_block_internal(
                         &mut self.huffman,
                         &block_data,
-                        bit_writer,
-                        &self.dc_table_lum,
-                        &self.ac_table_lum,
+                        sink,
                         &self.quantization_table_lum,
                         0,
+                        self.deterministic,
                     )?;
+                } else if components_count == 4 {
+                    // CMYK: the samples are stored as they are, without a color transform.
+                    let mut blocks = [[0.0f32; 64]; 4];
+                    for y in 0..8 {
+                        for x in 0..8 {
+                            let py = block_y + y;
+                            let px = block_x + x;
+                            if py < height && px < width {
+                                for (c, block) in blocks.iter_mut().enumerate() {
+                                    block[y * 8 + x] = samples.get(py, px * 4 + c) - center;
+                                }
+                            }
+                        }
+                    }
+
+                    // C: Quant 0; M, Y, K: Quant 1; Pred c
+                    for (c, block) in blocks.iter().enumerate() {
+                        let quant_table = if c == 0 {
+                            &self.quantization_table_lum
+                        } else {
+                            &self.quantization_table_chrom
+                        };
+                        Self::encode_block_internal(
+                            &mut self.huffman,
+                            block,
+                            sink,
+                            quant_table,
+                            c,
+                            self.deterministic,
+                        )?;
+                    }
                 } else {
                     // YCbCr Interleaved (4:4:4)
                     let mut block_y_data = [0.0f32; 64];
@@ -226,183 +580,174 @@ impl Jpeg1Encoder {
                             let py = block_y + y;
                             let px = block_x + x;
                             if py < height && px < width {
-                                let idx = (py * width + px) * 3;
-                                let r = source[idx] as f32;
-                                let g = source[idx + 1] as f32;
-                                let b = source[idx + 2] as f32;
+                                let r = samples.get(py, px * 3);
+                                let g = samples.get(py, px * 3 + 1);
+                                let b = samples.get(py, px * 3 + 2);
 
                                 // RGB to YCbCr
                                 let luma = 0.299 * r + 0.587 * g + 0.114 * b;
-                                let cb = -0.1687 * r - 0.3313 * g + 0.5 * b + 128.0;
-                                let cr = 0.5 * r - 0.4187 * g - 0.0813 * b + 128.0;
+                                let cb = -0.1687 * r - 0.3313 * g + 0.5 * b + center;
+                                let cr = 0.5 * r - 0.4187 * g - 0.0813 * b + center;
 
-                                block_y_data[y * 8 + x] = luma - 128.0;
-                                block_cb_data[y * 8 + x] = cb - 128.0;
-                                block_cr_data[y * 8 + x] = cr - 128.0;
+                                block_y_data[y * 8 + x] = luma - center;
+                                block_cb_data[y * 8 + x] = cb - center;
+                                block_cr_data[y * 8 + x] = cr - center;
                             }
                         }
                     }
 
-                    // Y: DC 0, AC 0, Quant 0, Pred 0
+                    // Y: Quant 0, Pred 0
                     Self::encode_block_internal(
                         &mut self.huffman,
                         &block_y_data,
-                        bit_writer,
-                        &self.dc_table_lum,
-                        &self.ac_table_lum,
+                        sink,
                         &self.quantization_table_lum,
                         0,
+                        self.deterministic,
                     )?;
 
-                    // Cb: DC 1, AC 1, Quant 1, Pred 1
+                    // Cb: Quant 1, Pred 1
                     Self::encode_block_internal(
                         &mut self.huffman,
                         &block_cb_data,
-                        bit_writer,
-                        &self.dc_table_chrom,
-                        &self.ac_table_chrom,
+                        sink,
                         &self.quantization_table_chrom,
                         1,
+                        self.deterministic,
                     )?;
-                    // Cr: DC 1, AC 1, Quant 1, Pred 2
+                    // Cr: Quant 1, Pred 2
                     Self::encode_block_internal(
                         &mut self.huffman,
                         &block_cr_data,
-                        bit_writer,
-                        &self.dc_table_chrom,
-                        &self.ac_table_chrom,
+                        sink,
                         &self.quantization_table_chrom,
                         2,
+                        self.deterministic,
                     )?;
                 }
                 mcus_encoded += 1;
             }
         }
+        Ok(())
+    }
 
-        // Final flush
-        let mut bw = bit_writer_opt.unwrap();
-        bw.flush()?;
-        let encoded_len = bw.len();
-        // #region agent log
-        {
-            use std::fs::OpenOptions;
-            use std::io::Write;
-            if let Ok(mut f) = OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log")
-            {
-                let _ = writeln!(
-                    f,
-                    r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"B","location":"encoder.rs:232","message":"before final flush and EOI","data":{{"encoded_len":{},"writer_position":{},"destination_len":{}}},"timestamp":{}}}"#,
-                    encoded_len,
-                    writer.len(),
-                    destination_len,
-                    std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap_or_default()
-                        .as_millis()
-                );
-            }
+    /// The Huffman tables of slot 0 (the first component) and slot 1 (the others). 8-bit
+    /// samples use the standard tables. Those do not cover the larger coefficients of
+    /// 12-bit samples, which get tables optimized for the image, as libjpeg does, from the
+    /// symbols `count_scans` produces. Tables set by the caller are always used.
+    fn huffman_tables(
+        &mut self,
+        frame_info: &FrameInfo,
+        count_scans: impl FnOnce(&mut Self, &mut [SymbolCounts]) -> Result<(), JpeglsError>,
+    ) -> Result<[(HuffmanTable, HuffmanTable); 2], JpeglsError> {
+        if frame_info.bits_per_sample == 8 || self.custom_huffman_tables {
+            return Ok([
+                (self.dc_table_lum.clone(), self.ac_table_lum.clone()),
+                (self.dc_table_chrom.clone(), self.ac_table_chrom.clone()),
+            ]);
         }
-        // #endregion
-        writer.advance(encoded_len);
-        // #region agent log
-        {
-            use std::fs::OpenOptions;
-            use std::io::Write;
-            if let Ok(mut f) = OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log")
-            {
-                let _ = writeln!(
-                    f,
-                    r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"D","location":"encoder.rs:236","message":"before write_end_of_image","data":{{"writer_position":{},"destination_len":{}}},"timestamp":{}}}"#,
-                    writer.len(),
-                    destination_len,
-                    std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap_or_default()
-                        .as_millis()
-                );
+        let mut counts: [SymbolCounts; 2] = std::array::from_fn(|_| SymbolCounts {
+            dc: [0; 256],
+            ac: [0; 256],
+        });
+        count_scans(self, &mut counts)?;
+        Ok(counts.map(|counts| {
+            (
+                HuffmanTable::optimal(&counts.dc),
+                HuffmanTable::optimal(&counts.ac),
+            )
+        }))
+    }
+
+    /// Writes DQT, DHT and DRI. Grayscale images only need the tables of slot 0.
+    fn write_tables<D: Sink>(
+        &self,
+        writer: &mut JpegStreamWriter<D>,
+        frame_info: &FrameInfo,
+        tables: &[(HuffmanTable, HuffmanTable); 2],
+    ) -> Result<(), JpeglsError> {
+        let slots = if frame_info.component_count == 1 {
+            1
+        } else {
+            2
+        };
+
+        // Write Quantization Tables, in zigzag order
+        let quantization_tables = [&self.quantization_table_lum, &self.quantization_table_chrom];
+        for (id, table) in quantization_tables.into_iter().take(slots).enumerate() {
+            let table = to_zigzag_order(table);
+            if self.quantization_precision == 16 {
+                writer.write_dqt_16bit(id as u8, &table)?;
+            } else {
+                writer.write_dqt(id as u8, &table.map(|value| value as u8))?;
             }
         }
-        // #endregion
-        writer.write_end_of_image()?;
-        let final_len = writer.len();
-        // #region agent log
-        {
-            use std::fs::OpenOptions;
-            use std::io::Write;
-            if let Ok(mut f) = OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log")
-            {
-                let _ = writeln!(
-                    f,
-                    r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"C,D","location":"encoder.rs:243","message":"encoder.encode exit","data":{{"final_len":{},"destination_len":{}}},"timestamp":{}}}"#,
-                    final_len,
-                    destination_len,
-                    std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap_or_default()
-                        .as_millis()
-                );
-            }
+
+        // Write Huffman Tables
+        for (slot, (dc_table, ac_table)) in tables.iter().take(slots).enumerate() {
+            writer.write_dht(0, slot as u8, &dc_table.lengths, &dc_table.values)?;
+            writer.write_dht(1, slot as u8, &ac_table.lengths, &ac_table.values)?;
         }
-        // #endregion
 
-        Ok(final_len)
+        if self.restart_interval > 0 {
+            writer.write_dri(self.restart_interval)?;
+        }
+        Ok(())
     }
 
-    pub fn encode_planar(
-        &mut self,
-        source: &[u8],
+    /// Writes SOF0, or SOF1 for 12-bit samples, with a height of 0 when the height follows
+    /// in a DNL segment.
+    fn write_frame_header<D: Sink>(
+        &self,
+        writer: &mut JpegStreamWriter<D>,
         frame_info: &FrameInfo,
-        destination: &mut [u8],
-    ) -> Result<usize, JpeglsError> {
-        let mut writer = JpegStreamWriter::new(destination);
-
-        let components_count = frame_info.component_count as usize;
-
-        writer.write_start_of_image()?;
-
-        // Write Quantization Tables (same as interleaved)
-        if components_count == 1 {
-            writer.write_dqt(0, &self.quantization_table_lum)?;
+    ) -> Result<(), JpeglsError> {
+        let frame_info = if self.define_number_of_lines {
+            FrameInfo {
+                height: 0,
+                ..*frame_info
+            }
         } else {
-            writer.write_dqt(0, &self.quantization_table_lum)?;
-            writer.write_dqt(1, &self.quantization_table_chrom)?;
-        }
-
-        // Write Huffman Tables (same as interleaved)
-        writer.write_dht(0, 0, &STD_LUMINANCE_DC_LENGTHS, &STD_LUMINANCE_DC_VALUES)?;
-        writer.write_dht(1, 0, &self.ac_table_lum.lengths, &self.ac_table_lum.values)?;
-        if components_count > 1 {
-            writer.write_dht(
-                0,
-                1,
-                &crate::jpeg1::huffman::STD_CHROMINANCE_DC_LENGTHS,
-                &crate::jpeg1::huffman::STD_CHROMINANCE_DC_VALUES,
-            )?;
-            writer.write_dht(
-                1,
-                1,
-                &self.ac_table_chrom.lengths,
-                &self.ac_table_chrom.values,
-            )?;
+            *frame_info
+        };
+        if frame_info.bits_per_sample == 8 {
+            writer.write_sof0_segment(&frame_info)
+        } else {
+            writer.write_sof1_segment(&frame_info)
         }
+    }
 
-        if self.restart_interval > 0 {
-            writer.write_dri(self.restart_interval)?;
+    fn write_define_number_of_lines<D: Sink>(
+        &self,
+        writer: &mut JpegStreamWriter<D>,
+        frame_info: &FrameInfo,
+    ) -> Result<(), JpeglsError> {
+        if self.define_number_of_lines {
+            writer.write_define_number_of_lines_segment(frame_info.height as u16)?;
         }
+        Ok(())
+    }
 
-        writer.write_sof0_segment(frame_info)?;
+    fn encode_non_interleaved<D: Sink>(
+        &mut self,
+        source: &[u8],
+        stride: usize,
+        frame_info: &FrameInfo,
+        writer: &mut JpegStreamWriter<D>,
+    ) -> Result<(), JpeglsError> {
+        let components_count = frame_info.component_count as usize;
+        let samples = Samples::new(source, stride, frame_info);
+        let tables = self.huffman_tables(frame_info, |encoder, counts| {
+            (0..components_count).try_for_each(|comp_idx| {
+                encoder.encode_component_scan(&samples, frame_info, comp_idx, counts)
+            })
+        })?;
 
-        let width = frame_info.width as usize;
-        let height = frame_info.height as usize;
+        writer.write_start_of_image()?;
+        self.write_metadata_segments(writer, frame_info)?;
+        // Tables are shared by all scans (same as interleaved)
+        self.write_tables(writer, frame_info, &tables)?;
+        self.write_frame_header(writer, frame_info)?;
 
         // Loop over each component creating a separate scan
         for comp_idx in 0..components_count {
@@ -419,17 +764,8 @@ impl Jpeg1Encoder {
             // Component ID (1-based)
             writer.write_byte((comp_idx + 1) as u8)?;
 
-            // Tables
-            let (dc_table_id, ac_table_id, quant_table, pred_idx) = if comp_idx == 0 {
-                // Luminance
-                (0x00, 0x00, &self.quantization_table_lum, 0)
-            } else {
-                // Chrominance
-                (0x11, 0x11, &self.quantization_table_chrom, comp_idx) // Use pred_idx 1 and 2
-            };
-
-            // DC/AC table selector
-            let table_sel = (dc_table_id & 0xF0) | (ac_table_id & 0x0F);
+            // DC/AC table selector: luminance 0x00, chrominance 0x11
+            let table_sel = if comp_idx == 0 { 0x00 } else { 0x11 };
             writer.write_byte(table_sel)?;
 
             writer.write_byte(0)?; // Ss
@@ -437,171 +773,464 @@ impl Jpeg1Encoder {
             writer.write_byte(0)?; // Ah/Al
 
             // Encode Scan Data
-            let mut bit_writer_opt = Some(JpegBitWriter::new(writer.remaining_slice()));
-            let mut bit_writer = bit_writer_opt.as_mut().unwrap();
-
-            // Reset DC predictor for this component at start of scan
-            self.huffman.dc_previous_value[pred_idx] = 0;
-            let mut mcus_encoded = 0;
-            let mut next_restart_index = 0;
-            // Total blocks for this component
-            #[allow(clippy::manual_div_ceil)]
-            let total_blocks = ((height + 7) / 8) * ((width + 7) / 8);
-
-            for block_y in (0..height).step_by(8) {
-                for block_x in (0..width).step_by(8) {
-                    // Restart Logic (Per Scan)
-                    if self.restart_interval > 0
-                        && mcus_encoded > 0
-                        && (mcus_encoded % self.restart_interval as usize == 0)
-                        && mcus_encoded < total_blocks
-                    {
-                        bit_writer.flush()?;
-                        let len = bit_writer.len();
-                        let _ = bit_writer_opt.take(); // Release borrow
-
-                        writer.advance(len);
-                        let marker = crate::jpeg_marker_code::JpegMarkerCode::try_from(
-                            0xD0 + (next_restart_index % 8),
-                        )
-                        .unwrap();
-                        writer.write_marker(marker)?;
-                        next_restart_index += 1;
-
-                        bit_writer_opt = Some(JpegBitWriter::new(writer.remaining_slice()));
-                        bit_writer = bit_writer_opt.as_mut().unwrap();
-
-                        self.huffman.dc_previous_value[pred_idx] = 0;
-                    }
-
-                    // Extract Block for Component
-                    let mut block_data = [0.0f32; 64];
-                    for y in 0..8 {
-                        for x in 0..8 {
-                            let py = block_y + y;
-                            let px = block_x + x;
-                            if py < height && px < width {
-                                if components_count == 1 {
-                                    block_data[y * 8 + x] = source[py * width + px] as f32 - 128.0;
-                                } else {
-                                    let idx = (py * width + px) * 3;
-                                    let r = source[idx] as f32;
-                                    let g = source[idx + 1] as f32;
-                                    let b = source[idx + 2] as f32;
-
-                                    if comp_idx == 0 {
-                                        block_data[y * 8 + x] =
-                                            (0.299 * r + 0.587 * g + 0.114 * b) - 128.0;
-                                    } else if comp_idx == 1 {
-                                        block_data[y * 8 + x] =
-                                            (-0.1687 * r - 0.3313 * g + 0.5 * b + 128.0) - 128.0;
-                                    } else {
-                                        block_data[y * 8 + x] =
-                                            (0.5 * r - 0.4187 * g - 0.0813 * b + 128.0) - 128.0;
-                                    }
-                                }
-                            }
-                        }
-                    }
-
-                    let ref_dc = if comp_idx == 0 {
-                        &self.dc_table_lum
-                    } else {
-                        &self.dc_table_chrom
-                    };
-                    let ref_ac = if comp_idx == 0 {
-                        &self.ac_table_lum
-                    } else {
-                        &self.ac_table_chrom
-                    };
-
-                    Self::encode_block_internal(
-                        &mut self.huffman,
-                        &block_data,
-                        bit_writer,
-                        ref_dc,
-                        ref_ac,
-                        quant_table,
-                        pred_idx,
-                    )?;
-                    mcus_encoded += 1;
-                }
+            let mut scan = ScanWriter {
+                bits: JpegBitWriter::with_sink(&mut *writer),
+                tables: &tables,
+            };
+            self.encode_component_scan(&samples, frame_info, comp_idx, &mut scan)?;
+            scan.bits.flush()?;
+            if comp_idx == 0 {
+                self.write_define_number_of_lines(writer, frame_info)?;
             }
-            bit_writer.flush()?;
-            let encoded_len = bit_writer.len();
-            // Advance
-            let _ = bit_writer_opt.take();
-            writer.advance(encoded_len);
         }
 
-        writer.write_end_of_image()?;
-
-        Ok(writer.len())
+        writer.write_end_of_image()
     }
 
-    fn encode_block_internal(
-        huffman: &mut HuffmanEncoder,
-        block: &[f32; 64],
-        bit_writer: &mut JpegBitWriter,
-        dc_table: &HuffmanTable,
-        ac_table: &HuffmanTable,
-        quant_table: &[u8; 64],
-        dc_pred_idx: usize,
+    /// Produces the symbols of the scan of component `comp_idx` alone.
+    fn encode_component_scan<S: SymbolSink + ?Sized>(
+        &mut self,
+        samples: &Samples,
+        frame_info: &FrameInfo,
+        comp_idx: usize,
+        sink: &mut S,
     ) -> Result<(), JpeglsError> {
-        let mut dct_coeffs = [0.0f32; 64];
-        fdct_8x8(block, &mut dct_coeffs);
+        let components_count = frame_info.component_count as usize;
+        let width = frame_info.width as usize;
+        let height = frame_info.height as usize;
+        let center = samples.center;
 
-        let mut quant_coeffs = [0i16; 64];
-        quantize_block(&dct_coeffs, quant_table, &mut quant_coeffs);
+        let (quant_table, pred_idx) = if comp_idx == 0 {
+            // Luminance
+            (&self.quantization_table_lum, 0)
+        } else {
+            // Chrominance
+            (&self.quantization_table_chrom, comp_idx) // Use pred_idx 1 to 3
+        };
 
-        let mut zigzag_coeffs = [0i16; 64];
-        for i in 0..64 {
-            zigzag_coeffs[i] = quant_coeffs[ZIGZAG_ORDER[i]];
-        }
+        // Reset DC predictor for this component at start of scan
+        self.huffman.dc_previous_value[pred_idx] = 0;
+        let mut mcus_encoded = 0;
+        let mut next_restart_index = 0;
+        // Total blocks for this component
+        let total_blocks = height.div_ceil(8) * width.div_ceil(8);
 
-        // DC
-        let dc_val = zigzag_coeffs[0];
-        let diff = dc_val - huffman.dc_previous_value[dc_pred_idx];
+        for block_y in (0..height).step_by(8) {
+            for block_x in (0..width).step_by(8) {
+                // Restart Logic (Per Scan)
+                if self.restart_interval > 0
+                    && mcus_encoded > 0
+                    && (mcus_encoded % self.restart_interval as usize == 0)
+                    && mcus_encoded < total_blocks
+                {
+                    sink.restart((next_restart_index % 8) as u8)?;
+                    next_restart_index += 1;
+
+                    self.huffman.dc_previous_value[pred_idx] = 0;
+                }
+
+                // Extract Block for Component
+                let mut block_data = [0.0f32; 64];
+                for y in 0..8 {
+                    for x in 0..8 {
+                        let py = block_y + y;
+                        let px = block_x + x;
+                        if py < height && px < width {
+                            if components_count == 1 {
+                                block_data[y * 8 + x] = samples.get(py, px) - center;
+                            } else if components_count == 4 {
+                                block_data[y * 8 + x] = samples.get(py, px * 4 + comp_idx) - center;
+                            } else {
+                                let r = samples.get(py, px * 3);
+                                let g = samples.get(py, px * 3 + 1);
+                                let b = samples.get(py, px * 3 + 2);
+
+                                if comp_idx == 0 {
+                                    block_data[y * 8 + x] =
+                                        (0.299 * r + 0.587 * g + 0.114 * b) - center;
+                                } else if comp_idx == 1 {
+                                    block_data[y * 8 + x] =
+                                        (-0.1687 * r - 0.3313 * g + 0.5 * b + center) - center;
+                                } else {
+                                    block_data[y * 8 + x] =
+                                        (0.5 * r - 0.4187 * g - 0.0813 * b + center) - center;
+                                }
+                            }
+                        }
+                    }
+                }
+
+                Self::encode_block_internal(
+                    &mut self.huffman,
+                    &block_data,
+                    sink,
+                    quant_table,
+                    pred_idx,
+                    self.deterministic,
+                )?;
+                mcus_encoded += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Transforms, quantizes and entropy codes one block of component `dc_pred_idx`, which
+    /// also picks the Huffman tables: slot 0 for the first component, slot 1 for the others.
+    fn encode_block_internal<S: SymbolSink + ?Sized>(
+        huffman: &mut HuffmanEncoder,
+        block: &[f32; 64],
+        sink: &mut S,
+        quant_table: &[u16; 64],
+        dc_pred_idx: usize,
+        deterministic: bool,
+    ) -> Result<(), JpeglsError> {
+        let slot = dc_pred_idx.min(1);
+        let mut dct_coeffs = [0.0f32; 64];
+        if deterministic {
+            fdct_8x8_scalar(block, &mut dct_coeffs);
+        } else {
+            fdct_8x8(block, &mut dct_coeffs);
+        }
+
+        let mut quant_coeffs = [0i16; 64];
+        quantize_block(&dct_coeffs, quant_table, &mut quant_coeffs);
+
+        let zigzag_coeffs = to_zigzag_order(&quant_coeffs);
+
+        // DC
+        let dc_val = zigzag_coeffs[0];
+        let diff = dc_val - huffman.dc_previous_value[dc_pred_idx];
         huffman.dc_previous_value[dc_pred_idx] = dc_val;
 
         let dc_category = HuffmanEncoder::get_category(diff);
-        let dc_code = dc_table.codes[dc_category as usize];
-        bit_writer.write_bits(dc_code.value, dc_code.length)?;
         let (dc_bits, dc_bit_len) = HuffmanEncoder::get_diff_bits(diff, dc_category);
-        bit_writer.write_bits(dc_bits, dc_bit_len)?;
+        sink.symbol(slot, false, dc_category, dc_bits, dc_bit_len)?;
 
         // AC
         let mut run = 0;
-        for i in 1..64 {
-            let ac_val = zigzag_coeffs[i];
+        for &ac_val in &zigzag_coeffs[1..] {
             if ac_val == 0 {
                 run += 1;
             } else {
                 while run > 15 {
-                    let zrl_code = ac_table.codes[0xF0];
-                    bit_writer.write_bits(zrl_code.value, zrl_code.length)?;
+                    sink.symbol(slot, true, 0xF0, 0, 0)?;
                     run -= 16;
                 }
                 let category = HuffmanEncoder::get_category(ac_val);
-                let symbol = (run << 4) | (category as usize);
-                let ac_code = ac_table.codes[symbol];
-                bit_writer.write_bits(ac_code.value, ac_code.length)?;
                 let (ac_bits, ac_bit_len) = HuffmanEncoder::get_diff_bits(ac_val, category);
-                bit_writer.write_bits(ac_bits, ac_bit_len)?;
+                sink.symbol(slot, true, (run << 4) | category, ac_bits, ac_bit_len)?;
                 run = 0;
             }
         }
         if run > 0 {
-            let eob_code = ac_table.codes[0x00];
-            bit_writer.write_bits(eob_code.value, eob_code.length)?;
+            sink.symbol(slot, true, 0x00, 0, 0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Configures a [`Jpeg1Encoder`] for images described by one frame info, and checks the
+/// frame and options when the encoder is built. Images encoded with the result must use
+/// that frame info.
+///
+/// ```rust
+/// use jpegexp_rs::jpeg1::Jpeg1EncoderBuilder;
+/// use jpegexp_rs::FrameInfo;
+///
+/// let frame_info = FrameInfo { width: 8, height: 8, bits_per_sample: 8, component_count: 1 };
+/// let mut encoder = Jpeg1EncoderBuilder::new(frame_info).quality(90).build().unwrap();
+/// let mut destination = vec![0u8; 4096];
+/// let len = encoder.encode(&[128u8; 64], &frame_info, &mut destination).unwrap();
+/// assert!(len > 0);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Jpeg1EncoderBuilder {
+    frame_info: FrameInfo,
+    quality: Option<u8>,
+    restart_interval: u16,
+    define_number_of_lines: bool,
+    deterministic: bool,
+    resolution: Option<Resolution>,
+}
+
+impl Jpeg1EncoderBuilder {
+    /// Starts a configuration with the standard quantization tables and no restart markers.
+    pub fn new(frame_info: FrameInfo) -> Self {
+        Self {
+            frame_info,
+            quality: None,
+            restart_interval: 0,
+            define_number_of_lines: false,
+            deterministic: false,
+            resolution: None,
+        }
+    }
+
+    /// See [`Jpeg1Encoder::set_quality`].
+    pub fn quality(mut self, quality: u8) -> Self {
+        self.quality = Some(quality);
+        self
+    }
+
+    /// Restart interval in MCUs; 0 disables restart markers.
+    pub fn restart_interval(mut self, restart_interval: u16) -> Self {
+        self.restart_interval = restart_interval;
+        self
+    }
+
+    /// See [`Jpeg1Encoder::set_define_number_of_lines`].
+    pub fn define_number_of_lines(mut self, enabled: bool) -> Self {
+        self.define_number_of_lines = enabled;
+        self
+    }
+
+    /// See [`Jpeg1Encoder::set_encode_deterministic`].
+    pub fn encode_deterministic(mut self, enabled: bool) -> Self {
+        self.deterministic = enabled;
+        self
+    }
+
+    /// See [`Jpeg1Encoder::set_resolution`].
+    pub fn resolution(mut self, resolution: Resolution) -> Self {
+        self.resolution = Some(resolution);
+        self
+    }
+
+    /// Checks the configuration and returns the encoder.
+    ///
+    /// Returns [`JpeglsError::InvalidArgumentWidth`] or
+    /// [`JpeglsError::InvalidArgumentHeight`] for dimensions outside 1-65535,
+    /// [`JpeglsError::InvalidArgumentComponentCount`] unless there are 1, 3 or 4 components,
+    /// [`JpeglsError::InvalidArgumentBitsPerSample`] for anything but 8-bit or 12-bit
+    /// samples, and
+    /// [`JpeglsError::InvalidArgumentEncodingOptions`] for a quality outside 1-100.
+    pub fn build(self) -> Result<Jpeg1Encoder, JpeglsError> {
+        let frame_info = &self.frame_info;
+        if !(1..=u16::MAX as u32).contains(&frame_info.width) {
+            return Err(JpeglsError::InvalidArgumentWidth);
+        }
+        if !(1..=u16::MAX as u32).contains(&frame_info.height) {
+            return Err(JpeglsError::InvalidArgumentHeight);
+        }
+        check_component_count(frame_info)?;
+        check_bits_per_sample(frame_info)?;
+        if self
+            .quality
+            .is_some_and(|quality| !(1..=100).contains(&quality))
+        {
+            return Err(JpeglsError::InvalidArgumentEncodingOptions);
+        }
+
+        let mut encoder = Jpeg1Encoder::new();
+        if let Some(quality) = self.quality {
+            encoder.set_quality(quality);
+        }
+        encoder.set_restart_interval(self.restart_interval);
+        encoder.set_define_number_of_lines(self.define_number_of_lines);
+        encoder.set_encode_deterministic(self.deterministic);
+        if let Some(resolution) = self.resolution {
+            encoder.set_resolution(resolution);
+        }
+        Ok(encoder)
+    }
+}
+
+/// Baseline images are grayscale (1 component), YCbCr encoded from RGB (3) or CMYK (4);
+/// other component counts are rejected before anything is written.
+fn check_component_count(frame_info: &FrameInfo) -> Result<(), JpeglsError> {
+    if matches!(frame_info.component_count, 1 | 3 | 4) {
+        Ok(())
+    } else {
+        Err(JpeglsError::InvalidArgumentComponentCount)
+    }
+}
+
+/// Samples are 8-bit (SOF0) or 12-bit (SOF1).
+fn check_bits_per_sample(frame_info: &FrameInfo) -> Result<(), JpeglsError> {
+    if matches!(frame_info.bits_per_sample, 8 | 12) {
+        Ok(())
+    } else {
+        Err(JpeglsError::InvalidArgumentBitsPerSample)
+    }
+}
+
+/// Entries are 1-255 in 8-bit tables and 1-65535 in 16-bit tables.
+fn check_quantization_table(table: &[u16; 64], precision: u8) -> Result<(), JpeglsError> {
+    let maximum = match precision {
+        8 => u8::MAX as u16,
+        16 => u16::MAX,
+        _ => return Err(JpeglsError::InvalidArgumentEncodingOptions),
+    };
+    if table.iter().all(|&value| (1..=maximum).contains(&value)) {
+        Ok(())
+    } else {
+        Err(JpeglsError::InvalidArgumentEncodingOptions)
+    }
+}
+
+/// The code counts and symbols must match, as in a DHT segment, and give every symbol one
+/// code (T.81, Annex C).
+fn check_huffman_table(table: &HuffmanTable) -> Result<(), JpeglsError> {
+    let code_count: usize = table.lengths.iter().map(|&count| count as usize).sum();
+    if code_count != table.values.len() {
+        return Err(JpeglsError::InvalidArgumentEncodingOptions);
+    }
+    let mut seen = [false; 256];
+    for &symbol in &table.values {
+        if std::mem::replace(&mut seen[symbol as usize], true) {
+            return Err(JpeglsError::InvalidArgumentEncodingOptions);
+        }
+    }
+    // Codes of each length follow the last code of the length before; they must fit
+    // without using the code of all ones, which T.81 Annex C reserves.
+    let mut next_code = 0u32;
+    for (index, &count) in table.lengths.iter().enumerate() {
+        next_code += count as u32;
+        if next_code >= 1 << (index + 1) {
+            return Err(JpeglsError::InvalidArgumentEncodingOptions);
+        }
+        next_code <<= 1;
+    }
+    Ok(())
+}
+
+/// Resolves the distance in bytes between source rows of 8-bit samples, or 12-bit samples
+/// in 2 bytes, and checks that `source` holds the whole image.
+fn source_stride(
+    source: &[u8],
+    stride: usize,
+    frame_info: &FrameInfo,
+) -> Result<usize, JpeglsError> {
+    let bytes_per_sample = if frame_info.bits_per_sample > 8 { 2 } else { 1 };
+    let row_bytes =
+        frame_info.width as usize * frame_info.component_count as usize * bytes_per_sample;
+    let stride = if stride == AUTO_CALCULATE_STRIDE {
+        row_bytes
+    } else {
+        stride
+    };
+    if stride < row_bytes {
+        return Err(JpeglsError::InvalidArgumentStride);
+    }
+    let height = frame_info.height as usize;
+    if height > 0 && source.len() < (height - 1) * stride + row_bytes {
+        return Err(JpeglsError::InvalidArgumentSize);
+    }
+    Ok(stride)
+}
+
+/// Source samples, 8-bit or 12-bit in 16-bit values of native byte order, and the level
+/// shift that centers them on 0.
+struct Samples<'a> {
+    source: &'a [u8],
+    stride: usize,
+    wide: bool,
+    center: f32,
+}
+
+impl<'a> Samples<'a> {
+    fn new(source: &'a [u8], stride: usize, frame_info: &FrameInfo) -> Self {
+        Self {
+            source,
+            stride,
+            wide: frame_info.bits_per_sample > 8,
+            center: (1 << (frame_info.bits_per_sample - 1)) as f32,
+        }
+    }
+
+    /// Sample `index` of row `y`. The upper 4 bits of 12-bit samples are ignored.
+    fn get(&self, y: usize, index: usize) -> f32 {
+        let row = &self.source[y * self.stride..];
+        if self.wide {
+            (u16::from_ne_bytes([row[2 * index], row[2 * index + 1]]) & 0x0FFF) as f32
+        } else {
+            row[index] as f32
+        }
+    }
+}
+
+/// Receives the Huffman symbols of a scan in stream order: the table slot, whether the
+/// symbol belongs to the AC table, the symbol, and the extra bits that follow it.
+trait SymbolSink {
+    fn symbol(
+        &mut self,
+        slot: usize,
+        is_ac: bool,
+        symbol: u8,
+        bits: u16,
+        bit_count: u8,
+    ) -> Result<(), JpeglsError>;
+
+    /// Ends a restart interval with restart marker `index` (0-7).
+    fn restart(&mut self, index: u8) -> Result<(), JpeglsError>;
+}
+
+/// Writes the symbols with the codes of the DC and AC tables of their slot.
+struct ScanWriter<'a, D: Sink> {
+    bits: JpegBitWriter<D>,
+    tables: &'a [(HuffmanTable, HuffmanTable); 2],
+}
+
+impl<D: Sink> SymbolSink for ScanWriter<'_, D> {
+    fn symbol(
+        &mut self,
+        slot: usize,
+        is_ac: bool,
+        symbol: u8,
+        bits: u16,
+        bit_count: u8,
+    ) -> Result<(), JpeglsError> {
+        let (dc_table, ac_table) = &self.tables[slot];
+        let code = if is_ac {
+            ac_table.codes[symbol as usize]
+        } else {
+            dc_table.codes[symbol as usize]
+        };
+        // Only tables set by the caller can lack a symbol.
+        if code.length == 0 {
+            return Err(JpeglsError::InvalidArgumentEncodingOptions);
+        }
+        self.bits.write_bits(code.value, code.length)?;
+        self.bits.write_bits(bits, bit_count)
+    }
+
+    fn restart(&mut self, index: u8) -> Result<(), JpeglsError> {
+        self.bits.write_restart_marker(index)
+    }
+}
+
+/// Counts the symbols of each slot, to optimize the tables for the image.
+impl SymbolSink for [SymbolCounts] {
+    fn symbol(
+        &mut self,
+        slot: usize,
+        is_ac: bool,
+        symbol: u8,
+        _bits: u16,
+        _bit_count: u8,
+    ) -> Result<(), JpeglsError> {
+        let counts = &mut self[slot];
+        if is_ac {
+            counts.ac[symbol as usize] += 1;
+        } else {
+            counts.dc[symbol as usize] += 1;
         }
         Ok(())
     }
+
+    fn restart(&mut self, _index: u8) -> Result<(), JpeglsError> {
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::jpeg1::dct::idct_8x8;
+    use crate::jpeg1::decoder::{ColorSpace, Jpeg1Decoder};
+    use crate::jpeg1::huffman::JpegBitReader;
+    use crate::jpeg1::metadata::{read_metadata, CodingProcess, HuffmanTableClass};
+    use crate::jpeg1::quantization::to_natural_order;
 
     #[test]
     fn test_encode_decode_roundtrip_grayscale() {
@@ -750,6 +1379,51 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parallel_restart_decode_matches_sequential() {
+        let (width, height) = (40, 24);
+        let source: Vec<u8> = (0..width * height * 3)
+            .map(|i| ((i * 7) ^ (i / 97)) as u8)
+            .collect();
+        for component_count in [1, 3] {
+            let frame_info = FrameInfo {
+                width: width as u32,
+                height: height as u32,
+                bits_per_sample: 8,
+                component_count,
+            };
+            let source = &source[..width * height * component_count as usize];
+            for restart_interval in [1, 4, 7, 100] {
+                let mut encoder = Jpeg1Encoder::new();
+                encoder.set_restart_interval(restart_interval);
+                let mut encoded = vec![0u8; 20000];
+                let interleaved_len = encoder.encode(source, &frame_info, &mut encoded).unwrap();
+                let mut planar = vec![0u8; 20000];
+                let planar_len = encoder
+                    .encode_planar(source, &frame_info, &mut planar)
+                    .unwrap();
+
+                for stream in [&encoded[..interleaved_len], &planar[..planar_len]] {
+                    let mut expected = vec![0u8; source.len()];
+                    let mut decoder = crate::jpeg1::decoder::Jpeg1Decoder::new(stream);
+                    decoder.read_header().unwrap();
+                    decoder.decode(&mut expected).unwrap();
+                    for thread_count in [0, 2, 5] {
+                        let mut decoded = vec![0u8; source.len()];
+                        let mut decoder = crate::jpeg1::decoder::Jpeg1Decoder::new(stream);
+                        decoder.set_thread_count(thread_count);
+                        decoder.read_header().unwrap();
+                        decoder.decode(&mut decoded).unwrap();
+                        assert_eq!(
+                            decoded, expected,
+                            "{component_count} {restart_interval} {thread_count}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_encode_decode_roundtrip_planar() {
         let width = 16;
@@ -807,4 +1481,1052 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_comment_and_application_data_round_trip() {
+        let source = vec![128u8; 8 * 8];
+        let frame_info = FrameInfo {
+            width: 8,
+            height: 8,
+            bits_per_sample: 8,
+            component_count: 1,
+        };
+
+        let mut encoder = Jpeg1Encoder::new();
+        encoder.write_comment(b"jpegexp-rs").unwrap();
+        encoder.write_application_data(11, &[1, 2, 3]).unwrap();
+        assert_eq!(
+            encoder.write_application_data(16, &[]),
+            Err(JpeglsError::InvalidArgument)
+        );
+        let mut encoded = vec![0u8; 4096];
+        let enc_len = encoder.encode(&source, &frame_info, &mut encoded).unwrap();
+        assert_eq!(&encoded[2..6], &[0xFF, 0xFE, 0x00, 12]);
+
+        let mut comments = Vec::new();
+        let mut application_data = Vec::new();
+        {
+            let mut decoder = crate::jpeg1::decoder::Jpeg1Decoder::new(&encoded[..enc_len]);
+            decoder.at_comment(|comment| {
+                comments.push(comment.to_vec());
+                Ok(())
+            });
+            decoder.at_application_data(|id, data| {
+                application_data.push((id, data.to_vec()));
+                Ok(())
+            });
+            decoder.read_header().unwrap();
+            let mut decoded = vec![0u8; source.len()];
+            decoder.decode(&mut decoded).unwrap();
+        }
+        assert_eq!(comments, vec![b"jpegexp-rs".to_vec()]);
+        assert_eq!(application_data, vec![(11, vec![1, 2, 3])]);
+    }
+
+    #[test]
+    fn test_stride_round_trip() {
+        let (width, height) = (12usize, 10usize);
+        let row_bytes = width * 3;
+        let stride = row_bytes + 5;
+        let mut packed = vec![0u8; row_bytes * height];
+        for (i, sample) in packed.iter_mut().enumerate() {
+            *sample = (i * 7 % 256) as u8;
+        }
+        let mut padded = vec![0xEEu8; stride * height];
+        for y in 0..height {
+            padded[y * stride..y * stride + row_bytes]
+                .copy_from_slice(&packed[y * row_bytes..(y + 1) * row_bytes]);
+        }
+        let frame_info = FrameInfo {
+            width: width as u32,
+            height: height as u32,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+
+        let mut expected = vec![0u8; 4096];
+        let expected_len = Jpeg1Encoder::new()
+            .encode(&packed, &frame_info, &mut expected)
+            .unwrap();
+        let mut encoded = vec![0u8; 4096];
+        let mut encoder = Jpeg1Encoder::new();
+        assert_eq!(
+            encoder.encode_with_stride(&padded, row_bytes - 1, &frame_info, &mut encoded),
+            Err(JpeglsError::InvalidArgumentStride)
+        );
+        let encoded_len = encoder
+            .encode_with_stride(&padded, stride, &frame_info, &mut encoded)
+            .unwrap();
+        assert_eq!(encoded[..encoded_len], expected[..expected_len]);
+
+        let mut decoder = crate::jpeg1::decoder::Jpeg1Decoder::new(&encoded[..encoded_len]);
+        decoder.read_header().unwrap();
+        let mut decoded = vec![0x11u8; stride * height];
+        assert_eq!(
+            decoder.decode_with_stride(&mut decoded[..stride * height - 6], stride),
+            Err(JpeglsError::DestinationTooSmall {
+                needed: stride * height - 5
+            })
+        );
+        decoder.decode_with_stride(&mut decoded, stride).unwrap();
+
+        let mut decoder = crate::jpeg1::decoder::Jpeg1Decoder::new(&encoded[..encoded_len]);
+        decoder.read_header().unwrap();
+        let mut packed_decoded = vec![0u8; row_bytes * height];
+        decoder.decode(&mut packed_decoded).unwrap();
+        for y in 0..height {
+            let row = &decoded[y * stride..(y + 1) * stride];
+            assert_eq!(row[..row_bytes], packed_decoded[y * row_bytes..(y + 1) * row_bytes]);
+            assert!(row[row_bytes..].iter().all(|&b| b == 0x11));
+        }
+    }
+
+    #[test]
+    fn test_planar_output_layout() {
+        let (width, height) = (9usize, 11usize);
+        let source: Vec<u8> = (0..width * height * 3).map(|i| (i * 5 % 256) as u8).collect();
+        let frame_info = FrameInfo {
+            width: width as u32,
+            height: height as u32,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let mut encoded = vec![0u8; 4096];
+        let encoded_len = Jpeg1Encoder::new()
+            .encode(&source, &frame_info, &mut encoded)
+            .unwrap();
+
+        let mut decoder = crate::jpeg1::decoder::Jpeg1Decoder::new(&encoded[..encoded_len]);
+        decoder.read_header().unwrap();
+        let mut interleaved = vec![0u8; source.len()];
+        decoder.decode(&mut interleaved).unwrap();
+
+        let mut decoder = crate::jpeg1::decoder::Jpeg1Decoder::new(&encoded[..encoded_len]);
+        decoder.read_header().unwrap();
+        decoder.set_output_layout(crate::OutputLayout::Planar);
+        let mut planar = vec![0u8; source.len()];
+        decoder.decode(&mut planar).unwrap();
+
+        let plane_size = width * height;
+        for (i, pixel) in interleaved.chunks_exact(3).enumerate() {
+            for (c, &sample) in pixel.iter().enumerate() {
+                assert_eq!(planar[c * plane_size + i], sample);
+            }
+        }
+    }
+
+    #[test]
+    fn test_output_scaling() {
+        // A smooth gray ramp, 21x11 so that the scaled sizes are rounded up.
+        let (width, height) = (21usize, 11usize);
+        let ramp = |x: f32, y: f32| x * 6.0 + y * 4.0;
+        let source: Vec<u8> = (0..width * height)
+            .map(|i| ramp((i % width) as f32, (i / width) as f32) as u8)
+            .collect();
+        let frame_info = FrameInfo {
+            width: width as u32,
+            height: height as u32,
+            bits_per_sample: 8,
+            component_count: 1,
+        };
+        let mut encoded = vec![0u8; 4096];
+        let encoded_len = Jpeg1Encoder::new()
+            .encode(&source, &frame_info, &mut encoded)
+            .unwrap();
+
+        for (numerator, scaled_width, scaled_height) in
+            [(1, 3, 2), (2, 6, 3), (3, 8, 5), (4, 11, 6)]
+        {
+            let mut decoder = Jpeg1Decoder::new(&encoded[..encoded_len]);
+            decoder.read_header().unwrap();
+            decoder.set_output_scaling(numerator).unwrap();
+            assert_eq!(decoder.output_size(), (scaled_width, scaled_height));
+            let mut scaled = vec![0u8; (scaled_width * scaled_height) as usize];
+            decoder.decode(&mut scaled).unwrap();
+
+            // Every output sample covers 8/numerator source samples around its center; the
+            // last ones also cover the padding of the partial blocks.
+            let step = 8.0 / numerator as f32;
+            let center = |x: usize| (x as f32 + 0.5) * step - 0.5;
+            let inside = |x: usize, size: usize| (x + 1) as f32 * step <= size as f32;
+            for y in (0..scaled_height as usize).filter(|&y| inside(y, height)) {
+                for x in (0..scaled_width as usize).filter(|&x| inside(x, width)) {
+                    let expected = ramp(center(x), center(y));
+                    let actual = scaled[y * scaled_width as usize + x] as f32;
+                    assert!(
+                        (actual - expected).abs() <= 4.0,
+                        "{numerator}: {actual} vs {expected}"
+                    );
+                }
+            }
+        }
+
+        let mut decoder = Jpeg1Decoder::new(&encoded[..encoded_len]);
+        assert_eq!(
+            decoder.set_output_scaling(0),
+            Err(JpeglsError::InvalidArgument)
+        );
+        assert_eq!(
+            decoder.set_output_scaling(9),
+            Err(JpeglsError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn test_crop() {
+        let (width, height) = (40usize, 37usize);
+        let source: Vec<u8> = (0..width * height * 3)
+            .map(|i| (i * 7 % 256) as u8)
+            .collect();
+        let frame_info = FrameInfo {
+            width: width as u32,
+            height: height as u32,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let mut restart = Jpeg1Encoder::new();
+        restart.set_restart_interval(2);
+        // One scan per component: the scans are skipped below the rectangle too.
+        let mut planar = vec![0u8; 8192];
+        let planar_len = Jpeg1Encoder::new()
+            .encode_planar(&source, &frame_info, &mut planar)
+            .unwrap();
+        planar.truncate(planar_len);
+        let streams = [
+            Jpeg1Encoder::new()
+                .encode_to_vec(&source, &frame_info)
+                .unwrap(),
+            restart.encode_to_vec(&source, &frame_info).unwrap(),
+            planar,
+        ];
+        let decode = |encoded: &[u8], numerator, crop: Option<(u32, u32, u32, u32)>| {
+            let mut decoder = Jpeg1Decoder::new(encoded);
+            decoder.read_header().unwrap();
+            decoder.set_output_scaling(numerator).unwrap();
+            if let Some((x, y, width, height)) = crop {
+                decoder.set_crop(x, y, width, height);
+            }
+            let (width, height) = decoder.output_size();
+            let mut decoded = vec![0u8; width as usize * height as usize * 3];
+            decoder
+                .decode(&mut decoded)
+                .map(|()| (width as usize, decoded))
+        };
+
+        for encoded in &streams {
+            for (numerator, (x, y, crop_width, crop_height)) in [
+                (8, (5, 3, 17, 9)),
+                (8, (16, 16, 24, 21)),
+                (8, (0, 0, 40, 37)),
+                (4, (3, 1, 9, 6)),
+            ] {
+                let (full_width, full) = decode(encoded, numerator, None).unwrap();
+                let (width, cropped) =
+                    decode(encoded, numerator, Some((x, y, crop_width, crop_height))).unwrap();
+                assert_eq!(width, crop_width as usize);
+                for (row, cropped_row) in cropped.chunks_exact(width * 3).enumerate() {
+                    let start = ((y as usize + row) * full_width + x as usize) * 3;
+                    assert_eq!(cropped_row, &full[start..start + width * 3]);
+                }
+            }
+
+            assert_eq!(
+                decode(encoded, 8, Some((30, 0, 11, 1))),
+                Err(JpeglsError::InvalidArgumentWidth)
+            );
+            assert_eq!(
+                decode(encoded, 4, Some((0, 10, 1, 10))),
+                Err(JpeglsError::InvalidArgumentHeight)
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_rows() {
+        let (width, height) = (40usize, 37usize);
+        let source: Vec<u8> = (0..width * height * 3)
+            .map(|i| (i * 7 % 256) as u8)
+            .collect();
+        let frame_info = FrameInfo {
+            width: width as u32,
+            height: height as u32,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let mut restart = Jpeg1Encoder::new();
+        restart.set_restart_interval(3);
+        // One scan per component: its coefficients are read in full.
+        let mut planar = vec![0u8; 8192];
+        let planar_len = Jpeg1Encoder::new()
+            .encode_planar(&source, &frame_info, &mut planar)
+            .unwrap();
+        planar.truncate(planar_len);
+        let baseline = Jpeg1Encoder::new()
+            .encode_to_vec(&source, &frame_info)
+            .unwrap();
+        let streams = [
+            restart.encode_to_vec(&source, &frame_info).unwrap(),
+            planar,
+            baseline.clone(),
+        ];
+        let new_decoder = |encoded, layout, crop: Option<(u32, u32, u32, u32)>| {
+            let mut decoder = Jpeg1Decoder::new(encoded);
+            decoder.read_header().unwrap();
+            decoder.set_output_layout(layout);
+            if let Some((x, y, width, height)) = crop {
+                decoder.set_crop(x, y, width, height);
+            }
+            decoder
+        };
+
+        for encoded in &streams {
+            for layout in [
+                crate::OutputLayout::Interleaved,
+                crate::OutputLayout::Planar,
+            ] {
+                for crop in [None, Some((5, 11, 17, 23))] {
+                    let mut full_decoder = new_decoder(encoded, layout, crop);
+                    let (width, height) = full_decoder.output_size();
+                    let (width, height) = (width as usize, height as usize);
+                    let mut full = vec![0u8; width * height * 3];
+                    full_decoder.decode(&mut full).unwrap();
+
+                    // Strips of 5 rows share MCU rows with the strips next to them.
+                    let mut decoder = new_decoder(encoded, layout, crop);
+                    let mut y = 0;
+                    while y < height {
+                        let rows = 5.min(height - y);
+                        let mut strip = vec![0u8; width * rows * 3];
+                        decoder.decode_rows(&mut strip, rows as u32).unwrap();
+                        for c in 0..3 {
+                            for row in 0..rows {
+                                let (strip_row, full_row) = match layout {
+                                    crate::OutputLayout::Interleaved => {
+                                        (row * width * 3, (y + row) * width * 3)
+                                    }
+                                    crate::OutputLayout::Planar => {
+                                        ((c * rows + row) * width, (c * height + y + row) * width)
+                                    }
+                                };
+                                let length = match layout {
+                                    crate::OutputLayout::Interleaved => width * 3,
+                                    crate::OutputLayout::Planar => width,
+                                };
+                                assert_eq!(
+                                    strip[strip_row..strip_row + length],
+                                    full[full_row..full_row + length]
+                                );
+                            }
+                        }
+                        y += rows;
+                    }
+                    assert_eq!(
+                        decoder.decode_rows(&mut [0u8; 120], 1),
+                        Err(JpeglsError::InvalidArgumentHeight)
+                    );
+                }
+            }
+        }
+
+        // A tolerant decode keeps the rows read before the data ends, as decode does.
+        let truncated = &baseline[..baseline.len() / 2];
+        let mut full_decoder = new_decoder(truncated, crate::OutputLayout::Interleaved, None);
+        full_decoder.set_tolerant(true);
+        let mut full = vec![0u8; source.len()];
+        full_decoder.decode(&mut full).unwrap();
+        let mut decoder = new_decoder(truncated, crate::OutputLayout::Interleaved, None);
+        decoder.set_tolerant(true);
+        let mut strips = vec![0u8; source.len()];
+        for strip in strips.chunks_mut(width * 3 * 8) {
+            let rows = strip.len() / (width * 3);
+            decoder.decode_rows(strip, rows as u32).unwrap();
+        }
+        assert_eq!(strips, full);
+        assert_eq!(decoder.truncation(), full_decoder.truncation());
+        assert!(decoder.truncation().is_some());
+
+        let mut decoder = new_decoder(truncated, crate::OutputLayout::Interleaved, None);
+        let mut all = vec![0u8; source.len()];
+        assert_eq!(
+            decoder.decode_rows(&mut all, height as u32),
+            Err(JpeglsError::InvalidData)
+        );
+    }
+
+    #[test]
+    fn test_quantization_tables_and_quality() {
+        for component_count in [1, 3] {
+            let frame_info = FrameInfo {
+                width: 16,
+                height: 16,
+                bits_per_sample: 8,
+                component_count,
+            };
+            let source = vec![128u8; 16 * 16 * component_count as usize];
+            for quality in 1..=100 {
+                let mut encoder = Jpeg1Encoder::new();
+                encoder.set_quality(quality);
+                let encoded = encoder.encode_to_vec(&source, &frame_info).unwrap();
+
+                let mut decoder = Jpeg1Decoder::new(&encoded);
+                decoder.read_header().unwrap();
+                // DQT segments hold the tables in zigzag order.
+                let zigzag = |table: &[u16; 64]| to_zigzag_order(table).map(|value| value as u8);
+                let tables = decoder.quantization_tables();
+                assert_eq!(tables[0], (0, zigzag(&encoder.quantization_table_lum)));
+                if component_count == 3 {
+                    assert_eq!(tables[1], (1, zigzag(&encoder.quantization_table_chrom)));
+                } else {
+                    assert_eq!(tables.len(), 1);
+                }
+                assert_eq!(decoder.estimate_quality(), Some(quality as u32));
+            }
+        }
+    }
+
+    #[test]
+    fn test_builder_validation() {
+        let frame_info = |width, height, bits_per_sample, component_count| FrameInfo {
+            width,
+            height,
+            bits_per_sample,
+            component_count,
+        };
+        let build = |builder: Jpeg1EncoderBuilder| builder.build().map(|_| ());
+
+        assert_eq!(
+            build(Jpeg1EncoderBuilder::new(frame_info(70_000, 8, 8, 1))),
+            Err(JpeglsError::InvalidArgumentWidth)
+        );
+        assert_eq!(
+            build(Jpeg1EncoderBuilder::new(frame_info(8, 0, 8, 1))),
+            Err(JpeglsError::InvalidArgumentHeight)
+        );
+        assert_eq!(
+            build(Jpeg1EncoderBuilder::new(frame_info(8, 8, 8, 2))),
+            Err(JpeglsError::InvalidArgumentComponentCount)
+        );
+        assert_eq!(
+            build(Jpeg1EncoderBuilder::new(frame_info(8, 8, 16, 1))),
+            Err(JpeglsError::InvalidArgumentBitsPerSample)
+        );
+        assert_eq!(
+            build(Jpeg1EncoderBuilder::new(frame_info(8, 8, 8, 3)).quality(0)),
+            Err(JpeglsError::InvalidArgumentEncodingOptions)
+        );
+
+        let encoder = Jpeg1EncoderBuilder::new(frame_info(8, 8, 8, 3))
+            .quality(90)
+            .restart_interval(4)
+            .build()
+            .unwrap();
+        let mut expected = Jpeg1Encoder::new();
+        expected.set_quality(90);
+        assert_eq!(encoder.quality, 90);
+        assert_eq!(encoder.restart_interval, 4);
+        assert_eq!(
+            encoder.quantization_table_lum,
+            expected.quantization_table_lum
+        );
+    }
+
+    #[test]
+    fn test_destination_too_small_reports_needed_size() {
+        let frame_info = FrameInfo {
+            width: 33,
+            height: 17,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let source: Vec<u8> = (0..33 * 17 * 3).map(|i| (i * 97 % 251) as u8).collect();
+        let mut encoder = Jpeg1Encoder::new();
+        encoder.set_quality(100);
+        encoder.write_comment(b"jpegexp-rs").unwrap();
+
+        let mut small = vec![0u8; 100];
+        let needed = match encoder.encode(&source, &frame_info, &mut small) {
+            Err(JpeglsError::DestinationTooSmall { needed }) => needed,
+            result => panic!("unexpected result {:?}", result),
+        };
+        assert_eq!(needed, encoder.estimated_destination_size(&frame_info));
+
+        let mut destination = vec![0u8; needed];
+        let len = encoder
+            .encode(&source, &frame_info, &mut destination)
+            .unwrap();
+        assert_eq!(
+            encoder.encode_to_vec(&source, &frame_info).unwrap(),
+            destination[..len]
+        );
+    }
+
+    #[test]
+    fn test_define_number_of_lines_round_trip() {
+        let frame_info = FrameInfo {
+            width: 16,
+            height: 11,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let source: Vec<u8> = (0..16 * 11 * 3).map(|i| (i / 3 % 256) as u8).collect();
+        let mut encoder = Jpeg1EncoderBuilder::new(frame_info)
+            .define_number_of_lines(true)
+            .build()
+            .unwrap();
+
+        for planar in [false, true] {
+            let encoded = if planar {
+                let mut destination = vec![0u8; encoder.estimated_destination_size(&frame_info)];
+                let len = encoder
+                    .encode_planar(&source, &frame_info, &mut destination)
+                    .unwrap();
+                destination.truncate(len);
+                destination
+            } else {
+                encoder.encode_to_vec(&source, &frame_info).unwrap()
+            };
+
+            // SOF0 declares 0 lines; the DNL segment after the first scan holds the height.
+            let sof = encoded.windows(2).position(|w| w == [0xFF, 0xC0]).unwrap();
+            assert_eq!(&encoded[sof + 5..sof + 7], &[0, 0]);
+            let dnl = encoded.windows(2).position(|w| w == [0xFF, 0xDC]).unwrap();
+            assert_eq!(&encoded[dnl + 2..dnl + 6], &[0, 4, 0, 11]);
+
+            let mut decoder = crate::jpeg1::decoder::Jpeg1Decoder::new(&encoded);
+            decoder.read_header().unwrap();
+            let mut decoded = vec![0u8; source.len()];
+            decoder.decode(&mut decoded).unwrap();
+            for (i, (&expected, &actual)) in source.iter().zip(&decoded).enumerate() {
+                assert!(
+                    (expected as i32 - actual as i32).abs() < 25,
+                    "planar {planar}: mismatch at index {i}: src={expected} dec={actual}"
+                );
+            }
+        }
+    }
+
+    /// Inserts an APPn segment right after SOI.
+    fn with_application_data(encoded: &[u8], id: u8, data: &[u8]) -> Vec<u8> {
+        let mut result = encoded[..2].to_vec();
+        result.extend_from_slice(&[0xFF, 0xE0 + id]);
+        result.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+        result.extend_from_slice(data);
+        result.extend_from_slice(&encoded[2..]);
+        result
+    }
+
+    fn adobe_segment(transform: u8) -> [u8; 12] {
+        [b'A', b'd', b'o', b'b', b'e', 0, 100, 0, 0, 0, 0, transform]
+    }
+
+    fn decode_with_color_space(encoded: &[u8], len: usize) -> (ColorSpace, Vec<u8>) {
+        let mut decoder = Jpeg1Decoder::new(encoded);
+        decoder.read_header().unwrap();
+        let mut decoded = vec![0u8; len];
+        decoder.decode(&mut decoded).unwrap();
+        (decoder.color_space(), decoded)
+    }
+
+    /// An 8x8 baseline image of four components, each with the flat sample of `samples`.
+    fn four_component_jpeg(samples: [u8; 4], adobe_transform: Option<u8>) -> Vec<u8> {
+        let frame_info = FrameInfo {
+            width: 8,
+            height: 8,
+            bits_per_sample: 8,
+            component_count: 4,
+        };
+        let tables = [
+            (
+                HuffmanTable::standard_luminance_dc(),
+                HuffmanTable::standard_luminance_ac(),
+            ),
+            (
+                HuffmanTable::standard_chrominance_dc(),
+                HuffmanTable::standard_chrominance_ac(),
+            ),
+        ];
+        let mut destination = vec![0u8; 2048];
+        let mut writer = JpegStreamWriter::new(&mut destination);
+        writer.write_start_of_image().unwrap();
+        if let Some(transform) = adobe_transform {
+            writer
+                .write_application_data(14, &adobe_segment(transform))
+                .unwrap();
+        }
+        for (id, (dc, ac)) in tables.iter().enumerate() {
+            writer.write_dqt(id as u8, &[1; 64]).unwrap();
+            writer
+                .write_dht(0, id as u8, &dc.lengths, &dc.values)
+                .unwrap();
+            writer
+                .write_dht(1, id as u8, &ac.lengths, &ac.values)
+                .unwrap();
+        }
+        writer.write_sof0_segment(&frame_info).unwrap();
+        writer.write_sos_segment(4).unwrap();
+
+        let mut bit_writer = JpegBitWriter::new(writer.remaining_slice());
+        for (c, &sample) in samples.iter().enumerate() {
+            let (dc_table, ac_table) = &tables[c.min(1)];
+            // A flat block of level-shifted value v has the DC coefficient 8 * v.
+            let dc = (sample as i16 - 128) * 8;
+            let category = HuffmanEncoder::get_category(dc);
+            let code = dc_table.codes[category as usize];
+            bit_writer.write_bits(code.value, code.length).unwrap();
+            let (bits, length) = HuffmanEncoder::get_diff_bits(dc, category);
+            bit_writer.write_bits(bits, length).unwrap();
+            let end_of_block = ac_table.codes[0];
+            bit_writer
+                .write_bits(end_of_block.value, end_of_block.length)
+                .unwrap();
+        }
+        bit_writer.flush().unwrap();
+        let scan_len = bit_writer.len();
+        writer.advance(scan_len);
+        writer.write_end_of_image().unwrap();
+        let len = writer.len();
+        destination.truncate(len);
+        destination
+    }
+
+    #[test]
+    fn test_color_space_from_jfif_and_adobe_segments() {
+        let frame_info = FrameInfo {
+            width: 16,
+            height: 16,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let source = [200u8, 50, 100].repeat(16 * 16);
+        let mut encoder = Jpeg1EncoderBuilder::new(frame_info).build().unwrap();
+        let encoded = encoder.encode_to_vec(&source, &frame_info).unwrap();
+        let close = |decoded: &[u8], expected: [u8; 3]| {
+            decoded.chunks(3).all(|pixel| {
+                pixel
+                    .iter()
+                    .zip(expected)
+                    .all(|(&actual, expected)| (actual as i32 - expected as i32).abs() <= 3)
+            })
+        };
+
+        // Without JFIF or Adobe segments, components 1, 2 and 3 are YCbCr.
+        let (color_space, decoded) = decode_with_color_space(&encoded, source.len());
+        assert_eq!(color_space, ColorSpace::YCbCr);
+        assert!(close(&decoded, [200, 50, 100]));
+
+        let jfif = with_application_data(&encoded, 0, b"JFIF\0\x01\x02\0\0\x01\0\x01\0\0");
+        let (color_space, decoded) = decode_with_color_space(&jfif, source.len());
+        assert_eq!(color_space, ColorSpace::YCbCr);
+        assert!(close(&decoded, [200, 50, 100]));
+
+        // Adobe transform 0 marks the components as RGB: the stored YCbCr comes out as is.
+        let adobe_rgb = with_application_data(&encoded, 14, &adobe_segment(0));
+        let (color_space, decoded) = decode_with_color_space(&adobe_rgb, source.len());
+        assert_eq!(color_space, ColorSpace::Rgb);
+        assert!(close(&decoded, [101, 128, 199]));
+
+        let adobe_ycbcr = with_application_data(&encoded, 14, &adobe_segment(1));
+        let (color_space, _) = decode_with_color_space(&adobe_ycbcr, source.len());
+        assert_eq!(color_space, ColorSpace::YCbCr);
+
+        // Without either segment, the component identifiers 'R', 'G' and 'B' mean RGB.
+        let mut rgb_ids = encoded.clone();
+        let sof = rgb_ids.windows(2).position(|w| w == [0xFF, 0xC0]).unwrap();
+        let sos = rgb_ids.windows(2).position(|w| w == [0xFF, 0xDA]).unwrap();
+        for (i, id) in [b'R', b'G', b'B'].into_iter().enumerate() {
+            rgb_ids[sof + 10 + 3 * i] = id;
+            rgb_ids[sos + 5 + 2 * i] = id;
+        }
+        let (color_space, decoded) = decode_with_color_space(&rgb_ids, source.len());
+        assert_eq!(color_space, ColorSpace::Rgb);
+        assert!(close(&decoded, [101, 128, 199]));
+    }
+
+    #[test]
+    fn test_encode_cmyk() {
+        let frame_info = FrameInfo {
+            width: 13,
+            height: 9,
+            bits_per_sample: 8,
+            component_count: 4,
+        };
+        let source = [10u8, 60, 200, 250].repeat(13 * 9);
+        let mut encoder = Jpeg1EncoderBuilder::new(frame_info)
+            .quality(95)
+            .build()
+            .unwrap();
+        let mut destination = vec![0u8; encoder.estimated_destination_size(&frame_info)];
+        let len = encoder
+            .encode_planar(&source, &frame_info, &mut destination)
+            .unwrap();
+
+        for encoded in [
+            encoder.encode_to_vec(&source, &frame_info).unwrap(),
+            destination[..len].to_vec(),
+        ] {
+            let adobe = encoded.windows(2).filter(|w| w == &[0xFF, 0xEE]).count();
+            assert_eq!(adobe, 1);
+            let (color_space, decoded) = decode_with_color_space(&encoded, source.len());
+            assert_eq!(color_space, ColorSpace::Cmyk);
+            // The partial blocks at the right and bottom edges are padded with zeros.
+            for (&expected, &actual) in source.iter().zip(&decoded) {
+                assert!((expected as i32 - actual as i32).abs() <= 8);
+            }
+        }
+
+        // An Adobe segment of the caller replaces the default one.
+        encoder
+            .write_application_data(14, &adobe_segment(2))
+            .unwrap();
+        let encoded = encoder.encode_to_vec(&source, &frame_info).unwrap();
+        assert_eq!(encoded.windows(2).filter(|w| w == &[0xFF, 0xEE]).count(), 1);
+        let (color_space, _) = decode_with_color_space(&encoded, source.len());
+        assert_eq!(color_space, ColorSpace::Ycck);
+
+        // Two components (gray and alpha) are rejected before anything is written.
+        let frame_info = FrameInfo {
+            component_count: 2,
+            ..frame_info
+        };
+        let mut destination = vec![0u8; 4096];
+        for planar in [false, true] {
+            let result = if planar {
+                Jpeg1Encoder::new().encode_planar(&source, &frame_info, &mut destination)
+            } else {
+                Jpeg1Encoder::new().encode(&source, &frame_info, &mut destination)
+            };
+            assert_eq!(result, Err(JpeglsError::InvalidArgumentComponentCount));
+        }
+        assert!(destination.iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn test_decode_cmyk_and_ycck() {
+        let cmyk = four_component_jpeg([10, 60, 200, 250], None);
+        let (color_space, decoded) = decode_with_color_space(&cmyk, 8 * 8 * 4);
+        assert_eq!(color_space, ColorSpace::Cmyk);
+        assert!(decoded.chunks(4).all(|pixel| pixel == [10, 60, 200, 250]));
+
+        let adobe_cmyk = four_component_jpeg([10, 60, 200, 250], Some(0));
+        let (color_space, decoded) = decode_with_color_space(&adobe_cmyk, 8 * 8 * 4);
+        assert_eq!(color_space, ColorSpace::Cmyk);
+        assert!(decoded.chunks(4).all(|pixel| pixel == [10, 60, 200, 250]));
+
+        // YCbCr (100, 128, 200) is RGB (200, 48, 100); YCCK stores the inverse of C, M, Y.
+        let ycck = four_component_jpeg([100, 128, 200, 40], Some(2));
+        let (color_space, decoded) = decode_with_color_space(&ycck, 8 * 8 * 4);
+        assert_eq!(color_space, ColorSpace::Ycck);
+        for pixel in decoded.chunks(4) {
+            for (&actual, expected) in pixel.iter().zip([55, 207, 155, 40]) {
+                assert!((actual as i32 - expected).abs() <= 1, "{pixel:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_set_quantization_tables() {
+        let frame_info = FrameInfo {
+            width: 16,
+            height: 16,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let source: Vec<u8> = (0..16 * 16 * 3)
+            .map(|i| (i / 3 % 16 * 9 + i / 48 * 5 + i % 3 * 20) as u8)
+            .collect();
+        // Fine steps for the low frequencies of the first row only, so the order matters.
+        let luminance: [u16; 64] = std::array::from_fn(|i| if i < 4 { 1 } else { 20 + i as u16 });
+        let chrominance = [30u16; 64];
+
+        let mut encoder = Jpeg1Encoder::new();
+        let mut invalid = luminance;
+        invalid[5] = 0;
+        for (table, precision) in [(invalid, 8), ([256; 64], 8), (luminance, 12)] {
+            assert_eq!(
+                encoder.set_quantization_tables(&table, &chrominance, precision),
+                Err(JpeglsError::InvalidArgumentEncodingOptions)
+            );
+        }
+        assert_eq!(
+            encoder.quantization_table_lum,
+            STD_LUMINANCE_QUANT_TABLE.map(u16::from)
+        );
+
+        encoder
+            .set_quantization_tables(&luminance, &chrominance, 8)
+            .unwrap();
+        let encoded = encoder.encode_to_vec(&source, &frame_info).unwrap();
+        let metadata = read_metadata(&encoded).unwrap();
+        assert_eq!(metadata.quantization_tables[0].precision, 8);
+        assert_eq!(
+            metadata.quantization_tables[0].values,
+            to_zigzag_order(&luminance)
+        );
+        assert_eq!(metadata.quantization_tables[1].values, chrominance);
+
+        let mut decoder = Jpeg1Decoder::new(&encoded);
+        decoder.read_header().unwrap();
+        let mut decoded = vec![0u8; source.len()];
+        decoder.decode(&mut decoded).unwrap();
+        let max_error = source
+            .iter()
+            .zip(&decoded)
+            .map(|(&a, &b)| (a as i32 - b as i32).abs())
+            .max()
+            .unwrap();
+        assert!(max_error <= 48, "max error {max_error}");
+
+        // 16-bit tables are only allowed with 12-bit samples.
+        encoder
+            .set_quantization_tables(&[300; 64], &[300; 64], 16)
+            .unwrap();
+        assert_eq!(encoder.quantization_precision(), 16);
+        assert_eq!(
+            encoder.encode_to_vec(&source, &frame_info),
+            Err(JpeglsError::InvalidArgumentEncodingOptions)
+        );
+        encoder.set_quality(75);
+        assert_eq!(encoder.quantization_precision(), 8);
+        assert!(encoder.encode_to_vec(&source, &frame_info).is_ok());
+    }
+
+    /// Decodes a 12-bit grayscale stream without restart markers with the tables of its
+    /// segments; the decoder reconstructs 8-bit samples only.
+    fn decode_12_bit_grayscale(encoded: &[u8], width: usize, height: usize) -> Vec<u16> {
+        let metadata = read_metadata(encoded).unwrap();
+        let huffman_table = |class| {
+            let spec = metadata
+                .huffman_tables
+                .iter()
+                .find(|table| table.class == class && table.id == 0)
+                .unwrap();
+            HuffmanTable::build_from_dht(&spec.code_counts, &spec.symbols)
+        };
+        let dc_table = huffman_table(HuffmanTableClass::Dc);
+        let ac_table = huffman_table(HuffmanTableClass::Ac);
+        let quant_table = to_natural_order(&metadata.quantization_tables[0].values);
+        // The entropy-coded data follows the SOS segment of one component.
+        let mut reader = JpegBitReader::new(&encoded[metadata.scans[0].offset + 10..]);
+        let read_value = |reader: &mut JpegBitReader, category| {
+            HuffmanEncoder::decode_value_bits(reader.read_bits(category).unwrap(), category)
+        };
+
+        let blocks_wide = width.div_ceil(8);
+        let mut pixels = vec![0u16; width * height];
+        let mut dc = 0i16;
+        for block in 0..blocks_wide * height.div_ceil(8) {
+            let mut coefficients = [0.0f32; 64];
+            let category = dc_table.decode(&mut reader).unwrap();
+            dc += read_value(&mut reader, category);
+            coefficients[0] = dc as f32 * quant_table[0] as f32;
+            let mut k = 1;
+            while k < 64 {
+                let symbol = ac_table.decode(&mut reader).unwrap();
+                let (run, size) = ((symbol >> 4) as usize, symbol & 15);
+                if size == 0 && run != 15 {
+                    break;
+                }
+                k += run;
+                if size > 0 {
+                    let position = ZIGZAG_ORDER[k];
+                    coefficients[position] =
+                        read_value(&mut reader, size) as f32 * quant_table[position] as f32;
+                }
+                k += 1;
+            }
+            let mut samples = [0.0f32; 64];
+            idct_8x8(&coefficients, &mut samples);
+            for (i, sample) in samples.iter().enumerate() {
+                let x = block % blocks_wide * 8 + i % 8;
+                let y = block / blocks_wide * 8 + i / 8;
+                if x < width && y < height {
+                    pixels[y * width + x] = (sample + 2048.0).round().clamp(0.0, 4095.0) as u16;
+                }
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn test_encode_12_bit() {
+        let (width, height) = (24, 16);
+        let source: Vec<u16> = (0..width * height)
+            .map(|i| {
+                // Steep gradients, for differences only 12-bit samples have.
+                let (x, y) = (i % width, i / width);
+                (x * 170 + y * 10) as u16
+            })
+            .collect();
+        let bytes: Vec<u8> = source
+            .iter()
+            .flat_map(|sample| sample.to_ne_bytes())
+            .collect();
+        let frame_info = FrameInfo {
+            width: width as u32,
+            height: height as u32,
+            bits_per_sample: 12,
+            component_count: 1,
+        };
+        // Within whole blocks, gradients only have coefficients in the first row and column.
+        let luminance: [u16; 64] = std::array::from_fn(|i| {
+            if i < 8 || i % 8 == 0 {
+                1 + i as u16
+            } else {
+                300 + i as u16
+            }
+        });
+
+        for precision in [8, 16] {
+            let luminance =
+                luminance.map(|value| value.min(if precision == 8 { 255 } else { 1000 }));
+            let mut encoder = Jpeg1EncoderBuilder::new(frame_info).build().unwrap();
+            encoder
+                .set_quantization_tables(&luminance, &[1; 64], precision)
+                .unwrap();
+            let encoded = encoder.encode_to_vec(&bytes, &frame_info).unwrap();
+
+            let metadata = read_metadata(&encoded).unwrap();
+            let frame = metadata.frame.as_ref().unwrap();
+            assert_eq!(frame.process, CodingProcess::ExtendedSequential);
+            assert_eq!(frame.precision, 12);
+            assert_eq!(metadata.quantization_tables.len(), 1);
+            assert_eq!(metadata.quantization_tables[0].precision, precision);
+            assert_eq!(
+                metadata.quantization_tables[0].values,
+                to_zigzag_order(&luminance)
+            );
+
+            let decoded = decode_12_bit_grayscale(&encoded, width, height);
+            let max_error = source
+                .iter()
+                .zip(&decoded)
+                .map(|(&a, &b)| (a as i32 - b as i32).abs())
+                .max()
+                .unwrap();
+            assert!(max_error <= 4, "max error {max_error}");
+        }
+
+        // Color and non-interleaved scans share the optimized tables.
+        let frame_info = FrameInfo {
+            component_count: 3,
+            ..frame_info
+        };
+        let color: Vec<u8> = bytes
+            .iter()
+            .cycle()
+            .take(bytes.len() * 3)
+            .copied()
+            .collect();
+        let mut encoder = Jpeg1Encoder::new();
+        encoder.set_restart_interval(2);
+        for planar in [false, true] {
+            let encoded = if planar {
+                let mut destination = vec![0u8; encoder.estimated_destination_size(&frame_info)];
+                let len = encoder
+                    .encode_planar(&color, &frame_info, &mut destination)
+                    .unwrap();
+                destination.truncate(len);
+                destination
+            } else {
+                encoder.encode_to_vec(&color, &frame_info).unwrap()
+            };
+            let metadata = read_metadata(&encoded).unwrap();
+            assert_eq!(metadata.frame.unwrap().precision, 12);
+            assert_eq!(metadata.huffman_tables.len(), 4);
+            assert_eq!(metadata.scans.len(), if planar { 3 } else { 1 });
+            assert!(metadata
+                .scans
+                .iter()
+                .all(|scan| scan.restart_marker_count > 0));
+        }
+    }
+
+    #[test]
+    fn test_set_huffman_tables() {
+        let frame_info = FrameInfo {
+            width: 16,
+            height: 16,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let source: Vec<u8> = (0..16 * 16 * 3)
+            .map(|i| (i / 3 % 16 * 9 + i / 48 * 5 + i % 3 * 20) as u8)
+            .collect();
+        // Tables of another encoder: every DC category, and AC symbols up to size 10.
+        let mut frequencies = [0u32; 256];
+        frequencies[..12].copy_from_slice(&[9, 8, 7, 6, 5, 4, 3, 2, 1, 1, 1, 1]);
+        let dc = HuffmanTable::optimal(&frequencies);
+        let mut frequencies = [0u32; 256];
+        for run in 0..16 {
+            for size in 1..=10 {
+                frequencies[run << 4 | size] = 1 + (16 - run as u32) * (11 - size as u32);
+            }
+        }
+        frequencies[0x00] = 500;
+        frequencies[0xF0] = 1;
+        let ac = HuffmanTable::optimal(&frequencies);
+
+        let mut encoder = Jpeg1Encoder::new();
+        encoder.set_huffman_tables(&dc, &ac, &dc, &ac).unwrap();
+        let encoded = encoder.encode_to_vec(&source, &frame_info).unwrap();
+        let metadata = read_metadata(&encoded).unwrap();
+        assert_eq!(metadata.huffman_tables.len(), 4);
+        for spec in &metadata.huffman_tables {
+            let table = if spec.class == HuffmanTableClass::Dc {
+                &dc
+            } else {
+                &ac
+            };
+            assert_eq!(
+                (spec.code_counts, &spec.symbols),
+                (table.lengths, &table.values)
+            );
+        }
+        let mut decoder = Jpeg1Decoder::new(&encoded);
+        decoder.read_header().unwrap();
+        let mut decoded = vec![0u8; source.len()];
+        decoder.decode(&mut decoded).unwrap();
+        let standard = Jpeg1Encoder::new()
+            .encode_to_vec(&source, &frame_info)
+            .unwrap();
+        let mut expected = vec![0u8; source.len()];
+        let mut decoder = Jpeg1Decoder::new(&standard);
+        decoder.read_header().unwrap();
+        decoder.decode(&mut expected).unwrap();
+        assert_eq!(decoded, expected);
+
+        // Tables that do not match their code counts, repeat a symbol, overflow a length or
+        // fill it up to the code of all ones.
+        let standard_dc = HuffmanTable::standard_luminance_dc();
+        let mut short = standard_dc.clone();
+        short.values.pop();
+        let mut repeated = standard_dc.clone();
+        repeated.values[1] = repeated.values[0];
+        let mut overfull = standard_dc.clone();
+        overfull.lengths[0] = 3;
+        overfull.values.extend([12, 13, 14]);
+        let full = HuffmanTable::build_from_dht(
+            &[0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            &[0, 1, 2, 3],
+        );
+        for table in [short, repeated, overfull, full] {
+            assert_eq!(
+                encoder.set_huffman_tables(&table, &ac, &dc, &ac),
+                Err(JpeglsError::InvalidArgumentEncodingOptions)
+            );
+        }
+
+        // A DC table without the larger categories cannot code the image.
+        let small_dc = HuffmanTable::build_from_dht(
+            &[0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            &[0, 1, 2],
+        );
+        encoder
+            .set_huffman_tables(&small_dc, &ac, &small_dc, &ac)
+            .unwrap();
+        assert_eq!(
+            encoder.encode_to_vec(&source, &frame_info),
+            Err(JpeglsError::InvalidArgumentEncodingOptions)
+        );
+    }
 }
@@ -1,6 +1,7 @@
 //! Huffman coding implementation for JPEG 1 Baseline.
 
 use crate::error::JpeglsError;
+use crate::jpeg_stream_writer::{Sink, SliceSink};
 
 #[derive(Debug, Clone, Copy, Default)]
 pub struct HuffmanCode {
@@ -149,6 +150,83 @@ impl HuffmanTable {
     pub fn standard_chrominance_ac() -> Self {
         Self::build_from_dht(&STD_CHROMINANCE_AC_LENGTHS, &STD_CHROMINANCE_AC_VALUES)
     }
+
+    /// Builds the optimal table for the given symbol frequencies, following ITU-T T.81
+    /// Annex K.2: code lengths are limited to 16 bits and no code consists of all 1 bits.
+    pub fn optimal(frequencies: &[u32; 256]) -> Self {
+        // Symbol 256 is reserved so that no real symbol gets the all-ones code.
+        let mut freq = [0u64; 257];
+        for (f, &count) in freq.iter_mut().zip(frequencies) {
+            *f = count as u64;
+        }
+        freq[256] = 1;
+        let mut code_size = [0usize; 257];
+        let mut others = [usize::MAX; 257];
+
+        loop {
+            // v1: least frequency, v2: next least; ties go to the larger symbol.
+            let mut v1 = None;
+            let mut v2 = None;
+            for i in 0..257 {
+                if freq[i] == 0 {
+                    continue;
+                }
+                if v1.is_none_or(|v: usize| freq[i] <= freq[v]) {
+                    v2 = v1;
+                    v1 = Some(i);
+                } else if v2.is_none_or(|v: usize| freq[i] <= freq[v]) {
+                    v2 = Some(i);
+                }
+            }
+            let (Some(mut v1), Some(mut v2)) = (v1, v2) else { break };
+
+            freq[v1] += freq[v2];
+            freq[v2] = 0;
+            code_size[v1] += 1;
+            while others[v1] != usize::MAX {
+                v1 = others[v1];
+                code_size[v1] += 1;
+            }
+            others[v1] = v2;
+            code_size[v2] += 1;
+            while others[v2] != usize::MAX {
+                v2 = others[v2];
+                code_size[v2] += 1;
+            }
+        }
+
+        let mut bits = [0u32; 33];
+        for &size in code_size.iter().filter(|&&size| size > 0) {
+            bits[size.min(32)] += 1;
+        }
+        // Limit code lengths to 16 bits (Figure K.3).
+        for i in (17..=32).rev() {
+            while bits[i] > 0 {
+                let mut j = i - 2;
+                while bits[j] == 0 {
+                    j -= 1;
+                }
+                bits[i] -= 2;
+                bits[i - 1] += 1;
+                bits[j + 1] += 2;
+                bits[j] -= 1;
+            }
+        }
+        // Remove the reserved symbol from the longest codes.
+        if let Some(longest) = (1..=16).rev().find(|&i| bits[i] > 0) {
+            bits[longest] -= 1;
+        }
+
+        let mut lengths = [0u8; 16];
+        for (length, &count) in lengths.iter_mut().zip(&bits[1..=16]) {
+            *length = count as u8;
+        }
+        // Symbols in order of increasing code size (Figure K.4); the adjusted counts in
+        // `lengths` assign the actual code lengths in this order.
+        let mut values: Vec<u8> = (0..=255u8).filter(|&v| code_size[v as usize] > 0).collect();
+        values.sort_by_key(|&v| code_size[v as usize]);
+        Self::build_from_dht(&lengths, &values)
+    }
 }
 
 pub struct JpegBitReader<'a> {
@@ -158,11 +236,37 @@ pub struct JpegBitReader<'a> {
     bits_in_buffer: i32,
 }
 
+/// Where a [`JpegBitReader`] stopped, to go on reading the same data later.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct BitPosition {
+    position: usize,
+    bit_buffer: u32,
+    bits_in_buffer: i32,
+}
+
 impl<'a> JpegBitReader<'a> {
     pub fn new(source: &'a [u8]) -> Self {
         Self { source, position: 0, bit_buffer: 0, bits_in_buffer: 0 }
     }
 
+    /// Continues reading `source` at a position taken from a reader of the same data.
+    pub(crate) fn resume(source: &'a [u8], at: BitPosition) -> Self {
+        Self {
+            source,
+            position: at.position,
+            bit_buffer: at.bit_buffer,
+            bits_in_buffer: at.bits_in_buffer,
+        }
+    }
+
+    pub(crate) fn bit_position(&self) -> BitPosition {
+        BitPosition {
+            position: self.position,
+            bit_buffer: self.bit_buffer,
+            bits_in_buffer: self.bits_in_buffer,
+        }
+    }
+
     pub fn read_bits(&mut self, count: u8) -> Result<u16, JpeglsError> {
         if count == 0 { return Ok(0); }
         let count = count as i32;
@@ -234,16 +338,23 @@ impl<'a> JpegBitReader<'a> {
     }
 }
 
-pub struct JpegBitWriter<'a> {
-    destination: &'a mut [u8],
+/// Writes the entropy-coded data of a scan, with a 0x00 stuffed after every 0xFF byte.
+pub struct JpegBitWriter<S: Sink> {
+    sink: S,
     position: usize,
     bit_buffer: u32,
     bits_in_buffer: i32,
 }
 
-impl<'a> JpegBitWriter<'a> {
+impl<'a> JpegBitWriter<SliceSink<'a>> {
     pub fn new(destination: &'a mut [u8]) -> Self {
-        Self { destination, position: 0, bit_buffer: 0, bits_in_buffer: 0 }
+        Self::with_sink(SliceSink::new(destination))
+    }
+}
+
+impl<S: Sink> JpegBitWriter<S> {
+    pub fn with_sink(sink: S) -> Self {
+        Self { sink, position: 0, bit_buffer: 0, bits_in_buffer: 0 }
     }
 
     pub fn write_bits(&mut self, value: u16, length: u8) -> Result<(), JpeglsError> {
@@ -266,14 +377,10 @@ impl<'a> JpegBitWriter<'a> {
     }
 
     fn emit_byte(&mut self, byte: u8) -> Result<(), JpeglsError> {
-        if self.position >= self.destination.len() { return Err(JpeglsError::ParameterValueNotSupported); }
-        self.destination[self.position] = byte;
-        self.position += 1;
-        if byte == 0xFF {
-            if self.position >= self.destination.len() { return Err(JpeglsError::ParameterValueNotSupported); }
-            self.destination[self.position] = 0x00;
-            self.position += 1;
-        }
+        // A byte and its stuffed zero are written together.
+        let bytes: &[u8] = if byte == 0xFF { &[0xFF, 0x00] } else { &[byte] };
+        self.sink.write(bytes)?;
+        self.position += bytes.len();
         Ok(())
     }
 
@@ -285,6 +392,15 @@ impl<'a> JpegBitWriter<'a> {
         Ok(())
     }
 
+    /// Pads the last byte with 1 bits and writes restart marker RST`index` (0-7), which is
+    /// not byte stuffed.
+    pub fn write_restart_marker(&mut self, index: u8) -> Result<(), JpeglsError> {
+        self.flush()?;
+        self.sink.write(&[0xFF, 0xD0 + index])?;
+        self.position += 2;
+        Ok(())
+    }
+
     pub fn len(&self) -> usize { self.position }
 
     pub fn is_empty(&self) -> bool { self.position == 0 }
@@ -310,7 +426,7 @@ impl HuffmanEncoder {
     }
     pub fn get_diff_bits(v: i16, cat: u8) -> (u16, u8) {
         if cat == 0 { return (0, 0); }
-        if v >= 0 { (v as u16, cat) } else { ((v + (1 << cat) - 1) as u16, cat) }
+        if v >= 0 { (v as u16, cat) } else { ((v as i32 + (1 << cat) - 1) as u16, cat) }
     }
     pub fn decode_value_bits(bits: u16, cat: u8) -> i16 {
         if cat == 0 { return 0; }
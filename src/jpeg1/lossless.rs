@@ -39,55 +39,77 @@ impl Jpeg1LosslessDecoder {
         huffman_table: &HuffmanTable,
     ) -> Result<Vec<i32>, JpeglsError> {
         let mut pixels = vec![0i32; width * height];
-
         for y in 0..height {
-            for x in 0..width {
-                // 1. Decode category (K) from Huffman table
-                let cat = huffman_table.decode(reader)?;
-
-                // 2. Read 'cat' additional bits
-                let bits = reader.read_bits(cat)?;
-
-                // 3. Convert bits to signed difference
-                let diff = HuffmanEncoder::decode_value_bits(bits, cat) as i32;
-
-                // 4. Calculate prediction
-                // Handle boundaries as per T.81
-                let ra = if x > 0 {
-                    pixels[y * width + x - 1]
-                } else if y > 0 {
-                    pixels[(y - 1) * width + x]
-                } else {
-                    1 << (bit_depth - 1)
-                };
-
-                let rb = if y > 0 {
-                    pixels[(y - 1) * width + x]
-                } else {
-                    ra
-                };
-
-                let rc = if x > 0 && y > 0 {
-                    pixels[(y - 1) * width + x - 1]
-                } else {
-                    rb
-                };
-
-                let px = if x == 0 && y == 0 {
-                    1 << (bit_depth - 1)
-                } else if y == 0 {
-                    ra // Special case for first row: use predictor 1
-                } else if x == 0 {
-                    rb // Special case for first column: use predictor 2
-                } else {
-                    LosslessPredictor::predict(predictor_id, ra, rb, rc)
-                };
-
-                pixels[y * width + x] = px + diff;
-            }
+            Self::decode_row(
+                predictor_id,
+                y,
+                width,
+                bit_depth,
+                reader,
+                huffman_table,
+                &mut pixels,
+            )?;
         }
         Ok(pixels)
     }
+
+    /// Decodes row `y` of a component of a lossless scan into `pixels`, which holds the
+    /// rows of `width` samples decoded before it.
+    pub fn decode_row(
+        predictor_id: u8,
+        y: usize,
+        width: usize,
+        bit_depth: u8,
+        reader: &mut JpegBitReader,
+        huffman_table: &HuffmanTable,
+        pixels: &mut [i32],
+    ) -> Result<(), JpeglsError> {
+        for x in 0..width {
+            // 1. Decode category (K) from Huffman table
+            let cat = huffman_table.decode(reader)?;
+
+            // 2. Read 'cat' additional bits
+            let bits = reader.read_bits(cat)?;
+
+            // 3. Convert bits to signed difference
+            let diff = HuffmanEncoder::decode_value_bits(bits, cat) as i32;
+
+            // 4. Calculate prediction
+            // Handle boundaries as per T.81
+            let ra = if x > 0 {
+                pixels[y * width + x - 1]
+            } else if y > 0 {
+                pixels[(y - 1) * width + x]
+            } else {
+                1 << (bit_depth - 1)
+            };
+
+            let rb = if y > 0 {
+                pixels[(y - 1) * width + x]
+            } else {
+                ra
+            };
+
+            let rc = if x > 0 && y > 0 {
+                pixels[(y - 1) * width + x - 1]
+            } else {
+                rb
+            };
+
+            let px = if x == 0 && y == 0 {
+                1 << (bit_depth - 1)
+            } else if y == 0 {
+                ra // Special case for first row: use predictor 1
+            } else if x == 0 {
+                rb // Special case for first column: use predictor 2
+            } else {
+                LosslessPredictor::predict(predictor_id, ra, rb, rc)
+            };
+
+            pixels[y * width + x] = px + diff;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,497 @@
+//! Structured header metadata of JPEG 1 streams.
+//!
+//! [`read_metadata`] walks every marker segment of a stream and returns the frame, table
+//! and scan parameters without decoding any entropy-coded data, e.g. to tell progressive
+//! from baseline files, list the scan script of a progressive file or compare the tables
+//! of two encoders:
+//!
+//! ```rust
+//! use jpegexp_rs::jpeg1::{read_metadata, CodingProcess, Jpeg1Encoder};
+//! use jpegexp_rs::FrameInfo;
+//!
+//! let frame_info = FrameInfo { width: 16, height: 16, bits_per_sample: 8, component_count: 1 };
+//! let data = Jpeg1Encoder::new().encode_to_vec(&[128; 256], &frame_info).unwrap();
+//! let metadata = read_metadata(&data).unwrap();
+//! assert_eq!(metadata.frame.unwrap().process, CodingProcess::Baseline);
+//! assert_eq!(metadata.scans.len(), 1);
+//! ```
+//!
+//! Unlike [`dump`](crate::dump::dump), which describes any stream for debugging, the
+//! result holds typed values and a malformed segment is an error.
+
+use crate::error::JpeglsError;
+use crate::jpeg_stream_reader::is_jpegls;
+use crate::resolution::Resolution;
+
+/// Header parameters of a JPEG 1 stream, in file order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JpegMetadata {
+    /// Every marker of the stream except the restart markers inside entropy-coded data,
+    /// which are counted per scan.
+    pub markers: Vec<Marker>,
+    /// The SOF segment, if the stream has one (an abbreviated table specification has
+    /// none).
+    pub frame: Option<FrameHeader>,
+    /// Every table of every DQT segment. A table that is redefined appears again.
+    pub quantization_tables: Vec<QuantizationTableSpec>,
+    /// Every table of every DHT segment. A table that is redefined appears again.
+    pub huffman_tables: Vec<HuffmanTableSpec>,
+    /// The scan script: the SOS segments of the frame.
+    pub scans: Vec<ScanHeader>,
+    /// Set when a JFIF (APP0) segment was read.
+    pub jfif: bool,
+    /// Color transform flag of an Adobe (APP14) segment, when one was read.
+    pub adobe_transform: Option<u8>,
+    /// Pixel density of the JFIF segment, when it gives one in dots per inch or
+    /// centimetre.
+    pub resolution: Option<Resolution>,
+}
+
+/// A marker and its segment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Marker {
+    /// Offset of the marker from the start of the stream.
+    pub offset: usize,
+    /// Second byte of the marker, e.g. `0xDB` for DQT.
+    pub code: u8,
+    /// Marker abbreviation, e.g. "DQT" or "SOF2".
+    pub name: String,
+    /// Size in bytes, including the marker and length field; 2 for markers without a
+    /// segment.
+    pub length: usize,
+}
+
+/// Coding process of a frame, given by its SOF marker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodingProcess {
+    /// SOF0: 8-bit sequential DCT with Huffman coding.
+    Baseline,
+    /// SOF1: 8- or 12-bit sequential DCT with Huffman coding.
+    ExtendedSequential,
+    /// SOF2: progressive DCT with Huffman coding.
+    Progressive,
+    /// SOF3: lossless (predictive) with Huffman coding.
+    Lossless,
+    /// Any other SOF marker (hierarchical or arithmetic coding), with its code.
+    Other(u8),
+}
+
+/// Parameters of the SOF segment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub process: CodingProcess,
+    /// Sample precision in bits.
+    pub precision: u8,
+    pub width: u16,
+    /// Number of lines; 0 when a DNL segment after the first scan defines it.
+    pub height: u16,
+    pub components: Vec<FrameComponent>,
+}
+
+/// A component of the SOF segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameComponent {
+    pub id: u8,
+    pub h_samp_factor: u8,
+    pub v_samp_factor: u8,
+    /// Quantization table selector (0-3).
+    pub quantization_table_id: u8,
+}
+
+/// A table of a DQT segment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuantizationTableSpec {
+    /// Offset of the DQT marker.
+    pub offset: usize,
+    /// Table selector (0-3).
+    pub id: u8,
+    /// 8 or 16 bits per entry.
+    pub precision: u8,
+    /// The entries as stored in the segment.
+    pub values: [u16; 64],
+}
+
+/// Table class of a DHT table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HuffmanTableClass {
+    Dc,
+    Ac,
+}
+
+/// A table of a DHT segment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HuffmanTableSpec {
+    /// Offset of the DHT marker.
+    pub offset: usize,
+    pub class: HuffmanTableClass,
+    /// Table selector (0-3).
+    pub id: u8,
+    /// Number of codes of each length from 1 to 16 bits.
+    pub code_counts: [u8; 16],
+    /// Symbols in order of increasing code length.
+    pub symbols: Vec<u8>,
+}
+
+/// Parameters of an SOS segment and its entropy-coded data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanHeader {
+    /// Offset of the SOS marker.
+    pub offset: usize,
+    pub components: Vec<ScanComponent>,
+    /// Start of spectral selection (Ss), or the predictor of lossless scans.
+    pub spectral_start: u8,
+    /// End of spectral selection (Se).
+    pub spectral_end: u8,
+    /// Successive approximation bit position high (Ah).
+    pub approximation_high: u8,
+    /// Successive approximation bit position low (Al), or the point transform of
+    /// lossless scans.
+    pub approximation_low: u8,
+    /// Restart interval in MCUs in effect for the scan (0 for none).
+    pub restart_interval: u16,
+    /// Size in bytes of the entropy-coded data, including restart markers.
+    pub entropy_coded_length: usize,
+    /// Number of restart markers in the entropy-coded data.
+    pub restart_marker_count: usize,
+}
+
+/// A component of an SOS segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanComponent {
+    pub id: u8,
+    /// DC Huffman table selector (0-3).
+    pub dc_table_id: u8,
+    /// AC Huffman table selector (0-3).
+    pub ac_table_id: u8,
+}
+
+impl JpegMetadata {
+    /// True for SOF2 frames.
+    pub fn is_progressive(&self) -> bool {
+        self.frame
+            .as_ref()
+            .is_some_and(|frame| frame.process == CodingProcess::Progressive)
+    }
+
+    /// True for SOF3 frames.
+    pub fn is_lossless(&self) -> bool {
+        self.frame
+            .as_ref()
+            .is_some_and(|frame| frame.process == CodingProcess::Lossless)
+    }
+
+    /// Restart interval in MCUs of the first scan (0 for none).
+    pub fn restart_interval(&self) -> u16 {
+        self.scans.first().map_or(0, |scan| scan.restart_interval)
+    }
+}
+
+/// Reads the header parameters of every marker segment of `data` up to EOI, skipping the
+/// entropy-coded data. A stream that ends inside entropy-coded data or without EOI is
+/// accepted, so partially received files can be inspected.
+///
+/// Returns [`JpeglsError::InvalidData`] if `data` does not start with SOI,
+/// [`JpeglsError::InvalidMarkerSegmentSize`] for a segment that is truncated or too short
+/// for its parameters and [`JpeglsError::EncodingNotSupported`] for JPEG-LS streams.
+pub fn read_metadata(data: &[u8]) -> Result<JpegMetadata, JpeglsError> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Err(JpeglsError::InvalidData);
+    }
+    if is_jpegls(data) {
+        return Err(JpeglsError::EncodingNotSupported);
+    }
+
+    let mut metadata = JpegMetadata::default();
+    let mut restart_interval = 0;
+    let mut position = 0;
+    while position + 1 < data.len() {
+        if data[position] != 0xFF {
+            return Err(JpeglsError::InvalidData);
+        }
+        // Any number of 0xFF fill bytes may precede a marker.
+        if data[position + 1] == 0xFF {
+            position += 1;
+            continue;
+        }
+
+        let offset = position;
+        let code = data[position + 1];
+        let (name, _) = crate::dump::jpeg_marker_name(code);
+        if matches!(code, 0x01 | 0xD0..=0xD9) {
+            metadata.markers.push(Marker {
+                offset,
+                code,
+                name,
+                length: 2,
+            });
+            position += 2;
+            if code == 0xD9 {
+                break;
+            }
+            continue;
+        }
+
+        let length = data
+            .get(position + 2..position + 4)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+            .ok_or(JpeglsError::InvalidMarkerSegmentSize)?;
+        let end = position + 2 + length;
+        if length < 2 || end > data.len() {
+            return Err(JpeglsError::InvalidMarkerSegmentSize);
+        }
+        let mut segment = Segment {
+            data: &data[position + 4..end],
+        };
+        metadata.markers.push(Marker {
+            offset,
+            code,
+            name,
+            length: 2 + length,
+        });
+        position = end;
+
+        match code {
+            0xC0..=0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => {
+                metadata.frame = Some(read_frame_header(code, &mut segment)?);
+            }
+            0xC4 => {
+                while !segment.data.is_empty() {
+                    let class_and_id = segment.u8()?;
+                    let code_counts: [u8; 16] = segment.bytes(16)?.try_into().unwrap();
+                    let count = code_counts.iter().map(|&n| n as usize).sum();
+                    metadata.huffman_tables.push(HuffmanTableSpec {
+                        offset,
+                        class: if class_and_id >> 4 == 0 {
+                            HuffmanTableClass::Dc
+                        } else {
+                            HuffmanTableClass::Ac
+                        },
+                        id: class_and_id & 0x0F,
+                        code_counts,
+                        symbols: segment.bytes(count)?.to_vec(),
+                    });
+                }
+            }
+            0xDB => {
+                while !segment.data.is_empty() {
+                    let precision_and_id = segment.u8()?;
+                    let sixteen_bit = precision_and_id >> 4 != 0;
+                    let mut values = [0u16; 64];
+                    for value in &mut values {
+                        *value = if sixteen_bit {
+                            segment.u16()?
+                        } else {
+                            segment.u8()? as u16
+                        };
+                    }
+                    metadata.quantization_tables.push(QuantizationTableSpec {
+                        offset,
+                        id: precision_and_id & 0x0F,
+                        precision: if sixteen_bit { 16 } else { 8 },
+                        values,
+                    });
+                }
+            }
+            0xDD => restart_interval = segment.u16()?,
+            0xE0 if segment.data.starts_with(b"JFIF\0") => {
+                metadata.jfif = true;
+                if let Some(density) = segment.data.get(7..12) {
+                    metadata.resolution = Resolution::from_density(
+                        density[0],
+                        u16::from_be_bytes([density[1], density[2]]) as u32,
+                        u16::from_be_bytes([density[3], density[4]]) as u32,
+                    );
+                }
+            }
+            0xEE if segment.data.len() >= 12 && segment.data.starts_with(b"Adobe") => {
+                metadata.adobe_transform = Some(segment.data[11]);
+            }
+            0xDA => {
+                let mut scan = read_scan_header(offset, &mut segment)?;
+                scan.restart_interval = restart_interval;
+                let start = position;
+                position = skip_entropy_coded_data(data, position, &mut scan);
+                scan.entropy_coded_length = position - start;
+                metadata.scans.push(scan);
+            }
+            _ => {}
+        }
+    }
+    Ok(metadata)
+}
+
+/// Big-endian reader over a segment payload that fails on a short segment.
+struct Segment<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Segment<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], JpeglsError> {
+        if self.data.len() < count {
+            return Err(JpeglsError::InvalidMarkerSegmentSize);
+        }
+        let (bytes, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, JpeglsError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, JpeglsError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+}
+
+fn read_frame_header(code: u8, segment: &mut Segment) -> Result<FrameHeader, JpeglsError> {
+    let process = match code {
+        0xC0 => CodingProcess::Baseline,
+        0xC1 => CodingProcess::ExtendedSequential,
+        0xC2 => CodingProcess::Progressive,
+        0xC3 => CodingProcess::Lossless,
+        _ => CodingProcess::Other(code),
+    };
+    let precision = segment.u8()?;
+    let height = segment.u16()?;
+    let width = segment.u16()?;
+    let component_count = segment.u8()?;
+    let components = (0..component_count)
+        .map(|_| {
+            let id = segment.u8()?;
+            let sampling = segment.u8()?;
+            Ok(FrameComponent {
+                id,
+                h_samp_factor: sampling >> 4,
+                v_samp_factor: sampling & 0x0F,
+                quantization_table_id: segment.u8()?,
+            })
+        })
+        .collect::<Result<_, JpeglsError>>()?;
+    Ok(FrameHeader {
+        process,
+        precision,
+        width,
+        height,
+        components,
+    })
+}
+
+fn read_scan_header(offset: usize, segment: &mut Segment) -> Result<ScanHeader, JpeglsError> {
+    let component_count = segment.u8()?;
+    let components = (0..component_count)
+        .map(|_| {
+            let id = segment.u8()?;
+            let tables = segment.u8()?;
+            Ok(ScanComponent {
+                id,
+                dc_table_id: tables >> 4,
+                ac_table_id: tables & 0x0F,
+            })
+        })
+        .collect::<Result<_, JpeglsError>>()?;
+    let spectral_start = segment.u8()?;
+    let spectral_end = segment.u8()?;
+    let approximation = segment.u8()?;
+    Ok(ScanHeader {
+        offset,
+        components,
+        spectral_start,
+        spectral_end,
+        approximation_high: approximation >> 4,
+        approximation_low: approximation & 0x0F,
+        restart_interval: 0,
+        entropy_coded_length: 0,
+        restart_marker_count: 0,
+    })
+}
+
+/// Returns the offset of the first marker after the entropy-coded data at `start` (or the
+/// end of `data`), counting the restart markers on the way.
+fn skip_entropy_coded_data(data: &[u8], start: usize, scan: &mut ScanHeader) -> usize {
+    let mut position = start;
+    while position + 1 < data.len() {
+        // Entropy-coded 0xFF bytes are followed by a stuffed 0x00.
+        if data[position] == 0xFF && data[position + 1] != 0x00 {
+            match data[position + 1] {
+                0xD0..=0xD7 => scan.restart_marker_count += 1,
+                // Fill bytes before a marker.
+                0xFF => {}
+                _ => return position,
+            }
+        }
+        position += 1;
+    }
+    data.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jpeg1::encoder::Jpeg1EncoderBuilder;
+    use crate::jpeg1::quantization::to_zigzag_order;
+    use crate::FrameInfo;
+
+    #[test]
+    fn test_read_metadata() {
+        let frame_info = FrameInfo {
+            width: 24,
+            height: 16,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let source: Vec<u8> = (0..24 * 16 * 3).map(|i| (i * 7 % 256) as u8).collect();
+        let mut encoder = Jpeg1EncoderBuilder::new(frame_info)
+            .restart_interval(2)
+            .build()
+            .unwrap();
+        let data = encoder.encode_to_vec(&source, &frame_info).unwrap();
+
+        let metadata = read_metadata(&data).unwrap();
+        let names: Vec<&str> = metadata.markers.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names.first(), Some(&"SOI"));
+        assert_eq!(names.last(), Some(&"EOI"));
+        assert!(!names.contains(&"RST0"));
+        for marker in &metadata.markers {
+            assert_eq!(data[marker.offset..][..2], [0xFF, marker.code]);
+        }
+
+        let frame = metadata.frame.as_ref().unwrap();
+        assert_eq!(frame.process, CodingProcess::Baseline);
+        assert_eq!((frame.width, frame.height, frame.precision), (24, 16, 8));
+        assert_eq!(frame.components.len(), 3);
+        assert!(!metadata.is_progressive() && !metadata.is_lossless());
+
+        assert_eq!(metadata.quantization_tables[0].id, 0);
+        assert_eq!(metadata.quantization_tables[0].precision, 8);
+        assert_eq!(
+            metadata.quantization_tables[0].values,
+            to_zigzag_order(&encoder.quantization_table_lum)
+        );
+        assert!(metadata
+            .huffman_tables
+            .iter()
+            .any(|table| table.class == HuffmanTableClass::Ac && table.symbols.len() == 162));
+
+        assert_eq!(metadata.restart_interval(), 2);
+        let scan = &metadata.scans[0];
+        assert_eq!(scan.components.len(), 3);
+        assert_eq!((scan.spectral_start, scan.spectral_end), (0, 63));
+        // 3 x 2 MCUs of 8x8 pixels in intervals of 2 MCUs.
+        assert_eq!(scan.restart_marker_count, 2);
+        assert_eq!(
+            scan.offset + 2 + 12 + scan.entropy_coded_length,
+            metadata.markers.last().unwrap().offset
+        );
+
+        // A stream cut inside the entropy-coded data keeps the scan read so far.
+        let cut = read_metadata(&data[..scan.offset + 20]).unwrap();
+        assert_eq!(cut.scans.len(), 1);
+        assert_eq!(
+            read_metadata(&data[..scan.offset + 6]),
+            Err(JpeglsError::InvalidMarkerSegmentSize)
+        );
+        assert_eq!(read_metadata(&data[2..]), Err(JpeglsError::InvalidData));
+    }
+}
@@ -3,17 +3,31 @@
 //! This module implements the classic DCT-based baseline JPEG standard.
 //!
 //! Features:
-//! - 8-bit depth support for grayscale and YCbCr images.
+//! - 8-bit depth support for grayscale, YCbCr, RGB, CMYK and YCCK images, with the color
+//!   space taken from the JFIF (APP0) and Adobe (APP14) segments. The encoder writes
+//!   grayscale, YCbCr (from RGB) and CMYK images.
+//! - 12-bit extended sequential (SOF1) encoding, with 8-bit or 16-bit custom quantization
+//!   tables.
 //! - Huffman coding with standard and custom tables.
 //! - Support for Restart Markers (DRI/RSTm).
 //! - Planar and Interleaved scan support.
+//! - Lossless re-encoding of DCT coefficients with optimized Huffman tables.
+//! - Lossless rotation, mirroring and cropping of DCT coefficients.
+//! - Header metadata (markers, tables and scan script) without decoding.
+//! - Decoding at 1/8 to 7/8 of the image size with reduced inverse DCTs.
 
 pub mod dct;
 pub mod decoder;
 pub mod encoder;
 pub mod huffman;
 pub mod lossless;
+pub mod metadata;
 pub mod quantization;
+pub mod transcode;
+pub mod transform;
 
-pub use decoder::Jpeg1Decoder;
-pub use encoder::Jpeg1Encoder;
+pub use decoder::{ColorSpace, Jpeg1Decoder};
+pub use encoder::{Jpeg1Encoder, Jpeg1EncoderBuilder};
+pub use metadata::{read_metadata, CodingProcess, JpegMetadata};
+pub use transcode::transcode;
+pub use transform::{transform, Transform};
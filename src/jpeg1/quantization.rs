@@ -2,6 +2,7 @@
 //! Handles quantization tables and the quantization of DCT coefficients.
 
 use crate::jpeg1::dct::BLOCK_DIM;
+use crate::jpeg1::encoder::ZIGZAG_ORDER;
 
 /// Standard JPEG luminance quantization table (Quality 50).
 pub const STD_LUMINANCE_QUANT_TABLE: [u8; BLOCK_DIM] = [
@@ -27,8 +28,8 @@ pub const STD_CHROMINANCE_QUANT_TABLE: [u8; BLOCK_DIM] = [
     99, 99, 99, 99, 99, 99, 99, 99,
 ];
 
-/// Quantizes DCT coefficients using a quantization table.
-pub fn quantize_block(dct_block: &[f32; BLOCK_DIM], quant_table: &[u8; BLOCK_DIM], output: &mut [i16; BLOCK_DIM]) {
+/// Quantizes DCT coefficients using a quantization table of 8-bit or 16-bit entries.
+pub fn quantize_block(dct_block: &[f32; BLOCK_DIM], quant_table: &[u16; BLOCK_DIM], output: &mut [i16; BLOCK_DIM]) {
     for i in 0..BLOCK_DIM {
         let q_val = quant_table[i] as f32;
         output[i] = (dct_block[i] / q_val).round() as i16;
@@ -43,6 +44,21 @@ pub fn dequantize_block(quant_block: &[i16; BLOCK_DIM], quant_table: &[u8; BLOCK
     }
 }
 
+/// Reorders a table from the zigzag order of DQT segments to the natural (row-major) order
+/// of the coefficients of a block.
+pub fn to_natural_order<T: Copy + Default>(table: &[T; BLOCK_DIM]) -> [T; BLOCK_DIM] {
+    let mut natural = [T::default(); BLOCK_DIM];
+    for (&position, &value) in ZIGZAG_ORDER.iter().zip(table) {
+        natural[position] = value;
+    }
+    natural
+}
+
+/// Reorders a table in natural order to the zigzag order it is written in DQT segments.
+pub fn to_zigzag_order<T: Copy>(table: &[T; BLOCK_DIM]) -> [T; BLOCK_DIM] {
+    ZIGZAG_ORDER.map(|position| table[position])
+}
+
 /// Scales a quantization table by a quality factor (1-100).
 pub fn get_scaled_quant_table(base_table: &[u8; BLOCK_DIM], quality: u32) -> [u8; BLOCK_DIM] {
     let mut scaled_table = [0u8; BLOCK_DIM];
@@ -56,3 +72,25 @@ pub fn get_scaled_quant_table(base_table: &[u8; BLOCK_DIM], quality: u32) -> [u8
     }
     scaled_table
 }
+
+/// Estimates the quality (1-100) at which [`get_scaled_quant_table`] scales the standard
+/// tables to `luminance` and, for color images, `chrominance`, the way ImageMagick does:
+/// the quality whose scaled tables have the nearest sum of entries. Sums do not depend on
+/// the order of the entries, so tables in zigzag order work too. Tables made some other
+/// way give the quality of the same overall coarseness.
+pub fn estimate_quality(luminance: &[u8; BLOCK_DIM], chrominance: Option<&[u8; BLOCK_DIM]>) -> u32 {
+    let sum = |table: &[u8; BLOCK_DIM]| table.iter().map(|&value| value as u32).sum::<u32>();
+    let target = sum(luminance) + chrominance.map_or(0, sum);
+    // Iterating from 100 down keeps the highest of equally near qualities.
+    (1..=100)
+        .rev()
+        .min_by_key(|&quality| {
+            let scaled = |table| sum(&get_scaled_quant_table(table, quality));
+            let mut total = scaled(&STD_LUMINANCE_QUANT_TABLE);
+            if chrominance.is_some() {
+                total += scaled(&STD_CHROMINANCE_QUANT_TABLE);
+            }
+            total.abs_diff(target)
+        })
+        .unwrap_or(100)
+}
@@ -0,0 +1,378 @@
+//! JPEG-to-JPEG transcoding in the DCT coefficient domain.
+//!
+//! [`transcode`] re-encodes the quantized DCT coefficients read by [`Jpeg1Decoder`] as a
+//! baseline sequential JPEG with Huffman tables optimized for the image. The
+//! coefficients and quantization tables are copied unchanged, so the output decodes to
+//! exactly the same pixels as the input; only the entropy coding changes. COM and APPn
+//! segments are kept, restart markers are not written.
+
+use std::sync::{Arc, Mutex};
+
+use crate::error::JpeglsError;
+use crate::jpeg1::decoder::{DctCoefficients, Jpeg1Decoder};
+use crate::jpeg1::encoder::ZIGZAG_ORDER;
+use crate::jpeg1::huffman::{HuffmanTable, JpegBitWriter};
+use crate::jpeg_marker_code::JpegMarkerCode;
+use crate::jpeg_stream_reader::is_jpegls;
+use crate::jpeg_stream_writer::{JpegStreamWriter, Sink};
+
+/// COM or APPn segment of the source, copied after SOI.
+pub(crate) enum Metadata {
+    Comment(Vec<u8>),
+    ApplicationData(u8, Vec<u8>),
+}
+
+/// Symbol counts of the DC and AC tables of each table slot.
+#[derive(Clone)]
+pub(crate) struct SymbolCounts {
+    pub(crate) dc: [u32; 256],
+    pub(crate) ac: [u32; 256],
+}
+
+/// Re-encodes a DCT-based JPEG without decoding it to pixels; see the module
+/// documentation. Lossless JPEG, JPEG-LS and sample precisions other than 8 bits fail with
+/// [`JpeglsError::EncodingNotSupported`].
+pub fn transcode(source: &[u8]) -> Result<Vec<u8>, JpeglsError> {
+    let (coefficients, metadata) = read_coefficients(source)?;
+    write_coefficients(&coefficients, &metadata)
+}
+
+/// Reads the quantized DCT coefficients and the COM and APPn segments of `source`.
+pub(crate) fn read_coefficients(
+    source: &[u8],
+) -> Result<(DctCoefficients, Vec<Metadata>), JpeglsError> {
+    if is_jpegls(source) {
+        return Err(JpeglsError::EncodingNotSupported);
+    }
+    let metadata = Arc::new(Mutex::new(Vec::new()));
+    let coefficients = {
+        let mut decoder = Jpeg1Decoder::new(source);
+        let comments = Arc::clone(&metadata);
+        decoder.at_comment(move |data| {
+            comments
+                .lock()
+                .unwrap()
+                .push(Metadata::Comment(data.to_vec()));
+            Ok(())
+        });
+        let application_data = Arc::clone(&metadata);
+        decoder.at_application_data(move |id, data| {
+            application_data
+                .lock()
+                .unwrap()
+                .push(Metadata::ApplicationData(id, data.to_vec()));
+            Ok(())
+        });
+        decoder.read_header()?;
+        decoder.read_coefficients()?
+    };
+    let metadata = std::mem::take(&mut *metadata.lock().unwrap());
+    if coefficients.components.is_empty() || coefficients.components.len() > 4 {
+        return Err(JpeglsError::EncodingNotSupported);
+    }
+    Ok((coefficients, metadata))
+}
+
+/// Writes `coefficients` as a baseline sequential JPEG with optimized Huffman tables,
+/// with the `metadata` segments after SOI.
+pub(crate) fn write_coefficients(
+    coefficients: &DctCoefficients,
+    metadata: &[Metadata],
+) -> Result<Vec<u8>, JpeglsError> {
+    // First pass: count symbols and extra bits to build the optimal tables.
+    let mut counts = vec![
+        SymbolCounts {
+            dc: [0; 256],
+            ac: [0; 256]
+        };
+        table_slots(coefficients)
+    ];
+    let mut extra_bits = 0u64;
+    encode_scan(coefficients, |slot, is_ac, symbol, _, bit_count| {
+        let table = &mut counts[slot];
+        if is_ac {
+            table.ac[symbol as usize] += 1;
+        } else {
+            table.dc[symbol as usize] += 1;
+        }
+        extra_bits += bit_count as u64;
+        Ok(())
+    })?;
+    let tables: Vec<_> = counts
+        .iter()
+        .map(|counts| {
+            (
+                HuffmanTable::optimal(&counts.dc),
+                HuffmanTable::optimal(&counts.ac),
+            )
+        })
+        .collect();
+
+    let code_bits: u64 = counts
+        .iter()
+        .zip(&tables)
+        .map(|(counts, (dc, ac))| {
+            code_length_total(&counts.dc, dc) + code_length_total(&counts.ac, ac)
+        })
+        .sum();
+    let metadata_bytes: usize = metadata
+        .iter()
+        .map(|segment| match segment {
+            Metadata::Comment(data) | Metadata::ApplicationData(_, data) => data.len() + 4,
+        })
+        .sum();
+    // Byte stuffing at most doubles the entropy-coded data.
+    let capacity = 4096 + metadata_bytes + 2 * ((code_bits + extra_bits) / 8 + 1) as usize;
+    let mut destination = vec![0u8; capacity];
+    let mut writer = JpegStreamWriter::new(&mut destination);
+
+    writer.write_start_of_image()?;
+    for segment in metadata {
+        match segment {
+            Metadata::Comment(data) => writer.write_comment(data)?,
+            Metadata::ApplicationData(id, data) => writer.write_application_data(*id, data)?,
+        }
+    }
+    write_frame_header(&mut writer, coefficients, &tables)?;
+
+    // Second pass: write the entropy-coded data.
+    let mut bit_writer = JpegBitWriter::new(writer.remaining_slice());
+    encode_scan(coefficients, |slot, is_ac, symbol, bits, bit_count| {
+        let (dc, ac) = &tables[slot];
+        let code = if is_ac {
+            ac.codes[symbol as usize]
+        } else {
+            dc.codes[symbol as usize]
+        };
+        bit_writer.write_bits(code.value, code.length)?;
+        bit_writer.write_bits(bits, bit_count)
+    })?;
+    bit_writer.flush()?;
+    let encoded_len = bit_writer.len();
+    writer.advance(encoded_len);
+    writer.write_end_of_image()?;
+
+    let len = writer.len();
+    destination.truncate(len);
+    Ok(destination)
+}
+
+/// Luminance (the first component) uses table slot 0, all other components slot 1.
+fn table_slot(component_index: usize) -> usize {
+    component_index.min(1)
+}
+
+fn table_slots(coefficients: &DctCoefficients) -> usize {
+    coefficients.components.len().min(2)
+}
+
+fn code_length_total(counts: &[u32; 256], table: &HuffmanTable) -> u64 {
+    counts
+        .iter()
+        .zip(&table.codes)
+        .map(|(&count, code)| count as u64 * code.length as u64)
+        .sum()
+}
+
+/// Writes DQT, DHT, SOF0 and SOS. Quantization tables keep their selectors and values.
+fn write_frame_header<D: Sink>(
+    writer: &mut JpegStreamWriter<D>,
+    coefficients: &DctCoefficients,
+    tables: &[(HuffmanTable, HuffmanTable)],
+) -> Result<(), JpeglsError> {
+    let components = &coefficients.components;
+    let mut written_tables = Vec::new();
+    for component in components {
+        if !written_tables.contains(&component.quantization_table_id) {
+            written_tables.push(component.quantization_table_id);
+            writer.write_dqt(
+                component.quantization_table_id,
+                &component.quantization_table,
+            )?;
+        }
+    }
+    for (slot, (dc, ac)) in tables.iter().enumerate() {
+        writer.write_dht(0, slot as u8, &dc.lengths, &dc.values)?;
+        writer.write_dht(1, slot as u8, &ac.lengths, &ac.values)?;
+    }
+
+    writer.write_marker(JpegMarkerCode::StartOfFrameBaseline)?;
+    writer.write_u16(8 + 3 * components.len() as u16)?;
+    writer.write_byte(8)?;
+    writer.write_u16(coefficients.height as u16)?;
+    writer.write_u16(coefficients.width as u16)?;
+    writer.write_byte(components.len() as u8)?;
+    for component in components {
+        writer.write_byte(component.id)?;
+        // Sampling factors have no meaning for a single component; its scan is not
+        // interleaved and covers only the blocks inside the image.
+        let sampling = if components.len() == 1 {
+            0x11
+        } else {
+            component.h_samp_factor << 4 | component.v_samp_factor
+        };
+        writer.write_byte(sampling)?;
+        writer.write_byte(component.quantization_table_id)?;
+    }
+
+    writer.write_marker(JpegMarkerCode::StartOfScan)?;
+    writer.write_u16(6 + 2 * components.len() as u16)?;
+    writer.write_byte(components.len() as u8)?;
+    for (index, component) in components.iter().enumerate() {
+        let slot = table_slot(index) as u8;
+        writer.write_byte(component.id)?;
+        writer.write_byte(slot << 4 | slot)?;
+    }
+    writer.write_byte(0)?; // Ss
+    writer.write_byte(63)?; // Se
+    writer.write_byte(0)?; // Ah/Al
+    Ok(())
+}
+
+/// Produces the Huffman symbols of a single baseline scan in stream order. `emit` receives
+/// the table slot, whether the symbol belongs to the AC table, the symbol, and the extra
+/// bits that follow it.
+fn encode_scan(
+    coefficients: &DctCoefficients,
+    mut emit: impl FnMut(usize, bool, u8, u16, u8) -> Result<(), JpeglsError>,
+) -> Result<(), JpeglsError> {
+    let components = &coefficients.components;
+    let mut previous_dc = [0i32; 4];
+    let mut encode_block = |index: usize, block: &[i16]| -> Result<(), JpeglsError> {
+        let slot = table_slot(index);
+        let dc = block[0] as i32;
+        let (category, bits) = magnitude(dc - previous_dc[index]);
+        previous_dc[index] = dc;
+        if category > 11 {
+            return Err(JpeglsError::InvalidData);
+        }
+        emit(slot, false, category, bits, category)?;
+
+        let mut run = 0;
+        for &position in &ZIGZAG_ORDER[1..] {
+            let value = block[position] as i32;
+            if value == 0 {
+                run += 1;
+                continue;
+            }
+            while run > 15 {
+                emit(slot, true, 0xF0, 0, 0)?;
+                run -= 16;
+            }
+            let (category, bits) = magnitude(value);
+            if category > 10 {
+                return Err(JpeglsError::InvalidData);
+            }
+            emit(slot, true, run << 4 | category, bits, category)?;
+            run = 0;
+        }
+        if run > 0 {
+            emit(slot, true, 0x00, 0, 0)?;
+        }
+        Ok(())
+    };
+
+    if let [component] = components.as_slice() {
+        let blocks_wide = (coefficients.width as usize).div_ceil(8);
+        let blocks_high = (coefficients.height as usize).div_ceil(8);
+        for block_y in 0..blocks_high {
+            for block_x in 0..blocks_wide {
+                let offset = (block_y * component.blocks_wide + block_x) * 64;
+                encode_block(0, &component.coefficients[offset..offset + 64])?;
+            }
+        }
+        return Ok(());
+    }
+
+    let blocks_per_mcu: usize = components
+        .iter()
+        .map(|c| c.h_samp_factor as usize * c.v_samp_factor as usize)
+        .sum();
+    if blocks_per_mcu > 10 {
+        return Err(JpeglsError::EncodingNotSupported);
+    }
+    let mcus_wide = components[0].blocks_wide / components[0].h_samp_factor as usize;
+    let mcus_high = components[0].blocks_high / components[0].v_samp_factor as usize;
+    for mcu_y in 0..mcus_high {
+        for mcu_x in 0..mcus_wide {
+            for (index, component) in components.iter().enumerate() {
+                let h_samp = component.h_samp_factor as usize;
+                let v_samp = component.v_samp_factor as usize;
+                for v in 0..v_samp {
+                    for h in 0..h_samp {
+                        let block_x = mcu_x * h_samp + h;
+                        let block_y = mcu_y * v_samp + v;
+                        let offset = (block_y * component.blocks_wide + block_x) * 64;
+                        encode_block(index, &component.coefficients[offset..offset + 64])?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Category (number of magnitude bits) and the extra bits of a DC difference or AC value.
+fn magnitude(value: i32) -> (u8, u16) {
+    let category = (32 - value.unsigned_abs().leading_zeros()) as u8;
+    let bits = if value < 0 {
+        value + (1 << category) - 1
+    } else {
+        value
+    };
+    (category, bits as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jpeg1::encoder::Jpeg1Encoder;
+    use crate::FrameInfo;
+
+    fn decode(data: &[u8]) -> Vec<u8> {
+        let mut decoder = Jpeg1Decoder::new(data);
+        decoder.read_header().unwrap();
+        let mut pixels = vec![0u8; 37 * 21 * 3];
+        decoder.decode(&mut pixels).unwrap();
+        pixels
+    }
+
+    #[test]
+    fn test_transcode_keeps_pixels() {
+        let frame_info = FrameInfo {
+            width: 37,
+            height: 21,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let pixels: Vec<u8> = (0..37 * 21 * 3).map(|i| (i * 13 % 251) as u8).collect();
+        let mut encoder = Jpeg1Encoder::new();
+        encoder.set_quality(90);
+        encoder.write_comment(b"kept").unwrap();
+        let mut encoded = vec![0u8; pixels.len() * 2];
+        let len = encoder.encode(&pixels, &frame_info, &mut encoded).unwrap();
+        encoded.truncate(len);
+
+        let transcoded = transcode(&encoded).unwrap();
+        assert!(transcoded.len() < encoded.len());
+        assert_eq!(decode(&transcoded), decode(&encoded));
+        assert!(transcoded.windows(4).any(|w| w == b"kept"));
+    }
+
+    #[test]
+    fn test_optimal_table_is_prefix_free() {
+        let mut counts = [0u32; 256];
+        for (symbol, count) in counts.iter_mut().enumerate() {
+            *count = (symbol as u32 * 7919) % 1000;
+        }
+        let table = HuffmanTable::optimal(&counts);
+        assert!(table.lengths.iter().map(|&n| n as usize).sum::<usize>() == table.values.len());
+        let used = counts.iter().filter(|&&c| c > 0).count();
+        assert_eq!(table.values.len(), used);
+        // Kraft inequality, strict because the all-ones code is unused.
+        let kraft: f64 = (0..16)
+            .map(|i| table.lengths[i] as f64 / (1u32 << (i + 1)) as f64)
+            .sum();
+        assert!(kraft < 1.0);
+    }
+}
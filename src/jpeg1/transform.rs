@@ -0,0 +1,350 @@
+//! Lossless rotation, mirroring and cropping of JPEG images in the DCT coefficient domain,
+//! as jpegtran does.
+//!
+//! [`transform`] moves the quantized DCT blocks read by [`Jpeg1Decoder`] to their new
+//! place and transposes them or changes the sign of their odd frequencies, so no pixels are
+//! decoded or re-quantized. The result is written like [`transcode`](super::transcode)
+//! writes it: a baseline sequential JPEG with optimized Huffman tables that keeps the COM
+//! and APPn segments.
+//!
+//! Blocks only move as whole MCUs. Mirroring would move the MCUs at the right or bottom
+//! edge, which extend past the image, to the opposite edge, so they are dropped like
+//! jpegtran's `-trim` does; a crop starts at the MCU boundary at or before the requested
+//! corner.
+//!
+//! [`Jpeg1Decoder`]: crate::jpeg1::Jpeg1Decoder
+
+use crate::error::JpeglsError;
+use crate::jpeg1::decoder::{ComponentCoefficients, DctCoefficients};
+use crate::jpeg1::quantization::{to_natural_order, to_zigzag_order};
+use crate::jpeg1::transcode::{read_coefficients, write_coefficients};
+
+/// A lossless rotation or mirroring of a JPEG image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Transform {
+    /// Keeps the orientation, e.g. to crop only.
+    #[default]
+    None,
+    /// Mirrors the image left to right.
+    FlipHorizontal,
+    /// Mirrors the image top to bottom.
+    FlipVertical,
+    /// Mirrors the image across the diagonal from the top left corner.
+    Transpose,
+    /// Mirrors the image across the diagonal from the top right corner.
+    Transverse,
+    /// Rotates the image by 90 degrees clockwise.
+    Rotate90,
+    /// Rotates the image by 180 degrees.
+    Rotate180,
+    /// Rotates the image by 270 degrees clockwise (90 degrees counterclockwise).
+    Rotate270,
+}
+
+impl Transform {
+    /// The clockwise rotation by `degrees` (0, 90, 180 or 270), if it is one of these.
+    pub fn rotation(degrees: u32) -> Option<Transform> {
+        match degrees {
+            0 => Some(Transform::None),
+            90 => Some(Transform::Rotate90),
+            180 => Some(Transform::Rotate180),
+            270 => Some(Transform::Rotate270),
+            _ => None,
+        }
+    }
+
+    /// The transform as a transposition followed by mirroring left to right and top to
+    /// bottom.
+    fn steps(self) -> (bool, bool, bool) {
+        match self {
+            Transform::None => (false, false, false),
+            Transform::FlipHorizontal => (false, true, false),
+            Transform::FlipVertical => (false, false, true),
+            Transform::Rotate180 => (false, true, true),
+            Transform::Transpose => (true, false, false),
+            Transform::Rotate90 => (true, true, false),
+            Transform::Rotate270 => (true, false, true),
+            Transform::Transverse => (true, true, true),
+        }
+    }
+}
+
+/// Applies `transform` to a DCT-based JPEG and then keeps the `crop` rectangle (left, top,
+/// width and height in the transformed image) of it, if given; see the module
+/// documentation.
+///
+/// Returns [`JpeglsError::InvalidArgumentWidth`] or [`JpeglsError::InvalidArgumentHeight`]
+/// if the crop rectangle is empty or does not lie within the transformed image, or if
+/// mirroring leaves no whole MCU. Lossless JPEG, JPEG-LS and sample precisions other than
+/// 8 bits fail with [`JpeglsError::EncodingNotSupported`].
+pub fn transform(
+    source: &[u8],
+    transform: Transform,
+    crop: Option<(u32, u32, u32, u32)>,
+) -> Result<Vec<u8>, JpeglsError> {
+    let (coefficients, metadata) = read_coefficients(source)?;
+    let transformed = transform_coefficients(&coefficients, transform, crop)?;
+    write_coefficients(&transformed, &metadata)
+}
+
+/// The coefficients of the image [`transform`] writes. The quantization tables are
+/// transposed along with the blocks, and so are the sampling factors.
+pub fn transform_coefficients(
+    coefficients: &DctCoefficients,
+    transform: Transform,
+    crop: Option<(u32, u32, u32, u32)>,
+) -> Result<DctCoefficients, JpeglsError> {
+    let (transpose, flip_horizontal, flip_vertical) = transform.steps();
+    let components = &coefficients.components;
+    // A single component is coded block by block, without MCUs.
+    let single = components.len() == 1;
+    let sampling = |component: &ComponentCoefficients| {
+        let (h, v) = if single {
+            (1, 1)
+        } else {
+            (component.h_samp_factor, component.v_samp_factor)
+        };
+        if transpose {
+            (v, h)
+        } else {
+            (h, v)
+        }
+    };
+    let max_h = components.iter().map(|c| sampling(c).0).max().unwrap_or(1) as u32;
+    let max_v = components.iter().map(|c| sampling(c).1).max().unwrap_or(1) as u32;
+    let (mcu_width, mcu_height) = (8 * max_h, 8 * max_v);
+
+    // The transformed image, without the partial MCUs that mirroring cannot move.
+    let (mut width, mut height) = if transpose {
+        (coefficients.height, coefficients.width)
+    } else {
+        (coefficients.width, coefficients.height)
+    };
+    if flip_horizontal {
+        width -= width % mcu_width;
+    }
+    if flip_vertical {
+        height -= height % mcu_height;
+    }
+    if width == 0 {
+        return Err(JpeglsError::InvalidArgumentWidth);
+    }
+    if height == 0 {
+        return Err(JpeglsError::InvalidArgumentHeight);
+    }
+
+    // Blocks across and down the mirrored image, in units of MCUs.
+    let (mcus_across, mcus_down) = (width / mcu_width, height / mcu_height);
+    let (left, top, crop_width, crop_height) = match crop {
+        Some((x, y, crop_width, crop_height)) => {
+            if crop_width == 0 || x.checked_add(crop_width).is_none_or(|right| right > width) {
+                return Err(JpeglsError::InvalidArgumentWidth);
+            }
+            if crop_height == 0
+                || y.checked_add(crop_height)
+                    .is_none_or(|bottom| bottom > height)
+            {
+                return Err(JpeglsError::InvalidArgumentHeight);
+            }
+            let (left, top) = (x - x % mcu_width, y - y % mcu_height);
+            (left, top, crop_width + x - left, crop_height + y - top)
+        }
+        None => (0, 0, width, height),
+    };
+    let mcus_wide = crop_width.div_ceil(mcu_width) as usize;
+    let mcus_high = crop_height.div_ceil(mcu_height) as usize;
+
+    let components = components
+        .iter()
+        .map(|component| {
+            let (h, v) = sampling(component);
+            let (h, v) = (h as usize, v as usize);
+            let blocks_wide = mcus_wide * h;
+            let blocks_high = mcus_high * v;
+            let block_left = (left / mcu_width) as usize * h;
+            let block_top = (top / mcu_height) as usize * v;
+            // Mirroring reverses the whole MCUs of the image.
+            let mirror_width = mcus_across as usize * h;
+            let mirror_height = mcus_down as usize * v;
+
+            let mut transformed = vec![0i16; blocks_wide * blocks_high * 64];
+            for (index, block) in transformed.chunks_exact_mut(64).enumerate() {
+                let x = index % blocks_wide + block_left;
+                let y = index / blocks_wide + block_top;
+                let x = if flip_horizontal {
+                    match mirror_width.checked_sub(x + 1) {
+                        Some(x) => x,
+                        None => continue,
+                    }
+                } else {
+                    x
+                };
+                let y = if flip_vertical {
+                    match mirror_height.checked_sub(y + 1) {
+                        Some(y) => y,
+                        None => continue,
+                    }
+                } else {
+                    y
+                };
+                let (x, y) = if transpose { (y, x) } else { (x, y) };
+                // Blocks past the source grid lie outside the cropped image.
+                if x >= component.blocks_wide || y >= component.blocks_high {
+                    continue;
+                }
+                let offset = (y * component.blocks_wide + x) * 64;
+                transform_block(
+                    &component.coefficients[offset..offset + 64],
+                    block,
+                    transpose,
+                    flip_horizontal,
+                    flip_vertical,
+                );
+            }
+
+            let mut quantization_table = component.quantization_table;
+            if transpose {
+                // The table is in zigzag order; the transposition applies to the natural order.
+                let natural = to_natural_order(&component.quantization_table);
+                let transposed: [u8; 64] =
+                    std::array::from_fn(|index| natural[index % 8 * 8 + index / 8]);
+                quantization_table = to_zigzag_order(&transposed);
+            }
+            ComponentCoefficients {
+                h_samp_factor: h as u8,
+                v_samp_factor: v as u8,
+                quantization_table,
+                blocks_wide,
+                blocks_high,
+                coefficients: transformed,
+                ..*component
+            }
+        })
+        .collect();
+    Ok(DctCoefficients {
+        width: crop_width,
+        height: crop_height,
+        components,
+    })
+}
+
+/// Transposes the 8x8 `source` block into `destination` and mirrors it. Mirroring a block
+/// changes the sign of the coefficients of odd horizontal or vertical frequency.
+fn transform_block(
+    source: &[i16],
+    destination: &mut [i16],
+    transpose: bool,
+    flip_horizontal: bool,
+    flip_vertical: bool,
+) {
+    for (index, value) in destination.iter_mut().enumerate() {
+        let (row, column) = (index / 8, index % 8);
+        let source_index = if transpose { column * 8 + row } else { index };
+        let negate = (flip_horizontal && column % 2 == 1) != (flip_vertical && row % 2 == 1);
+        *value = if negate {
+            -source[source_index]
+        } else {
+            source[source_index]
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jpeg1::{Jpeg1Decoder, Jpeg1Encoder};
+    use crate::FrameInfo;
+
+    fn decode(data: &[u8]) -> (u32, u32, Vec<u8>) {
+        let mut decoder = Jpeg1Decoder::new(data);
+        decoder.read_header().unwrap();
+        let (width, height) = decoder.output_size();
+        let mut pixels = vec![0u8; (width * height * 3) as usize];
+        decoder.decode(&mut pixels).unwrap();
+        (width, height, pixels)
+    }
+
+    #[test]
+    fn test_transforms_match_pixel_transforms() {
+        // 40x24 is 5x3 MCUs of 8x8 pixels (no chroma subsampling), so nothing is trimmed.
+        let frame_info = FrameInfo {
+            width: 40,
+            height: 24,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let source: Vec<u8> = (0..40 * 24 * 3).map(|i| (i * 13 % 251) as u8).collect();
+        let encoded = Jpeg1Encoder::new()
+            .encode_to_vec(&source, &frame_info)
+            .unwrap();
+        let (_, _, original) = decode(&encoded);
+        let pixel = |x: u32, y: u32| &original[(y * 40 + x) as usize * 3..][..3];
+
+        type Position = fn(u32, u32) -> (u32, u32);
+        let cases: [(Transform, Position); 8] = [
+            (Transform::None, |x, y| (x, y)),
+            (Transform::FlipHorizontal, |x, y| (39 - x, y)),
+            (Transform::FlipVertical, |x, y| (x, 23 - y)),
+            (Transform::Transpose, |x, y| (y, x)),
+            (Transform::Transverse, |x, y| (39 - y, 23 - x)),
+            (Transform::Rotate90, |x, y| (y, 23 - x)),
+            (Transform::Rotate180, |x, y| (39 - x, 23 - y)),
+            (Transform::Rotate270, |x, y| (39 - y, x)),
+        ];
+        for (operation, source_position) in cases {
+            let (width, height, pixels) = decode(&transform(&encoded, operation, None).unwrap());
+            for y in 0..height {
+                for x in 0..width {
+                    let (source_x, source_y) = source_position(x, y);
+                    let actual = &pixels[(y * width + x) as usize * 3..][..3];
+                    // The same blocks decode to the same pixels up to rounding.
+                    for (&a, &e) in actual.iter().zip(pixel(source_x, source_y)) {
+                        assert!(a.abs_diff(e) <= 2, "{operation:?} at ({x}, {y})");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_trim_and_crop() {
+        let frame_info = FrameInfo {
+            width: 21,
+            height: 13,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let source: Vec<u8> = (0..21 * 13 * 3).map(|i| (i * 7 % 256) as u8).collect();
+        let encoded = Jpeg1Encoder::new()
+            .encode_to_vec(&source, &frame_info)
+            .unwrap();
+        let (_, _, original) = decode(&encoded);
+
+        // Mirroring drops the partial MCUs at the right and bottom edges.
+        let (width, height, _) = decode(&transform(&encoded, Transform::Rotate180, None).unwrap());
+        assert_eq!((width, height), (16, 8));
+        let (width, height, _) = decode(&transform(&encoded, Transform::Rotate90, None).unwrap());
+        assert_eq!((width, height), (8, 21));
+
+        // The crop starts at the MCU boundary before (10, 9).
+        let cropped = transform(&encoded, Transform::None, Some((10, 9, 5, 3))).unwrap();
+        let (width, height, pixels) = decode(&cropped);
+        assert_eq!((width, height), (7, 4));
+        for y in 0..4 {
+            let start = ((8 + y) * 21 + 8) * 3;
+            assert_eq!(
+                &pixels[y * 7 * 3..(y + 1) * 7 * 3],
+                &original[start..start + 7 * 3]
+            );
+        }
+
+        assert_eq!(
+            transform(&encoded, Transform::None, Some((10, 0, 12, 1))),
+            Err(JpeglsError::InvalidArgumentWidth)
+        );
+        assert_eq!(
+            transform(&encoded, Transform::FlipVertical, Some((0, 5, 1, 4))),
+            Err(JpeglsError::InvalidArgumentHeight)
+        );
+    }
+}
@@ -66,11 +66,20 @@ impl J2kBitWriter {
     pub fn write_bit(&mut self, bit: u8) {
         self.bit_buffer = (self.bit_buffer << 1) | (bit & 1);
         self.bits_count += 1;
-        if self.bits_count == 8 {
+        if self.bits_count == self.byte_bits() {
             self.flush_byte();
         }
     }
 
+    /// Bits in the next byte: a byte after 0xFF starts with a stuffed 0 bit.
+    fn byte_bits(&self) -> u8 {
+        if self.data.last() == Some(&0xFF) {
+            7
+        } else {
+            8
+        }
+    }
+
     pub fn write_bits(&mut self, value: u32, mut count: u8) {
         while count > 0 {
             let bit = ((value >> (count - 1)) & 1) as u8;
@@ -80,21 +89,20 @@ impl J2kBitWriter {
     }
 
     fn flush_byte(&mut self) {
-        let b = self.bit_buffer;
-        self.data.push(b);
-        if b == 0xFF {
-            // Stuffing
-            self.data.push(0x00);
-        }
+        self.data.push(self.bit_buffer);
         self.bit_buffer = 0;
         self.bits_count = 0;
     }
 
     pub fn finish(mut self) -> Vec<u8> {
         if self.bits_count > 0 {
-            self.bit_buffer <<= 8 - self.bits_count;
+            self.bit_buffer <<= self.byte_bits() - self.bits_count;
             self.flush_byte();
         }
+        // The reader skips the byte after a final 0xFF.
+        if self.data.last() == Some(&0xFF) {
+            self.data.push(0x00);
+        }
         self.data
     }
 
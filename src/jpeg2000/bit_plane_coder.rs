@@ -35,6 +35,37 @@ impl<'a> BitPlaneCoder<'a> {
         }
     }
 
+    /// Creates a decoder that works in buffers handed over by the caller: the coefficients
+    /// and state of a code-block decoded up to an earlier layer, or empty ones, and an MQ
+    /// coder whose allocations are reused. [`Self::into_parts`] gives them back.
+    pub fn with_buffers(
+        width: u32,
+        height: u32,
+        mut coefficients: Vec<i32>,
+        mut state: Vec<u8>,
+        mut mq: MqCoder,
+    ) -> Self {
+        let size = (width * height) as usize;
+        coefficients.resize(size, 0);
+        state.resize(size, 0);
+        mq.init_contexts(19);
+
+        Self {
+            width,
+            height,
+            data: &[],
+            state,
+            mq,
+            coefficients,
+            num_passes_decoded: 0,
+        }
+    }
+
+    /// Returns the coefficients, the state and the MQ coder.
+    pub fn into_parts(self) -> (Vec<i32>, Vec<u8>, MqCoder) {
+        (self.coefficients, self.state, self.mq)
+    }
+
     // State Bit Definitions
     const SIG: u8 = 1 << 0;
     const VISITED: u8 = 1 << 1;
@@ -157,8 +188,21 @@ impl<'a> BitPlaneCoder<'a> {
         num_new_passes: u8,
         orientation: u8,
     ) -> Result<Vec<i32>, crate::jpeg2000::bit_io::BitIoError> {
+        self.decode_passes(data, max_bit_plane, num_new_passes, orientation)?;
+        Ok(self.coefficients.clone())
+    }
+
+    /// Decodes the next `num_new_passes` coding passes into [`Self::coefficients`],
+    /// like [`Self::decode_codeblock`] without copying them.
+    pub fn decode_passes(
+        &mut self,
+        data: &[u8],
+        max_bit_plane: u8,
+        num_new_passes: u8,
+        orientation: u8,
+    ) -> Result<(), crate::jpeg2000::bit_io::BitIoError> {
         if num_new_passes == 0 {
-            return Ok(self.coefficients.clone());
+            return Ok(());
         }
 
         self.mq.init_decoder(data);
@@ -205,7 +249,7 @@ impl<'a> BitPlaneCoder<'a> {
             self.num_passes_decoded += 1;
         }
 
-        Ok(self.coefficients.clone())
+        Ok(())
     }
 
     fn decode_significance_propagation(
@@ -606,4 +650,27 @@ mod tests {
             "Index 10 (-3) should be significant"
         );
     }
+
+    #[test]
+    fn test_decode_with_reused_buffers() {
+        let data = [10, 0, 0, 0, 0, 5, 0, 0, 0, 0, -3, 0, 0, 0, 0, 1];
+        let mut encoder = BitPlaneCoder::new(4, 4, &data);
+        encoder.encode_codeblock();
+        encoder.mq.flush();
+        let encoded = encoder.mq.get_buffer().to_vec();
+
+        let mut decoder = BitPlaneCoder::new(4, 4, &[]);
+        let expected = decoder.decode_codeblock(&encoded, 4, 13, 0).unwrap();
+        assert!(expected.iter().any(|&c| c != 0));
+
+        // A coder handed from one code-block to the next decodes each the same way.
+        let mut mq = MqCoder::new();
+        for _ in 0..2 {
+            let mut decoder = BitPlaneCoder::with_buffers(4, 4, Vec::new(), Vec::new(), mq);
+            decoder.decode_passes(&encoded, 4, 13, 0).unwrap();
+            let (coefficients, _, used) = decoder.into_parts();
+            assert_eq!(coefficients, expected);
+            mq = used;
+        }
+    }
 }
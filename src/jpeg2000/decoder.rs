@@ -3,20 +3,49 @@
 //! This module provides the `J2kDecoder` which manages the high-level
 //! decoding process, including header parsing and dispatching to Tier-1/Tier-2 coders.
 
-use super::image::J2kImage;
+use super::bit_plane_coder::BitPlaneCoder;
+use super::image::{BlockCoder, J2kImage, J2kPreview, J2kTile, J2kTilePart, J2kUpsampling};
+use super::mq_coder::MqCoder;
 use super::parser::J2kParser;
+use super::progression::{self, ComponentGrid, ProgressionOrder, ProgressionVolume, TileBounds};
 use crate::JpeglsError;
 use crate::jpeg_stream_reader::JpegStreamReader;
+use crate::mem_profiling::{track_elements, DecodeStats, Session};
 
-use crate::jpeg2000::packet::PrecinctState;
+use crate::jpeg2000::packet::{PacketHeader, PrecinctState};
 use std::collections::HashMap;
-#[derive(Default)]
+#[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ResolutionState {
     pub width: u32,
     pub height: u32,
+    #[cfg_attr(feature = "serde", serde(with = "precinct_map"))]
     pub precincts: HashMap<(u32, u32), crate::jpeg2000::packet::PrecinctState>,
 }
 
+/// Writes the precincts of a resolution as a list of entries, since formats such as JSON
+/// only take strings as map keys.
+#[cfg(feature = "serde")]
+mod precinct_map {
+    use crate::jpeg2000::packet::PrecinctState;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::collections::HashMap;
+
+    pub fn serialize<S: Serializer>(
+        precincts: &HashMap<(u32, u32), PrecinctState>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(precincts)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<(u32, u32), PrecinctState>, D::Error> {
+        let entries = Vec::<((u32, u32), PrecinctState)>::deserialize(deserializer)?;
+        Ok(entries.into_iter().collect())
+    }
+}
+
 impl ResolutionState {
     pub fn new(w: usize, h: usize) -> Self {
         Self {
@@ -27,117 +56,624 @@ impl ResolutionState {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ComponentState {
     pub resolutions: Vec<ResolutionState>,
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TileState {
     pub components: Vec<ComponentState>,
+    /// Packets read from earlier tile-parts of the tile.
+    pub packets_read: usize,
+    /// Offset of the first packet of the current tile-part not read yet.
+    pub packet_position: usize,
+    /// Bytes of the tile's packed packet headers (PPM/PPT) read so far.
+    pub packed_headers_read: usize,
+    /// Quality layers whose packets are all read.
+    pub layers_read: u16,
+    /// Resolutions whose packets are all read.
+    pub resolutions_read: u8,
+}
+
+/// A tile-part whose header was read but whose packets run past the end of the data.
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct OpenTilePart {
+    /// Offset of its SOT marker.
+    sot: usize,
+    header: J2kTilePart,
+    /// Offset of the end of the tile-part.
+    end: usize,
+    /// Offset of its first packet not read.
+    position: usize,
+}
+
+/// Where decoding stopped: the first tile-part not read in full.
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Checkpoint {
+    /// Index of the tile-part in the codestream.
+    tile_part: usize,
+    /// Offset of its SOT marker.
+    sot: usize,
+    /// The tile-part, when the data ended in its packets.
+    open: Option<OpenTilePart>,
+}
+
+impl Checkpoint {
+    /// Moves the offsets back by `origin`.
+    fn rebase(&mut self, origin: usize) {
+        self.sot -= origin;
+        if let Some(open) = &mut self.open {
+            open.sot -= origin;
+            open.end -= origin;
+            open.position -= origin;
+        }
+    }
+}
+
+/// The state one decode builds up and the next continues from. Its offsets are relative
+/// to the start of the codestream.
+#[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct DecodeState {
+    tile_states: Vec<TileState>,
+    /// `None` until the main header is read.
+    checkpoint: Option<Checkpoint>,
+    /// Whether the data may end in the middle of a tile-part, as in a partial decode.
+    partial: bool,
+    /// Offset of the codestream in the stream, past the boxes before it in a JP2 file.
+    codestream_start: usize,
+}
+
+/// A snapshot of a [`J2kDecoder`], taken by [`J2kDecoder::save_state`].
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct J2kDecoderState {
+    image: Box<J2kImage>,
+    state: DecodeState,
+}
+
+impl J2kDecoderState {
+    /// The image with the packets read when the snapshot was taken.
+    pub fn image(&self) -> &J2kImage {
+        &self.image
+    }
+
+    /// Serializes the snapshot, e.g. for a tile server to cache it outside the process.
+    #[cfg(feature = "serde")]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("decoder state serializes to JSON")
+    }
+
+    /// Reads a snapshot written by [`to_bytes`](Self::to_bytes). Returns `InvalidData` if
+    /// the bytes do not hold one; whether it belongs to a stream is checked by
+    /// [`J2kDecoder::resume`].
+    #[cfg(feature = "serde")]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, JpeglsError> {
+        serde_json::from_slice(bytes).map_err(|_| JpeglsError::InvalidData)
+    }
+}
+
+/// What [`J2kDecoder::decode_partial`] recovered from a truncated stream.
+#[derive(Debug, Clone, Copy)]
+pub struct J2kPartialDecode<'d> {
+    /// The image with the packets that were fully present.
+    pub image: &'d J2kImage,
+    /// Quality layers complete in every decoded tile.
+    pub layers: u16,
+    /// Resolutions complete in every decoded tile, from 0 (none) to the number of
+    /// decomposition levels plus one (the full image).
+    pub resolutions: u8,
+    /// Offset of the first packet not read, or of the first tile-part when the data ended
+    /// between tile-parts, from where the reader was when the decoder was created.
+    pub offset: usize,
+    /// Rows at the top of the image whose tiles have every quality layer and resolution
+    /// read.
+    pub complete_rows: u32,
+}
+
+impl J2kPartialDecode<'_> {
+    /// Whether every quality layer and resolution was read, i.e. the stream was complete.
+    pub fn is_complete(&self) -> bool {
+        self.image.cod.as_ref().is_none_or(|cod| {
+            self.layers >= cod.number_of_layers && self.resolutions > cod.decomposition_levels
+        })
+    }
+}
+
+/// Receives the image reconstructed at every resolution, from the lowest to the full image.
+pub type ProgressHandler<'a> = dyn FnMut(&J2kPreview) -> Result<(), JpeglsError> + Send + Sync + 'a;
+
+/// Reports resolutions to the progress handler as their packets are read.
+struct Progress<'h, 'a> {
+    handler: Option<&'h mut ProgressHandler<'a>>,
+    /// Resolutions reported so far.
+    reported: u8,
+}
+
+impl Progress<'_, '_> {
+    /// Reports the resolutions up to `resolution` that were not reported yet.
+    fn report(&mut self, image: &J2kImage, resolution: u8) -> Result<(), JpeglsError> {
+        let Some(handler) = self.handler.as_mut() else {
+            return Ok(());
+        };
+        while self.reported <= resolution {
+            let preview = image
+                .reconstruct_resolution(self.reported, J2kUpsampling::Nearest)
+                .map_err(|_| JpeglsError::InvalidData)?;
+            handler(&preview)?;
+            self.reported += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Buffers kept from one code-block to the next, across packets, tiles and decodes, so
+/// the block decoders do not allocate for every code-block.
+#[derive(Default)]
+struct BlockScratch {
+    /// The MQ decoder with its contexts and its copy of the code-block data.
+    mq: Option<MqCoder>,
+    /// The segments of an HT code-block, joined.
+    segments: Vec<u8>,
 }
 
 /// High-level generic JPEG 2000 Decoder.
 /// Orchestrates parsing, block decoding, and image reconstruction.
 pub struct J2kDecoder<'a, 'b> {
     parser: J2kParser<'a, 'b>,
-    tile_states: Vec<TileState>,
+    /// Position of the reader when the decoder was created: the start of the stream.
+    origin: usize,
+    state: DecodeState,
+    /// Indices of the tiles to decode; `None` decodes every tile.
+    tiles: Option<Vec<u16>>,
+    /// Index of the codestream box of a JPX file to decode.
+    codestream: usize,
+    progress_handler: Option<Box<ProgressHandler<'a>>>,
+    stats: DecodeStats,
+    scratch: BlockScratch,
+}
+
+impl J2kDecoder<'static, 'static> {
+    /// Creates a decoder that owns `source` instead of borrowing a reader, e.g. to move it
+    /// to another thread or into an async task.
+    pub fn from_vec(source: Vec<u8>) -> Self {
+        Self::with_parser(J2kParser::from_reader(JpegStreamReader::from_vec(source)))
+    }
 }
 
 impl<'a, 'b> J2kDecoder<'a, 'b> {
     pub fn new(reader: &'b mut JpegStreamReader<'a>) -> Self {
+        Self::with_parser(J2kParser::new(reader))
+    }
+
+    fn with_parser(parser: J2kParser<'a, 'b>) -> Self {
         Self {
-            parser: J2kParser::new(reader),
-            tile_states: Vec::new(),
+            origin: parser.reader.position(),
+            parser,
+            state: DecodeState::default(),
+            tiles: None,
+            codestream: 0,
+            progress_handler: None,
+            stats: DecodeStats::default(),
+            scratch: BlockScratch::default(),
         }
     }
 
+    /// Sets the handler that receives the image at every resolution, lowest first, so a
+    /// viewer can show a coarse preview that sharpens as decoding goes on. In a single-tile
+    /// image a resolution is reported as soon as its last packet is read, well before the
+    /// end of the codestream in the RLCP and RPCL progression orders; the remaining
+    /// resolutions follow once the codestream is read. An error returned by the handler
+    /// stops decoding and is passed on to the caller.
+    pub fn set_progress_handler(
+        &mut self,
+        handler: impl FnMut(&J2kPreview) -> Result<(), JpeglsError> + Send + Sync + 'a,
+    ) {
+        self.progress_handler = Some(Box::new(handler));
+    }
+
+    /// Decodes only the tiles with these indices. The tile-parts of other tiles are
+    /// skipped without reading their packets, straight from the TLM tile-part lengths
+    /// when the main header has them and by their Psot lengths otherwise.
+    pub fn set_tiles(&mut self, tiles: &[u16]) {
+        self.tiles = Some(tiles.to_vec());
+    }
+
+    /// Decodes codestream `index` of a JP2 or JPX file with several codestream boxes, e.g.
+    /// a frame of an animation, instead of the first; see
+    /// [`Jp2Reader::codestreams`](super::jp2::Jp2Reader::codestreams). Decoding fails with
+    /// `InvalidArgument` if the file has no such codestream.
+    pub fn set_codestream(&mut self, index: usize) {
+        self.codestream = index;
+    }
+
+    /// Memory statistics of the most recent call to [`decode`](Self::decode) or
+    /// [`decode_partial`](Self::decode_partial).
+    ///
+    /// Code-block and coefficient buffers stay attached to the returned image, so they
+    /// are included in the peak even though they outlive the call.
+    pub fn stats(&self) -> DecodeStats {
+        self.stats
+    }
+
     /// Decodes the JPEG 2000 image from the stream.
     pub fn decode(&mut self) -> Result<&J2kImage, JpeglsError> {
-        // 0. Container Detection (JP2 Box)
-        // We use a separate reader/parser logic for checking the container.
-        let codestream = {
-            let mut jp2_reader =
-                crate::jpeg2000::jp2::Jp2Reader::new(self.parser.reader.remaining_data());
-            jp2_reader.find_codestream().unwrap_or_default()
+        self.decode_tracked(None)?;
+        Ok(&self.parser.image)
+    }
+
+    /// Decodes what the first `available_bytes` bytes of the stream hold, as when a
+    /// JPEG 2000 file is fetched piece by piece with HTTP range requests. Every packet
+    /// that is fully present is decoded and decoding stops at the first tile-part cut
+    /// short, so the image sharpens as more bytes arrive. Returns how many quality layers
+    /// and resolutions are complete, or `NeedMoreData` while the main header is not.
+    ///
+    /// A later call, with more bytes, continues with the first packet not read.
+    pub fn decode_partial(
+        &mut self,
+        available_bytes: usize,
+    ) -> Result<J2kPartialDecode<'_>, JpeglsError> {
+        self.decode_tracked(Some(available_bytes))?;
+        let (layers, resolutions) = Self::completed(
+            &self.parser.image,
+            &self.state.tile_states,
+            self.tiles.as_deref(),
+        );
+        let image = &self.parser.image;
+        let offset = self.state.checkpoint.map_or(available_bytes, |checkpoint| {
+            let stop = checkpoint.open.map_or(checkpoint.sot, |open| open.position);
+            self.state.codestream_start + stop
+        });
+        Ok(J2kPartialDecode {
+            image,
+            layers,
+            resolutions,
+            offset,
+            complete_rows: Self::complete_rows(image, &self.state.tile_states),
+        })
+    }
+
+    /// Snapshots the image decoded so far and where decoding stopped, e.g. for a tile
+    /// server to cache a tile read with [`decode_partial`](Self::decode_partial) and refine
+    /// it when more bytes arrive without decoding the packets already read again.
+    pub fn save_state(&self) -> J2kDecoderState {
+        J2kDecoderState {
+            image: self.parser.image.clone(),
+            state: self.state.clone(),
+        }
+    }
+
+    /// Restores a snapshot taken by [`save_state`](Self::save_state) from a decoder of the
+    /// same stream; the next [`decode_partial`](Self::decode_partial) or
+    /// [`decode`](Self::decode) continues with the first packet not read. The reader must
+    /// start where that of the saved decoder did.
+    ///
+    /// The main header of this decoder's stream is read and compared with the snapshot:
+    /// returns `InvalidArgument` if the codestream starts elsewhere, e.g. behind other JP2
+    /// boxes, or its image and tile sizes, components or coding style differ, and the
+    /// error of the main header if it cannot be read. The decoder is left as it was then.
+    pub fn resume(&mut self, state: J2kDecoderState) -> Result<(), JpeglsError> {
+        if state.state.checkpoint.is_some() {
+            let (codestream_start, header) = self.read_main_header()?;
+            let saved = &state.image;
+            let siz = |image: &J2kImage| {
+                (
+                    (image.width, image.height, image.x_origin, image.y_origin),
+                    (image.tile_width, image.tile_height),
+                    (image.tile_x_origin, image.tile_y_origin),
+                    image.component_count,
+                )
+            };
+            if codestream_start != state.state.codestream_start
+                || siz(&header) != siz(saved)
+                || header.components != saved.components
+                || header.cod != saved.cod
+                || state.state.tile_states.len() > header.tile_count() as usize
+                || saved.tiles.len() > header.tile_count() as usize
+            {
+                return Err(JpeglsError::InvalidArgument);
+            }
+        }
+        self.parser.image = state.image;
+        self.state = state.state;
+        Ok(())
+    }
+
+    /// Reads the main header of the stream from where the reader started, past the boxes
+    /// before the codestream in a JP2 file, without moving the reader. Returns the offset
+    /// of the codestream and the image with the header read.
+    fn read_main_header(&mut self) -> Result<(usize, Box<J2kImage>), JpeglsError> {
+        let position = self.parser.reader.position();
+        self.parser.reader.seek(self.origin);
+        let result = {
+            let data = self.parser.reader.remaining_data();
+            let codestream_start = crate::jpeg2000::jp2::Jp2Reader::new(data)
+                .codestreams()
+                .get(self.codestream)
+                .map_or(0, |range| range.start);
+            let mut reader = JpegStreamReader::new(&data[codestream_start..]);
+            let mut parser = J2kParser::new(&mut reader);
+            parser
+                .parse_main_header()
+                .map(|_| (codestream_start, std::mem::take(&mut parser.image)))
+        };
+        self.parser.reader.seek(position);
+        result
+    }
+
+    fn decode_tracked(&mut self, available: Option<usize>) -> Result<(), JpeglsError> {
+        let session = Session::begin();
+        let result = self.decode_image(available);
+        let image = &self.parser.image;
+        let _image_memory = track_elements::<u8>(image.buffer_bytes());
+        let (width, height) = image.size();
+        self.stats = DecodeStats {
+            peak_buffer_bytes: session.finish(),
+            pixel_count: width as u64 * height as u64,
+        };
+        result
+    }
+
+    /// Quality layers and resolutions whose packets are all read in every selected tile.
+    fn completed(image: &J2kImage, tile_states: &[TileState], tiles: Option<&[u16]>) -> (u16, u8) {
+        let all: Vec<u16> = (0..image.tile_count() as u16).collect();
+        tiles
+            .unwrap_or(&all)
+            .iter()
+            .map(|&t| {
+                tile_states
+                    .get(t as usize)
+                    .map_or((0, 0), |state| (state.layers_read, state.resolutions_read))
+            })
+            .fold((u16::MAX, u8::MAX), |(layers, resolutions), (l, r)| {
+                (layers.min(l), resolutions.min(r))
+            })
+    }
+
+    /// Rows of the image area above the first tile, in raster order, that has a quality
+    /// layer or resolution not read.
+    fn complete_rows(image: &J2kImage, tile_states: &[TileState]) -> u32 {
+        let Some(cod) = image.cod.as_ref() else {
+            return 0;
+        };
+        let complete = |index: u32| {
+            tile_states.get(index as usize).is_some_and(|state| {
+                state.layers_read >= cod.number_of_layers
+                    && state.resolutions_read > cod.decomposition_levels
+            })
         };
+        let (_, height) = image.size();
+        match (0..image.tile_count()).find(|&index| !complete(index)) {
+            Some(index) => image.tile_bounds(index).1 - image.y_origin,
+            None => height,
+        }
+    }
 
-        let icc_profile = {
-            let mut jp2_reader =
-                crate::jpeg2000::jp2::Jp2Reader::new(self.parser.reader.remaining_data());
-            jp2_reader.find_icc_profile().unwrap_or_default()
+    /// Decodes the image, from the first `available` bytes of the stream when given.
+    fn decode_image(&mut self, available: Option<usize>) -> Result<(), JpeglsError> {
+        let mut progress = Progress {
+            handler: self.progress_handler.as_deref_mut(),
+            reported: 0,
         };
+        let partial = available.is_some();
+        self.state.partial = partial;
+        // A decode continuing an earlier one reads the stream from its start again.
+        let resuming = self.state.checkpoint;
+        if resuming.is_some() {
+            self.parser.reader.seek(self.origin);
+        }
+        let data = self.parser.reader.remaining_data();
+        let data = &data[..available.map_or(data.len(), |n| n.min(data.len()))];
 
-        if let Some(cs) = codestream {
+        // 0. Container Detection (JP2 Box)
+        // We use a separate reader/parser logic for checking the container.
+        let codestreams = crate::jpeg2000::jp2::Jp2Reader::new(data).codestreams();
+        self.state.codestream_start = codestreams.get(self.codestream).map_or(0, |r| r.start);
+        let codestream = match codestreams.get(self.codestream) {
+            Some(range) => Some(&data[range.clone()]),
+            None if self.codestream == 0 => None,
+            // The selected codestream may be in the bytes still to come.
+            None if partial => return Err(JpeglsError::NeedMoreData),
+            None => return Err(JpeglsError::InvalidArgument),
+        };
+
+        // A partial or continued decode reads the bytes available from a reader of their own.
+        if let Some(cs) = codestream.or((partial || resuming.is_some()).then_some(data)) {
             let mut sub_reader = JpegStreamReader::new(cs);
             let mut sub_parser = J2kParser::new(&mut sub_reader);
 
-            // 1. Parse Main Header with sub_parser
-            let last_marker = sub_parser.parse_main_header()?;
-
-            // 2. Identify Decoding Path
-            let is_htj2k = if let Some(cap) = &sub_parser.image.cap {
-                (cap.pcap & (1 << 14)) != 0
-            } else {
-                false
+            let last_marker = match resuming {
+                // The main header was read before: go on from the checkpoint.
+                Some(checkpoint) => {
+                    sub_parser.image = std::mem::take(&mut self.parser.image);
+                    sub_parser.reader.seek(checkpoint.sot);
+                    Self::find_next_marker(&mut sub_parser).ok()
+                }
+                // 1. Parse Main Header with sub_parser
+                // Without a tile-part the main header may be cut short too.
+                None => match sub_parser.parse_main_header() {
+                    Err(_) if partial && !cs.windows(2).any(|w| w == [0xFF, 0x90]) => {
+                        return Err(JpeglsError::NeedMoreData);
+                    }
+                    result => {
+                        let marker = result?;
+                        if !partial {
+                            Self::check_tile_parts(&sub_parser.image, cs.len())?;
+                        }
+                        Some(marker)
+                    }
+                },
             };
 
-            // 3. Decode Tiles using sub_parser
-            Self::__decode_tiles_loop(
-                &mut sub_parser,
-                last_marker,
-                is_htj2k,
-                &mut self.tile_states,
-            )?;
-
-            // Copy results back to main parser state
-            self.parser.image = sub_parser.image.clone();
-            self.parser.image.icc_profile = icc_profile;
+            // 2. Decode Tiles using sub_parser
+            let result = last_marker.map_or(Ok(()), |marker| {
+                Self::__decode_tiles_loop(
+                    &mut sub_parser,
+                    marker,
+                    self.tiles.as_deref(),
+                    &mut self.state,
+                    &mut progress,
+                    &mut self.scratch,
+                )
+            });
+
+            // Move results back to main parser state
+            self.parser.image = std::mem::take(&mut sub_parser.image);
+            crate::jpeg2000::parser::read_jp2_boxes(&mut self.parser.image, data);
+            result?;
         } else {
             // 1. Parse Main Header with self.parser
+            let len = data.len();
             let last_marker = self.parser.parse_main_header()?;
+            Self::check_tile_parts(&self.parser.image, len)?;
 
-            // 2. Identify Decoding Path
-            let is_htj2k = if let Some(cap) = &self.parser.image.cap {
-                (cap.pcap & (1 << 14)) != 0
-            } else {
-                false
-            };
-
-            // 3. Decode Tiles using self.parser
-            Self::__decode_tiles_loop(
+            // 2. Decode Tiles using self.parser
+            let result = Self::__decode_tiles_loop(
                 &mut self.parser,
                 last_marker,
-                is_htj2k,
-                &mut self.tile_states,
-            )?;
+                self.tiles.as_deref(),
+                &mut self.state,
+                &mut progress,
+                &mut self.scratch,
+            );
+            if let Some(checkpoint) = &mut self.state.checkpoint {
+                checkpoint.rebase(self.origin);
+            }
+            result?;
         }
 
-        Ok(&self.parser.image)
+        let image = &self.parser.image;
+        let levels = image.cod.as_ref().map_or(0, |cod| cod.decomposition_levels);
+        if partial {
+            let (_, resolutions) =
+                Self::completed(image, &self.state.tile_states, self.tiles.as_deref());
+            return match resolutions.checked_sub(1) {
+                Some(resolution) => progress.report(image, resolution),
+                None => Ok(()),
+            };
+        }
+        progress.report(image, levels)
     }
 
     /// Internal loop to process tiles.
     /// Detached from `self` to allow using either `self.parser` or `sub_parser`.
+    /// Fails a complete codestream too short to hold a tile-part, a 12-byte SOT marker
+    /// segment and an SOD marker, for each tile its SIZ declares.
+    fn check_tile_parts(image: &J2kImage, len: usize) -> Result<(), JpeglsError> {
+        if image.tile_count() as usize * 14 > len {
+            return Err(JpeglsError::InvalidData);
+        }
+        Ok(())
+    }
+
     fn __decode_tiles_loop(
         parser: &mut J2kParser,
         mut marker: crate::jpeg_marker_code::JpegMarkerCode,
-        is_htj2k: bool,
-        tile_states: &mut Vec<TileState>,
+        tiles: Option<&[u16]>,
+        state: &mut DecodeState,
+        progress: &mut Progress,
+        scratch: &mut BlockScratch,
     ) -> Result<(), JpeglsError> {
+        let is_selected = |isot: u16| tiles.is_none_or(|tiles| tiles.contains(&isot));
+        let partial = state.partial;
+        // A decode continuing an earlier one starts at its checkpoint.
+        let first_tile_part = state
+            .checkpoint
+            .map_or(0, |checkpoint| checkpoint.tile_part);
+        // The checkpoint of the tile-part whose SOT marker starts at `sot`; a tile-part
+        // left open keeps its packets read.
+        let checkpoint = |state: &DecodeState, tile_part: usize, sot: usize| Checkpoint {
+            tile_part,
+            sot,
+            open: state
+                .checkpoint
+                .and_then(|checkpoint| checkpoint.open)
+                .filter(|open| open.sot == sot),
+        };
+
+        // With TLM the selected tile-parts are found without reading the others.
+        if tiles.is_some()
+            && !parser.image.tile_part_lengths.is_empty()
+            && marker == crate::jpeg_marker_code::JpegMarkerCode::StartOfTile
+        {
+            let mut sot = parser.reader.position() - 2;
+            let tile_part_lengths = parser.image.tile_part_lengths.clone();
+            let entries = tile_part_lengths.iter().enumerate().skip(first_tile_part);
+            for (tile_part, entry) in entries {
+                if is_selected(entry.tile_index) {
+                    state.checkpoint = Some(checkpoint(state, tile_part, sot));
+                    parser.reader.seek(sot + 2);
+                    let result = Self::decode_tile_part(
+                        parser, sot, tile_part, tiles, state, progress, scratch,
+                    );
+                    // The available bytes end in this tile-part: keep the packets read.
+                    match result {
+                        Err(_) if partial && Self::cut_short(parser, sot) => return Ok(()),
+                        result => result?,
+                    };
+                }
+                sot += entry.length as usize;
+                state.checkpoint = Some(checkpoint(state, tile_part + 1, sot));
+            }
+            return Ok(());
+        }
+
+        let mut tile_part = first_tile_part;
         loop {
             if marker == crate::jpeg_marker_code::JpegMarkerCode::EndOfImage {
                 break;
             }
 
             if marker == crate::jpeg_marker_code::JpegMarkerCode::StartOfTile {
-                let (psot, isot) = parser.parse_tile_part_header()?;
-                Self::decode_tile_data(parser, psot, isot, is_htj2k, tile_states)?;
+                let sot = parser.reader.position() - 2;
+                state.checkpoint = Some(checkpoint(state, tile_part, sot));
+                let result =
+                    Self::decode_tile_part(parser, sot, tile_part, tiles, state, progress, scratch);
+                // The available bytes end in this tile-part: keep the packets read.
+                let header = match result {
+                    Err(_) if partial && Self::cut_short(parser, sot) => break,
+                    result => result?,
+                };
+                tile_part += 1;
+                // The last tile-part (Psot 0) runs to the end of the data.
+                let next = match header.length {
+                    0 => parser.reader.position() + parser.reader.remaining_data().len(),
+                    psot => sot + psot as usize,
+                };
+                state.checkpoint = Some(checkpoint(state, tile_part, next));
+
+                // Every tile-part of the selected tiles is read.
+                let complete = |&t: &u16| {
+                    parser
+                        .image
+                        .tiles
+                        .get(t as usize)
+                        .is_some_and(J2kTile::is_complete)
+                };
+                if tiles.is_some_and(|tiles| tiles.iter().all(complete)) {
+                    break;
+                }
 
+                // Psot locates the next tile-part; 0 marks the last one.
+                if header.length != 0 {
+                    parser.reader.seek(sot + header.length as usize);
+                } else if !is_selected(header.tile_index) {
+                    break;
+                }
                 if parser.reader.remaining_data().is_empty() {
                     break;
                 }
 
-                marker = Self::find_next_marker(parser)?;
+                marker = match Self::find_next_marker(parser) {
+                    // The available bytes end before the next tile-part.
+                    Err(_) if partial => break,
+                    result => result?,
+                };
             } else {
                 break;
             }
@@ -145,6 +681,121 @@ impl<'a, 'b> J2kDecoder<'a, 'b> {
         Ok(())
     }
 
+    /// Reads the header of the tile-part whose SOT marker starts at `sot` and, if its
+    /// tile is selected, decodes its packets. The packets of a tile continue from one
+    /// tile-part to the next, so these must come in TPsot order; a tile-part repeating
+    /// one already read is skipped. A tile-part the checkpoint left open continues with
+    /// its first packet not read.
+    fn decode_tile_part(
+        parser: &mut J2kParser,
+        sot: usize,
+        tile_part: usize,
+        tiles: Option<&[u16]>,
+        state: &mut DecodeState,
+        progress: &mut Progress,
+        scratch: &mut BlockScratch,
+    ) -> Result<J2kTilePart, JpeglsError> {
+        let open = state.checkpoint.and_then(|checkpoint| checkpoint.open);
+        let (header, end) = match open.filter(|open| open.sot == sot) {
+            Some(open) => {
+                parser.reader.seek(open.position);
+                (open.header, open.end)
+            }
+            None => {
+                let plt_lengths = |image: &J2kImage| -> usize {
+                    image.tiles.iter().map(|t| t.packet_lengths.len()).sum()
+                };
+                let plt_before = plt_lengths(&parser.image);
+                let header = Self::read_tile_part_header(parser)?;
+                let (psot, isot) = (header.length, header.tile_index);
+                let tile = parser.tile_mut(isot);
+                match header.part_index.cmp(&tile.tile_parts_read) {
+                    std::cmp::Ordering::Less => return Ok(header),
+                    std::cmp::Ordering::Greater => return Err(JpeglsError::InvalidData),
+                    std::cmp::Ordering::Equal => tile.tile_parts_read += 1,
+                }
+                if tiles.is_some_and(|tiles| !tiles.contains(&isot)) {
+                    return Ok(header);
+                }
+
+                // PLM packet lengths stand in for a tile-part header without PLT.
+                if plt_lengths(&parser.image) == plt_before {
+                    if let Some(lengths) = parser.image.packet_lengths.get(tile_part).cloned() {
+                        parser.tile_mut(isot).packet_lengths.extend(lengths);
+                    }
+                }
+                // PPM runs are in tile-part order; a codestream with PPM has no PPT.
+                if let Some(headers) = parser.image.packed_headers.get(tile_part).cloned() {
+                    parser.tile_mut(isot).packed_headers.extend(headers);
+                }
+
+                let end = if psot == 0 {
+                    parser.reader.position() + parser.reader.remaining_data().len()
+                } else {
+                    sot + psot as usize
+                };
+                (header, end)
+            }
+        };
+
+        let isot = header.tile_index as usize;
+        if state.tile_states.len() <= isot {
+            state.tile_states.resize_with(isot + 1, Default::default);
+        }
+        state.tile_states[isot].packet_position = parser.reader.position();
+        let result =
+            Self::decode_tile_data(parser, end, header.tile_index, state, progress, scratch);
+        // Cut short, the tile-part is left open at its first packet not read.
+        if let (Err(_), Some(checkpoint)) = (&result, &mut state.checkpoint) {
+            checkpoint.open = Some(OpenTilePart {
+                sot,
+                header,
+                end,
+                position: state.tile_states[isot].packet_position,
+            });
+        }
+        result.map(|()| header)
+    }
+
+    /// Reads a tile-part header. One cut short by the end of the data leaves the tile as
+    /// it was, so the header can be read again once more bytes arrive.
+    fn read_tile_part_header(parser: &mut J2kParser) -> Result<J2kTilePart, JpeglsError> {
+        // Isot follows Lsot.
+        let isot = match parser.reader.remaining_data().get(2..4) {
+            Some(&[a, b]) => u16::from_be_bytes([a, b]) as usize,
+            _ => return parser.parse_tile_part_header(),
+        };
+        let (packet_lengths, poc) = parser
+            .image
+            .tiles
+            .get(isot)
+            .map_or((0, 0), |tile| (tile.packet_lengths.len(), tile.poc.len()));
+        let result = parser.parse_tile_part_header();
+        if result.is_err() {
+            if let Some(tile) = parser.image.tiles.get_mut(isot) {
+                tile.packet_lengths.truncate(packet_lengths);
+                tile.poc.truncate(poc);
+            }
+        }
+        result
+    }
+
+    /// Whether the tile-part whose SOT marker starts at `sot` runs past the end of the
+    /// data: its Psot length does or, for the last tile-part (Psot 0), the data does not
+    /// end with the EOC marker.
+    fn cut_short(parser: &mut J2kParser, sot: usize) -> bool {
+        parser.reader.seek(sot);
+        let tile_part = parser.reader.remaining_data();
+        // Psot follows the marker, Lsot and Isot.
+        match tile_part.get(6..10) {
+            Some(&[a, b, c, d]) => match u32::from_be_bytes([a, b, c, d]) as usize {
+                0 => !tile_part.ends_with(&[0xFF, 0xD9]),
+                psot => psot > tile_part.len(),
+            },
+            _ => true,
+        }
+    }
+
     /// Scans the stream for the next marker.
     fn find_next_marker(
         parser: &mut J2kParser,
@@ -165,13 +816,14 @@ impl<'a, 'b> J2kDecoder<'a, 'b> {
         }
     }
 
-    /// Decodes the tile data.
+    /// Decodes the tile data up to `end`, the offset of the end of the tile-part.
     fn decode_tile_data(
         parser: &mut J2kParser,
-        _len: u32,
+        end: usize,
         isot: u16,
-        _is_htj2k: bool,
-        tile_states: &mut Vec<TileState>,
+        state: &mut DecodeState,
+        progress: &mut Progress,
+        scratch: &mut BlockScratch,
     ) -> Result<(), JpeglsError> {
         let tile_idx = isot as usize;
         if parser.image.tiles.len() <= tile_idx {
@@ -192,34 +844,12 @@ impl<'a, 'b> J2kDecoder<'a, 'b> {
         let num_resolutions = (cod.decomposition_levels + 1) as usize;
         let num_components = parser.image.component_count as usize;
 
-        let _progression_order = cod.progression_order;
         let _cb_w = 1 << (cod.codeblock_width_exp + 2);
         let _cb_h = 1 << (cod.codeblock_height_exp + 2);
 
         // Coordinate calculation logic based on ISO/IEC 15444-1 Annex B
-        // 1. Determine Tile Grid indices (p, q)
-        let x_siz = parser.image.width;
-        let y_siz = parser.image.height;
-        let x_osiz = parser.image.x_origin;
-        let y_osiz = parser.image.y_origin;
-        let x_tsiz = parser.image.tile_width;
-        let y_tsiz = parser.image.tile_height;
-        let x_tosiz = parser.image.tile_x_origin;
-        let y_tosiz = parser.image.tile_y_origin;
-
-        // Number of tiles in X and Y
-        let num_tiles_x = (x_siz.saturating_sub(x_tosiz)).div_ceil(x_tsiz);
-        // avoid div by zero if tile size is huge or something (parser checks usually catch this)
-        let num_tiles_x = if num_tiles_x == 0 { 1 } else { num_tiles_x };
-
-        let p = isot as u32 % num_tiles_x;
-        let q = isot as u32 / num_tiles_x;
-
-        // 2. Determine Tile coordinates on Reference Grid (tx0, ty0, tx1, ty1)
-        let tx0 = (x_tosiz + p * x_tsiz).max(x_osiz);
-        let ty0 = (y_tosiz + q * y_tsiz).max(y_osiz);
-        let tx1 = (x_tosiz + (p + 1) * x_tsiz).min(x_siz);
-        let ty1 = (y_tosiz + (q + 1) * y_tsiz).min(y_siz);
+        // 1-2. Tile grid indices (p, q) and the tile coordinates on the reference grid
+        let (tx0, ty0, tx1, ty1) = parser.image.tile_bounds(isot as u32);
 
         // Initialize tile components and resolutions with correct dimensions
         {
@@ -254,13 +884,12 @@ impl<'a, 'b> J2kDecoder<'a, 'b> {
                     let shift = num_resolutions.saturating_sub(1 + r);
 
                     // 4. Determine Resolution Level coordinates (trx0, try0, trx1, try1)
-                    // Division by 2^shift is equivalent to >> shift, but ceil requires handling.
-                    // ceil(x / 2^s) = (x + (1<<s) - 1) >> s
-                    let denom = 1 << shift;
-                    let trx0 = (tcx0 + denom - 1) >> shift;
-                    let trx1 = (tcx1 + denom - 1) >> shift;
-                    let try0 = (tcy0 + denom - 1) >> shift;
-                    let try1 = (tcy1 + denom - 1) >> shift;
+                    // ceil(x / 2^s), in 64 bits as up to 32 levels may be discarded.
+                    let reduce = |v: u32| (v as u64).div_ceil(1 << shift) as u32;
+                    let trx0 = reduce(tcx0);
+                    let trx1 = reduce(tcx1);
+                    let try0 = reduce(tcy0);
+                    let try1 = reduce(tcy1);
 
                     let res_w = trx1.saturating_sub(trx0);
                     let res_h = try1.saturating_sub(try0);
@@ -320,19 +949,66 @@ impl<'a, 'b> J2kDecoder<'a, 'b> {
         }
 
         // Finalize decoding steps (e.g. IDWT, Color Transform) are handled in `decode` after this returns
-        Self::decode_packets(parser, tile_states)
+        let tile = (tx0 as usize, ty0 as usize, tx1 as usize, ty1 as usize);
+        Self::decode_packets(parser, tile_idx, tile, end, state, progress, scratch)
+    }
+
+    /// Progression volumes of a tile: its POC progressions, else those of the main
+    /// header, else the COD progression order over the whole tile.
+    fn progression_volumes(
+        image: &J2kImage,
+        tile_idx: usize,
+        grids: &[ComponentGrid],
+    ) -> Result<Vec<ProgressionVolume>, JpeglsError> {
+        let cod = image.cod.as_ref().ok_or(JpeglsError::InvalidData)?;
+        let tile_poc = &image.tiles[tile_idx].poc;
+        let poc = if tile_poc.is_empty() {
+            &image.poc
+        } else {
+            tile_poc
+        };
+        if poc.is_empty() {
+            let order = ProgressionOrder::try_from(cod.progression_order)?;
+            return Ok(vec![ProgressionVolume::whole(
+                order,
+                cod.number_of_layers,
+                grids,
+            )]);
+        }
+        poc.iter()
+            .map(|poc| {
+                Ok(ProgressionVolume {
+                    order: ProgressionOrder::try_from(poc.progression_order)?,
+                    layer_end: poc.layer_end,
+                    resolution_start: poc.resolution_start as usize,
+                    resolution_end: poc.resolution_end as usize,
+                    component_start: poc.component_start as usize,
+                    component_end: poc.component_end as usize,
+                })
+            })
+            .collect()
     }
 
     // Updated decode_packets to use per-precinct TagTrees
     fn decode_packets(
         parser: &mut J2kParser,
-        tile_states: &mut Vec<TileState>,
+        tile_idx: usize,
+        tile: TileBounds,
+        end: usize,
+        state: &mut DecodeState,
+        progress: &mut Progress,
+        scratch: &mut BlockScratch,
     ) -> Result<(), JpeglsError> {
+        // A packet of a tile-part that runs to the end of the data may be cut short.
+        let may_be_cut =
+            state.partial && end >= parser.reader.position() + parser.reader.remaining_data().len();
+        let tile_states = &mut state.tile_states;
+
         // Ensure we have state for the current tile
-        if tile_states.is_empty() {
-            tile_states.push(TileState::default());
+        if tile_states.len() <= tile_idx {
+            tile_states.resize_with(tile_idx + 1, Default::default);
         }
-        let tile_state_idx = tile_states.len() - 1;
+        let tile_state_idx = tile_idx;
 
         let cod = parser
             .image
@@ -341,201 +1017,274 @@ impl<'a, 'b> J2kDecoder<'a, 'b> {
             .ok_or(JpeglsError::InvalidData)?
             .clone();
 
-        let num_layers = cod.number_of_layers as usize;
-        let num_resolutions = (cod.decomposition_levels + 1) as usize;
         let num_components = parser.image.component_count as usize;
 
-        // Use actual number of layers
-        let safe_num_layers = num_layers; // Corrected logic
-
-        let isot = parser.image.tiles.len().saturating_sub(1) as u16;
-        let is_htj2k = false; // Placeholder
-
-        // LRCP Loop
-        for l in 0..safe_num_layers {
-            for r in 0..num_resolutions {
-                let num_subbands = if r == 0 { 1 } else { 3 };
-
-                for c in 0..num_components {
-                    // Ensure state exists
-                    if tile_states[tile_state_idx].components.len() <= c {
-                        tile_states[tile_state_idx]
-                            .components
-                            .resize_with(c + 1, Default::default);
-                    };
-                    let comp_state = &mut tile_states[tile_state_idx].components[c];
-
-                    // Ensure resolution state exists
-                    if comp_state.resolutions.len() <= r {
-                        // NOTE: decode_tile_data MUST have been called first to populate dimensions in parser.image.tiles
-                        // However, we need to get dimensions from parser.image to init state if needed,
-                        // or better, rely on decode_tile_data having set it up?
-                        // decode_tile_data updates parser.image.tiles.
-                        // tile_states is parallel.
-                        // We need width/height here.
-                        // Since we are iterating c, r, we can pull from parser.image
-                        let tile = &parser.image.tiles[tile_state_idx]; // assuming isot matches idx
-                        // Wait, tile_idx passed to decode_tile_data was isot.
-                        // Here tile_states uses tile_state_idx.
-                        // We should lookup tile by index if possible, but for now assume sequential.
-                        let comp_info = &tile.components[c];
-                        let res_info = &comp_info.resolutions[r];
-                        comp_state.resolutions.resize_with(r + 1, || {
-                            ResolutionState::new(res_info.width as usize, res_info.height as usize)
-                        });
-                    };
-                    let res_state = &mut comp_state.resolutions[r];
-                    let res_w = res_state.width;
-                    let res_h = res_state.height;
-
-                    // Calculate grid dimensions for this component/resolution
-                    let (ppx, ppy) = if !cod.precinct_sizes.is_empty() {
-                        if r < cod.precinct_sizes.len() {
-                            let s = cod.precinct_sizes[r];
-                            let shift_x = s & 0x0F;
-                            let shift_y = (s >> 4) & 0x0F;
-                            (1 << shift_x, 1 << shift_y)
-                        } else {
-                            (32768, 32768)
-                        }
-                    } else {
-                        (32768, 32768)
-                    };
+        let isot = tile_idx as u16;
+
+        // Precinct grids of every component; without precinct sizes each resolution
+        // has one precinct (PPx = PPy = 15).
+        let precinct_sizes: Vec<(u8, u8)> = cod
+            .precinct_sizes
+            .iter()
+            .map(|s| (s & 0x0F, s >> 4))
+            .collect();
+        let grids: Vec<ComponentGrid> = (0..num_components)
+            .map(|c| {
+                let (dx, dy) = parser.image.components.get(c).map_or((1, 1), |info| {
+                    (info.dx.max(1) as usize, info.dy.max(1) as usize)
+                });
+                ComponentGrid::new(tile, (dx, dy), cod.decomposition_levels, &precinct_sizes)
+            })
+            .collect();
+        let volumes = Self::progression_volumes(&parser.image, tile_idx, &grids)?;
+
+        // Earlier tile-parts of the tile hold the first packets of the sequence.
+        let packets = progression::packet_sequence(tile, &grids, cod.number_of_layers, &volumes);
+
+        // Index of the packet after which every resolution up to r is read. The other tiles
+        // are not read yet mid-stream, so only single-tile images report resolutions then.
+        let image = &parser.image;
+        let single_tile = tile_idx == 0
+            && image.tile_x_origin.saturating_add(image.tile_width) >= image.width
+            && image.tile_y_origin.saturating_add(image.tile_height) >= image.height;
+        let mut resolution_read = vec![0; cod.decomposition_levels as usize + 1];
+        for (packet_idx, &(_, r, _, _)) in packets.iter().enumerate() {
+            if let Some(read) = resolution_read.get_mut(r) {
+                *read = packet_idx;
+            }
+        }
+        let mut last = 0;
+        for read in &mut resolution_read {
+            last = last.max(*read);
+            *read = last;
+        }
+        // The same for quality layers.
+        let mut layer_read = vec![0; cod.number_of_layers as usize];
+        for (packet_idx, &(layer, _, _, _)) in packets.iter().enumerate() {
+            if let Some(read) = layer_read.get_mut(layer as usize) {
+                *read = packet_idx;
+            }
+        }
+        let mut last = 0;
+        for read in &mut layer_read {
+            last = last.max(*read);
+            *read = last;
+        }
 
-                    let grid_w = res_w.div_ceil(ppx);
-                    let grid_h = res_h.div_ceil(ppy);
-
-                    // Iterate Precincts
-                    let num_px = grid_w;
-                    let num_py = grid_h;
-
-                    for py in 0..num_py {
-                        for px in 0..num_px {
-                            let precinct_state = res_state
-                                .precincts
-                                .entry((px, py))
-                                .or_insert_with(|| PrecinctState::new(num_subbands, 0));
-
-                            // SOP Marker Handling
-                            if (cod.coding_style & 0x02) != 0 {
-                                // SOP: FF 91 + Lsop(2) + Nsop(2) = 6 bytes
-
-                                // Read strict
-                                let marker = parser.reader.read_u16().unwrap_or(0);
-                                if marker == 0xFF91 {
-                                    // eprintln!("DEBUG: Found SOP marker at {}", pos);
-                                    let _lsop = parser.reader.read_u16().unwrap_or(0);
-                                    let _nsop = parser.reader.read_u16().unwrap_or(0);
-                                } else {
-                                    // eprintln!("DEBUG: Expected SOP at {}, got {:04X}", pos, marker);
-                                    return Err(JpeglsError::InvalidData);
-                                }
-                            }
+        let packets_read = tile_states[tile_state_idx].packets_read;
+        let packed_headers = parser.image.tiles[tile_idx].packed_headers.clone();
+        let mut packed_reader = (!packed_headers.is_empty()).then(|| {
+            let mut reader = JpegStreamReader::new(&packed_headers);
+            reader.seek(tile_states[tile_state_idx].packed_headers_read);
+            reader
+        });
+        for (packet_idx, (layer, r, c, p)) in packets.into_iter().enumerate().skip(packets_read) {
+            // With packed headers a tile-part may hold packets without any body bytes.
+            let done = match packed_reader.as_ref() {
+                Some(reader) => reader.remaining_data().is_empty(),
+                None => parser.reader.position() >= end,
+            };
+            if done {
+                break;
+            }
+            let packet_start = parser.reader.position();
+            let packet_length = parser.image.tiles[tile_idx]
+                .packet_lengths
+                .get(packet_idx)
+                .copied();
+            let l = layer as usize;
+
+            // Ensure state exists
+            if tile_states[tile_state_idx].components.len() <= c {
+                tile_states[tile_state_idx]
+                    .components
+                    .resize_with(c + 1, Default::default);
+            };
+            let comp_state = &mut tile_states[tile_state_idx].components[c];
+
+            // Ensure resolution state exists
+            if comp_state.resolutions.len() <= r {
+                // decode_tile_data has set up the dimensions in parser.image.tiles.
+                let res_info = &parser.image.tiles[tile_idx].components[c].resolutions[r];
+                comp_state.resolutions.resize_with(r + 1, || {
+                    ResolutionState::new(res_info.width as usize, res_info.height as usize)
+                });
+            };
+            let res_state = &mut comp_state.resolutions[r];
+
+            let grid = &grids[c].resolutions[r];
+            let grid_w = grid.precincts_wide as u32;
+            let px = p as u32 % grid_w;
+            let py = p as u32 / grid_w;
+
+            // The state of a precinct persists over the layers and tile-parts of the tile.
+            let precinct_state = res_state.precincts.entry((px, py)).or_insert_with(|| {
+                let code_block_size = (cod.codeblock_width_exp + 2, cod.codeblock_height_exp + 2);
+                PrecinctState::new(&grid.code_block_grids(r, p, code_block_size))
+            });
+
+            // SOP Marker Handling
+            if (cod.coding_style & 0x02) != 0 {
+                // SOP: FF 91 + Lsop(2) + Nsop(2) = 6 bytes
+
+                // Read strict
+                let marker = parser.reader.read_u16().unwrap_or(0);
+                if marker == 0xFF91 {
+                    let _lsop = parser.reader.read_u16().unwrap_or(0);
+                    let _nsop = parser.reader.read_u16().unwrap_or(0);
+                } else {
+                    return Err(JpeglsError::InvalidData);
+                }
+            }
 
-                            // Read Packet Header
-                            let mut header = None;
-                            let mut _pos_to_advance = 0;
-                            {
-                                let remaining = parser.reader.remaining_data();
-                                if !remaining.is_empty() {
-                                    // J2kBitReader now uses parser.reader internal bit state, so creating/destroying it is safe
-                                    // We create a new scope to limit lifetime of bit_reader
-                                    let h = {
-                                        let mut bit_reader = crate::jpeg2000::bit_io::J2kBitReader::new(&mut parser.reader);
-                                        crate::jpeg2000::packet::PacketHeader::read(
-                                            &mut bit_reader,
-                                            precinct_state,
-                                            l as u32,
-                                            grid_w as usize,
-                                            grid_h as usize,
-                                            num_subbands,
-                                        )
-                                    };
-                                    match h {
-                                        Ok(h) => {
-                                            header = Some(h);
-                                        }
-                                        Err(_) => {
-                                            return Err(JpeglsError::InvalidData);
-                                        }
-                                    }
-                                }
-                            }
-                            if let Some(h) = header {
-                                if std::env::var("J2K_DEBUG").is_ok() {
-                                    let pos = parser.reader.position();
-                                    let remaining = parser.reader.remaining_data().len();
-                                    eprintln!("DECODE_PACKET: L={} R={} C={} P=({},{}) empty={} cblks={} pos={} remaining={}",
-                                        l, r, c, px, py, h.empty, h.included_cblks.len(), pos, remaining);
-                                }
-                                // If body follows AND there's data to read, we must align to byte boundary
-                                // Per ISO 15444-1 B.9: byte alignment happens after packet header
-                                // but only when there's actual codeblock data to follow
-                                if !h.empty && !h.included_cblks.is_empty() {
-                                    // Only align if there's codeblock data to read
-                                    let has_data = h.included_cblks.iter().any(|cb| cb.data_len > 0);
-                                    if has_data {
-                                        if std::env::var("J2K_DEBUG").is_ok() {
-                                            let pos_before = parser.reader.position();
-                                            parser.reader.align_to_byte();
-                                            let pos_after = parser.reader.position();
-                                            eprintln!("  align_to_byte: {} -> {}", pos_before, pos_after);
-                                        } else {
-                                            parser.reader.align_to_byte();
-                                        }
-                                    }
-                                }
+            // HT and Part 1 code-blocks differ in their packet headers too.
+            let block_coder = parser.image.block_coder(tile_idx, c)?;
+            let is_ht = block_coder == BlockCoder::Ht;
 
-                                // EPH Marker Handling
-                                if (cod.coding_style & 0x04) != 0 {
-                                    // EPH: FF 92 (2 bytes)
-                                    let marker = parser.reader.read_u16().unwrap_or(0);
-                                    if marker == 0xFF92 {
-                                        // eprintln!("DEBUG: Found EPH marker at {}", pos);
-                                    } else {
-                                        // If EPH is mandatory and missing, error.
-                                        return Err(JpeglsError::InvalidData);
-                                    }
-                                }
+            // A packet cut short leaves the precinct as it was, to be read again once more
+            // bytes arrive.
+            let saved = may_be_cut.then(|| precinct_state.clone());
 
-                                Self::decode_packet_body(parser, h, isot, c, r, l, is_htj2k)?;
-                            }
-                        }
+            // PPM/PPT move the packet headers out of the tile-part (A.7.4).
+            let header = match packed_reader.as_mut() {
+                Some(reader) => {
+                    Self::read_packet_header(reader, precinct_state, l, cod.coding_style, is_ht)
+                }
+                None => Self::read_packet_header(
+                    &mut parser.reader,
+                    precinct_state,
+                    l,
+                    cod.coding_style,
+                    is_ht,
+                ),
+            };
+            // A packet whose body is cut short by the end of the data is left out whole.
+            let remaining = parser.reader.remaining_data().len();
+            let body = |h: &PacketHeader| -> usize {
+                h.included_cblks.iter().map(|cb| cb.data_len as usize).sum()
+            };
+            let header = header.and_then(|header| match header {
+                Some(h) if body(&h) > remaining => Err(JpeglsError::InvalidData),
+                header => Ok(header),
+            });
+            let h = match header {
+                Ok(Some(h)) => h,
+                // The data ends before this packet: the tile-part is left open.
+                Ok(None) if may_be_cut => return Err(JpeglsError::InvalidData),
+                Ok(None) => break,
+                Err(error) => {
+                    if let Some(saved) = saved {
+                        *precinct_state = saved;
                     }
+                    return Err(error);
                 }
+            };
+            j2k_trace!(
+                "decode packet: L={} R={} C={} P=({},{}) empty={} cblks={} pos={} remaining={}",
+                l,
+                r,
+                c,
+                px,
+                py,
+                h.empty,
+                h.included_cblks.len(),
+                parser.reader.position(),
+                parser.reader.remaining_data().len()
+            );
+            Self::decode_packet_body(parser, scratch, h, isot, c, r, l)?;
+
+            // PLT/PLM lengths locate the next packet exactly.
+            if let Some(length) = packet_length {
+                parser.reader.seek(packet_start + length as usize);
+            }
+            let tile_state = &mut tile_states[tile_state_idx];
+            tile_state.packets_read = packet_idx + 1;
+            tile_state.packet_position = parser.reader.position();
+            if let Some(reader) = &packed_reader {
+                tile_state.packed_headers_read = reader.position();
             }
-        }
 
+            let complete = resolution_read
+                .iter()
+                .take_while(|&&read| read <= packet_idx)
+                .count();
+            let layers = layer_read
+                .iter()
+                .take_while(|&&read| read <= packet_idx)
+                .count();
+            tile_states[tile_state_idx].resolutions_read = complete as u8;
+            tile_states[tile_state_idx].layers_read = layers as u16;
+            if single_tile && complete > 0 {
+                progress.report(&parser.image, complete as u8 - 1)?;
+            }
+        }
         Ok(())
     }
 
+    /// Reads the header of a packet from `reader`, the codestream itself or the packed
+    /// headers of the tile, up to and including its EPH marker. Returns `None` when
+    /// `reader` has no data left. `is_ht` tells whether the code-blocks are HT coded.
+    fn read_packet_header(
+        reader: &mut JpegStreamReader,
+        precinct_state: &mut PrecinctState,
+        layer: usize,
+        coding_style: u8,
+        is_ht: bool,
+    ) -> Result<Option<PacketHeader>, JpeglsError> {
+        if reader.remaining_data().is_empty() {
+            return Ok(None);
+        }
+        // J2kBitReader uses the reader's internal bit state, so creating/destroying it is safe
+        let header = {
+            let mut bit_reader = crate::jpeg2000::bit_io::J2kBitReader::new(reader);
+            PacketHeader::read(&mut bit_reader, precinct_state, layer as u32, is_ht)
+                .map_err(|_| JpeglsError::InvalidData)?
+        };
+
+        // Per ISO 15444-1 B.10.1 every packet header, empty ones included, ends on a
+        // byte boundary.
+        reader.align_to_byte();
+        j2k_trace!("packet header ends at {}", reader.position());
+
+        // EPH Marker Handling
+        if (coding_style & 0x04) != 0 {
+            // EPH: FF 92 (2 bytes); if EPH is mandatory and missing, error.
+            let marker = reader.read_u16().unwrap_or(0);
+            if marker != 0xFF92 {
+                return Err(JpeglsError::InvalidData);
+            }
+        }
+        Ok(Some(header))
+    }
+
     fn decode_packet_body(
         parser: &mut J2kParser,
+        scratch: &mut BlockScratch,
         header: crate::jpeg2000::packet::PacketHeader,
         isot: u16,
         comp: usize,
         res: usize,
         layer: usize,
-        is_htj2k: bool,
     ) -> Result<(), JpeglsError> {
+        j2k_span!(_span, "j2k_tier1");
+        let block_coder = parser.image.block_coder(isot as usize, comp)?;
+        let roi_shift = parser.image.roi_shift(isot as usize, comp);
         for cb_info in header.included_cblks {
             if cb_info.data_len > 0 {
                 let data_len = cb_info.data_len as usize;
-                if std::env::var("J2K_DEBUG").is_ok() {
-                    let pos_before = parser.reader.position();
-                    eprintln!("  reading {} bytes of codeblock data at pos={}", data_len, pos_before);
-                }
-                let mut data = vec![0u8; data_len];
-                for item in &mut data {
-                    *item = parser.reader.read_u8()?;
-                }
-                if std::env::var("J2K_DEBUG").is_ok() {
-                    let pos_after = parser.reader.position();
-                    let remaining = parser.reader.remaining_data().len();
-                    let next_bytes: Vec<_> = parser.reader.remaining_data().iter().take(4).collect();
-                    eprintln!("  after reading: pos={} remaining={} next_bytes={:02X?}", 
-                        pos_after, remaining, next_bytes);
-                }
+                j2k_trace!(
+                    "reading {} bytes of code-block data at {}",
+                    data_len,
+                    parser.reader.position()
+                );
+                let data = parser
+                    .reader
+                    .remaining_data()
+                    .get(..data_len)
+                    .ok_or(JpeglsError::InvalidData)?
+                    .to_vec();
+                let _data_memory = track_elements::<u8>(data_len);
+                parser.reader.advance(data_len);
 
                 let tile = &mut parser.image.tiles[isot as usize];
                 if tile.components.len() <= comp {
@@ -568,44 +1317,92 @@ impl<'a, 'b> J2kDecoder<'a, 'b> {
                         _ => {}
                     };
                 }
-                if is_htj2k {
-                    let mut coder = crate::jpeg2000::ht_block_coder::coder::HTBlockCoder::new(
-                        &data, &data, 64, 64,
-                    );
-                    let mut block = crate::jpeg2000::image::J2kCodeBlock::default();
-                    block.layer_data.push(data.clone());
-                    block.layers_decoded = (layer + 1) as u8;
-                    let _ = coder.decode_block(&mut block);
-                    subband.codeblocks.push(block);
+
+                let cod = parser.image.cod.as_ref().unwrap();
+                // Code-blocks are no larger than the precincts of the subband, which
+                // are half the size of those of the resolution past resolution 0.
+                let (ppx, ppy) = cod
+                    .precinct_sizes
+                    .get(res)
+                    .map_or((15, 15), |s| (s & 0x0F, s >> 4));
+                let halved = (res > 0) as u8;
+                let nom_w = 1 << (cod.codeblock_width_exp + 2).min(ppx.saturating_sub(halved));
+                let nom_h = 1 << (cod.codeblock_height_exp + 2).min(ppy.saturating_sub(halved));
+
+                let (res_w, res_h) = (resolution.width as usize, resolution.height as usize);
+                let (sb_w, sb_h) = if res == 0 {
+                    (res_w, res_h)
                 } else {
-                    let cod = parser.image.cod.as_ref().unwrap();
-                    let nom_w = 1 << (cod.codeblock_width_exp + 2);
-                    let nom_h = 1 << (cod.codeblock_height_exp + 2);
+                    let ll_w = res_w.div_ceil(2);
+                    let ll_h = res_h.div_ceil(2);
+                    match subband_idx {
+                        0 => (res_w - ll_w, ll_h),         // HL
+                        1 => (ll_w, res_h - ll_h),         // LH
+                        2 => (res_w - ll_w, res_h - ll_h), // HH
+                        _ => (0, 0),
+                    }
+                };
 
-                    let (res_w, res_h) = (resolution.width as usize, resolution.height as usize);
-                    let (sb_w, sb_h) = if res == 0 {
-                        (res_w, res_h)
-                    } else {
-                        let ll_w = res_w.div_ceil(2);
-                        let ll_h = res_h.div_ceil(2);
-                        match subband_idx {
-                            0 => (res_w - ll_w, ll_h),         // HL
-                            1 => (ll_w, res_h - ll_h),         // LH
-                            2 => (res_w - ll_w, res_h - ll_h), // HH
-                            _ => (0, 0),
+                let cb_x = cb_info.x * nom_w;
+                let cb_y = cb_info.y * nom_h;
+                let cb_width = nom_w.min(sb_w.saturating_sub(cb_x));
+                let cb_height = nom_h.min(sb_h.saturating_sub(cb_y));
+
+                if block_coder == BlockCoder::Ht {
+                    let cb_idx = subband
+                        .codeblocks
+                        .iter()
+                        .position(|cb| cb.x == cb_info.x as u32 && cb.y == cb_info.y as u32);
+                    let block = match cb_idx {
+                        Some(idx) => &mut subband.codeblocks[idx],
+                        None => {
+                            subband
+                                .codeblocks
+                                .push(crate::jpeg2000::image::J2kCodeBlock {
+                                    x: cb_info.x as u32,
+                                    y: cb_info.y as u32,
+                                    block_coder,
+                                    ..Default::default()
+                                });
+                            subband.codeblocks.last_mut().unwrap()
                         }
                     };
-
-                    let cb_x = cb_info.x * nom_w;
-                    let cb_y = cb_info.y * nom_h;
-                    let cb_width = nom_w.min(sb_w.saturating_sub(cb_x));
-                    let cb_height = nom_h.min(sb_h.saturating_sub(cb_y));
-
-                    let qcd = parser
-                        .image
-                        .qcd
-                        .as_ref()
-                        .map_or(Default::default(), |q| q.clone());
+                    block.layer_data.push(data);
+                    block.layers_decoded = (layer + 1) as u8;
+                    block.coding_passes = block.coding_passes.saturating_add(cb_info.num_passes);
+                    block.segment_lengths.extend(&cb_info.segment_lengths);
+
+                    // Every contribution is decoded again from the start: the cleanup
+                    // segment comes first and the refinement segment, possibly spread
+                    // over several layers, after it.
+                    let segments = &mut scratch.segments;
+                    segments.clear();
+                    for contribution in &block.layer_data {
+                        segments.extend_from_slice(contribution);
+                    }
+                    let lcup = (block.segment_lengths[0] as usize).min(segments.len());
+                    let (cleanup, refinement) = segments.split_at(lcup);
+                    let refinement_passes = (block.coding_passes - 1) % 3;
+                    let mut coder = crate::jpeg2000::ht_block_coder::coder::HTBlockCoder::new(
+                        cleanup, cb_width, cb_height,
+                    )
+                    .ok_or(JpeglsError::InvalidData)?;
+                    // A quad whose CxtVLC codeword is not in the table fails the block; the
+                    // codewords of Annex C are not transcribed yet, so the image cannot be
+                    // decoded rather than coming out flat.
+                    coder
+                        .decode_block(block)
+                        .map_err(|()| JpeglsError::EncodingNotSupported)?;
+                    if refinement_passes > 0 {
+                        crate::jpeg2000::ht_block_coder::refinement::decode_refinement(
+                            block,
+                            refinement,
+                            refinement_passes,
+                        );
+                    }
+                } else {
+                    let default_qcd = Default::default();
+                    let qcd = parser.image.qcd.as_ref().unwrap_or(&default_qcd);
                     let guard_bits = (qcd.quant_style >> 5) & 0x07;
 
                     let qcd_idx = if res == 0 {
@@ -636,6 +1433,8 @@ impl<'a, 'b> J2kDecoder<'a, 'b> {
                         guard_bits + epsilon_b - 1
                     };
 
+                    // Max-shift ROI coefficients come with SPrgn more magnitude bit-planes.
+                    let m_b = m_b.saturating_add(roi_shift);
                     let max_bit_plane = m_b.saturating_sub(1).saturating_sub(cb_info.zero_bp);
 
                     let cb_idx = subband
@@ -643,48 +1442,58 @@ impl<'a, 'b> J2kDecoder<'a, 'b> {
                         .iter()
                         .position(|cb| cb.x == cb_info.x as u32 && cb.y == cb_info.y as u32);
 
+                    let orientation = subband.orientation as u8;
+                    let mq = scratch.mq.take().unwrap_or_default();
                     if let Some(idx) = cb_idx {
                         let block = &mut subband.codeblocks[idx];
-                        block.layer_data.push(data.clone());
-                        block.layers_decoded = (layer + 1) as u8;
-
-                        let mut bpc = crate::jpeg2000::bit_plane_coder::BitPlaneCoder::new(
+                        // The block carries on from its coefficients and state so far.
+                        let mut bpc = BitPlaneCoder::with_buffers(
                             block.width,
                             block.height,
-                            &[],
+                            std::mem::take(&mut block.coefficients),
+                            std::mem::take(&mut block.state),
+                            mq,
                         );
-                        bpc.coefficients = block.coefficients.clone();
-                        bpc.state = block.state.clone();
                         bpc.num_passes_decoded = block.coding_passes as u32;
 
-                        let _ = bpc.decode_codeblock(&data, max_bit_plane, cb_info.num_passes, subband.orientation as u8);
+                        let _ = bpc.decode_passes(&data, max_bit_plane, cb_info.num_passes, orientation);
 
-                        block.coefficients = bpc.coefficients;
-                        block.state = bpc.state;
                         block.coding_passes = bpc.num_passes_decoded as u8;
+                        let (coefficients, state, mq) = bpc.into_parts();
+                        block.coefficients = coefficients;
+                        block.state = state;
+                        block.layer_data.push(data);
+                        block.layers_decoded = (layer + 1) as u8;
+                        scratch.mq = Some(mq);
                     } else {
                         let mut block = crate::jpeg2000::image::J2kCodeBlock::default();
                         block.x = cb_info.x as u32;
                         block.y = cb_info.y as u32;
                         block.width = cb_width as u32;
                         block.height = cb_height as u32;
-                        block.layer_data.push(data.clone());
                         block.layers_decoded = (layer + 1) as u8;
                         block.coding_passes = 0;
 
-                        let mut bpc = crate::jpeg2000::bit_plane_coder::BitPlaneCoder::new(
+                        let mut bpc = BitPlaneCoder::with_buffers(
                             cb_width as u32,
                             cb_height as u32,
-                            &[],
+                            Vec::new(),
+                            Vec::new(),
+                            mq,
                         );
-                        if let Ok(coefficients) =
-                            bpc.decode_codeblock(&data, max_bit_plane, cb_info.num_passes, subband.orientation as u8)
-                        {
+                        let decoded = bpc
+                            .decode_passes(&data, max_bit_plane, cb_info.num_passes, orientation)
+                            .is_ok();
+                        let passes = bpc.num_passes_decoded as u8;
+                        let (coefficients, state, mq) = bpc.into_parts();
+                        if decoded {
                             block.coefficients = coefficients;
-                            block.state = bpc.state;
-                            block.coding_passes = bpc.num_passes_decoded as u8;
+                            block.state = state;
+                            block.coding_passes = passes;
                         }
+                        block.layer_data.push(data);
                         subband.codeblocks.push(block);
+                        scratch.mq = Some(mq);
                     }
                 }
             }
@@ -697,7 +1506,7 @@ impl<'a, 'b> J2kDecoder<'a, 'b> {
 mod tests {
     use super::*;
     use crate::jpeg_stream_reader::JpegStreamReader;
-    use crate::jpeg2000::image::{J2kCod, J2kComponentInfo, J2kImage};
+    use crate::jpeg2000::image::{J2kCod, J2kComponentInfo, J2kImage, J2kPoc};
     use crate::jpeg2000::parser::J2kParser;
 
     #[test]
@@ -744,6 +1553,7 @@ mod tests {
             decomposition_levels: 1,
             codeblock_width_exp: 4,
             codeblock_height_exp: 4,
+            codeblock_style: 0,
             transformation: 0,
             precinct_sizes: vec![],
         });
@@ -755,10 +1565,22 @@ mod tests {
         // Inject our constructed image into parser
         parser.image = Box::new(image);
 
-        let mut tile_states = Vec::new();
+        let mut state = DecodeState::default();
 
         // Call decode_tile_data
-        let _ = J2kDecoder::decode_tile_data(&mut parser, 0, 0, false, &mut tile_states);
+        let mut progress = Progress {
+            handler: None,
+            reported: 0,
+        };
+        let mut scratch = BlockScratch::default();
+        let _ = J2kDecoder::decode_tile_data(
+            &mut parser,
+            0,
+            0,
+            &mut state,
+            &mut progress,
+            &mut scratch,
+        );
 
         // Verify tile_states
         let tile = &parser.image.tiles[0];
@@ -808,4 +1630,498 @@ mod tests {
             "Comp 1 Res 1 height mismatch"
         );
     }
+
+    #[test]
+    fn test_progression_volumes() {
+        let mut image = J2kImage {
+            cod: Some(J2kCod {
+                progression_order: 2,
+                number_of_layers: 3,
+                decomposition_levels: 2,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        image.tiles.resize_with(2, Default::default);
+        let grids = vec![ComponentGrid::new((0, 0, 64, 64), (1, 1), 2, &[]); 3];
+
+        // COD order over the whole tile
+        let volumes = J2kDecoder::progression_volumes(&image, 0, &grids).unwrap();
+        assert_eq!(
+            volumes,
+            [ProgressionVolume::whole(ProgressionOrder::Rpcl, 3, &grids)]
+        );
+
+        // Main header POC, replaced by the tile-part POC of tile 1
+        let poc = |progression_order| J2kPoc {
+            resolution_start: 0,
+            component_start: 1,
+            layer_end: 2,
+            resolution_end: 3,
+            component_end: 256,
+            progression_order,
+        };
+        image.poc = vec![poc(4), poc(0)];
+        image.tiles[1].poc = vec![poc(1)];
+        let orders = |tile_idx| {
+            J2kDecoder::progression_volumes(&image, tile_idx, &grids)
+                .unwrap()
+                .iter()
+                .map(|v| v.order)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(orders(0), [ProgressionOrder::Cprl, ProgressionOrder::Lrcp]);
+        assert_eq!(orders(1), [ProgressionOrder::Rlcp]);
+
+        image.tiles[1].poc = vec![poc(7)];
+        assert!(J2kDecoder::progression_volumes(&image, 1, &grids).is_err());
+    }
+
+    #[test]
+    fn test_decode_selected_tiles() {
+        let mut encoder = crate::jpeg2000::encoder::J2kEncoder::new();
+        encoder.set_tile_size(16, 16);
+        let frame_info = crate::FrameInfo {
+            width: 40,
+            height: 24,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let mut data = vec![0u8; 4096];
+        let len = encoder
+            .encode(&[128; 40 * 24 * 3], &frame_info, &mut data)
+            .unwrap();
+        data.truncate(len);
+
+        let decode = |data: &[u8]| {
+            let mut reader = JpegStreamReader::new(data);
+            let mut decoder = J2kDecoder::new(&mut reader);
+            decoder.set_tiles(&[4]);
+            let image = decoder.decode().unwrap().clone();
+            let decoded: Vec<bool> = image
+                .tiles
+                .iter()
+                .map(|t| !t.components.is_empty())
+                .collect();
+            (image, decoded)
+        };
+        let (_, decoded) = decode(&data);
+        assert_eq!(decoded, [false, false, false, false, true]);
+
+        // TLM (1-byte tile indices, 4-byte lengths) in front of the first tile-part.
+        let sots: Vec<usize> = (0..data.len() - 1)
+            .filter(|&i| data[i..i + 2] == [0xFF, 0x90])
+            .collect();
+        assert_eq!(sots.len(), 6);
+        let mut tlm = vec![0xFF, 0x55, 0x00, 4 + 6 * 5, 0x00, 0x50];
+        for (tile, &sot) in sots.iter().enumerate() {
+            tlm.push(tile as u8);
+            tlm.extend_from_slice(&data[sot + 6..sot + 10]);
+        }
+        // The tile-parts before tile 4 are not read at all.
+        data[sots[1]..sots[4]].fill(0xFF);
+        data.splice(sots[0]..sots[0], tlm);
+
+        let (image, decoded) = decode(&data);
+        assert_eq!(image.tile_part_lengths.len(), 6);
+        assert_eq!(image.tile_part_lengths[4].tile_index, 4);
+        assert_eq!(decoded, [false, false, false, false, true]);
+    }
+
+    #[test]
+    fn test_reconstruct_tile() {
+        let mut encoder = crate::jpeg2000::encoder::J2kEncoder::new();
+        encoder.set_tile_size(16, 16);
+        let frame_info = crate::FrameInfo {
+            width: 40,
+            height: 24,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let pixels: Vec<u8> = (0..40 * 24 * 3).map(|i| (i * 7 % 251) as u8).collect();
+        let mut data = vec![0u8; 16384];
+        let len = encoder.encode(&pixels, &frame_info, &mut data).unwrap();
+        data.truncate(len);
+
+        let mut reader = JpegStreamReader::new(&data);
+        let full = J2kDecoder::new(&mut reader)
+            .decode()
+            .unwrap()
+            .reconstruct_pixels()
+            .unwrap();
+        for index in 0..6u16 {
+            let mut reader = JpegStreamReader::new(&data);
+            let mut decoder = J2kDecoder::new(&mut reader);
+            decoder.set_tiles(&[index]);
+            let image = decoder.decode().unwrap();
+            let tile = image
+                .reconstruct_tile(index as u32, J2kUpsampling::Nearest)
+                .unwrap();
+            let (x0, y0, x1, y1) = image.tile_bounds(index as u32);
+            assert_eq!((tile.width, tile.height), (x1 - x0, y1 - y0));
+            let expected: Vec<u8> = (y0..y1)
+                .flat_map(|y| {
+                    let start = (y as usize * 40 + x0 as usize) * 3;
+                    full[start..start + tile.width as usize * 3].iter().copied()
+                })
+                .collect();
+            assert_eq!(tile.samples.into_bytes(), expected, "tile {index}");
+        }
+    }
+
+    #[test]
+    fn test_decode_tile_parts() {
+        let mut encoder = crate::jpeg2000::encoder::J2kEncoder::new();
+        let frame_info = crate::FrameInfo {
+            width: 16,
+            height: 16,
+            bits_per_sample: 8,
+            component_count: 1,
+        };
+        let mut data = vec![0u8; 1024];
+        let len = encoder
+            .encode(&[128; 16 * 16], &frame_info, &mut data)
+            .unwrap();
+        data.truncate(len);
+
+        // One tile-part whose packets are all empty, one header byte each; they are
+        // split over tile-parts given as (Isot, TPsot, packets).
+        let sot = (0..data.len() - 1)
+            .find(|&i| data[i..i + 2] == [0xFF, 0x90])
+            .unwrap();
+        let packets = data[sot + 14..data.len() - 2].to_vec();
+        let split = |parts: &[(u16, u8, std::ops::Range<usize>)]| {
+            let mut stream = data[..sot].to_vec();
+            for (isot, tpsot, range) in parts {
+                let psot = 14 + range.len() as u32;
+                stream.extend_from_slice(&[0xFF, 0x90, 0x00, 0x0A]);
+                stream.extend_from_slice(&isot.to_be_bytes());
+                stream.extend_from_slice(&psot.to_be_bytes());
+                stream.extend_from_slice(&[*tpsot, 2, 0xFF, 0x93]);
+                stream.extend_from_slice(&packets[range.clone()]);
+            }
+            stream.extend_from_slice(&[0xFF, 0xD9]);
+            stream
+        };
+        let decode = |data: &[u8]| {
+            let mut reader = JpegStreamReader::new(data);
+            let mut decoder = J2kDecoder::new(&mut reader);
+            decoder.decode()?;
+            let tile = &decoder.parser.image.tiles[0];
+            assert_eq!(tile.tile_part_count, 2);
+            Ok::<_, JpeglsError>((
+                decoder.state.tile_states[0].packets_read,
+                tile.tile_parts_read,
+            ))
+        };
+
+        let n = packets.len();
+        assert_eq!(decode(&split(&[(0, 0, 0..2), (0, 1, 2..n)])), Ok((n, 2)));
+        // A repeated tile-part is skipped.
+        assert_eq!(
+            decode(&split(&[(0, 0, 0..2), (0, 0, 0..2), (0, 1, 2..n)])),
+            Ok((n, 2))
+        );
+        // A missing tile-part or a tile index outside the image is an error.
+        assert!(decode(&split(&[(0, 1, 2..n)])).is_err());
+        assert!(decode(&split(&[(0, 0, 0..2), (1, 0, 2..n)])).is_err());
+    }
+
+    #[test]
+    fn test_progress_handler() {
+        let mut encoder = crate::jpeg2000::encoder::J2kEncoder::new();
+        encoder.set_decomposition_levels(2);
+        encoder.set_progression_order(crate::jpeg2000::encoder::ProgressionOrder::Rpcl);
+        let frame_info = crate::FrameInfo {
+            width: 30,
+            height: 20,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let mut data = vec![0u8; 1024];
+        let len = encoder
+            .encode(&[128; 30 * 20 * 3], &frame_info, &mut data)
+            .unwrap();
+        data.truncate(len);
+
+        let mut previews = Vec::new();
+        {
+            let mut reader = JpegStreamReader::new(&data);
+            let mut decoder = J2kDecoder::new(&mut reader);
+            decoder.set_progress_handler(|preview| {
+                let samples = preview.samples.clone().into_bytes().len();
+                previews.push((preview.resolution, preview.width, preview.height, samples));
+                Ok(())
+            });
+            decoder.decode().unwrap();
+        }
+        assert_eq!(
+            previews,
+            [
+                (0, 8, 5, 8 * 5 * 3),
+                (1, 15, 10, 15 * 10 * 3),
+                (2, 30, 20, 30 * 20 * 3)
+            ]
+        );
+
+        // An error from the handler stops decoding.
+        let mut reader = JpegStreamReader::new(&data);
+        let mut decoder = J2kDecoder::new(&mut reader);
+        decoder.set_progress_handler(|_| Err(JpeglsError::InvalidOperation));
+        assert!(matches!(
+            decoder.decode(),
+            Err(JpeglsError::InvalidOperation)
+        ));
+    }
+
+    #[test]
+    fn test_decode_partial() {
+        let mut encoder = crate::jpeg2000::encoder::J2kEncoder::new();
+        encoder.set_decomposition_levels(2);
+        encoder.set_progression_order(crate::jpeg2000::encoder::ProgressionOrder::Rpcl);
+        let frame_info = crate::FrameInfo {
+            width: 30,
+            height: 20,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let mut data = vec![0u8; 1024];
+        let len = encoder
+            .encode(&[128; 30 * 20 * 3], &frame_info, &mut data)
+            .unwrap();
+        data.truncate(len);
+
+        let decode_partial = |data: &[u8], available: usize| {
+            let mut reader = JpegStreamReader::new(data);
+            let mut decoder = J2kDecoder::new(&mut reader);
+            decoder
+                .decode_partial(available)
+                .map(|partial| (partial.layers, partial.resolutions))
+        };
+
+        // Nine empty packets of one header byte each, three per resolution.
+        let sot = (0..data.len() - 1)
+            .find(|&i| data[i..i + 2] == [0xFF, 0x90])
+            .unwrap();
+        let sod = sot + 12;
+        assert_eq!(data.len(), sod + 2 + 9 + 2);
+        assert_eq!(decode_partial(&data, sod + 2), Ok((0, 0)));
+        assert_eq!(decode_partial(&data, sod + 2 + 2), Ok((0, 0)));
+        assert_eq!(decode_partial(&data, sod + 2 + 3), Ok((0, 1)));
+        assert_eq!(decode_partial(&data, sod + 2 + 8), Ok((0, 2)));
+        assert_eq!(decode_partial(&data, sod + 2 + 9), Ok((1, 3)));
+        assert_eq!(decode_partial(&data, data.len()), Ok((1, 3)));
+        assert_eq!(decode_partial(&data, usize::MAX), Ok((1, 3)));
+
+        // Without a tile-part the main header may be incomplete.
+        assert_eq!(decode_partial(&data, sot), Err(JpeglsError::NeedMoreData));
+
+        // A JP2 file cut short in its codestream box.
+        let mut jp2 = b"\x00\x00\x00\x0CjP  \r\n\x87\n".to_vec();
+        jp2.extend_from_slice(&(data.len() as u32 + 8).to_be_bytes());
+        jp2.extend_from_slice(b"jp2c");
+        jp2.extend_from_slice(&data);
+        assert_eq!(decode_partial(&jp2, 20 + sod + 2 + 3), Ok((0, 1)));
+
+        // Decoding stops at the first packet not read, counted from the start of the file.
+        let stop = |data: &[u8], available: usize| {
+            let mut reader = JpegStreamReader::new(data);
+            let mut decoder = J2kDecoder::new(&mut reader);
+            decoder
+                .decode_partial(available)
+                .map(|partial| (partial.offset, partial.complete_rows))
+        };
+        assert_eq!(stop(&data, sod + 2 + 2), Ok((sod + 2 + 2, 0)));
+        assert_eq!(stop(&jp2, 20 + sod + 2 + 3), Ok((20 + sod + 2 + 3, 0)));
+        assert_eq!(stop(&data, data.len()).map(|(_, rows)| rows), Ok(20));
+    }
+
+    #[test]
+    fn test_save_state_and_resume() {
+        let mut encoder = crate::jpeg2000::encoder::J2kEncoder::new();
+        encoder.set_tile_size(16, 16);
+        let frame_info = crate::FrameInfo {
+            width: 40,
+            height: 24,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let mut data = vec![0u8; 4096];
+        let len = encoder
+            .encode(&[128; 40 * 24 * 3], &frame_info, &mut data)
+            .unwrap();
+        data.truncate(len);
+
+        let mut reader = JpegStreamReader::new(&data);
+        let mut decoder = J2kDecoder::new(&mut reader);
+        let full = format!("{:?}", decoder.decode().unwrap());
+
+        // Cut short anywhere after the main header: in a tile-part header, between
+        // packets or between tile-parts.
+        let sot = (0..data.len() - 1)
+            .find(|&i| data[i..i + 2] == [0xFF, 0x90])
+            .unwrap();
+        for available in sot + 2..data.len() {
+            let mut reader = JpegStreamReader::new(&data);
+            let mut decoder = J2kDecoder::new(&mut reader);
+            decoder.decode_partial(available).unwrap();
+            let state = decoder.save_state();
+
+            // The decoder goes on with the packets not read yet...
+            let partial = decoder.decode_partial(data.len()).unwrap();
+            assert!(partial.is_complete());
+            assert_eq!(format!("{:?}", partial.image), full);
+
+            // ...and so does another one resumed from the snapshot.
+            let mut reader = JpegStreamReader::new(&data);
+            let mut resumed = J2kDecoder::new(&mut reader);
+            resumed.resume(state.clone()).unwrap();
+            assert_eq!(format!("{:?}", resumed.decode().unwrap()), full);
+
+            // The snapshot survives a round trip through bytes.
+            #[cfg(feature = "serde")]
+            {
+                let state = J2kDecoderState::from_bytes(&state.to_bytes()).unwrap();
+                let mut reader = JpegStreamReader::new(&data);
+                let mut resumed = J2kDecoder::new(&mut reader);
+                resumed.resume(state).unwrap();
+                assert_eq!(format!("{:?}", resumed.decode().unwrap()), full);
+            }
+        }
+    }
+
+    #[test]
+    fn test_resume_checks_main_header() {
+        let encode = |width: u32| {
+            let mut encoder = crate::jpeg2000::encoder::J2kEncoder::new();
+            let frame_info = crate::FrameInfo {
+                width,
+                height: 16,
+                bits_per_sample: 8,
+                component_count: 1,
+            };
+            let mut data = vec![0u8; 2048];
+            let len = encoder
+                .encode(&vec![128; width as usize * 16], &frame_info, &mut data)
+                .unwrap();
+            data.truncate(len);
+            data
+        };
+        let data = encode(16);
+        let mut reader = JpegStreamReader::new(&data);
+        let mut decoder = J2kDecoder::new(&mut reader);
+        decoder.decode_partial(data.len() - 4).unwrap();
+        let state = decoder.save_state();
+        let resume = |data: &[u8]| {
+            let mut reader = JpegStreamReader::new(data);
+            let mut decoder = J2kDecoder::new(&mut reader);
+            decoder.resume(state.clone())
+        };
+        assert_eq!(resume(&data), Ok(()));
+
+        // Another image...
+        assert_eq!(resume(&encode(24)), Err(JpeglsError::InvalidArgument));
+        // ...the same codestream behind JP2 boxes...
+        let mut jp2 = b"\x00\x00\x00\x0CjP  \r\n\x87\n".to_vec();
+        jp2.extend_from_slice(&(data.len() as u32 + 8).to_be_bytes());
+        jp2.extend_from_slice(b"jp2c");
+        jp2.extend_from_slice(&data);
+        assert_eq!(resume(&jp2), Err(JpeglsError::InvalidArgument));
+        // ...or no main header at all.
+        assert!(resume(&data[..2]).is_err());
+
+        // A snapshot taken before decoding holds nothing to check.
+        let mut reader = JpegStreamReader::new(&data);
+        let decoder = J2kDecoder::new(&mut reader);
+        let empty = decoder.save_state();
+        let mut reader = JpegStreamReader::new(&jp2);
+        let mut decoder = J2kDecoder::new(&mut reader);
+        assert_eq!(decoder.resume(empty), Ok(()));
+
+        #[cfg(feature = "serde")]
+        assert!(matches!(
+            J2kDecoderState::from_bytes(b"{}"),
+            Err(JpeglsError::InvalidData)
+        ));
+    }
+
+    #[test]
+    fn test_decode_packed_headers() {
+        let mut encoder = crate::jpeg2000::encoder::J2kEncoder::new();
+        let frame_info = crate::FrameInfo {
+            width: 16,
+            height: 16,
+            bits_per_sample: 8,
+            component_count: 1,
+        };
+        let mut data = vec![0u8; 1024];
+        let len = encoder
+            .encode(&[128; 16 * 16], &frame_info, &mut data)
+            .unwrap();
+        data.truncate(len);
+
+        // One tile-part whose packets are all empty, one header byte each.
+        let sot = (0..data.len() - 1)
+            .find(|&i| data[i..i + 2] == [0xFF, 0x90])
+            .unwrap();
+        let sod = sot + 12;
+        assert_eq!(data[sod..sod + 2], [0xFF, 0x93]);
+        let headers = data[sod + 2..data.len() - 2].to_vec();
+        let packets = headers.len();
+
+        let decode = |data: &[u8]| {
+            let mut reader = JpegStreamReader::new(data);
+            let mut decoder = J2kDecoder::new(&mut reader);
+            decoder.decode().unwrap();
+            let state = &decoder.state.tile_states[0];
+            (state.packets_read, state.packed_headers_read)
+        };
+
+        // PPT: the headers move into the tile-part header and the body is empty.
+        let mut ppt = data[..sod].to_vec();
+        ppt.extend_from_slice(&[0xFF, 0x61, 0x00, 3 + packets as u8, 0x00]);
+        ppt.extend_from_slice(&headers);
+        ppt.extend_from_slice(&[0xFF, 0x93, 0xFF, 0xD9]);
+        let psot = (12 + 5 + packets + 2) as u32;
+        ppt[sot + 6..sot + 10].copy_from_slice(&psot.to_be_bytes());
+        assert_eq!(decode(&ppt), (packets, packets));
+
+        // PPM: the headers move into the main header, after their length Nppm.
+        let mut ppm = data[..sot].to_vec();
+        ppm.extend_from_slice(&[0xFF, 0x60, 0x00, 7 + packets as u8, 0x00]);
+        ppm.extend_from_slice(&(packets as u32).to_be_bytes());
+        ppm.extend_from_slice(&headers);
+        let sot = ppm.len();
+        ppm.extend_from_slice(&data[sot - 9 - packets..sod + 2]);
+        ppm.extend_from_slice(&[0xFF, 0xD9]);
+        ppm[sot + 6..sot + 10].copy_from_slice(&14u32.to_be_bytes());
+        assert_eq!(decode(&ppm), (packets, packets));
+    }
+
+    #[test]
+    fn test_owned_decoder_on_another_thread() {
+        let frame_info = crate::FrameInfo {
+            width: 20,
+            height: 12,
+            bits_per_sample: 8,
+            component_count: 1,
+        };
+        let source: Vec<u8> = (0..20 * 12).map(|i| (i * 3 % 256) as u8).collect();
+        let data = crate::jpeg2000::encoder::J2kEncoder::new()
+            .encode_to_vec(&source, &frame_info)
+            .unwrap();
+
+        let expected = {
+            let mut reader = JpegStreamReader::new(&data);
+            let mut decoder = J2kDecoder::new(&mut reader);
+            decoder.decode().unwrap().reconstruct_pixels().unwrap()
+        };
+
+        let mut decoder = J2kDecoder::from_vec(data);
+        let pixels =
+            std::thread::spawn(move || decoder.decode().unwrap().reconstruct_pixels().unwrap())
+                .join()
+                .unwrap();
+        assert_eq!(pixels, expected);
+    }
 }
@@ -1,4 +1,10 @@
 //! Discrete Wavelet Transforms for JPEG 2000
+//!
+//! [`inverse_2d_batch`] runs the inverse 2D transform of many bands held in one buffer,
+//! laid out so the same batch can be handed to a GPU (`dwt_gpu` with
+//! the `gpu` feature).
+
+use crate::JpeglsError;
 
 #[allow(dead_code)]
 pub struct Dwt53;
@@ -6,10 +12,103 @@ pub struct Dwt53;
 impl Dwt53 {
     /// Forward 5/3 Reversible Transform (1D)
     /// Input: `signal` (spatial domain)
-    /// Output: `coeffs` (interleaved Low/High pass coeffs)
-    /// Note: This is an in-place implementation sketch or we can return new vec.
-    /// Standard usually separates into Low (first half) and High (second half) subbands.
+    /// Output: low-pass coefficients in `out_l`, high-pass coefficients in `out_h`
     pub fn forward(signal: &[i32], out_l: &mut [i32], out_h: &mut [i32]) {
+        let mut line = deinterleave(signal);
+        let (low, high) = line.split_at_mut(signal.len().div_ceil(2));
+        Self::forward_lines(low, high, 1);
+        copy_prefix(out_l, low);
+        copy_prefix(out_h, high);
+    }
+
+    /// Inverse 5/3 Reversible Transform (1D)
+    pub fn inverse(in_l: &[i32], in_h: &[i32], output: &mut [i32]) {
+        let mut line = vec![0i32; output.len()];
+        let (low, high) = line.split_at_mut(output.len().div_ceil(2));
+        copy_prefix(low, in_l);
+        copy_prefix(high, in_h);
+        Self::inverse_lines(low, high, 1);
+        interleave(low, high, output);
+    }
+
+    /// Inverse 2D 5/3 Transform
+    /// Reconstructs image from LL, HL, LH, HH subbands
+    ///
+    /// The 2D DWT structure:
+    /// ```text
+    /// +-------+-------+
+    /// |  LL   |  HL   |  <- low-pass rows (top half)
+    /// +-------+-------+
+    /// |  LH   |  HH   |  <- high-pass rows (bottom half)
+    /// +-------+-------+
+    ///    ^       ^
+    ///  low-col  high-col
+    /// ```
+    ///
+    /// Inverse order:
+    /// 1. Vertical inverse: combine LL+LH → left cols, HL+HH → right cols
+    /// 2. Horizontal inverse: combine left+right → output rows
+    ///
+    /// The vertical pass lifts whole rows at a time instead of gathering columns.
+    pub fn inverse_2d(
+        ll: &[i32],
+        hl: &[i32],
+        lh: &[i32],
+        hh: &[i32],
+        width: u32,
+        height: u32,
+        output: &mut [i32],
+    ) {
+        let w = width as usize;
+        let h = height as usize;
+        if w == 0 || h == 0 {
+            return;
+        }
+        let ll_w = w.div_ceil(2);
+        let ll_h = h.div_ceil(2);
+
+        // Low-pass rows hold [LL | HL], high-pass rows hold [LH | HH].
+        let mut temp = vec![0i32; w * h];
+        let (low, high) = temp.split_at_mut(ll_h * w);
+        gather_rows(low, ll, hl, ll_w, w);
+        gather_rows(high, lh, hh, ll_w, w);
+        Self::inverse_quadrants(&mut temp, w, h, output);
+    }
+
+    /// Inverse 2D transform of `temp`, which holds the subbands in the quadrant order of
+    /// [`inverse_2d_batch`], into the first `w * h` samples of `output`.
+    fn inverse_quadrants(temp: &mut [i32], w: usize, h: usize, output: &mut [i32]) {
+        let ll_w = w.div_ceil(2);
+        let ll_h = h.div_ceil(2);
+
+        // Step 1: Vertical inverse DWT on all columns at once
+        let (low, high) = temp.split_at_mut(ll_h * w);
+        Self::inverse_lines(low, high, w);
+
+        // Step 2: Horizontal inverse DWT on each row, taken in spatial order
+        for (y, out_row) in output[..w * h].chunks_exact_mut(w).enumerate() {
+            let start = if y % 2 == 0 { y / 2 } else { ll_h + y / 2 } * w;
+            let (row_l, row_h) = temp[start..start + w].split_at_mut(ll_w);
+            Self::inverse_lines(row_l, row_h, 1);
+            interleave(row_l, row_h, out_row);
+        }
+    }
+
+    fn forward_lines(low: &mut [i32], high: &mut [i32], width: usize) {
+        // y[2n+1] = x[2n+1] - floor((x[2n] + x[2n+2]) / 2)
+        predict_lines(low, high, width, |d, a, b| lift_i32(d, a, b, 0, 1, true));
+        // y[2n] = x[2n] + floor((y[2n-1] + y[2n+1] + 2) / 4)
+        update_lines(low, high, width, |d, a, b| lift_i32(d, a, b, 2, 2, false));
+    }
+
+    fn inverse_lines(low: &mut [i32], high: &mut [i32], width: usize) {
+        update_lines(low, high, width, |d, a, b| lift_i32(d, a, b, 2, 2, true));
+        predict_lines(low, high, width, |d, a, b| lift_i32(d, a, b, 0, 1, false));
+    }
+
+    /// Per-sample reference implementation of [`forward`](Self::forward), kept for
+    /// benchmarking and cross-checking.
+    pub fn forward_baseline(signal: &[i32], out_l: &mut [i32], out_h: &mut [i32]) {
         let len = signal.len();
         if len == 0 {
             return;
@@ -77,8 +176,8 @@ impl Dwt53 {
         }
     }
 
-    /// Inverse 5/3 Reversible Transform (1D)
-    pub fn inverse(in_l: &[i32], in_h: &[i32], output: &mut [i32]) {
+    /// Per-sample reference implementation of [`inverse`](Self::inverse).
+    pub fn inverse_baseline(in_l: &[i32], in_h: &[i32], output: &mut [i32]) {
         let len = output.len();
         // Re-interleave
         let mut x = vec![0i32; len];
@@ -131,24 +230,8 @@ impl Dwt53 {
         output.copy_from_slice(&x);
     }
 
-    /// Inverse 2D 5/3 Transform
-    /// Reconstructs image from LL, HL, LH, HH subbands
-    /// 
-    /// The 2D DWT structure:
-    /// ```
-    /// +-------+-------+
-    /// |  LL   |  HL   |  <- low-pass rows (top half)
-    /// +-------+-------+
-    /// |  LH   |  HH   |  <- high-pass rows (bottom half)
-    /// +-------+-------+
-    ///    ^       ^
-    ///  low-col  high-col
-    /// ```
-    /// 
-    /// Inverse order:
-    /// 1. Vertical inverse: combine LL+LH → left cols, HL+HH → right cols
-    /// 2. Horizontal inverse: combine left+right → output rows
-    pub fn inverse_2d(
+    /// Column-by-column reference implementation of [`inverse_2d`](Self::inverse_2d).
+    pub fn inverse_2d_baseline(
         ll: &[i32],
         hl: &[i32],
         lh: &[i32],
@@ -159,14 +242,14 @@ impl Dwt53 {
     ) {
         let w = width as usize;
         let h = height as usize;
-        
+
         // Subband dimensions
         #[allow(clippy::manual_div_ceil)]
-        let ll_w = (w + 1) / 2;  // LL and LH width (low-pass cols)
-        let hl_w = w / 2;        // HL and HH width (high-pass cols)
+        let ll_w = (w + 1) / 2; // LL and LH width (low-pass cols)
+        let hl_w = w / 2; // HL and HH width (high-pass cols)
         #[allow(clippy::manual_div_ceil)]
-        let ll_h = (h + 1) / 2;  // LL and HL height (low-pass rows)
-        let lh_h = h / 2;        // LH and HH height (high-pass rows)
+        let ll_h = (h + 1) / 2; // LL and HL height (low-pass rows)
+        let lh_h = h / 2; // LH and HH height (high-pass rows)
 
         // Intermediate buffer after vertical inverse
         let mut temp = vec![0i32; w * h];
@@ -194,7 +277,7 @@ impl Dwt53 {
             }
 
             let mut col_output = vec![0i32; h];
-            Self::inverse(&col_l, &col_h, &mut col_output);
+            Self::inverse_baseline(&col_l, &col_h, &mut col_output);
 
             // Store in left half of temp
             for y in 0..h {
@@ -224,7 +307,7 @@ impl Dwt53 {
             }
 
             let mut col_output = vec![0i32; h];
-            Self::inverse(&col_l, &col_h, &mut col_output);
+            Self::inverse_baseline(&col_l, &col_h, &mut col_output);
 
             // Store in right half of temp
             for y in 0..h {
@@ -248,7 +331,7 @@ impl Dwt53 {
             }
 
             let mut row_output = vec![0i32; w];
-            Self::inverse(&row_l, &row_h, &mut row_output);
+            Self::inverse_baseline(&row_l, &row_h, &mut row_output);
 
             // Store in output
             for x in 0..w {
@@ -271,6 +354,99 @@ impl Dwt97 {
     const INV_K: f32 = 1.0 / 1.2301741;
 
     pub fn forward(signal: &[f32], out_l: &mut [f32], out_h: &mut [f32]) {
+        let mut line = deinterleave(signal);
+        let (low, high) = line.split_at_mut(signal.len().div_ceil(2));
+        Self::forward_lines(low, high, 1);
+        copy_prefix(out_l, low);
+        copy_prefix(out_h, high);
+    }
+
+    pub fn inverse(in_l: &[f32], in_h: &[f32], output: &mut [f32]) {
+        let mut line = vec![0.0f32; output.len()];
+        let (low, high) = line.split_at_mut(output.len().div_ceil(2));
+        copy_prefix(low, in_l);
+        copy_prefix(high, in_h);
+        Self::inverse_lines(low, high, 1);
+        interleave(low, high, output);
+    }
+
+    /// Inverse 2D 9/7 Transform
+    /// Reconstructs image from LL, HL, LH, HH subbands
+    pub fn inverse_2d(
+        ll: &[f32],
+        hl: &[f32],
+        lh: &[f32],
+        hh: &[f32],
+        width: u32,
+        height: u32,
+        output: &mut [f32],
+    ) {
+        let w = width as usize;
+        let h = height as usize;
+        if w == 0 || h == 0 {
+            return;
+        }
+        let ll_w = w.div_ceil(2);
+        let ll_h = h.div_ceil(2);
+
+        let mut temp = vec![0.0f32; w * h];
+        let (low, high) = temp.split_at_mut(ll_h * w);
+        gather_rows(low, ll, hl, ll_w, w);
+        gather_rows(high, lh, hh, ll_w, w);
+        Self::inverse_quadrants(&mut temp, w, h, output);
+    }
+
+    /// Inverse 2D transform of `temp`, which holds the subbands in the quadrant order of
+    /// [`inverse_2d_batch`], into the first `w * h` samples of `output`.
+    fn inverse_quadrants(temp: &mut [f32], w: usize, h: usize, output: &mut [f32]) {
+        let ll_w = w.div_ceil(2);
+        let ll_h = h.div_ceil(2);
+
+        // 1. Row Inverse Transform
+        let mut row_out = vec![0.0f32; w];
+        for row in temp.chunks_exact_mut(w) {
+            let (row_l, row_h) = row.split_at_mut(ll_w);
+            Self::inverse_lines(row_l, row_h, 1);
+            interleave(row_l, row_h, &mut row_out);
+            row.copy_from_slice(&row_out);
+        }
+
+        // 2. Column Inverse Transform, lifting whole rows at a time
+        let (low, high) = temp.split_at_mut(ll_h * w);
+        Self::inverse_lines(low, high, w);
+        for (y, out_row) in output[..w * h].chunks_exact_mut(w).enumerate() {
+            let start = if y % 2 == 0 { y / 2 } else { ll_h + y / 2 } * w;
+            out_row.copy_from_slice(&temp[start..start + w]);
+        }
+    }
+
+    fn forward_lines(low: &mut [f32], high: &mut [f32], width: usize) {
+        // A single sample passes through unchanged.
+        if high.is_empty() {
+            return;
+        }
+        predict_lines(low, high, width, |d, a, b| lift_f32(d, a, b, Self::ALPHA));
+        update_lines(low, high, width, |d, a, b| lift_f32(d, a, b, Self::BETA));
+        predict_lines(low, high, width, |d, a, b| lift_f32(d, a, b, Self::GAMMA));
+        update_lines(low, high, width, |d, a, b| lift_f32(d, a, b, Self::DELTA));
+        low.iter_mut().for_each(|v| *v *= Self::INV_K);
+        high.iter_mut().for_each(|v| *v *= Self::K);
+    }
+
+    fn inverse_lines(low: &mut [f32], high: &mut [f32], width: usize) {
+        if high.is_empty() {
+            return;
+        }
+        low.iter_mut().for_each(|v| *v *= Self::K);
+        high.iter_mut().for_each(|v| *v *= Self::INV_K);
+        update_lines(low, high, width, |d, a, b| lift_f32(d, a, b, -Self::DELTA));
+        predict_lines(low, high, width, |d, a, b| lift_f32(d, a, b, -Self::GAMMA));
+        update_lines(low, high, width, |d, a, b| lift_f32(d, a, b, -Self::BETA));
+        predict_lines(low, high, width, |d, a, b| lift_f32(d, a, b, -Self::ALPHA));
+    }
+
+    /// Per-sample reference implementation of [`forward`](Self::forward).
+    pub fn forward_baseline(signal: &[f32], out_l: &mut [f32], out_h: &mut [f32]) {
         let len = signal.len();
         if len == 0 {
             return;
@@ -360,7 +536,8 @@ impl Dwt97 {
         }
     }
 
-    pub fn inverse(in_l: &[f32], in_h: &[f32], output: &mut [f32]) {
+    /// Per-sample reference implementation of [`inverse`](Self::inverse).
+    pub fn inverse_baseline(in_l: &[f32], in_h: &[f32], output: &mut [f32]) {
         let len = output.len();
         let mut x = vec![0.0f32; len];
         let mut l_idx = 0;
@@ -427,9 +604,8 @@ impl Dwt97 {
         output.copy_from_slice(&x);
     }
 
-    /// Inverse 2D 9/7 Transform
-    /// Reconstructs image from LL, HL, LH, HH subbands
-    pub fn inverse_2d(
+    /// Column-by-column reference implementation of [`inverse_2d`](Self::inverse_2d).
+    pub fn inverse_2d_baseline(
         ll: &[f32],
         hl: &[f32],
         lh: &[f32],
@@ -468,7 +644,7 @@ impl Dwt97 {
             row_h[..row_hl.len()].copy_from_slice(row_hl);
 
             let mut row_out = vec![0.0f32; w];
-            Self::inverse(&row_l, &row_h, &mut row_out);
+            Self::inverse_baseline(&row_l, &row_h, &mut row_out);
 
             // Store in top half of temp
             for x in 0..w {
@@ -501,7 +677,7 @@ impl Dwt97 {
             row_h[..row_hh.len()].copy_from_slice(row_hh);
 
             let mut row_out = vec![0.0f32; w];
-            Self::inverse(&row_l, &row_h, &mut row_out);
+            Self::inverse_baseline(&row_l, &row_h, &mut row_out);
 
             // Store in bottom half of temp
             // Offset y by ll_h
@@ -525,7 +701,7 @@ impl Dwt97 {
             }
 
             let mut col_out = vec![0.0f32; h];
-            Self::inverse(&col_l, &col_h, &mut col_out);
+            Self::inverse_baseline(&col_l, &col_h, &mut col_out);
 
             for y in 0..h {
                 output[y * w + x] = col_out[y];
@@ -533,6 +709,436 @@ impl Dwt97 {
         }
     }
 }
+
+/// Wavelet filter of a batched inverse transform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaveletFilter {
+    /// The reversible 5-3 filter; the coefficients are integers.
+    Reversible53,
+    /// The irreversible 9-7 filter.
+    Irreversible97,
+}
+
+/// One band of a batch for [`inverse_2d_batch`]: `width` x `height` coefficients
+/// starting at `offset` in the batch buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DwtBand {
+    pub offset: usize,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl DwtBand {
+    /// Number of samples of the band.
+    pub fn sample_count(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+}
+
+/// Inverse 2D transform of every band of a batch, in place.
+///
+/// Each band holds its four subbands in quadrant order, row by row: the first
+/// `ceil(height / 2)` rows are a row of LL (`ceil(width / 2)` values) followed by a row
+/// of HL, the other rows a row of LH followed by a row of HH. The band is replaced by
+/// its `width` x `height` reconstructed samples. With the bands of many tiles,
+/// components or resolutions in one buffer the whole batch is a single call, and a
+/// single upload to the GPU. The 5-3 coefficients are integers held as `f32`, exact up
+/// to 2^24.
+///
+/// Returns `InvalidArgumentSize` when a band runs past the end of `data` or overlaps
+/// another.
+pub fn inverse_2d_batch(
+    filter: WaveletFilter,
+    data: &mut [f32],
+    bands: &[DwtBand],
+) -> Result<(), JpeglsError> {
+    check_batch(data.len(), bands)?;
+    let mut temp = Vec::new();
+    let (mut temp_i32, mut output_i32) = (Vec::new(), Vec::new());
+    for band in bands {
+        let (w, h) = (band.width as usize, band.height as usize);
+        let samples = &mut data[band.offset..band.offset + w * h];
+        if samples.is_empty() {
+            continue;
+        }
+        match filter {
+            WaveletFilter::Reversible53 => {
+                temp_i32.clear();
+                temp_i32.extend(samples.iter().map(|&v| v as i32));
+                output_i32.resize(samples.len(), 0);
+                Dwt53::inverse_quadrants(&mut temp_i32, w, h, &mut output_i32);
+                for (sample, &value) in samples.iter_mut().zip(&output_i32) {
+                    *sample = value as f32;
+                }
+            }
+            WaveletFilter::Irreversible97 => {
+                temp.clear();
+                temp.extend_from_slice(samples);
+                Dwt97::inverse_quadrants(&mut temp, w, h, samples);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the bands lie within a buffer of `len` samples and do not overlap.
+pub(crate) fn check_batch(len: usize, bands: &[DwtBand]) -> Result<(), JpeglsError> {
+    let mut ranges = Vec::with_capacity(bands.len());
+    for band in bands {
+        let end = band
+            .offset
+            .checked_add(band.sample_count())
+            .filter(|&end| end <= len)
+            .ok_or(JpeglsError::InvalidArgumentSize)?;
+        if end > band.offset {
+            ranges.push((band.offset, end));
+        }
+    }
+    ranges.sort_unstable();
+    if ranges.windows(2).any(|pair| pair[0].1 > pair[1].0) {
+        return Err(JpeglsError::InvalidArgumentSize);
+    }
+    Ok(())
+}
+
+// The lifting steps below work on a signal split into its even (`low`) and odd (`high`)
+// samples, where every sample is a line of `width` values: `width == 1` is a 1D row
+// transform and `width == row length` lifts all columns of an image at once. Neighbouring
+// lines are contiguous, so each step is a single element-wise kernel call over the
+// interior plus one call per boundary line (whole-sample symmetric extension).
+
+/// Updates every odd line from its even neighbours: `high[k] op= f(low[k], low[k + 1])`.
+fn predict_lines<T>(
+    low: &[T],
+    high: &mut [T],
+    width: usize,
+    kernel: impl Fn(&mut [T], &[T], &[T]),
+) {
+    let low_count = low.len() / width;
+    let high_count = high.len() / width;
+    if high_count == 0 {
+        return;
+    }
+    let interior = high_count.min(low_count - 1);
+    kernel(
+        &mut high[..interior * width],
+        &low[..interior * width],
+        &low[width..(interior + 1) * width],
+    );
+    if high_count > interior {
+        // Even length: the last odd line mirrors its left neighbour.
+        let edge = &low[interior * width..(interior + 1) * width];
+        kernel(&mut high[interior * width..], edge, edge);
+    }
+}
+
+/// Updates every even line from its odd neighbours: `low[k] op= f(high[k - 1], high[k])`.
+fn update_lines<T>(low: &mut [T], high: &[T], width: usize, kernel: impl Fn(&mut [T], &[T], &[T])) {
+    let low_count = low.len() / width;
+    let high_count = high.len() / width;
+    if high_count == 0 {
+        return;
+    }
+    let first = &high[..width];
+    kernel(&mut low[..width], first, first);
+    let interior = low_count.min(high_count);
+    kernel(
+        &mut low[width..interior * width],
+        &high[..(interior - 1) * width],
+        &high[width..interior * width],
+    );
+    if low_count > high_count {
+        // Odd length: the last even line mirrors its left neighbour.
+        let edge = &high[(high_count - 1) * width..];
+        kernel(&mut low[high_count * width..], edge, edge);
+    }
+}
+
+fn deinterleave<T: Copy>(signal: &[T]) -> Vec<T> {
+    signal
+        .iter()
+        .step_by(2)
+        .chain(signal.iter().skip(1).step_by(2))
+        .copied()
+        .collect()
+}
+
+fn interleave<T: Copy>(low: &[T], high: &[T], output: &mut [T]) {
+    for (out, &value) in output.iter_mut().step_by(2).zip(low) {
+        *out = value;
+    }
+    for (out, &value) in output.iter_mut().skip(1).step_by(2).zip(high) {
+        *out = value;
+    }
+}
+
+fn copy_prefix<T: Copy>(destination: &mut [T], source: &[T]) {
+    let count = destination.len().min(source.len());
+    destination[..count].copy_from_slice(&source[..count]);
+}
+
+/// Fills each `width`-long row of `rows` with a row of `left` followed by a row of
+/// `right`. Values missing from short subbands are left as zero.
+fn gather_rows<T: Copy>(rows: &mut [T], left: &[T], right: &[T], left_width: usize, width: usize) {
+    let right_width = width - left_width;
+    for (y, row) in rows.chunks_exact_mut(width).enumerate() {
+        let (row_l, row_r) = row.split_at_mut(left_width);
+        copy_prefix(row_l, left.get(y * left_width..).unwrap_or(&[]));
+        copy_prefix(row_r, right.get(y * right_width..).unwrap_or(&[]));
+    }
+}
+
+/// 5/3 lifting kernel: `dst[i] ±= (a[i] + b[i] + offset) >> shift`.
+fn lift_i32(dst: &mut [i32], a: &[i32], b: &[i32], offset: i32, shift: i32, subtract: bool) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if std::is_x86_feature_detected!("avx2") {
+            // SAFETY: AVX2 support was verified at runtime.
+            unsafe { x86::lift_i32_avx2(dst, a, b, offset, shift, subtract) };
+        } else {
+            // SAFETY: SSE2 is part of the x86-64 baseline.
+            unsafe { x86::lift_i32_sse2(dst, a, b, offset, shift, subtract) };
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        // SAFETY: NEON is part of the AArch64 baseline.
+        unsafe { aarch64::lift_i32_neon(dst, a, b, offset, shift, subtract) };
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    lift_i32_scalar(dst, a, b, offset, shift, subtract);
+}
+
+/// 9/7 lifting kernel: `dst[i] += coefficient * (a[i] + b[i])`.
+fn lift_f32(dst: &mut [f32], a: &[f32], b: &[f32], coefficient: f32) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if std::is_x86_feature_detected!("avx") {
+            // SAFETY: AVX support was verified at runtime.
+            unsafe { x86::lift_f32_avx(dst, a, b, coefficient) };
+        } else {
+            // SAFETY: SSE2 is part of the x86-64 baseline.
+            unsafe { x86::lift_f32_sse2(dst, a, b, coefficient) };
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        // SAFETY: NEON is part of the AArch64 baseline.
+        unsafe { aarch64::lift_f32_neon(dst, a, b, coefficient) };
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    lift_f32_scalar(dst, a, b, coefficient);
+}
+
+fn lift_i32_scalar(dst: &mut [i32], a: &[i32], b: &[i32], offset: i32, shift: i32, subtract: bool) {
+    for ((d, &a), &b) in dst.iter_mut().zip(a).zip(b) {
+        let delta = (a + b + offset) >> shift;
+        if subtract {
+            *d -= delta;
+        } else {
+            *d += delta;
+        }
+    }
+}
+
+fn lift_f32_scalar(dst: &mut [f32], a: &[f32], b: &[f32], coefficient: f32) {
+    for ((d, &a), &b) in dst.iter_mut().zip(a).zip(b) {
+        *d += coefficient * (a + b);
+    }
+}
+
+// The vector kernels process as many full registers as fit and leave the remainder to the
+// scalar kernel. The multiply and add are kept separate so results match the scalar path
+// bit for bit.
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "sse2")]
+    pub(super) unsafe fn lift_i32_sse2(
+        dst: &mut [i32],
+        a: &[i32],
+        b: &[i32],
+        offset: i32,
+        shift: i32,
+        subtract: bool,
+    ) {
+        let len = dst.len().min(a.len()).min(b.len());
+        let vector_len = len - len % 4;
+        let offset_v = _mm_set1_epi32(offset);
+        let count = _mm_cvtsi32_si128(shift);
+        for i in (0..vector_len).step_by(4) {
+            let a_v = _mm_loadu_si128(a.as_ptr().add(i) as *const __m128i);
+            let b_v = _mm_loadu_si128(b.as_ptr().add(i) as *const __m128i);
+            let delta = _mm_sra_epi32(_mm_add_epi32(_mm_add_epi32(a_v, b_v), offset_v), count);
+            let d = dst.as_mut_ptr().add(i) as *mut __m128i;
+            let d_v = _mm_loadu_si128(d);
+            let result = if subtract {
+                _mm_sub_epi32(d_v, delta)
+            } else {
+                _mm_add_epi32(d_v, delta)
+            };
+            _mm_storeu_si128(d, result);
+        }
+        super::lift_i32_scalar(
+            &mut dst[vector_len..len],
+            &a[vector_len..len],
+            &b[vector_len..len],
+            offset,
+            shift,
+            subtract,
+        );
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn lift_i32_avx2(
+        dst: &mut [i32],
+        a: &[i32],
+        b: &[i32],
+        offset: i32,
+        shift: i32,
+        subtract: bool,
+    ) {
+        let len = dst.len().min(a.len()).min(b.len());
+        let vector_len = len - len % 8;
+        let offset_v = _mm256_set1_epi32(offset);
+        let count = _mm_cvtsi32_si128(shift);
+        for i in (0..vector_len).step_by(8) {
+            let a_v = _mm256_loadu_si256(a.as_ptr().add(i) as *const __m256i);
+            let b_v = _mm256_loadu_si256(b.as_ptr().add(i) as *const __m256i);
+            let delta = _mm256_sra_epi32(
+                _mm256_add_epi32(_mm256_add_epi32(a_v, b_v), offset_v),
+                count,
+            );
+            let d = dst.as_mut_ptr().add(i) as *mut __m256i;
+            let d_v = _mm256_loadu_si256(d);
+            let result = if subtract {
+                _mm256_sub_epi32(d_v, delta)
+            } else {
+                _mm256_add_epi32(d_v, delta)
+            };
+            _mm256_storeu_si256(d, result);
+        }
+        super::lift_i32_scalar(
+            &mut dst[vector_len..len],
+            &a[vector_len..len],
+            &b[vector_len..len],
+            offset,
+            shift,
+            subtract,
+        );
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub(super) unsafe fn lift_f32_sse2(dst: &mut [f32], a: &[f32], b: &[f32], coefficient: f32) {
+        let len = dst.len().min(a.len()).min(b.len());
+        let vector_len = len - len % 4;
+        let c = _mm_set1_ps(coefficient);
+        for i in (0..vector_len).step_by(4) {
+            let sum = _mm_add_ps(
+                _mm_loadu_ps(a.as_ptr().add(i)),
+                _mm_loadu_ps(b.as_ptr().add(i)),
+            );
+            let d = dst.as_mut_ptr().add(i);
+            _mm_storeu_ps(d, _mm_add_ps(_mm_loadu_ps(d), _mm_mul_ps(c, sum)));
+        }
+        super::lift_f32_scalar(
+            &mut dst[vector_len..len],
+            &a[vector_len..len],
+            &b[vector_len..len],
+            coefficient,
+        );
+    }
+
+    #[target_feature(enable = "avx")]
+    pub(super) unsafe fn lift_f32_avx(dst: &mut [f32], a: &[f32], b: &[f32], coefficient: f32) {
+        let len = dst.len().min(a.len()).min(b.len());
+        let vector_len = len - len % 8;
+        let c = _mm256_set1_ps(coefficient);
+        for i in (0..vector_len).step_by(8) {
+            let sum = _mm256_add_ps(
+                _mm256_loadu_ps(a.as_ptr().add(i)),
+                _mm256_loadu_ps(b.as_ptr().add(i)),
+            );
+            let d = dst.as_mut_ptr().add(i);
+            _mm256_storeu_ps(d, _mm256_add_ps(_mm256_loadu_ps(d), _mm256_mul_ps(c, sum)));
+        }
+        super::lift_f32_scalar(
+            &mut dst[vector_len..len],
+            &a[vector_len..len],
+            &b[vector_len..len],
+            coefficient,
+        );
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod aarch64 {
+    use std::arch::aarch64::*;
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn lift_i32_neon(
+        dst: &mut [i32],
+        a: &[i32],
+        b: &[i32],
+        offset: i32,
+        shift: i32,
+        subtract: bool,
+    ) {
+        let len = dst.len().min(a.len()).min(b.len());
+        let vector_len = len - len % 4;
+        let offset_v = vdupq_n_s32(offset);
+        // A negative left shift is an arithmetic right shift.
+        let count = vdupq_n_s32(-shift);
+        for i in (0..vector_len).step_by(4) {
+            let sum = vaddq_s32(
+                vaddq_s32(vld1q_s32(a.as_ptr().add(i)), vld1q_s32(b.as_ptr().add(i))),
+                offset_v,
+            );
+            let delta = vshlq_s32(sum, count);
+            let d = dst.as_mut_ptr().add(i);
+            let result = if subtract {
+                vsubq_s32(vld1q_s32(d), delta)
+            } else {
+                vaddq_s32(vld1q_s32(d), delta)
+            };
+            vst1q_s32(d, result);
+        }
+        super::lift_i32_scalar(
+            &mut dst[vector_len..len],
+            &a[vector_len..len],
+            &b[vector_len..len],
+            offset,
+            shift,
+            subtract,
+        );
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn lift_f32_neon(dst: &mut [f32], a: &[f32], b: &[f32], coefficient: f32) {
+        let len = dst.len().min(a.len()).min(b.len());
+        let vector_len = len - len % 4;
+        let c = vdupq_n_f32(coefficient);
+        for i in (0..vector_len).step_by(4) {
+            let sum = vaddq_f32(vld1q_f32(a.as_ptr().add(i)), vld1q_f32(b.as_ptr().add(i)));
+            let d = dst.as_mut_ptr().add(i);
+            vst1q_f32(d, vaddq_f32(vld1q_f32(d), vmulq_f32(c, sum)));
+        }
+        super::lift_f32_scalar(
+            &mut dst[vector_len..len],
+            &a[vector_len..len],
+            &b[vector_len..len],
+            coefficient,
+        );
+    }
+}
+
+#[cfg(test)]
 mod tests {
     #[allow(unused_imports)]
     use super::*;
@@ -599,4 +1205,204 @@ mod tests {
             );
         }
     }
+
+    fn noise(len: usize, seed: u32) -> Vec<i32> {
+        let mut state = seed.wrapping_mul(2_654_435_761).wrapping_add(1);
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                ((state >> 16) % 4096) as i32 - 2048
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_lifting_matches_baseline_1d() {
+        for len in 2..70 {
+            let signal = noise(len, len as u32);
+            let (l_len, h_len) = (len.div_ceil(2), len / 2);
+
+            let (mut l, mut h) = (vec![0i32; l_len], vec![0i32; h_len]);
+            let (mut l_ref, mut h_ref) = (vec![0i32; l_len], vec![0i32; h_len]);
+            Dwt53::forward(&signal, &mut l, &mut h);
+            Dwt53::forward_baseline(&signal, &mut l_ref, &mut h_ref);
+            assert_eq!((&l, &h), (&l_ref, &h_ref), "5/3 forward, len {}", len);
+
+            let mut output = vec![0i32; len];
+            let mut output_ref = vec![0i32; len];
+            Dwt53::inverse(&l, &h, &mut output);
+            Dwt53::inverse_baseline(&l, &h, &mut output_ref);
+            assert_eq!(output, output_ref, "5/3 inverse, len {}", len);
+            assert_eq!(output, signal);
+
+            let signal: Vec<f32> = signal.iter().map(|&v| v as f32).collect();
+            let (mut l, mut h) = (vec![0.0f32; l_len], vec![0.0f32; h_len]);
+            let (mut l_ref, mut h_ref) = (vec![0.0f32; l_len], vec![0.0f32; h_len]);
+            Dwt97::forward(&signal, &mut l, &mut h);
+            Dwt97::forward_baseline(&signal, &mut l_ref, &mut h_ref);
+            assert_eq!((&l, &h), (&l_ref, &h_ref), "9/7 forward, len {}", len);
+
+            let mut output = vec![0.0f32; len];
+            let mut output_ref = vec![0.0f32; len];
+            Dwt97::inverse(&l, &h, &mut output);
+            Dwt97::inverse_baseline(&l, &h, &mut output_ref);
+            assert_eq!(output, output_ref, "9/7 inverse, len {}", len);
+        }
+    }
+
+    #[test]
+    fn test_lifting_matches_baseline_2d() {
+        for w in 2..19usize {
+            for h in 2..13usize {
+                let (lw, hw, lh, hh) = (w.div_ceil(2), w / 2, h.div_ceil(2), h / 2);
+                let seed = (w * 100 + h) as u32;
+                let ll = noise(lw * lh, seed);
+                let hl = noise(hw * lh, seed + 1);
+                let lh_band = noise(lw * hh, seed + 2);
+                let hh_band = noise(hw * hh, seed + 3);
+
+                let mut out = vec![0i32; w * h];
+                let mut out_ref = vec![0i32; w * h];
+                Dwt53::inverse_2d(&ll, &hl, &lh_band, &hh_band, w as u32, h as u32, &mut out);
+                Dwt53::inverse_2d_baseline(
+                    &ll,
+                    &hl,
+                    &lh_band,
+                    &hh_band,
+                    w as u32,
+                    h as u32,
+                    &mut out_ref,
+                );
+                assert_eq!(out, out_ref, "5/3 {}x{}", w, h);
+
+                let to_f32 = |v: &[i32]| v.iter().map(|&x| x as f32).collect::<Vec<_>>();
+                let (ll, hl) = (to_f32(&ll), to_f32(&hl));
+                let (lh_band, hh_band) = (to_f32(&lh_band), to_f32(&hh_band));
+                let mut out = vec![0.0f32; w * h];
+                let mut out_ref = vec![0.0f32; w * h];
+                Dwt97::inverse_2d(&ll, &hl, &lh_band, &hh_band, w as u32, h as u32, &mut out);
+                Dwt97::inverse_2d_baseline(
+                    &ll,
+                    &hl,
+                    &lh_band,
+                    &hh_band,
+                    w as u32,
+                    h as u32,
+                    &mut out_ref,
+                );
+                assert_eq!(out, out_ref, "9/7 {}x{}", w, h);
+            }
+        }
+    }
+
+    #[test]
+    fn test_single_sample_passes_through() {
+        let mut output = [0i32; 1];
+        Dwt53::inverse(&[42], &[], &mut output);
+        assert_eq!(output, [42]);
+
+        let mut output = vec![0.0f32; 5];
+        Dwt97::inverse_2d(&[1.0, 2.0, 3.0], &[4.0, 5.0], &[], &[], 5, 1, &mut output);
+        let mut expected = vec![0.0f32; 5];
+        Dwt97::inverse(&[1.0, 2.0, 3.0], &[4.0, 5.0], &mut expected);
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_batch_matches_inverse_2d() {
+        let sizes = [(17, 9), (1, 5), (8, 8), (6, 1), (0, 4), (13, 20)];
+        let mut bands = Vec::new();
+        let mut offset = 0;
+        for (width, height) in sizes {
+            bands.push(DwtBand {
+                offset,
+                width,
+                height,
+            });
+            // A gap between bands is left alone.
+            offset += (width * height) as usize + 3;
+        }
+        let coefficients: Vec<f32> = noise(offset, 7).iter().map(|&v| v as f32).collect();
+
+        for filter in [WaveletFilter::Reversible53, WaveletFilter::Irreversible97] {
+            let mut data = coefficients.clone();
+            inverse_2d_batch(filter, &mut data, &bands).unwrap();
+            for band in &bands {
+                let (w, h) = (band.width as usize, band.height as usize);
+                let (lw, lh) = (w.div_ceil(2), h.div_ceil(2));
+                let band_data = &coefficients[band.offset..band.offset + w * h];
+                // Split the quadrants into the four subbands of inverse_2d.
+                let quadrant = |rows: std::ops::Range<usize>, columns: std::ops::Range<usize>| {
+                    rows.flat_map(|y| columns.clone().map(move |x| y * w + x))
+                        .map(|i| band_data[i])
+                        .collect::<Vec<f32>>()
+                };
+                let ll = quadrant(0..lh, 0..lw);
+                let hl = quadrant(0..lh, lw..w);
+                let lh_band = quadrant(lh..h, 0..lw);
+                let hh_band = quadrant(lh..h, lw..w);
+
+                let expected: Vec<f32> = match filter {
+                    WaveletFilter::Reversible53 => {
+                        let to_i32 = |v: &[f32]| v.iter().map(|&x| x as i32).collect::<Vec<_>>();
+                        let mut out = vec![0i32; w * h];
+                        Dwt53::inverse_2d(
+                            &to_i32(&ll),
+                            &to_i32(&hl),
+                            &to_i32(&lh_band),
+                            &to_i32(&hh_band),
+                            w as u32,
+                            h as u32,
+                            &mut out,
+                        );
+                        out.iter().map(|&v| v as f32).collect()
+                    }
+                    WaveletFilter::Irreversible97 => {
+                        let mut out = vec![0.0f32; w * h];
+                        Dwt97::inverse_2d(
+                            &ll, &hl, &lh_band, &hh_band, w as u32, h as u32, &mut out,
+                        );
+                        out
+                    }
+                };
+                assert_eq!(
+                    &data[band.offset..band.offset + w * h],
+                    &expected[..],
+                    "{:?} {}x{}",
+                    filter,
+                    w,
+                    h
+                );
+                let gap = band.offset + w * h..band.offset + w * h + 3;
+                assert_eq!(&data[gap.clone()], &coefficients[gap]);
+            }
+        }
+
+        let overlapping = [
+            DwtBand {
+                offset: 0,
+                width: 4,
+                height: 4,
+            },
+            DwtBand {
+                offset: 15,
+                width: 2,
+                height: 2,
+            },
+        ];
+        let mut data = vec![0.0f32; 32];
+        assert_eq!(
+            inverse_2d_batch(WaveletFilter::Reversible53, &mut data, &overlapping),
+            Err(JpeglsError::InvalidArgumentSize)
+        );
+        let past_end = [DwtBand {
+            offset: 30,
+            width: 2,
+            height: 2,
+        }];
+        assert_eq!(
+            inverse_2d_batch(WaveletFilter::Irreversible97, &mut data, &past_end),
+            Err(JpeglsError::InvalidArgumentSize)
+        );
+    }
 }
@@ -0,0 +1,258 @@
+//! Batched inverse DWT on the GPU through `wgpu` compute shaders (feature `gpu`).
+//!
+//! [`GpuDwt::inverse_2d_batch`] takes the same buffer and bands as
+//! [`inverse_2d_batch`](super::dwt::inverse_2d_batch), so a renderer can fill one batch
+//! with the bands of many tiles and run it on whichever side is free. The whole batch is
+//! uploaded once, each of the two passes is one dispatch with a workgroup row per band,
+//! and the result is read back once:
+//!
+//! ```no_run
+//! use jpegexp_rs::jpeg2000::dwt::{DwtBand, WaveletFilter};
+//! use jpegexp_rs::jpeg2000::dwt_gpu::GpuDwt;
+//!
+//! let gpu = GpuDwt::new().unwrap();
+//! let mut data = vec![0.0f32; 2 * 256 * 256];
+//! let bands = [
+//!     DwtBand { offset: 0, width: 256, height: 256 },
+//!     DwtBand { offset: 256 * 256, width: 256, height: 256 },
+//! ];
+//! gpu.inverse_2d_batch(WaveletFilter::Irreversible97, &mut data, &bands).unwrap();
+//! ```
+//!
+//! The 9-7 results may differ from the CPU's in the last bits where the GPU fuses a
+//! multiply and an add; the 5-3 results are exact.
+
+use std::sync::mpsc;
+
+use wgpu::util::DeviceExt;
+
+use super::dwt::{check_batch, DwtBand, WaveletFilter};
+use crate::JpeglsError;
+
+/// Invocations per workgroup, one line each; must match the shader.
+const WORKGROUP_SIZE: u32 = 64;
+
+/// A GPU device with the inverse DWT pipeline. Creating one is slow, so a renderer keeps
+/// it for all its batches.
+pub struct GpuDwt {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl GpuDwt {
+    /// Opens the default GPU adapter, the high-performance one where there are several.
+    /// Returns `InvalidOperation` when the system has none.
+    pub fn new() -> Result<Self, JpeglsError> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::new_without_display_handle());
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            ..Default::default()
+        }))
+        .map_err(|_| JpeglsError::InvalidOperation)?;
+        // The largest buffers the adapter allows, for large tiles.
+        let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+            label: Some("jpegexp dwt"),
+            required_limits: adapter.limits(),
+            ..Default::default()
+        }))
+        .map_err(|_| JpeglsError::InvalidOperation)?;
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("inverse dwt"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("dwt_gpu.wgsl").into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("inverse dwt"),
+            layout: None,
+            module: &module,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        Ok(Self {
+            device,
+            queue,
+            pipeline,
+        })
+    }
+
+    /// Inverse 2D transform of every band of a batch, in place, on the GPU. See
+    /// [`inverse_2d_batch`](super::dwt::inverse_2d_batch) for the layout.
+    ///
+    /// Returns `InvalidArgumentSize` when a band runs past the end of `data` or overlaps
+    /// another, or when `data` is larger than a storage buffer of the device, and
+    /// `InvalidOperation` when the device is lost.
+    pub fn inverse_2d_batch(
+        &self,
+        filter: WaveletFilter,
+        data: &mut [f32],
+        bands: &[DwtBand],
+    ) -> Result<(), JpeglsError> {
+        check_batch(data.len(), bands)?;
+        let bands: Vec<&DwtBand> = bands.iter().filter(|b| b.sample_count() > 0).collect();
+        if bands.is_empty() {
+            return Ok(());
+        }
+        let limits = self.device.limits();
+        let size = std::mem::size_of_val(data) as u64;
+        if size
+            > limits
+                .max_storage_buffer_binding_size
+                .min(limits.max_buffer_size)
+            || data.len() > u32::MAX as usize
+        {
+            return Err(JpeglsError::InvalidArgumentSize);
+        }
+
+        let bytes: Vec<u8> = data.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let samples = self.buffer(
+            &bytes,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        );
+        let scratch = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("dwt scratch"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let readback = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("dwt readback"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let band_words: Vec<u8> = bands
+            .iter()
+            .flat_map(|b| [b.offset as u32, b.width, b.height, 0])
+            .flat_map(u32::to_le_bytes)
+            .collect();
+        let band_buffer = self.buffer(&band_words, wgpu::BufferUsages::STORAGE);
+
+        // 5-3 lifts the columns first and 9-7 the rows, as the CPU transforms do. The
+        // first pass reads the coefficients into the scratch buffer, the second writes
+        // the samples back.
+        let (filter_code, vertical_first) = match filter {
+            WaveletFilter::Reversible53 => (0, true),
+            WaveletFilter::Irreversible97 => (1, false),
+        };
+        let layout = self.pipeline.get_bind_group_layout(0);
+        let max_bands = limits.max_compute_workgroups_per_dimension as usize;
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        for (source, destination, vertical) in [
+            (&samples, &scratch, vertical_first),
+            (&scratch, &samples, !vertical_first),
+        ] {
+            let lines = bands
+                .iter()
+                .map(|b| if vertical { b.width } else { b.height })
+                .max()
+                .unwrap_or(0);
+            for first in (0..bands.len()).step_by(max_bands) {
+                let count = (bands.len() - first).min(max_bands);
+                let params: Vec<u8> = [filter_code, vertical as u32, first as u32, count as u32]
+                    .into_iter()
+                    .flat_map(u32::to_le_bytes)
+                    .collect();
+                let params = self.buffer(&params, wgpu::BufferUsages::UNIFORM);
+                let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: None,
+                    layout: &layout,
+                    entries: &[
+                        entry(0, &params),
+                        entry(1, &band_buffer),
+                        entry(2, source),
+                        entry(3, destination),
+                    ],
+                });
+                let mut pass = encoder.begin_compute_pass(&Default::default());
+                pass.set_pipeline(&self.pipeline);
+                pass.set_bind_group(0, &bind_group, &[]);
+                pass.dispatch_workgroups(lines.div_ceil(WORKGROUP_SIZE), count as u32, 1);
+            }
+        }
+        encoder.copy_buffer_to_buffer(&samples, 0, &readback, 0, size);
+        self.queue.submit([encoder.finish()]);
+
+        let (sender, receiver) = mpsc::channel();
+        readback.map_async(wgpu::MapMode::Read, .., move |result| {
+            let _ = sender.send(result);
+        });
+        self.device
+            .poll(wgpu::PollType::wait_indefinitely())
+            .map_err(|_| JpeglsError::InvalidOperation)?;
+        receiver
+            .recv()
+            .map_err(|_| JpeglsError::InvalidOperation)?
+            .map_err(|_| JpeglsError::InvalidOperation)?;
+        {
+            let view = readback
+                .get_mapped_range(..)
+                .map_err(|_| JpeglsError::InvalidOperation)?;
+            for (sample, bytes) in data.iter_mut().zip(view.chunks_exact(4)) {
+                *sample = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            }
+        }
+        readback.unmap();
+        Ok(())
+    }
+
+    fn buffer(&self, contents: &[u8], usage: wgpu::BufferUsages) -> wgpu::Buffer {
+        self.device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents,
+                usage,
+            })
+    }
+}
+
+fn entry(binding: u32, buffer: &wgpu::Buffer) -> wgpu::BindGroupEntry<'_> {
+    wgpu::BindGroupEntry {
+        binding,
+        resource: buffer.as_entire_binding(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jpeg2000::dwt::inverse_2d_batch;
+
+    #[test]
+    fn test_matches_cpu() {
+        // Without a GPU, or a software adapter, there is nothing to compare.
+        let Ok(gpu) = GpuDwt::new() else {
+            return;
+        };
+        let sizes = [(17, 9), (1, 5), (64, 64), (6, 1), (33, 20)];
+        let mut bands = Vec::new();
+        let mut offset = 0;
+        for (width, height) in sizes {
+            bands.push(DwtBand {
+                offset,
+                width,
+                height,
+            });
+            offset += (width * height) as usize + 3;
+        }
+        let coefficients: Vec<f32> = (0..offset)
+            .map(|i| ((i as i32).wrapping_mul(7919) % 511 - 255) as f32)
+            .collect();
+
+        for filter in [WaveletFilter::Reversible53, WaveletFilter::Irreversible97] {
+            let mut cpu = coefficients.clone();
+            inverse_2d_batch(filter, &mut cpu, &bands).unwrap();
+            let mut gpu_data = coefficients.clone();
+            gpu.inverse_2d_batch(filter, &mut gpu_data, &bands).unwrap();
+            for (i, (a, b)) in cpu.iter().zip(&gpu_data).enumerate() {
+                match filter {
+                    WaveletFilter::Reversible53 => assert_eq!(a, b, "5-3 sample {}", i),
+                    WaveletFilter::Irreversible97 => {
+                        assert!((a - b).abs() < 1e-2, "9-7 sample {}: {} vs {}", i, a, b)
+                    }
+                }
+            }
+        }
+    }
+}
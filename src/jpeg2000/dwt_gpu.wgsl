@@ -0,0 +1,124 @@
+// Inverse 2D DWT of a batch of bands for jpeg2000::dwt_gpu, with the band layout of
+// jpeg2000::dwt::inverse_2d_batch. A pass lifts every row or every column of the bands:
+// each invocation interleaves the low- and high-pass halves of one line from `source`
+// into `destination` and lifts it there, as the CPU lifting steps do.
+
+struct Band {
+    offset: u32,
+    width: u32,
+    height: u32,
+    padding: u32,
+}
+
+struct Params {
+    // 0: 5-3, 1: 9-7.
+    wavelet: u32,
+    // 0: rows, 1: columns.
+    vertical: u32,
+    first_band: u32,
+    band_count: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> bands: array<Band>;
+@group(0) @binding(2) var<storage, read> source: array<f32>;
+@group(0) @binding(3) var<storage, read_write> destination: array<f32>;
+
+const ALPHA: f32 = -1.5861343;
+const BETA: f32 = -0.05298012;
+const GAMMA: f32 = 0.8829111;
+const DELTA: f32 = 0.44350687;
+const K: f32 = 1.2301741;
+const INV_K: f32 = 1.0 / 1.2301741;
+
+// The line of this invocation: `len` samples `stride` apart from `base`.
+var<private> base: u32;
+var<private> stride: u32;
+var<private> len: i32;
+
+// Sample `i` of the line, with whole-sample symmetric extension past both ends.
+fn sample(i: i32) -> f32 {
+    var j = abs(i);
+    if j >= len {
+        j = 2 * (len - 1) - j;
+    }
+    return destination[base + u32(j) * stride];
+}
+
+fn store(i: i32, value: f32) {
+    destination[base + u32(i) * stride] = value;
+}
+
+// Even samples from their odd neighbours.
+fn update(coefficient: f32) {
+    for (var k = 0; k < len; k += 2) {
+        store(k, sample(k) + coefficient * (sample(k - 1) + sample(k + 1)));
+    }
+}
+
+// Odd samples from their even neighbours.
+fn predict(coefficient: f32) {
+    for (var k = 1; k < len; k += 2) {
+        store(k, sample(k) + coefficient * (sample(k - 1) + sample(k + 1)));
+    }
+}
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.y >= params.band_count {
+        return;
+    }
+    let band = bands[params.first_band + id.y];
+    var lines: u32;
+    if params.vertical == 1u {
+        lines = band.width;
+        len = i32(band.height);
+        base = band.offset + id.x;
+        stride = band.width;
+    } else {
+        lines = band.height;
+        len = i32(band.width);
+        base = band.offset + id.x * band.width;
+        stride = 1u;
+    }
+    if id.x >= lines {
+        return;
+    }
+
+    // The low-pass half of the line comes first.
+    let low = (len + 1) / 2;
+    for (var k = 0; k < len; k++) {
+        var index = k / 2;
+        if k % 2 == 1 {
+            index = low + k / 2;
+        }
+        store(k, source[base + u32(index) * stride]);
+    }
+    // A single sample passes through unchanged.
+    if len < 2 {
+        return;
+    }
+
+    if params.wavelet == 0u {
+        // x[2n] = y[2n] - floor((y[2n-1] + y[2n+1] + 2) / 4)
+        for (var k = 0; k < len; k += 2) {
+            store(k, sample(k) - floor((sample(k - 1) + sample(k + 1) + 2.0) / 4.0));
+        }
+        // x[2n+1] = y[2n+1] + floor((x[2n] + x[2n+2]) / 2)
+        for (var k = 1; k < len; k += 2) {
+            store(k, sample(k) + floor((sample(k - 1) + sample(k + 1)) / 2.0));
+        }
+    } else {
+        for (var k = 0; k < len; k++) {
+            if k % 2 == 0 {
+                store(k, sample(k) * K);
+            } else {
+                store(k, sample(k) * INV_K);
+            }
+        }
+        update(-DELTA);
+        predict(-GAMMA);
+        update(-BETA);
+        predict(-ALPHA);
+    }
+}
@@ -5,9 +5,13 @@
 
 use super::bit_io::J2kBitWriter;
 use super::dwt::{Dwt53, Dwt97};
-use super::image::{J2kCod, J2kQcd};
+use super::image::{J2kCap, J2kCod, J2kQcd};
+pub use super::progression::ProgressionOrder;
+use super::progression::{self, ComponentGrid, PacketId, ProgressionVolume, TileBounds};
 use super::quantization;
 use super::writer::J2kWriter;
+use crate::jpeg_stream_writer::{Sink, SliceSink};
+use crate::mem_profiling::{EncodeStats, Session};
 use crate::FrameInfo;
 use crate::JpeglsError;
 
@@ -19,6 +23,20 @@ pub struct J2kEncoder {
     use_irreversible: bool,
     /// Quality parameter (0-100, maps to quantization step size)
     quality: u8,
+    /// Packet order written to COD
+    progression_order: ProgressionOrder,
+    /// Number of quality layers
+    number_of_layers: u16,
+    /// Tile width and height; `None` encodes the image as a single tile
+    tile_size: Option<(u32, u32)>,
+    /// Code-block width and height exponents, minus 2 as written to COD
+    codeblock_size_exp: (u8, u8),
+    /// Precinct size exponents (PPx, PPy) per resolution, lowest first
+    precinct_sizes: Vec<(u8, u8)>,
+    /// Signal the HT block coder (HTJ2K, Part 15)
+    high_throughput: bool,
+    /// Memory statistics of the most recent encode
+    stats: EncodeStats,
 }
 
 impl J2kEncoder {
@@ -28,10 +46,18 @@ impl J2kEncoder {
             decomposition_levels: 5,
             use_irreversible: true,
             quality: 85,
+            progression_order: ProgressionOrder::Lrcp,
+            number_of_layers: 1,
+            tile_size: None,
+            codeblock_size_exp: (4, 4), // 64x64
+            precinct_sizes: Vec::new(),
+            high_throughput: false,
+            stats: EncodeStats::default(),
         }
     }
 
-    /// Set the quality level (0-100)
+    /// Set the quality level (1-100), which sets the quantization steps written to QCD.
+    /// The encoder writes no code-block data yet, so the decoded image does not change.
     pub fn set_quality(&mut self, quality: u8) {
         self.quality = quality.min(100).max(1);
     }
@@ -46,12 +72,139 @@ impl J2kEncoder {
         self.use_irreversible = irreversible;
     }
 
+    /// Write an HTJ2K (Part 15) codestream: Rsiz and a CAP segment declare the HT block
+    /// coder, which every code-block uses. This only changes the headers until code-block
+    /// data is written.
+    pub fn set_high_throughput(&mut self, high_throughput: bool) {
+        self.high_throughput = high_throughput;
+    }
+
+    /// Set the progression order of the packets
+    pub fn set_progression_order(&mut self, order: ProgressionOrder) {
+        self.progression_order = order;
+    }
+
+    /// Set the number of quality layers (at least 1)
+    pub fn set_num_layers(&mut self, layers: u16) {
+        self.number_of_layers = layers.max(1);
+    }
+
+    /// Set the tile size. Tiles start at the image origin; those in the last column and
+    /// row are cut off at the image edge. The image may have at most 65535 tiles.
+    pub fn set_tile_size(&mut self, width: u32, height: u32) {
+        self.tile_size = Some((width.max(1), height.max(1)));
+    }
+
+    /// Set the nominal code-block size in samples. Width and height must be powers of two
+    /// from 4 to 1024, with at most 4096 samples per code-block.
+    pub fn set_codeblock_size(&mut self, width: u32, height: u32) -> Result<(), JpeglsError> {
+        let valid = |size: u32| size.is_power_of_two() && (4..=1024).contains(&size);
+        if !valid(width) || !valid(height) || width * height > 4096 {
+            return Err(JpeglsError::ParameterValueNotSupported);
+        }
+        self.codeblock_size_exp = (
+            width.trailing_zeros() as u8 - 2,
+            height.trailing_zeros() as u8 - 2,
+        );
+        Ok(())
+    }
+
+    /// Set the precinct sizes as (PPx, PPy) exponents, one pair per resolution starting
+    /// with the lowest; resolutions beyond the list use its last pair. Exponents go up to
+    /// 15 and may only be 0 for the lowest resolution. An empty list (the default) gives
+    /// every resolution a single precinct and leaves Scod bit 0 clear.
+    pub fn set_precinct_sizes(&mut self, sizes: &[(u8, u8)]) -> Result<(), JpeglsError> {
+        if sizes.iter().any(|&(ppx, ppy)| ppx > 15 || ppy > 15)
+            || sizes.iter().skip(1).any(|&(ppx, ppy)| ppx == 0 || ppy == 0)
+        {
+            return Err(JpeglsError::ParameterValueNotSupported);
+        }
+        self.precinct_sizes = sizes.to_vec();
+        Ok(())
+    }
+
+    /// Size of a destination buffer large enough to encode an image described by
+    /// `frame_info` with the current settings: the main header, the tile-part headers and
+    /// one byte per empty packet, plus twice the sample bytes for code-block data.
+    pub fn estimated_destination_size(&self, frame_info: &FrameInfo) -> usize {
+        let width = frame_info.width as usize;
+        let height = frame_info.height as usize;
+        let components = frame_info.component_count.max(0) as usize;
+        let (tile_width, tile_height) = self.tile_dimensions(width, height);
+        let tile_count = width.div_ceil(tile_width) * height.div_ceil(tile_height);
+        let precinct_sizes = self.resolution_precinct_sizes();
+        let precincts: usize = (0..tile_count)
+            .map(|tile_index| {
+                let tile = tile_bounds(tile_index, (width, height), (tile_width, tile_height));
+                let grid =
+                    ComponentGrid::new(tile, (1, 1), self.decomposition_levels, &precinct_sizes);
+                grid.resolutions
+                    .iter()
+                    .map(|resolution| resolution.precinct_count())
+                    .sum::<usize>()
+            })
+            .sum();
+        let packets = precincts * components * self.number_of_layers as usize;
+        // SIZ grows by 3 bytes per component; the other main header segments fit in 1024.
+        1024 + 3 * components + 14 * tile_count + packets + 2 * width * height * components
+    }
+
+    /// Memory statistics of the most recent call to [`encode`](Self::encode)
+    pub fn stats(&self) -> EncodeStats {
+        self.stats
+    }
+
     /// Encode pixel data to JPEG 2000 codestream
+    ///
+    /// Returns [`JpeglsError::DestinationTooSmall`] with the estimated size, or more than
+    /// the destination if that did not fit either, when the codestream does not fit.
     pub fn encode(
         &mut self,
         pixels: &[u8],
         frame_info: &FrameInfo,
         destination: &mut [u8],
+    ) -> Result<usize, JpeglsError> {
+        let destination_len = destination.len();
+        let result = self.encode_to_sink(pixels, frame_info, SliceSink::new(destination));
+        let needed = self
+            .estimated_destination_size(frame_info)
+            .max(destination_len + 1);
+        result.map_err(|e| e.with_needed_size(needed))
+    }
+
+    /// Encode pixel data into a buffer that grows as the codestream is written
+    pub fn encode_to_vec(
+        &mut self,
+        pixels: &[u8],
+        frame_info: &FrameInfo,
+    ) -> Result<Vec<u8>, JpeglsError> {
+        let mut codestream = Vec::new();
+        self.encode_to_sink(pixels, frame_info, &mut codestream)?;
+        Ok(codestream)
+    }
+
+    /// Encode pixel data, writing the codestream to `sink` as it is produced, and return
+    /// its length
+    pub fn encode_to_sink<D: Sink>(
+        &mut self,
+        pixels: &[u8],
+        frame_info: &FrameInfo,
+        sink: D,
+    ) -> Result<usize, JpeglsError> {
+        let session = Session::begin();
+        let result = self.encode_codestream(pixels, frame_info, sink);
+        self.stats = EncodeStats {
+            peak_buffer_bytes: session.finish(),
+            pixel_count: frame_info.width as u64 * frame_info.height as u64,
+        };
+        result
+    }
+
+    fn encode_codestream<D: Sink>(
+        &mut self,
+        pixels: &[u8],
+        frame_info: &FrameInfo,
+        sink: D,
     ) -> Result<usize, JpeglsError> {
         let width = frame_info.width as usize;
         let height = frame_info.height as usize;
@@ -64,18 +217,25 @@ impl J2kEncoder {
             return Err(JpeglsError::InvalidData);
         }
 
+        let (tile_width, tile_height) = self.tile_dimensions(width, height);
+        let tile_count = width.div_ceil(tile_width) * height.div_ceil(tile_height);
+        if tile_count > 65535 {
+            return Err(JpeglsError::ParameterValueNotSupported);
+        }
+
         // Initialize writer
-        let mut writer = J2kWriter::new(destination);
+        let mut writer = J2kWriter::with_sink(sink);
 
         // Write SOC (Start of Codestream)
         writer.write_soc()?;
 
         // Write SIZ (Image and Tile Size)
         writer.write_siz(
+            if self.high_throughput { 0x4000 } else { 0 },
             width as u32,
             height as u32,
-            width as u32, // single tile
-            height as u32,
+            tile_width as u32,
+            tile_height as u32,
             components as u16,
             depth,
             1,
@@ -85,31 +245,62 @@ impl J2kEncoder {
         // Determine transform type
         let transformation = if self.use_irreversible { 0 } else { 1 }; // 0=9-7, 1=5-3
 
+        // Precinct sizes of every resolution; a repeated last entry must not be 0.
+        let precinct_sizes = self.resolution_precinct_sizes();
+        if precinct_sizes
+            .iter()
+            .skip(1)
+            .any(|&(ppx, ppy)| ppx == 0 || ppy == 0)
+        {
+            return Err(JpeglsError::ParameterValueNotSupported);
+        }
+
         // Create COD marker
         let cod = J2kCod {
-            coding_style: 0,
-            progression_order: 0, // LRCP
-            number_of_layers: 1,
+            coding_style: if precinct_sizes.is_empty() { 0 } else { 1 },
+            progression_order: self.progression_order as u8,
+            number_of_layers: self.number_of_layers,
             mct: if components >= 3 { 1 } else { 0 },
             decomposition_levels: self.decomposition_levels,
-            codeblock_width_exp: 4, // 64x64 code-blocks
-            codeblock_height_exp: 4,
+            codeblock_width_exp: self.codeblock_size_exp.0,
+            codeblock_height_exp: self.codeblock_size_exp.1,
+            codeblock_style: if self.high_throughput { 0x40 } else { 0 },
             transformation,
-            precinct_sizes: Vec::new(),
+            precinct_sizes: precinct_sizes
+                .iter()
+                .map(|&(ppx, ppy)| ppy << 4 | ppx)
+                .collect(),
         };
-        writer.write_cod(&cod)?;
 
         // Create QCD marker
         let num_subbands = 1 + 3 * self.decomposition_levels as usize; // LL + 3 per level
         let base_step = self.calculate_step_size(depth);
-        let step_sizes: Vec<u16> = (0..num_subbands)
-            .map(|i| self.encode_step_size(base_step, i))
-            .collect();
+        let step_sizes: Vec<u16> = if self.use_irreversible {
+            (0..num_subbands)
+                .map(|i| self.encode_step_size(base_step, i))
+                .collect()
+        } else {
+            // Reversible: only the exponent, the bit depth plus the subband gain (E.1.1)
+            (0..num_subbands)
+                .map(|i| {
+                    let gain = match i {
+                        0 => 0,
+                        i if i % 3 == 0 => 2, // HH
+                        _ => 1,               // HL, LH
+                    };
+                    (depth as u16 + gain) << 3
+                })
+                .collect()
+        };
 
         let qcd = J2kQcd {
             quant_style: if self.use_irreversible { 2 } else { 0 }, // 2=expounded, 0=no quant
             step_sizes,
         };
+        if self.high_throughput {
+            writer.write_cap(&ht_capabilities(&qcd, self.use_irreversible))?;
+        }
+        writer.write_cod(&cod)?;
         writer.write_qcd(&qcd)?;
 
         // TODO: Full JPEG2000 encoding requires EBCOT (Tier-1) bit-plane coding:
@@ -124,25 +315,25 @@ impl J2kEncoder {
         // Currently, we write empty packets which produces a valid J2K that
         // decodes to 0 (after level shift = 128 for 8-bit).
 
-        // Write SOT (Start of Tile)
-        writer.write_sot(0, 0, 0, 1)?;
-
-        // Write SOD (Start of Data)
-        writer.write_sod()?;
+        for tile_index in 0..tile_count {
+            let tile = tile_bounds(tile_index, (width, height), (tile_width, tile_height));
+            let grids = self.component_grids(tile, &precinct_sizes, components);
 
-        // Write empty packets for valid J2K structure
-        let num_resolutions = (self.decomposition_levels + 1) as usize;
-
-        // LRCP order: Layer -> Resolution -> Component -> Precinct
-        for _layer in 0..1 {
-            for _res in 0..num_resolutions {
-                for _comp in 0..components {
-                    // Write empty packet header (single 0 bit = empty)
-                    let mut bit_writer = J2kBitWriter::new();
-                    bit_writer.write_bit(0);
-                    writer.write_bytes(&bit_writer.finish())?;
-                }
+            // Write empty packets for valid J2K structure
+            let mut tile_data = Vec::new();
+            for (_layer, _res, _comp, _precinct) in self.packet_sequence(tile, &grids) {
+                // Write empty packet header (single 0 bit = empty)
+                let mut bit_writer = J2kBitWriter::new();
+                bit_writer.write_bit(0);
+                tile_data.extend(bit_writer.finish());
             }
+
+            // Psot counts from the SOT marker to the end of the tile-part: 12 bytes of
+            // SOT, 2 of SOD and the packets.
+            let tile_part_length = 12 + 2 + tile_data.len();
+            writer.write_sot(tile_index as u16, tile_part_length as u32, 0, 1)?;
+            writer.write_sod()?;
+            writer.write_bytes(&tile_data)?;
         }
 
         // Write EOC (End of Codestream)
@@ -151,6 +342,46 @@ impl J2kEncoder {
         Ok(writer.len())
     }
 
+    /// Tile width and height for an image of `width` x `height`, at most the image size.
+    fn tile_dimensions(&self, width: usize, height: usize) -> (usize, usize) {
+        let (tile_width, tile_height) = self
+            .tile_size
+            .map(|(w, h)| (w as usize, h as usize))
+            .unwrap_or((width, height));
+        (
+            tile_width.clamp(1, width.max(1)),
+            tile_height.clamp(1, height.max(1)),
+        )
+    }
+
+    /// Precinct size exponents of every resolution, or none for maximal precincts.
+    fn resolution_precinct_sizes(&self) -> Vec<(u8, u8)> {
+        if self.precinct_sizes.is_empty() {
+            return Vec::new();
+        }
+        (0..=self.decomposition_levels as usize)
+            .map(|r| self.precinct_sizes[r.min(self.precinct_sizes.len() - 1)])
+            .collect()
+    }
+
+    /// Precinct grids of the components of a tile; the encoder does not subsample.
+    fn component_grids(
+        &self,
+        tile: TileBounds,
+        precinct_sizes: &[(u8, u8)],
+        components: usize,
+    ) -> Vec<ComponentGrid> {
+        let grid = ComponentGrid::new(tile, (1, 1), self.decomposition_levels, precinct_sizes);
+        vec![grid; components]
+    }
+
+    /// (layer, resolution, component, precinct) of every packet of a tile in the selected
+    /// progression order.
+    fn packet_sequence(&self, tile: TileBounds, grids: &[ComponentGrid]) -> Vec<PacketId> {
+        let volume = ProgressionVolume::whole(self.progression_order, self.number_of_layers, grids);
+        progression::packet_sequence(tile, grids, self.number_of_layers, &[volume])
+    }
+
     /// Calculate quantization step size based on quality
     fn calculate_step_size(&self, depth: u8) -> f32 {
         let base = 1.0 / (1 << depth) as f32;
@@ -168,9 +399,10 @@ impl J2kEncoder {
         };
         let adjusted = step * gain;
 
-        // Convert to (exponent, mantissa) format
-        let log2 = adjusted.log2();
-        let exponent = ((-log2).floor() as i32).clamp(0, 31) as u16;
+        // Convert to (exponent, mantissa) format: exponent = floor(-log2(adjusted)).
+        let ceil_log2 =
+            quantization::floor_log2(adjusted) + (adjusted.to_bits() & 0x7F_FFFF != 0) as i32;
+        let exponent = (-ceil_log2).clamp(0, 31) as u16;
         let mantissa = ((adjusted * (1 << exponent) as f32 - 1.0) * 2048.0) as u16 & 0x7FF;
 
         (exponent << 11) | mantissa
@@ -308,7 +540,7 @@ impl J2kEncoder {
                         );
 
                         // Find MSB position
-                        let msb = (max_val as f32).log2().ceil() as u8;
+                        let msb = (max_val as u32).next_power_of_two().trailing_zeros() as u8;
                         let num_bitplanes = msb.min(depth);
 
                         // Encode bit-planes from MSB to 0
@@ -393,9 +625,9 @@ impl J2kEncoder {
     }
 
     /// Encode a single component
-    fn _encode_component(
+    fn _encode_component<D: Sink>(
         &mut self,
-        _writer: &mut J2kWriter,
+        _writer: &mut J2kWriter<D>,
         pixels: &[u8],
         width: usize,
         height: usize,
@@ -497,8 +729,454 @@ impl J2kEncoder {
     }
 }
 
+/// CAP segment of an HTJ2K codestream whose code-blocks all use the HT block coder
+/// (ISO/IEC 15444-15, A.3): Pcap declares Part 15 and Ccap15 whether the irreversible
+/// transform is used and the most magnitude bit-planes a code-block can have.
+fn ht_capabilities(qcd: &J2kQcd, irreversible: bool) -> J2kCap {
+    // The magnitude bit-planes of a subband are the guard bits plus its exponent minus 1.
+    let guard_bits = (qcd.quant_style >> 5) as u32;
+    let exponent_shift = if irreversible { 11 } else { 3 };
+    let bit_planes = qcd
+        .step_sizes
+        .iter()
+        .map(|&step| (guard_bits + (step >> exponent_shift) as u32).saturating_sub(1))
+        .max()
+        .unwrap_or(0);
+    let magb = match bit_planes {
+        0..=8 => 0,
+        9..=27 => bit_planes - 8,
+        28..=47 => 13 + (bit_planes >> 2),
+        _ => 31,
+    };
+    let irreversible = if irreversible { 0x0020 } else { 0 };
+    J2kCap {
+        pcap: 1 << 17,
+        ccap: vec![irreversible | magb as u16],
+    }
+}
+
+/// Bounds of tile `tile_index` of an image, in raster order.
+fn tile_bounds(
+    tile_index: usize,
+    (width, height): (usize, usize),
+    (tile_width, tile_height): (usize, usize),
+) -> TileBounds {
+    let tiles_wide = width.div_ceil(tile_width);
+    let tx0 = tile_index % tiles_wide * tile_width;
+    let ty0 = tile_index / tiles_wide * tile_height;
+    (
+        tx0,
+        ty0,
+        (tx0 + tile_width).min(width),
+        (ty0 + tile_height).min(height),
+    )
+}
+
 impl Default for J2kEncoder {
     fn default() -> Self {
         Self::new()
     }
 }
+
+/// Builder for a [`J2kEncoder`] that checks the frame and the coding options together
+/// when it is built. Images encoded with the result must use the same frame info.
+///
+/// ```rust
+/// use jpegexp_rs::jpeg2000::encoder::{J2kEncoderBuilder, ProgressionOrder};
+/// use jpegexp_rs::FrameInfo;
+///
+/// let frame_info = FrameInfo { width: 64, height: 64, bits_per_sample: 8, component_count: 1 };
+/// let mut encoder = J2kEncoderBuilder::new(frame_info)
+///     .irreversible(false)
+///     .progression_order(ProgressionOrder::Rlcp)
+///     .tile_size(32, 32)
+///     .build()
+///     .unwrap();
+/// let mut destination = vec![0u8; 16384];
+/// let len = encoder.encode(&[128u8; 64 * 64], &frame_info, &mut destination).unwrap();
+/// assert!(len > 0);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct J2kEncoderBuilder {
+    frame_info: FrameInfo,
+    quality: Option<u8>,
+    decomposition_levels: Option<u8>,
+    irreversible: bool,
+    high_throughput: bool,
+    progression_order: ProgressionOrder,
+    number_of_layers: u16,
+    tile_size: Option<(u32, u32)>,
+    codeblock_size: Option<(u32, u32)>,
+    precinct_sizes: Vec<(u8, u8)>,
+}
+
+impl J2kEncoderBuilder {
+    /// Start from the default settings of [`J2kEncoder::new`]
+    pub fn new(frame_info: FrameInfo) -> Self {
+        Self {
+            frame_info,
+            quality: None,
+            decomposition_levels: None,
+            irreversible: true,
+            high_throughput: false,
+            progression_order: ProgressionOrder::Lrcp,
+            number_of_layers: 1,
+            tile_size: None,
+            codeblock_size: None,
+            precinct_sizes: Vec::new(),
+        }
+    }
+
+    /// Quality level (1-100)
+    pub fn quality(mut self, quality: u8) -> Self {
+        self.quality = Some(quality);
+        self
+    }
+
+    /// Number of decomposition levels (at most 32)
+    pub fn decomposition_levels(mut self, levels: u8) -> Self {
+        self.decomposition_levels = Some(levels);
+        self
+    }
+
+    /// Use the irreversible 9-7 (`true`) or reversible 5-3 transform
+    pub fn irreversible(mut self, irreversible: bool) -> Self {
+        self.irreversible = irreversible;
+        self
+    }
+
+    /// See [`J2kEncoder::set_high_throughput`]
+    pub fn high_throughput(mut self, high_throughput: bool) -> Self {
+        self.high_throughput = high_throughput;
+        self
+    }
+
+    /// Progression order of the packets
+    pub fn progression_order(mut self, order: ProgressionOrder) -> Self {
+        self.progression_order = order;
+        self
+    }
+
+    /// Number of quality layers (at least 1)
+    pub fn num_layers(mut self, layers: u16) -> Self {
+        self.number_of_layers = layers;
+        self
+    }
+
+    /// See [`J2kEncoder::set_tile_size`]
+    pub fn tile_size(mut self, width: u32, height: u32) -> Self {
+        self.tile_size = Some((width, height));
+        self
+    }
+
+    /// See [`J2kEncoder::set_codeblock_size`]
+    pub fn codeblock_size(mut self, width: u32, height: u32) -> Self {
+        self.codeblock_size = Some((width, height));
+        self
+    }
+
+    /// See [`J2kEncoder::set_precinct_sizes`]
+    pub fn precinct_sizes(mut self, sizes: &[(u8, u8)]) -> Self {
+        self.precinct_sizes = sizes.to_vec();
+        self
+    }
+
+    /// Check the configuration and return the encoder.
+    ///
+    /// Returns [`JpeglsError::InvalidArgumentWidth`] or
+    /// [`JpeglsError::InvalidArgumentHeight`] for an empty image,
+    /// [`JpeglsError::InvalidArgumentComponentCount`] outside 1-16384 components,
+    /// [`JpeglsError::InvalidArgumentBitsPerSample`] outside 1-8 bits, and
+    /// [`JpeglsError::InvalidArgumentEncodingOptions`] for options out of range, more
+    /// than 65535 tiles, or precinct sizes that give a resolution a 0 exponent.
+    pub fn build(self) -> Result<J2kEncoder, JpeglsError> {
+        let frame_info = &self.frame_info;
+        if frame_info.width == 0 {
+            return Err(JpeglsError::InvalidArgumentWidth);
+        }
+        if frame_info.height == 0 {
+            return Err(JpeglsError::InvalidArgumentHeight);
+        }
+        if !(1..=16384).contains(&frame_info.component_count) {
+            return Err(JpeglsError::InvalidArgumentComponentCount);
+        }
+        if !(1..=8).contains(&frame_info.bits_per_sample) {
+            return Err(JpeglsError::InvalidArgumentBitsPerSample);
+        }
+
+        let invalid = JpeglsError::InvalidArgumentEncodingOptions;
+        let levels = self.decomposition_levels.unwrap_or(5);
+        if self
+            .quality
+            .is_some_and(|quality| !(1..=100).contains(&quality))
+            || levels > 32
+            || self.number_of_layers == 0
+        {
+            return Err(invalid);
+        }
+        if let Some((tile_width, tile_height)) = self.tile_size {
+            if tile_width == 0 || tile_height == 0 {
+                return Err(invalid);
+            }
+            let tiles = |size: u32, tile: u32| size.div_ceil(tile.min(size)) as u64;
+            let tile_count =
+                tiles(frame_info.width, tile_width) * tiles(frame_info.height, tile_height);
+            if tile_count > 65535 {
+                return Err(invalid);
+            }
+        }
+        // Resolutions beyond the list repeat its last pair.
+        if self.precinct_sizes.len() == 1
+            && levels > 0
+            && matches!(self.precinct_sizes[0], (0, _) | (_, 0))
+        {
+            return Err(invalid);
+        }
+
+        let mut encoder = J2kEncoder::new();
+        if let Some(quality) = self.quality {
+            encoder.set_quality(quality);
+        }
+        encoder.set_decomposition_levels(levels);
+        encoder.set_irreversible(self.irreversible);
+        encoder.set_high_throughput(self.high_throughput);
+        encoder.set_progression_order(self.progression_order);
+        encoder.set_num_layers(self.number_of_layers);
+        if let Some((width, height)) = self.tile_size {
+            encoder.set_tile_size(width, height);
+        }
+        if let Some((width, height)) = self.codeblock_size {
+            encoder
+                .set_codeblock_size(width, height)
+                .map_err(|_| invalid)?;
+        }
+        encoder
+            .set_precinct_sizes(&self.precinct_sizes)
+            .map_err(|_| invalid)?;
+        Ok(encoder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jpeg2000::decoder::J2kDecoder;
+    use crate::jpeg_stream_reader::JpegStreamReader;
+
+    fn encode(encoder: &mut J2kEncoder) -> Vec<u8> {
+        let frame_info = FrameInfo {
+            width: 40,
+            height: 24,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let pixels = vec![128u8; 40 * 24 * 3];
+        let mut destination = vec![0u8; 4096];
+        let len = encoder
+            .encode(&pixels, &frame_info, &mut destination)
+            .unwrap();
+        destination.truncate(len);
+        destination
+    }
+
+    #[test]
+    fn test_layers_and_progression_order_in_cod() {
+        let mut encoder = J2kEncoder::new();
+        encoder.set_progression_order(ProgressionOrder::Rpcl);
+        encoder.set_num_layers(4);
+        encoder.set_decomposition_levels(2);
+        let data = encode(&mut encoder);
+        assert!(crate::jpeg2000::validate(&data).is_valid());
+
+        let mut reader = JpegStreamReader::new(&data);
+        let mut decoder = J2kDecoder::new(&mut reader);
+        let image = decoder.decode().unwrap();
+        let cod = image.cod.as_ref().unwrap();
+        assert_eq!(cod.progression_order, 2);
+        assert_eq!(cod.number_of_layers, 4);
+        assert_eq!(cod.decomposition_levels, 2);
+    }
+
+    #[test]
+    fn test_tiles() {
+        let mut encoder = J2kEncoder::new();
+        encoder.set_tile_size(16, 16);
+        let data = encode(&mut encoder);
+        let report = crate::jpeg2000::validate(&data);
+        assert!(report.is_valid(), "{:?}", report.errors);
+
+        let mut reader = JpegStreamReader::new(&data);
+        let mut decoder = J2kDecoder::new(&mut reader);
+        let image = decoder.decode().unwrap();
+        assert_eq!((image.tile_width, image.tile_height), (16, 16));
+        // 40x24 pixels in 16x16 tiles: 3 columns, 2 rows.
+        assert_eq!(data.windows(2).filter(|w| w == &[0xFF, 0x90]).count(), 6);
+        // Every tile, including the partial ones at the right and bottom, is decoded.
+        assert_eq!(
+            image.reconstruct_pixels().unwrap(),
+            vec![128u8; 40 * 24 * 3]
+        );
+
+        encoder.set_tile_size(1, 1);
+        let frame_info = FrameInfo {
+            width: 300,
+            height: 300,
+            bits_per_sample: 8,
+            component_count: 1,
+        };
+        let mut destination = vec![0u8; 1 << 20];
+        assert_eq!(
+            encoder.encode(&[0; 300 * 300], &frame_info, &mut destination),
+            Err(JpeglsError::ParameterValueNotSupported)
+        );
+    }
+
+    #[test]
+    fn test_codeblock_and_precinct_sizes() {
+        let mut encoder = J2kEncoder::new();
+        encoder.set_decomposition_levels(2);
+        encoder.set_codeblock_size(32, 16).unwrap();
+        encoder.set_precinct_sizes(&[(3, 3)]).unwrap();
+        assert!(encoder.set_codeblock_size(128, 64).is_err());
+        assert!(encoder.set_precinct_sizes(&[(3, 3), (0, 4)]).is_err());
+
+        let data = encode(&mut encoder);
+        let report = crate::jpeg2000::validate(&data);
+        assert!(report.is_valid(), "{:?}", report.errors);
+        let mut reader = JpegStreamReader::new(&data);
+        let mut decoder = J2kDecoder::new(&mut reader);
+        let image = decoder.decode().unwrap();
+        let cod = image.cod.as_ref().unwrap();
+        assert_eq!(cod.coding_style & 1, 1);
+        assert_eq!((cod.codeblock_width_exp, cod.codeblock_height_exp), (3, 2));
+        assert_eq!(cod.precinct_sizes, vec![0x33; 3]);
+
+        // 8x8 precincts over resolutions of 10x6, 20x12 and 40x24 samples.
+        let tile = (0, 0, 40, 24);
+        let grids = encoder.component_grids(tile, &[(3, 3); 3], 3);
+        let per_component = [2, 6, 15];
+        for order in [
+            ProgressionOrder::Lrcp,
+            ProgressionOrder::Rpcl,
+            ProgressionOrder::Pcrl,
+            ProgressionOrder::Cprl,
+        ] {
+            encoder.set_progression_order(order);
+            let mut packets = encoder.packet_sequence(tile, &grids);
+            assert_eq!(packets.len(), 3 * per_component.iter().sum::<usize>());
+            packets.sort();
+            packets.dedup();
+            assert_eq!(packets.len(), 3 * per_component.iter().sum::<usize>());
+        }
+
+        // Every order visits the same packets of a tile that is not aligned to precincts.
+        let tile = (20, 12, 40, 24);
+        let grids = encoder.component_grids(tile, &[(1, 1), (2, 1), (2, 2)], 2);
+        encoder.set_progression_order(ProgressionOrder::Lrcp);
+        let mut expected = encoder.packet_sequence(tile, &grids);
+        expected.sort();
+        for order in [
+            ProgressionOrder::Rpcl,
+            ProgressionOrder::Pcrl,
+            ProgressionOrder::Cprl,
+        ] {
+            encoder.set_progression_order(order);
+            let mut packets = encoder.packet_sequence(tile, &grids);
+            packets.sort();
+            assert_eq!(packets, expected, "{:?}", order);
+        }
+    }
+
+    #[test]
+    fn test_reversible_codestream_is_valid() {
+        let mut encoder = J2kEncoder::new();
+        encoder.set_irreversible(false);
+        let data = encode(&mut encoder);
+        let report = crate::jpeg2000::validate(&data);
+        assert!(report.is_valid(), "{:?}", report.errors);
+    }
+
+    #[test]
+    fn test_high_throughput_codestream() {
+        let mut encoder = J2kEncoder::new();
+        encoder.set_high_throughput(true);
+        for irreversible in [true, false] {
+            encoder.set_irreversible(irreversible);
+            let data = encode(&mut encoder);
+            let report = crate::jpeg2000::validate(&data);
+            assert!(report.is_valid(), "{:?}", report.errors);
+
+            let mut reader = JpegStreamReader::new(&data);
+            let mut decoder = J2kDecoder::new(&mut reader);
+            let image = decoder.decode().unwrap();
+            let cap = image.cap.as_ref().unwrap();
+            assert_eq!(
+                cap.ht_declaration(),
+                Some(crate::jpeg2000::image::HtDeclaration::HtOnly)
+            );
+            let irreversible_flag = if irreversible { 0x0020 } else { 0 };
+            assert_eq!(cap.ccap[0] & 0x0020, irreversible_flag);
+            assert_eq!(image.cod.as_ref().unwrap().codeblock_style, 0x40);
+        }
+    }
+
+    #[test]
+    fn test_builder_validation() {
+        let frame_info = FrameInfo {
+            width: 40,
+            height: 24,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let build = |builder: J2kEncoderBuilder| builder.build().map(|_| ());
+        let invalid = Err(JpeglsError::InvalidArgumentEncodingOptions);
+
+        assert_eq!(
+            build(J2kEncoderBuilder::new(FrameInfo {
+                width: 0,
+                ..frame_info
+            })),
+            Err(JpeglsError::InvalidArgumentWidth)
+        );
+        assert_eq!(
+            build(J2kEncoderBuilder::new(FrameInfo {
+                bits_per_sample: 12,
+                ..frame_info
+            })),
+            Err(JpeglsError::InvalidArgumentBitsPerSample)
+        );
+        let builder = J2kEncoderBuilder::new(frame_info);
+        assert_eq!(build(builder.clone().quality(101)), invalid);
+        assert_eq!(build(builder.clone().decomposition_levels(33)), invalid);
+        assert_eq!(build(builder.clone().num_layers(0)), invalid);
+        assert_eq!(build(builder.clone().tile_size(0, 8)), invalid);
+        assert_eq!(build(builder.clone().codeblock_size(128, 64)), invalid);
+        assert_eq!(build(builder.clone().precinct_sizes(&[(0, 0)])), invalid);
+        assert_eq!(
+            build(
+                builder
+                    .clone()
+                    .decomposition_levels(0)
+                    .precinct_sizes(&[(0, 0)])
+            ),
+            Ok(())
+        );
+
+        let wide = FrameInfo {
+            width: 65536 * 2,
+            height: 2,
+            bits_per_sample: 8,
+            component_count: 1,
+        };
+        assert_eq!(build(J2kEncoderBuilder::new(wide).tile_size(1, 1)), invalid);
+
+        let mut encoder = builder
+            .num_layers(3)
+            .progression_order(ProgressionOrder::Rpcl)
+            .irreversible(false)
+            .build()
+            .unwrap();
+        let data = encode(&mut encoder);
+        assert!(crate::jpeg2000::validate(&data).is_valid());
+    }
+}
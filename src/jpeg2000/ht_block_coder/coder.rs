@@ -1,231 +1,204 @@
 use crate::jpeg2000::ht_block_coder::mag_sgn::MagSgnDecoder;
 use crate::jpeg2000::ht_block_coder::mel::MelDecoder;
-use crate::jpeg2000::ht_block_coder::vlc;
+use crate::jpeg2000::ht_block_coder::vlc::{self, CxtVlcTable, VlcReader};
 use crate::jpeg2000::image::J2kCodeBlock;
 
 /// High Throughput Block Coder (HTJ2K Part 15).
 /// Processes code-blocks using non-iterative entropy coding.
+///
+/// The cleanup segment holds the MagSgn bitstream growing forward from its start and
+/// the MEL/VLC segment at its end: the MEL bitstream grows forward and the VLC bitstream
+/// backward within it. The last 12 bits of the segment give the MEL/VLC length Scup.
 pub struct HTBlockCoder<'a> {
     mel_decoder: MelDecoder<'a>,
+    vlc_reader: VlcReader<'a>,
     magsgn_decoder: MagSgnDecoder<'a>,
-    // VLC doesn't hold state (static tables), but context does.
+    table: &'a CxtVlcTable,
 
-    // State
     width: usize,
     height: usize,
-    stripe_height: usize, // Usually 4
 }
 
+/// Offsets of the samples of a quad in scan order, which is column by column.
+const QUAD_SAMPLES: [(usize, usize); 4] = [(0, 0), (0, 1), (1, 0), (1, 1)];
+
 impl<'a> HTBlockCoder<'a> {
-    pub fn new(mel_data: &'a [u8], magsgn_data: &'a [u8], width: usize, height: usize) -> Self {
-        Self {
-            mel_decoder: MelDecoder::new(mel_data),
-            magsgn_decoder: MagSgnDecoder::new(magsgn_data),
-            width,
-            height,
-            stripe_height: 4,
-        }
+    /// Splits the cleanup segment `data` of a `width` x `height` code-block.
+    /// Returns `None` when Scup does not fit the segment.
+    pub fn new(data: &'a [u8], width: usize, height: usize) -> Option<Self> {
+        Self::with_table(data, width, height, CxtVlcTable::standard())
     }
 
-    /// Decodes an entire codeblock into quantized coefficients.
-    pub fn decode_block(&mut self, block: &mut J2kCodeBlock) -> Result<(), ()> {
-        // HTJ2K decoding flow:
-        // 1. Cleanup Pass (logic driven by MEL and VLC)
-        // 2. SigProp Pass (forward scan)
-        // 3. MagRef Pass (refinement)
-
-        // Actually, HTJ2K (Part 15) defines a single "Cleanup Pass" that does ALMOST EVERYTHING?
-        // "The fast block coder ... is based on a single pass cleaning up ... "
-        // Wait, Part 15 Section 6.1: "The block coder ... one pass per bit-plane?"
-        // NO. "The HT block coder ... all bit-planes are coded ... in a non-iterative manner."
-        // "Sub-bit-plane passes are NOT used."
-        // It processes "quads" of samples.
-
-        // Initialize block dimensions and coefficients if not already set
-        if block.width == 0 {
-            block.width = self.width as u32;
+    /// As [`HTBlockCoder::new`], decoding CxtVLC codewords with `table`.
+    pub fn with_table(
+        data: &'a [u8],
+        width: usize,
+        height: usize,
+        table: &'a CxtVlcTable,
+    ) -> Option<Self> {
+        let lcup = data.len();
+        if lcup < 2 {
+            return None;
         }
-        if block.height == 0 {
-            block.height = self.height as u32;
+        let scup = (data[lcup - 1] as usize) << 4 | (data[lcup - 2] & 0x0F) as usize;
+        if !(2..=lcup).contains(&scup) {
+            return None;
         }
-        let size = (block.width * block.height) as usize;
-        if block.coefficients.is_empty() {
-            block.coefficients = vec![0i32; size];
-        } else if block.coefficients.len() < size {
-            block.coefficients.resize(size, 0i32);
-        }
-
-        // We iterate through "stripes" (4 rows high).
-        for y_stripe in (0..self.height).step_by(self.stripe_height) {
-            for x in 0..self.width {
-                // Decode quad at (x, y_stripe)
-                self.decode_quad(x, y_stripe, block)?;
-            }
-        }
-
-        Ok(())
+        let (magsgn, mel_vlc) = data.split_at(lcup - scup);
+        Some(Self {
+            mel_decoder: MelDecoder::new(&mel_vlc[..scup - 1]),
+            vlc_reader: VlcReader::new(mel_vlc),
+            magsgn_decoder: MagSgnDecoder::new(magsgn),
+            table,
+            width,
+            height,
+        })
     }
 
-    fn decode_quad(&mut self, x: usize, y_base: usize, block: &mut J2kCodeBlock) -> Result<(), ()> {
-        // 1. Calculate Context (sigma neighbors)
-        // Context is based on significance of neighboring samples
-        // For HTJ2K, context is typically 0 or 1 based on neighbor significance
-        let context = self.calculate_context(x, y_base, block);
-
-        // 2. MEL Decoding (is the quad significant?)
-        // MEL decodes runs of insignificant quads.
-        // We need to ask MEL: "Are we in a run of zeros?"
-
-        // Logic: if mel.run > 0 -> insignificant.
-        // If mel.run == 0 -> we decode next MEL symbol.
-        // If symbol is 0 -> Run of length 2^k -> insignificant.
-        // If symbol is 1 -> Significant quad.
-
-        let is_significant = self.mel_decoder.decode();
-
-        if is_significant {
-            // 3. VLC Decoding
-            // VLC codewords are interleaved with MEL in the same bitstream.
-            // We peek ahead to decode the VLC codeword, then consume the bits.
-
-            // VLC Decoding
-            // MEL and VLC share the same bitstream (read backwards from end of packet).
-            // Use peek to read VLC codeword without consuming bits yet.
-            let peek = self.mel_decoder.peek_bits(16);
-            let (rho, _u_off, _e_k, bits_consumed) = vlc::decode_vlc(peek, context);
+    /// Decodes the cleanup pass of the code-block into quantized coefficients.
+    ///
+    /// Quads of 2x2 samples are scanned in pairs along each line-pair. A quad whose
+    /// neighbours are all insignificant first reads a MEL event telling whether it has
+    /// significant samples; otherwise, and when it has, a CxtVLC codeword gives its
+    /// significance pattern. The U-VLC codes of the pair then bound the magnitude
+    /// exponents from which the MagSgn bits of each significant sample are read.
+    pub fn decode_block(&mut self, block: &mut J2kCodeBlock) -> Result<(), ()> {
+        let (width, height) = (self.width, self.height);
+        block.width = width as u32;
+        block.height = height as u32;
+        block.coefficients = vec![0i32; width * height];
+
+        // Magnitude exponents of the last row of the previous line-pair, with a column
+        // of padding on the left and two on the right.
+        let mut above = vec![0u8; width + 4];
+        let quads_wide = width.div_ceil(2);
+        for y in (0..height).step_by(2) {
+            let initial = y == 0;
+            let mut below = vec![0u8; width + 4];
+            let mut left_rho = 0u8;
+            for pair in (0..quads_wide).step_by(2) {
+                let quads = if pair + 1 < quads_wide { 2 } else { 1 };
+                let mut rho = [0u8; 2];
+                let mut u_off = [false; 2];
+                let mut e_k = [0u8; 2];
+                let mut e_1 = [0u8; 2];
+                for i in 0..quads {
+                    let x = 2 * (pair + i);
+                    let c_q = if initial {
+                        (left_rho & 1 | left_rho >> 1 & 1)
+                            | (left_rho >> 2 & 1) << 1
+                            | (left_rho >> 3 & 1) << 2
+                    } else {
+                        let north = above[x] | above[x + 1] != 0;
+                        let west = left_rho & 0b1100 != 0;
+                        let east = above[x + 2] | above[x + 3] != 0;
+                        north as u8 | (west as u8) << 1 | (east as u8) << 2
+                    };
+                    if c_q != 0 || self.mel_decoder.decode() {
+                        let codeword = self
+                            .table
+                            .decode(initial, c_q, self.vlc_reader.peek())
+                            .ok_or(())?;
+                        self.vlc_reader.advance(codeword.len as u32);
+                        rho[i] = codeword.rho;
+                        u_off[i] = codeword.u_off != 0;
+                        e_k[i] = codeword.e_k;
+                        e_1[i] = codeword.e_1;
+                    }
+                    left_rho = rho[i];
+                }
 
-            // Consume the VLC bits by reading raw bits (bypass MEL state machine)
-            // since VLC codewords are encoded as raw bits in the shared stream
-            for _ in 0..bits_consumed {
-                let _ = self.mel_decoder.read_raw_bit();
+                let mel = initial && u_off == [true, true] && self.mel_decoder.decode();
+                let u = vlc::decode_uvlc(&mut self.vlc_reader, u_off, initial, mel);
+
+                for i in 0..quads {
+                    let x = 2 * (pair + i);
+                    // κ is 1 except below significant exponents for quads with more than
+                    // one significant sample.
+                    let kappa = if initial || rho[i].count_ones() < 2 {
+                        1
+                    } else {
+                        let e_max = above[x..x + 4].iter().copied().max().unwrap_or(0);
+                        (e_max as u32).saturating_sub(1).max(1)
+                    };
+                    let u_q = u[i] + kappa;
+                    for (n, &(dx, dy)) in QUAD_SAMPLES.iter().enumerate() {
+                        if rho[i] >> n & 1 == 0 {
+                            continue;
+                        }
+                        let m = u_q
+                            .checked_sub((e_k[i] >> n & 1) as u32)
+                            .filter(|&m| m <= 30)
+                            .ok_or(())?;
+                        let v = self.magsgn_decoder.read_bits(m as u8)
+                            | ((e_1[i] >> n & 1) as u32) << m;
+                        let magnitude = (v >> 1) + 1;
+                        let (px, py) = (x + dx, y + dy);
+                        if px < width && py < height {
+                            block.coefficients[py * width + px] = if v & 1 == 1 {
+                                -(magnitude as i32)
+                            } else {
+                                magnitude as i32
+                            };
+                            if dy == 1 {
+                                // E = bit length of 2μ - 1.
+                                below[px + 1] = (32 - (v | 1).leading_zeros()) as u8;
+                            }
+                        }
+                    }
+                }
             }
-
-            // Advance MEL/VLC stream by `bits_consumed`.
-
-            // 4. Update Block State (Significance)
-            // Apply `rho` pattern to the 2x2 quad at (x, y_base).
-            // rho is 4 bits: (0,0), (1,0), (0,1), (1,1) -> LSB to MSB?
-
-            self.apply_rho(x, y_base, rho, block);
-
-            // 5. Magnitude Refinement / Sign (MagSgn)
-            // For each significant pixel in rho, read sign bit and refinement bits from MagSgn stream.
-            self.process_magsgn(x, y_base, rho, block)?;
-        } else {
-            // insignificant quad
+            above = below;
         }
 
         Ok(())
     }
+}
 
-    /// Calculate context based on neighbor significance
-    fn calculate_context(&self, x: usize, y_base: usize, block: &J2kCodeBlock) -> u8 {
-        // Context is 0 if no significant neighbors, 1 if there are significant neighbors
-        // This is a simplified version - full HTJ2K context calculation considers
-        // the specific pattern of neighbor significance (H, V, D neighbors)
-        let width = block.width as usize;
-        let height = block.height as usize;
-
-        // Check neighbors: left, above, above-left, above-right
-        let neighbors = [
-            if x > 0 { Some((x - 1, y_base)) } else { None },
-            if y_base > 0 { Some((x, y_base - 1)) } else { None },
-            if x > 0 && y_base > 0 { Some((x - 1, y_base - 1)) } else { None },
-            if x + 1 < width && y_base > 0 { Some((x + 1, y_base - 1)) } else { None },
-        ];
-
-        for neighbor in neighbors.iter().flatten() {
-            let (nx, ny) = *neighbor;
-            if nx < width && ny < height {
-                let idx = ny * width + nx;
-                if idx < block.coefficients.len() && block.coefficients[idx] != 0 {
-                    return 1; // Has significant neighbor
-                }
-            }
-        }
-        0 // No significant neighbors
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jpeg2000::ht_block_coder::vlc::CxtVlcCodeword;
+
+    #[test]
+    fn test_insignificant_block() {
+        // Scup = 2: an empty MagSgn segment, and MEL bits 1111 coding runs of zero
+        // events, so no quad is significant.
+        let data = [0xF2, 0x00];
+        let mut coder = HTBlockCoder::new(&data, 4, 4).unwrap();
+        let mut block = J2kCodeBlock::default();
+        coder.decode_block(&mut block).unwrap();
+        assert_eq!(block.coefficients, vec![0; 16]);
     }
 
-    fn apply_rho(&mut self, x: usize, y_base: usize, rho: u8, block: &mut J2kCodeBlock) {
-        // Apply significance mapping
-        // pixel order: (0,0), (1,0), (0,1), (1,1) usually (raster within quad)
-        // rho bits: bit 0 = (x, y), bit 1 = (x+1, y), bit 2 = (x, y+1), bit 3 = (x+1, y+1)
-        let width = block.width as usize;
-        let coords = [(x, y_base), (x + 1, y_base), (x, y_base + 1), (x + 1, y_base + 1)];
-
-        for (i, &(px, py)) in coords.iter().enumerate() {
-            if (rho >> i) & 1 != 0 {
-                // Pixel is significant - mark it (coefficient will be set in process_magsgn)
-                if px < width && py < block.height as usize {
-                    let idx = py * width + px;
-                    if idx < block.coefficients.len() {
-                        // Coefficient will be set to proper value in process_magsgn
-                        // For now, just ensure it's non-zero to mark significance
-                        // (actual value will be overwritten)
-                    }
-                }
-            }
-        }
+    #[test]
+    fn test_scup_out_of_range() {
+        assert!(HTBlockCoder::new(&[0x03, 0x00], 4, 4).is_none());
+        assert!(HTBlockCoder::new(&[0x00], 4, 4).is_none());
     }
 
-    fn process_magsgn(
-        &mut self,
-        x: usize,
-        y_base: usize,
-        rho: u8,
-        block: &mut J2kCodeBlock,
-    ) -> Result<(), ()> {
-        // Process each pixel in the quad in raster order
-        let coords = [(0, 0), (1, 0), (0, 1), (1, 1)];
-        let width = block.width as usize;
-
-        for (i, &(dx, dy)) in coords.iter().enumerate() {
-            let is_sig_in_quad = (rho >> i) & 1 == 1;
-
-            if is_sig_in_quad {
-                let px = x + dx;
-                let py = y_base + dy;
-
-                if px >= width || py >= block.height as usize {
-                    continue;
-                }
-
-                let idx = py * width + px;
-                if idx >= block.coefficients.len() {
-                    continue;
-                }
-
-                // If pixel was NOT significant before this pass (HT Cleanup), we read SIGN.
-                // In HTJ2K Cleanup pass, we process *newly* significant pixels.
-                // Standard says: "If sample becomes significant ... read sign bit."
-
-                // Check if already significant (coefficient non-zero means already significant)
-                let is_newly_significant = block.coefficients[idx] == 0;
-
-                if is_newly_significant {
-                    // Read Sign Bit
-                    let sign_bit = self.magsgn_decoder.read_bit().ok_or(())?;
-                    // 0 = Positive, 1 = Negative
-                    // Store sign: we'll set the coefficient sign based on this
-
-                    // For newly significant pixels, start with magnitude 1 (will be refined)
-                    block.coefficients[idx] = if sign_bit == 0 { 1 } else { -1 };
-                } else {
-                    // Already significant - read magnitude refinement bit
-                    // This refines the existing magnitude
-                    if let Some(mag_bit) = self.magsgn_decoder.read_bit() {
-                        let current_mag = block.coefficients[idx].abs();
-                        // Add the refinement bit to the magnitude
-                        // Simplified: shift left and add bit
-                        block.coefficients[idx] = if block.coefficients[idx] >= 0 {
-                            (current_mag << 1) | (mag_bit as i32)
-                        } else {
-                            -((current_mag << 1) | (mag_bit as i32))
-                        };
-                    }
-                }
-            }
-        }
-        Ok(())
+    #[test]
+    fn test_single_significant_sample() {
+        // Context 0 codeword "1" makes only the top-left sample significant, without a
+        // U-VLC offset, so U = κ = 1 and one MagSgn bit gives its sign.
+        let table = CxtVlcTable::new(
+            &[CxtVlcCodeword {
+                c_q: 0,
+                rho: 1,
+                u_off: 0,
+                e_k: 0,
+                e_1: 0,
+                cwd: 1,
+                len: 1,
+            }],
+            &[],
+        );
+        // MagSgn: 0x01 (sign bit 1). MEL: 0x12 starts with a 0 at k=0, a one event.
+        // VLC: upper nibble of 0x12 is 0001, the codeword "1". Scup = 0x002.
+        let data = [0x01, 0x12, 0x00];
+        let mut coder = HTBlockCoder::with_table(&data, 2, 2, &table).unwrap();
+        let mut block = J2kCodeBlock::default();
+        coder.decode_block(&mut block).unwrap();
+        assert_eq!(block.coefficients, vec![-1, 0, 0, 0]);
     }
 }
@@ -5,6 +5,8 @@ pub struct MagSgnDecoder<'a> {
     pos: usize,
     bits_buffer: u8,
     bits_left: u8,
+    /// Whether the last byte read was 0xFF, so the next one has a stuffed MSB.
+    unstuff: bool,
 }
 
 impl<'a> MagSgnDecoder<'a> {
@@ -14,39 +16,47 @@ impl<'a> MagSgnDecoder<'a> {
             pos: 0,
             bits_buffer: 0,
             bits_left: 0,
+            unstuff: false,
         }
     }
 
     /// Read bits from the MagSgn stream.
-    /// The MagSgn stream is typically read from the *beginning* of the buffer,
-    /// while MEL/VLC might be read from the end or reverse?
-    /// Standard says: "The MagSgn bitstream ... grows forward from the start of the buffer."
-    /// "The MEL bitstream ... grows backward from the end of the buffer."
-    /// This splitting logic belongs in the parent coder. This struct just reads forward.
-    pub fn read_bit(&mut self) -> Option<u8> {
+    /// The MagSgn bitstream grows forward from the start of the cleanup segment and is
+    /// read LSB first; a byte following 0xFF carries only 7 bits, its MSB being stuffed.
+    /// Past its end it reads as 0xFF.
+    pub fn read_bit(&mut self) -> u8 {
         if self.bits_left == 0 {
-            if self.pos >= self.data.len() {
-                return None; // EOF
-            }
-            self.bits_buffer = self.data[self.pos];
+            let byte = self.data.get(self.pos).copied().unwrap_or(0xFF);
             self.pos += 1;
-
-            // Byte stuffing logic if needed (usually handled by packet parser splitting)
-            // If raw buffer provided here, assume clean.
-
-            self.bits_left = 8;
+            self.bits_left = if self.unstuff { 7 } else { 8 };
+            self.unstuff = byte == 0xFF;
+            self.bits_buffer = byte;
         }
-
-        let bit = (self.bits_buffer >> (self.bits_left - 1)) & 1;
+        let bit = self.bits_buffer & 1;
+        self.bits_buffer >>= 1;
         self.bits_left -= 1;
-        Some(bit)
+        bit
     }
 
-    pub fn read_bits(&mut self, count: u8) -> Option<u32> {
-        let mut res = 0;
-        for _ in 0..count {
-            res = (res << 1) | (self.read_bit()? as u32);
-        }
-        Some(res)
+    /// Reads `count` bits, the first one read being the LSB of the result.
+    pub fn read_bits(&mut self, count: u8) -> u32 {
+        (0..count).fold(0, |value, i| value | (self.read_bit() as u32) << i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_magsgn_unstuffing() {
+        // LSB first; the stuffed MSB of the byte after 0xFF is skipped.
+        let data = [0xFF, 0x05];
+        let mut magsgn = MagSgnDecoder::new(&data);
+        assert_eq!(magsgn.read_bits(8), 0xFF);
+        assert_eq!(magsgn.read_bits(7), 0x05);
+        // Past the end the stream reads as 0xFF, again with a stuffed MSB.
+        assert_eq!(magsgn.read_bits(8), 0xFF);
+        assert_eq!(magsgn.read_bits(8), 0xFF);
     }
 }
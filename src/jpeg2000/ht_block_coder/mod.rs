@@ -2,4 +2,5 @@ pub mod coder;
 pub mod encoder;
 pub mod mag_sgn;
 pub mod mel;
+pub mod refinement;
 pub mod vlc;
@@ -0,0 +1,201 @@
+use crate::jpeg2000::image::J2kCodeBlock;
+
+/// Forward reader of the SigProp bitstream, which grows from the start of the
+/// refinement segment. Bits are read LSB first; a byte following 0xFF carries only 7
+/// bits. Past its end it reads as zeros.
+struct SigPropReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits_buffer: u8,
+    bits_left: u8,
+    unstuff: bool,
+}
+
+impl<'a> SigPropReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            bits_buffer: 0,
+            bits_left: 0,
+            unstuff: false,
+        }
+    }
+
+    fn read_bit(&mut self) -> u8 {
+        if self.bits_left == 0 {
+            let byte = self.data.get(self.pos).copied().unwrap_or(0);
+            self.pos += 1;
+            self.bits_left = if self.unstuff { 7 } else { 8 };
+            self.unstuff = byte == 0xFF;
+            self.bits_buffer = byte;
+        }
+        let bit = self.bits_buffer & 1;
+        self.bits_buffer >>= 1;
+        self.bits_left -= 1;
+        bit
+    }
+}
+
+/// Backward reader of the MagRef bitstream, which grows from the end of the refinement
+/// segment towards its start. Bits are read LSB first; a byte whose 7 LSBs are all set
+/// carries only 7 bits when it is the last byte or follows (in reading order) one above
+/// 0x8F. Past the start of the segment it reads as zeros.
+struct MagRefReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits_buffer: u8,
+    bits_left: u8,
+    unstuff: bool,
+}
+
+impl<'a> MagRefReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: data.len(),
+            bits_buffer: 0,
+            bits_left: 0,
+            unstuff: true,
+        }
+    }
+
+    fn read_bit(&mut self) -> u8 {
+        if self.bits_left == 0 {
+            let byte = if self.pos > 0 {
+                self.pos -= 1;
+                self.data[self.pos]
+            } else {
+                0
+            };
+            self.bits_left = if self.unstuff && byte & 0x7F == 0x7F {
+                7
+            } else {
+                8
+            };
+            self.unstuff = byte > 0x8F;
+            self.bits_buffer = byte;
+        }
+        let bit = self.bits_buffer & 1;
+        self.bits_buffer >>= 1;
+        self.bits_left -= 1;
+        bit
+    }
+}
+
+/// Whether any of the eight neighbours of sample (`x`, `y`) is significant.
+fn has_significant_neighbour(
+    significant: &[bool],
+    width: usize,
+    height: usize,
+    x: usize,
+    y: usize,
+) -> bool {
+    let xs = x.saturating_sub(1)..=(x + 1).min(width - 1);
+    xs.flat_map(|nx| (y.saturating_sub(1)..=(y + 1).min(height - 1)).map(move |ny| (nx, ny)))
+        .any(|(nx, ny)| (nx, ny) != (x, y) && significant[ny * width + nx])
+}
+
+/// Decodes the HT refinement passes of a code-block from its refinement `segment`:
+/// the SigProp pass and, when `passes` is 2, the MagRef pass. They refine the
+/// coefficients left in `block` by its cleanup pass with one more bit-plane.
+///
+/// Both passes scan stripes of 4 rows column by column. SigProp visits every
+/// insignificant sample with a significant neighbour, in groups of 4 columns: one bit
+/// per visited sample tells whether it becomes significant, and the sign bits of the
+/// group's new significant samples follow. MagRef reads one magnitude bit for every
+/// sample made significant by the cleanup pass.
+pub fn decode_refinement(block: &mut J2kCodeBlock, segment: &[u8], passes: u8) {
+    let (width, height) = (block.width as usize, block.height as usize);
+    if width == 0 || height == 0 || block.coefficients.len() < width * height {
+        return;
+    }
+    let cleanup: Vec<bool> = block.coefficients.iter().map(|&c| c != 0).collect();
+    let mut significant = cleanup.clone();
+    for coefficient in &mut block.coefficients {
+        *coefficient *= 2;
+    }
+
+    let mut sigprop = SigPropReader::new(segment);
+    for y0 in (0..height).step_by(4) {
+        let rows = y0..(y0 + 4).min(height);
+        for x0 in (0..width).step_by(4) {
+            let mut new_significant = Vec::new();
+            for x in x0..(x0 + 4).min(width) {
+                for y in rows.clone() {
+                    let idx = y * width + x;
+                    if significant[idx]
+                        || !has_significant_neighbour(&significant, width, height, x, y)
+                    {
+                        continue;
+                    }
+                    if sigprop.read_bit() == 1 {
+                        significant[idx] = true;
+                        new_significant.push(idx);
+                    }
+                }
+            }
+            for idx in new_significant {
+                block.coefficients[idx] = if sigprop.read_bit() == 1 { -1 } else { 1 };
+            }
+        }
+    }
+
+    if passes < 2 {
+        return;
+    }
+    let mut magref = MagRefReader::new(segment);
+    for y0 in (0..height).step_by(4) {
+        for x in 0..width {
+            for y in y0..(y0 + 4).min(height) {
+                let idx = y * width + x;
+                if cleanup[idx] {
+                    let bit = magref.read_bit() as i32;
+                    let coefficient = &mut block.coefficients[idx];
+                    *coefficient += if *coefficient < 0 { -bit } else { bit };
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cleanup_block() -> J2kCodeBlock {
+        J2kCodeBlock {
+            width: 2,
+            height: 2,
+            coefficients: vec![-3, 0, 0, 0],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_sigprop_and_magref() {
+        // SigProp, LSB first: (0,1) becomes significant, (1,0) and (1,1) do not, then
+        // its sign bit 1: 1 0 0 1 = 0x09. MagRef, from the last byte: 1.
+        let segment = [0x09, 0x01];
+        let mut block = cleanup_block();
+        decode_refinement(&mut block, &segment, 2);
+        assert_eq!(block.coefficients, vec![-7, 0, -1, 0]);
+    }
+
+    #[test]
+    fn test_sigprop_only() {
+        let segment = [0x09, 0x01];
+        let mut block = cleanup_block();
+        decode_refinement(&mut block, &segment, 1);
+        assert_eq!(block.coefficients, vec![-6, 0, -1, 0]);
+    }
+
+    #[test]
+    fn test_magref_unstuffing() {
+        // The last byte 0x7F carries 7 bits, so the 8th MagRef bit is the LSB of 0x00.
+        let segment = [0x00, 0x7F];
+        let mut magref = MagRefReader::new(&segment);
+        let bits: Vec<u8> = (0..8).map(|_| magref.read_bit()).collect();
+        assert_eq!(bits, [1, 1, 1, 1, 1, 1, 1, 0]);
+    }
+}
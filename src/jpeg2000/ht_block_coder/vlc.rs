@@ -1,87 +1,189 @@
-/// Variable Length Coding (VLC) tables and logic for HTJ2K.
-/// Based on ISO/IEC 15444-15 Table 6 and Table 8.
-/// Decodes a VLC code word into a 4-pixel quad significance pattern (rho),
-/// an embedded context (emb_k) correction, and context for the next quad.
+//! Variable Length Coding (VLC) of the HT cleanup pass (ISO/IEC 15444-15).
+//!
+//! Each quad of a code-block carries a CxtVLC codeword giving its significance pattern
+//! and exponent bounds, and each pair of quads a U-VLC code for the magnitude exponent
+//! bounds of both.
+
+use std::sync::OnceLock;
+
+/// Backward reader of the VLC bitstream of an HT cleanup segment.
 ///
-/// Arguments:
-/// - `peek`: 16 bits of lookahead from the bitstream.
-/// - `context`: The current context (0 or 1) derived from neighbors.
+/// The VLC bits are read LSB first from the end of the MEL/VLC segment towards its
+/// start, beginning with the upper nibble of the byte before the last (the last byte and
+/// the lower nibble hold Scup). A byte read after one above 0x8F whose 7 LSBs are all
+/// set carries only 7 bits. Past the start of the segment the stream reads as zeros.
+pub struct VlcReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u64,
+    count: u32,
+    /// Whether the last byte read was above 0x8F, so the next one may be stuffed.
+    unstuff: bool,
+}
+
+impl<'a> VlcReader<'a> {
+    /// `segment` is the whole MEL/VLC segment, at least 2 bytes long.
+    pub fn new(segment: &'a [u8]) -> Self {
+        let last = segment.len() - 2;
+        let d = segment[last];
+        let count = if (d >> 4) & 7 == 7 { 3 } else { 4 };
+        Self {
+            data: &segment[..last],
+            pos: last,
+            bits: (d >> 4) as u64 & ((1 << count) - 1),
+            count,
+            unstuff: (d | 0x0F) > 0x8F,
+        }
+    }
+
+    fn fill(&mut self) {
+        while self.count <= 32 {
+            let d = if self.pos > 0 {
+                self.pos -= 1;
+                self.data[self.pos]
+            } else {
+                0
+            };
+            let n = if self.unstuff && d & 0x7F == 0x7F {
+                7
+            } else {
+                8
+            };
+            self.bits |= (d as u64 & ((1 << n) - 1)) << self.count;
+            self.count += n;
+            self.unstuff = d > 0x8F;
+        }
+    }
+
+    /// Returns the next 32 bits without consuming them, the first one in the LSB.
+    pub fn peek(&mut self) -> u32 {
+        self.fill();
+        self.bits as u32
+    }
+
+    /// Consumes `n` bits (at most 32).
+    pub fn advance(&mut self, n: u32) {
+        self.fill();
+        self.bits >>= n;
+        self.count -= n;
+    }
+
+    /// Reads `n` bits (at most 31), the first one read being the LSB of the result.
+    pub fn read(&mut self, n: u32) -> u32 {
+        let value = self.peek() & ((1 << n) - 1);
+        self.advance(n);
+        value
+    }
+}
+
+/// A CxtVLC codeword (Annex C of Part 15): in quad context `c_q`, the `len` bits of
+/// `cwd`, read LSB first, code the significance pattern `rho` of the quad, whether a
+/// U-VLC code follows for it (`u_off`) and its exponent patterns `e_k` and `e_1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CxtVlcCodeword {
+    pub c_q: u8,
+    pub rho: u8,
+    pub u_off: u8,
+    pub e_k: u8,
+    pub e_1: u8,
+    pub cwd: u8,
+    pub len: u8,
+}
+
+/// CxtVLC codewords of the quads in the initial line-pair of a code-block.
 ///
-/// Returns: `(rho, u_off, e_k, bits_consumed)`
-/// - `rho`: 4-bit significance pattern (0..15).
-/// - `u_off`: u-value offset (used for magnitude exponent prediction).
-/// - `e_k`: exponent prediction calculation helper? (Actually "emb_k" logic).
-/// - `bits_consumed`: Number of bits used by the VLC code.
-pub fn decode_vlc(peek: u16, context: u8) -> (u8, u8, u8, u8) {
-    // VLC decoding based on Table 8 of ISO 15444-15
-    // Format: (Codeword, Mask, Rho, U_off, E_k)
-    // We match the prefix of 'peek'.
-
-    // Note: The standard defines 2 contexts for VLC:
-    // Context 0 (Initial) and Context 1 (Adapted locally?)
-
-    // Simplified Table matching logic.
-    // Ideally this should be a lookup table.
-    // Since we are implementing a subset/skeleton first, we implement the logic for common cases.
-
-    if context == 0 {
-        // Context 0 Table
-        // 0... -> 0000 (rho=0), len=1
-        if peek & 0x8000 == 0 {
-            (0, 0, 0, 1)
-        } else {
-            // 10... -> 0001/0010/0100/1000 (rho=1/2/4/8), len=2?
-            // Standard says:
-            // 100 -> 1000 (rho=8), len=3
-            // 101 -> 0100 (rho=4), len=3
-            // 110 -> 0010 (rho=2), len=3
-            // 1110 -> 0001 (rho=1), len=4
-            // ...
-
-            // Let's implement a small switch for the prompt.
-            // Leading bits:
-            let top3 = (peek >> 13) & 0x7;
-            match top3 {
-                0..=3 => (0, 0, 0, 1), // 0xxx -> 0000
-                0b100 => (8, 0, 0, 3), // 100
-                0b101 => (4, 0, 0, 3), // 101
-                0b110 => (2, 0, 0, 3), // 110
-                0b111 => {
-                    // 111...
-                    if peek & 0x1000 == 0 {
-                        // 1110
-                        (1, 0, 0, 4)
-                    } else {
-                        // 1111...
-                        // Fallback/Extenstion
-                        (15, 1, 1, 5) // Dummy fallback for fully significant
-                    }
+/// The codewords of Annex C are not transcribed yet; until they are, quads coded with
+/// CxtVLC fail to decode, and the JPEG 2000 decoder reports such code-blocks as
+/// [`EncodingNotSupported`](crate::JpeglsError::EncodingNotSupported).
+pub const CXT_VLC_INITIAL: &[CxtVlcCodeword] = &[];
+
+/// CxtVLC codewords of the quads in the other line-pairs, see [`CXT_VLC_INITIAL`].
+pub const CXT_VLC_NON_INITIAL: &[CxtVlcCodeword] = &[];
+
+/// Lookup of CxtVLC codewords by context and the next 7 VLC bits (no codeword is
+/// longer), for the initial and the non-initial line-pairs.
+pub struct CxtVlcTable {
+    entries: Vec<Option<CxtVlcCodeword>>,
+}
+
+impl CxtVlcTable {
+    pub fn new(initial: &[CxtVlcCodeword], non_initial: &[CxtVlcCodeword]) -> Self {
+        let mut entries = vec![None; 2 * 8 * 128];
+        for (table, codewords) in [initial, non_initial].into_iter().enumerate() {
+            for codeword in codewords {
+                let len = codeword.len.min(7) as usize;
+                let base = (table * 8 + (codeword.c_q & 7) as usize) * 128;
+                for high in 0..1 << (7 - len) {
+                    entries[base + (codeword.cwd as usize | high << len)] = Some(*codeword);
                 }
-                _ => (0, 0, 0, 1), // Should not reach
             }
         }
-    } else {
-        // Context 1 Table (different probabilities)
-        // Context 1 uses similar structure but with different codeword assignments
-        // For now, use same logic as Context 0 as a reasonable approximation
-        // Full implementation would use the actual Context 1 table from the standard
-        if peek & 0x8000 == 0 {
-            (0, 0, 0, 1)
-        } else {
-            let top3 = (peek >> 13) & 0x7;
-            match top3 {
-                0..=3 => (0, 0, 0, 1), // 0xxx -> 0000
-                0b100 => (8, 0, 0, 3), // 100
-                0b101 => (4, 0, 0, 3), // 101
-                0b110 => (2, 0, 0, 3), // 110
-                0b111 => {
-                    if peek & 0x1000 == 0 {
-                        (1, 0, 0, 4) // 1110
-                    } else {
-                        (15, 1, 1, 5) // 1111... (fallback)
-                    }
+        Self { entries }
+    }
+
+    /// The table of Annex C.
+    pub fn standard() -> &'static Self {
+        static TABLE: OnceLock<CxtVlcTable> = OnceLock::new();
+        TABLE.get_or_init(|| Self::new(CXT_VLC_INITIAL, CXT_VLC_NON_INITIAL))
+    }
+
+    /// Decodes the codeword in the LSBs of `bits` for a quad in context `c_q`.
+    pub fn decode(&self, initial: bool, c_q: u8, bits: u32) -> Option<CxtVlcCodeword> {
+        let table = if initial { 0 } else { 1 };
+        self.entries[(table * 8 + (c_q & 7) as usize) * 128 + (bits & 0x7F) as usize]
+    }
+}
+
+/// U-VLC prefixes "1", "01", "001" and "000" read LSB first, indexed by the next 3
+/// bits: (prefix length, suffix length, u_pfx).
+const UVLC_PREFIX: [(u32, u32, u32); 8] = [
+    (3, 5, 5),
+    (1, 0, 1),
+    (2, 0, 2),
+    (1, 0, 1),
+    (3, 1, 3),
+    (1, 0, 1),
+    (2, 0, 2),
+    (1, 0, 1),
+];
+
+fn uvlc_prefix(reader: &mut VlcReader) -> (u32, u32) {
+    let (len, suffix_len, u_pfx) = UVLC_PREFIX[(reader.peek() & 7) as usize];
+    reader.advance(len);
+    (u_pfx, suffix_len)
+}
+
+/// Decodes the U-VLC codes of a quad pair whose quads have the offset flags `u_off`,
+/// returning u for both quads (0 without an offset); the caller adds κ.
+///
+/// In the initial line-pair a pair with both offsets is preceded by a MEL event `mel`:
+/// a one adds 2 to both values, while after a zero a first prefix of "001" or "000"
+/// leaves only a single bit choosing 1 or 2 for the second quad.
+pub fn decode_uvlc(reader: &mut VlcReader, u_off: [bool; 2], initial: bool, mel: bool) -> [u32; 2] {
+    match u_off {
+        [false, false] => [0, 0],
+        [true, false] => {
+            let (u_pfx, suffix_len) = uvlc_prefix(reader);
+            [u_pfx + reader.read(suffix_len), 0]
+        }
+        [false, true] => {
+            let (u_pfx, suffix_len) = uvlc_prefix(reader);
+            [0, u_pfx + reader.read(suffix_len)]
+        }
+        [true, true] => {
+            let (u_pfx0, suffix_len0) = uvlc_prefix(reader);
+            if initial && !mel && u_pfx0 > 2 {
+                let u1 = 1 + reader.read(1);
+                [u_pfx0 + reader.read(suffix_len0), u1]
+            } else {
+                let (u_pfx1, suffix_len1) = uvlc_prefix(reader);
+                let u0 = u_pfx0 + reader.read(suffix_len0);
+                let u1 = u_pfx1 + reader.read(suffix_len1);
+                if initial && mel {
+                    [u0 + 2, u1 + 2]
+                } else {
+                    [u0, u1]
                 }
-                _ => (0, 0, 0, 1),
             }
         }
     }
@@ -94,9 +196,9 @@ pub struct VlcCodeword {
 }
 
 /// Encode a significance pattern (rho) to a VLC codeword
-/// This is the inverse of decode_vlc
+/// (placeholder codewords, not those of Annex C)
 pub fn encode_vlc(rho: u8, context: u8) -> VlcCodeword {
-    // Map rho patterns to VLC codewords (inverse of decode table)
+    // Map rho patterns to VLC codewords
     // Context 0 and Context 1 use same structure for simplicity
     let _ = context; // Both contexts use similar encoding for now
 
@@ -174,3 +276,41 @@ pub fn encode_vlc(rho: u8, context: u8) -> VlcCodeword {
 }
 
 // In a real optimized decoder, these would be 256-entry or 1024-entry lookup tables.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vlc_reader_unstuffing() {
+        // Upper nibble 0x9 of the byte before the last comes first; 0x9F makes the
+        // next byte 0x7F carry only 7 bits.
+        let segment = [0x7F, 0x9F, 0x00];
+        let mut reader = VlcReader::new(&segment);
+        assert_eq!(reader.read(4), 0x9);
+        assert_eq!(reader.read(7), 0x7F);
+        assert_eq!(reader.read(8), 0);
+    }
+
+    #[test]
+    fn test_decode_uvlc() {
+        // Bits, LSB first: "1" (u_pfx 1) then "01" (u_pfx 2).
+        let segment = [0b0000_0101, 0x00, 0x00];
+        let mut reader = VlcReader::new(&segment);
+        reader.advance(4);
+        assert_eq!(decode_uvlc(&mut reader, [true, true], false, false), [1, 2]);
+
+        // Initial line-pair, MEL event 0: "001" (u_pfx 3), the 1-bit second value, then
+        // the suffix bit of the first.
+        let segment = [0b0000_1100, 0x00, 0x00];
+        let mut reader = VlcReader::new(&segment);
+        reader.advance(4);
+        assert_eq!(decode_uvlc(&mut reader, [true, true], true, false), [3, 2]);
+
+        // Initial line-pair, MEL event 1: both values gain 2.
+        let segment = [0b0000_0011, 0x00, 0x00];
+        let mut reader = VlcReader::new(&segment);
+        reader.advance(4);
+        assert_eq!(decode_uvlc(&mut reader, [true, true], true, true), [3, 3]);
+    }
+}
@@ -1,9 +1,14 @@
+use super::jp2::{Jp2ChannelDefinition, Jp2ComponentMapping, Jp2Palette, Jp2UuidBox};
+use crate::{ComponentInfo, FrameInfo, JpeglsError, OutputLayout};
+
 /// Top-level J2K/HTJ2K Image structure containing metadata and tile data.
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct J2kImage {
-    /// Total width of the image in pixels.
+    /// Width of the reference grid (Xsiz); the image area starts at `x_origin`, see
+    /// [`size`](Self::size).
     pub width: u32,
-    /// Total height of the image in pixels.
+    /// Height of the reference grid (Ysiz).
     pub height: u32,
     /// Width of an individual tile. If equal to `width`, the image has only one tile.
     pub tile_width: u32,
@@ -27,10 +32,36 @@ pub struct J2kImage {
     pub cap: Option<J2kCap>,
     /// List of tiles that make up the image.
     pub tiles: Vec<J2kTile>,
-    /// Optional Region of Interest information.
-    pub roi: Option<J2kRoi>,
+    /// Regions of interest (RGN) from the main header, at most one per component.
+    pub roi: Vec<J2kRoi>,
+    /// Component coding styles (COC) from the main header, at most one per component.
+    pub coc: Vec<J2kCoc>,
+    /// Progression order changes (POC) from the main header.
+    pub poc: Vec<J2kPoc>,
+    /// Tile-part lengths (TLM) from the main header, in codestream order.
+    pub tile_part_lengths: Vec<J2kTilePartLength>,
+    /// Packet lengths (PLM) from the main header, one list per tile-part in codestream
+    /// order.
+    pub packet_lengths: Vec<Vec<u32>>,
+    /// Packed packet headers (PPM) from the main header, one run per tile-part in
+    /// codestream order.
+    pub packed_headers: Vec<Vec<u8>>,
+    /// Component registration (CRG): offsets (Xcrg, Ycrg) of each component's samples in
+    /// units of 1/65536 of its subsampling factors; empty without a CRG segment.
+    pub registration: Vec<(u16, u16)>,
     /// Optional ICC color profile extracted from JP2 container.
     pub icc_profile: Option<Vec<u8>>,
+    /// Palette of an indexed-colour JP2 file, applied to the components that
+    /// `component_mapping` maps through it when the image is reconstructed.
+    pub palette: Option<Jp2Palette>,
+    /// Component mapping of a JP2 file: the reconstructed channels with a palette.
+    pub component_mapping: Vec<Jp2ComponentMapping>,
+    /// Channel definitions of a JP2 file, which mark opacity channels.
+    pub channel_definitions: Vec<Jp2ChannelDefinition>,
+    /// Contents of the XML boxes of a JP2 file, e.g. XMP or GML metadata.
+    pub xml_boxes: Vec<Vec<u8>>,
+    /// UUID boxes of a JP2 file.
+    pub uuid_boxes: Vec<Jp2UuidBox>,
     /// Number of quality layers decoded (for progressive quality).
     pub decoded_layers: u32,
     /// Component information (depth, signedness, subsampling) from SIZ marker.
@@ -38,7 +69,8 @@ pub struct J2kImage {
 }
 
 /// Metadata for a single component from the SIZ marker.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct J2kComponentInfo {
     /// bit depth (e.g. 8, 12, 16)
     pub depth: u8,
@@ -52,15 +84,42 @@ pub struct J2kComponentInfo {
 
 /// A single tile-part or tile within a J2K codestream.
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct J2kTile {
     /// Index of the tile (0-indexed).
     pub index: u32,
     /// Components belonging to this tile.
     pub components: Vec<J2kTileComponent>,
+    /// Progression order changes (POC) from the tile-part headers; they replace the
+    /// main header ones for this tile.
+    pub poc: Vec<J2kPoc>,
+    /// Packet lengths (PLT from the tile-part headers, or PLM) in packet order.
+    pub packet_lengths: Vec<u32>,
+    /// Packed packet headers (PPT from the tile-part headers, or PPM) in packet order.
+    /// When present, the packets in the tile-parts hold only their bodies.
+    pub packed_headers: Vec<u8>,
+    /// Regions of interest (RGN) from the tile-part headers; they replace the main
+    /// header ones for their components.
+    pub roi: Vec<J2kRoi>,
+    /// Component coding styles (COC) from the tile-part headers; they replace the main
+    /// header ones for their components.
+    pub coc: Vec<J2kCoc>,
+    /// Tile-parts of the tile read so far, which is the TPsot of the next one.
+    pub tile_parts_read: u8,
+    /// Number of tile-parts of the tile (TNsot), or 0 while no tile-part gave it.
+    pub tile_part_count: u8,
+}
+
+impl J2kTile {
+    /// Whether every tile-part of the tile is read; never when TNsot is not known.
+    pub fn is_complete(&self) -> bool {
+        self.tile_part_count != 0 && self.tile_parts_read >= self.tile_part_count
+    }
 }
 
 /// Component data specific to a single tile.
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct J2kTileComponent {
     /// Index of the component.
     pub component_index: u32,
@@ -72,6 +131,7 @@ pub struct J2kTileComponent {
 
 /// A specific resolution level in the DWT decomposition.
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct J2kResolution {
     /// Decomposition level (0 is the lowest resolution/LL).
     pub level: u8,
@@ -85,6 +145,7 @@ pub struct J2kResolution {
 
 /// A frequency subband within a resolution level.
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct J2kSubband {
     /// Orientation of the subband (LL, HL, LH, HH).
     pub orientation: SubbandOrientation,
@@ -100,6 +161,7 @@ pub struct J2kSubband {
 
 /// Orientation of a wavelet subband.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SubbandOrientation {
     #[default]
     /// Low-Low (base image)
@@ -114,6 +176,7 @@ pub enum SubbandOrientation {
 
 /// A core unit of compressed data in J2K (typically 32x32 or 64x64).
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct J2kCodeBlock {
     /// Compressed bitstream for the code-block.
     pub compressed_data: Vec<u8>,
@@ -131,15 +194,31 @@ pub struct J2kCodeBlock {
     pub coding_passes: u8,
     /// Layer contributions: each entry contains data for a specific quality layer.
     pub layer_data: Vec<Vec<u8>>,
+    /// Lengths of the codeword segments received so far, across all layers.
+    pub segment_lengths: Vec<u32>,
     /// Number of layers that have contributed to this codeblock.
     pub layers_decoded: u8,
     /// Decoded coefficient values (accumulated across layers).
     pub coefficients: Vec<i32>,
     /// Internal state of the entropy coder (significance, visited, etc.)
     pub state: Vec<u8>,
+    /// Block coder the code-block was decoded with.
+    pub block_coder: BlockCoder,
+}
+
+/// Block coder of the code-blocks of a tile-component (ISO/IEC 15444-15, A.3).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BlockCoder {
+    /// The EBCOT block coder of Part 1.
+    #[default]
+    Part1,
+    /// The HT block coder of Part 15.
+    Ht,
 }
 /// Coding Style Default (COD) marker information
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct J2kCod {
     pub coding_style: u8,
     pub progression_order: u8,
@@ -148,6 +227,9 @@ pub struct J2kCod {
     pub decomposition_levels: u8,
     pub codeblock_width_exp: u8,
     pub codeblock_height_exp: u8,
+    /// Code-block style (SPcod): bit 6 selects the HT block coder, and bit 7 with it
+    /// lets every code-block use either coder.
+    pub codeblock_style: u8,
     pub transformation: u8,
     /// Precinct sizes if defined (Scod bit 0 set).
     /// One byte per resolution level (PPx + PPy<<4).
@@ -156,34 +238,428 @@ pub struct J2kCod {
 
 /// Quantization Default (QCD) marker information
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct J2kQcd {
     pub quant_style: u8,
     pub step_sizes: Vec<u16>,
 }
 
+/// One progression of a Progression Order Change (POC) marker segment. The end values
+/// are exclusive.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct J2kPoc {
+    pub resolution_start: u8,
+    pub component_start: u16,
+    pub layer_end: u16,
+    pub resolution_end: u8,
+    pub component_end: u16,
+    pub progression_order: u8,
+}
+
+/// One entry of a Tile-part lengths (TLM) marker segment.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct J2kTilePartLength {
+    /// Tile index (Ttlm, or the entry's position when TLM leaves it out).
+    pub tile_index: u16,
+    /// Length of the tile-part from its SOT marker (Ptlm).
+    pub length: u32,
+}
+
+/// Start of tile-part (SOT) marker segment.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct J2kTilePart {
+    /// Tile index (Isot).
+    pub tile_index: u16,
+    /// Length of the tile-part from its SOT marker (Psot); 0 when it runs to the EOC
+    /// marker.
+    pub length: u32,
+    /// Index of the tile-part within its tile (TPsot).
+    pub part_index: u8,
+    /// Number of tile-parts of the tile (TNsot), or 0 when not given.
+    pub part_count: u8,
+}
+
 /// Capability (CAP) marker information (Part 15)
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct J2kCap {
     pub pcap: u32,
     pub ccap: Vec<u16>,
 }
 
+/// Use of the HT block coder declared by Ccap15 (ISO/IEC 15444-15, A.3).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HtDeclaration {
+    /// Every code-block uses the HT block coder.
+    HtOnly,
+    /// Some tile-components use the HT block coder, the others the Part 1 one.
+    HtDeclared,
+    /// Code-blocks may use either block coder.
+    Mixed,
+}
+
+impl J2kCap {
+    /// Whether Pcap declares Part 15 capabilities (bit 15, counting from the MSB).
+    pub fn is_htj2k(&self) -> bool {
+        self.pcap & (1 << 17) != 0
+    }
+
+    /// Use of the HT block coder from bits 15-14 of Ccap15, or `None` without Part 15
+    /// capabilities.
+    pub fn ht_declaration(&self) -> Option<HtDeclaration> {
+        if !self.is_htj2k() {
+            return None;
+        }
+        // Ccap entries follow the order of the Pcap bits set, from the MSB.
+        let ccap15 = *self.ccap.get((self.pcap >> 18).count_ones() as usize)?;
+        match ccap15 >> 14 {
+            0b00 => Some(HtDeclaration::HtOnly),
+            0b10 => Some(HtDeclaration::HtDeclared),
+            0b11 => Some(HtDeclaration::Mixed),
+            _ => None,
+        }
+    }
+}
+
 // Extend J2kImage with optional COD and QCD information
 impl J2kImage {
-    /// Reconstruct pixels from DWT coefficients using IDWT
-    /// Returns a vector of pixel values (u8) for the image
+    /// Number of tiles on the reference grid (B.3).
+    pub fn tile_count(&self) -> u32 {
+        let tiles_x = self
+            .width
+            .saturating_sub(self.tile_x_origin)
+            .div_ceil(self.tile_width.max(1));
+        let tiles_y = self
+            .height
+            .saturating_sub(self.tile_y_origin)
+            .div_ceil(self.tile_height.max(1));
+        tiles_x.saturating_mul(tiles_y).max(1)
+    }
+
+    /// Width and height of the image area, Xsiz - XOsiz by Ysiz - YOsiz (B.2), which is
+    /// the size of the decoded image.
+    pub fn size(&self) -> (u32, u32) {
+        (
+            self.width.saturating_sub(self.x_origin),
+            self.height.saturating_sub(self.y_origin),
+        )
+    }
+
+    /// Bounds `(tx0, ty0, tx1, ty1)` of tile `index` on the reference grid (B.3),
+    /// clipped to the image area.
+    pub fn tile_bounds(&self, index: u32) -> (u32, u32, u32, u32) {
+        let (tile_width, tile_height) = (self.tile_width.max(1), self.tile_height.max(1));
+        let tiles_x = self
+            .width
+            .saturating_sub(self.tile_x_origin)
+            .div_ceil(tile_width)
+            .max(1);
+        let (p, q) = (index % tiles_x, index / tiles_x);
+        let tx0 = self
+            .tile_x_origin
+            .saturating_add(p.saturating_mul(tile_width));
+        let ty0 = self
+            .tile_y_origin
+            .saturating_add(q.saturating_mul(tile_height));
+        (
+            tx0.max(self.x_origin),
+            ty0.max(self.y_origin),
+            tx0.saturating_add(tile_width).min(self.width),
+            ty0.saturating_add(tile_height).min(self.height),
+        )
+    }
+
+    /// Total size of the coefficient and code-block buffers held by the decoded tiles.
+    pub(crate) fn buffer_bytes(&self) -> usize {
+        let mut bytes = 0;
+        for component in self.tiles.iter().flat_map(|t| &t.components) {
+            bytes += component.data.len() * std::mem::size_of::<f32>();
+            for subband in component.resolutions.iter().flat_map(|r| &r.subbands) {
+                bytes += subband.data.len() * std::mem::size_of::<f32>();
+                for cb in &subband.codeblocks {
+                    bytes += cb.compressed_data.len()
+                        + cb.layer_data.iter().map(Vec::len).sum::<usize>()
+                        + cb.coefficients.len() * std::mem::size_of::<i32>()
+                        + cb.state.len();
+                }
+            }
+        }
+        bytes
+    }
+
+    /// Max-shift scaling (SPrgn) of the region of interest of `component` in tile
+    /// `tile_idx`, or 0 without one. Tile-part RGN segments take precedence over the main
+    /// header ones.
+    pub fn roi_shift(&self, tile_idx: usize, component: usize) -> u8 {
+        let for_component = |roi: &[J2kRoi]| {
+            roi.iter()
+                .find(|roi| roi.component_index as usize == component)
+                .map(|roi| roi.shift_value)
+        };
+        self.tiles
+            .get(tile_idx)
+            .and_then(|tile| for_component(&tile.roi))
+            .or_else(|| for_component(&self.roi))
+            .unwrap_or(0)
+    }
+
+    /// Block coder of the code-blocks of `component` in tile `tile_idx`, from its
+    /// code-block style: a tile-part COC segment takes precedence over a main header one,
+    /// which takes precedence over the COD. The mixed mode, where each code-block may
+    /// use either coder, is not supported.
+    pub fn block_coder(
+        &self,
+        tile_idx: usize,
+        component: usize,
+    ) -> Result<BlockCoder, JpeglsError> {
+        let for_component = |coc: &[J2kCoc]| {
+            coc.iter()
+                .find(|coc| coc.component_index as usize == component)
+                .map(|coc| coc.codeblock_style)
+        };
+        let style = self
+            .tiles
+            .get(tile_idx)
+            .and_then(|tile| for_component(&tile.coc))
+            .or_else(|| for_component(&self.coc))
+            .or_else(|| self.cod.as_ref().map(|cod| cod.codeblock_style))
+            .unwrap_or(0);
+        match style & 0xC0 {
+            0x00 => Ok(BlockCoder::Part1),
+            0x40 => Ok(BlockCoder::Ht),
+            _ => Err(JpeglsError::ParameterValueNotSupported),
+        }
+    }
+
+    /// Largest bit depth of the components (8 if SIZ listed none).
+    pub fn bits_per_sample(&self) -> u8 {
+        self.components.iter().map(|c| c.depth).max().unwrap_or(8)
+    }
+
+    /// Precision, signedness and subsampling of every component listed in SIZ.
+    pub fn component_info(&self) -> Vec<ComponentInfo> {
+        self.components
+            .iter()
+            .map(|c| ComponentInfo {
+                bits_per_sample: c.depth as i32,
+                signed: c.is_signed,
+                horizontal_subsampling: c.dx,
+                vertical_subsampling: c.dy,
+            })
+            .collect()
+    }
+
+    /// Whether the samples are signed, taken from the first component.
+    pub fn is_signed(&self) -> bool {
+        self.components.first().is_some_and(|c| c.is_signed)
+    }
+
+    /// Number of channels the image is reconstructed to: the components, or the entries
+    /// of the component mapping of an indexed-colour JP2 file, e.g. 3 for an RGB palette
+    /// indexed by one component.
+    pub fn channel_count(&self) -> u32 {
+        match self.palette_mapping() {
+            Some((_, mapping)) => mapping.len() as u32,
+            None => self.component_count,
+        }
+    }
+
+    /// Largest bit depth of the reconstructed channels; see
+    /// [`channel_count`](Self::channel_count).
+    pub fn channel_bits_per_sample(&self) -> u8 {
+        self.channels()
+            .iter()
+            .map(|&(depth, _)| depth)
+            .max()
+            .unwrap_or(8)
+    }
+
+    /// Depth and signedness of the reconstructed channels.
+    fn channels(&self) -> Vec<(u8, bool)> {
+        let component = |c: usize| {
+            self.components
+                .get(c)
+                .map_or((8, false), |info| (info.depth, info.is_signed))
+        };
+        match self.palette_mapping() {
+            Some((palette, mapping)) => mapping
+                .iter()
+                .map(|m| match m.palette_column {
+                    Some(column) => {
+                        let column = palette.columns[column as usize];
+                        (column.depth, column.signed)
+                    }
+                    None => component(m.component as usize),
+                })
+                .collect(),
+            None => (0..self.component_count as usize).map(component).collect(),
+        }
+    }
+
+    /// The palette and component mapping, if both are present and consistent with each
+    /// other and the codestream; otherwise the raw components are reconstructed.
+    fn palette_mapping(&self) -> Option<(&Jp2Palette, &[Jp2ComponentMapping])> {
+        let palette = self.palette.as_ref()?;
+        let mapping = &self.component_mapping;
+        let valid = !palette.entries.is_empty()
+            && mapping.iter().all(|m| {
+                (m.component as u32) < self.component_count
+                    && m.palette_column
+                        .is_none_or(|column| (column as usize) < palette.columns.len())
+            });
+        (valid && !mapping.is_empty()).then_some((palette, mapping.as_slice()))
+    }
+
+    /// Looks up the components of interleaved `samples` in the palette, giving the
+    /// channels of the component mapping.
+    fn apply_palette(&self, samples: Vec<i32>) -> Vec<i32> {
+        let Some((palette, mapping)) = self.palette_mapping() else {
+            return samples;
+        };
+        let last_entry = palette.entries.len() - 1;
+        samples
+            .chunks_exact(self.component_count.max(1) as usize)
+            .flat_map(|pixel| {
+                mapping.iter().map(move |m| {
+                    let sample = pixel[m.component as usize];
+                    match m.palette_column {
+                        Some(column) => {
+                            let index = (sample.max(0) as usize).min(last_entry);
+                            palette.entries[index][column as usize]
+                        }
+                        None => sample,
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Reconstruct pixels from DWT coefficients using IDWT.
+    /// Returns the interleaved samples as bytes, see [`J2kSamples::into_bytes`].
     pub fn reconstruct_pixels(&self) -> Result<Vec<u8>, String> {
+        Ok(self.reconstruct_samples()?.into_bytes())
+    }
+
+    /// Reconstruct the interleaved samples (e.g. RGBRGB...) of the image. Depths up to 16
+    /// bits are supported; unsigned components are level shifted back to `0..2^depth`,
+    /// signed components keep their range around zero. Subsampled components are
+    /// brought to the full image size with [`J2kUpsampling::Nearest`].
+    pub fn reconstruct_samples(&self) -> Result<J2kSamples, String> {
+        self.reconstruct_samples_with(J2kUpsampling::Nearest)
+    }
+
+    /// Like [`reconstruct_samples`](Self::reconstruct_samples), upsampling components
+    /// subsampled by SIZ (dx, dy > 1) to the reference grid with `upsampling`. Component
+    /// registration (CRG) offsets place the samples between the reference grid points.
+    pub fn reconstruct_samples_with(
+        &self,
+        upsampling: J2kUpsampling,
+    ) -> Result<J2kSamples, String> {
+        Ok(self.reconstruct_resolution(u8::MAX, upsampling)?.samples)
+    }
+
+    /// Reconstructs the image at `resolution`: 0 is the LL band of the lowest resolution
+    /// level and every further resolution doubles the width and height, up to the full
+    /// image at the number of decomposition levels (larger values give the full image
+    /// too). A low resolution needs only the packets of its resolution levels, which is
+    /// what progressive viewers show first.
+    pub fn reconstruct_resolution(
+        &self,
+        resolution: u8,
+        upsampling: J2kUpsampling,
+    ) -> Result<J2kPreview, String> {
+        self.reconstruct_area(resolution, upsampling, None)
+    }
+
+    /// Reconstructs tile `index` alone, at the bounds of
+    /// [`tile_bounds`](Self::tile_bounds), e.g. after decoding only that tile with
+    /// [`J2kDecoder::set_tiles`](super::decoder::J2kDecoder::set_tiles) so that no more
+    /// than the tile is held in memory. The samples are those of the tile in the whole
+    /// image, except that [`J2kUpsampling::Bilinear`] does not reach across the tile
+    /// border.
+    pub fn reconstruct_tile(
+        &self,
+        index: u32,
+        upsampling: J2kUpsampling,
+    ) -> Result<J2kPreview, String> {
+        self.reconstruct_area(u8::MAX, upsampling, Some(index))
+    }
+
+    /// Reconstructs the image area, or tile `only_tile` of it, at `resolution`.
+    fn reconstruct_area(
+        &self,
+        resolution: u8,
+        upsampling: J2kUpsampling,
+        only_tile: Option<u32>,
+    ) -> Result<J2kPreview, String> {
         if self.tiles.is_empty() {
             return Err("No tiles in image".to_string());
         }
+        let bits_per_sample = self.bits_per_sample().max(self.channel_bits_per_sample());
+        if bits_per_sample > 16 {
+            return Err(format!("{}-bit samples are not supported", bits_per_sample));
+        }
+
+        let cod = self.cod.as_ref().ok_or("No COD marker")?;
+        // Every discarded level halves the image on the reference grid.
+        let discarded = cod.decomposition_levels.saturating_sub(resolution) as u32;
+        let reduce = |v: u32| (v as u64).div_ceil(1 << discarded) as u32;
+        // The image area from (XOsiz, YOsiz) to (Xsiz, Ysiz), or the tile, at this
+        // resolution.
+        let (area_x0, area_y0, area_x1, area_y1) = match only_tile {
+            Some(index) => self.tile_bounds(index),
+            None => (self.x_origin, self.y_origin, self.width, self.height),
+        };
+        let width = reduce(area_x1).saturating_sub(reduce(area_x0));
+        let height = reduce(area_y1).saturating_sub(reduce(area_y0));
 
-        let mut pixels = vec![0u8; (self.width * self.height * self.component_count) as usize];
-        let pixels_per_component = (self.width * self.height) as usize;
+        // One sample per byte of an 8-bit frame of the same size.
+        let sample_count = FrameInfo {
+            width,
+            height,
+            bits_per_sample: 8,
+            component_count: self.component_count as i32,
+        }
+        .decoded_size(OutputLayout::Interleaved)
+        .map_err(|e| e.to_string())?;
+        let mut samples = vec![0i32; sample_count];
+        let pixels_per_component = width as usize * height as usize;
 
-        // For now, handle single tile case
-        let tile = &self.tiles[0];
+        // Every component is assembled from its tile-components, from the component
+        // origin ceil(XOsiz / dx) to ceil(Xsiz / dx) (B.2), reduced like the image.
+        let subsampling = |c: usize| {
+            self.components.get(c).map_or((1, 1), |info| {
+                (info.dx.max(1) as u32, info.dy.max(1) as u32)
+            })
+        };
+        let component_bounds: Vec<(u32, u32, u32, u32)> = (0..self.component_count as usize)
+            .map(|c| {
+                let (dx, dy) = subsampling(c);
+                (
+                    reduce(area_x0.div_ceil(dx)),
+                    reduce(area_y0.div_ceil(dy)),
+                    reduce(area_x1.div_ceil(dx)),
+                    reduce(area_y1.div_ceil(dy)),
+                )
+            })
+            .collect();
+        let component_sizes: Vec<(usize, usize)> = component_bounds
+            .iter()
+            .map(|&(x0, y0, x1, y1)| {
+                (
+                    x1.saturating_sub(x0) as usize,
+                    y1.saturating_sub(y0) as usize,
+                )
+            })
+            .collect();
+        let mut component_buffers: Vec<Vec<f32>> = component_sizes
+            .iter()
+            .map(|&(w, h)| vec![0.0f32; w * h])
+            .collect();
 
-        let cod = self.cod.as_ref().ok_or("No COD marker")?;
         let nom_w = 1 << (cod.codeblock_width_exp + 2);
         let nom_h = 1 << (cod.codeblock_height_exp + 2);
 
@@ -241,92 +717,41 @@ impl J2kImage {
             }
         };
 
-        let mut component_buffers = Vec::new();
-
-        for (comp_idx, component) in tile.components.iter().enumerate() {
-            if component.resolutions.is_empty() {
-                // Ensure we push something to keep indices aligned, even if empty/invalid
-                component_buffers.push(vec![0.0f32; pixels_per_component]);
+        for (tile_idx, tile) in self.tiles.iter().enumerate() {
+            if only_tile.is_some_and(|index| index as usize != tile_idx) {
                 continue;
             }
-
-            // Start with LL from Resolution 0
-            let mut current_ll =
-                get_subband_data(&component.resolutions[0], SubbandOrientation::LL);
-
-            if current_ll.is_empty() {
-                let r0 = &component.resolutions[0];
-                current_ll = vec![0.0f32; (r0.width * r0.height) as usize];
-            }
-
-            let cod = self.cod.as_ref().ok_or("No COD marker")?;
-            let _is_reversible = cod.transformation == 1;
-
-            if !_is_reversible {
-                let qcd = self.qcd.as_ref().ok_or("No QCD for Irreversible")?;
-                let guard_bits = (qcd.quant_style >> 5) & 0x07;
-                // Helper to decode step size
-                let depth = if self.components.len() > comp_idx {
-                    self.components[comp_idx].depth
-                } else {
-                    8
+            let (tx0, ty0, _, _) = self.tile_bounds(tile_idx as u32);
+            for (comp_idx, component) in tile.components.iter().enumerate() {
+                if component.resolutions.is_empty() || comp_idx >= component_buffers.len() {
+                    // Tiles that were not decoded stay zero.
+                    continue;
+                }
+                let resolutions = &component.resolutions[..component
+                    .resolutions
+                    .len()
+                    .saturating_sub(discarded as usize)
+                    .max(1)];
+                let roi_shift = self.roi_shift(tile_idx, comp_idx);
+                let get_subband_data = |res: &J2kResolution, orientation: SubbandOrientation| {
+                    descale_roi(get_subband_data(res, orientation), roi_shift)
                 };
 
-                let calc_step = |exp: u16, mant: u16, log2_gain: u8| -> f32 {
-                    let rb = depth + guard_bits + log2_gain;
-                    (1.0 + (mant as f32 / 2048.0)) * 2.0f32.powi(rb as i32 - exp as i32)
-                };
+                // Start with LL from Resolution 0
+                let mut current_ll =
+                    get_subband_data(&component.resolutions[0], SubbandOrientation::LL);
 
-                // Decode base step size (LL subband), gain=1 (log2=0)
-                let step_ll = if !qcd.step_sizes.is_empty() {
-                    let val = qcd.step_sizes[0];
-                    let exp = (val >> 11) & 0x1F;
-                    let mant = val & 0x7FF;
-                    calc_step(exp, mant, 0)
-                } else {
-                    1.0
-                };
-                for v in &mut current_ll {
-                    *v *= step_ll;
+                if current_ll.is_empty() {
+                    let r0 = &component.resolutions[0];
+                    current_ll = vec![0.0f32; (r0.width * r0.height) as usize];
                 }
-            }
 
-            // Iterate through higher resolutions (1..N) to apply IDWT
-            for r in 1..component.resolutions.len() {
-                let res = &component.resolutions[r];
-                let hl = get_subband_data(res, SubbandOrientation::HL);
-                let lh = get_subband_data(res, SubbandOrientation::LH);
-                let hh = get_subband_data(res, SubbandOrientation::HH);
-
-                let mut output = vec![0.0f32; (res.width * res.height) as usize];
-
-                if _is_reversible {
-                    // Reversible 5-3 (Integers)
-                    let ll_i32: Vec<i32> = current_ll.iter().map(|&f| f as i32).collect();
-                    let hl_i32: Vec<i32> = hl.iter().map(|&f| f as i32).collect();
-                    let lh_i32: Vec<i32> = lh.iter().map(|&f| f as i32).collect();
-                    let hh_i32: Vec<i32> = hh.iter().map(|&f| f as i32).collect();
-                    let mut output_i32 = vec![0i32; output.len()];
-
-                    crate::jpeg2000::dwt::Dwt53::inverse_2d(
-                        &ll_i32,
-                        &hl_i32,
-                        &lh_i32,
-                        &hh_i32,
-                        res.width,
-                        res.height,
-                        &mut output_i32,
-                    );
-                    for i in 0..output.len() {
-                        output[i] = output_i32[i] as f32;
-                    }
-                } else {
-                    // Irreversible 9-7 (Floats)
-                    // Dequantization required.
+                let cod = self.cod.as_ref().ok_or("No COD marker")?;
+                let _is_reversible = cod.transformation == 1;
+
+                if !_is_reversible {
                     let qcd = self.qcd.as_ref().ok_or("No QCD for Irreversible")?;
                     let guard_bits = (qcd.quant_style >> 5) & 0x07;
-                    let quant_style = qcd.quant_style & 0x1F; // 0=No, 1=Derived, 2=Expounded
-
                     // Helper to decode step size
                     let depth = if self.components.len() > comp_idx {
                         self.components[comp_idx].depth
@@ -335,86 +760,167 @@ impl J2kImage {
                     };
 
                     let calc_step = |exp: u16, mant: u16, log2_gain: u8| -> f32 {
-                        // Table E.1: HL/LH gain=1 (log2=0), HH gain=2 (log2=1).
                         let rb = depth + guard_bits + log2_gain;
                         (1.0 + (mant as f32 / 2048.0)) * 2.0f32.powi(rb as i32 - exp as i32)
                     };
 
-                    let decode_step_val = |val: u16, is_hh: bool| -> f32 {
-                        let log2_gain = if is_hh { 1 } else { 0 };
-                        calc_step((val >> 11) & 0x1F, val & 0x7FF, log2_gain)
+                    // Decode base step size (LL subband), gain=1 (log2=0)
+                    let step_ll = if !qcd.step_sizes.is_empty() {
+                        let val = qcd.step_sizes[0];
+                        let exp = (val >> 11) & 0x1F;
+                        let mant = val & 0x7FF;
+                        calc_step(exp, mant, 0)
+                    } else {
+                        1.0
                     };
+                    for v in &mut current_ll {
+                        *v *= step_ll;
+                    }
+                }
+
+                // Iterate through higher resolutions (1..N) to apply IDWT
+                for (r, res) in resolutions.iter().enumerate().skip(1) {
+                    j2k_span!(_span, "j2k_idwt");
+                    let hl = get_subband_data(res, SubbandOrientation::HL);
+                    let lh = get_subband_data(res, SubbandOrientation::LH);
+                    let hh = get_subband_data(res, SubbandOrientation::HH);
+
+                    let mut output = vec![0.0f32; (res.width * res.height) as usize];
+
+                    if _is_reversible {
+                        // Reversible 5-3 (Integers)
+                        let ll_i32: Vec<i32> = current_ll.iter().map(|&f| f as i32).collect();
+                        let hl_i32: Vec<i32> = hl.iter().map(|&f| f as i32).collect();
+                        let lh_i32: Vec<i32> = lh.iter().map(|&f| f as i32).collect();
+                        let hh_i32: Vec<i32> = hh.iter().map(|&f| f as i32).collect();
+                        let mut output_i32 = vec![0i32; output.len()];
+
+                        crate::jpeg2000::dwt::Dwt53::inverse_2d(
+                            &ll_i32,
+                            &hl_i32,
+                            &lh_i32,
+                            &hh_i32,
+                            res.width,
+                            res.height,
+                            &mut output_i32,
+                        );
+                        for i in 0..output.len() {
+                            output[i] = output_i32[i] as f32;
+                        }
+                    } else {
+                        // Irreversible 9-7 (Floats)
+                        // Dequantization required.
+                        let qcd = self.qcd.as_ref().ok_or("No QCD for Irreversible")?;
+                        let guard_bits = (qcd.quant_style >> 5) & 0x07;
+                        let quant_style = qcd.quant_style & 0x1F; // 0=No, 1=Derived, 2=Expounded
 
-                    // Determine step sizes for HL, LH, HH
-                    let (step_hl, step_lh, step_hh) = if quant_style == 1 {
-                        // Derived
-                        if qcd.step_sizes.is_empty() {
-                            (1.0, 1.0, 1.0)
+                        // Helper to decode step size
+                        let depth = if self.components.len() > comp_idx {
+                            self.components[comp_idx].depth
                         } else {
-                            let base = qcd.step_sizes[0];
-                            let base_exp = (base >> 11) & 0x1F;
-                            let base_mant = base & 0x7FF;
+                            8
+                        };
+
+                        let calc_step = |exp: u16, mant: u16, log2_gain: u8| -> f32 {
+                            // Table E.1: HL/LH gain=1 (log2=0), HH gain=2 (log2=1).
+                            let rb = depth + guard_bits + log2_gain;
+                            (1.0 + (mant as f32 / 2048.0)) * 2.0f32.powi(rb as i32 - exp as i32)
+                        };
 
-                            let base_step_ll = calc_step(base_exp, base_mant, 0); // Gain=1, log2=0
+                        let decode_step_val = |val: u16, is_hh: bool| -> f32 {
+                            let log2_gain = if is_hh { 1 } else { 0 };
+                            calc_step((val >> 11) & 0x1F, val & 0x7FF, log2_gain)
+                        };
 
-                            // Derived formula:
-                            // Delta_b = Delta_0 * 2^(exp_0 - exp_b) * gain_correction
-                            // exp_b = exp_0 + (r - 1)
-                            // So exp_0 - exp_b = -(r - 1) = 1 - r
-                            let derived_exp = base_exp + (r as u16) - 1;
+                        // Determine step sizes for HL, LH, HH
+                        let (step_hl, step_lh, step_hh) = if quant_style == 1 {
+                            // Derived
+                            if qcd.step_sizes.is_empty() {
+                                (1.0, 1.0, 1.0)
+                            } else {
+                                let base = qcd.step_sizes[0];
+                                let base_exp = (base >> 11) & 0x1F;
+                                let base_mant = base & 0x7FF;
 
-                            // Gain correction:
-                            // HL/LH (log2=0): gain_correction = 2^(0-0) = 1
-                            // HH (log2=1): gain_correction = 2^(1-0) = 2
+                                let base_step_ll = calc_step(base_exp, base_mant, 0); // Gain=1, log2=0
 
-                            let factor_common = 2.0f32.powi(base_exp as i32 - derived_exp as i32);
+                                // Derived formula:
+                                // Delta_b = Delta_0 * 2^(exp_0 - exp_b) * gain_correction
+                                // exp_b = exp_0 + (r - 1)
+                                // So exp_0 - exp_b = -(r - 1) = 1 - r
+                                let derived_exp = base_exp + (r as u16) - 1;
 
+                                // Gain correction:
+                                // HL/LH (log2=0): gain_correction = 2^(0-0) = 1
+                                // HH (log2=1): gain_correction = 2^(1-0) = 2
+
+                                let factor_common =
+                                    2.0f32.powi(base_exp as i32 - derived_exp as i32);
+
+                                (
+                                    base_step_ll * factor_common,       // HL
+                                    base_step_ll * factor_common,       // LH
+                                    base_step_ll * factor_common * 2.0, // HH
+                                )
+                            }
+                        } else {
+                            // Expounded or Fallback
+                            let idx_hl = 1 + (r - 1) * 3;
+                            let idx_lh = idx_hl + 1;
+                            let idx_hh = idx_hl + 2;
                             (
-                                base_step_ll * factor_common,       // HL
-                                base_step_ll * factor_common,       // LH
-                                base_step_ll * factor_common * 2.0, // HH
+                                decode_step_val(
+                                    qcd.step_sizes[idx_hl.min(qcd.step_sizes.len() - 1)],
+                                    false,
+                                ),
+                                decode_step_val(
+                                    qcd.step_sizes[idx_lh.min(qcd.step_sizes.len() - 1)],
+                                    false,
+                                ),
+                                decode_step_val(
+                                    qcd.step_sizes[idx_hh.min(qcd.step_sizes.len() - 1)],
+                                    true,
+                                ),
                             )
-                        }
-                    } else {
-                        // Expounded or Fallback
-                        let idx_hl = 1 + (r - 1) * 3;
-                        let idx_lh = idx_hl + 1;
-                        let idx_hh = idx_hl + 2;
-                        (
-                            decode_step_val(
-                                qcd.step_sizes[idx_hl.min(qcd.step_sizes.len() - 1)],
-                                false,
-                            ),
-                            decode_step_val(
-                                qcd.step_sizes[idx_lh.min(qcd.step_sizes.len() - 1)],
-                                false,
-                            ),
-                            decode_step_val(
-                                qcd.step_sizes[idx_hh.min(qcd.step_sizes.len() - 1)],
-                                true,
-                            ),
-                        )
-                    };
+                        };
+
+                        // Apply step sizes
+                        let hl_fq: Vec<f32> = hl.iter().map(|&v| v * step_hl).collect();
+                        let lh_fq: Vec<f32> = lh.iter().map(|&v| v * step_lh).collect();
+                        let hh_fq: Vec<f32> = hh.iter().map(|&v| v * step_hh).collect();
+
+                        crate::jpeg2000::dwt::Dwt97::inverse_2d(
+                            &current_ll,
+                            &hl_fq,
+                            &lh_fq,
+                            &hh_fq,
+                            res.width,
+                            res.height,
+                            &mut output,
+                        );
+                    }
+                    current_ll = output;
+                }
 
-                    // Apply step sizes
-                    let hl_fq: Vec<f32> = hl.iter().map(|&v| v * step_hl).collect();
-                    let lh_fq: Vec<f32> = lh.iter().map(|&v| v * step_lh).collect();
-                    let hh_fq: Vec<f32> = hh.iter().map(|&v| v * step_hh).collect();
-
-                    crate::jpeg2000::dwt::Dwt97::inverse_2d(
-                        &current_ll,
-                        &hl_fq,
-                        &lh_fq,
-                        &hh_fq,
-                        res.width,
-                        res.height,
-                        &mut output,
-                    );
+                // Place the tile-component in its component (B.3).
+                let top = &resolutions[resolutions.len() - 1];
+                let (dx, dy) = subsampling(comp_idx);
+                let (x0, y0, _, _) = component_bounds[comp_idx];
+                let left = reduce(tx0.div_ceil(dx)).saturating_sub(x0) as usize;
+                let top_row = reduce(ty0.div_ceil(dy)).saturating_sub(y0) as usize;
+                let (component_width, component_height) = component_sizes[comp_idx];
+                let tile_width = top.width as usize;
+                let columns = tile_width.min(component_width.saturating_sub(left));
+                for (y, row) in current_ll.chunks(tile_width.max(1)).enumerate() {
+                    if top_row + y >= component_height {
+                        break;
+                    }
+                    let start = (top_row + y) * component_width + left;
+                    let count = columns.min(row.len());
+                    component_buffers[comp_idx][start..start + count]
+                        .copy_from_slice(&row[..count]);
                 }
-                current_ll = output;
             }
-
-            component_buffers.push(current_ll);
         }
 
         // Apply Multiple Component Transform (MCT) if enabled
@@ -422,87 +928,408 @@ impl J2kImage {
         if cod.mct == 1 && component_buffers.len() >= 3 {
             let count = component_buffers[0].len();
             if component_buffers[1].len() == count && component_buffers[2].len() == count {
+                let (first, rest) = component_buffers.split_at_mut(1);
+                let (second, third) = rest.split_at_mut(1);
+                let (c0, c1, c2) = (&mut first[0], &mut second[0], &mut third[0]);
                 if cod.transformation == 1 {
-                    // Reversible (RCT)
-                    // G = Y - floor((Cb + Cr) / 4)
-                    // R = Cr + G
-                    // B = Cb + G
-                    for i in 0..count {
-                        let y = component_buffers[0][i] as i32;
-                        let cb = component_buffers[1][i] as i32;
-                        let cr = component_buffers[2][i] as i32;
-
-                        let g = y - ((cb + cr) >> 2);
-                        let r = cr + g;
-                        let b = cb + g;
-
-                        component_buffers[0][i] = r as f32;
-                        component_buffers[1][i] = g as f32;
-                        component_buffers[2][i] = b as f32;
+                    // Reversible (RCT) on the integer 5-3 output
+                    let to_i32 =
+                        |c: &[f32]| c.iter().map(|&v| v.round() as i32).collect::<Vec<_>>();
+                    let (mut y, mut cb, mut cr) = (to_i32(c0), to_i32(c1), to_i32(c2));
+                    crate::jpeg2000::mct::inverse_rct(&mut y, &mut cb, &mut cr);
+                    for (buffer, values) in [(c0, y), (c1, cb), (c2, cr)] {
+                        for (v, value) in buffer.iter_mut().zip(values) {
+                            *v = value as f32;
+                        }
                     }
                 } else {
                     // Irreversible (ICT)
-                    // R = Y + 1.402 * Cr
-                    // G = Y - 0.34413 * Cb - 0.71414 * Cr
-                    // B = Y + 1.772 * Cb
-                    for i in 0..count {
-                        let y = component_buffers[0][i];
-                        let cb = component_buffers[1][i];
-                        let cr = component_buffers[2][i];
-
-                        let r = y + 1.402 * cr;
-                        let g = y - 0.34413 * cb - 0.71414 * cr;
-                        let b = y + 1.772 * cb;
-
-                        component_buffers[0][i] = r;
-                        component_buffers[1][i] = g;
-                        component_buffers[2][i] = b;
-                    }
+                    crate::jpeg2000::mct::inverse_ict(c0, c1, c2);
                 }
             }
         }
 
+        // Bring subsampled or offset components to the reference grid
+        for (c, buffer) in component_buffers.iter_mut().enumerate() {
+            let (dx, dy) = self
+                .components
+                .get(c)
+                .map_or((1, 1), |info| (info.dx.max(1), info.dy.max(1)));
+            let (xcrg, ycrg) = self.registration.get(c).copied().unwrap_or((0, 0));
+            if (dx, dy, xcrg, ycrg) == (1, 1, 0, 0) {
+                continue;
+            }
+            let (samples_wide, samples_high) = component_sizes[c];
+            let (x_origin, y_origin) = (reduce(area_x0), reduce(area_y0));
+            let columns = sample_positions(width, x_origin, dx, xcrg, samples_wide, upsampling);
+            let rows = sample_positions(height, y_origin, dy, ycrg, samples_high, upsampling);
+            *buffer = upsample(buffer, samples_wide, &columns, &rows);
+        }
+
         // Finalize: Level Shift, Clamp, and Interleave
         // Output format is Interleaved (e.g. RGBRGB...)
-        let _cod = self.cod.as_ref().ok_or("No COD marker")?;
+        for (c, buffer) in component_buffers.iter().enumerate() {
+            let (depth, is_signed) = self
+                .components
+                .get(c)
+                .map_or((8, false), |info| (info.depth, info.is_signed));
+            let half = 1i32 << (depth - 1);
+            let (level_offset, min, max) = if is_signed {
+                (0, -half, half - 1)
+            } else {
+                (half, 0, 2 * half - 1)
+            };
 
-        for i in 0..pixels_per_component {
-            for (c, buffer) in component_buffers.iter().enumerate() {
-                if i >= buffer.len() {
-                    continue;
+            for (i, &v) in buffer.iter().take(pixels_per_component).enumerate() {
+                let dest_idx = i * self.component_count as usize + c;
+                if dest_idx < samples.len() {
+                    samples[dest_idx] = (v.round() as i32 + level_offset).clamp(min, max);
                 }
+            }
+        }
 
-                let depth = if self.components.len() > c {
-                    self.components[c].depth
-                } else {
-                    8
-                };
+        // Indexed-colour images are expanded to the channels of their palette.
+        let samples = self.apply_palette(samples);
+        let bits_per_sample = self.channel_bits_per_sample();
+        let signed = self.channels().first().is_some_and(|&(_, signed)| signed);
+        let samples = match (bits_per_sample > 8, signed) {
+            (false, false) => J2kSamples::U8(samples.into_iter().map(|v| v as u8).collect()),
+            (false, true) => J2kSamples::I8(samples.into_iter().map(|v| v as i8).collect()),
+            (true, false) => J2kSamples::U16(samples.into_iter().map(|v| v as u16).collect()),
+            (true, true) => J2kSamples::I16(samples.into_iter().map(|v| v as i16).collect()),
+        };
+        Ok(J2kPreview {
+            resolution: resolution.min(cod.decomposition_levels),
+            width,
+            height,
+            samples,
+        })
+    }
+}
 
-                let shift = depth.saturating_sub(8);
-                let level_offset = (1 << (depth - 1)) as f32;
-                let scale_div = (1 << shift) as f32;
+/// The image reconstructed at one resolution by [`J2kImage::reconstruct_resolution`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct J2kPreview {
+    /// Resolution, from 0 (lowest) to the number of decomposition levels (full image).
+    pub resolution: u8,
+    /// Width in pixels at this resolution.
+    pub width: u32,
+    /// Height in pixels at this resolution.
+    pub height: u32,
+    /// Interleaved samples, as from [`J2kImage::reconstruct_samples`].
+    pub samples: J2kSamples,
+}
 
-                let v = buffer[i];
+/// How [`J2kImage::reconstruct_samples_with`] fills the reference grid from components
+/// subsampled by SIZ.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum J2kUpsampling {
+    /// Every sample covers its dx × dy block of the reference grid.
+    #[default]
+    Nearest,
+    /// Reference grid points between samples are interpolated from the two (or four)
+    /// closest samples.
+    Bilinear,
+}
 
-                let val = ((v + level_offset) / scale_div).round().clamp(0.0, 255.0) as u8;
+/// For the reference grid columns (or rows) `0..size` of an image area starting at
+/// `origin`, the neighbouring samples of a component subsampled by `factor` and offset by
+/// `offset` / 65536 samples, with the weight of the second one.
+fn sample_positions(
+    size: u32,
+    origin: u32,
+    factor: u8,
+    offset: u16,
+    samples: usize,
+    upsampling: J2kUpsampling,
+) -> Vec<(usize, usize, f32)> {
+    let factor = factor as f64;
+    let first = (origin as f64 / factor).ceil();
+    let last = samples.saturating_sub(1);
+    (0..size)
+        .map(|x| {
+            // Sample u of the component lies at (u + offset / 65536) * factor.
+            let t = ((origin + x) as f64 / factor - offset as f64 / 65536.0 - first).max(0.0);
+            let u = (t.floor() as usize).min(last);
+            match upsampling {
+                J2kUpsampling::Nearest => (u, u, 0.0),
+                J2kUpsampling::Bilinear => (u, (u + 1).min(last), t.fract() as f32),
+            }
+        })
+        .collect()
+}
 
-                let dest_idx = i * self.component_count as usize + c;
-                if dest_idx < pixels.len() {
-                    pixels[dest_idx] = val;
-                }
+/// Resamples a component `width` samples wide at the given column and row positions.
+fn upsample(
+    buffer: &[f32],
+    width: usize,
+    columns: &[(usize, usize, f32)],
+    rows: &[(usize, usize, f32)],
+) -> Vec<f32> {
+    let at = |u: usize, v: usize| buffer.get(v * width + u).copied().unwrap_or(0.0);
+    let mut output = Vec::with_capacity(columns.len() * rows.len());
+    for &(v0, v1, wy) in rows {
+        for &(u0, u1, wx) in columns {
+            let top = at(u0, v0) + (at(u1, v0) - at(u0, v0)) * wx;
+            let bottom = at(u0, v1) + (at(u1, v1) - at(u0, v1)) * wx;
+            output.push(top + (bottom - top) * wy);
+        }
+    }
+    output
+}
+
+/// Interleaved samples reconstructed by [`J2kImage::reconstruct_samples`]. Images with at
+/// most 8 bits per sample give 8-bit samples, deeper images 16-bit samples; signed
+/// components give signed samples.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum J2kSamples {
+    U8(Vec<u8>),
+    I8(Vec<i8>),
+    U16(Vec<u16>),
+    I16(Vec<i16>),
+}
+
+impl J2kSamples {
+    /// The samples as bytes; 16-bit samples are stored little-endian and signed samples in
+    /// two's complement.
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self {
+            J2kSamples::U8(samples) => samples,
+            J2kSamples::I8(samples) => samples.into_iter().map(|v| v as u8).collect(),
+            J2kSamples::U16(samples) => samples.into_iter().flat_map(u16::to_le_bytes).collect(),
+            J2kSamples::I16(samples) => samples.into_iter().flat_map(i16::to_le_bytes).collect(),
+        }
+    }
+}
+
+/// Undoes the max-shift ROI scaling (Annex H): coefficients at or above 2^`roi_shift`
+/// belong to the region of interest and were scaled up by `roi_shift` bit-planes; the
+/// background ones were not.
+fn descale_roi(mut coefficients: Vec<f32>, roi_shift: u8) -> Vec<f32> {
+    if roi_shift > 0 {
+        let scale = 2.0f32.powi(roi_shift as i32);
+        for v in &mut coefficients {
+            if v.abs() >= scale {
+                *v = (*v / scale).trunc();
             }
         }
-        Ok(pixels)
     }
+    coefficients
+}
+
+/// Coding style of one component (COC marker); only the code-block style is kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct J2kCoc {
+    /// Component index the coding style applies to.
+    pub component_index: u16,
+    /// Code-block style (SPcoc), as in [`J2kCod::codeblock_style`].
+    pub codeblock_style: u8,
 }
 
 /// Region of Interest (ROI) marker information.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct J2kRoi {
     /// Component index affected by ROI.
-    pub component_index: u8,
-    /// ROI style (0 = implicit, 1 = explicit).
+    pub component_index: u16,
+    /// ROI style (Srgn); 0, the max-shift method, is the only one in Part 1.
     pub roi_style: u8,
     /// Shift value for ROI coefficients (SPrgn).
     pub shift_value: u8,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2x2 single-component image whose LL band holds `coefficients`, without any
+    /// decomposition levels, in a single tile of up to 4x4.
+    fn image(depth: u8, is_signed: bool, coefficients: Vec<i32>) -> J2kImage {
+        let codeblock = J2kCodeBlock {
+            width: 2,
+            height: 2,
+            coefficients,
+            ..Default::default()
+        };
+        let resolution = J2kResolution {
+            width: 2,
+            height: 2,
+            subbands: vec![J2kSubband {
+                width: 2,
+                height: 2,
+                codeblocks: vec![codeblock],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        J2kImage {
+            width: 2,
+            height: 2,
+            tile_width: 4,
+            tile_height: 4,
+            component_count: 1,
+            cod: Some(J2kCod {
+                codeblock_width_exp: 4,
+                codeblock_height_exp: 4,
+                transformation: 1,
+                ..Default::default()
+            }),
+            tiles: vec![J2kTile {
+                components: vec![J2kTileComponent {
+                    resolutions: vec![resolution],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            components: vec![J2kComponentInfo {
+                depth,
+                is_signed,
+                dx: 1,
+                dy: 1,
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_reconstruct_samples_depths() {
+        let samples = image(8, false, vec![-128, 0, 127, 200]).reconstruct_samples();
+        assert_eq!(samples, Ok(J2kSamples::U8(vec![0, 128, 255, 255])));
+
+        let samples = image(12, false, vec![-2048, 0, 2047, -3000]).reconstruct_samples();
+        assert_eq!(samples, Ok(J2kSamples::U16(vec![0, 2048, 4095, 0])));
+
+        let samples = image(16, true, vec![-1024, 0, 32767, 40000]).reconstruct_samples();
+        assert_eq!(samples, Ok(J2kSamples::I16(vec![-1024, 0, 32767, 32767])));
+        assert_eq!(
+            samples.unwrap().into_bytes(),
+            [0x00, 0xFC, 0x00, 0x00, 0xFF, 0x7F, 0xFF, 0x7F]
+        );
+
+        let samples = image(8, true, vec![-200, -1, 1, 127]).reconstruct_samples();
+        assert_eq!(samples, Ok(J2kSamples::I8(vec![-128, -1, 1, 127])));
+
+        assert!(image(20, false, vec![0; 4]).reconstruct_samples().is_err());
+    }
+
+    #[test]
+    fn test_reconstruct_subsampled() {
+        // A 2x2 component subsampled by 2 in both directions covers a 4x4 image.
+        let mut image = image(8, false, vec![-128, 0, 64, 127]);
+        image.width = 4;
+        image.height = 4;
+        image.components[0].dx = 2;
+        image.components[0].dy = 2;
+
+        let samples = image.reconstruct_samples();
+        #[rustfmt::skip]
+        let expected = vec![
+            0, 0, 128, 128,
+            0, 0, 128, 128,
+            192, 192, 255, 255,
+            192, 192, 255, 255,
+        ];
+        assert_eq!(samples, Ok(J2kSamples::U8(expected)));
+
+        let samples = image.reconstruct_samples_with(J2kUpsampling::Bilinear);
+        #[rustfmt::skip]
+        let expected = vec![
+            0, 64, 128, 128,
+            96, 144, 192, 192,
+            192, 224, 255, 255,
+            192, 224, 255, 255,
+        ];
+        assert_eq!(samples, Ok(J2kSamples::U8(expected)));
+
+        // CRG moves the samples half a sample to the right.
+        image.registration = vec![(32768, 0)];
+        let samples = image.reconstruct_samples_with(J2kUpsampling::Bilinear);
+        assert_eq!(samples.unwrap().into_bytes()[..4], [0, 0, 64, 128]);
+    }
+
+    #[test]
+    fn test_reconstruct_tiles() {
+        // A 4x2 image of two 2x2 tiles, the second one decoded from `right`.
+        let right = image(8, false, vec![1, 2, 3, 4]).tiles.remove(0);
+        let mut image = image(8, false, vec![-128, -64, 0, 64]);
+        image.width = 4;
+        image.tile_width = 2;
+        image.tile_height = 2;
+        image.tiles.push(right);
+        assert_eq!(image.tile_count(), 2);
+        assert_eq!(image.tile_bounds(1), (2, 0, 4, 2));
+
+        let samples = image.reconstruct_samples();
+        #[rustfmt::skip]
+        let expected = vec![
+            0, 64, 129, 130,
+            128, 192, 131, 132,
+        ];
+        assert_eq!(samples, Ok(J2kSamples::U8(expected)));
+
+        // With the image area starting at x = 2 only the second tile is in the image.
+        let mut cut = image.clone();
+        cut.x_origin = 2;
+        cut.tiles[0].components.clear();
+        assert_eq!(cut.size(), (2, 2));
+        assert_eq!(cut.tile_bounds(0), (2, 0, 2, 2));
+        let samples = cut.reconstruct_samples();
+        assert_eq!(samples, Ok(J2kSamples::U8(vec![129, 130, 131, 132])));
+
+        // Tiles that are not decoded stay at the level shift.
+        image.tiles[1].components.clear();
+        let samples = image.reconstruct_samples().unwrap().into_bytes();
+        assert_eq!(samples, [0, 64, 128, 128, 128, 192, 128, 128]);
+    }
+
+    #[test]
+    fn test_cap_ht_declaration() {
+        let cap = |pcap, ccap: &[u16]| J2kCap {
+            pcap,
+            ccap: ccap.to_vec(),
+        };
+        assert_eq!(cap(0, &[]).ht_declaration(), None);
+        assert!(cap(0x0002_0000, &[0x0020]).is_htj2k());
+        assert_eq!(
+            cap(0x0002_0000, &[0x0020]).ht_declaration(),
+            Some(HtDeclaration::HtOnly)
+        );
+        assert_eq!(
+            cap(0x0002_0000, &[0x8000]).ht_declaration(),
+            Some(HtDeclaration::HtDeclared)
+        );
+        // Ccap15 comes after the Ccap of a capability with a lower Pcap bit index.
+        assert_eq!(
+            cap(0x4002_0000, &[0x0000, 0xC000]).ht_declaration(),
+            Some(HtDeclaration::Mixed)
+        );
+    }
+
+    #[test]
+    fn test_reconstruct_roi_max_shift() {
+        // With SPrgn = 4 the region of interest holds 16 * 10 and -16 * 3; the
+        // background 15 and -5 stay below 2^4.
+        let mut image = image(8, true, vec![160, -48, 15, -5]);
+        assert_eq!(image.roi_shift(0, 0), 0);
+        image.roi.push(J2kRoi {
+            component_index: 0,
+            roi_style: 0,
+            shift_value: 4,
+        });
+        assert_eq!(image.roi_shift(0, 0), 4);
+        let samples = image.reconstruct_samples();
+        assert_eq!(samples, Ok(J2kSamples::I8(vec![10, -3, 15, -5])));
+
+        // A tile-part RGN replaces the main header one.
+        image.tiles[0].roi.push(J2kRoi {
+            component_index: 0,
+            roi_style: 0,
+            shift_value: 5,
+        });
+        assert_eq!(image.roi_shift(0, 0), 5);
+        assert_eq!(image.roi_shift(0, 1), 0);
+        let samples = image.reconstruct_samples();
+        assert_eq!(samples, Ok(J2kSamples::I8(vec![5, -1, 15, -5])));
+    }
+}
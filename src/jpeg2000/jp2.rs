@@ -1,6 +1,14 @@
 //! JP2 Box structure implementation (ISO/IEC 15444-1 Annex I).
 
+use std::ops::Range;
+
 use crate::error::JpeglsError;
+use crate::jpeg_stream_reader::JpegStreamReader;
+use crate::resolution::Resolution;
+
+use super::parser::J2kParser;
+
+const JP2_SIGNATURE: &[u8] = b"\x00\x00\x00\x0CjP  \r\n\x87\n";
 
 pub struct Jp2Box {
     pub length: u64,
@@ -19,23 +27,83 @@ impl<'a> Jp2Reader<'a> {
     }
 
     pub fn find_codestream(&mut self) -> Result<Option<&'a [u8]>, JpeglsError> {
-        // JP2 signature box is exactly 12 bytes: 00 00 00 0C 6A 50 20 20 0D 0A 87 0A
-        if self.data.len() < 12 {
-            return Ok(None);
+        let mut ranges = Vec::new();
+        self.scan_codestreams(&mut ranges, 1)?;
+        Ok(ranges.pop().map(|range| &self.data[range]))
+    }
+
+    /// Byte ranges of the contiguous codestream boxes (`jp2c`) of a JP2 or JPX file in file
+    /// order, e.g. the frames of an animation or the codestreams of composition layers.
+    /// Empty if the data is not a JP2 container; a box that cannot be read ends the list.
+    pub fn codestreams(&mut self) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        // The codestreams before a malformed box are still usable.
+        let _ = self.scan_codestreams(&mut ranges, usize::MAX);
+        ranges
+    }
+
+    /// Whether a JP2 file received from its start, e.g. in chunks from the network, is
+    /// whole: its top-level boxes run exactly to the end of the data and include a
+    /// codestream box. A codestream box of length 0 runs to the end of the file, so it is
+    /// only whole once it ends with the EOC marker. Boxes still to come after a box that
+    /// ends the data cannot be told apart from the end of the file.
+    pub fn is_complete(&mut self) -> bool {
+        self.position = 0;
+        if !self.data.starts_with(JP2_SIGNATURE) {
+            return false;
         }
 
-        if &self.data[0..12] != b"\x00\x00\x00\x0CjP  \r\n\x87\n" {
+        let mut has_codestream = false;
+        loop {
+            let start = self.position;
+            match self.read_box() {
+                Ok(Some(b)) if b.box_type == *b"jp2c" => {
+                    if self.data[start..start + 4] == [0; 4]
+                        && !self.data[b.data_range].ends_with(&[0xFF, 0xD9])
+                    {
+                        return false;
+                    }
+                    has_codestream = true;
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => return has_codestream && self.position == self.data.len(),
+                Err(_) => return false,
+            }
+        }
+    }
+
+    fn scan_codestreams(
+        &mut self,
+        ranges: &mut Vec<Range<usize>>,
+        limit: usize,
+    ) -> Result<(), JpeglsError> {
+        self.position = 0;
+        if !self.data.starts_with(JP2_SIGNATURE) {
             // Not a JP2 container
-            return Ok(None);
+            return Ok(());
         }
 
-        // It is a JP2 container, proceed to find jp2c
-        while let Some(b) = self.read_box()? {
-            if b.box_type == *b"jp2c" {
-                return Ok(Some(&self.data[b.data_range]));
+        while ranges.len() < limit {
+            let start = self.position;
+            match self.read_box() {
+                Ok(Some(b)) if b.box_type == *b"jp2c" => ranges.push(b.data_range),
+                Ok(Some(_)) => {}
+                Ok(None) => break,
+                // A codestream box cut short, as by a byte-range fetch, holds the bytes
+                // present; a length of 1 is followed by an 8-byte extended length.
+                Err(_) if self.data.get(start + 4..start + 8) == Some(b"jp2c") => {
+                    let header_size = if self.data[start..start + 4] == [0, 0, 0, 1] {
+                        16
+                    } else {
+                        8
+                    };
+                    ranges.push((start + header_size).min(self.data.len())..self.data.len());
+                    break;
+                }
+                Err(e) => return Err(e),
             }
         }
-        Ok(None)
+        Ok(())
     }
 
     /// Finds and extracts the ICC profile from the JP2 colr box.
@@ -52,17 +120,19 @@ impl<'a> Jp2Reader<'a> {
             return Ok(None);
         }
 
-        // Look for the colr box
+        // Look for the colr box, which belongs in the JP2 header box
         while let Some(b) = self.read_box()? {
-            if b.box_type == *b"colr" {
-                let box_data = &self.data[b.data_range.clone()];
-                if box_data.len() < 3 {
-                    continue;
+            if b.box_type == *b"jp2h" {
+                let mut header = Jp2Reader::new(&self.data[b.data_range]);
+                while let Some(b) = header.read_box()? {
+                    if b.box_type == *b"colr" {
+                        if let Some(icc_data) = icc_profile(&header.data[b.data_range]) {
+                            return Ok(Some(icc_data));
+                        }
+                    }
                 }
-                let method = box_data[0];
-                // Method 2 = ICC profile
-                if method == 2 && box_data.len() > 3 {
-                    let icc_data = box_data[3..].to_vec();
+            } else if b.box_type == *b"colr" {
+                if let Some(icc_data) = icc_profile(&self.data[b.data_range]) {
                     return Ok(Some(icc_data));
                 }
             }
@@ -70,6 +140,169 @@ impl<'a> Jp2Reader<'a> {
         Ok(None)
     }
 
+    /// Contents of the XML boxes (`xml `) of a JP2 file in file order, e.g. XMP packets or
+    /// GML application schemas. Empty if the data is not a JP2 container.
+    pub fn find_xml_boxes(&mut self) -> Vec<Vec<u8>> {
+        self.top_level_boxes(*b"xml ")
+            .map(|b| self.data[b.data_range].to_vec())
+            .collect()
+    }
+
+    /// UUID boxes (`uuid`) of a JP2 file in file order, e.g. GeoJP2 GeoTIFF data. Empty if
+    /// the data is not a JP2 container.
+    pub fn find_uuid_boxes(&mut self) -> Vec<Jp2UuidBox> {
+        self.top_level_boxes(*b"uuid")
+            .filter_map(|b| {
+                let contents = &self.data[b.data_range];
+                Some(Jp2UuidBox {
+                    uuid: contents.get(..16)?.try_into().ok()?,
+                    data: contents[16..].to_vec(),
+                })
+            })
+            .collect()
+    }
+
+    /// Channel definitions of a JP2 file's header (`cdef` box), which tell opacity
+    /// channels from colour channels. Empty without the box: every component is then a
+    /// colour channel.
+    pub fn find_channel_definitions(&mut self) -> Vec<Jp2ChannelDefinition> {
+        // N, then N entries of channel index, type and association.
+        let Some((count, entries)) = self
+            .find_header_box(*b"cdef")
+            .and_then(<[u8]>::split_first_chunk)
+        else {
+            return Vec::new();
+        };
+        entries
+            .chunks_exact(6)
+            .take(u16::from_be_bytes(*count) as usize)
+            .map(|entry| Jp2ChannelDefinition {
+                channel: u16::from_be_bytes([entry[0], entry[1]]),
+                channel_type: Jp2ChannelType::from_code(u16::from_be_bytes([entry[2], entry[3]])),
+                association: u16::from_be_bytes([entry[4], entry[5]]),
+            })
+            .collect()
+    }
+
+    /// Palette of an indexed-colour JP2 file (`pclr` box), or `None` without one or if it
+    /// is malformed or has columns deeper than 32 bits. See
+    /// [`find_component_mapping`](Self::find_component_mapping) for the components it
+    /// applies to.
+    pub fn find_palette(&mut self) -> Option<Jp2Palette> {
+        let contents = self.find_header_box(*b"pclr")?;
+        let (&[ne_high, ne_low, column_count], rest) = contents.split_first_chunk::<3>()?;
+        let entry_count = u16::from_be_bytes([ne_high, ne_low]) as usize;
+        let (depths, mut values) = rest.split_at_checked(column_count as usize)?;
+        let columns: Vec<Jp2PaletteColumn> = depths
+            .iter()
+            .map(|&b| Jp2PaletteColumn {
+                depth: (b & 0x7F) + 1,
+                signed: b & 0x80 != 0,
+            })
+            .collect();
+        if columns.iter().any(|column| column.depth > 32) {
+            return None;
+        }
+
+        let mut entries = Vec::with_capacity(entry_count);
+        for _ in 0..entry_count {
+            let mut entry = Vec::with_capacity(columns.len());
+            for column in &columns {
+                // Every value takes whole bytes, big-endian.
+                let (bytes, rest) = values.split_at_checked(column.depth.div_ceil(8) as usize)?;
+                values = rest;
+                let value = bytes.iter().fold(0u64, |v, &b| v << 8 | b as u64);
+                let unused = 64 - column.depth as u32;
+                entry.push(if column.signed {
+                    ((value << unused) as i64 >> unused) as i32
+                } else {
+                    value as i32
+                });
+            }
+            entries.push(entry);
+        }
+        Some(Jp2Palette { columns, entries })
+    }
+
+    /// Component mapping of a JP2 file (`cmap` box): the channels the components make with
+    /// the palette, in order. Empty without the box.
+    pub fn find_component_mapping(&mut self) -> Vec<Jp2ComponentMapping> {
+        let Some(contents) = self.find_header_box(*b"cmap") else {
+            return Vec::new();
+        };
+        contents
+            .chunks_exact(4)
+            .map(|entry| Jp2ComponentMapping {
+                component: u16::from_be_bytes([entry[0], entry[1]]),
+                // Mapping type 1 looks the component up in a palette column.
+                palette_column: (entry[2] == 1).then_some(entry[3]),
+            })
+            .collect()
+    }
+
+    /// Capture resolution of a JP2 file (`resc` box in the `res ` box of its header): the
+    /// density at which the image was scanned or photographed.
+    pub fn find_capture_resolution(&mut self) -> Option<Resolution> {
+        self.find_resolution(*b"resc")
+    }
+
+    /// Default display resolution of a JP2 file (`resd` box in the `res ` box of its
+    /// header).
+    pub fn find_display_resolution(&mut self) -> Option<Resolution> {
+        self.find_resolution(*b"resd")
+    }
+
+    fn find_resolution(&mut self, box_type: [u8; 4]) -> Option<Resolution> {
+        let mut boxes = Jp2Reader::new(self.find_header_box(*b"res ")?);
+        while let Ok(Some(b)) = boxes.read_box() {
+            if b.box_type != box_type {
+                continue;
+            }
+            // Vertical then horizontal numerator, denominator and exponent, giving grid
+            // points per metre as N / D * 10^E.
+            let contents = boxes.data.get(b.data_range)?;
+            let &[vn0, vn1, vd0, vd1, hn0, hn1, hd0, hd1, ve, he] = contents.first_chunk()?;
+            let density = |n: [u8; 2], d: [u8; 2], e: u8| {
+                let denominator = u16::from_be_bytes(d);
+                (denominator != 0).then(|| {
+                    u16::from_be_bytes(n) as f64 / denominator as f64 * 10f64.powi(e as i8 as i32)
+                })
+            };
+            let vertical = density([vn0, vn1], [vd0, vd1], ve)?;
+            let horizontal = density([hn0, hn1], [hd0, hd1], he)?;
+            return Resolution::from_dots_per_metre(horizontal, vertical);
+        }
+        None
+    }
+
+    /// Contents of the first box of the given type in the JP2 header box.
+    fn find_header_box(&mut self, box_type: [u8; 4]) -> Option<&'a [u8]> {
+        let header = self.top_level_boxes(*b"jp2h").next()?;
+        let data = self.data;
+        let mut header = Jp2Reader::new(&data[header.data_range]);
+        while let Ok(Some(b)) = header.read_box() {
+            if b.box_type == box_type {
+                return Some(&header.data[b.data_range]);
+            }
+        }
+        None
+    }
+
+    /// Boxes of the given type at the top level of a JP2 file. A box cut short, as the
+    /// codestream box of a partial file, ends the search.
+    fn top_level_boxes(&mut self, box_type: [u8; 4]) -> impl Iterator<Item = Jp2Box> {
+        self.position = 0;
+        let mut boxes = Vec::new();
+        if self.data.starts_with(JP2_SIGNATURE) {
+            while let Ok(Some(b)) = self.read_box() {
+                if b.box_type == box_type {
+                    boxes.push(b);
+                }
+            }
+        }
+        boxes.into_iter()
+    }
+
     pub fn read_box(&mut self) -> Result<Option<Jp2Box>, JpeglsError> {
         if self.position + 8 > self.data.len() {
             return Ok(None);
@@ -113,12 +346,16 @@ impl<'a> Jp2Reader<'a> {
             length = self.data.len() as u64 - start_pos as u64;
         }
 
-        let data_start = start_pos + header_size;
-        let data_end = start_pos + length as usize;
-
-        if data_end > self.data.len() {
+        // A length shorter than the box header would end the box before its contents.
+        if length < header_size as u64 {
             return Err(JpeglsError::InvalidData);
         }
+        let data_start = start_pos + header_size;
+        let data_end = usize::try_from(length)
+            .ok()
+            .and_then(|length| start_pos.checked_add(length))
+            .filter(|&end| end <= self.data.len())
+            .ok_or(JpeglsError::InvalidData)?;
 
         self.position = data_end;
 
@@ -129,3 +366,609 @@ impl<'a> Jp2Reader<'a> {
         }))
     }
 }
+
+/// ICC profile of a colour specification box, if it has one.
+fn icc_profile(box_data: &[u8]) -> Option<Vec<u8>> {
+    // Method 2 = ICC profile
+    if box_data.len() > 3 && box_data[0] == 2 {
+        Some(box_data[3..].to_vec())
+    } else {
+        None
+    }
+}
+
+/// Role of a channel in the channel definition box (`cdef`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Jp2ChannelType {
+    /// A colour channel, e.g. red or luminance.
+    Color,
+    /// Opacity, from transparent at 0 to opaque at the largest sample value.
+    Opacity,
+    /// Opacity by which the colour channels are already multiplied.
+    PremultipliedOpacity,
+    /// Unspecified or a reserved type.
+    Unspecified,
+}
+
+impl Jp2ChannelType {
+    fn from_code(code: u16) -> Self {
+        match code {
+            0 => Self::Color,
+            1 => Self::Opacity,
+            2 => Self::PremultipliedOpacity,
+            _ => Self::Unspecified,
+        }
+    }
+
+    fn code(self) -> u16 {
+        match self {
+            Self::Color => 0,
+            Self::Opacity => 1,
+            Self::PremultipliedOpacity => 2,
+            Self::Unspecified => 0xFFFF,
+        }
+    }
+
+    /// True for both kinds of opacity.
+    pub fn is_opacity(self) -> bool {
+        matches!(self, Self::Opacity | Self::PremultipliedOpacity)
+    }
+}
+
+/// An entry of the channel definition box (`cdef`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Jp2ChannelDefinition {
+    /// Index of the channel, that is of the component.
+    pub channel: u16,
+    pub channel_type: Jp2ChannelType,
+    /// Colour the channel belongs to, numbered from 1 in the order of the colour space
+    /// (1 is red in sRGB); 0 for the whole image, as for the usual opacity channel, and
+    /// 65535 for none.
+    pub association: u16,
+}
+
+/// Palette of an indexed-colour JP2 file (`pclr` box).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Jp2Palette {
+    /// Precision of every column, e.g. red, green and blue.
+    pub columns: Vec<Jp2PaletteColumn>,
+    /// The entries in index order, each with a value for every column.
+    pub entries: Vec<Vec<i32>>,
+}
+
+/// Precision of one column of a [`Jp2Palette`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Jp2PaletteColumn {
+    /// Bit depth, from 1 to 32.
+    pub depth: u8,
+    pub signed: bool,
+}
+
+/// An entry of the component mapping box (`cmap`): one channel of the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Jp2ComponentMapping {
+    /// Index of the codestream component the channel comes from.
+    pub component: u16,
+    /// Palette column the component's samples are looked up in, or `None` to use them
+    /// directly.
+    pub palette_column: Option<u8>,
+}
+
+/// A UUID box of a JP2 file: data in a format identified by a 16-byte UUID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Jp2UuidBox {
+    pub uuid: [u8; 16],
+    pub data: Vec<u8>,
+}
+
+/// Writes a codestream into a JP2 file (ISO/IEC 15444-1 Annex I).
+///
+/// The file holds the signature, file type and JP2 header boxes, the XML and UUID boxes
+/// added to the writer, and the codestream box. The image header is filled in from the
+/// SIZ marker of the codestream. The colour specification box holds the ICC profile if
+/// one is set, and declares greyscale for one or two channels and sRGB otherwise.
+///
+/// ```
+/// use jpegexp_rs::jpeg2000::jp2::{Jp2Reader, Jp2Writer};
+/// use jpegexp_rs::jpeg2000::encoder::J2kEncoder;
+/// use jpegexp_rs::FrameInfo;
+///
+/// let frame_info = FrameInfo { width: 8, height: 8, bits_per_sample: 8, component_count: 1 };
+/// let mut codestream = vec![0u8; 4096];
+/// let len = J2kEncoder::new().encode(&[128; 64], &frame_info, &mut codestream).unwrap();
+///
+/// let mut writer = Jp2Writer::new();
+/// writer.add_xml(b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>".to_vec());
+/// let jp2 = writer.write(&codestream[..len]).unwrap();
+/// assert_eq!(Jp2Reader::new(&jp2).find_xml_boxes().len(), 1);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Jp2Writer {
+    icc_profile: Option<Vec<u8>>,
+    channel_definitions: Vec<Jp2ChannelDefinition>,
+    palette: Option<(Jp2Palette, Vec<Jp2ComponentMapping>)>,
+    resolution: Option<Resolution>,
+    xml_boxes: Vec<Vec<u8>>,
+    uuid_boxes: Vec<Jp2UuidBox>,
+}
+
+impl Jp2Writer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the ICC profile written to the colour specification box.
+    pub fn set_icc_profile(&mut self, profile: Vec<u8>) {
+        self.icc_profile = Some(profile);
+    }
+
+    /// Sets the channel definitions written to a `cdef` box, e.g. to mark the fourth
+    /// component of an RGBA image as opacity.
+    pub fn set_channel_definitions(&mut self, definitions: Vec<Jp2ChannelDefinition>) {
+        self.channel_definitions = definitions;
+    }
+
+    /// Sets the palette (`pclr` box) and component mapping (`cmap` box) of an
+    /// indexed-colour image, whose codestream holds palette indices.
+    pub fn set_palette(&mut self, palette: Jp2Palette, mapping: Vec<Jp2ComponentMapping>) {
+        self.palette = Some((palette, mapping));
+    }
+
+    /// Sets the capture resolution written to a `resc` box.
+    pub fn set_resolution(&mut self, resolution: Resolution) {
+        self.resolution = Some(resolution);
+    }
+
+    /// Adds an XML box with the given contents.
+    pub fn add_xml(&mut self, contents: Vec<u8>) {
+        self.xml_boxes.push(contents);
+    }
+
+    /// Adds a UUID box with the given UUID and data.
+    pub fn add_uuid(&mut self, uuid: [u8; 16], data: Vec<u8>) {
+        self.uuid_boxes.push(Jp2UuidBox { uuid, data });
+    }
+
+    /// Returns the JP2 file holding `codestream`.
+    pub fn write(&self, codestream: &[u8]) -> Result<Vec<u8>, JpeglsError> {
+        let mut reader = JpegStreamReader::new(codestream);
+        let mut parser = J2kParser::new(&mut reader);
+        parser.parse_main_header()?;
+        let image = &parser.image;
+        let component_count =
+            u16::try_from(image.component_count).map_err(|_| JpeglsError::InvalidData)?;
+        // Ssiz of the components, or 255 with a bits per component box if they differ.
+        let depths: Vec<u8> = image
+            .components
+            .iter()
+            .map(|c| (c.depth.saturating_sub(1)) | (u8::from(c.is_signed) << 7))
+            .collect();
+        let depth = match depths.split_first() {
+            Some((&first, rest)) if rest.iter().all(|&d| d == first) => first,
+            _ => 0xFF,
+        };
+
+        let (width, height) = image.size();
+        let mut ihdr = Vec::with_capacity(14);
+        ihdr.extend_from_slice(&height.to_be_bytes());
+        ihdr.extend_from_slice(&width.to_be_bytes());
+        ihdr.extend_from_slice(&component_count.to_be_bytes());
+        // Compression type 7, colour space known, no intellectual property box.
+        ihdr.extend_from_slice(&[depth, 7, 0, 0]);
+        let mut header = Vec::new();
+        write_box(&mut header, b"ihdr", &ihdr);
+        if depth == 0xFF {
+            write_box(&mut header, b"bpcc", &depths);
+        }
+        if let Some((palette, mapping)) = &self.palette {
+            write_box(&mut header, b"pclr", &palette_box(palette)?);
+            let cmap: Vec<u8> = mapping
+                .iter()
+                .flat_map(|m| {
+                    let [high, low] = m.component.to_be_bytes();
+                    [
+                        high,
+                        low,
+                        m.palette_column.is_some() as u8,
+                        m.palette_column.unwrap_or(0),
+                    ]
+                })
+                .collect();
+            write_box(&mut header, b"cmap", &cmap);
+        }
+        let channel_count = self
+            .palette
+            .as_ref()
+            .map_or(component_count as usize, |(_, mapping)| mapping.len());
+        let colr = match &self.icc_profile {
+            Some(profile) => [&[2, 0, 0][..], profile].concat(),
+            // Enumerated colour space 17 (greyscale) or 16 (sRGB).
+            None => vec![1, 0, 0, 0, 0, 0, if channel_count <= 2 { 17 } else { 16 }],
+        };
+        write_box(&mut header, b"colr", &colr);
+        if !self.channel_definitions.is_empty() {
+            let count = u16::try_from(self.channel_definitions.len())
+                .map_err(|_| JpeglsError::InvalidArgument)?;
+            let mut cdef = count.to_be_bytes().to_vec();
+            for definition in &self.channel_definitions {
+                cdef.extend_from_slice(&definition.channel.to_be_bytes());
+                cdef.extend_from_slice(&definition.channel_type.code().to_be_bytes());
+                cdef.extend_from_slice(&definition.association.to_be_bytes());
+            }
+            write_box(&mut header, b"cdef", &cdef);
+        }
+        if let Some(resolution) = self.resolution {
+            let mut resc = Vec::with_capacity(10);
+            let vertical = resolution_fraction(resolution.y_dpi);
+            let horizontal = resolution_fraction(resolution.x_dpi);
+            resc.extend_from_slice(&vertical.0.to_be_bytes());
+            resc.extend_from_slice(&254u16.to_be_bytes());
+            resc.extend_from_slice(&horizontal.0.to_be_bytes());
+            resc.extend_from_slice(&254u16.to_be_bytes());
+            resc.extend_from_slice(&[vertical.1 as u8, horizontal.1 as u8]);
+            let mut res = Vec::new();
+            write_box(&mut res, b"resc", &resc);
+            write_box(&mut header, b"res ", &res);
+        }
+
+        let mut file = JP2_SIGNATURE.to_vec();
+        write_box(&mut file, b"ftyp", b"jp2 \0\0\0\0jp2 ");
+        write_box(&mut file, b"jp2h", &header);
+        for contents in &self.xml_boxes {
+            write_box(&mut file, b"xml ", contents);
+        }
+        for uuid_box in &self.uuid_boxes {
+            write_box(
+                &mut file,
+                b"uuid",
+                &[&uuid_box.uuid[..], &uuid_box.data].concat(),
+            );
+        }
+        write_box(&mut file, b"jp2c", codestream);
+        Ok(file)
+    }
+}
+
+/// Numerator and exponent of a density in grid points per metre over a denominator of 254:
+/// dpi / 254 * 10^4 is exact, and larger densities give up their last digits.
+fn resolution_fraction(dpi: u32) -> (u16, i8) {
+    let (mut numerator, mut exponent) = (dpi, 4);
+    while numerator > u16::MAX as u32 {
+        numerator /= 10;
+        exponent += 1;
+    }
+    (numerator as u16, exponent)
+}
+
+/// Contents of the palette box: the entry and column counts, the column precisions and
+/// the values, each in whole bytes.
+fn palette_box(palette: &Jp2Palette) -> Result<Vec<u8>, JpeglsError> {
+    let entry_count = u16::try_from(palette.entries.len())
+        .ok()
+        .filter(|&n| (1..=1024).contains(&n))
+        .ok_or(JpeglsError::InvalidArgument)?;
+    let column_count =
+        u8::try_from(palette.columns.len()).map_err(|_| JpeglsError::InvalidArgument)?;
+    if palette.columns.iter().any(|c| !(1..=32).contains(&c.depth)) {
+        return Err(JpeglsError::InvalidArgument);
+    }
+    let mut contents = entry_count.to_be_bytes().to_vec();
+    contents.push(column_count);
+    contents.extend(
+        palette
+            .columns
+            .iter()
+            .map(|c| (c.depth - 1) | (u8::from(c.signed) << 7)),
+    );
+    for entry in &palette.entries {
+        if entry.len() != palette.columns.len() {
+            return Err(JpeglsError::InvalidArgument);
+        }
+        for (&value, column) in entry.iter().zip(&palette.columns) {
+            let bytes = (value as u32).to_be_bytes();
+            contents.extend_from_slice(&bytes[4 - column.depth.div_ceil(8) as usize..]);
+        }
+    }
+    Ok(contents)
+}
+
+/// Appends a box, with an extended length if it does not fit in 32 bits.
+fn write_box(file: &mut Vec<u8>, box_type: &[u8; 4], contents: &[u8]) {
+    match u32::try_from(contents.len() + 8) {
+        Ok(length) => {
+            file.extend_from_slice(&length.to_be_bytes());
+            file.extend_from_slice(box_type);
+        }
+        Err(_) => {
+            file.extend_from_slice(&1u32.to_be_bytes());
+            file.extend_from_slice(box_type);
+            file.extend_from_slice(&(contents.len() as u64 + 16).to_be_bytes());
+        }
+    }
+    file.extend_from_slice(contents);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jpeg2000::decoder::J2kDecoder;
+    use crate::jpeg2000::encoder::J2kEncoder;
+    use crate::jpeg2000::validate::validate;
+    use crate::{Decoder, FrameInfo, OutputLayout};
+
+    fn encode(frame_info: &FrameInfo) -> Vec<u8> {
+        let size = frame_info.decoded_size(OutputLayout::Interleaved).unwrap();
+        let pixels = vec![128u8; size];
+        let mut codestream = vec![0u8; 8192];
+        let len = J2kEncoder::new()
+            .encode(&pixels, frame_info, &mut codestream)
+            .unwrap();
+        codestream.truncate(len);
+        codestream
+    }
+
+    #[test]
+    fn test_metadata_boxes_survive_transcoding() {
+        let frame_info = FrameInfo {
+            width: 24,
+            height: 16,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let xmp = b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>".to_vec();
+        let gml = b"<gml:FeatureCollection/>".to_vec();
+        let geojp2 = [
+            0xB1, 0x4B, 0xF8, 0xBD, 0x08, 0x3D, 0x4B, 0x43, 0xA5, 0xAE, 0x8C, 0xD7, 0xD5, 0xA6,
+            0xCE, 0x03,
+        ];
+        let mut writer = Jp2Writer::new();
+        writer.add_xml(xmp.clone());
+        writer.add_xml(gml.clone());
+        writer.add_uuid(geojp2, b"GeoTIFF".to_vec());
+        let jp2 = writer.write(&encode(&frame_info)).unwrap();
+        let report = validate(&jp2);
+        assert!(report.is_valid(), "{:?}", report.errors);
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+
+        // Decode, re-encode and copy the metadata boxes into the new file.
+        let image = Decoder::auto(&jp2).unwrap();
+        assert_eq!(image.metadata.xml_boxes, [xmp.clone(), gml.clone()]);
+        let mut writer = Jp2Writer::new();
+        for contents in &image.metadata.xml_boxes {
+            writer.add_xml(contents.clone());
+        }
+        for uuid_box in &image.metadata.uuid_boxes {
+            writer.add_uuid(uuid_box.uuid, uuid_box.data.clone());
+        }
+        let transcoded = writer.write(&encode(&frame_info)).unwrap();
+        let mut reader = Jp2Reader::new(&transcoded);
+        assert_eq!(reader.find_xml_boxes(), [xmp, gml]);
+        assert_eq!(
+            reader.find_uuid_boxes(),
+            [Jp2UuidBox {
+                uuid: geojp2,
+                data: b"GeoTIFF".to_vec()
+            }]
+        );
+        // A codestream is not a JP2 container.
+        assert!(Jp2Reader::new(&encode(&frame_info))
+            .find_xml_boxes()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_write_greyscale_and_icc_profile() {
+        let frame_info = FrameInfo {
+            width: 8,
+            height: 8,
+            bits_per_sample: 8,
+            component_count: 1,
+        };
+        let codestream = encode(&frame_info);
+        let jp2 = Jp2Writer::new().write(&codestream).unwrap();
+        assert!(validate(&jp2).is_valid());
+        assert_eq!(
+            Jp2Reader::new(&jp2).find_codestream(),
+            Ok(Some(&codestream[..]))
+        );
+
+        let mut writer = Jp2Writer::new();
+        writer.set_icc_profile(vec![1, 2, 3, 4]);
+        let jp2 = writer.write(&codestream).unwrap();
+        assert_eq!(
+            Jp2Reader::new(&jp2).find_icc_profile(),
+            Ok(Some(vec![1, 2, 3, 4]))
+        );
+        assert!(Jp2Writer::new().write(&jp2).is_err());
+    }
+
+    #[test]
+    fn test_decode_selected_codestream() {
+        let frame = |width| FrameInfo {
+            width,
+            height: 8,
+            bits_per_sample: 8,
+            component_count: 1,
+        };
+        let first = encode(&frame(8));
+        let second = encode(&frame(16));
+        let mut jpx = Jp2Writer::new().write(&first).unwrap();
+        write_box(&mut jpx, b"jp2c", &second);
+
+        let codestreams = Jp2Reader::new(&jpx).codestreams();
+        assert_eq!(codestreams.len(), 2);
+        assert_eq!(jpx[codestreams[0].clone()], first);
+        assert_eq!(jpx[codestreams[1].clone()], second);
+
+        let decode = |index| {
+            let mut reader = JpegStreamReader::new(&jpx);
+            let mut decoder = J2kDecoder::new(&mut reader);
+            decoder.set_codestream(index);
+            decoder.decode().map(|image| image.width)
+        };
+        assert_eq!(decode(0), Ok(8));
+        assert_eq!(decode(1), Ok(16));
+        assert_eq!(decode(2), Err(JpeglsError::InvalidArgument));
+
+        // The last codestream box cut short holds the bytes present.
+        let cut = &jpx[..jpx.len() - 10];
+        let codestreams = Jp2Reader::new(cut).codestreams();
+        assert_eq!(codestreams[1], codestreams[1].start..cut.len());
+
+        // Lengths shorter than the box header are rejected, not sliced backwards.
+        for length in 2u32..8 {
+            let mut file = JP2_SIGNATURE.to_vec();
+            file.extend_from_slice(&length.to_be_bytes());
+            file.extend_from_slice(b"xml ");
+            file.extend_from_slice(&[0; 16]);
+            write_box(&mut file, b"jp2c", &first);
+            assert_eq!(
+                Jp2Reader::new(&file).find_codestream(),
+                Err(JpeglsError::InvalidData)
+            );
+            assert!(Jp2Reader::new(&file).codestreams().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_is_complete() {
+        let frame_info = FrameInfo {
+            width: 8,
+            height: 8,
+            bits_per_sample: 8,
+            component_count: 1,
+        };
+        let codestream = encode(&frame_info);
+        let jp2 = Jp2Writer::new().write(&codestream).unwrap();
+        // A box after the codestream box: the file no longer ends with EOC, and the data
+        // up to the end of the codestream box looks like a whole file.
+        let mut file = jp2.clone();
+        write_box(&mut file, b"xml ", b"<metadata/>");
+        for len in 0..file.len() {
+            let complete = Jp2Reader::new(&file[..len]).is_complete();
+            assert_eq!(complete, len == jp2.len(), "{len}");
+        }
+        assert!(Jp2Reader::new(&file).is_complete());
+
+        // A codestream box of length 0 runs to the end of the file.
+        let mut file = jp2[..jp2.len() - codestream.len() - 8].to_vec();
+        file.extend_from_slice(&[0, 0, 0, 0]);
+        file.extend_from_slice(b"jp2c");
+        file.extend_from_slice(&codestream[..codestream.len() - 2]);
+        assert!(!Jp2Reader::new(&file).is_complete());
+        file.extend_from_slice(&[0xFF, 0xD9]);
+        assert!(Jp2Reader::new(&file).is_complete());
+
+        // Without a codestream box, or not a JP2 file at all.
+        assert!(!Jp2Reader::new(&jp2[..jp2.len() - codestream.len() - 8]).is_complete());
+        assert!(!Jp2Reader::new(&codestream).is_complete());
+    }
+
+    #[test]
+    fn test_channel_definitions() {
+        let frame_info = FrameInfo {
+            width: 8,
+            height: 8,
+            bits_per_sample: 8,
+            component_count: 4,
+        };
+        let codestream = encode(&frame_info);
+        let image = Decoder::auto(&Jp2Writer::new().write(&codestream).unwrap()).unwrap();
+        assert!(!image.has_alpha());
+
+        let color = |channel| Jp2ChannelDefinition {
+            channel,
+            channel_type: Jp2ChannelType::Color,
+            association: channel + 1,
+        };
+        let definitions = vec![
+            color(0),
+            color(1),
+            color(2),
+            Jp2ChannelDefinition {
+                channel: 3,
+                channel_type: Jp2ChannelType::PremultipliedOpacity,
+                association: 0,
+            },
+        ];
+        let mut writer = Jp2Writer::new();
+        writer.set_channel_definitions(definitions.clone());
+        let jp2 = writer.write(&codestream).unwrap();
+        assert!(validate(&jp2).is_valid());
+        assert_eq!(Jp2Reader::new(&jp2).find_channel_definitions(), definitions);
+        let image = Decoder::auto(&jp2).unwrap();
+        assert!(image.has_alpha());
+        assert_eq!(image.metadata.channel_definitions, definitions);
+    }
+
+    #[test]
+    fn test_palette_expands_to_rgb() {
+        let frame_info = FrameInfo {
+            width: 8,
+            height: 4,
+            bits_per_sample: 8,
+            component_count: 1,
+        };
+        // Every pixel has index 128.
+        let codestream = encode(&frame_info);
+        let raw = Decoder::auto(&codestream).unwrap().pixels;
+        assert_eq!(raw, [128; 32]);
+
+        let palette = Jp2Palette {
+            columns: vec![
+                Jp2PaletteColumn {
+                    depth: 8,
+                    signed: false,
+                },
+                Jp2PaletteColumn {
+                    depth: 12,
+                    signed: false,
+                },
+                Jp2PaletteColumn {
+                    depth: 8,
+                    signed: true,
+                },
+            ],
+            entries: (0..256)
+                .map(|i| vec![255 - i, i * 16, i / 2 - 64])
+                .collect(),
+        };
+        // Three channels from the palette, then the index itself.
+        let mut mapping: Vec<Jp2ComponentMapping> = (0..3)
+            .map(|column| Jp2ComponentMapping {
+                component: 0,
+                palette_column: Some(column),
+            })
+            .collect();
+        mapping.push(Jp2ComponentMapping {
+            component: 0,
+            palette_column: None,
+        });
+        let mut writer = Jp2Writer::new();
+        writer.set_palette(palette.clone(), mapping.clone());
+        let jp2 = writer.write(&codestream).unwrap();
+        assert!(validate(&jp2).is_valid());
+        let mut reader = Jp2Reader::new(&jp2);
+        assert_eq!(reader.find_palette(), Some(palette));
+        assert_eq!(reader.find_component_mapping(), mapping);
+
+        let image = Decoder::auto(&jp2).unwrap();
+        assert_eq!(image.frame_info.component_count, 4);
+        assert_eq!(image.frame_info.bits_per_sample, 12);
+        let pixel: Vec<u8> = [127u16, 2048, 0, 128]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        assert_eq!(image.pixels, pixel.repeat(32));
+
+        // Without the palette the raw index plane is decoded.
+        let image = Decoder::auto(&Jp2Writer::new().write(&codestream).unwrap()).unwrap();
+        assert_eq!(image.pixels, raw);
+    }
+}
@@ -0,0 +1,88 @@
+//! Multiple component transforms (Annex G).
+//!
+//! When COD signals a multiple component transform, the first three components hold
+//! Y, Cb and Cr instead of R, G and B. The reversible colour transform (RCT) pairs with the
+//! 5-3 wavelet and is exact on integers; the irreversible colour transform (ICT) pairs with
+//! the 9-7 wavelet. Samples are DC level shifted (centred on zero) in both directions.
+
+/// Forward RCT (G.2): R, G, B in the three slices are replaced by Y, Cb, Cr.
+pub fn forward_rct(c0: &mut [i32], c1: &mut [i32], c2: &mut [i32]) {
+    for ((c0, c1), c2) in c0.iter_mut().zip(c1.iter_mut()).zip(c2.iter_mut()) {
+        let (r, g, b) = (*c0, *c1, *c2);
+        *c0 = (r + 2 * g + b) >> 2;
+        *c1 = b - g;
+        *c2 = r - g;
+    }
+}
+
+/// Inverse RCT (G.3): Y, Cb, Cr in the three slices are replaced by R, G, B.
+pub fn inverse_rct(c0: &mut [i32], c1: &mut [i32], c2: &mut [i32]) {
+    for ((c0, c1), c2) in c0.iter_mut().zip(c1.iter_mut()).zip(c2.iter_mut()) {
+        let (y, cb, cr) = (*c0, *c1, *c2);
+        let g = y - ((cb + cr) >> 2);
+        *c0 = cr + g;
+        *c1 = g;
+        *c2 = cb + g;
+    }
+}
+
+/// Forward ICT (G.4): R, G, B in the three slices are replaced by Y, Cb, Cr.
+pub fn forward_ict(c0: &mut [f32], c1: &mut [f32], c2: &mut [f32]) {
+    for ((c0, c1), c2) in c0.iter_mut().zip(c1.iter_mut()).zip(c2.iter_mut()) {
+        let (r, g, b) = (*c0, *c1, *c2);
+        *c0 = 0.299 * r + 0.587 * g + 0.114 * b;
+        *c1 = -0.168_736 * r - 0.331_264 * g + 0.5 * b;
+        *c2 = 0.5 * r - 0.418_688 * g - 0.081_312 * b;
+    }
+}
+
+/// Inverse ICT (G.5): Y, Cb, Cr in the three slices are replaced by R, G, B.
+pub fn inverse_ict(c0: &mut [f32], c1: &mut [f32], c2: &mut [f32]) {
+    for ((c0, c1), c2) in c0.iter_mut().zip(c1.iter_mut()).zip(c2.iter_mut()) {
+        let (y, cb, cr) = (*c0, *c1, *c2);
+        *c0 = y + 1.402 * cr;
+        *c1 = y - 0.344_136 * cb - 0.714_136 * cr;
+        *c2 = y + 1.772 * cb;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rct_roundtrip() {
+        let r: Vec<i32> = (-128..128).collect();
+        let g: Vec<i32> = r.iter().map(|v| (v * 7) % 128).collect();
+        let b: Vec<i32> = r.iter().rev().copied().collect();
+        let (mut c0, mut c1, mut c2) = (r.clone(), g.clone(), b.clone());
+        forward_rct(&mut c0, &mut c1, &mut c2);
+        inverse_rct(&mut c0, &mut c1, &mut c2);
+        assert_eq!((c0, c1, c2), (r, g, b));
+    }
+
+    #[test]
+    fn test_ict_roundtrip() {
+        let (mut c0, mut c1, mut c2) = (vec![100.0, -50.0], vec![-20.0, 0.0], vec![3.5, 127.0]);
+        forward_ict(&mut c0, &mut c1, &mut c2);
+        // Grey has no chroma.
+        let (mut y, mut cb, mut cr) = ([10.0f32], [10.0f32], [10.0f32]);
+        forward_ict(&mut y, &mut cb, &mut cr);
+        assert!(cb[0].abs() < 1e-4 && cr[0].abs() < 1e-4);
+
+        inverse_ict(&mut c0, &mut c1, &mut c2);
+        for (actual, expected) in c0
+            .iter()
+            .chain(&c1)
+            .chain(&c2)
+            .zip([100.0, -50.0, -20.0, 0.0, 3.5, 127.0])
+        {
+            assert!(
+                (actual - expected).abs() < 1e-3,
+                "{} != {}",
+                actual,
+                expected
+            );
+        }
+    }
+}
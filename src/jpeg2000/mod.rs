@@ -5,24 +5,71 @@
 //!
 //! - `parser` / `writer`: Handling of the Codestream syntax (Markers, Headers).
 //! - `packet`: Parsing and writing of Tile-Part packets and packet headers.
+//! - `progression`: Packet progression orders and POC progression volumes.
 //! - `tag_tree`: Implementation of Tag Trees (used in packet headers).
 //! - `image`: Data structures representing the Image, Tiles, Components, and Code-blocks.
 //! - `mq_coder`: The MQ Arithmetic Coder (Tier-1 Coding).
 //! - `bit_plane_coder`: Context modeling and bit-plane coding (Tier-1 Coding).
 //! - `dwt`: Discrete Wavelet Transform (5-3 and 9-7).
+//! - `dwt_gpu`: The batched inverse DWT on the GPU (feature `gpu`).
+//! - `mct`: Multiple component transforms (RCT and ICT).
 //! - `quantization`: Scalar quantization.
+//! - `validate`: Structural validation of codestreams and JP2 files.
+//!
+//! With the `trace-j2k` feature the decoder reports packet and code-block events at
+//! `trace` level and wraps the parser, tier-1 and IDWT stages in `debug` spans through
+//! the `tracing` crate; a subscriber such as `tracing-subscriber` with span close events
+//! shows the time spent per stage. Without the feature nothing is emitted.
+
+// Decoder events through `tracing` with the `trace-j2k` feature. The arguments are still
+// type checked without it, but never evaluated.
+#[cfg(feature = "trace-j2k")]
+macro_rules! j2k_trace {
+    ($($arg:tt)*) => {
+        tracing::trace!($($arg)*)
+    };
+}
+
+#[cfg(not(feature = "trace-j2k"))]
+macro_rules! j2k_trace {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    };
+}
+
+// Enters a `debug` span named `$name` until `$guard` goes out of scope.
+#[cfg(feature = "trace-j2k")]
+macro_rules! j2k_span {
+    ($guard:ident, $name:literal) => {
+        let $guard = tracing::debug_span!($name).entered();
+    };
+}
+
+#[cfg(not(feature = "trace-j2k"))]
+macro_rules! j2k_span {
+    ($guard:ident, $name:literal) => {};
+}
 
 pub mod bit_io;
 pub mod bit_plane_coder;
 pub mod decoder;
 pub mod dwt;
+#[cfg(feature = "gpu")]
+pub mod dwt_gpu;
 pub mod encoder;
 pub mod ht_block_coder;
 pub mod image;
 pub mod jp2;
+pub mod mct;
 pub mod mq_coder;
 pub mod packet;
+pub mod progression;
 pub mod parser;
 pub mod quantization;
 pub mod tag_tree;
+pub mod validate;
 pub mod writer;
+
+pub use validate::{validate, ValidationIssue, ValidationReport};
@@ -343,14 +343,16 @@ impl MqCoder {
     }
 
     pub fn init_contexts(&mut self, size: usize) {
-        self.contexts = vec![0; size];
+        self.contexts.clear();
+        self.contexts.resize(size, 0);
     }
 
     // ... (Encoder methods omitted or assumed present) ...
 
     // Decoder Initialization (C.3.1) - Following OpenJPEG's approach
     pub fn init_decoder(&mut self, data: &[u8]) {
-        self.source = data.to_vec();
+        self.source.clear();
+        self.source.extend_from_slice(data);
         self.src_pos = 0;
         self.ct = 0;
 
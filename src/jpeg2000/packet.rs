@@ -1,10 +1,26 @@
 use super::bit_io::{BitIoError, J2kBitReader};
 use super::tag_tree::TagTree;
 
+/// Code-blocks of a precinct in one subband (B.7): the index of the first one in
+/// the subband and how many there are across and down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CodeBlockGrid {
+    pub x0: usize,
+    pub y0: usize,
+    pub wide: usize,
+    pub high: usize,
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SubbandState {
     pub inclusion_tree: TagTree,
     pub zero_bp_tree: TagTree,
-    pub lblock_tree: TagTree,
+    /// Lblock of every code-block, which starts at 3 and only grows (B.10.7.1).
+    pub lblock: Vec<u8>,
+    /// Coding passes included so far for every code-block.
+    pub passes: Vec<u8>,
 }
 
 impl SubbandState {
@@ -12,27 +28,40 @@ impl SubbandState {
         Self {
             inclusion_tree: TagTree::new(w, h),
             zero_bp_tree: TagTree::new(w, h),
-            lblock_tree: TagTree::new(w, h),
+            lblock: vec![3; w * h],
+            passes: vec![0; w * h],
         }
     }
 
     pub fn reset(&mut self) {
         self.inclusion_tree.reset();
         self.zero_bp_tree.reset();
-        self.lblock_tree.reset();
+        self.lblock.fill(3);
+        self.passes.fill(0);
     }
 }
 
-/// Represents the state of a Precinct during parsing.
+/// Represents the state of a Precinct during parsing. It lives as long as the tile,
+/// so the tag trees carry over from one layer, and tile-part, to the next.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PrecinctState {
     /// Trees for each subband (resolution 0 has 1, others have 3)
     pub subbands: Vec<SubbandState>,
+    /// Code-block grid of each subband
+    pub code_blocks: Vec<CodeBlockGrid>,
 }
 
 impl PrecinctState {
-    pub fn new(_w: usize, _h: usize) -> Self {
-        let subbands = Vec::with_capacity(3);
-        Self { subbands }
+    pub fn new(code_blocks: &[CodeBlockGrid]) -> Self {
+        let subbands = code_blocks
+            .iter()
+            .map(|grid| SubbandState::new(grid.wide, grid.high))
+            .collect();
+        Self {
+            subbands,
+            code_blocks: code_blocks.to_vec(),
+        }
     }
 
     pub fn reset(&mut self) {
@@ -57,18 +86,20 @@ pub struct CodeBlockInfo {
     pub included: bool,
     pub num_passes: u8,
     pub data_len: u32,
+    /// Lengths of the codeword segments making up the data, in order.
+    pub segment_lengths: Vec<u32>,
     pub zero_bp: u8,
 }
 
 impl PacketHeader {
     /// Read a packet header from the bit stream.
+    /// With `ht` the code-blocks are HT coded and signal the lengths of their cleanup
+    /// and refinement segments separately (ISO/IEC 15444-15, B.10.7).
     pub fn read(
         reader: &mut J2kBitReader<'_, '_>,
         state: &mut PrecinctState,
         layer: u32,
-        grid_width: usize,
-        grid_height: usize,
-        num_subbands: usize,
+        ht: bool,
     ) -> Result<Self, BitIoError> {
         let mut header = PacketHeader {
             packet_seq_num: 0,
@@ -79,26 +110,26 @@ impl PacketHeader {
 
         // 1. Zero-length packet bit
         let bit = reader.read_bit()?;
-        if std::env::var("J2K_DEBUG").is_ok() {
-            eprintln!("PACKET: layer={}, grid={}x{}, subbands={}, empty_bit={}", 
-                layer, grid_width, grid_height, num_subbands, bit);
-        }
+        j2k_trace!(
+            "packet: layer={}, code_blocks={:?}, empty_bit={}",
+            layer,
+            state.code_blocks,
+            bit
+        );
         if bit == 0 {
             header.empty = true;
             return Ok(header);
         }
 
         // 2. Code-block inclusion and header info
-        for s in 0..num_subbands {
-            if state.subbands.len() <= s {
-                state
-                    .subbands
-                    .push(SubbandState::new(grid_width, grid_height));
-            }
-            let subband_state = &mut state.subbands[s];
-
-            for y in 0..grid_height {
-                for x in 0..grid_width {
+        for (s, (subband_state, grid)) in state
+            .subbands
+            .iter_mut()
+            .zip(&state.code_blocks)
+            .enumerate()
+        {
+            for y in 0..grid.high {
+                for x in 0..grid.wide {
                     // Determine inclusion
                     let threshold = (layer + 1) as i32;
                     // A codeblock is "already included" only if we have decoded its exact
@@ -131,25 +162,48 @@ impl PacketHeader {
                         // Decode Number of Passes
                         let num_passes = Self::read_coding_passes(reader)?;
 
-                        // Data Length
-                        // Decode LBlock parameter with arbitrary threshold (32)
-                        let _ = subband_state.lblock_tree.decode(reader, x, y, 32)?;
-                        let lbits = subband_state.lblock_tree.get_current_value(x, y) + 3;
-
-                        let data_len = reader.read_bits(lbits as u8)?;
-
-                        if std::env::var("J2K_DEBUG").is_ok() {
-                            eprintln!("  CB[{},{}] subband={}: zero_bp={}, passes={}, lbits={}, len={}",
-                                x, y, s, zero_bp, num_passes, lbits, data_len);
+                        // Data Length: Lblock grows by the number of 1 bits before a 0.
+                        let lblock = &mut subband_state.lblock[y * grid.wide + x];
+                        while reader.read_bit()? == 1 {
+                            *lblock = lblock.saturating_add(1);
                         }
+                        let lbits = *lblock;
 
-                        header.included_cblks.push(CodeBlockInfo {
+                        let segment_lengths = if ht {
+                            let seen = &mut subband_state.passes[y * grid.wide + x];
+                            let segments = Self::ht_segment_passes(*seen, num_passes);
+                            *seen = seen.saturating_add(num_passes);
+                            segments
+                                .into_iter()
+                                .map(|passes| {
+                                    reader.read_bits((lbits as u32 + passes.ilog2()) as u8)
+                                })
+                                .collect::<Result<Vec<_>, _>>()?
+                        } else {
+                            let bits = lbits as u32 + num_passes.ilog2();
+                            vec![reader.read_bits(bits as u8)?]
+                        };
+                        let data_len = segment_lengths.iter().sum();
+
+                        j2k_trace!(
+                            "code-block ({}, {}) subband={}: zero_bp={}, passes={}, lbits={}, len={}",
                             x,
                             y,
+                            s,
+                            zero_bp,
+                            num_passes,
+                            lbits,
+                            data_len
+                        );
+
+                        header.included_cblks.push(CodeBlockInfo {
+                            x: grid.x0 + x,
+                            y: grid.y0 + y,
                             subband_index: s as u8,
                             included: true,
                             num_passes,
                             data_len,
+                            segment_lengths,
                             zero_bp,
                         });
                     }
@@ -160,40 +214,50 @@ impl PacketHeader {
         Ok(header)
     }
 
+    /// Splits the `passes` new coding passes of an HT code-block having `seen` passes
+    /// already into the number of passes of each codeword segment they contribute to.
+    /// The first contribution holds the cleanup pass, after any placeholder passes
+    /// (whole HT sets without data), in a segment of its own; the SigProp and MagRef
+    /// passes after it share the refinement segment.
+    fn ht_segment_passes(seen: u8, passes: u8) -> Vec<u8> {
+        if seen > 0 {
+            return vec![passes];
+        }
+        let refinement = (passes - 1) % 3;
+        if refinement == 0 {
+            vec![passes]
+        } else {
+            vec![passes - refinement, refinement]
+        }
+    }
+
     /// Reads the number of coding passes using J2K codeword table (Table B.4).
     fn read_coding_passes(reader: &mut J2kBitReader<'_, '_>) -> Result<u8, BitIoError> {
         if reader.read_bit()? == 0 {
-            // eprintln!("DEBUG: passes codework 0 -> 1");
             return Ok(1);
         }
         if reader.read_bit()? == 0 {
-            // eprintln!("DEBUG: passes codework 10 -> 2");
             return Ok(2);
         }
         let bits = reader.read_bits(2)?;
         if bits < 3 {
-            // eprintln!("DEBUG: passes codeword 11{} -> {}", bits, 3 + bits);
             return Ok((3 + bits) as u8);
         }
         let bits = reader.read_bits(5)?;
         if bits < 31 {
-            // eprintln!("DEBUG: passes codeword 1111{} -> {}", bits, 6 + bits);
             return Ok((6 + bits) as u8);
         }
-        // Extension: 32 + 5 bits... (Very rare for typical images)
-        let bits2 = reader.read_bits(5)?;
-        // eprintln!("DEBUG: passes codeword extension -> {}", 37 + bits2);
+        // Extension: 9 ones and 7 bits for 37 to 164 passes.
+        let bits2 = reader.read_bits(7)?;
         Ok((37 + bits2) as u8)
     }
 
-    /// Write a packet header to the bit stream.
+    /// Write a packet header to the bit stream, the counterpart of `read` for Part 1
+    /// code-blocks. Code-blocks without an entry in `included_cblks` are left out.
     pub fn write(
         &self,
         writer: &mut crate::jpeg2000::bit_io::J2kBitWriter,
         state: &mut PrecinctState,
-        grid_width: usize,
-        grid_height: usize,
-        num_subbands: usize,
     ) {
         if self.empty {
             writer.write_bit(0);
@@ -201,93 +265,209 @@ impl PacketHeader {
         }
         writer.write_bit(1);
 
-        for s in 0..num_subbands {
-            if state.subbands.len() <= s {
-                state
-                    .subbands
-                    .push(SubbandState::new(grid_width, grid_height));
-            }
-            let subband_state = &mut state.subbands[s];
-
-            for y in 0..grid_height {
-                for x in 0..grid_width {
-                    let cb_info = self
-                        .included_cblks
-                        .iter()
-                        .find(|c| c.x == x && c.y == y && c.subband_index == s as u8);
+        let layer = self.layer_index as i32;
+        for (s, (subband_state, grid)) in state
+            .subbands
+            .iter_mut()
+            .zip(&state.code_blocks)
+            .enumerate()
+        {
+            for y in 0..grid.high {
+                for x in 0..grid.wide {
+                    let cb_info = self.included_cblks.iter().find(|c| {
+                        c.included
+                            && c.x == grid.x0 + x
+                            && c.y == grid.y0 + y
+                            && c.subband_index == s as u8
+                    });
 
-                    let included_now = cb_info.is_some() && cb_info.unwrap().included;
-
-                    if included_now {
-                        subband_state.inclusion_tree.encode(
-                            writer,
-                            x,
-                            y,
-                            (self.layer_index + 1) as i32,
-                        );
+                    let already_included =
+                        subband_state
+                            .inclusion_tree
+                            .is_known_below_threshold(x, y, layer + 1);
+                    if already_included {
+                        writer.write_bit(cb_info.is_some() as u8);
+                    } else {
+                        // Not included yet: at least the next layer as far as this one knows.
+                        let inclusion_layer = if cb_info.is_some() { layer } else { layer + 1 };
+                        subband_state
+                            .inclusion_tree
+                            .set_value(x, y, inclusion_layer);
+                        subband_state.inclusion_tree.encode(writer, x, y, layer + 1);
+                    }
+                    let Some(cb) = cb_info else {
+                        continue;
+                    };
 
-                        let cb = cb_info.unwrap();
+                    if !already_included {
                         subband_state
                             .zero_bp_tree
                             .set_value(x, y, cb.zero_bp as i32);
-                        subband_state.zero_bp_tree.encode(
-                            writer,
-                            x,
-                            y,
-                            (self.layer_index + 1) as i32,
-                        );
+                        subband_state.zero_bp_tree.encode(writer, x, y, 128);
+                    }
 
-                        let num_passes = cb.num_passes.max(1);
-                        for _ in 0..(num_passes - 1) {
-                            writer.write_bit(1);
-                        }
-                        writer.write_bit(0);
-
-                        if cb.data_len > 0 {
-                            subband_state
-                                .lblock_tree
-                                .set_value(x, y, cb.data_len as i32);
-                            subband_state.lblock_tree.encode(
-                                writer,
-                                x,
-                                y,
-                                (self.layer_index + 1) as i32,
-                            );
-                            writer.write_bits(cb.data_len, 16);
-                        } else {
-                            subband_state.lblock_tree.set_value(x, y, 0);
-                            subband_state.lblock_tree.encode(
-                                writer,
-                                x,
-                                y,
-                                (self.layer_index + 1) as i32,
-                            );
-                        }
-                    } else {
-                        subband_state.inclusion_tree.encode(
-                            writer,
-                            x,
-                            y,
-                            (self.layer_index + 1) as i32,
-                        );
+                    let num_passes = cb.num_passes.max(1);
+                    Self::write_coding_passes(writer, num_passes);
+
+                    // Grow Lblock until the length fits in Lblock + floor(log2(passes)) bits.
+                    let lblock = &mut subband_state.lblock[y * grid.wide + x];
+                    let needed = (u32::BITS - cb.data_len.leading_zeros())
+                        .saturating_sub(num_passes.ilog2()) as u8;
+                    while *lblock < needed {
+                        writer.write_bit(1);
+                        *lblock += 1;
                     }
+                    writer.write_bit(0);
+                    writer.write_bits(cb.data_len, *lblock + num_passes.ilog2() as u8);
                 }
             }
         }
     }
+
+    /// Writes the number of coding passes with the codewords of Table B.4.
+    fn write_coding_passes(writer: &mut crate::jpeg2000::bit_io::J2kBitWriter, passes: u8) {
+        match passes {
+            1 => writer.write_bit(0),
+            2 => writer.write_bits(0b10, 2),
+            3..=5 => writer.write_bits(0b1100 | (passes as u32 - 3), 4),
+            6..=36 => writer.write_bits((0b1111 << 5) | (passes as u32 - 6), 9),
+            _ => writer.write_bits((0x1FF << 7) | (passes as u32 - 37), 16),
+        }
+    }
 }
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::jpeg2000::bit_io::J2kBitWriter;
+
+    fn grid(wide: usize, high: usize) -> CodeBlockGrid {
+        CodeBlockGrid {
+            wide,
+            high,
+            ..Default::default()
+        }
+    }
 
     #[test]
     fn test_packet_read_empty() {
         let data = vec![0x00];
         let mut buf_reader = crate::jpeg_stream_reader::JpegStreamReader::new(&data);
         let mut reader = J2kBitReader::new(&mut buf_reader);
-        let mut state = PrecinctState::new(2, 2);
+        let mut state = PrecinctState::new(&[grid(2, 2)]);
 
-        let header = PacketHeader::read(&mut reader, &mut state, 0, 2, 2, 1).unwrap();
+        let header = PacketHeader::read(&mut reader, &mut state, 0, false).unwrap();
         assert!(header.empty);
     }
+
+    #[test]
+    fn test_packet_state_across_layers() {
+        // A 2x1 precinct over three layers: block 0 comes in with layer 0 and needs a
+        // longer Lblock in layer 2, block 1 only comes in with layer 1.
+        let cblk = |x, num_passes, data_len| CodeBlockInfo {
+            x,
+            y: 0,
+            subband_index: 0,
+            included: true,
+            num_passes,
+            data_len,
+            segment_lengths: vec![data_len],
+            zero_bp: x as u8 + 1,
+        };
+        let layers = [
+            vec![cblk(0, 1, 4)],
+            vec![cblk(1, 3, 6)],
+            vec![cblk(0, 2, 40), cblk(1, 1, 2)],
+        ];
+        let code_blocks = [grid(2, 1)];
+        let mut writer = J2kBitWriter::new();
+        let mut state = PrecinctState::new(&code_blocks);
+        for (layer, included_cblks) in layers.iter().enumerate() {
+            let header = PacketHeader {
+                packet_seq_num: 0,
+                empty: false,
+                layer_index: layer as u32,
+                included_cblks: included_cblks.clone(),
+            };
+            header.write(&mut writer, &mut state);
+        }
+        let data = writer.finish();
+
+        let mut buf_reader = crate::jpeg_stream_reader::JpegStreamReader::new(&data);
+        let mut reader = J2kBitReader::new(&mut buf_reader);
+        let mut state = PrecinctState::new(&code_blocks);
+        for (layer, expected) in layers.iter().enumerate() {
+            let header = PacketHeader::read(&mut reader, &mut state, layer as u32, false).unwrap();
+            let read: Vec<_> = header
+                .included_cblks
+                .iter()
+                .map(|cb| (cb.x, cb.num_passes, cb.data_len, cb.zero_bp))
+                .collect();
+            let expected: Vec<_> = expected
+                .iter()
+                .map(|cb| (cb.x, cb.num_passes, cb.data_len, cb.zero_bp))
+                .collect();
+            assert_eq!(read, expected, "layer {layer}");
+        }
+        assert_eq!(state.subbands[0].lblock, vec![5, 3]);
+    }
+
+    #[test]
+    fn test_packet_header_bit_stuffing() {
+        // A long Lblock comma code fills whole bytes with 1 bits, so some header bytes
+        // are 0xFF and the bytes after them carry 7 bits.
+        let header = PacketHeader {
+            packet_seq_num: 0,
+            empty: false,
+            layer_index: 0,
+            included_cblks: vec![CodeBlockInfo {
+                x: 0,
+                y: 0,
+                subband_index: 0,
+                included: true,
+                num_passes: 1,
+                data_len: 0x15_5555,
+                segment_lengths: vec![0x15_5555],
+                zero_bp: 0,
+            }],
+        };
+        let mut writer = J2kBitWriter::new();
+        header.write(&mut writer, &mut PrecinctState::new(&[grid(1, 1)]));
+        let data = writer.finish();
+        assert!(data.contains(&0xFF));
+
+        let mut buf_reader = crate::jpeg_stream_reader::JpegStreamReader::new(&data);
+        let mut reader = J2kBitReader::new(&mut buf_reader);
+        let mut state = PrecinctState::new(&[grid(1, 1)]);
+        let read = PacketHeader::read(&mut reader, &mut state, 0, false).unwrap();
+        reader.align_to_byte();
+        assert_eq!(read.included_cblks[0].data_len, 0x15_5555);
+        assert_eq!(buf_reader.position(), data.len());
+    }
+
+    #[test]
+    fn test_packet_read_ht_segments() {
+        // Non-empty, included, no zero bit-planes, 3 passes ("1100"), Lblock 3 ("0"):
+        // the cleanup length in 3 bits (5) and the refinement length in 4 bits (3).
+        // 1 1 1 1100 0 101 0011 (padded with 0)
+        let data = vec![0xF8, 0xA6];
+        let mut buf_reader = crate::jpeg_stream_reader::JpegStreamReader::new(&data);
+        let mut reader = J2kBitReader::new(&mut buf_reader);
+        let mut state = PrecinctState::new(&[grid(1, 1)]);
+
+        let header = PacketHeader::read(&mut reader, &mut state, 0, true).unwrap();
+        let cb = &header.included_cblks[0];
+        assert_eq!(cb.num_passes, 3);
+        assert_eq!(cb.segment_lengths, vec![5, 3]);
+        assert_eq!(cb.data_len, 8);
+        assert_eq!(state.subbands[0].passes, vec![3]);
+    }
+
+    #[test]
+    fn test_ht_segment_passes() {
+        assert_eq!(PacketHeader::ht_segment_passes(0, 1), vec![1]);
+        assert_eq!(PacketHeader::ht_segment_passes(0, 3), vec![1, 2]);
+        // Three placeholder passes before the cleanup pass.
+        assert_eq!(PacketHeader::ht_segment_passes(0, 5), vec![4, 1]);
+        assert_eq!(PacketHeader::ht_segment_passes(1, 2), vec![2]);
+    }
 }
@@ -3,26 +3,114 @@
 //! Handles the parsing of Main Headers (SOC, SIZ, COD, QCD, CAP) and
 //! Tile-Part Headers (SOT, SOD).
 
-use super::image::{J2kCap, J2kCod, J2kComponentInfo, J2kImage, J2kQcd};
+use super::image::{
+    J2kCap, J2kCoc, J2kCod, J2kComponentInfo, J2kImage, J2kPoc, J2kQcd, J2kRoi, J2kTile,
+    J2kTilePart, J2kTilePartLength,
+};
+use super::jp2::Jp2Reader;
 use crate::JpeglsError;
 use crate::jpeg_marker_code::JpegMarkerCode;
 use crate::jpeg_stream_reader::JpegStreamReader;
+use crate::{FrameInfo, OutputLayout};
+use std::ops::{Deref, DerefMut};
+
+/// Fills in what the boxes of the JP2 file `data` say about `image`: its ICC profile,
+/// palette, component mapping, channel definitions and XML and UUID boxes.
+pub(crate) fn read_jp2_boxes(image: &mut J2kImage, data: &[u8]) {
+    let mut reader = Jp2Reader::new(data);
+    image.icc_profile = reader.find_icc_profile().unwrap_or_default();
+    image.palette = reader.find_palette();
+    image.component_mapping = reader.find_component_mapping();
+    image.channel_definitions = reader.find_channel_definitions();
+    image.xml_boxes = reader.find_xml_boxes();
+    image.uuid_boxes = reader.find_uuid_boxes();
+}
 
 /// A parser that transforms raw J2K marker segments into structured metadata.
 pub struct J2kParser<'a, 'b> {
-    pub reader: &'b mut JpegStreamReader<'a>,
+    pub reader: ParserReader<'a, 'b>,
     pub image: Box<J2kImage>,
+    /// Zppm index and Ippm bytes of the PPM segments so far; a run may continue in the
+    /// next segment.
+    ppm: Vec<(u8, Vec<u8>)>,
+}
+
+/// The reader of a [`J2kParser`]: borrowed from the caller or owned by the parser.
+pub enum ParserReader<'a, 'b> {
+    Borrowed(&'b mut JpegStreamReader<'a>),
+    Owned(Box<JpegStreamReader<'a>>),
+}
+
+impl<'a> Deref for ParserReader<'a, '_> {
+    type Target = JpegStreamReader<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Borrowed(reader) => reader,
+            Self::Owned(reader) => reader,
+        }
+    }
+}
+
+impl DerefMut for ParserReader<'_, '_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            Self::Borrowed(reader) => reader,
+            Self::Owned(reader) => reader,
+        }
+    }
+}
+
+impl<'a> J2kParser<'a, 'static> {
+    /// Creates a parser that owns `reader`.
+    pub fn from_reader(reader: JpegStreamReader<'a>) -> Self {
+        Self::with_reader(ParserReader::Owned(Box::new(reader)))
+    }
 }
 
 impl<'a, 'b> J2kParser<'a, 'b> {
     pub fn new(reader: &'b mut JpegStreamReader<'a>) -> Self {
+        Self::with_reader(ParserReader::Borrowed(reader))
+    }
+
+    fn with_reader(reader: ParserReader<'a, 'b>) -> Self {
         Self {
             reader,
             image: Box::new(J2kImage::default()),
+            ppm: Vec::new(),
+        }
+    }
+
+    /// Reads the main header and stops at the first tile-part, e.g. to report the size and
+    /// sample precision (from SIZ) of an untrusted file without decoding it. A JP2 file is
+    /// searched for its codestream box first.
+    ///
+    /// Returns [`JpeglsError::ParameterOutOfRange`] if the size of the decoded image cannot
+    /// be addressed on this platform; a limit below that is up to the caller, see
+    /// [`Decoder::set_max_decoded_bytes`](crate::Decoder::set_max_decoded_bytes).
+    pub fn parse_info(&mut self) -> Result<&J2kImage, JpeglsError> {
+        let data = self.reader.remaining_data();
+        let offset = Jp2Reader::new(data)
+            .find_codestream()?
+            .map(|codestream| codestream.as_ptr() as usize - data.as_ptr() as usize);
+        if let Some(offset) = offset {
+            read_jp2_boxes(&mut self.image, data);
+            self.reader.advance(offset);
+        }
+        self.parse_main_header()?;
+        let (width, height) = self.image.size();
+        FrameInfo {
+            width,
+            height,
+            bits_per_sample: self.image.channel_bits_per_sample() as i32,
+            component_count: self.image.channel_count() as i32,
         }
+        .decoded_size(OutputLayout::Interleaved)?;
+        Ok(&self.image)
     }
 
     pub fn parse_main_header(&mut self) -> Result<JpegMarkerCode, JpeglsError> {
+        j2k_span!(_span, "j2k_parse_main_header");
         // Expect SOC (0xFF4F)
         let soc = self.reader.read_u16()?;
         if soc != 0xFF4F {
@@ -51,24 +139,46 @@ impl<'a, 'b> J2kParser<'a, 'b> {
                 JpegMarkerCode::QuantizationDefault => self.parse_qcd()?,
                 JpegMarkerCode::StartOfTile => {
                     // SOT indicates end of main header
+                    let ippm = concat_packed_headers(std::mem::take(&mut self.ppm));
+                    self.image.packed_headers = split_ppm(&ippm)?;
                     return Ok(JpegMarkerCode::StartOfTile);
                 }
                 JpegMarkerCode::Capability => self.parse_cap()?,
-                JpegMarkerCode::RegionOfInterest => self.parse_rgn()?,
+                JpegMarkerCode::CodingStyleComponent => {
+                    let coc = self.parse_coc()?;
+                    set_coc(&mut self.image.coc, coc);
+                }
+                JpegMarkerCode::RegionOfInterest => {
+                    let roi = self.parse_rgn()?;
+                    set_roi(&mut self.image.roi, roi);
+                }
+                JpegMarkerCode::ProgressionOrderChange => {
+                    let poc = self.parse_poc()?;
+                    self.image.poc.extend(poc);
+                }
+                JpegMarkerCode::ComponentRegistration => {
+                    self.image.registration = self.parse_crg()?;
+                }
+                JpegMarkerCode::TilePartLengths => self.parse_tlm()?,
+                JpegMarkerCode::PacketLengthMain => self.parse_plm()?,
+                JpegMarkerCode::PackedPacketHeadersMain => {
+                    let ppm = self.parse_packed_headers()?;
+                    self.ppm.push(ppm);
+                }
                 JpegMarkerCode::J2kComment => {
-                    let len = self.reader.read_u16()?;
-                    if len < 2 {
+                    let len = self.reader.read_u16()? as usize;
+                    if len < 2 || len - 2 > self.reader.remaining_data().len() {
                         return Err(JpeglsError::InvalidData);
                     }
-                    self.reader.advance((len - 2) as usize);
+                    self.reader.advance(len - 2);
                 }
                 _ => {
                     // Skip unknown segment
-                    let len = self.reader.read_u16()?;
-                    if len < 2 {
+                    let len = self.reader.read_u16()? as usize;
+                    if len < 2 || len - 2 > self.reader.remaining_data().len() {
                         return Err(JpeglsError::InvalidData);
                     }
-                    self.reader.advance((len - 2) as usize);
+                    self.reader.advance(len - 2);
                 }
             }
         }
@@ -104,6 +214,10 @@ impl<'a, 'b> J2kParser<'a, 'b> {
                 dy: sub_y,
             });
         }
+        // Isot numbers the tiles with 16 bits (A.4.2).
+        if self.image.tile_count() > 65535 {
+            return Err(JpeglsError::InvalidData);
+        }
         Ok(())
     }
 
@@ -123,8 +237,17 @@ impl<'a, 'b> J2kParser<'a, 'b> {
         let decomposition_levels = self.reader.read_u8()?; // number of decomposition levels
         let codeblock_width_exp = self.reader.read_u8()?; // codeblock width exponent (log2)
         let codeblock_height_exp = self.reader.read_u8()?; // codeblock height exponent (log2)
-        let _codeblock_style = self.reader.read_u8()?;
+        let codeblock_style = self.reader.read_u8()?;
         let transformation = self.reader.read_u8()?;
+        // At most 32 decomposition levels, and code-blocks of 4 to 1024 samples a side
+        // with at most 4096 samples: xcb + ycb <= 12 with the offset of 2 of each (A.6.1).
+        if decomposition_levels > 32
+            || codeblock_width_exp > 8
+            || codeblock_height_exp > 8
+            || codeblock_width_exp + codeblock_height_exp > 8
+        {
+            return Err(JpeglsError::InvalidData);
+        }
 
         let mut precinct_sizes = Vec::new();
         // If Scod bit 0 (Precincts defined) is set, read precinct sizes.
@@ -153,6 +276,7 @@ impl<'a, 'b> J2kParser<'a, 'b> {
             decomposition_levels,
             codeblock_width_exp,
             codeblock_height_exp,
+            codeblock_style,
             transformation,
             precinct_sizes,
         });
@@ -162,12 +286,10 @@ impl<'a, 'b> J2kParser<'a, 'b> {
     pub fn parse_qcd(&mut self) -> Result<(), JpeglsError> {
         // QCD marker parsing
         let len = self.reader.read_u16()?;
-        // eprintln!("DEBUG: parse_qcd len={}", len);
         if len < 3 {
             return Err(JpeglsError::InvalidData);
         }
         let sqcd = self.reader.read_u8()?; // quantization style flags
-        // eprintln!("DEBUG: parse_qcd sqcd={:02X}", sqcd);
 
         // Remaining in the marker segment
         // len includes 2 bytes for len.
@@ -235,86 +357,321 @@ impl<'a, 'b> J2kParser<'a, 'b> {
         Ok(())
     }
 
-    pub fn parse_rgn(&mut self) -> Result<(), JpeglsError> {
+    /// Parses a COC marker segment (A.6.2) into the code-block style of one component; its
+    /// other coding parameters are not used.
+    pub fn parse_coc(&mut self) -> Result<J2kCoc, JpeglsError> {
+        let len = self.reader.read_u16()? as usize;
+        // The component index takes two bytes when there are more than 256 components.
+        let wide = self.image.component_count > 256;
+        let header = if wide { 10 } else { 9 };
+        if len < header {
+            return Err(JpeglsError::InvalidData);
+        }
+        let component_index = if wide {
+            self.reader.read_u16()?
+        } else {
+            self.reader.read_u8()? as u16
+        };
+        let _scoc = self.reader.read_u8()?;
+        let _decomposition_levels = self.reader.read_u8()?;
+        let _codeblock_width_exp = self.reader.read_u8()?;
+        let _codeblock_height_exp = self.reader.read_u8()?;
+        let codeblock_style = self.reader.read_u8()?;
+
+        // Transformation and precinct sizes
+        self.reader.advance(len - header + 1);
+
+        Ok(J2kCoc {
+            component_index,
+            codeblock_style,
+        })
+    }
+
+    /// Parses an RGN marker segment (A.6.3) into the region of interest of one component.
+    pub fn parse_rgn(&mut self) -> Result<J2kRoi, JpeglsError> {
         // RGN marker (0xFF5E) - Region of Interest
-        let len = self.reader.read_u16()?;
-        if len < 5 {
+        let len = self.reader.read_u16()? as usize;
+        // The component index takes two bytes when there are more than 256 components.
+        let wide = self.image.component_count > 256;
+        if len < if wide { 6 } else { 5 } {
             return Err(JpeglsError::InvalidData);
         }
-        let component_index = self.reader.read_u8()?;
+        let component_index = if wide {
+            self.reader.read_u16()?
+        } else {
+            self.reader.read_u8()? as u16
+        };
         let roi_style = self.reader.read_u8()?;
         let shift_value = self.reader.read_u8()?;
 
         // Skip remaining bytes if any
-        let remaining = (len as usize).saturating_sub(5);
+        let remaining = len - if wide { 6 } else { 5 };
         if remaining > 0 {
             self.reader.advance(remaining);
         }
 
-        self.image.roi = Some(super::image::J2kRoi {
+        Ok(J2kRoi {
             component_index,
             roi_style,
             shift_value,
-        });
+        })
+    }
 
+    /// Parses a CRG marker segment (A.9.1) into the (Xcrg, Ycrg) offsets of every
+    /// component.
+    pub fn parse_crg(&mut self) -> Result<Vec<(u16, u16)>, JpeglsError> {
+        let len = self.reader.read_u16()? as usize;
+        if len != 2 + 4 * self.image.component_count as usize {
+            return Err(JpeglsError::InvalidData);
+        }
+        let mut registration = Vec::with_capacity(self.image.component_count as usize);
+        for _ in 0..self.image.component_count {
+            let x = self.reader.read_u16()?;
+            let y = self.reader.read_u16()?;
+            registration.push((x, y));
+        }
+        Ok(registration)
+    }
+
+    /// Parses a POC marker segment (A.6.6) into its progressions.
+    pub fn parse_poc(&mut self) -> Result<Vec<J2kPoc>, JpeglsError> {
+        let len = self.reader.read_u16()? as usize;
+        // Component indices take two bytes when there are more than 256 components.
+        let wide = self.image.component_count > 256;
+        let entry_len = if wide { 9 } else { 7 };
+        if len < 2 + entry_len || !(len - 2).is_multiple_of(entry_len) {
+            return Err(JpeglsError::InvalidData);
+        }
+
+        let mut pocs = Vec::with_capacity((len - 2) / entry_len);
+        for _ in 0..(len - 2) / entry_len {
+            let resolution_start = self.reader.read_u8()?;
+            let component_start = if wide {
+                self.reader.read_u16()?
+            } else {
+                self.reader.read_u8()? as u16
+            };
+            let layer_end = self.reader.read_u16()?;
+            let resolution_end = self.reader.read_u8()?;
+            let component_end = if wide {
+                self.reader.read_u16()?
+            } else {
+                // CEpoc = 0 stands for 256
+                match self.reader.read_u8()? {
+                    0 => 256,
+                    v => v as u16,
+                }
+            };
+            let progression_order = self.reader.read_u8()?;
+            pocs.push(J2kPoc {
+                resolution_start,
+                component_start,
+                layer_end,
+                resolution_end,
+                component_end,
+                progression_order,
+            });
+        }
+        Ok(pocs)
+    }
+
+    /// Parses a TLM marker segment (A.7.1) into the image's tile-part lengths.
+    pub fn parse_tlm(&mut self) -> Result<(), JpeglsError> {
+        let len = self.reader.read_u16()? as usize;
+        if len < 4 {
+            return Err(JpeglsError::InvalidData);
+        }
+        let _ztlm = self.reader.read_u8()?;
+        let stlm = self.reader.read_u8()?;
+        let index_bytes = match (stlm >> 4) & 0x03 {
+            3 => return Err(JpeglsError::InvalidData),
+            size => size as usize,
+        };
+        let length_bytes = if stlm & 0x40 != 0 { 4 } else { 2 };
+        let entry_len = index_bytes + length_bytes;
+        if !(len - 4).is_multiple_of(entry_len) {
+            return Err(JpeglsError::InvalidData);
+        }
+
+        for _ in 0..(len - 4) / entry_len {
+            let tile_index = match index_bytes {
+                // Without Ttlm every tile has one tile-part, in order.
+                0 => self.image.tile_part_lengths.len() as u16,
+                1 => self.reader.read_u8()? as u16,
+                _ => self.reader.read_u16()?,
+            };
+            let length = if length_bytes == 4 {
+                self.reader.read_u32()?
+            } else {
+                self.reader.read_u16()? as u32
+            };
+            self.image
+                .tile_part_lengths
+                .push(J2kTilePartLength { tile_index, length });
+        }
         Ok(())
     }
 
-    /// Parses a Tile-Part.
-    /// Returns (Psot, Isot).
-    /// - Psot: Length of the data.
-    /// - Isot: Tile index.
-    pub fn parse_tile_part_header(&mut self) -> Result<(u32, u16), JpeglsError> {
+    /// Parses a PLM marker segment (A.7.2): one list of packet lengths per tile-part.
+    pub fn parse_plm(&mut self) -> Result<(), JpeglsError> {
+        let len = self.reader.read_u16()? as usize;
+        if len < 3 {
+            return Err(JpeglsError::InvalidData);
+        }
+        let _zplm = self.reader.read_u8()?;
+        let mut remaining = len - 3;
+        while remaining > 0 {
+            let count = self.reader.read_u8()? as usize;
+            if count >= remaining {
+                return Err(JpeglsError::InvalidData);
+            }
+            let lengths = self.read_packet_lengths(count)?;
+            self.image.packet_lengths.push(lengths);
+            remaining -= 1 + count;
+        }
+        Ok(())
+    }
+
+    /// Parses a PLT marker segment (A.7.3) and returns its packet lengths.
+    pub fn parse_plt(&mut self) -> Result<Vec<u32>, JpeglsError> {
+        let len = self.reader.read_u16()? as usize;
+        if len < 3 {
+            return Err(JpeglsError::InvalidData);
+        }
+        let _zplt = self.reader.read_u8()?;
+        self.read_packet_lengths(len - 3)
+    }
+
+    /// Parses a PPM or PPT marker segment (A.7.4, A.7.5) and returns its Zppm/Zppt index
+    /// and the bytes after it.
+    pub fn parse_packed_headers(&mut self) -> Result<(u8, Vec<u8>), JpeglsError> {
+        let len = self.reader.read_u16()? as usize;
+        if len < 3 {
+            return Err(JpeglsError::InvalidData);
+        }
+        let z = self.reader.read_u8()?;
+        let bytes = self
+            .reader
+            .remaining_data()
+            .get(..len - 3)
+            .ok_or(JpeglsError::InvalidData)?
+            .to_vec();
+        self.reader.advance(len - 3);
+        Ok((z, bytes))
+    }
+
+    /// Reads `count` bytes of packet lengths: 7 bits per byte, most significant first,
+    /// with the high bit set on every byte but the last of a length.
+    fn read_packet_lengths(&mut self, count: usize) -> Result<Vec<u32>, JpeglsError> {
+        let mut lengths = Vec::new();
+        let mut length = 0u32;
+        for _ in 0..count {
+            let b = self.reader.read_u8()?;
+            length = length << 7 | (b & 0x7F) as u32;
+            if b & 0x80 == 0 {
+                lengths.push(length);
+                length = 0;
+            }
+        }
+        Ok(lengths)
+    }
+
+    /// Parses a Tile-Part header up to and including its SOD marker.
+    /// The tile index (Isot) must be one of the image's tiles; TNsot, when given, is
+    /// recorded with the tile.
+    pub fn parse_tile_part_header(&mut self) -> Result<J2kTilePart, JpeglsError> {
         // Assume SOT marker (FF90) has been consumed (or we are inside SOT segment).
 
         let _lsot = self.reader.read_u16()?;
         let isot = self.reader.read_u16()?;
         let psot = self.reader.read_u32()?;
-        let _tpsot = self.reader.read_u8()?;
-        let _tnsot = self.reader.read_u8()?;
+        let tpsot = self.reader.read_u8()?;
+        let tnsot = self.reader.read_u8()?;
+        if isot as u32 >= self.image.tile_count() {
+            return Err(JpeglsError::InvalidData);
+        }
+        if tnsot != 0 {
+            self.tile_mut(isot).tile_part_count = tnsot;
+        }
 
-        // eprintln!("DEBUG: SOT isot={} psot={}", isot, psot);
+        j2k_trace!("tile-part header: isot={} psot={}", isot, psot);
 
+        let mut ppt = Vec::new();
         // Loop for other markers until SOD
         loop {
             // Check for potential markers
             if self.reader.remaining_data().len() < 2 {
-                // eprintln!("DEBUG: SOT Loop EOF");
                 return Err(JpeglsError::InvalidData);
             }
 
             let b1 = self.reader.read_u8()?;
             if b1 != 0xFF {
-                // eprintln!("DEBUG: SOT Loop expected FF, got {:02X}", b1);
                 return Err(JpeglsError::InvalidData);
             }
             let b2 = self.reader.read_u8()?;
             if b2 == 0x93 {
                 // SOD
-                // eprintln!("DEBUG: Found SOD");
                 break;
             }
 
             let marker = JpegMarkerCode::try_from(b2)?;
-            // eprintln!("DEBUG: Tile Marker {:?} ({:02X})", marker, b2);
 
             match marker {
                 JpegMarkerCode::CodingStyleDefault => self.parse_cod()?,
+                JpegMarkerCode::CodingStyleComponent => {
+                    let coc = self.parse_coc()?;
+                    set_coc(&mut self.tile_mut(isot).coc, coc);
+                }
                 JpegMarkerCode::QuantizationDefault => self.parse_qcd()?,
+                JpegMarkerCode::ProgressionOrderChange => {
+                    let poc = self.parse_poc()?;
+                    self.tile_mut(isot).poc.extend(poc);
+                }
+                JpegMarkerCode::RegionOfInterest => {
+                    let roi = self.parse_rgn()?;
+                    set_roi(&mut self.tile_mut(isot).roi, roi);
+                }
+                JpegMarkerCode::PacketLengthTilePart => {
+                    let lengths = self.parse_plt()?;
+                    self.tile_mut(isot).packet_lengths.extend(lengths);
+                }
+                JpegMarkerCode::PackedPacketHeadersTilePart => {
+                    ppt.push(self.parse_packed_headers()?);
+                }
                 // Add COC, QCC, etc. support as needed
                 _ => {
                     // Skip unknown
-                    let len = self.reader.read_u16()?;
-                    if len < 2 {
+                    let len = self.reader.read_u16()? as usize;
+                    if len < 2 || len - 2 > self.reader.remaining_data().len() {
                         return Err(JpeglsError::InvalidData);
                     }
-                    self.reader.advance((len - 2) as usize);
+                    self.reader.advance(len - 2);
                 }
             }
         }
 
+        if !ppt.is_empty() {
+            let ippt = concat_packed_headers(ppt);
+            self.tile_mut(isot).packed_headers.extend(ippt);
+        }
+
         // At this point we are at the start of bitstream.
-        Ok((psot, isot))
+        Ok(J2kTilePart {
+            tile_index: isot,
+            length: psot,
+            part_index: tpsot,
+            part_count: tnsot,
+        })
+    }
+
+    /// The tile with index `isot`, created if the image has no such tile yet.
+    pub fn tile_mut(&mut self, isot: u16) -> &mut J2kTile {
+        let tile_idx = isot as usize;
+        if self.image.tiles.len() <= tile_idx {
+            self.image.tiles.resize_with(tile_idx + 1, Default::default);
+            self.image.tiles[tile_idx].index = isot as u32;
+        }
+        &mut self.image.tiles[tile_idx]
     }
 
     /// Parses the entire codestream (Main Header + All Tiles).
@@ -328,10 +685,10 @@ impl<'a, 'b> J2kParser<'a, 'b> {
 
             if marker == JpegMarkerCode::StartOfTile {
                 // Parse tile part
-                let (psot, _isot) = self.parse_tile_part_header()?;
+                let tile_part = self.parse_tile_part_header()?;
 
                 // Read Tile Data (packets)
-                if psot == 0 {
+                if tile_part.length == 0 {
                     // Read until EOC.
                     break;
                 } else {
@@ -364,9 +721,43 @@ impl<'a, 'b> J2kParser<'a, 'b> {
     }
 }
 
+/// Records `roi`, replacing an earlier region of interest of the same component.
+fn set_roi(rois: &mut Vec<J2kRoi>, roi: J2kRoi) {
+    rois.retain(|r| r.component_index != roi.component_index);
+    rois.push(roi);
+}
+
+/// Records `coc`, replacing an earlier coding style of the same component.
+fn set_coc(cocs: &mut Vec<J2kCoc>, coc: J2kCoc) {
+    cocs.retain(|c| c.component_index != coc.component_index);
+    cocs.push(coc);
+}
+
+/// Joins the bytes of PPM or PPT segments in the order of their Zppm/Zppt index, which
+/// need not be the order of the segments in the header.
+fn concat_packed_headers(mut segments: Vec<(u8, Vec<u8>)>) -> Vec<u8> {
+    segments.sort_by_key(|(z, _)| *z);
+    segments.into_iter().flat_map(|(_, bytes)| bytes).collect()
+}
+
+/// Splits the Ippm bytes of all PPM segments into the packed headers of each tile-part;
+/// every run starts with its length Nppm.
+fn split_ppm(mut ippm: &[u8]) -> Result<Vec<Vec<u8>>, JpeglsError> {
+    let mut runs = Vec::new();
+    while !ippm.is_empty() {
+        let (nppm, rest) = ippm.split_at_checked(4).ok_or(JpeglsError::InvalidData)?;
+        let len = u32::from_be_bytes(nppm.try_into().unwrap()) as usize;
+        let (run, rest) = rest.split_at_checked(len).ok_or(JpeglsError::InvalidData)?;
+        runs.push(run.to_vec());
+        ippm = rest;
+    }
+    Ok(runs)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::jpeg2000::image::BlockCoder;
 
     #[test]
     fn test_parse_siz() {
@@ -407,6 +798,45 @@ mod tests {
         assert_eq!(parser.image.component_count, 1);
     }
 
+    #[test]
+    fn test_parse_info() {
+        // SOC, SIZ of one tile with one 12-bit component and the start of a tile-part that is
+        // not read.
+        let main_header = |size: u32, depth: u8| {
+            let mut data = vec![0xFF, 0x4F, 0xFF, 0x51, 0x00, 0x29, 0x00, 0x00];
+            for value in [size, size, 0, 0, size, size, 0, 0] {
+                data.extend(value.to_be_bytes());
+            }
+            data.extend([0x00, 0x01, depth - 1, 0x01, 0x01, 0xFF, 0x90, 0x00, 0x0A]);
+            data
+        };
+        let codestream = main_header(300, 12);
+
+        let mut reader = JpegStreamReader::new(&codestream);
+        let mut parser = J2kParser::new(&mut reader);
+        let image = parser.parse_info().unwrap();
+        assert_eq!((image.width, image.height), (300, 300));
+        assert_eq!(image.bits_per_sample(), 12);
+        assert!(image.tiles.is_empty());
+
+        // JP2 signature box and a codestream box.
+        let mut jp2 = b"\x00\x00\x00\x0CjP  \r\n\x87\n".to_vec();
+        jp2.extend((8 + codestream.len() as u32).to_be_bytes());
+        jp2.extend(b"jp2c");
+        jp2.extend(&codestream);
+        let mut reader = JpegStreamReader::new(&jp2);
+        let mut parser = J2kParser::new(&mut reader);
+        assert_eq!(parser.parse_info().unwrap().width, 300);
+
+        let huge = main_header(u32::MAX, 16);
+        let mut reader = JpegStreamReader::new(&huge);
+        let mut parser = J2kParser::new(&mut reader);
+        assert_eq!(
+            parser.parse_info().err(),
+            Some(JpeglsError::ParameterOutOfRange)
+        );
+    }
+
     #[test]
     fn test_parse_codestream() {
         let data = vec![
@@ -492,7 +922,7 @@ mod tests {
             0x00, // mct (unused)
             0x03, // decomposition levels
             0x04, // codeblock width exponent
-            0x05, // codeblock height exponent
+            0x04, // codeblock height exponent
             0x00, // codeblock style
             0x00, // transformation
             // QCD marker
@@ -515,4 +945,201 @@ mod tests {
         assert_eq!(qcd.quant_style, 0x06);
         assert_eq!(qcd.step_sizes, vec![0x1000]);
     }
+
+    #[test]
+    fn test_parse_poc() {
+        let data = vec![
+            0xFF, 0x5F, // POC
+            0x00, 0x10, // length 16 (2 len + two 7-byte progressions)
+            0x00, 0x00, 0x00, 0x01, 0x01, 0x03, 0x01, // RLCP: r 0..1, c 0..3, 1 layer
+            0x01, 0x00, 0x00, 0x01, 0x04, 0x00, 0x02, // RPCL: r 1..4, c 0..256
+        ];
+        let mut reader = JpegStreamReader::new(&data);
+        let mut parser = J2kParser::new(&mut reader);
+        parser.image.component_count = 3;
+        assert_eq!(parser.reader.read_u16().unwrap(), 0xFF5F);
+        let pocs = parser.parse_poc().unwrap();
+        assert_eq!(
+            pocs,
+            vec![
+                J2kPoc {
+                    resolution_start: 0,
+                    component_start: 0,
+                    layer_end: 1,
+                    resolution_end: 1,
+                    component_end: 3,
+                    progression_order: 1,
+                },
+                J2kPoc {
+                    resolution_start: 1,
+                    component_start: 0,
+                    layer_end: 1,
+                    resolution_end: 4,
+                    component_end: 256,
+                    progression_order: 2,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_length_markers() {
+        let data = vec![
+            0xFF, 0x55, // TLM
+            0x00, 0x08, // length 8
+            0x00, 0x00, // Ztlm, Stlm: no tile indices, 2-byte lengths
+            0x01, 0x00, 0x00, 0x80, // 256, 128
+            0xFF, 0x57, // PLM
+            0x00, 0x09, // length 9
+            0x00, // Zplm
+            0x02, 0x81, 0x00, // tile-part 0: 128
+            0x02, 0x05, 0x06, // tile-part 1: 5, 6
+            0xFF, 0x58, // PLT
+            0x00, 0x07, // length 7
+            0x00, // Zplt
+            0x83, 0xFF, 0x7F, 0x01, // 65535, 1
+        ];
+        let mut reader = JpegStreamReader::new(&data);
+        let mut parser = J2kParser::new(&mut reader);
+        parser.reader.advance(2);
+        parser.parse_tlm().unwrap();
+        assert_eq!(
+            parser.image.tile_part_lengths,
+            vec![
+                J2kTilePartLength {
+                    tile_index: 0,
+                    length: 256,
+                },
+                J2kTilePartLength {
+                    tile_index: 1,
+                    length: 128,
+                },
+            ]
+        );
+        parser.reader.advance(2);
+        parser.parse_plm().unwrap();
+        assert_eq!(parser.image.packet_lengths, vec![vec![128], vec![5, 6]]);
+        parser.reader.advance(2);
+        assert_eq!(parser.parse_plt().unwrap(), vec![65535, 1]);
+    }
+
+    #[test]
+    fn test_parse_rgn() {
+        let data = vec![
+            0xFF, 0x5E, 0x00, 0x05, 0x01, 0x00, 0x03, // RGN: component 1, SPrgn 3
+            0xFF, 0x5E, 0x00, 0x05, 0x01, 0x00, 0x06, // RGN: component 1 again
+            0xFF, 0x5E, 0x00, 0x06, 0x01, 0x02, 0x00, 0x07, // RGN, 2-byte Crgn: 258
+        ];
+        let mut reader = JpegStreamReader::new(&data);
+        let mut parser = J2kParser::new(&mut reader);
+        for _ in 0..2 {
+            parser.reader.advance(2);
+            let roi = parser.parse_rgn().unwrap();
+            set_roi(&mut parser.image.roi, roi);
+        }
+        assert_eq!(
+            parser.image.roi,
+            vec![J2kRoi {
+                component_index: 1,
+                roi_style: 0,
+                shift_value: 6,
+            }]
+        );
+        assert_eq!(parser.image.roi_shift(0, 1), 6);
+        assert_eq!(parser.image.roi_shift(0, 0), 0);
+
+        parser.image.component_count = 300;
+        parser.reader.advance(2);
+        let roi = parser.parse_rgn().unwrap();
+        assert_eq!((roi.component_index, roi.shift_value), (258, 7));
+    }
+
+    #[test]
+    fn test_parse_coc() {
+        let data = vec![
+            0xFF, 0x53, 0x00, 0x0B, 0x01, 0x01, // COC: component 1, precincts
+            0x01, 0x04, 0x04, 0x40, 0x01, 0xFF, 0xFF, // HT code-block style
+            0xFF, 0x53, 0x00, 0x09, 0x00, 0x00, // COC: component 0
+            0x05, 0x04, 0x04, 0xC0, 0x00, // mixed code-block style
+        ];
+        let mut reader = JpegStreamReader::new(&data);
+        let mut parser = J2kParser::new(&mut reader);
+        parser.image.component_count = 3;
+        parser.image.cod = Some(J2kCod::default());
+        for _ in 0..2 {
+            parser.reader.advance(2);
+            let coc = parser.parse_coc().unwrap();
+            set_coc(&mut parser.image.coc, coc);
+        }
+        assert!(parser.reader.remaining_data().is_empty());
+        assert_eq!(parser.image.block_coder(0, 1), Ok(BlockCoder::Ht));
+        assert_eq!(
+            parser.image.block_coder(0, 0),
+            Err(JpeglsError::ParameterValueNotSupported)
+        );
+        assert_eq!(parser.image.block_coder(0, 2), Ok(BlockCoder::Part1));
+
+        // A tile-part COC replaces the main header one in its tile.
+        parser.image.tiles.push(J2kTile {
+            coc: vec![J2kCoc {
+                component_index: 1,
+                codeblock_style: 0,
+            }],
+            ..Default::default()
+        });
+        assert_eq!(parser.image.block_coder(0, 1), Ok(BlockCoder::Part1));
+        assert_eq!(parser.image.block_coder(1, 1), Ok(BlockCoder::Ht));
+    }
+
+    #[test]
+    fn test_parse_crg() {
+        let data = vec![
+            0xFF, 0x63, 0x00, 0x0A, // CRG, 2 components
+            0x00, 0x00, 0x00, 0x00, // component 0: no offset
+            0x80, 0x00, 0x40, 0x00, // component 1: 1/2, 1/4
+        ];
+        let mut reader = JpegStreamReader::new(&data);
+        let mut parser = J2kParser::new(&mut reader);
+        parser.image.component_count = 2;
+        parser.reader.advance(2);
+        assert_eq!(parser.parse_crg().unwrap(), vec![(0, 0), (32768, 16384)]);
+
+        // The segment has to list every component.
+        let mut reader = JpegStreamReader::new(&data);
+        let mut parser = J2kParser::new(&mut reader);
+        parser.image.component_count = 3;
+        parser.reader.advance(2);
+        assert!(parser.parse_crg().is_err());
+    }
+
+    #[test]
+    fn test_parse_packed_headers() {
+        let data = vec![
+            0xFF, 0x60, // PPM
+            0x00, 0x0E, // length 14
+            0x00, // Zppm
+            0x00, 0x00, 0x00, 0x02, 0xC0, 0x80, // tile-part 0: 2 bytes
+            0x00, 0x00, 0x00, 0x01, 0x00, // tile-part 1: 1 byte
+            0xFF, 0x61, // PPT
+            0x00, 0x05, // length 5
+            0x01, // Zppt
+            0xA0, 0x40, // header bytes
+        ];
+        let mut reader = JpegStreamReader::new(&data);
+        let mut parser = J2kParser::new(&mut reader);
+        parser.reader.advance(2);
+        let ppm = parser.parse_packed_headers().unwrap();
+        assert_eq!(
+            split_ppm(&ppm.1).unwrap(),
+            vec![vec![0xC0, 0x80], vec![0x00]]
+        );
+        parser.reader.advance(2);
+        let ppt = parser.parse_packed_headers().unwrap();
+        assert_eq!(ppt, (1, vec![0xA0, 0x40]));
+        assert_eq!(
+            concat_packed_headers(vec![ppt, (0, vec![0x80])]),
+            vec![0x80, 0xA0, 0x40]
+        );
+        assert!(split_ppm(&[0x00, 0x00, 0x00, 0x05, 0x00]).is_err());
+    }
 }
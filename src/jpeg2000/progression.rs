@@ -0,0 +1,462 @@
+//! Packet progression (B.12).
+//!
+//! The packets of a tile are ordered by layer, resolution, component and precinct in one of
+//! five progression orders. POC marker segments split the tile into progression volumes,
+//! each with its own order and ranges. The encoder and decoder share the iteration here.
+
+use super::packet::CodeBlockGrid;
+use crate::JpeglsError;
+
+/// Order of the packets in a tile (Table A.16); the letters name the loops from the
+/// outermost to the innermost: layer, resolution, component and position (precinct).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProgressionOrder {
+    /// Quality progressive: every resolution at low quality first.
+    #[default]
+    Lrcp = 0,
+    /// Resolution progressive: low resolutions at full quality first.
+    Rlcp = 1,
+    Rpcl = 2,
+    Pcrl = 3,
+    /// Component progressive.
+    Cprl = 4,
+}
+
+impl TryFrom<u8> for ProgressionOrder {
+    type Error = JpeglsError;
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(Self::Lrcp),
+            1 => Ok(Self::Rlcp),
+            2 => Ok(Self::Rpcl),
+            3 => Ok(Self::Pcrl),
+            4 => Ok(Self::Cprl),
+            _ => Err(JpeglsError::InvalidData),
+        }
+    }
+}
+
+/// Tile on the reference grid (x0, y0, x1, y1).
+pub(crate) type TileBounds = (usize, usize, usize, usize);
+
+/// A packet: (layer, resolution, component, precinct).
+pub(crate) type PacketId = (u16, usize, usize, usize);
+
+/// Extent of one resolution level of a tile-component and its precinct grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ResolutionGrid {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+    /// Precinct size exponents (PPx, PPy)
+    pub ppx: u8,
+    pub ppy: u8,
+    pub precincts_wide: usize,
+    pub precincts_high: usize,
+}
+
+impl ResolutionGrid {
+    pub fn precinct_count(&self) -> usize {
+        self.precincts_wide * self.precincts_high
+    }
+
+    /// Code-blocks of nominal size 2^`xcb` x 2^`ycb` in each subband of precinct `p`
+    /// of resolution `r` (B.6, B.7): the LL subband alone at resolution 0, else HL, LH
+    /// and HH. A subband is half the size of its resolution, and so are the precincts
+    /// in it; code-blocks never cross a precinct boundary.
+    pub fn code_block_grids(&self, r: usize, p: usize, (xcb, ycb): (u8, u8)) -> Vec<CodeBlockGrid> {
+        let wide = self.precincts_wide.max(1);
+        let (px, py) = (p % wide, p / wide);
+        let halved = (r > 0) as u8;
+        let (band_ppx, band_ppy) = (
+            self.ppx.saturating_sub(halved),
+            self.ppy.saturating_sub(halved),
+        );
+        let (xcb, ycb) = (xcb.min(band_ppx), ycb.min(band_ppy));
+        // (first code-block of the precinct in the subband, code-blocks in the precinct)
+        let axis =
+            |start: usize, end: usize, high: bool, pp: u8, band_pp: u8, cb: u8, index: usize| {
+                let band = |v: usize| match (r, high) {
+                    (0, _) => v,
+                    (_, true) => v / 2,
+                    (_, false) => v.div_ceil(2),
+                };
+                let (b0, b1) = (band(start), band(end));
+                let precinct = (start >> pp) + index;
+                let p0 = (precinct << band_pp).max(b0);
+                let p1 = ((precinct + 1) << band_pp).min(b1);
+                if p1 <= p0 {
+                    return (0, 0);
+                }
+                ((p0 >> cb) - (b0 >> cb), p1.div_ceil(1 << cb) - (p0 >> cb))
+            };
+        let bands: &[(bool, bool)] = if r == 0 {
+            &[(false, false)]
+        } else {
+            &[(true, false), (false, true), (true, true)]
+        };
+        bands
+            .iter()
+            .map(|&(high_x, high_y)| {
+                let (x0, wide) = axis(self.x0, self.x1, high_x, self.ppx, band_ppx, xcb, px);
+                let (y0, high) = axis(self.y0, self.y1, high_y, self.ppy, band_ppy, ycb, py);
+                CodeBlockGrid { x0, y0, wide, high }
+            })
+            .collect()
+    }
+}
+
+/// Resolution levels of a tile-component with their precinct grids, lowest first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ComponentGrid {
+    /// Subsampling factors (XRsiz, YRsiz)
+    pub dx: usize,
+    pub dy: usize,
+    pub resolutions: Vec<ResolutionGrid>,
+}
+
+impl ComponentGrid {
+    /// Grids of a component with subsampling (dx, dy) in `tile`. `precinct_sizes` holds
+    /// the exponents per resolution; resolutions without an entry have one precinct.
+    pub fn new(
+        tile: TileBounds,
+        (dx, dy): (usize, usize),
+        decomposition_levels: u8,
+        precinct_sizes: &[(u8, u8)],
+    ) -> Self {
+        let levels = decomposition_levels as u32;
+        let (tcx0, tcy0) = (tile.0.div_ceil(dx), tile.1.div_ceil(dy));
+        let (tcx1, tcy1) = (tile.2.div_ceil(dx), tile.3.div_ceil(dy));
+        let ceil_shift = |value: usize, shift: u32| (value + (1 << shift) - 1) >> shift;
+        let resolutions = (0..=levels)
+            .map(|r| {
+                let shift = levels - r;
+                let (x0, y0) = (ceil_shift(tcx0, shift), ceil_shift(tcy0, shift));
+                let (x1, y1) = (ceil_shift(tcx1, shift), ceil_shift(tcy1, shift));
+                let (ppx, ppy) = precinct_sizes.get(r as usize).copied().unwrap_or((15, 15));
+                let count = |start: usize, end: usize, pp: u8| {
+                    if end > start {
+                        ceil_shift(end, pp as u32) - (start >> pp)
+                    } else {
+                        0
+                    }
+                };
+                ResolutionGrid {
+                    x0,
+                    y0,
+                    x1,
+                    y1,
+                    ppx,
+                    ppy,
+                    precincts_wide: count(x0, x1, ppx),
+                    precincts_high: count(y0, y1, ppy),
+                }
+            })
+            .collect();
+        Self {
+            dx,
+            dy,
+            resolutions,
+        }
+    }
+
+    /// Index of the precinct of resolution `r` that starts at reference grid position
+    /// (x, y), if any (B.12.1.3).
+    fn precinct_at(&self, tile: TileBounds, r: usize, x: usize, y: usize) -> Option<usize> {
+        let grid = self.resolutions.get(r)?;
+        if grid.precinct_count() == 0 {
+            return None;
+        }
+        let shift = self.resolutions.len() - 1 - r;
+        let x_span = self.dx << (grid.ppx as usize + shift);
+        let y_span = self.dy << (grid.ppy as usize + shift);
+        let starts_x = x.is_multiple_of(x_span)
+            || x == tile.0 && !(grid.x0 << shift).is_multiple_of(1 << (grid.ppx as usize + shift));
+        let starts_y = y.is_multiple_of(y_span)
+            || y == tile.1 && !(grid.y0 << shift).is_multiple_of(1 << (grid.ppy as usize + shift));
+        if !starts_x || !starts_y {
+            return None;
+        }
+        let px = (x.div_ceil(self.dx << shift) >> grid.ppx) - (grid.x0 >> grid.ppx);
+        let py = (y.div_ceil(self.dy << shift) >> grid.ppy) - (grid.y0 >> grid.ppy);
+        Some(py * grid.precincts_wide + px)
+    }
+}
+
+/// Packets of layers below `layer_end`, resolutions `resolution_start..resolution_end` and
+/// components `component_start..component_end`, in `order`. A codestream without POC
+/// marker segments has one volume covering the whole tile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ProgressionVolume {
+    pub order: ProgressionOrder,
+    pub layer_end: u16,
+    pub resolution_start: usize,
+    pub resolution_end: usize,
+    pub component_start: usize,
+    pub component_end: usize,
+}
+
+impl ProgressionVolume {
+    /// The volume covering every packet of a tile.
+    pub fn whole(order: ProgressionOrder, layers: u16, components: &[ComponentGrid]) -> Self {
+        Self {
+            order,
+            layer_end: layers,
+            resolution_start: 0,
+            resolution_end: components
+                .iter()
+                .map(|c| c.resolutions.len())
+                .max()
+                .unwrap_or(0),
+            component_start: 0,
+            component_end: components.len(),
+        }
+    }
+}
+
+/// Every packet of a tile in progression order (B.12.1). The volumes are walked in turn
+/// and a packet already visited by an earlier volume is not repeated. The
+/// position-driven orders visit precincts by their location on the reference grid.
+pub(crate) fn packet_sequence(
+    tile: TileBounds,
+    components: &[ComponentGrid],
+    layers: u16,
+    volumes: &[ProgressionVolume],
+) -> Vec<PacketId> {
+    // Next layer of every precinct, so packets shared by volumes are visited once.
+    let mut next_layers: Vec<Vec<Vec<u16>>> = components
+        .iter()
+        .map(|component| {
+            let precincts = |grid: &ResolutionGrid| vec![0; grid.precinct_count()];
+            component.resolutions.iter().map(precincts).collect()
+        })
+        .collect();
+    let mut packets = Vec::new();
+
+    for volume in volumes {
+        let layer_end = volume.layer_end.min(layers);
+        let component_end = volume.component_end.min(components.len());
+        if volume.component_start >= component_end {
+            continue;
+        }
+        let component_range = volume.component_start..component_end;
+        let resolution_range = |c: usize| {
+            volume.resolution_start..volume.resolution_end.min(components[c].resolutions.len())
+        };
+        let resolution_end = component_range
+            .clone()
+            .map(|c| resolution_range(c).end)
+            .max()
+            .unwrap_or(0);
+        let precincts = |c: usize, r: usize| {
+            if resolution_range(c).contains(&r) {
+                components[c].resolutions[r].precinct_count()
+            } else {
+                0
+            }
+        };
+        let precinct_at = |c: usize, r: usize, x: usize, y: usize| {
+            if resolution_range(c).contains(&r) {
+                components[c].precinct_at(tile, r, x, y)
+            } else {
+                None
+            }
+        };
+        let mut visit = |l: u16, r: usize, c: usize, p: usize| {
+            let next = &mut next_layers[c][r][p];
+            if l >= *next {
+                *next = l + 1;
+                packets.push((l, r, c, p));
+            }
+        };
+
+        match volume.order {
+            ProgressionOrder::Lrcp => {
+                for l in 0..layer_end {
+                    for r in volume.resolution_start..resolution_end {
+                        for c in component_range.clone() {
+                            for p in 0..precincts(c, r) {
+                                visit(l, r, c, p);
+                            }
+                        }
+                    }
+                }
+            }
+            ProgressionOrder::Rlcp => {
+                for r in volume.resolution_start..resolution_end {
+                    for l in 0..layer_end {
+                        for c in component_range.clone() {
+                            for p in 0..precincts(c, r) {
+                                visit(l, r, c, p);
+                            }
+                        }
+                    }
+                }
+            }
+            ProgressionOrder::Rpcl => {
+                let positions = precinct_positions(tile, &components[component_range.clone()]);
+                for r in volume.resolution_start..resolution_end {
+                    for &(y, x) in &positions {
+                        for c in component_range.clone() {
+                            if let Some(p) = precinct_at(c, r, x, y) {
+                                for l in 0..layer_end {
+                                    visit(l, r, c, p);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            ProgressionOrder::Pcrl => {
+                let positions = precinct_positions(tile, &components[component_range.clone()]);
+                for (y, x) in positions {
+                    for c in component_range.clone() {
+                        for r in volume.resolution_start..resolution_end {
+                            if let Some(p) = precinct_at(c, r, x, y) {
+                                for l in 0..layer_end {
+                                    visit(l, r, c, p);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            ProgressionOrder::Cprl => {
+                for c in component_range.clone() {
+                    for (y, x) in precinct_positions(tile, &components[c..=c]) {
+                        for r in volume.resolution_start..resolution_end {
+                            if let Some(p) = precinct_at(c, r, x, y) {
+                                for l in 0..layer_end {
+                                    visit(l, r, c, p);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    packets
+}
+
+/// Reference grid positions (y, x) of a tile where a precinct of some component and
+/// resolution may start: the tile origin and multiples of the smallest precinct step.
+fn precinct_positions(tile: TileBounds, components: &[ComponentGrid]) -> Vec<(usize, usize)> {
+    let steps = |subsampling: fn(&ComponentGrid) -> usize, pp: fn(&ResolutionGrid) -> u8| {
+        components
+            .iter()
+            .flat_map(|component| {
+                let levels = component.resolutions.len() - 1;
+                component
+                    .resolutions
+                    .iter()
+                    .enumerate()
+                    .map(move |(r, grid)| {
+                        subsampling(component) << (pp(grid) as usize + levels - r)
+                    })
+            })
+            .min()
+            .unwrap_or(1)
+    };
+    let x_step = steps(|c| c.dx, |g| g.ppx);
+    let y_step = steps(|c| c.dy, |g| g.ppy);
+    let positions = |start: usize, end: usize, step: usize| {
+        std::iter::successors(Some(start), move |&v| Some((v / step + 1) * step))
+            .take_while(move |&v| v < end)
+    };
+    positions(tile.1, tile.3, y_step)
+        .flat_map(|y| positions(tile.0, tile.2, x_step).map(move |x| (y, x)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subsampled_components_visit_every_packet() {
+        // 4:2:0 with 16x16 precincts on every resolution of an unaligned tile.
+        let tile = (8, 4, 72, 50);
+        let components = [
+            ComponentGrid::new(tile, (1, 1), 2, &[(4, 4); 3]),
+            ComponentGrid::new(tile, (2, 2), 2, &[(4, 4); 3]),
+            ComponentGrid::new(tile, (2, 2), 2, &[(4, 4); 3]),
+        ];
+        let whole = |order| [ProgressionVolume::whole(order, 2, &components)];
+        let mut expected = packet_sequence(tile, &components, 2, &whole(ProgressionOrder::Lrcp));
+        let total: usize = components
+            .iter()
+            .flat_map(|c| &c.resolutions)
+            .map(ResolutionGrid::precinct_count)
+            .sum();
+        assert_eq!(expected.len(), 2 * total);
+        expected.sort();
+        for order in [
+            ProgressionOrder::Rlcp,
+            ProgressionOrder::Rpcl,
+            ProgressionOrder::Pcrl,
+            ProgressionOrder::Cprl,
+        ] {
+            let mut packets = packet_sequence(tile, &components, 2, &whole(order));
+            packets.sort();
+            assert_eq!(packets, expected, "{:?}", order);
+        }
+    }
+
+    #[test]
+    fn test_code_block_grids() {
+        // 32x32 code-blocks; at resolution 1 the 64x64 precincts are 32x32 in the subbands.
+        let grid = ComponentGrid::new((0, 0, 100, 60), (1, 1), 1, &[(6, 6); 2]);
+        let cb = |x0, y0, wide, high| CodeBlockGrid { x0, y0, wide, high };
+        assert_eq!(
+            grid.resolutions[0].code_block_grids(0, 0, (5, 5)),
+            [cb(0, 0, 2, 1)]
+        );
+        let resolution = &grid.resolutions[1];
+        assert_eq!(resolution.precinct_count(), 2);
+        assert_eq!(
+            resolution.code_block_grids(1, 0, (6, 6)),
+            [cb(0, 0, 1, 1); 3]
+        );
+        assert_eq!(
+            resolution.code_block_grids(1, 1, (6, 6)),
+            [cb(1, 0, 1, 1); 3]
+        );
+    }
+
+    #[test]
+    fn test_volumes_skip_visited_packets() {
+        let tile = (0, 0, 32, 32);
+        let components = vec![ComponentGrid::new(tile, (1, 1), 1, &[]); 2];
+        // Resolution 0 of every component in RLCP, then everything in CPRL.
+        let volumes = [
+            ProgressionVolume {
+                order: ProgressionOrder::Rlcp,
+                layer_end: 2,
+                resolution_start: 0,
+                resolution_end: 1,
+                component_start: 0,
+                component_end: 2,
+            },
+            ProgressionVolume::whole(ProgressionOrder::Cprl, 2, &components),
+        ];
+        let packets = packet_sequence(tile, &components, 2, &volumes);
+        assert_eq!(
+            packets,
+            [
+                (0, 0, 0, 0),
+                (0, 0, 1, 0),
+                (1, 0, 0, 0),
+                (1, 0, 1, 0),
+                (0, 1, 0, 0),
+                (1, 1, 0, 0),
+                (0, 1, 1, 0),
+                (1, 1, 1, 0),
+            ]
+        );
+
+        assert_eq!(ProgressionOrder::try_from(2), Ok(ProgressionOrder::Rpcl));
+        assert!(ProgressionOrder::try_from(5).is_err());
+    }
+}
@@ -28,6 +28,13 @@ pub fn dequantize_scalar(q: i32, step_size: f32) -> f32 {
 // For 5/3 Integer, quantization is implicit (step_size = 1.0, effectively lossless if no shift)
 // Usually just bit-shifts.
 
+/// `floor(log2(value))` of a positive `value`, read from its exponent bits rather than
+/// computed with `f32::log2`, whose last bit depends on the platform's math library;
+/// subnormal values give -127.
+pub(crate) fn floor_log2(value: f32) -> i32 {
+    ((value.to_bits() >> 23) & 0xFF) as i32 - 127
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -45,4 +52,12 @@ mod tests {
         // Deadzone quantization is lossy.
         assert!((val - recon).abs() <= step);
     }
+
+    #[test]
+    fn test_floor_log2() {
+        assert_eq!(floor_log2(1.0), 0);
+        assert_eq!(floor_log2(0.75), -1);
+        assert_eq!(floor_log2(0.5), -1);
+        assert_eq!(floor_log2(1000.0), 9);
+    }
 }
@@ -2,6 +2,8 @@ use crate::jpeg2000::bit_io::{BitIoError, J2kBitReader, J2kBitWriter};
 
 /// Tag Tree for JPEG 2000 Packet Header coding.
 /// Represents a quad-tree structure used to encode 2D arrays of values (e.g. inclusion, zero bit-planes).
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TagTree {
     nodes: Vec<TagTreeNode>,
     leaf_width: usize,
@@ -9,6 +11,7 @@ pub struct TagTree {
 }
 
 #[derive(Clone, Default, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 struct TagTreeNode {
     value: i32,
     low: i32,
@@ -62,11 +65,13 @@ impl TagTree {
             levels.push((current_level_start, current_w, current_h));
         }
 
-        Self {
+        let mut tree = Self {
             nodes,
             leaf_width: w,
             leaf_height: h,
-        }
+        };
+        tree.reset();
+        tree
     }
 
     /// Reset the tree state (values and known status).
@@ -101,13 +106,20 @@ impl TagTree {
         node.known && node.low < threshold
     }
 
-    /// Set the value at a leaf coordinate (x, y).
+    /// Set the value at a leaf coordinate (x, y). Every node above it holds the
+    /// minimum of its children, so values may only be lowered once they are encoded.
     pub fn set_value(&mut self, x: usize, y: usize, value: i32) {
         if x >= self.leaf_width || y >= self.leaf_height {
             return;
         }
         let leaf_idx = y * self.leaf_width + x;
         self.nodes[leaf_idx].value = value;
+        let mut parent = self.nodes[leaf_idx].parent_index;
+        while let Some(idx) = parent {
+            let node = &mut self.nodes[idx];
+            node.value = node.value.min(value);
+            parent = node.parent_index;
+        }
     }
 
     /// Encode the value for leaf at (x, y) given a threshold.
@@ -140,8 +152,15 @@ impl TagTree {
         // bit=1 means "value equals current low" (found!)
         // bit=0 means "value is higher than current low" (continue)
         while let Some(curr_idx) = stack.pop() {
+            let parent_low = self.nodes[curr_idx]
+                .parent_index
+                .map_or(0, |p_idx| self.nodes[p_idx].low);
+
             let node = &mut self.nodes[curr_idx];
-            while node.low < threshold {
+            if node.low < parent_low {
+                node.low = parent_low;
+            }
+            while node.low < threshold && !node.known {
                 if node.value == node.low {
                     // Found: value equals current low, write 1
                     writer.write_bit(1);
@@ -206,10 +225,14 @@ impl TagTree {
                     break;
                 }
                 let bit = reader.read_bit()?;
-                if std::env::var("J2K_DEBUG").is_ok() {
-                    eprintln!("    TT[{}]: bit={} low={} known={} threshold={}", 
-                        curr_idx, bit, node.low, node.known, threshold);
-                }
+                j2k_trace!(
+                    "tag tree node {}: bit={} low={} known={} threshold={}",
+                    curr_idx,
+                    bit,
+                    node.low,
+                    node.known,
+                    threshold
+                );
                 // JPEG 2000 tag tree semantics (per OpenJPEG):
                 // bit=1 means "value equals current low" (found!)
                 // bit=0 means "value is higher than current low" (continue)
@@ -224,10 +247,12 @@ impl TagTree {
         }
 
         let result = self.nodes[leaf_idx].low >= threshold;
-        if std::env::var("J2K_DEBUG").is_ok() {
-            eprintln!("    TT result: low={} >= threshold={} ? {}", 
-                self.nodes[leaf_idx].low, threshold, result);
-        }
+        j2k_trace!(
+            "tag tree result: low={} >= threshold={} ? {}",
+            self.nodes[leaf_idx].low,
+            threshold,
+            result
+        );
         Ok(result)
     }
 }
@@ -0,0 +1,1093 @@
+//! Structural validation of JPEG 2000 codestreams and JP2 files.
+//!
+//! [`validate`] checks what a decoder relies on to find its way through a file without
+//! decoding any code-blocks: the JP2 box structure, marker ordering, main and tile-part
+//! header parameters, tile-part lengths (Psot, TLM) and the packet framing that can be
+//! checked from the outside (PLT packet lengths, SOP/EPH markers). Problems that make the
+//! file undecodable or violate a "shall" of ISO/IEC 15444-1 are errors; problems that
+//! common decoders tolerate are warnings.
+
+use super::jp2::Jp2Reader;
+
+const JP2_SIGNATURE: &[u8] = b"\x00\x00\x00\x0CjP  \r\n\x87\n";
+
+/// A problem found by [`validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationIssue {
+    /// Offset of the offending marker or box from the start of the file.
+    pub offset: usize,
+    pub message: String,
+}
+
+/// Result of [`validate`], with fatal errors and warnings reported separately.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub errors: Vec<ValidationIssue>,
+    pub warnings: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    /// True if no errors were found. Warnings do not make a file invalid.
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    fn error(&mut self, offset: usize, message: impl Into<String>) {
+        self.errors.push(ValidationIssue {
+            offset,
+            message: message.into(),
+        });
+    }
+
+    fn warning(&mut self, offset: usize, message: impl Into<String>) {
+        self.warnings.push(ValidationIssue {
+            offset,
+            message: message.into(),
+        });
+    }
+}
+
+/// Validates a JP2 file or a raw JPEG 2000 codestream.
+pub fn validate(data: &[u8]) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    if data.starts_with(JP2_SIGNATURE) {
+        validate_jp2(data, &mut report);
+    } else if data.starts_with(&[0xFF, 0x4F]) {
+        validate_codestream(data, 0, &mut report);
+    } else {
+        report.error(
+            0,
+            "Neither a JP2 signature nor a start of codestream (SOC) marker",
+        );
+    }
+    report
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Image parameters from SIZ, for comparison with the JP2 image header box.
+struct ImageSize {
+    width: u32,
+    height: u32,
+    /// Ssiz of each component: bit depth minus one, with the sign in the high bit.
+    depths: Vec<u8>,
+}
+
+// JP2 boxes
+
+fn validate_jp2(data: &[u8], report: &mut ValidationReport) {
+    let mut reader = Jp2Reader::new(data);
+    let mut box_start = 0;
+    let mut box_index = 0;
+    let mut header: Option<(usize, std::ops::Range<usize>)> = None;
+    let mut codestream_found = false;
+
+    loop {
+        let jp2_box = match reader.read_box() {
+            Ok(Some(jp2_box)) => jp2_box,
+            Ok(None) => break,
+            Err(_) => {
+                report.error(box_start, "Box length exceeds the file");
+                return;
+            }
+        };
+        let range = jp2_box.data_range.clone();
+        if range.end < range.start {
+            report.error(box_start, "Box length is smaller than its header");
+            return;
+        }
+        let contents = &data[range.clone()];
+
+        match (box_index, &jp2_box.box_type) {
+            (0, _) => {}
+            (1, b"ftyp") => validate_file_type(contents, box_start, report),
+            (1, box_type) => report.error(
+                box_start,
+                format!(
+                    "Second box must be the file type box, found '{}'",
+                    String::from_utf8_lossy(box_type)
+                ),
+            ),
+            (_, b"ftyp") => report.error(box_start, "Duplicate file type box"),
+            (_, b"jp2h") if header.is_some() => report.error(box_start, "Duplicate JP2 header box"),
+            (_, b"jp2h") if codestream_found => {
+                report.error(box_start, "JP2 header box must precede the codestream box");
+                header = Some((box_start, range.clone()));
+            }
+            (_, b"jp2h") => header = Some((box_start, range.clone())),
+            (_, b"jp2c") if !codestream_found => {
+                codestream_found = true;
+                let image_size = validate_codestream(contents, range.start, report);
+                if let (Some((header_offset, header_range)), Some(image_size)) =
+                    (&header, image_size)
+                {
+                    validate_header(
+                        &data[header_range.clone()],
+                        *header_offset,
+                        header_range.start,
+                        &image_size,
+                        report,
+                    );
+                }
+            }
+            _ => {}
+        }
+
+        box_start = range.end;
+        box_index += 1;
+    }
+
+    if box_start < data.len() {
+        report.warning(
+            box_start,
+            format!(
+                "{} trailing bytes after the last box",
+                data.len() - box_start
+            ),
+        );
+    }
+    if header.is_none() {
+        report.error(0, "Missing JP2 header box (jp2h)");
+    }
+    if !codestream_found {
+        report.error(0, "Missing contiguous codestream box (jp2c)");
+    }
+}
+
+fn validate_file_type(contents: &[u8], offset: usize, report: &mut ValidationReport) {
+    if contents.len() < 8 || !contents.len().is_multiple_of(4) {
+        report.error(offset, "File type box has an invalid length");
+        return;
+    }
+    // "jph " is the HTJ2K (Part 15) counterpart of "jp2 ".
+    let is_jp2 = |brand: &[u8]| brand == b"jp2 " || brand == b"jph ";
+    let compatible = contents[8..].chunks_exact(4).any(is_jp2);
+    if !is_jp2(&contents[..4]) {
+        report.warning(
+            offset,
+            format!(
+                "Brand is '{}', not 'jp2 ' or 'jph '",
+                String::from_utf8_lossy(&contents[..4])
+            ),
+        );
+    }
+    if !compatible {
+        report.error(
+            offset,
+            "File type box lists neither 'jp2 ' nor 'jph ' as compatible",
+        );
+    }
+}
+
+/// Checks the boxes inside the JP2 header box and compares the image header with SIZ.
+fn validate_header(
+    contents: &[u8],
+    offset: usize,
+    contents_offset: usize,
+    image_size: &ImageSize,
+    report: &mut ValidationReport,
+) {
+    let mut reader = Jp2Reader::new(contents);
+    let mut boxes = Vec::new();
+    loop {
+        match reader.read_box() {
+            Ok(Some(jp2_box)) if jp2_box.data_range.end >= jp2_box.data_range.start => {
+                boxes.push(jp2_box)
+            }
+            Ok(None) => break,
+            _ => {
+                report.error(
+                    offset,
+                    "JP2 header box contains a box with an invalid length",
+                );
+                return;
+            }
+        }
+    }
+
+    let Some(image_header) = boxes.first().filter(|b| b.box_type == *b"ihdr") else {
+        report.error(
+            offset,
+            "First box in the JP2 header must be the image header (ihdr)",
+        );
+        return;
+    };
+    let ihdr_offset = contents_offset + image_header.data_range.start - 8;
+    let ihdr = &contents[image_header.data_range.clone()];
+    if ihdr.len() != 14 {
+        report.error(ihdr_offset, "Image header box must be 14 bytes");
+        return;
+    }
+    if !boxes.iter().any(|b| b.box_type == *b"colr") {
+        report.error(
+            offset,
+            "JP2 header box has no colour specification box (colr)",
+        );
+    }
+
+    let height = u32_at(ihdr, 0).unwrap_or_default();
+    let width = u32_at(ihdr, 4).unwrap_or_default();
+    let components = u16_at(ihdr, 8).unwrap_or_default();
+    let depth = ihdr[10];
+    if (width, height) != (image_size.width, image_size.height) {
+        report.error(
+            ihdr_offset,
+            format!(
+                "Image header size {}x{} differs from the codestream size {}x{}",
+                width, height, image_size.width, image_size.height
+            ),
+        );
+    }
+    if components as usize != image_size.depths.len() {
+        report.error(
+            ihdr_offset,
+            format!(
+                "Image header has {} components, the codestream {}",
+                components,
+                image_size.depths.len()
+            ),
+        );
+    }
+    if depth == 0xFF {
+        if !boxes.iter().any(|b| b.box_type == *b"bpcc") {
+            report.error(
+                ihdr_offset,
+                "Image header declares varying bit depths but there is no bpcc box",
+            );
+        }
+    } else if image_size.depths.iter().any(|&d| d != depth) {
+        report.error(
+            ihdr_offset,
+            "Image header bit depth differs from the codestream component depths",
+        );
+    }
+    if ihdr[11] != 7 {
+        report.error(
+            ihdr_offset,
+            format!("Compression type is {}, must be 7", ihdr[11]),
+        );
+    }
+}
+
+// Codestream
+
+/// Coding parameters needed to check later markers.
+#[derive(Default)]
+struct CodingStyle {
+    uses_sop: bool,
+    uses_eph: bool,
+    decomposition_levels: u8,
+}
+
+/// Per-tile bookkeeping of tile-parts.
+#[derive(Default, Clone)]
+struct TileParts {
+    seen: u32,
+    /// TNsot from the first tile-part that declares it.
+    declared: Option<u8>,
+    /// Offset of the first tile-part, for errors about the whole tile.
+    offset: usize,
+    /// Sum of the PLT packet lengths, if every tile-part so far had PLT markers.
+    packet_lengths: Option<u64>,
+    bit_stream_length: u64,
+    /// Nsop of the last SOP marker; numbering continues across tile-parts.
+    sop_sequence: Option<u16>,
+    /// Set after the first out-of-order SOP marker, which is the only one reported.
+    sop_out_of_order: bool,
+}
+
+fn validate_codestream(
+    data: &[u8],
+    base: usize,
+    report: &mut ValidationReport,
+) -> Option<ImageSize> {
+    if !data.starts_with(&[0xFF, 0x4F]) {
+        report.error(base, "Codestream does not start with SOC");
+        return None;
+    }
+    if u16_at(data, 2) != Some(0xFF51) {
+        report.error(base + 2, "SIZ must immediately follow SOC");
+        return None;
+    }
+    let siz_length = u16_at(data, 4)? as usize;
+    let Some(siz) = data.get(6..4 + siz_length) else {
+        report.error(base + 2, "SIZ segment truncated");
+        return None;
+    };
+    let (image_size, tile_count) = validate_image_and_tile_size(siz, base + 2, report)?;
+
+    // Main header
+    let mut position = 4 + siz_length;
+    let mut coding_style = None;
+    let mut quantization: Option<(usize, u8, usize)> = None;
+    let mut has_capabilities = false;
+    let mut has_ppm = false;
+    let mut tile_part_lengths_declared = Vec::new();
+    loop {
+        let Some(marker) = u16_at(data, position) else {
+            report.error(base + position, "Codestream ends in the main header");
+            return Some(image_size);
+        };
+        if marker == 0xFF90 {
+            break;
+        }
+        let name = marker_name(marker);
+        match marker {
+            0xFFD9 => {
+                report.error(base + position, "Codestream has no tile-parts");
+                return Some(image_size);
+            }
+            0xFF51 => report.error(base + position, "Duplicate SIZ marker"),
+            0xFF52 | 0xFF53 | 0xFF5C | 0xFF5D | 0xFF5E | 0xFF5F | 0xFF60 | 0xFF55 | 0xFF57
+            | 0xFF63 | 0xFF64 | 0xFF50 | 0xFF59 => {}
+            0xFF58 | 0xFF61 => report.error(
+                base + position,
+                format!("{} is not allowed in the main header", name),
+            ),
+            0xFF91..=0xFF93 => {
+                report.error(
+                    base + position,
+                    format!("{} is not allowed in the main header", name),
+                );
+                return Some(image_size);
+            }
+            _ if marker < 0xFF30 => {
+                report.error(
+                    base + position,
+                    format!(
+                        "Expected a marker in the main header, found 0x{:04X}",
+                        marker
+                    ),
+                );
+                return Some(image_size);
+            }
+            _ => report.warning(base + position, format!("Unknown marker {}", name)),
+        }
+
+        let Some(segment) = segment(data, position) else {
+            report.error(base + position, format!("{} segment truncated", name));
+            return Some(image_size);
+        };
+        match marker {
+            0xFF52 => {
+                if coding_style.is_some() {
+                    report.error(base + position, "Duplicate COD marker in the main header");
+                }
+                coding_style = validate_coding_style(
+                    segment,
+                    base + position,
+                    image_size.depths.len(),
+                    report,
+                );
+            }
+            0xFF5C => {
+                if quantization.is_some() {
+                    report.error(base + position, "Duplicate QCD marker in the main header");
+                }
+                quantization = segment
+                    .first()
+                    .map(|&style| (base + position, style, segment.len() - 1));
+            }
+            0xFF50 => has_capabilities = true,
+            0xFF60 => has_ppm = true,
+            0xFF55 => tile_part_lengths_declared.extend(read_tile_part_lengths(
+                segment,
+                base + position,
+                report,
+            )),
+            _ => {}
+        }
+        position += 2 + segment.len() + 2;
+    }
+
+    let Some(coding_style) = coding_style else {
+        report.error(base, "Main header has no COD marker");
+        return Some(image_size);
+    };
+    match quantization {
+        None => report.error(base, "Main header has no QCD marker"),
+        Some((offset, style, step_bytes)) => {
+            validate_quantization(style, step_bytes, &coding_style, offset, report)
+        }
+    }
+    if siz_capabilities(siz) & 0x4000 != 0 && !has_capabilities {
+        report.error(
+            base + 2,
+            "Rsiz indicates HTJ2K (Part 15) but there is no CAP marker",
+        );
+    }
+
+    // Tile-parts
+    let mut tiles = vec![TileParts::default(); tile_count];
+    let mut tile_part_lengths = Vec::new();
+    loop {
+        match u16_at(data, position) {
+            Some(0xFF90) => {}
+            Some(0xFFD9) => {
+                position += 2;
+                break;
+            }
+            None if position == data.len() => {
+                report.warning(base + position, "Missing EOC marker");
+                break;
+            }
+            _ => {
+                report.error(
+                    base + position,
+                    "Expected SOT or EOC after a tile-part; a tile-part length (Psot) is wrong",
+                );
+                return Some(image_size);
+            }
+        }
+
+        let Some(end) = validate_tile_part(
+            data,
+            position,
+            base,
+            &coding_style,
+            has_ppm,
+            &mut tiles,
+            report,
+        ) else {
+            return Some(image_size);
+        };
+        tile_part_lengths.push((end - position) as u32);
+        position = end;
+    }
+
+    if position < data.len() {
+        report.warning(
+            base + position,
+            format!("{} bytes after the EOC marker", data.len() - position),
+        );
+    }
+    let missing: Vec<_> = (0..tiles.len()).filter(|&i| tiles[i].seen == 0).collect();
+    if !missing.is_empty() {
+        report.error(
+            base,
+            format!(
+                "{} of {} tiles have no tile-parts, the first is tile {}",
+                missing.len(),
+                tiles.len(),
+                missing[0]
+            ),
+        );
+    }
+    for (index, tile) in tiles.iter().enumerate().filter(|(_, tile)| tile.seen > 0) {
+        if let Some(declared) = tile.declared.filter(|&n| n != 0 && n as u32 != tile.seen) {
+            report.error(
+                tile.offset,
+                format!(
+                    "Tile {} has {} tile-parts, TNsot declares {}",
+                    index, tile.seen, declared
+                ),
+            );
+        }
+        if let Some(packet_lengths) = tile.packet_lengths {
+            if packet_lengths != tile.bit_stream_length {
+                report.error(
+                    tile.offset,
+                    format!(
+                        "Tile {}: PLT packet lengths add up to {} bytes but the tile-parts hold {}",
+                        index, packet_lengths, tile.bit_stream_length
+                    ),
+                );
+            }
+        }
+    }
+    if !tile_part_lengths_declared.is_empty() && tile_part_lengths_declared != tile_part_lengths {
+        report.error(
+            base,
+            format!(
+                "TLM tile-part lengths {:?} differ from the tile-parts {:?}",
+                tile_part_lengths_declared, tile_part_lengths
+            ),
+        );
+    }
+    Some(image_size)
+}
+
+/// Returns the payload of the marker segment at `position`, after the length field.
+fn segment(data: &[u8], position: usize) -> Option<&[u8]> {
+    let length = u16_at(data, position + 2)? as usize;
+    if length < 2 {
+        return None;
+    }
+    data.get(position + 4..position + 2 + length)
+}
+
+fn siz_capabilities(siz: &[u8]) -> u16 {
+    u16_at(siz, 0).unwrap_or_default()
+}
+
+fn validate_image_and_tile_size(
+    siz: &[u8],
+    offset: usize,
+    report: &mut ValidationReport,
+) -> Option<(ImageSize, usize)> {
+    if siz.len() < 36 {
+        report.error(offset, "SIZ segment truncated");
+        return None;
+    }
+    let value = |index: usize| u32_at(siz, 2 + index * 4).unwrap_or_default();
+    let (width, height) = (value(0), value(1));
+    let (x_offset, y_offset) = (value(2), value(3));
+    let (tile_width, tile_height) = (value(4), value(5));
+    let (tile_x_offset, tile_y_offset) = (value(6), value(7));
+    let components = u16_at(siz, 34).unwrap_or_default() as usize;
+
+    if siz.len() != 36 + 3 * components {
+        report.error(
+            offset,
+            format!("SIZ length does not match its {} components", components),
+        );
+        return None;
+    }
+    if components == 0 || components > 16384 {
+        report.error(offset, format!("Invalid component count {}", components));
+        return None;
+    }
+    if width <= x_offset || height <= y_offset {
+        report.error(
+            offset,
+            "Image area is empty (Xsiz/Ysiz not above XOsiz/YOsiz)",
+        );
+        return None;
+    }
+    if tile_width == 0 || tile_height == 0 {
+        report.error(offset, "Tile size is zero");
+        return None;
+    }
+    if tile_x_offset > x_offset
+        || tile_y_offset > y_offset
+        || tile_x_offset as u64 + tile_width as u64 <= x_offset as u64
+        || tile_y_offset as u64 + tile_height as u64 <= y_offset as u64
+    {
+        report.error(offset, "First tile does not overlap the image area");
+    }
+
+    let mut depths = Vec::with_capacity(components);
+    for component in 0..components {
+        let entry = &siz[36 + component * 3..39 + component * 3];
+        if (entry[0] & 0x7F) + 1 > 38 {
+            report.error(
+                offset,
+                format!("Component {} depth exceeds 38 bits", component),
+            );
+        }
+        if entry[1] == 0 || entry[2] == 0 {
+            report.error(
+                offset,
+                format!("Component {} has zero subsampling", component),
+            );
+        }
+        depths.push(entry[0]);
+    }
+
+    let tiles_x = (width - tile_x_offset).div_ceil(tile_width) as usize;
+    let tiles_y = (height - tile_y_offset).div_ceil(tile_height) as usize;
+    if tiles_x * tiles_y > 65535 {
+        report.error(
+            offset,
+            format!("{} tiles exceed the limit of 65535", tiles_x * tiles_y),
+        );
+        return None;
+    }
+    let image_size = ImageSize {
+        width: width - x_offset,
+        height: height - y_offset,
+        depths,
+    };
+    Some((image_size, tiles_x * tiles_y))
+}
+
+fn validate_coding_style(
+    cod: &[u8],
+    offset: usize,
+    components: usize,
+    report: &mut ValidationReport,
+) -> Option<CodingStyle> {
+    if cod.len() < 10 {
+        report.error(offset, "COD segment truncated");
+        return None;
+    }
+    let style = cod[0];
+    let levels = cod[5];
+    let expected_length = 10
+        + if style & 1 != 0 {
+            levels as usize + 1
+        } else {
+            0
+        };
+    if cod.len() != expected_length {
+        report.error(
+            offset,
+            format!(
+                "COD length {} does not match its parameters ({})",
+                cod.len(),
+                expected_length
+            ),
+        );
+    }
+    if cod[1] > 4 {
+        report.error(offset, format!("Invalid progression order {}", cod[1]));
+    }
+    if u16_at(cod, 2) == Some(0) {
+        report.error(offset, "Number of layers is zero");
+    }
+    if cod[4] > 1 {
+        report.warning(
+            offset,
+            format!("Multiple component transform {} is not Part 1", cod[4]),
+        );
+    } else if cod[4] == 1 && components < 3 {
+        report.error(
+            offset,
+            "Multiple component transform needs at least 3 components",
+        );
+    }
+    if levels > 32 {
+        report.error(offset, format!("{} decomposition levels exceed 32", levels));
+    }
+    let (block_width, block_height) = (cod[6], cod[7]);
+    if block_width > 8 || block_height > 8 || block_width + block_height > 8 {
+        report.error(
+            offset,
+            format!(
+                "Code-block size {}x{} exceeds the limits",
+                1u32 << (block_width.min(30) + 2),
+                1u32 << (block_height.min(30) + 2)
+            ),
+        );
+    }
+    if cod[9] > 1 {
+        report.error(offset, format!("Invalid wavelet transform {}", cod[9]));
+    }
+    Some(CodingStyle {
+        uses_sop: style & 0x02 != 0,
+        uses_eph: style & 0x04 != 0,
+        decomposition_levels: levels,
+    })
+}
+
+fn validate_quantization(
+    style: u8,
+    step_bytes: usize,
+    coding_style: &CodingStyle,
+    offset: usize,
+    report: &mut ValidationReport,
+) {
+    let subbands = 3 * coding_style.decomposition_levels as usize + 1;
+    let expected = match style & 0x1F {
+        0 => subbands,
+        1 => 2,
+        2 => 2 * subbands,
+        other => {
+            report.error(offset, format!("Invalid quantization style {}", other));
+            return;
+        }
+    };
+    if step_bytes != expected {
+        report.error(
+            offset,
+            format!(
+                "QCD has {} bytes of step sizes, {} decomposition levels need {}",
+                step_bytes, coding_style.decomposition_levels, expected
+            ),
+        );
+    }
+}
+
+fn read_tile_part_lengths(tlm: &[u8], offset: usize, report: &mut ValidationReport) -> Vec<u32> {
+    let Some(&stlm) = tlm.get(1) else {
+        report.error(offset, "TLM segment truncated");
+        return Vec::new();
+    };
+    let index_bytes = match (stlm >> 4) & 0x03 {
+        3 => {
+            report.error(offset, "Invalid TLM index size");
+            return Vec::new();
+        }
+        size => size as usize,
+    };
+    let length_bytes = if stlm & 0x40 != 0 { 4 } else { 2 };
+    let entries = &tlm[2..];
+    if !entries.len().is_multiple_of(index_bytes + length_bytes) {
+        report.error(offset, "TLM length is not a whole number of entries");
+    }
+    entries
+        .chunks_exact(index_bytes + length_bytes)
+        .map(|entry| {
+            entry[index_bytes..]
+                .iter()
+                .fold(0u32, |length, &b| length << 8 | b as u32)
+        })
+        .collect()
+}
+
+/// Validates the tile-part starting with the SOT marker at `position` and returns the
+/// offset of the byte after it, or `None` if the codestream cannot be followed further.
+fn validate_tile_part(
+    data: &[u8],
+    position: usize,
+    base: usize,
+    coding_style: &CodingStyle,
+    has_ppm: bool,
+    tiles: &mut [TileParts],
+    report: &mut ValidationReport,
+) -> Option<usize> {
+    let offset = base + position;
+    let Some(sot) = segment(data, position).filter(|sot| sot.len() == 8) else {
+        report.error(offset, "SOT segment must be 10 bytes");
+        return None;
+    };
+    let tile_index = u16_at(sot, 0)? as usize;
+    let tile_part_length = u32_at(sot, 2)? as usize;
+    let (tile_part_index, tile_part_count) = (sot[6], sot[7]);
+
+    let end = if tile_part_length == 0 {
+        // Only the last tile-part may omit its length; it then runs to EOC.
+        if data.ends_with(&[0xFF, 0xD9]) {
+            data.len() - 2
+        } else {
+            data.len()
+        }
+    } else {
+        position + tile_part_length
+    };
+    if tile_part_length != 0 && tile_part_length < 14 {
+        report.error(
+            offset,
+            format!("Tile-part length {} is too small", tile_part_length),
+        );
+        return None;
+    }
+    if end > data.len() {
+        report.error(
+            offset,
+            format!(
+                "Tile-part length {} runs {} bytes past the end of the codestream",
+                tile_part_length,
+                end - data.len()
+            ),
+        );
+        return None;
+    }
+
+    let tile_count = tiles.len();
+    let mut unknown_tile = TileParts::default();
+    let tile = match tiles.get_mut(tile_index) {
+        None => {
+            report.error(
+                offset,
+                format!(
+                    "Tile index {} exceeds the {} tiles of the image",
+                    tile_index, tile_count
+                ),
+            );
+            &mut unknown_tile
+        }
+        Some(tile) => {
+            if tile_part_index as u32 != tile.seen {
+                report.warning(
+                    offset,
+                    format!(
+                        "Tile {} part {} follows {} earlier parts",
+                        tile_index, tile_part_index, tile.seen
+                    ),
+                );
+            }
+            if tile_part_count != 0 {
+                if tile
+                    .declared
+                    .is_some_and(|declared| declared != tile_part_count)
+                {
+                    report.error(
+                        offset,
+                        format!("Tile {} declares different TNsot values", tile_index),
+                    );
+                }
+                tile.declared.get_or_insert(tile_part_count);
+            }
+            if tile.seen == 0 {
+                tile.offset = offset;
+                tile.packet_lengths = Some(0);
+            }
+            tile.seen += 1;
+            tile
+        }
+    };
+
+    // Tile-part header
+    let mut header_position = position + 12;
+    let mut packet_lengths: Option<u64> = None;
+    loop {
+        let Some(marker) = u16_at(data, header_position).filter(|_| header_position < end) else {
+            report.error(offset, "Tile-part header has no SOD marker");
+            return Some(end);
+        };
+        if marker == 0xFF93 {
+            break;
+        }
+        let name = marker_name(marker);
+        match marker {
+            0xFF52 | 0xFF53 | 0xFF5C | 0xFF5D | 0xFF5E | 0xFF5F if tile_part_index > 0 => report
+                .error(
+                    base + header_position,
+                    format!("{} is only allowed in the first tile-part of a tile", name),
+                ),
+            0xFF52 | 0xFF53 | 0xFF5C | 0xFF5D | 0xFF5E | 0xFF5F | 0xFF58 | 0xFF64 => {}
+            0xFF61 if has_ppm => report.error(
+                base + header_position,
+                "PPT is not allowed together with PPM",
+            ),
+            0xFF61 => {}
+            _ if marker < 0xFF30 => {
+                report.error(
+                    base + header_position,
+                    format!(
+                        "Expected a marker in the tile-part header, found 0x{:04X}",
+                        marker
+                    ),
+                );
+                return Some(end);
+            }
+            _ => report.error(
+                base + header_position,
+                format!("{} is not allowed in a tile-part header", name),
+            ),
+        }
+        let Some(segment) =
+            segment(data, header_position).filter(|s| header_position + 4 + s.len() <= end)
+        else {
+            report.error(
+                base + header_position,
+                format!("{} segment truncated", name),
+            );
+            return Some(end);
+        };
+        if marker == 0xFF58 {
+            *packet_lengths.get_or_insert(0) +=
+                sum_packet_lengths(&segment[1.min(segment.len())..]);
+        }
+        header_position += 4 + segment.len();
+    }
+
+    let bit_stream_start = header_position + 2;
+    let bit_stream = &data[bit_stream_start..end];
+    // PLT markers may describe packets of later tile-parts of the same tile, so the
+    // lengths are compared with the bit streams of the whole tile.
+    tile.packet_lengths = tile.packet_lengths.zip(packet_lengths).map(|(a, b)| a + b);
+    tile.bit_stream_length += bit_stream.len() as u64;
+    validate_packet_markers(
+        bit_stream,
+        base + bit_stream_start,
+        coding_style,
+        tile,
+        report,
+    );
+    Some(end)
+}
+
+/// Adds up the packet lengths of a PLT segment (7 bits per byte, high bit continues).
+fn sum_packet_lengths(lengths: &[u8]) -> u64 {
+    let mut total = 0;
+    let mut length = 0u64;
+    for &b in lengths {
+        length = length << 7 | (b & 0x7F) as u64;
+        if b & 0x80 == 0 {
+            total += length;
+            length = 0;
+        }
+    }
+    total
+}
+
+/// Checks SOP and EPH markers in a tile-part bit stream. Bit stuffing guarantees that
+/// 0xFF in packet data is followed by a byte below 0x90, so any 0xFF 0x9x pair is a marker.
+fn validate_packet_markers(
+    bit_stream: &[u8],
+    offset: usize,
+    coding_style: &CodingStyle,
+    tile: &mut TileParts,
+    report: &mut ValidationReport,
+) {
+    let mut sop_count = 0;
+    let mut eph_count = 0;
+    let mut position = 0;
+    while position + 1 < bit_stream.len() {
+        if bit_stream[position] != 0xFF || bit_stream[position + 1] < 0x90 {
+            position += 1;
+            continue;
+        }
+        match bit_stream[position + 1] {
+            0x91 => {
+                sop_count += 1;
+                if !coding_style.uses_sop {
+                    report.warning(
+                        offset + position,
+                        "SOP marker although COD does not enable SOP",
+                    );
+                }
+                if u16_at(bit_stream, position + 2) != Some(4) {
+                    report.error(offset + position, "SOP segment length must be 4");
+                }
+                if let Some(sequence) = u16_at(bit_stream, position + 4) {
+                    let expected = tile.sop_sequence.map(|p| p.wrapping_add(1));
+                    if expected.is_some_and(|e| e != sequence) && !tile.sop_out_of_order {
+                        tile.sop_out_of_order = true;
+                        report.warning(
+                            offset + position,
+                            format!(
+                                "SOP sequence number {} follows {}; packets are out of order",
+                                sequence,
+                                tile.sop_sequence.unwrap_or_default()
+                            ),
+                        );
+                    }
+                    tile.sop_sequence = Some(sequence);
+                }
+                position += 6;
+            }
+            0x92 => {
+                eph_count += 1;
+                position += 2;
+            }
+            marker => {
+                report.error(
+                    offset + position,
+                    format!("Unexpected marker 0xFF{:02X} in tile-part data", marker),
+                );
+                return;
+            }
+        }
+    }
+
+    if bit_stream.is_empty() {
+        return;
+    }
+    if coding_style.uses_sop && !bit_stream.starts_with(&[0xFF, 0x91]) {
+        report.warning(
+            offset,
+            "COD enables SOP markers but the tile-part data does not start with SOP",
+        );
+    }
+    if coding_style.uses_eph && eph_count == 0 {
+        report.warning(
+            offset,
+            "COD enables EPH markers but the tile-part data has none",
+        );
+    }
+    if coding_style.uses_sop && coding_style.uses_eph && eph_count < sop_count {
+        report.warning(
+            offset,
+            format!(
+                "{} SOP markers but only {} EPH markers",
+                sop_count, eph_count
+            ),
+        );
+    }
+}
+
+fn marker_name(marker: u16) -> String {
+    let name = match marker {
+        0xFF4F => "SOC",
+        0xFF50 => "CAP",
+        0xFF51 => "SIZ",
+        0xFF52 => "COD",
+        0xFF53 => "COC",
+        0xFF55 => "TLM",
+        0xFF57 => "PLM",
+        0xFF58 => "PLT",
+        0xFF59 => "CPF",
+        0xFF5C => "QCD",
+        0xFF5D => "QCC",
+        0xFF5E => "RGN",
+        0xFF5F => "POC",
+        0xFF60 => "PPM",
+        0xFF61 => "PPT",
+        0xFF63 => "CRG",
+        0xFF64 => "COM",
+        0xFF90 => "SOT",
+        0xFF91 => "SOP",
+        0xFF92 => "EPH",
+        0xFF93 => "SOD",
+        0xFFD9 => "EOC",
+        _ => return format!("0x{:04X}", marker),
+    };
+    name.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SOC, SIZ (16x8, one 8-bit component), COD (0 levels), QCD, one tile-part, EOC.
+    fn codestream(tile_part_length: u32) -> Vec<u8> {
+        let mut data = vec![0xFF, 0x4F, 0xFF, 0x51, 0x00, 0x29, 0x00, 0x00];
+        for value in [16u32, 8, 0, 0, 16, 8, 0, 0] {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        data.extend_from_slice(&[0x00, 0x01, 0x07, 0x01, 0x01]);
+        data.extend_from_slice(&[0xFF, 0x52, 0x00, 0x0C, 0x00, 0x00, 0x00, 0x01, 0x00]);
+        data.extend_from_slice(&[0x00, 0x04, 0x04, 0x00, 0x01]);
+        data.extend_from_slice(&[0xFF, 0x5C, 0x00, 0x04, 0x00, 0x40]);
+        data.extend_from_slice(&[0xFF, 0x90, 0x00, 0x0A, 0x00, 0x00]);
+        data.extend_from_slice(&tile_part_length.to_be_bytes());
+        data.extend_from_slice(&[0x00, 0x01, 0xFF, 0x93, 0x01, 0x02, 0x03, 0xFF, 0xD9]);
+        data
+    }
+
+    #[test]
+    fn test_valid_codestream() {
+        let report = validate(&codestream(17));
+        assert_eq!(report, ValidationReport::default());
+        assert!(validate(&codestream(0)).is_valid());
+    }
+
+    #[test]
+    fn test_wrong_tile_part_length() {
+        let report = validate(&codestream(16));
+        assert!(!report.is_valid());
+        assert!(report.errors[0].message.contains("Expected SOT or EOC"));
+
+        let report = validate(&codestream(100));
+        assert!(report.errors[0].message.contains("past the end"));
+    }
+
+    #[test]
+    fn test_missing_eoc_is_a_warning() {
+        let mut data = codestream(17);
+        data.truncate(data.len() - 2);
+        let report = validate(&data);
+        assert!(report.is_valid());
+        assert_eq!(report.warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_jp2_header_must_match_codestream() {
+        let codestream = codestream(17);
+        let mut data = JP2_SIGNATURE.to_vec();
+        data.extend_from_slice(&[0, 0, 0, 20]);
+        data.extend_from_slice(b"ftypjp2 \0\0\0\0jp2 ");
+        // jp2h with an ihdr that claims 3 components, and a colr box.
+        data.extend_from_slice(&[0, 0, 0, 45]);
+        data.extend_from_slice(b"jp2h\0\0\0\x16ihdr\0\0\0\x08\0\0\0\x10\0\x03\x07\x07\0\0");
+        data.extend_from_slice(b"\0\0\0\x0Fcolr\x01\0\0\0\0\0\x11");
+        data.extend_from_slice(&(codestream.len() as u32 + 8).to_be_bytes());
+        data.extend_from_slice(b"jp2c");
+        data.extend_from_slice(&codestream);
+
+        let report = validate(&data);
+        assert_eq!(report.errors.len(), 1, "{:?}", report.errors);
+        assert!(report.errors[0].message.contains("3 components"));
+    }
+}
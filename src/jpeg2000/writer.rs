@@ -1,16 +1,22 @@
-use super::image::{J2kCod, J2kQcd};
-use crate::JpeglsError;
+use super::image::{J2kCap, J2kCod, J2kQcd};
 use crate::jpeg_marker_code::JpegMarkerCode;
-use crate::jpeg_stream_writer::JpegStreamWriter;
+use crate::jpeg_stream_writer::{JpegStreamWriter, Sink, SliceSink};
+use crate::JpeglsError;
 
-pub struct J2kWriter<'a> {
-    writer: JpegStreamWriter<'a>,
+pub struct J2kWriter<S: Sink> {
+    writer: JpegStreamWriter<S>,
 }
 
-impl<'a> J2kWriter<'a> {
+impl<'a> J2kWriter<SliceSink<'a>> {
     pub fn new(destination: &'a mut [u8]) -> Self {
+        Self::with_sink(SliceSink::new(destination))
+    }
+}
+
+impl<S: Sink> J2kWriter<S> {
+    pub fn with_sink(sink: S) -> Self {
         Self {
-            writer: JpegStreamWriter::new(destination),
+            writer: JpegStreamWriter::with_sink(sink),
         }
     }
 
@@ -32,6 +38,7 @@ impl<'a> J2kWriter<'a> {
 
     pub fn write_siz(
         &mut self,
+        rsiz: u16,
         width: u32,
         height: u32,
         tile_width: u32,
@@ -46,7 +53,7 @@ impl<'a> J2kWriter<'a> {
         // Length: 2 (Rsiz) + 4(W) + 4(H) + 4(OX) + 4(OY) + 4(TW) + 4(TH) + 4(TOX) + 4(TOY) + 2(C) + 3*C
         let length = 38 + 3 * component_count;
         self.writer.write_u16(length)?;
-        self.writer.write_u16(0)?; // Rsiz (Capabilities) - 0 for default
+        self.writer.write_u16(rsiz)?; // Rsiz (Capabilities): bit 14 for Part 15
         self.writer.write_u32(width)?;
         self.writer.write_u32(height)?;
         self.writer.write_u32(0)?; // OffX
@@ -65,6 +72,18 @@ impl<'a> J2kWriter<'a> {
         Ok(())
     }
 
+    /// Writes a CAP segment; it follows SIZ.
+    pub fn write_cap(&mut self, cap: &J2kCap) -> Result<(), JpeglsError> {
+        self.writer.write_marker(JpegMarkerCode::Capability)?;
+        // Lcap (2) + Pcap (4) + one Ccap (2) per Pcap bit set
+        self.writer.write_u16(6 + 2 * cap.ccap.len() as u16)?;
+        self.writer.write_u32(cap.pcap)?;
+        for &ccap in &cap.ccap {
+            self.writer.write_u16(ccap)?;
+        }
+        Ok(())
+    }
+
     pub fn write_cod(&mut self, cod: &J2kCod) -> Result<(), JpeglsError> {
         self.writer
             .write_marker(JpegMarkerCode::CodingStyleDefault)?;
@@ -77,7 +96,13 @@ impl<'a> J2kWriter<'a> {
         // Total 1 byte Scod + 4 bytes SG + 5 bytes SP = 10 bytes payload.
         // So len = 12.
 
-        let length = 12; // Minimal COD length
+        // Scod bit 0: one precinct size byte per resolution follows SPcod
+        let precinct_count = if cod.coding_style & 0x01 != 0 {
+            cod.precinct_sizes.len()
+        } else {
+            0
+        };
+        let length = 12 + precinct_count as u16;
         self.writer.write_u16(length)?;
 
         self.writer.write_byte(cod.coding_style)?;
@@ -85,14 +110,18 @@ impl<'a> J2kWriter<'a> {
         // SGcod
         self.writer.write_byte(cod.progression_order)?;
         self.writer.write_u16(cod.number_of_layers)?;
-        self.writer.write_byte(0)?; // MCT enabled?
+        self.writer.write_byte(cod.mct)?;
 
         // SPcod
-        self.writer.write_byte(5)?; // Number of decomposition levels (default 5 or struct field?)
-        self.writer.write_byte(4)?; // Code-block width (xcb) - 4 -> 16
-        self.writer.write_byte(4)?; // Code-block height (ycb) - 4 -> 16
-        self.writer.write_byte(0)?; // Code-block style
-        self.writer.write_byte(0)?; // Wavelet transform (0=9-7, 1=5-3)
+        self.writer.write_byte(cod.decomposition_levels)?;
+        self.writer.write_byte(cod.codeblock_width_exp)?; // xcb - 2
+        self.writer.write_byte(cod.codeblock_height_exp)?; // ycb - 2
+        self.writer.write_byte(cod.codeblock_style)?; // Code-block style
+        self.writer.write_byte(cod.transformation)?; // 0=9-7, 1=5-3
+
+        for &size in &cod.precinct_sizes[..precinct_count] {
+            self.writer.write_byte(size)?; // PPx | PPy << 4
+        }
 
         Ok(())
     }
@@ -103,13 +132,20 @@ impl<'a> J2kWriter<'a> {
 
         // Length: 3 (Sqcd) + 2 * step_sizes.len() + 2 (len field) = 5?
         // Lqcd (2) + Sqcd (1) + SPqcd (n)
-        let payload_len = 1 + qcd.step_sizes.len() * 2;
+        // Without quantization each SPqcd is a single byte (exponent << 3).
+        let reversible = qcd.quant_style & 0x1F == 0;
+        let step_len = if reversible { 1 } else { 2 };
+        let payload_len = 1 + qcd.step_sizes.len() * step_len;
         self.writer.write_u16((payload_len + 2) as u16)?;
 
         self.writer.write_byte(qcd.quant_style)?;
 
         for &step in &qcd.step_sizes {
-            self.writer.write_u16(step)?;
+            if reversible {
+                self.writer.write_byte(step as u8)?;
+            } else {
+                self.writer.write_u16(step)?;
+            }
         }
         Ok(())
     }
@@ -136,10 +172,7 @@ impl<'a> J2kWriter<'a> {
 
     // Helper to access internal buffer to write raw data (packets)
     pub fn write_bytes(&mut self, data: &[u8]) -> Result<(), JpeglsError> {
-        for &b in data {
-            self.writer.write_byte(b)?;
-        }
-        Ok(())
+        self.writer.write_bytes(data)
     }
 }
 
@@ -156,6 +189,7 @@ mod tests {
 
         j2k_writer
             .write_siz(
+                0, // Rsiz
                 512, 512, // W, H
                 128, 128, // TW, TH
                 3,   // Comps
@@ -192,7 +226,7 @@ mod tests {
         // Verify markers present
         assert_eq!(written[0], 0xFF);
         assert_eq!(written[1], 0x4F); // SOC
-        // SIZ
+                                      // SIZ
         assert_eq!(written[2], 0xFF);
         assert_eq!(written[3], 0x51);
         // COD
@@ -102,12 +102,26 @@ pub enum JpegMarkerCode {
     CodingStyleDefault = 0x52,
     /// COC: Coding style component
     CodingStyleComponent = 0x53,
+    /// TLM: Tile-part lengths
+    TilePartLengths = 0x55,
+    /// PLM: Packet length, main header
+    PacketLengthMain = 0x57,
+    /// PLT: Packet length, tile-part header
+    PacketLengthTilePart = 0x58,
     /// QCD: Quantization default
     QuantizationDefault = 0x5C,
     /// QCC: Quantization component
     QuantizationComponent = 0x5D,
     /// RGN: Region of Interest
     RegionOfInterest = 0x5E,
+    /// POC: Progression order change
+    ProgressionOrderChange = 0x5F,
+    /// PPM: Packed packet headers, main header
+    PackedPacketHeadersMain = 0x60,
+    /// PPT: Packed packet headers, tile-part header
+    PackedPacketHeadersTilePart = 0x61,
+    /// CRG: Component registration
+    ComponentRegistration = 0x63,
     /// SOT: Start of Tile
     StartOfTile = 0x90,
     /// SOP: Start of Packet
@@ -169,9 +183,16 @@ impl std::convert::TryFrom<u8> for JpegMarkerCode {
             0x51 => Ok(Self::ImageAndTileSize),
             0x52 => Ok(Self::CodingStyleDefault),
             0x53 => Ok(Self::CodingStyleComponent),
+            0x55 => Ok(Self::TilePartLengths),
+            0x57 => Ok(Self::PacketLengthMain),
+            0x58 => Ok(Self::PacketLengthTilePart),
             0x5C => Ok(Self::QuantizationDefault),
             0x5D => Ok(Self::QuantizationComponent),
             0x5E => Ok(Self::RegionOfInterest),
+            0x5F => Ok(Self::ProgressionOrderChange),
+            0x60 => Ok(Self::PackedPacketHeadersMain),
+            0x61 => Ok(Self::PackedPacketHeadersTilePart),
+            0x63 => Ok(Self::ComponentRegistration),
             0x90 => Ok(Self::StartOfTile),
             0x91 => Ok(Self::StartOfPacket),
             0x92 => Ok(Self::EndOfPacketHeader),
@@ -3,11 +3,14 @@
 //! This module provides the `JpegStreamReader` which handles the sequential
 //! reading of JPEG markers and segments (DQT, DHT, SOF, SOS, etc.).
 
-use crate::FrameInfo;
+use crate::{ComponentInfo, FrameInfo};
+use crate::constants::SPIFF_END_OF_DIRECTORY_ENTRY_TYPE;
 use crate::error::JpeglsError;
 use crate::jpeg_marker_code::{JPEG_MARKER_START_BYTE, JpegMarkerCode};
 use crate::jpegls::coding_parameters::{CodingParameters, JpeglsPcParameters};
-use crate::jpegls::{InterleaveMode, SpiffHeader};
+use crate::jpegls::color_transform::COLOR_TRANSFORMATION_IDENTIFIER;
+use crate::jpegls::{ColorTransformation, InterleaveMode, MappingTable, SpiffHeader};
+use std::borrow::Cow;
 use std::convert::{TryFrom, TryInto};
 
 /// Metadata for an individual image component (e.g. Y, Cb, Cr).
@@ -27,6 +30,8 @@ pub struct JpegComponent {
     pub ac_table_dest: u8,
     /// DC prediction state (used during scan decoding).
     pub dc_pred: i16,
+    /// JPEG-LS mapping table selected by the most recent scan of the component (0 for none).
+    pub mapping_table_id: u8,
 }
 
 /// Internal state of the stream reader.
@@ -34,13 +39,18 @@ pub struct JpegComponent {
 pub enum JpegStreamReaderState {
     BeforeStartOfImage,
     HeaderSection,
+    /// After a SPIFF header, until its end-of-directory entry.
+    SpiffHeaderSection,
     ScanSection,
     EndOfImage,
 }
 
 /// A reader for JPEG/JLS codestreams that manages marker parsing and segment state.
+///
+/// The codestream is borrowed ([`new`](Self::new)) or owned ([`from_vec`](Self::from_vec));
+/// an owned reader is `'static` and can be moved to another thread or task.
 pub struct JpegStreamReader<'a> {
-    source: &'a [u8],
+    source: Cow<'a, [u8]>,
     position: usize,
     bit_buffer: u8,
     bits_left: u8,
@@ -48,12 +58,18 @@ pub struct JpegStreamReader<'a> {
     frame_info: FrameInfo,
     parameters: CodingParameters,
     preset_coding_parameters: JpeglsPcParameters,
+    mapping_tables: Vec<MappingTable>,
+    height_from_define_number_of_lines: bool,
     spiff_header: Option<SpiffHeader>,
     pub quantization_tables: [[u8; 64]; 4],
     pub huffman_tables_dc: [Option<crate::jpeg1::huffman::HuffmanTable>; 4],
     pub huffman_tables_ac: [Option<crate::jpeg1::huffman::HuffmanTable>; 4],
     pub components: Vec<JpegComponent>,
     pub restart_interval: u16,
+    /// Set when a JFIF (APP0) segment was read.
+    pub jfif: bool,
+    /// Color transform flag of an Adobe (APP14) segment, when one was read.
+    pub adobe_transform: Option<u8>,
     pub scan_component_indices: Vec<usize>,
     pub is_lossless: bool,
     pub is_progressive: bool,
@@ -63,10 +79,30 @@ pub struct JpegStreamReader<'a> {
     pub se: u8,
     pub ah: u8,
     pub al: u8,
+    comment_handler: Option<Box<CommentHandler<'a>>>,
+    application_data_handler: Option<Box<ApplicationDataHandler<'a>>>,
+}
+
+/// Receives the payload of every COM segment.
+pub type CommentHandler<'a> = dyn FnMut(&[u8]) -> Result<(), JpeglsError> + Send + Sync + 'a;
+
+/// Receives the APPn number (0 to 15) and the payload of every application data segment.
+pub type ApplicationDataHandler<'a> =
+    dyn FnMut(u8, &[u8]) -> Result<(), JpeglsError> + Send + Sync + 'a;
+
+impl JpegStreamReader<'static> {
+    /// Creates a reader that owns the codestream.
+    pub fn from_vec(source: Vec<u8>) -> Self {
+        Self::with_source(Cow::Owned(source))
+    }
 }
 
 impl<'a> JpegStreamReader<'a> {
     pub fn new(source: &'a [u8]) -> Self {
+        Self::with_source(Cow::Borrowed(source))
+    }
+
+    fn with_source(source: Cow<'a, [u8]>) -> Self {
         Self {
             source,
             position: 0,
@@ -76,12 +112,16 @@ impl<'a> JpegStreamReader<'a> {
             frame_info: FrameInfo::default(),
             parameters: CodingParameters::default(),
             preset_coding_parameters: JpeglsPcParameters::default(),
+            mapping_tables: Vec::new(),
+            height_from_define_number_of_lines: false,
             spiff_header: None,
             quantization_tables: [[0u8; 64]; 4],
             huffman_tables_dc: [const { None }; 4],
             huffman_tables_ac: [const { None }; 4],
             components: Vec::new(),
             restart_interval: 0,
+            jfif: false,
+            adobe_transform: None,
             scan_component_indices: Vec::new(),
             is_lossless: false,
             is_progressive: false,
@@ -91,6 +131,8 @@ impl<'a> JpegStreamReader<'a> {
             se: 63,
             ah: 0,
             al: 0,
+            comment_handler: None,
+            application_data_handler: None,
         }
     }
 
@@ -98,6 +140,40 @@ impl<'a> JpegStreamReader<'a> {
         self.frame_info
     }
 
+    /// Precision and subsampling of the components of the frame header, derived from the
+    /// sampling factors: a component with half the largest factor is subsampled by 2.
+    pub fn component_info(&self) -> Vec<ComponentInfo> {
+        let max_h = self.components.iter().map(|c| c.h_samp_factor).max();
+        let max_v = self.components.iter().map(|c| c.v_samp_factor).max();
+        self.components
+            .iter()
+            .map(|component| ComponentInfo {
+                bits_per_sample: self.frame_info.bits_per_sample,
+                signed: false,
+                horizontal_subsampling: subsampling(max_h, component.h_samp_factor),
+                vertical_subsampling: subsampling(max_v, component.v_samp_factor),
+            })
+            .collect()
+    }
+
+    /// Sets the handler that is called for COM segments. An error returned by the handler
+    /// stops reading and is passed on to the caller.
+    pub fn set_comment_handler(
+        &mut self,
+        handler: impl FnMut(&[u8]) -> Result<(), JpeglsError> + Send + Sync + 'a,
+    ) {
+        self.comment_handler = Some(Box::new(handler));
+    }
+
+    /// Sets the handler that is called for APPn segments that are not interpreted by the
+    /// reader itself (SPIFF header and color transformation).
+    pub fn set_application_data_handler(
+        &mut self,
+        handler: impl FnMut(u8, &[u8]) -> Result<(), JpeglsError> + Send + Sync + 'a,
+    ) {
+        self.application_data_handler = Some(Box::new(handler));
+    }
+
     pub fn parameters(&self) -> CodingParameters {
         self.parameters
     }
@@ -106,6 +182,21 @@ impl<'a> JpegStreamReader<'a> {
         self.preset_coding_parameters
     }
 
+    /// Presets the coding parameters, e.g. to those of an abbreviated table specification.
+    /// An LSE segment in the stream replaces them.
+    pub fn set_preset_coding_parameters(&mut self, preset_coding_parameters: JpeglsPcParameters) {
+        self.preset_coding_parameters = preset_coding_parameters;
+    }
+
+    /// Mapping tables defined by the LSE segments read so far.
+    pub fn mapping_tables(&self) -> &[MappingTable] {
+        &self.mapping_tables
+    }
+
+    pub fn state(&self) -> JpegStreamReaderState {
+        self.state
+    }
+
     pub fn spiff_header(&self) -> Option<SpiffHeader> {
         self.spiff_header
     }
@@ -121,6 +212,20 @@ impl<'a> JpegStreamReader<'a> {
     pub fn read_header(
         &mut self,
         spiff_header: &mut Option<SpiffHeader>,
+    ) -> Result<(), JpeglsError> {
+        self.read_header_or_table_specification(spiff_header)?;
+        if self.state == JpegStreamReaderState::EndOfImage {
+            return Err(JpeglsError::InvalidData);
+        }
+        Ok(())
+    }
+
+    /// Like [`read_header`](Self::read_header), but also accepts an abbreviated table
+    /// specification: a stream that ends without a frame, leaving the reader in the
+    /// [`JpegStreamReaderState::EndOfImage`] state.
+    pub fn read_header_or_table_specification(
+        &mut self,
+        spiff_header: &mut Option<SpiffHeader>,
     ) -> Result<(), JpeglsError> {
         self.read_start_of_image()?;
 
@@ -135,14 +240,43 @@ impl<'a> JpegStreamReader<'a> {
                 }
                 JpegMarkerCode::StartOfScan => {
                     self.position -= 2;
+                    if self.frame_info.height == 0 && !self.components.is_empty() {
+                        self.find_define_number_of_lines()?;
+                    }
                     self.state = JpegStreamReaderState::HeaderSection;
                     break;
                 }
+                JpegMarkerCode::DefineNumberOfLines => {
+                    return Err(JpeglsError::UnexpectedDefineNumberOfLinesMarker);
+                }
+                // Only an abbreviated table specification ends without a frame.
+                JpegMarkerCode::EndOfImage => {
+                    if !self.components.is_empty() {
+                        return Err(JpeglsError::UnexpectedEndOfImageMarker);
+                    }
+                    self.state = JpegStreamReaderState::EndOfImage;
+                    break;
+                }
                 JpegMarkerCode::ApplicationData8 => {
-                    let spiff = self.read_spiff_header_segment()?;
-                    if spiff.is_some() {
-                        *spiff_header = spiff;
-                        self.spiff_header = spiff;
+                    if self.read_color_transform_segment()? {
+                        continue;
+                    }
+                    if self.state == JpegStreamReaderState::SpiffHeaderSection {
+                        // SPIFF directory entry; the end-of-directory entry closes the header.
+                        let end_of_directory = SPIFF_END_OF_DIRECTORY_ENTRY_TYPE as u32;
+                        if self.segment_starts_with(&end_of_directory.to_be_bytes()) {
+                            self.state = JpegStreamReaderState::HeaderSection;
+                        }
+                        self.skip_segment()?;
+                    } else if self.segment_starts_with(b"SPIFF\0") {
+                        let spiff = self.read_spiff_header_segment()?;
+                        if spiff.is_some() {
+                            *spiff_header = spiff;
+                            self.spiff_header = spiff;
+                            self.state = JpegStreamReaderState::SpiffHeaderSection;
+                        }
+                    } else {
+                        self.skip_or_report_segment(marker)?;
                     }
                 }
                 JpegMarkerCode::StartOfFrameBaseline => {
@@ -164,7 +298,7 @@ impl<'a> JpegStreamReader<'a> {
                     self.read_dri_segment()?;
                 }
                 _ => {
-                    self.skip_segment()?;
+                    self.skip_or_report_segment(marker)?;
                 }
             }
         }
@@ -232,29 +366,87 @@ impl<'a> JpegStreamReader<'a> {
         self.frame_info.width = self.read_u16()? as u32;
         self.frame_info.component_count = self.read_u8()? as i32;
 
+        self.components.clear();
         for _ in 0..self.frame_info.component_count {
-            let _id = self.read_u8()?;
-            let _sampling = self.read_u8()?;
+            let id = self.read_u8()?;
+            let sampling = self.read_u8()?;
             let _tq = self.read_u8()?;
+            self.components.push(JpegComponent {
+                id,
+                h_samp_factor: sampling >> 4,
+                v_samp_factor: sampling & 0x0F,
+                ..Default::default()
+            });
         }
         Ok(())
     }
 
-    fn read_jpegls_preset_parameters_segment(&mut self) -> Result<(), JpeglsError> {
-        let _length = self.read_u16()?;
+    /// Reads an LSE segment: preset coding parameters or (part of) a mapping table. The
+    /// marker must already have been read.
+    pub fn read_jpegls_preset_parameters_segment(&mut self) -> Result<(), JpeglsError> {
+        let length = self.read_u16()?;
         let param_type = self.read_u8()?;
-        if param_type == 1 {
-            self.preset_coding_parameters.maximum_sample_value = self.read_u16()? as i32;
-            self.preset_coding_parameters.threshold1 = self.read_u16()? as i32;
-            self.preset_coding_parameters.threshold2 = self.read_u16()? as i32;
-            self.preset_coding_parameters.threshold3 = self.read_u16()? as i32;
-            self.preset_coding_parameters.reset_value = self.read_u16()? as i32;
+        match param_type {
+            1 => {
+                self.preset_coding_parameters.maximum_sample_value = self.read_u16()? as i32;
+                self.preset_coding_parameters.threshold1 = self.read_u16()? as i32;
+                self.preset_coding_parameters.threshold2 = self.read_u16()? as i32;
+                self.preset_coding_parameters.threshold3 = self.read_u16()? as i32;
+                self.preset_coding_parameters.reset_value = self.read_u16()? as i32;
+            }
+            2 | 3 => self.read_mapping_table_segment(param_type == 3, length as usize)?,
+            _ => self.position += (length as usize) - 3,
+        }
+        Ok(())
+    }
+
+    /// Reads the rest of an LSE segment of type 2 (mapping table) or 3 (continuation of
+    /// the table with the same ID and entry size).
+    fn read_mapping_table_segment(
+        &mut self,
+        continuation: bool,
+        length: usize,
+    ) -> Result<(), JpeglsError> {
+        if length < 2 + 3 || self.position + length - 3 > self.source.len() {
+            return Err(JpeglsError::InvalidMarkerSegmentSize);
+        }
+        let table_id = self.read_u8()?;
+        let entry_size = self.read_u8()?;
+        let data = &self.source[self.position..self.position + length - 5];
+        self.position += data.len();
+        if table_id == 0 || entry_size == 0 {
+            return Err(JpeglsError::InvalidParameterMappingTableId);
+        }
+
+        if continuation {
+            let table = self
+                .mapping_tables
+                .iter_mut()
+                .find(|table| table.table_id == table_id && table.entry_size == entry_size)
+                .ok_or(JpeglsError::InvalidParameterMappingTableContinuation)?;
+            table.data.extend_from_slice(data);
         } else {
-            self.position += (_length as usize) - 3;
+            self.store_mapping_table(MappingTable {
+                table_id,
+                entry_size,
+                data: data.to_vec(),
+            });
         }
         Ok(())
     }
 
+    /// Adds `table`, replacing an earlier table with the same ID.
+    fn store_mapping_table(&mut self, table: MappingTable) {
+        match self
+            .mapping_tables
+            .iter_mut()
+            .find(|existing| existing.table_id == table.table_id)
+        {
+            Some(existing) => *existing = table,
+            None => self.mapping_tables.push(table),
+        }
+    }
+
     pub fn read_start_of_scan_segment_jpegls(&mut self) -> Result<(), JpeglsError> {
         if self.read_marker()? != JpegMarkerCode::StartOfScan {
             return Err(JpeglsError::InvalidData);
@@ -264,10 +456,15 @@ impl<'a> JpegStreamReader<'a> {
 
         let components_in_scan = self.read_u8()? as i32;
         consumed += 1;
+        self.scan_component_indices.clear();
         for _ in 0..components_in_scan {
-            let _id = self.read_u8()?;
-            let _mapping = self.read_u8()?;
+            let id = self.read_u8()?;
+            let mapping_table_id = self.read_u8()?;
             consumed += 2;
+            if let Some(index) = self.components.iter().position(|c| c.id == id) {
+                self.components[index].mapping_table_id = mapping_table_id;
+                self.scan_component_indices.push(index);
+            }
         }
         self.parameters.near_lossless = self.read_u8()? as i32;
         self.parameters.interleave_mode = InterleaveMode::try_from(self.read_u8()?)?;
@@ -324,6 +521,68 @@ impl<'a> JpegStreamReader<'a> {
         Ok(())
     }
 
+    /// Reads an APP8 "mrfx" segment into the coding parameters. Returns false, without
+    /// consuming anything, when the APP8 segment holds other data.
+    fn read_color_transform_segment(&mut self) -> Result<bool, JpeglsError> {
+        let segment = &self.source[self.position.min(self.source.len())..];
+        if segment.len() < 2 + 4 + 1
+            || u16::from_be_bytes([segment[0], segment[1]]) != 2 + 4 + 1
+            || !self.segment_starts_with(&COLOR_TRANSFORMATION_IDENTIFIER)
+        {
+            return Ok(false);
+        }
+
+        self.parameters.transformation = ColorTransformation::try_from(segment[6])
+            .map_err(|_| JpeglsError::InvalidParameterColorTransformation)?;
+        self.position += 2 + 4 + 1;
+        Ok(true)
+    }
+
+    /// Returns true when the data of the segment whose length field is at the current
+    /// position starts with `identifier`.
+    fn segment_starts_with(&self, identifier: &[u8]) -> bool {
+        let data = self.source.get(self.position + 2..).unwrap_or_default();
+        data.starts_with(identifier)
+    }
+
+    /// Passes COM and APPn segments to the registered handlers and skips all other segments.
+    /// The marker must already have been read.
+    pub fn skip_or_report_segment(&mut self, marker: JpegMarkerCode) -> Result<(), JpeglsError> {
+        let marker_byte = marker as u8;
+        let is_comment = marker == JpegMarkerCode::Comment;
+        let is_application_data = (JpegMarkerCode::ApplicationData0 as u8
+            ..=JpegMarkerCode::ApplicationData15 as u8)
+            .contains(&marker_byte);
+        if !is_comment && !is_application_data {
+            return self.skip_segment();
+        }
+
+        let length = self.read_u16()? as usize;
+        if length < 2 || self.position + length - 2 > self.source.len() {
+            return Err(JpeglsError::InvalidMarkerSegmentSize);
+        }
+        let data = &self.source[self.position..self.position + length - 2];
+        self.position += length - 2;
+
+        if marker == JpegMarkerCode::ApplicationData0 && data.starts_with(b"JFIF\0") {
+            self.jfif = true;
+        } else if marker == JpegMarkerCode::ApplicationData14
+            && data.len() >= 12
+            && data.starts_with(b"Adobe")
+        {
+            self.adobe_transform = Some(data[11]);
+        }
+
+        if is_comment {
+            if let Some(handler) = self.comment_handler.as_mut() {
+                handler(data)?;
+            }
+        } else if let Some(handler) = self.application_data_handler.as_mut() {
+            handler(marker_byte - JpegMarkerCode::ApplicationData0 as u8, data)?;
+        }
+        Ok(())
+    }
+
     fn read_spiff_header_segment(&mut self) -> Result<Option<SpiffHeader>, JpeglsError> {
         let length = self.read_u16()? as usize;
         if length < 32 {
@@ -371,6 +630,63 @@ impl<'a> JpegStreamReader<'a> {
     // Deprecated? No, used in other methods I didn't verify fully?
     // I replaced read_u32_internal usage with read_u32 above.
 
+    /// True when the frame header declared a height of 0 and the height was taken from
+    /// the DNL segment after the first scan.
+    pub fn height_from_define_number_of_lines(&self) -> bool {
+        self.height_from_define_number_of_lines
+    }
+
+    /// Looks ahead, past the SOS segment at the current position and the entropy-coded
+    /// data of the first scan, for the DNL segment that defines the height of a frame
+    /// whose header declared 0 lines. Scan data never holds 0xFF followed by the DNL
+    /// code: JPEG-LS stuffs a zero bit after 0xFF and JPEG 1 a zero byte.
+    fn find_define_number_of_lines(&mut self) -> Result<(), JpeglsError> {
+        let scan_header_length = self
+            .source
+            .get(self.position + 2..self.position + 4)
+            .map(|length| u16::from_be_bytes([length[0], length[1]]) as usize)
+            .ok_or(JpeglsError::InvalidMarkerSegmentSize)?;
+        let scan_data = self
+            .source
+            .get(self.position + 2 + scan_header_length..)
+            .unwrap_or_default();
+        let offset = scan_data
+            .windows(2)
+            .position(|pair| {
+                pair == [
+                    JPEG_MARKER_START_BYTE,
+                    JpegMarkerCode::DefineNumberOfLines as u8,
+                ]
+            })
+            .ok_or(JpeglsError::DefineNumberOfLinesMarkerNotFound)?;
+        let segment = &scan_data[offset + 2..];
+        if segment.len() < 4 || u16::from_be_bytes([segment[0], segment[1]]) != 4 {
+            return Err(JpeglsError::InvalidMarkerSegmentSize);
+        }
+        let height = u16::from_be_bytes([segment[2], segment[3]]);
+        if height == 0 {
+            return Err(JpeglsError::InvalidParameterHeight);
+        }
+        self.frame_info.height = height as u32;
+        self.height_from_define_number_of_lines = true;
+        Ok(())
+    }
+
+    /// Reads the DNL segment that follows the first scan of a frame whose height was
+    /// taken from it. The marker must already have been read.
+    pub fn read_define_number_of_lines_segment(&mut self) -> Result<(), JpeglsError> {
+        if !self.height_from_define_number_of_lines {
+            return Err(JpeglsError::UnexpectedDefineNumberOfLinesMarker);
+        }
+        if self.read_u16()? != 4 {
+            return Err(JpeglsError::InvalidMarkerSegmentSize);
+        }
+        if self.read_u16()? as u32 != self.frame_info.height {
+            return Err(JpeglsError::InvalidParameterHeight);
+        }
+        Ok(())
+    }
+
     pub fn skip_segment(&mut self) -> Result<(), JpeglsError> {
         let length = self.read_u16()?;
         if length < 2 {
@@ -384,10 +700,20 @@ impl<'a> JpegStreamReader<'a> {
         self.position += count;
     }
 
-    // Helper to align to the next byte boundary.
-    // Since this reader operates on a byte slice, it is always byte-aligned.
-    // This is a no-op for now but kept for API compatibility with bit-stream readers.
+    /// Moves to `position` (clamped to the end of the data) and drops any partially read
+    /// byte, e.g. to jump to a tile-part or packet whose offset is known.
+    pub fn seek(&mut self, position: usize) {
+        self.position = position.min(self.source.len());
+        self.bits_left = 0;
+        self.bit_buffer = 0;
+    }
+
+    /// Drops the rest of the byte being read by `read_bit`. When that byte is 0xFF the
+    /// byte after it, which holds only its stuffed bit and padding, is skipped too.
     pub fn align_to_byte(&mut self) {
+        if self.bit_buffer == 0xFF && self.position < self.source.len() {
+            self.position += 1;
+        }
         self.bits_left = 0;
         self.bit_buffer = 0;
     }
@@ -400,16 +726,10 @@ impl<'a> JpegStreamReader<'a> {
             let b = self.source[self.position];
             self.position += 1;
 
-            // Byte stuffing handling for J2K Packet Headers
-            if b == 0xFF && self.position < self.source.len() {
-                let next = self.source[self.position];
-                if next == 0x00 {
-                    self.position += 1; // Skip stuffing
-                }
-            }
-
+            // Bit stuffing in J2K packet headers (B.10.1): the MSB of a byte after
+            // 0xFF is a stuffed 0.
+            self.bits_left = if self.bit_buffer == 0xFF { 7 } else { 8 };
             self.bit_buffer = b;
-            self.bits_left = 8;
         }
 
         let shift = self.bits_left - 1;
@@ -441,6 +761,7 @@ impl<'a> JpegStreamReader<'a> {
                 dc_table_dest: 0,
                 ac_table_dest: 0,
                 dc_pred: 0,
+                mapping_table_id: 0,
             });
         }
         Ok(())
@@ -525,3 +846,30 @@ impl<'a> JpegStreamReader<'a> {
         Ok(())
     }
 }
+
+/// Subsampling of a component with sampling factor `factor` when the largest one is
+/// `max_factor`; 1 for factors that do not divide it (or are 0).
+fn subsampling(max_factor: Option<u8>, factor: u8) -> u8 {
+    match max_factor {
+        Some(max_factor) if factor != 0 && max_factor % factor == 0 => max_factor / factor,
+        _ => 1,
+    }
+}
+
+/// JPEG-LS streams also start with SOI; they are told apart from JPEG 1 by a SOF55 or LSE
+/// marker ahead of the first scan.
+pub fn is_jpegls(data: &[u8]) -> bool {
+    let mut i = 0;
+    while i + 1 < data.len() {
+        if data[i] == 0xFF {
+            match data[i + 1] {
+                0xF7 | 0xF8 => return true,
+                0xDA => break,
+                _ => i += 2,
+            }
+        } else {
+            i += 1;
+        }
+    }
+    false
+}
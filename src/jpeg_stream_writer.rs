@@ -1,20 +1,51 @@
 //! JPEG Codestream Writer utilities.
 //!
 //! This module provides the `JpegStreamWriter` which handles the generation
-//! of JPEG markers and segments (SOI, EOI, SOF, SOD, etc.) for various standards.
+//! of JPEG markers and segments (SOI, EOI, SOF, SOD, etc.) for various standards, and the
+//! [`Sink`] trait through which all encoders write their output.
 
 use crate::FrameInfo;
+use crate::constants::{
+    MAXIMUM_APPLICATION_DATA_ID, SEGMENT_MAX_DATA_SIZE, SPIFF_END_OF_DIRECTORY_ENTRY_TYPE,
+    SPIFF_MAJOR_REVISION_NUMBER, SPIFF_MINOR_REVISION_NUMBER,
+};
 use crate::error::JpeglsError;
 use crate::jpeg_marker_code::{JPEG_MARKER_START_BYTE, JpegMarkerCode};
-use crate::jpegls::{InterleaveMode, JpeglsPcParameters};
+use crate::jpegls::color_transform::COLOR_TRANSFORMATION_IDENTIFIER;
+use crate::jpegls::{ColorTransformation, InterleaveMode, JpeglsPcParameters, SpiffHeader};
+use std::io::{self, Write};
+
+/// Destination of an encoder's output.
+///
+/// Implemented by [`SliceSink`] for a preallocated buffer, by `Vec<u8>`, which grows as
+/// the stream is written, and by [`WriteSink`] for any [`io::Write`], so an encoder can
+/// stream to a file or socket without knowing the size of the stream in advance.
+pub trait Sink {
+    /// Appends `bytes`, or fails without writing any of them.
+    fn write(&mut self, bytes: &[u8]) -> Result<(), JpeglsError>;
+}
 
-/// A writer for JPEG/JLS codestreams that manages marker emission and byte stuffing.
-pub struct JpegStreamWriter<'a> {
+impl<S: Sink + ?Sized> Sink for &mut S {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), JpeglsError> {
+        (**self).write(bytes)
+    }
+}
+
+impl Sink for Vec<u8> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), JpeglsError> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// A sink that fills a fixed buffer and fails with
+/// [`DestinationTooSmall`](JpeglsError::DestinationTooSmall) when it is full.
+pub struct SliceSink<'a> {
     destination: &'a mut [u8],
     position: usize,
 }
 
-impl<'a> JpegStreamWriter<'a> {
+impl<'a> SliceSink<'a> {
     pub fn new(destination: &'a mut [u8]) -> Self {
         Self {
             destination,
@@ -22,6 +53,7 @@ impl<'a> JpegStreamWriter<'a> {
         }
     }
 
+    /// Number of bytes written.
     pub fn len(&self) -> usize {
         self.position
     }
@@ -30,38 +62,138 @@ impl<'a> JpegStreamWriter<'a> {
         self.position == 0
     }
 
-    pub fn write_byte(&mut self, value: u8) -> Result<(), JpeglsError> {
+    /// Size of the buffer.
+    pub fn capacity(&self) -> usize {
+        self.destination.len()
+    }
+
+    /// The part of the buffer after the bytes written.
+    pub fn remaining_slice(&mut self) -> &mut [u8] {
         if self.position >= self.destination.len() {
-            // #region agent log
-            {
-                use std::fs::OpenOptions;
-                use std::io::Write;
-                if let Ok(mut f) = OpenOptions::new().create(true).append(true).open(r"c:\Users\aroja\CODE\jpegexp-rs\.cursor\debug.log") {
-                    let _ = writeln!(f, r#"{{"sessionId":"debug-session","runId":"run1","hypothesisId":"B","location":"jpeg_stream_writer.rs:34","message":"buffer overflow in write_byte","data":{{"position":{},"destination_len":{},"value":{}}},"timestamp":{}}}"#, self.position, self.destination.len(), value, std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_millis());
-                }
-            }
-            // #endregion
-            return Err(JpeglsError::ParameterValueNotSupported); // Use appropriate error (BufferTooSmall)
+            &mut []
+        } else {
+            &mut self.destination[self.position..]
         }
-        self.destination[self.position] = value;
-        self.position += 1;
+    }
+
+    /// Counts `count` bytes written directly into [`remaining_slice`](Self::remaining_slice).
+    pub fn advance(&mut self, count: usize) {
+        self.position += count;
+    }
+}
+
+impl Sink for SliceSink<'_> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), JpeglsError> {
+        let end = self.position + bytes.len();
+        let destination = self
+            .destination
+            .get_mut(self.position..end)
+            .ok_or(JpeglsError::DestinationTooSmall { needed: end })?;
+        destination.copy_from_slice(bytes);
+        self.position = end;
         Ok(())
     }
+}
+
+/// A sink that passes the stream on to an [`io::Write`].
+///
+/// The encoders write a few bytes at a time, so `inner` should be buffered, e.g. a
+/// [`BufWriter`](std::io::BufWriter) around a file. A failed write is reported as
+/// [`CallbackFailed`](JpeglsError::CallbackFailed); [`take_error`](Self::take_error)
+/// returns the I/O error itself.
+pub struct WriteSink<W: Write> {
+    inner: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> WriteSink<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, error: None }
+    }
 
-    pub fn write_u16(&mut self, value: u16) -> Result<(), JpeglsError> {
-        let bytes = value.to_be_bytes();
-        self.write_byte(bytes[0])?;
-        self.write_byte(bytes[1])?;
+    /// The I/O error that made the last write fail, if any.
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Sink for WriteSink<W> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), JpeglsError> {
+        self.inner.write_all(bytes).map_err(|e| {
+            self.error = Some(e);
+            JpeglsError::CallbackFailed
+        })
+    }
+}
+
+/// A writer for JPEG/JLS codestreams that manages marker emission and byte stuffing.
+pub struct JpegStreamWriter<S: Sink> {
+    sink: S,
+    position: usize,
+}
+
+impl<'a> JpegStreamWriter<SliceSink<'a>> {
+    pub fn new(destination: &'a mut [u8]) -> Self {
+        Self::with_sink(SliceSink::new(destination))
+    }
+
+    /// Size of the destination.
+    pub fn capacity(&self) -> usize {
+        self.sink.capacity()
+    }
+
+    pub fn remaining_slice(&mut self) -> &mut [u8] {
+        self.sink.remaining_slice()
+    }
+
+    pub fn advance(&mut self, count: usize) {
+        self.sink.advance(count);
+        self.position += count;
+    }
+}
+
+impl<S: Sink> JpegStreamWriter<S> {
+    pub fn with_sink(sink: S) -> Self {
+        Self { sink, position: 0 }
+    }
+
+    /// Returns the sink, e.g. to take back a `Vec<u8>` that holds the stream.
+    pub fn into_sink(self) -> S {
+        self.sink
+    }
+
+    pub fn len(&self) -> usize {
+        self.position
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position == 0
+    }
+
+    pub fn write_byte(&mut self, value: u8) -> Result<(), JpeglsError> {
+        self.write_bytes(&[value])
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), JpeglsError> {
+        self.sink.write(bytes)?;
+        self.position += bytes.len();
         Ok(())
     }
 
+    pub fn write_u16(&mut self, value: u16) -> Result<(), JpeglsError> {
+        self.write_bytes(&value.to_be_bytes())
+    }
+
     pub fn write_u32(&mut self, value: u32) -> Result<(), JpeglsError> {
-        let bytes = value.to_be_bytes();
-        self.write_byte(bytes[0])?;
-        self.write_byte(bytes[1])?;
-        self.write_byte(bytes[2])?;
-        self.write_byte(bytes[3])?;
-        Ok(())
+        self.write_bytes(&value.to_be_bytes())
     }
 
     pub fn write_marker(&mut self, marker: JpegMarkerCode) -> Result<(), JpeglsError> {
@@ -109,6 +241,18 @@ impl<'a> JpegStreamWriter<'a> {
         Ok(())
     }
 
+    /// Writes a DQT segment with 16-bit entries (Pq = 1), which T.81 allows for 12-bit
+    /// samples only.
+    pub fn write_dqt_16bit(&mut self, table_id: u8, table: &[u16; 64]) -> Result<(), JpeglsError> {
+        self.write_marker(JpegMarkerCode::DefineQuantizationTable)?;
+        self.write_u16(2 + 1 + 128)?;
+        self.write_byte(0x10 | (table_id & 0x0F))?; // Precision 1 (16-bit), ID
+        for &val in table {
+            self.write_u16(val)?;
+        }
+        Ok(())
+    }
+
     pub fn write_dht(
         &mut self,
         table_class: u8,
@@ -130,7 +274,21 @@ impl<'a> JpegStreamWriter<'a> {
     }
 
     pub fn write_sof0_segment(&mut self, frame_info: &FrameInfo) -> Result<(), JpeglsError> {
-        self.write_marker(JpegMarkerCode::StartOfFrameBaseline)?;
+        self.write_dct_frame_segment(JpegMarkerCode::StartOfFrameBaseline, frame_info)
+    }
+
+    /// Writes SOF1 (extended sequential DCT), the frame header of 12-bit samples, with the
+    /// components laid out like [`write_sof0_segment`](Self::write_sof0_segment).
+    pub fn write_sof1_segment(&mut self, frame_info: &FrameInfo) -> Result<(), JpeglsError> {
+        self.write_dct_frame_segment(JpegMarkerCode::StartOfFrameExtendedSequential, frame_info)
+    }
+
+    fn write_dct_frame_segment(
+        &mut self,
+        marker: JpegMarkerCode,
+        frame_info: &FrameInfo,
+    ) -> Result<(), JpeglsError> {
+        self.write_marker(marker)?;
         let length = 2 + 1 + 2 + 2 + 1 + (frame_info.component_count as usize * 3);
         self.write_u16(length as u16)?;
         self.write_byte(frame_info.bits_per_sample as u8)?;
@@ -165,6 +323,14 @@ impl<'a> JpegStreamWriter<'a> {
         Ok(())
     }
 
+    /// Writes a DNL segment, which defines the height of a frame whose header declared 0
+    /// lines. It follows the first scan of the frame.
+    pub fn write_define_number_of_lines_segment(&mut self, height: u16) -> Result<(), JpeglsError> {
+        self.write_marker(JpegMarkerCode::DefineNumberOfLines)?;
+        self.write_u16(4)?;
+        self.write_u16(height)
+    }
+
     pub fn write_dri(&mut self, restart_interval: u16) -> Result<(), JpeglsError> {
         self.write_marker(JpegMarkerCode::DefineRestartInterval)?;
         self.write_u16(4)?; // Length
@@ -172,20 +338,22 @@ impl<'a> JpegStreamWriter<'a> {
         Ok(())
     }
 
+    /// Writes a JPEG-LS SOS segment for components 1 to `mapping_table_ids.len()`, with the
+    /// mapping table selector (0 for none) of each.
     pub fn write_start_of_scan_segment(
         &mut self,
-        component_count: i32,
+        mapping_table_ids: &[u8],
         near_lossless: i32,
         interleave_mode: InterleaveMode,
     ) -> Result<(), JpeglsError> {
         self.write_marker(JpegMarkerCode::StartOfScan)?;
-        let length = 2 + 1 + (component_count as usize * 2) + 3;
+        let length = 2 + 1 + (mapping_table_ids.len() * 2) + 3;
         self.write_u16(length as u16)?;
 
-        self.write_byte(component_count as u8)?;
-        for i in 0..component_count {
+        self.write_byte(mapping_table_ids.len() as u8)?;
+        for (i, &mapping_table_id) in mapping_table_ids.iter().enumerate() {
             self.write_byte((i + 1) as u8)?; // Component Selector (assuming 1-based sequential)
-            self.write_byte(0)?; // Mapping table selector
+            self.write_byte(mapping_table_id)?;
         }
 
         self.write_byte(near_lossless as u8)?;
@@ -198,6 +366,7 @@ impl<'a> JpegStreamWriter<'a> {
     pub fn write_start_of_scan_segment_planar(
         &mut self,
         component_id: u8,
+        mapping_table_id: u8,
         near_lossless: i32,
         interleave_mode: InterleaveMode,
     ) -> Result<(), JpeglsError> {
@@ -207,7 +376,7 @@ impl<'a> JpegStreamWriter<'a> {
 
         self.write_byte(1)?; // 1 component
         self.write_byte(component_id)?; // Component Selector
-        self.write_byte(0)?; // Mapping table selector
+        self.write_byte(mapping_table_id)?; // Mapping table selector
 
         self.write_byte(near_lossless as u8)?;
         self.write_byte(interleave_mode as u8)?;
@@ -216,6 +385,80 @@ impl<'a> JpegStreamWriter<'a> {
         Ok(())
     }
 
+    /// Writes a COM segment holding `comment`, which may be text or binary data.
+    pub fn write_comment(&mut self, comment: &[u8]) -> Result<(), JpeglsError> {
+        self.write_segment(JpegMarkerCode::Comment, comment)
+    }
+
+    /// Writes an APPn segment; `id` selects the marker (0 for APP0 up to 15 for APP15).
+    pub fn write_application_data(&mut self, id: u8, data: &[u8]) -> Result<(), JpeglsError> {
+        if id as i32 > MAXIMUM_APPLICATION_DATA_ID {
+            return Err(JpeglsError::InvalidArgument);
+        }
+        let marker = JpegMarkerCode::try_from(JpegMarkerCode::ApplicationData0 as u8 + id)?;
+        self.write_segment(marker, data)
+    }
+
+    fn write_segment(&mut self, marker: JpegMarkerCode, data: &[u8]) -> Result<(), JpeglsError> {
+        if data.len() > SEGMENT_MAX_DATA_SIZE {
+            return Err(JpeglsError::InvalidArgumentSize);
+        }
+        self.write_marker(marker)?;
+        self.write_u16(2 + data.len() as u16)?;
+        for &byte in data {
+            self.write_byte(byte)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the APP8 segment that holds a SPIFF header (ISO/IEC 10918-3, F.2.1).
+    pub fn write_spiff_header_segment(&mut self, header: &SpiffHeader) -> Result<(), JpeglsError> {
+        self.write_marker(JpegMarkerCode::ApplicationData8)?;
+        self.write_u16(2 + 30)?;
+        for &byte in b"SPIFF\0" {
+            self.write_byte(byte)?;
+        }
+        self.write_byte(SPIFF_MAJOR_REVISION_NUMBER)?;
+        self.write_byte(SPIFF_MINOR_REVISION_NUMBER)?;
+        self.write_byte(header.profile_id as u8)?;
+        self.write_byte(header.component_count as u8)?;
+        self.write_u32(header.height)?;
+        self.write_u32(header.width)?;
+        self.write_byte(header.color_space as u8)?;
+        self.write_byte(header.bits_per_sample as u8)?;
+        self.write_byte(header.compression_type as u8)?;
+        self.write_byte(header.resolution_units as u8)?;
+        self.write_u32(header.vertical_resolution)?;
+        self.write_u32(header.horizontal_resolution)?;
+        Ok(())
+    }
+
+    /// Writes the SPIFF end-of-directory entry (ISO/IEC 10918-3, F.2.2.3).
+    ///
+    /// The entry is declared with a length of 8 but only holds 6 bytes: the EOD tag followed
+    /// by the SOI marker of the image that the SPIFF header wraps. Like CharLS, that SOI is
+    /// written as part of the entry, so no separate SOI follows it.
+    pub fn write_spiff_end_of_directory_entry(&mut self) -> Result<(), JpeglsError> {
+        self.write_marker(JpegMarkerCode::ApplicationData8)?;
+        self.write_u16(2 + 6)?;
+        self.write_u32(SPIFF_END_OF_DIRECTORY_ENTRY_TYPE as u32)?;
+        self.write_marker(JpegMarkerCode::StartOfImage)
+    }
+
+    /// Writes the APP8 "mrfx" segment that signals an HP color transformation.
+    pub fn write_color_transform_segment(
+        &mut self,
+        transformation: ColorTransformation,
+    ) -> Result<(), JpeglsError> {
+        self.write_marker(JpegMarkerCode::ApplicationData8)?;
+        self.write_u16(2 + 4 + 1)?;
+        for &byte in &COLOR_TRANSFORMATION_IDENTIFIER {
+            self.write_byte(byte)?;
+        }
+        self.write_byte(transformation as u8)?;
+        Ok(())
+    }
+
     pub fn write_jpegls_preset_parameters_segment(
         &mut self,
         pc: &JpeglsPcParameters,
@@ -234,15 +477,34 @@ impl<'a> JpegStreamWriter<'a> {
         Ok(())
     }
 
-    pub fn remaining_slice(&mut self) -> &mut [u8] {
-        if self.position >= self.destination.len() {
-            &mut []
-        } else {
-            &mut self.destination[self.position..]
+    /// Writes a mapping table as an LSE segment of type 2, followed by segments of type 3
+    /// (continuation) when it does not fit in one. Every segment holds whole entries.
+    pub fn write_mapping_table_segments(
+        &mut self,
+        table_id: u8,
+        entry_size: u8,
+        table_data: &[u8],
+    ) -> Result<(), JpeglsError> {
+        // The type, table ID and entry size take 3 bytes of every segment.
+        let entries_per_segment = (SEGMENT_MAX_DATA_SIZE - 3) / entry_size as usize;
+        let chunk_size = entries_per_segment * entry_size as usize;
+        for (i, chunk) in table_data.chunks(chunk_size).enumerate() {
+            self.write_marker(JpegMarkerCode::JpeglsPresetParameters)?;
+            self.write_u16((2 + 3 + chunk.len()) as u16)?;
+            self.write_byte(if i == 0 { 2 } else { 3 })?;
+            self.write_byte(table_id)?;
+            self.write_byte(entry_size)?;
+            for &byte in chunk {
+                self.write_byte(byte)?;
+            }
         }
+        Ok(())
     }
+}
 
-    pub fn advance(&mut self, count: usize) {
-        self.position += count;
+/// Entropy coders write through the stream writer, which counts their bytes.
+impl<S: Sink> Sink for JpegStreamWriter<S> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), JpeglsError> {
+        self.write_bytes(bytes)
     }
 }
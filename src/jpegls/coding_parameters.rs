@@ -232,6 +232,11 @@ pub fn is_valid(
     Ok(validated_parameters)
 }
 
+/// Number of bits needed to hold samples up to `maximum_sample_value` (bpp in ISO/IEC 14495-1, A.2).
+pub fn compute_bits_per_sample(maximum_sample_value: i32) -> i32 {
+    max(2, 32 - (maximum_sample_value as u32).leading_zeros() as i32)
+}
+
 pub fn compute_limit_parameter(
     bits_per_sample: i32,
     _near_lossless: i32,
@@ -0,0 +1,185 @@
+//! HP color transformations for 3-component JPEG-LS images.
+//!
+//! The HP1, HP2 and HP3 transforms are not part of ISO/IEC 14495-1; they come from the HP
+//! LOCO-I implementation and are signalled with an APP8 segment that carries the
+//! identifier "mrfx" followed by the transformation id. They decorrelate RGB by coding red
+//! and blue relative to green. All arithmetic wraps modulo 2^bits of the sample type, which
+//! is why only 8 and 16-bit samples can be transformed.
+
+use crate::error::JpeglsError;
+use crate::jpegls::sample_layout::SampleLayout;
+use crate::jpegls::traits::JpeglsSample;
+use crate::jpegls::ColorTransformation;
+use crate::FrameInfo;
+
+/// Identifier at the start of the APP8 segment that carries the color transformation.
+pub const COLOR_TRANSFORMATION_IDENTIFIER: [u8; 4] = *b"mrfx";
+
+/// Returns true when `transformation` can be applied to images described by `frame_info`.
+pub fn is_supported(transformation: ColorTransformation, frame_info: &FrameInfo) -> bool {
+    transformation == ColorTransformation::None
+        || (frame_info.component_count == 3
+            && (frame_info.bits_per_sample == 8 || frame_info.bits_per_sample == 16))
+}
+
+/// Replaces every RGB pixel of a pixel-interleaved buffer with the values that are coded.
+pub(crate) fn forward<T: JpeglsSample>(transformation: ColorTransformation, pixels: &mut [T]) {
+    let range = 1i32 << T::BITS;
+    let mask = range - 1;
+    let half = range / 2;
+    for pixel in pixels.chunks_exact_mut(3) {
+        let [red, green, blue] = [pixel[0].to_i32(), pixel[1].to_i32(), pixel[2].to_i32()];
+        let transformed = match transformation {
+            ColorTransformation::None => return,
+            ColorTransformation::Hp1 => [red - green + half, green, blue - green + half],
+            ColorTransformation::Hp2 => [
+                red - green + half,
+                green,
+                blue - ((red + green) >> 1) + half,
+            ],
+            ColorTransformation::Hp3 => {
+                let v2 = (blue - green + half) & mask;
+                let v3 = (red - green + half) & mask;
+                [green + ((v2 + v3) >> 2) - range / 4, v2, v3]
+            }
+        };
+        for (sample, value) in pixel.iter_mut().zip(transformed) {
+            *sample = T::from_i32(value & mask);
+        }
+    }
+}
+
+/// Returns the RGB values of one transformed pixel.
+fn restore<T: JpeglsSample>(
+    transformation: ColorTransformation,
+    [v1, v2, v3]: [i32; 3],
+) -> [i32; 3] {
+    let range = 1i32 << T::BITS;
+    let mask = range - 1;
+    let half = range / 2;
+    let restored = match transformation {
+        ColorTransformation::None => [v1, v2, v3],
+        ColorTransformation::Hp1 => [v1 + v2 - half, v2, v3 + v2 - half],
+        ColorTransformation::Hp2 => {
+            let red = (v1 + v2 - half) & mask;
+            [red, v2, v3 + ((red + v2) >> 1) - half]
+        }
+        ColorTransformation::Hp3 => {
+            let green = (v1 - ((v2 + v3) >> 2) + range / 4) & mask;
+            [v3 + green - half, green, v2 + green - half]
+        }
+    };
+    restored.map(|value| value & mask)
+}
+
+/// Applies the inverse transformation to a decoded image of 8 or 16-bit samples arranged as
+/// described by `layout`.
+pub(crate) fn inverse_bytes(
+    transformation: ColorTransformation,
+    frame_info: &FrameInfo,
+    destination: &mut [u8],
+    layout: SampleLayout,
+) -> Result<(), JpeglsError> {
+    if frame_info.bits_per_sample <= 8 {
+        inverse_samples(transformation, frame_info, destination, layout)
+    } else {
+        let (head, body, _) = unsafe { destination.align_to_mut::<u16>() };
+        if !head.is_empty() {
+            return Err(JpeglsError::InvalidData);
+        }
+        inverse_samples(transformation, frame_info, body, layout)
+    }
+}
+
+fn inverse_samples<T: JpeglsSample>(
+    transformation: ColorTransformation,
+    frame_info: &FrameInfo,
+    samples: &mut [T],
+    layout: SampleLayout,
+) -> Result<(), JpeglsError> {
+    let (width, height) = (frame_info.width as usize, frame_info.height as usize);
+    let needed = layout.required_len(width, height, 3);
+    if samples.len() < needed {
+        return Err(JpeglsError::DestinationTooSmall { needed });
+    }
+    for y in 0..height {
+        for x in 0..width {
+            let indices = [0, 1, 2].map(|c| layout.index(x, y, c));
+            let restored = restore::<T>(transformation, indices.map(|i| samples[i].to_i32()));
+            for (index, value) in indices.into_iter().zip(restored) {
+                samples[index] = T::from_i32(value);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inverse<T: JpeglsSample>(transformation: ColorTransformation, pixels: &mut [T]) {
+        let width = pixels.len() / 3;
+        let frame_info = FrameInfo {
+            width: width as u32,
+            height: 1,
+            bits_per_sample: T::BITS as i32,
+            component_count: 3,
+        };
+        let layout = SampleLayout::interleaved(3, width * 3);
+        inverse_samples(transformation, &frame_info, pixels, layout).unwrap();
+    }
+
+    const TRANSFORMATIONS: [ColorTransformation; 3] = [
+        ColorTransformation::Hp1,
+        ColorTransformation::Hp2,
+        ColorTransformation::Hp3,
+    ];
+
+    #[test]
+    fn inverse_restores_every_8_bit_pixel() {
+        let mut pixels = Vec::new();
+        for red in (0..=255u8).step_by(5) {
+            for green in (0..=255u8).step_by(3) {
+                for blue in [0u8, 1, 127, 128, 200, 255] {
+                    pixels.extend_from_slice(&[red, green, blue]);
+                }
+            }
+        }
+
+        for transformation in TRANSFORMATIONS {
+            let mut transformed = pixels.clone();
+            forward(transformation, &mut transformed);
+            assert_ne!(transformed, pixels);
+            inverse(transformation, &mut transformed);
+            assert_eq!(transformed, pixels, "{:?}", transformation);
+        }
+    }
+
+    #[test]
+    fn inverse_restores_16_bit_pixels() {
+        let values = [0u16, 1, 255, 4095, 32767, 32768, 65534, 65535];
+        let mut pixels = Vec::new();
+        for &red in &values {
+            for &green in &values {
+                for &blue in &values {
+                    pixels.extend_from_slice(&[red, green, blue]);
+                }
+            }
+        }
+
+        for transformation in TRANSFORMATIONS {
+            let mut transformed = pixels.clone();
+            forward(transformation, &mut transformed);
+            inverse(transformation, &mut transformed);
+            assert_eq!(transformed, pixels, "{:?}", transformation);
+        }
+    }
+
+    #[test]
+    fn hp1_codes_gray_as_constant_chroma() {
+        let mut pixels = [90u8, 90, 90, 10, 10, 10];
+        forward(ColorTransformation::Hp1, &mut pixels);
+        assert_eq!(pixels, [128, 90, 128, 128, 10, 128]);
+    }
+}
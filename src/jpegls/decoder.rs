@@ -1,31 +1,199 @@
-use crate::error::JpeglsError;
-use crate::jpeg_stream_reader::JpegStreamReader;
+use crate::constants::AUTO_CALCULATE_STRIDE;
+use crate::error::{JpeglsError, Truncation};
+use crate::jpeg_marker_code::JpegMarkerCode;
+use crate::jpeg_stream_reader::{JpegStreamReader, JpegStreamReaderState};
 use crate::jpegls::validate_spiff_header::validate_spiff_header;
-use crate::FrameInfo;
-use crate::jpegls::SpiffHeader;
+use crate::{ComponentInfo, FrameInfo, OutputLayout};
+use crate::jpegls::traits::CodingTraits;
+use crate::jpegls::color_transform;
+use crate::jpegls::sample_layout::SampleLayout;
+use crate::jpegls::scan_decoder::ScanDecoder;
+use crate::jpegls::{
+    CodingParameters, ColorTransformation, CompressedDataFormat, JpeglsPcParameters, MappingTable,
+    SpiffHeader,
+};
+use crate::mem_profiling::{DecodeStats, Session};
 
 pub struct JpeglsDecoder<'a> {
     reader: JpegStreamReader<'a>,
     spiff_header: Option<SpiffHeader>,
+    output_layout: OutputLayout,
+    /// Mapping tables of the abbreviated table specifications read beforehand.
+    preset_mapping_tables: Vec<MappingTable>,
+    compressed_data_format: CompressedDataFormat,
+    tolerant: bool,
+    truncation: Option<Truncation>,
+    stats: DecodeStats,
+}
+
+impl JpeglsDecoder<'static> {
+    /// Creates a decoder that owns `source`, e.g. to move it to another thread or into an
+    /// async task.
+    pub fn from_vec(source: Vec<u8>) -> Self {
+        Self::with_reader(JpegStreamReader::from_vec(source))
+    }
 }
 
 impl<'a> JpeglsDecoder<'a> {
     pub fn new(source: &'a [u8]) -> Self {
+        Self::with_reader(JpegStreamReader::new(source))
+    }
+
+    fn with_reader(reader: JpegStreamReader<'a>) -> Self {
         Self {
-            reader: JpegStreamReader::new(source),
+            reader,
             spiff_header: None,
+            output_layout: OutputLayout::Interleaved,
+            preset_mapping_tables: Vec::new(),
+            compressed_data_format: CompressedDataFormat::Unknown,
+            tolerant: false,
+            truncation: None,
+            stats: DecodeStats::default(),
         }
     }
 
+    /// Reads the stream up to the first scan, or to the end of an abbreviated table
+    /// specification, which has no frame to decode.
     pub fn read_header(&mut self) -> Result<(), JpeglsError> {
-        self.reader.read_header(&mut self.spiff_header)?;
+        self.reader
+            .read_header_or_table_specification(&mut self.spiff_header)?;
+        if self.reader.state() == JpegStreamReaderState::EndOfImage {
+            if self.spiff_header.is_some() {
+                return Err(JpeglsError::AbbreviatedFormatAndSpiffHeaderMismatch);
+            }
+            self.compressed_data_format = CompressedDataFormat::AbbreviatedTableSpecification;
+            return Ok(());
+        }
         if let Some(spiff) = &self.spiff_header {
             let frame_info = self.frame_info();
             validate_spiff_header(spiff, &frame_info)?;
         }
+
+        // SOS: marker, length and component count, then a component selector and a mapping
+        // table selector per component.
+        let scan = self.reader.remaining_data();
+        let component_count = scan.get(4).copied().unwrap_or(0) as usize;
+        let mapping_table_ids: Vec<u8> = scan
+            .get(5..5 + 2 * component_count)
+            .unwrap_or_default()
+            .chunks_exact(2)
+            .map(|selectors| selectors[1])
+            .collect();
+        self.compressed_data_format = CompressedDataFormat::Interchange;
+        self.check_mapping_table_ids(&mapping_table_ids);
+        Ok(())
+    }
+
+    /// Reads an abbreviated table specification (a stream of tables without a frame, see
+    /// [`JpeglsEncoder::create_tables_only`](crate::jpegls::JpeglsEncoder::create_tables_only))
+    /// whose mapping tables and preset coding parameters the image data relies on. Must be
+    /// called before [`read_header`](Self::read_header); tables and parameters that the
+    /// image stream defines itself take precedence.
+    pub fn read_table_specification(&mut self, source: &[u8]) -> Result<(), JpeglsError> {
+        if self.reader.state() != JpegStreamReaderState::BeforeStartOfImage {
+            return Err(JpeglsError::InvalidOperation);
+        }
+        let mut tables = JpeglsDecoder::new(source);
+        tables.read_header()?;
+        if tables.compressed_data_format != CompressedDataFormat::AbbreviatedTableSpecification {
+            return Err(JpeglsError::InvalidArgument);
+        }
+
+        for table in tables.reader.mapping_tables() {
+            self.preset_mapping_tables
+                .retain(|preset| preset.table_id != table.table_id);
+            self.preset_mapping_tables.push(table.clone());
+        }
+        self.reader
+            .set_preset_coding_parameters(tables.reader.preset_coding_parameters());
         Ok(())
     }
 
+    /// Format of the stream as far as it has been read: [`CompressedDataFormat::Unknown`]
+    /// before [`read_header`](Self::read_header), and
+    /// [`CompressedDataFormat::AbbreviatedImageData`] once a scan selects a mapping table
+    /// that the stream does not define.
+    pub fn compressed_data_format(&self) -> CompressedDataFormat {
+        self.compressed_data_format
+    }
+
+    /// Mapping table `table_id`, from the stream or else from an abbreviated table
+    /// specification read with [`read_table_specification`](Self::read_table_specification).
+    pub fn mapping_table(&self, table_id: u8) -> Option<&MappingTable> {
+        self.mapping_tables()
+            .find(|table| table.table_id == table_id)
+    }
+
+    /// The mapping tables read so far: those of the stream, followed by those of the table
+    /// specifications that the stream does not redefine.
+    pub fn mapping_tables(&self) -> impl Iterator<Item = &MappingTable> {
+        let stream_tables = self.reader.mapping_tables();
+        stream_tables.iter().chain(
+            self.preset_mapping_tables
+                .iter()
+                .filter(|preset| stream_tables.iter().all(|t| t.table_id != preset.table_id)),
+        )
+    }
+
+    /// Number of [`mapping_tables`](Self::mapping_tables), like CharLS'
+    /// `mapping_table_count`.
+    pub fn mapping_table_count(&self) -> usize {
+        self.mapping_tables().count()
+    }
+
+    /// Entries of mapping table `table_id`, `entry_size` bytes each.
+    pub fn mapping_table_data(&self, table_id: u8) -> Option<&[u8]> {
+        self.mapping_table(table_id)
+            .map(|table| table.data.as_slice())
+    }
+
+    /// Mapping table selected for the component at `component_index` by the scans decoded
+    /// so far (0 for none). The decoded samples of such a component are table indices.
+    pub fn mapping_table_id(&self, component_index: usize) -> Option<u8> {
+        self.reader
+            .components
+            .get(component_index)
+            .map(|component| component.mapping_table_id)
+    }
+
+    /// Marks the stream as abbreviated image data when a scan selects a table it does not
+    /// define.
+    fn check_mapping_table_ids(&mut self, mapping_table_ids: &[u8]) {
+        let tables = self.reader.mapping_tables();
+        if mapping_table_ids
+            .iter()
+            .any(|&id| id != 0 && !tables.iter().any(|table| table.table_id == id))
+        {
+            self.compressed_data_format = CompressedDataFormat::AbbreviatedImageData;
+        }
+    }
+
+    /// Arrangement of the components in the decoded image. Defaults to
+    /// [`OutputLayout::Interleaved`]; with [`OutputLayout::Planar`] every component is
+    /// written as a plane of `height` rows, each `stride` bytes apart.
+    pub fn set_output_layout(&mut self, output_layout: OutputLayout) {
+        self.output_layout = output_layout;
+    }
+
+    /// Registers a handler that receives the data of every COM segment found while reading
+    /// the header and decoding. Returning an error from the handler aborts with that error.
+    /// Handlers must be `Send + Sync` so that the decoder stays so too.
+    pub fn at_comment(
+        &mut self,
+        handler: impl FnMut(&[u8]) -> Result<(), JpeglsError> + Send + Sync + 'a,
+    ) {
+        self.reader.set_comment_handler(handler);
+    }
+
+    /// Registers a handler that receives the APPn number (0 to 15) and the data of every
+    /// application data segment.
+    pub fn at_application_data(
+        &mut self,
+        handler: impl FnMut(u8, &[u8]) -> Result<(), JpeglsError> + Send + Sync + 'a,
+    ) {
+        self.reader.set_application_data_handler(handler);
+    }
+
     pub fn read_spiff_header(&mut self) -> Result<bool, JpeglsError> {
         // Logic to just read spiff header if present
         // self.reader.try_read_spiff_header... ?
@@ -33,49 +201,431 @@ impl<'a> JpeglsDecoder<'a> {
         Ok(self.spiff_header.is_some())
     }
 
+    /// SPIFF header found by [`read_header`](Self::read_header), if the stream has one.
+    pub fn spiff_header(&self) -> Option<SpiffHeader> {
+        self.spiff_header
+    }
+
     pub fn frame_info(&self) -> FrameInfo {
         self.reader.frame_info()
     }
 
+    /// Precision and subsampling of every component, see
+    /// [`JpegStreamReader::component_info`].
+    pub fn component_info(&self) -> Vec<ComponentInfo> {
+        self.reader.component_info()
+    }
+
+    /// Decodes truncated and damaged streams as far as the data goes instead of failing:
+    /// the lines decoded before the error are kept, the rest of the image is filled with
+    /// mid-grey samples and [`truncation`](Self::truncation) reports where decoding stopped.
+    /// Errors in the frame header still fail the decode. Defaults to `false`.
+    pub fn set_tolerant(&mut self, tolerant: bool) {
+        self.tolerant = tolerant;
+    }
+
+    /// Where the most recent tolerant [`decode`](Self::decode) stopped, or `None` if the
+    /// whole image was read.
+    pub fn truncation(&self) -> Option<Truncation> {
+        self.truncation
+    }
+
+    /// Memory statistics of the most recent call to [`decode`](Self::decode).
+    pub fn stats(&self) -> DecodeStats {
+        self.stats
+    }
+
     pub fn decode(&mut self, destination: &mut [u8]) -> Result<(), JpeglsError> {
-        self.reader.read_start_of_scan_segment_jpegls()?;
+        self.decode_with_stride(destination, AUTO_CALCULATE_STRIDE)
+    }
+
+    /// Decodes the image into pixel-interleaved rows that are `stride` bytes apart.
+    ///
+    /// A stride of [`AUTO_CALCULATE_STRIDE`] means the rows are tightly packed. The padding
+    /// at the end of each row is left untouched, and the last row needs no padding.
+    pub fn decode_with_stride(
+        &mut self,
+        destination: &mut [u8],
+        stride: usize,
+    ) -> Result<(), JpeglsError> {
+        let session = Session::begin();
+        self.truncation = None;
+        let result = self.decode_frame(destination, stride);
         let frame_info = self.frame_info();
+        self.stats = DecodeStats {
+            peak_buffer_bytes: session.finish(),
+            pixel_count: frame_info.width as u64 * frame_info.height as u64,
+        };
+        result
+    }
 
-        let preset = self.reader.preset_coding_parameters();
-        let preset = if crate::jpegls::coding_parameters::is_default(&preset, &Default::default()) {
-            let max_val = (1 << frame_info.bits_per_sample) - 1;
-            crate::jpegls::coding_parameters::compute_default(
-                max_val,
-                self.reader.parameters().near_lossless,
-            )
-        } else {
-            preset
+    /// Decodes the image `rows` rows at a time, handing each batch of pixel-interleaved
+    /// rows to `handler` with the index of its first row as soon as its lines are decoded,
+    /// e.g. to send a large image on while the rest is still being decoded. An image whose
+    /// components are all coded in one scan is decoded into a buffer of one batch; one
+    /// coded a component per scan is decoded whole first, as its rows are only complete
+    /// after the last scan. The output layout is not used. An error returned by `handler`
+    /// stops decoding and is passed on; in tolerant mode the rows the data does not reach
+    /// are mid-grey, as with [`decode`](Self::decode).
+    pub fn decode_row_batches(
+        &mut self,
+        rows: u32,
+        mut handler: impl FnMut(u32, &[u8]) -> Result<(), JpeglsError>,
+    ) -> Result<(), JpeglsError> {
+        if self.compressed_data_format == CompressedDataFormat::AbbreviatedTableSpecification {
+            return Err(JpeglsError::InvalidOperation);
+        }
+        let frame_info = self.frame_info();
+        let size = frame_info.decoded_size(OutputLayout::Interleaved)?;
+        let height = frame_info.height as usize;
+        let row_bytes = size / height.max(1);
+        let rows = (rows.max(1) as usize).min(height.max(1));
+
+        // The segments before the first scan apply to it either way.
+        let mut at_scan = Ok(());
+        while at_scan.is_ok() && self.reader.peek_marker() != Ok(JpegMarkerCode::StartOfScan) {
+            at_scan = self.read_segment_between_scans();
+        }
+        // Ns, the number of components of the scan, follows the marker and Ls.
+        let scan_components = self.reader.remaining_data().get(4).copied();
+        if at_scan.is_err() || scan_components != Some(frame_info.component_count as u8) {
+            let output_layout =
+                std::mem::replace(&mut self.output_layout, OutputLayout::Interleaved);
+            let mut pixels = vec![0u8; size];
+            let result = self.decode(&mut pixels);
+            self.output_layout = output_layout;
+            result?;
+            for (index, batch) in pixels.chunks((rows * row_bytes).max(1)).enumerate() {
+                handler((index * rows) as u32, batch)?;
+            }
+            return Ok(());
+        }
+
+        let session = Session::begin();
+        self.truncation = None;
+        let result = self.decode_scan_in_batches(rows, row_bytes, &mut handler);
+        self.stats = DecodeStats {
+            peak_buffer_bytes: session.finish(),
+            pixel_count: frame_info.width as u64 * frame_info.height as u64,
         };
+        result
+    }
 
-        // Build coding parameters with proper limit computation
-        let mut coding_params = self.reader.parameters();
-        coding_params.limit = crate::jpegls::coding_parameters::compute_limit_parameter(
-            frame_info.bits_per_sample,
-            coding_params.near_lossless,
-            frame_info.component_count,
-        );
-        coding_params.quantized_bits_per_sample = frame_info.bits_per_sample;
+    /// Decodes a scan of every component for [`decode_row_batches`](Self::decode_row_batches).
+    fn decode_scan_in_batches(
+        &mut self,
+        rows: usize,
+        row_bytes: usize,
+        handler: &mut impl FnMut(u32, &[u8]) -> Result<(), JpeglsError>,
+    ) -> Result<(), JpeglsError> {
+        let frame_info = self.frame_info();
+        let transformation = self.reader.parameters().transformation;
+        if !color_transform::is_supported(transformation, &frame_info) {
+            return Err(JpeglsError::ColorTransformNotSupported);
+        }
+        let components = frame_info.component_count as usize;
+        let height = frame_info.height as usize;
+        let bytes_per_sample = if frame_info.bits_per_sample <= 8 {
+            1
+        } else {
+            2
+        };
+        let layout = SampleLayout::interleaved(components, row_bytes / bytes_per_sample);
 
-        let mut scan_decoder = crate::jpegls::scan_decoder::ScanDecoder::new(
+        self.reader.read_start_of_scan_segment_jpegls()?;
+        scan_components(&self.reader, &vec![false; components])?;
+        let mapping_table_ids: Vec<u8> = self
+            .reader
+            .components
+            .iter()
+            .map(|component| component.mapping_table_id)
+            .collect();
+        self.check_mapping_table_ids(&mapping_table_ids);
+        let (preset, coding_params) = scan_parameters(&self.reader, &frame_info)?;
+        let mut scan_decoder = ScanDecoder::new(
             frame_info,
             preset,
             coding_params,
             self.reader.remaining_data(),
         )?;
 
+        // The color transform and the handler see whole batches.
+        let mut send = |first_row: usize, pixels: &mut [u8]| {
+            let batch_info = FrameInfo {
+                height: (pixels.len() / row_bytes.max(1)) as u32,
+                ..frame_info
+            };
+            if transformation != ColorTransformation::None {
+                color_transform::inverse_bytes(transformation, &batch_info, pixels, layout)?;
+            }
+            handler(first_row as u32, pixels)
+        };
+        let mut batch = vec![0u8; rows * row_bytes];
+        let mut handler_failed = false;
+        let result = scan_decoder.decode_row_batches(&mut batch, |first_row, pixels| {
+            let result = send(first_row, pixels);
+            handler_failed = result.is_err();
+            result
+        });
+        let lines = scan_decoder.lines_decoded();
+        // A failed scan leaves the reader where the scan data stopped.
+        self.reader
+            .advance(*result.as_ref().unwrap_or(&scan_decoder.position()));
+        let error = match result {
+            Ok(_) => return Ok(()),
+            Err(error) if handler_failed || !self.tolerant => return Err(error),
+            Err(error) => error,
+        };
+
+        self.truncation = Some(Truncation {
+            offset: self.reader.position(),
+            complete_rows: lines as u32,
+            error,
+        });
+        // The batch with the first row not decoded and those after it, filled with mid-grey.
+        for first_row in (lines / rows * rows..height).step_by(rows) {
+            let batch_rows = rows.min(height - first_row);
+            let pixels = &mut batch[..batch_rows * row_bytes];
+            let batch_info = FrameInfo {
+                height: batch_rows as u32,
+                ..frame_info
+            };
+            let rows_read = vec![lines.saturating_sub(first_row); components];
+            fill_missing_rows(pixels, layout, &batch_info, &rows_read);
+            send(first_row, pixels)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the marker segment at the reader that is not a scan, e.g. preset parameters
+    /// between the scans of a non-interleaved image.
+    fn read_segment_between_scans(&mut self) -> Result<(), JpeglsError> {
+        match self.reader.peek_marker()? {
+            JpegMarkerCode::EndOfImage => return Err(JpeglsError::InvalidData),
+            JpegMarkerCode::JpeglsPresetParameters => {
+                self.reader.read_marker()?;
+                self.reader.read_jpegls_preset_parameters_segment()?;
+            }
+            JpegMarkerCode::DefineNumberOfLines => {
+                self.reader.read_marker()?;
+                self.reader.read_define_number_of_lines_segment()?;
+            }
+            JpegMarkerCode::DefineRestartInterval => {
+                self.reader.read_marker()?;
+                self.reader.read_dri_segment()?;
+            }
+            _ => {
+                let marker = self.reader.read_marker()?;
+                self.reader.skip_or_report_segment(marker)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn decode_frame(&mut self, destination: &mut [u8], stride: usize) -> Result<(), JpeglsError> {
+        if self.compressed_data_format == CompressedDataFormat::AbbreviatedTableSpecification {
+            return Err(JpeglsError::InvalidOperation);
+        }
+        let frame_info = self.frame_info();
+        // The row arithmetic below assumes the image size fits in usize.
+        frame_info.decoded_size(self.output_layout)?;
         let components = frame_info.component_count as usize;
         let width = frame_info.width as usize;
+        let height = frame_info.height as usize;
         let bytes_per_sample = if frame_info.bits_per_sample <= 8 { 1 } else { 2 };
-        let stride = width * components * bytes_per_sample;
+        let (pixel_components, row_count) = match self.output_layout {
+            OutputLayout::Interleaved => (components, height),
+            OutputLayout::Planar => (1, height * components),
+        };
+        let row_bytes = width * pixel_components * bytes_per_sample;
+        let stride = if stride == AUTO_CALCULATE_STRIDE {
+            row_bytes
+        } else {
+            stride
+        };
+        if stride < row_bytes || stride % bytes_per_sample != 0 {
+            return Err(JpeglsError::InvalidArgumentStride);
+        }
+        let needed = match row_count {
+            0 => 0,
+            _ => (row_count - 1) * stride + row_bytes,
+        };
+        if destination.len() < needed {
+            return Err(JpeglsError::DestinationTooSmall { needed });
+        }
+        let layout = match self.output_layout {
+            OutputLayout::Interleaved => {
+                SampleLayout::interleaved(components, stride / bytes_per_sample)
+            }
+            OutputLayout::Planar => {
+                SampleLayout::planar_with_stride(stride / bytes_per_sample, height)
+            }
+        };
+        let transformation = self.reader.parameters().transformation;
+        if !color_transform::is_supported(transformation, &frame_info) {
+            return Err(JpeglsError::ColorTransformNotSupported);
+        }
+
+        // A non-interleaved image stores every component in a scan of its own; decode scans
+        // until all components are present.
+        let mut decoded = vec![false; components];
+        // Lines of every component decoded so far.
+        let mut rows_read = vec![0usize; components];
+        let mut decode_scans = || -> Result<(), JpeglsError> {
+            while decoded.iter().any(|&done| !done) {
+                match self.reader.peek_marker()? {
+                    JpegMarkerCode::StartOfScan => {
+                        self.reader.read_start_of_scan_segment_jpegls()?;
+                        let (first, count) = scan_components(&self.reader, &decoded)?;
+                        let mapping_table_ids: Vec<u8> = self.reader.components
+                            [first..first + count]
+                            .iter()
+                            .map(|component| component.mapping_table_id)
+                            .collect();
+                        self.check_mapping_table_ids(&mapping_table_ids);
 
-        scan_decoder.decode_scan(destination, stride)?;
+                        let mut scan_frame_info = frame_info;
+                        scan_frame_info.component_count = count as i32;
+                        let (preset, coding_params) =
+                            scan_parameters(&self.reader, &scan_frame_info)?;
 
+                        let mut scan_decoder = ScanDecoder::new(
+                            scan_frame_info,
+                            preset,
+                            coding_params,
+                            self.reader.remaining_data(),
+                        )?;
+                        let result = match self.output_layout {
+                            OutputLayout::Interleaved => scan_decoder.decode_scan_into(
+                                destination,
+                                stride,
+                                components,
+                                first,
+                            ),
+                            OutputLayout::Planar => decode_planar_scan(
+                                &mut scan_decoder,
+                                frame_info.bits_per_sample,
+                                destination,
+                                layout,
+                                first,
+                            ),
+                        };
+                        let lines = scan_decoder.lines_decoded();
+                        rows_read[first..first + count].fill(lines);
+                        // A failed scan leaves the reader where the scan data stopped.
+                        self.reader
+                            .advance(result.unwrap_or_else(|_| scan_decoder.position()));
+                        result?;
 
+                        decoded[first..first + count].fill(true);
+                    }
+                    _ => self.read_segment_between_scans()?,
+                }
+            }
+            Ok(())
+        };
+        if let Err(error) = decode_scans() {
+            if !self.tolerant {
+                return Err(error);
+            }
+            self.truncation = Some(Truncation {
+                offset: self.reader.position(),
+                complete_rows: rows_read.iter().copied().min().unwrap_or(0) as u32,
+                error,
+            });
+            fill_missing_rows(destination, layout, &frame_info, &rows_read);
+        }
+
+        if transformation != ColorTransformation::None {
+            color_transform::inverse_bytes(transformation, &frame_info, destination, layout)?;
+        }
         Ok(())
     }
 }
+
+/// Fills the lines of every component below the `rows_read` it has with mid-grey samples.
+fn fill_missing_rows(
+    destination: &mut [u8],
+    layout: SampleLayout,
+    frame_info: &FrameInfo,
+    rows_read: &[usize],
+) {
+    let mid_grey = 1u16 << (frame_info.bits_per_sample - 1);
+    for (c, &rows) in rows_read.iter().enumerate() {
+        for y in rows..frame_info.height as usize {
+            for x in 0..frame_info.width as usize {
+                let index = layout.index(x, y, c);
+                if frame_info.bits_per_sample <= 8 {
+                    destination[index] = mid_grey as u8;
+                } else {
+                    destination[2 * index..2 * index + 2].copy_from_slice(&mid_grey.to_ne_bytes());
+                }
+            }
+        }
+    }
+}
+
+/// Decodes a scan into the component planes of `destination`, starting with the plane of
+/// frame component `first`.
+fn decode_planar_scan(
+    scan_decoder: &mut ScanDecoder,
+    bits_per_sample: i32,
+    destination: &mut [u8],
+    layout: SampleLayout,
+    first: usize,
+) -> Result<usize, JpeglsError> {
+    let offset = layout.index(0, 0, first);
+    if bits_per_sample <= 8 {
+        scan_decoder.decode_samples(&mut destination[offset..], layout)
+    } else {
+        let (head, body, _) = unsafe { destination.align_to_mut::<u16>() };
+        if !head.is_empty() {
+            return Err(JpeglsError::InvalidData);
+        }
+        scan_decoder.decode_samples(&mut body[offset..], layout)
+    }
+}
+
+/// Returns the first frame component and the number of components of the scan whose header
+/// was just read. The components of a scan must be consecutive frame components that have
+/// not been decoded yet.
+pub(crate) fn scan_components(
+    reader: &JpegStreamReader,
+    decoded: &[bool],
+) -> Result<(usize, usize), JpeglsError> {
+    let indices = &reader.scan_component_indices;
+    let first = *indices.first().ok_or(JpeglsError::InvalidData)?;
+    if indices.iter().enumerate().any(|(i, &index)| index != first + i) {
+        return Err(JpeglsError::ParameterValueNotSupported);
+    }
+    if indices.iter().any(|&index| decoded[index]) {
+        return Err(JpeglsError::InvalidData);
+    }
+    Ok((first, indices.len()))
+}
+
+/// Derives the preset and coding parameters of the scan whose header was just read.
+pub(crate) fn scan_parameters(
+    reader: &JpegStreamReader,
+    frame_info: &FrameInfo,
+) -> Result<(JpeglsPcParameters, CodingParameters), JpeglsError> {
+    let preset = crate::jpegls::encoder::validate_preset_coding_parameters(
+        &reader.preset_coding_parameters(),
+        frame_info.bits_per_sample,
+        reader.parameters().near_lossless,
+    )?;
+
+    // Build coding parameters with proper limit computation
+    let mut coding_params = reader.parameters();
+    coding_params.restart_interval = reader.restart_interval as u32;
+    coding_params.limit = crate::jpegls::coding_parameters::compute_limit_parameter(
+        crate::jpegls::coding_parameters::compute_bits_per_sample(preset.maximum_sample_value),
+        coding_params.near_lossless,
+        frame_info.component_count,
+    );
+    coding_params.quantized_bits_per_sample =
+        CodingTraits::new(preset.maximum_sample_value, coding_params.near_lossless)
+            .quantized_bits_per_sample;
+
+    Ok((preset, coding_params))
+}
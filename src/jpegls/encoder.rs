@@ -1,35 +1,134 @@
 use crate::FrameInfo;
+use crate::constants::{
+    AUTO_CALCULATE_STRIDE, MAXIMUM_COMPONENT_COUNT, MAXIMUM_COMPONENT_COUNT_IN_SCAN,
+    MAXIMUM_MAPPING_ENTRY_SIZE, MAXIMUM_MAPPING_TABLE_ID, MAXIMUM_NEAR_LOSSLESS,
+    MINIMUM_MAPPING_ENTRY_SIZE, MINIMUM_MAPPING_TABLE_ID, SPIFF_HEADER_SIZE_IN_BYTES,
+};
 use crate::error::JpeglsError;
-use crate::jpeg_stream_writer::JpegStreamWriter;
-use crate::jpegls::coding_parameters::{compute_default, compute_limit_parameter};
+use crate::jpeg_marker_code::{
+    JPEG_MARKER_START_BYTE, JPEG_RESTART_MARKER_BASE, JPEG_RESTART_MARKER_RANGE,
+};
+use crate::jpeg_stream_writer::{JpegStreamWriter, Sink, SliceSink};
+use crate::jpegls::coding_parameters::{
+    compute_bits_per_sample, compute_default, compute_limit_parameter,
+    compute_maximum_near_lossless, is_default, is_valid,
+};
 use crate::jpegls::scan_encoder::ScanEncoder;
-use crate::jpegls::{CodingParameters, InterleaveMode, JpeglsPcParameters};
+use crate::jpegls::color_transform;
+use crate::jpegls::{
+    CodingParameters, ColorTransformation, InterleaveMode, JpeglsPcParameters,
+    SpiffColorSpace, SpiffCompressionType, SpiffHeader, SpiffProfileId, SpiffResolutionUnits,
+};
+use crate::jpegls::sample_layout::SampleLayout;
+use crate::jpegls::traits::CodingTraits;
+use crate::mem_profiling::{track_elements, EncodeStats, Session};
+use crate::resolution::Resolution;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 
-pub struct JpeglsEncoder<'a> {
-    writer: JpegStreamWriter<'a>,
+/// Which part of the stream the encoder has written so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EncoderState {
+    Initial,
+    SpiffHeader,
+    TablesAndMiscellaneous,
+}
+
+/// Writes a JPEG-LS stream to a sink `S`, by default a [`SliceSink`] over the buffer
+/// passed to [`new`](JpeglsEncoder::new).
+pub struct JpeglsEncoder<'a, S: Sink = SliceSink<'a>> {
+    writer: JpegStreamWriter<S>,
     frame_info: Option<FrameInfo>,
     near_lossless: i32,
     interleave_mode: InterleaveMode,
     pc_parameters: Option<JpeglsPcParameters>,
+    color_transformation: ColorTransformation,
+    /// Mapping table selector per component; components past the end use none.
+    mapping_table_ids: Vec<u8>,
+    define_number_of_lines: bool,
+    restart_interval: u16,
+    thread_count: usize,
+    auto_restart_interval: bool,
+    deterministic: bool,
+    state: EncoderState,
+    stats: EncodeStats,
+    destination: PhantomData<&'a mut [u8]>,
 }
 
 impl<'a> JpeglsEncoder<'a> {
     pub fn new(destination: &'a mut [u8]) -> Self {
+        Self::with_sink(SliceSink::new(destination))
+    }
+
+    /// Size of a destination buffer large enough to encode an image described by
+    /// `frame_info` in `interleave_mode`, like CharLS' `estimated_destination_size`.
+    ///
+    /// Images that do not compress, such as noise, take a little more than their sample
+    /// precision per sample, and the start of every scan more while the contexts adapt.
+    /// The estimate allows 4 extra bits per sample and 128 bytes per scan for that, and
+    /// 1024 bytes and a SPIFF header for the marker segments; COM and APPn segments come
+    /// on top.
+    pub fn estimated_destination_size(
+        frame_info: &FrameInfo,
+        interleave_mode: InterleaveMode,
+    ) -> usize {
+        let components = frame_info.component_count.max(0) as usize;
+        let samples = frame_info.width as usize * frame_info.height as usize * components;
+        let bits = samples * (frame_info.bits_per_sample.max(0) as usize + 4);
+        let scans = if interleave_mode == InterleaveMode::None {
+            components
+        } else {
+            1
+        };
+        bits.div_ceil(8) + scans * 128 + 1024 + SPIFF_HEADER_SIZE_IN_BYTES
+    }
+}
+
+impl<S: Sink> JpeglsEncoder<'_, S> {
+    /// Creates an encoder that writes the stream to `sink` as it is produced, e.g. a
+    /// `Vec<u8>` or a [`WriteSink`](crate::jpeg_stream_writer::WriteSink).
+    pub fn with_sink(sink: S) -> Self {
         Self {
-            writer: JpegStreamWriter::new(destination),
+            writer: JpegStreamWriter::with_sink(sink),
             frame_info: None,
             near_lossless: 0,
             interleave_mode: InterleaveMode::None,
             pc_parameters: None,
+            color_transformation: ColorTransformation::None,
+            mapping_table_ids: Vec::new(),
+            define_number_of_lines: false,
+            restart_interval: 0,
+            thread_count: 1,
+            auto_restart_interval: false,
+            deterministic: false,
+            state: EncoderState::Initial,
+            stats: EncodeStats::default(),
+            destination: PhantomData,
         }
     }
 
+    /// Returns the sink, e.g. to take back a `Vec<u8>` that holds the stream.
+    pub fn into_sink(self) -> S {
+        self.writer.into_sink()
+    }
+
     pub fn set_frame_info(&mut self, frame_info: FrameInfo) -> Result<(), JpeglsError> {
         self.frame_info = Some(frame_info);
         Ok(())
     }
 
+    /// Sets the NEAR parameter: the maximum absolute difference allowed between a source
+    /// sample and its reconstructed value. 0 (the default) selects lossless coding.
+    ///
+    /// The upper bound depends on the sample precision (`MAXVAL / 2`, at most 255); it is
+    /// checked here when the frame info is already known and again when encoding.
     pub fn set_near_lossless(&mut self, near_lossless: i32) -> Result<(), JpeglsError> {
+        if let Some(frame_info) = self.frame_info {
+            validate_near_lossless(near_lossless, frame_info.bits_per_sample)?;
+        } else if !(0..=MAXIMUM_NEAR_LOSSLESS).contains(&near_lossless) {
+            return Err(JpeglsError::InvalidParameterNearLossless);
+        }
         self.near_lossless = near_lossless;
         Ok(())
     }
@@ -42,50 +141,385 @@ impl<'a> JpeglsEncoder<'a> {
         Ok(())
     }
 
+    /// Sets the preset coding parameters (MAXVAL, T1, T2, T3 and RESET) written to the LSE
+    /// marker segment. A field left at 0 takes its default value from ISO/IEC 14495-1,
+    /// C.2.4.1.1; the segment is only written when the parameters differ from the defaults.
+    ///
+    /// The values are checked against Table C.1 here when the frame info is already known
+    /// and again when encoding. The source samples must not exceed MAXVAL.
     pub fn set_preset_coding_parameters(
         &mut self,
         pc_parameters: JpeglsPcParameters,
     ) -> Result<(), JpeglsError> {
+        if let Some(frame_info) = self.frame_info {
+            validate_preset_coding_parameters(
+                &pc_parameters,
+                frame_info.bits_per_sample,
+                self.near_lossless,
+            )?;
+        }
         self.pc_parameters = Some(pc_parameters);
         Ok(())
     }
 
+    /// Sets the HP color transformation applied to RGB pixels before they are coded. The
+    /// transformation is signalled in an APP8 "mrfx" segment and requires 3 components of
+    /// 8 or 16 bits; it is checked when encoding. Decoders that do not recognize the segment
+    /// return the transformed values. Combined with near-lossless coding, the error of the
+    /// red and blue samples may exceed NEAR.
+    pub fn set_color_transformation(
+        &mut self,
+        color_transformation: ColorTransformation,
+    ) -> Result<(), JpeglsError> {
+        self.color_transformation = color_transformation;
+        Ok(())
+    }
+
+    /// Writes a height of 0 in the frame header and the actual height in a DNL segment after
+    /// the first scan, the layout produced by line-scan cameras that do not know the number
+    /// of lines in advance. Off by default.
+    pub fn set_define_number_of_lines(&mut self, enabled: bool) {
+        self.define_number_of_lines = enabled;
+    }
+
+    /// Ends every `restart_interval` lines of a scan with a restart marker (RSTm), announced
+    /// in a DRI segment. The coding state is reset after each marker, so the intervals can
+    /// be encoded and decoded independently of each other. A line of a line- or
+    /// sample-interleaved scan holds all its components. 0, the default, writes no restart
+    /// markers.
+    pub fn set_restart_interval(&mut self, restart_interval: u16) {
+        self.restart_interval = restart_interval;
+    }
+
+    /// Encodes the restart intervals of each scan on up to `thread_count` threads and joins
+    /// them with their RSTm markers; the stream is the same as when encoding on one thread.
+    /// Without a restart interval a scan is encoded on one thread, unless
+    /// [`set_auto_restart_interval`](Self::set_auto_restart_interval) is on. 0 uses
+    /// [`std::thread::available_parallelism`] threads; 1, the default, encodes on the calling
+    /// thread. The buffers of the worker threads are not counted in [`stats`](Self::stats).
+    pub fn set_thread_count(&mut self, thread_count: usize) {
+        self.thread_count = thread_count;
+    }
+
+    /// Without a restart interval, splits the lines into one restart interval per thread
+    /// of [`set_thread_count`](Self::set_thread_count), so that a scan is encoded in
+    /// parallel. This writes a DRI segment and RSTm markers that were not asked for with
+    /// [`set_restart_interval`](Self::set_restart_interval), and the stream then depends on
+    /// the thread count. Off by default.
+    pub fn set_auto_restart_interval(&mut self, enabled: bool) {
+        self.auto_restart_interval = enabled;
+    }
+
+    /// Makes the stream byte-identical whatever the thread count and the CPU, e.g. for
+    /// archives that hash the encoded images to find duplicates: the lines are no longer
+    /// split into one restart interval per thread even with
+    /// [`set_auto_restart_interval`](Self::set_auto_restart_interval), so without a restart
+    /// interval a scan is encoded on one thread. Scans with a restart interval are still
+    /// encoded in parallel; their stripes are joined in order. Off by default.
+    pub fn set_encode_deterministic(&mut self, enabled: bool) {
+        self.deterministic = enabled;
+    }
+
+    /// Writes SOI and a SPIFF header (ISO/IEC 10918-3, Annex F) to the destination. Must be
+    /// called before anything else is written; the SPIFF end-of-directory entry is written
+    /// when the image is encoded.
+    pub fn write_spiff_header(&mut self, spiff_header: &SpiffHeader) -> Result<(), JpeglsError> {
+        if spiff_header.height == 0 {
+            return Err(JpeglsError::InvalidArgumentHeight);
+        }
+        if spiff_header.width == 0 {
+            return Err(JpeglsError::InvalidArgumentWidth);
+        }
+        if self.state != EncoderState::Initial {
+            return Err(JpeglsError::InvalidOperation);
+        }
+
+        self.writer.write_start_of_image()?;
+        self.writer.write_spiff_header_segment(spiff_header)?;
+        self.state = EncoderState::SpiffHeader;
+        Ok(())
+    }
+
+    /// Writes a SPIFF header that describes the frame set with
+    /// [`set_frame_info`](Self::set_frame_info), with a 1:1 aspect ratio as resolution.
+    pub fn write_standard_spiff_header(
+        &mut self,
+        color_space: SpiffColorSpace,
+    ) -> Result<(), JpeglsError> {
+        let frame_info = self.frame_info.ok_or(JpeglsError::InvalidOperation)?;
+        self.write_spiff_header(&SpiffHeader {
+            profile_id: SpiffProfileId::None,
+            component_count: frame_info.component_count,
+            height: frame_info.height,
+            width: frame_info.width,
+            color_space,
+            bits_per_sample: frame_info.bits_per_sample,
+            compression_type: SpiffCompressionType::JpegLs,
+            resolution_units: SpiffResolutionUnits::AspectRatio,
+            vertical_resolution: 1,
+            horizontal_resolution: 1,
+        })
+    }
+
+    /// Writes a COM segment, e.g. a software tag. Segments are written in the order of the
+    /// calls, after the SPIFF header (if any) and before the frame.
+    pub fn write_comment(&mut self, comment: &[u8]) -> Result<(), JpeglsError> {
+        self.begin_tables_and_miscellaneous()?;
+        self.writer.write_comment(comment)
+    }
+
+    /// Writes an APPn segment (`id` 0 to 15), e.g. to embed DICOM UIDs. APP8 segments that
+    /// start with "mrfx" or "SPIFF" are reserved for the color transformation and the SPIFF
+    /// header.
+    pub fn write_application_data(&mut self, id: u8, data: &[u8]) -> Result<(), JpeglsError> {
+        self.begin_tables_and_miscellaneous()?;
+        self.writer.write_application_data(id, data)
+    }
+
+    /// Writes a mapping table (`table_id` 1 to 255) with entries of `entry_size` bytes, split
+    /// over continuation segments when it holds more than 65530 bytes. Scans refer to it
+    /// through [`set_mapping_table_id`](Self::set_mapping_table_id).
+    ///
+    /// Tables written before [`create_tables_only`](Self::create_tables_only) make up an
+    /// abbreviated table specification; an image that selects tables without writing them
+    /// is abbreviated image data, decoded with
+    /// [`JpeglsDecoder::read_table_specification`](crate::jpegls::JpeglsDecoder::read_table_specification).
+    pub fn write_mapping_table(
+        &mut self,
+        table_id: i32,
+        entry_size: i32,
+        table_data: &[u8],
+    ) -> Result<(), JpeglsError> {
+        if !(MINIMUM_MAPPING_TABLE_ID..=MAXIMUM_MAPPING_TABLE_ID).contains(&table_id)
+            || !(MINIMUM_MAPPING_ENTRY_SIZE..=MAXIMUM_MAPPING_ENTRY_SIZE).contains(&entry_size)
+        {
+            return Err(JpeglsError::InvalidArgument);
+        }
+        if table_data.is_empty() || !table_data.len().is_multiple_of(entry_size as usize) {
+            return Err(JpeglsError::InvalidArgumentSize);
+        }
+        self.begin_tables_and_miscellaneous()?;
+        self.writer
+            .write_mapping_table_segments(table_id as u8, entry_size as u8, table_data)
+    }
+
+    /// Selects mapping table `table_id` (0 for none, the default) for the component at
+    /// `component_index`.
+    pub fn set_mapping_table_id(
+        &mut self,
+        component_index: i32,
+        table_id: i32,
+    ) -> Result<(), JpeglsError> {
+        if !(0..MAXIMUM_COMPONENT_COUNT).contains(&component_index)
+            || !(0..=MAXIMUM_MAPPING_TABLE_ID).contains(&table_id)
+        {
+            return Err(JpeglsError::InvalidArgument);
+        }
+        let index = component_index as usize;
+        if self.mapping_table_ids.len() <= index {
+            self.mapping_table_ids.resize(index + 1, 0);
+        }
+        self.mapping_table_ids[index] = table_id as u8;
+        Ok(())
+    }
+
+    /// Ends the stream after the tables and miscellaneous segments written so far, giving
+    /// an abbreviated table specification without a frame. Returns the length of the stream.
+    pub fn create_tables_only(&mut self) -> Result<usize, JpeglsError> {
+        self.begin_tables_and_miscellaneous()?;
+        self.writer.write_end_of_image()?;
+        Ok(self.writer.len())
+    }
+
+    fn mapping_table_id(&self, component_index: usize) -> u8 {
+        self.mapping_table_ids
+            .get(component_index)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Writes whatever has to precede the table and miscellaneous segments: SOI, or the
+    /// SPIFF end-of-directory entry when a SPIFF header was written.
+    fn begin_tables_and_miscellaneous(&mut self) -> Result<(), JpeglsError> {
+        match self.state {
+            EncoderState::Initial => self.writer.write_start_of_image()?,
+            EncoderState::SpiffHeader => self.writer.write_spiff_end_of_directory_entry()?,
+            EncoderState::TablesAndMiscellaneous => {}
+        }
+        self.state = EncoderState::TablesAndMiscellaneous;
+        Ok(())
+    }
+
+    /// Memory statistics of the most recent call to [`encode`](Self::encode).
+    pub fn stats(&self) -> EncodeStats {
+        self.stats
+    }
+
     pub fn encode(&mut self, source: &[u8]) -> Result<usize, JpeglsError> {
+        self.encode_with_stride(source, AUTO_CALCULATE_STRIDE)
+    }
+
+    /// Encodes pixel-interleaved rows that are `stride` bytes apart in `source`.
+    ///
+    /// A stride of [`AUTO_CALCULATE_STRIDE`] means the rows are tightly packed. The padding
+    /// at the end of each row is never read, and the last row needs no padding.
+    ///
+    /// Returns [`JpeglsError::DestinationTooSmall`] with the estimated size of the stream,
+    /// including what was written before, when it does not fit in the destination.
+    pub fn encode_with_stride(
+        &mut self,
+        source: &[u8],
+        stride: usize,
+    ) -> Result<usize, JpeglsError> {
+        let session = Session::begin();
+        let written = self.writer.len();
+        let result = self.encode_frame(source, stride).map_err(|e| match e {
+            // The sink reports more than it holds.
+            JpeglsError::DestinationTooSmall { needed } => {
+                let estimate = self.frame_info.map_or(0, |frame_info| {
+                    JpeglsEncoder::estimated_destination_size(&frame_info, self.interleave_mode)
+                });
+                JpeglsError::DestinationTooSmall {
+                    needed: needed.max(written + estimate),
+                }
+            }
+            e => e,
+        });
+        self.stats = EncodeStats {
+            peak_buffer_bytes: session.finish(),
+            pixel_count: self
+                .frame_info
+                .map_or(0, |f| f.width as u64 * f.height as u64),
+        };
+        result
+    }
+
+    fn encode_frame(&mut self, source: &[u8], stride: usize) -> Result<usize, JpeglsError> {
         let frame_info = *self
             .frame_info
             .as_ref()
             .ok_or(JpeglsError::InvalidParameterComponentCount)?;
 
-        let max_sample_value = (1 << frame_info.bits_per_sample) - 1;
-        let pc = if let Some(p) = self.pc_parameters {
-            p
+        let bytes_per_sample = if frame_info.bits_per_sample <= 8 { 1 } else { 2 };
+        let components = frame_info.component_count as usize;
+        let row_bytes = frame_info.width as usize * components * bytes_per_sample;
+        let stride = if stride == AUTO_CALCULATE_STRIDE {
+            row_bytes
         } else {
-            compute_default(max_sample_value, self.near_lossless)
+            stride
         };
+        if stride < row_bytes || stride % bytes_per_sample != 0 {
+            return Err(JpeglsError::InvalidArgumentStride);
+        }
+        let height = frame_info.height as usize;
+        let expected_size = if height == 0 {
+            0
+        } else {
+            (height - 1) * stride + row_bytes
+        };
+        if source.len() < expected_size {
+            return Err(JpeglsError::InvalidArgumentSize);
+        }
+        let source = &source[..expected_size];
+
+        let layout = SampleLayout::interleaved(components, stride / bytes_per_sample);
+        if frame_info.bits_per_sample <= 8 {
+            self.encode_pixels::<u8>(source, layout)
+        } else {
+            let (head, body, tail) = unsafe { source.align_to::<u16>() };
+            if !head.is_empty() || !tail.is_empty() {
+                return Err(JpeglsError::InvalidData);
+            }
+            self.encode_pixels::<u16>(body, layout)
+        }
+    }
+
+    /// Applies the color transformation, if any, to a packed copy of the pixels and encodes
+    /// it.
+    fn encode_pixels<T: crate::jpegls::traits::JpeglsSample>(
+        &mut self,
+        pixels: &[T],
+        layout: SampleLayout,
+    ) -> Result<usize, JpeglsError> {
+        if self.color_transformation == ColorTransformation::None {
+            return self.encode_samples(pixels, layout);
+        }
+
+        let frame_info = self.validated_frame_info()?;
+        let components = frame_info.component_count as usize;
+        let row_len = frame_info.width as usize * components;
+        let mut transformed = Vec::with_capacity(row_len * frame_info.height as usize);
+        for y in 0..frame_info.height as usize {
+            let start = layout.index(0, y, 0);
+            transformed.extend_from_slice(&pixels[start..start + row_len]);
+        }
+        let _transformed_memory = track_elements::<T>(transformed.len());
+        color_transform::forward(self.color_transformation, &mut transformed);
+        self.encode_samples(&transformed, SampleLayout::interleaved(components, row_len))
+    }
+
+    /// Writes the complete JPEG-LS stream for samples arranged as described by `layout`.
+    pub(crate) fn encode_samples<T: crate::jpegls::traits::JpeglsSample>(
+        &mut self,
+        samples: &[T],
+        layout: SampleLayout,
+    ) -> Result<usize, JpeglsError> {
+        let frame_info = self.validated_frame_info()?;
+        validate_near_lossless(self.near_lossless, frame_info.bits_per_sample)?;
+        let preset = self.pc_parameters.unwrap_or_default();
+        let pc = validate_preset_coding_parameters(
+            &preset,
+            frame_info.bits_per_sample,
+            self.near_lossless,
+        )?;
 
-        // Fallback to Planar (None) if InterleaveMode is None but we have components > 1.
         let interleave_mode = self.interleave_mode;
+        // Without a restart interval, the lines are split into one interval per thread when
+        // asked for, unless the stream must not depend on the thread count.
+        let split = self.auto_restart_interval && !self.deterministic;
+        let restart_interval = match (self.restart_interval, self.threads()) {
+            (0, threads) if threads > 1 && split => (frame_info.height as usize)
+                .div_ceil(threads)
+                .min(u16::MAX as usize) as u16,
+            (restart_interval, _) => restart_interval,
+        };
 
+        let traits = CodingTraits::new(pc.maximum_sample_value, self.near_lossless);
         let coding_parameters = CodingParameters {
             near_lossless: self.near_lossless,
             interleave_mode,
-            restart_interval: 0,
+            restart_interval: restart_interval as u32,
             limit: compute_limit_parameter(
-                frame_info.bits_per_sample,
+                compute_bits_per_sample(pc.maximum_sample_value),
                 self.near_lossless,
                 frame_info.component_count,
             ),
-            quantized_bits_per_sample: frame_info.bits_per_sample,
-            transformation: crate::jpegls::ColorTransformation::None,
+            quantized_bits_per_sample: traits.quantized_bits_per_sample,
+            transformation: self.color_transformation,
             mapping_table_id: 0,
         };
 
-        self.writer.write_start_of_image()?;
-        self.writer.write_start_of_frame_jpegls(&frame_info)?;
+        self.begin_tables_and_miscellaneous()?;
+        if self.define_number_of_lines {
+            let header_frame_info = FrameInfo {
+                height: 0,
+                ..frame_info
+            };
+            self.writer
+                .write_start_of_frame_jpegls(&header_frame_info)?;
+        } else {
+            self.writer.write_start_of_frame_jpegls(&frame_info)?;
+        }
+        if self.color_transformation != ColorTransformation::None {
+            self.writer.write_color_transform_segment(self.color_transformation)?;
+        }
 
-        let defaults = compute_default(max_sample_value, self.near_lossless);
-        if !crate::jpegls::coding_parameters::is_default(&pc, &defaults) {
-            self.writer.write_jpegls_preset_parameters_segment(&pc)?;
+        let defaults = compute_default((1 << frame_info.bits_per_sample) - 1, self.near_lossless);
+        if !is_default(&preset, &defaults) {
+            self.writer.write_jpegls_preset_parameters_segment(&preset)?;
+        }
+        if restart_interval != 0 {
+            self.writer.write_dri(restart_interval)?;
         }
 
         if interleave_mode == InterleaveMode::None && frame_info.component_count > 1 {
@@ -94,66 +528,39 @@ impl<'a> JpeglsEncoder<'a> {
                 // Write SOS for SINGLE component `c+1`
                 self.writer.write_start_of_scan_segment_planar(
                     c as u8 + 1, // Component ID (1-based)
+                    self.mapping_table_id(c as usize),
                     self.near_lossless,
                     InterleaveMode::None,
                 )?;
 
-                // Extract component data
-                // Assume source is Interleaved (standard). We need to de-interleave.
-                let component_index = c as usize;
-                let width = frame_info.width as usize;
-                let height = frame_info.height as usize;
-                let total_components = frame_info.component_count as usize;
-
-                if frame_info.bits_per_sample <= 8 {
-                    let pixel_count = width * height;
-                    let mut plane_data = vec![0u8; pixel_count];
-                    for i in 0..pixel_count {
-                        plane_data[i] = source[i * total_components + component_index];
-                    }
-                    self.encode_scan_typed::<u8>(
-                        &plane_data,
-                        &frame_info,
-                        pc,
-                        coding_parameters,
-                        true,
-                    )?;
-                } else {
-                    let (head, body, tail) = unsafe { source.align_to::<u16>() };
-                    if !head.is_empty() || !tail.is_empty() {
-                        return Err(JpeglsError::InvalidData);
-                    }
-                    let pixel_count = width * height;
-                    let mut plane_data = vec![0u16; pixel_count];
-                    for i in 0..pixel_count {
-                        plane_data[i] = body[i * total_components + component_index];
-                    }
-                    self.encode_scan_typed::<u16>(
-                        &plane_data,
-                        &frame_info,
-                        pc,
-                        coding_parameters,
-                        true,
-                    )?;
+                // The component's samples are addressed in place; no de-interleaving copy.
+                let component_samples = samples
+                    .get(layout.index(0, 0, c as usize)..)
+                    .ok_or(JpeglsError::InvalidArgumentSize)?;
+                self.encode_scan_typed(
+                    component_samples,
+                    layout,
+                    &frame_info,
+                    pc,
+                    coding_parameters,
+                    true,
+                )?;
+                if c == 0 {
+                    self.write_define_number_of_lines(&frame_info)?;
                 }
             }
         } else {
             // Single Scan (Monochrome or Interleaved)
+            let mapping_table_ids: Vec<u8> = (0..frame_info.component_count as usize)
+                .map(|c| self.mapping_table_id(c))
+                .collect();
             self.writer.write_start_of_scan_segment(
-                frame_info.component_count,
+                &mapping_table_ids,
                 self.near_lossless,
                 interleave_mode,
             )?;
-
-            let _bytes_written = if frame_info.bits_per_sample <= 8 {
-                self.encode_scan_typed::<u8>(source, &frame_info, pc, coding_parameters, false)?
-            } else {
-                let (head, body, tail) = unsafe { source.align_to::<u16>() };
-                if !head.is_empty() || !tail.is_empty() {
-                    return Err(JpeglsError::InvalidData);
-                }
-                self.encode_scan_typed::<u16>(body, &frame_info, pc, coding_parameters, false)?
-            };
+            self.encode_scan_typed(samples, layout, &frame_info, pc, coding_parameters, false)?;
+            self.write_define_number_of_lines(&frame_info)?;
         }
 
         self.writer.write_end_of_image()?;
@@ -161,31 +568,415 @@ impl<'a> JpeglsEncoder<'a> {
         Ok(self.writer.len())
     }
 
+    fn write_define_number_of_lines(&mut self, frame_info: &FrameInfo) -> Result<(), JpeglsError> {
+        if self.define_number_of_lines {
+            self.writer
+                .write_define_number_of_lines_segment(frame_info.height as u16)?;
+        }
+        Ok(())
+    }
+
+    /// Number of threads to encode a scan on.
+    fn threads(&self) -> usize {
+        match self.thread_count {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            thread_count => thread_count,
+        }
+    }
+
+    fn validated_frame_info(&self) -> Result<FrameInfo, JpeglsError> {
+        let frame_info = self
+            .frame_info
+            .ok_or(JpeglsError::InvalidParameterComponentCount)?;
+        if !color_transform::is_supported(self.color_transformation, &frame_info) {
+            return Err(JpeglsError::InvalidArgumentColorTransformation);
+        }
+        Ok(frame_info)
+    }
+
     fn encode_scan_typed<T: crate::jpegls::traits::JpeglsSample>(
         &mut self,
         source: &[T],
+        layout: SampleLayout,
         frame_info: &FrameInfo,
         pc: JpeglsPcParameters,
         mut coding_params: CodingParameters,
         is_planar_component: bool,
     ) -> Result<usize, JpeglsError> {
-        let stride = frame_info.width as usize;
-
-        let dest_slice = self.writer.remaining_slice();
-
         let mut scan_frame_info = *frame_info;
         if is_planar_component {
             scan_frame_info.component_count = 1;
             coding_params.interleave_mode = InterleaveMode::None;
         }
 
-        let mut scan_encoder = ScanEncoder::new(scan_frame_info, pc, coding_params, dest_slice);
+        let height = scan_frame_info.height as usize;
+        let restart_interval = coding_params.restart_interval as usize;
+        if self.threads() > 1 && restart_interval != 0 && height > restart_interval {
+            return self.encode_stripes(source, layout, &scan_frame_info, pc, coding_params);
+        }
 
-        let bytes_written = scan_encoder.encode_scan(source, stride)?;
-        drop(scan_encoder);
+        let mut scan_encoder =
+            ScanEncoder::new(scan_frame_info, pc, coding_params, &mut self.writer);
+        scan_encoder.encode_scan(source, layout)
+    }
 
-        self.writer.advance(bytes_written);
+    /// Encodes every restart interval of a scan as a stripe of its own on the worker
+    /// threads and writes the stripes in order, each but the first after the RSTm marker
+    /// that ends the interval before it. Resetting the coding state at each marker makes
+    /// the result identical to encoding the scan in one piece.
+    fn encode_stripes<T: crate::jpegls::traits::JpeglsSample>(
+        &mut self,
+        source: &[T],
+        layout: SampleLayout,
+        frame_info: &FrameInfo,
+        pc: JpeglsPcParameters,
+        coding_params: CodingParameters,
+    ) -> Result<usize, JpeglsError> {
+        let height = frame_info.height as usize;
+        let restart_interval = coding_params.restart_interval as usize;
+        let stripe_count = height.div_ceil(restart_interval);
+        let stripe_params = CodingParameters {
+            restart_interval: 0,
+            ..coding_params
+        };
+        let encode = |stripe: usize| {
+            let first_line = stripe * restart_interval;
+            let stripe_info = FrameInfo {
+                height: restart_interval.min(height - first_line) as u32,
+                ..*frame_info
+            };
+            let stripe_source = source
+                .get(layout.index(0, first_line, 0)..)
+                .ok_or(JpeglsError::InvalidArgumentSize)?;
+            encode_stripe(stripe_source, layout, stripe_info, pc, stripe_params)
+        };
+        let next_stripe = AtomicUsize::new(0);
+
+        // Workers take the next stripe until none are left.
+        let mut stripes: Vec<(usize, Result<Vec<u8>, JpeglsError>)> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.threads().min(stripe_count))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut stripes = Vec::new();
+                        loop {
+                            let stripe = next_stripe.fetch_add(1, Ordering::Relaxed);
+                            if stripe >= stripe_count {
+                                break stripes;
+                            }
+                            stripes.push((stripe, encode(stripe)));
+                        }
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| {
+                    worker
+                        .join()
+                        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                })
+                .collect()
+        });
+        stripes.sort_by_key(|(stripe, _)| *stripe);
+
+        let start = self.writer.len();
+        for (stripe, encoded) in stripes {
+            if stripe > 0 {
+                let index = (stripe - 1) % JPEG_RESTART_MARKER_RANGE as usize;
+                self.writer.write_byte(JPEG_MARKER_START_BYTE)?;
+                self.writer
+                    .write_byte(JPEG_RESTART_MARKER_BASE + index as u8)?;
+            }
+            self.writer.write_bytes(&encoded?)?;
+        }
+        Ok(self.writer.len() - start)
+    }
+}
 
-        Ok(bytes_written)
+/// Encodes the lines of one restart interval into a buffer of its own.
+fn encode_stripe<T: crate::jpegls::traits::JpeglsSample>(
+    source: &[T],
+    layout: SampleLayout,
+    frame_info: FrameInfo,
+    pc: JpeglsPcParameters,
+    coding_params: CodingParameters,
+) -> Result<Vec<u8>, JpeglsError> {
+    let mut stripe = Vec::new();
+    ScanEncoder::new(frame_info, pc, coding_params, &mut stripe).encode_scan(source, layout)?;
+    Ok(stripe)
+}
+
+/// Configures a [`JpeglsEncoder`] and checks the whole configuration when the encoder is
+/// built, instead of when the image is encoded.
+///
+/// ```rust
+/// use jpegexp_rs::jpegls::{InterleaveMode, JpeglsEncoderBuilder};
+/// use jpegexp_rs::FrameInfo;
+///
+/// let frame_info = FrameInfo { width: 4, height: 4, bits_per_sample: 8, component_count: 3 };
+/// let mut destination = vec![0u8; 1024];
+/// let mut encoder = JpeglsEncoderBuilder::new(frame_info)
+///     .near_lossless(2)
+///     .interleave_mode(InterleaveMode::Sample)
+///     .build(&mut destination)
+///     .unwrap();
+/// let len = encoder.encode(&[128u8; 48]).unwrap();
+/// assert!(len > 0);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JpeglsEncoderBuilder {
+    frame_info: FrameInfo,
+    near_lossless: i32,
+    interleave_mode: InterleaveMode,
+    pc_parameters: Option<JpeglsPcParameters>,
+    color_transformation: ColorTransformation,
+    define_number_of_lines: bool,
+    restart_interval: u16,
+    thread_count: usize,
+    auto_restart_interval: bool,
+    deterministic: bool,
+    resolution: Option<Resolution>,
+}
+
+impl JpeglsEncoderBuilder {
+    /// Starts a lossless, non-interleaved configuration for images described by
+    /// `frame_info`.
+    pub fn new(frame_info: FrameInfo) -> Self {
+        Self {
+            frame_info,
+            near_lossless: 0,
+            interleave_mode: InterleaveMode::None,
+            pc_parameters: None,
+            color_transformation: ColorTransformation::None,
+            define_number_of_lines: false,
+            restart_interval: 0,
+            thread_count: 1,
+            auto_restart_interval: false,
+            deterministic: false,
+            resolution: None,
+        }
+    }
+
+    /// See [`JpeglsEncoder::set_near_lossless`].
+    pub fn near_lossless(mut self, near_lossless: i32) -> Self {
+        self.near_lossless = near_lossless;
+        self
     }
+
+    /// Interleave mode of multi-component images.
+    pub fn interleave_mode(mut self, interleave_mode: InterleaveMode) -> Self {
+        self.interleave_mode = interleave_mode;
+        self
+    }
+
+    /// See [`JpeglsEncoder::set_preset_coding_parameters`].
+    pub fn preset_coding_parameters(mut self, pc_parameters: JpeglsPcParameters) -> Self {
+        self.pc_parameters = Some(pc_parameters);
+        self
+    }
+
+    /// See [`JpeglsEncoder::set_color_transformation`].
+    pub fn color_transformation(mut self, color_transformation: ColorTransformation) -> Self {
+        self.color_transformation = color_transformation;
+        self
+    }
+
+    /// See [`JpeglsEncoder::set_define_number_of_lines`].
+    pub fn define_number_of_lines(mut self, enabled: bool) -> Self {
+        self.define_number_of_lines = enabled;
+        self
+    }
+
+    /// See [`JpeglsEncoder::set_restart_interval`].
+    pub fn restart_interval(mut self, restart_interval: u16) -> Self {
+        self.restart_interval = restart_interval;
+        self
+    }
+
+    /// See [`JpeglsEncoder::set_thread_count`].
+    pub fn thread_count(mut self, thread_count: usize) -> Self {
+        self.thread_count = thread_count;
+        self
+    }
+
+    /// See [`JpeglsEncoder::set_auto_restart_interval`].
+    pub fn auto_restart_interval(mut self, enabled: bool) -> Self {
+        self.auto_restart_interval = enabled;
+        self
+    }
+
+    /// See [`JpeglsEncoder::set_encode_deterministic`].
+    pub fn encode_deterministic(mut self, enabled: bool) -> Self {
+        self.deterministic = enabled;
+        self
+    }
+
+    /// Writes a SPIFF header giving the pixel density in dots per inch, and grayscale or
+    /// RGB as the color space of 1 or 3 components.
+    pub fn resolution(mut self, resolution: Resolution) -> Self {
+        self.resolution = Some(resolution);
+        self
+    }
+
+    /// [`JpeglsEncoder::estimated_destination_size`] of the configured frame and interleave
+    /// mode, plus the restart markers and the padding in front of them.
+    pub fn estimated_destination_size(&self) -> usize {
+        let size =
+            JpeglsEncoder::estimated_destination_size(&self.frame_info, self.interleave_mode);
+        let scans = match self.interleave_mode {
+            InterleaveMode::None => self.frame_info.component_count.max(0) as usize,
+            _ => 1,
+        };
+        let restart_markers = match self.restart_interval {
+            0 => 0,
+            interval => scans * (self.frame_info.height as usize).div_ceil(interval as usize) * 3,
+        };
+        size + restart_markers
+    }
+
+    /// Checks the configuration and encodes `source`, pixel-interleaved rows as for
+    /// [`JpeglsEncoder::encode`], into a buffer that grows as the stream is written.
+    ///
+    /// ```rust
+    /// use jpegexp_rs::jpegls::JpeglsEncoderBuilder;
+    /// use jpegexp_rs::FrameInfo;
+    ///
+    /// let frame_info = FrameInfo { width: 4, height: 4, bits_per_sample: 8, component_count: 1 };
+    /// let encoded = JpeglsEncoderBuilder::new(frame_info).encode_to_vec(&[7u8; 16]).unwrap();
+    /// assert_eq!(&encoded[..2], &[0xFF, 0xD8]);
+    /// ```
+    pub fn encode_to_vec(&self, source: &[u8]) -> Result<Vec<u8>, JpeglsError> {
+        let mut stream = Vec::new();
+        self.build_with_sink(&mut stream)?.encode(source)?;
+        Ok(stream)
+    }
+
+    /// Checks the configuration and returns an encoder writing to `destination`.
+    ///
+    /// Returns [`JpeglsError::InvalidArgumentWidth`] or
+    /// [`JpeglsError::InvalidArgumentHeight`] for dimensions outside 1-65535,
+    /// [`JpeglsError::InvalidArgumentComponentCount`] for more than 255 components,
+    /// [`JpeglsError::InvalidArgumentBitsPerSample`] outside 2-16 bits,
+    /// [`JpeglsError::InvalidArgumentInterleaveMode`] for a line or sample interleaved scan
+    /// of more than 4 components, [`JpeglsError::InvalidArgumentNearLossless`],
+    /// [`JpeglsError::InvalidArgumentJpeglsPcParameters`], and
+    /// [`JpeglsError::InvalidArgumentColorTransformation`] for a transformation of anything
+    /// but 3 components of 8 or 16 bits.
+    pub fn build(self, destination: &mut [u8]) -> Result<JpeglsEncoder<'_>, JpeglsError> {
+        self.build_with_sink(SliceSink::new(destination))
+    }
+
+    /// Like [`build`](Self::build), for an encoder that writes to `sink`.
+    pub fn build_with_sink<'a, S: Sink>(
+        self,
+        sink: S,
+    ) -> Result<JpeglsEncoder<'a, S>, JpeglsError> {
+        self.validate()?;
+        let mut encoder = JpeglsEncoder::with_sink(sink);
+        encoder.frame_info = Some(self.frame_info);
+        encoder.near_lossless = self.near_lossless;
+        encoder.interleave_mode = self.interleave_mode;
+        encoder.pc_parameters = self.pc_parameters;
+        encoder.color_transformation = self.color_transformation;
+        encoder.define_number_of_lines = self.define_number_of_lines;
+        encoder.restart_interval = self.restart_interval;
+        encoder.thread_count = self.thread_count;
+        encoder.auto_restart_interval = self.auto_restart_interval;
+        encoder.deterministic = self.deterministic;
+        if let Some(resolution) = self.resolution {
+            let frame_info = self.frame_info;
+            encoder.write_spiff_header(&SpiffHeader {
+                profile_id: SpiffProfileId::None,
+                component_count: frame_info.component_count,
+                height: frame_info.height,
+                width: frame_info.width,
+                color_space: match frame_info.component_count {
+                    1 => SpiffColorSpace::Grayscale,
+                    3 => SpiffColorSpace::Rgb,
+                    _ => SpiffColorSpace::None,
+                },
+                bits_per_sample: frame_info.bits_per_sample,
+                compression_type: SpiffCompressionType::JpegLs,
+                resolution_units: SpiffResolutionUnits::DotsPerInch,
+                vertical_resolution: resolution.y_dpi,
+                horizontal_resolution: resolution.x_dpi,
+            })?;
+        }
+        Ok(encoder)
+    }
+
+    fn validate(&self) -> Result<(), JpeglsError> {
+        let frame_info = &self.frame_info;
+        if !(1..=u16::MAX as u32).contains(&frame_info.width) {
+            return Err(JpeglsError::InvalidArgumentWidth);
+        }
+        if !(1..=u16::MAX as u32).contains(&frame_info.height) {
+            return Err(JpeglsError::InvalidArgumentHeight);
+        }
+        if !(1..=MAXIMUM_COMPONENT_COUNT).contains(&frame_info.component_count) {
+            return Err(JpeglsError::InvalidArgumentComponentCount);
+        }
+        if !(2..=16).contains(&frame_info.bits_per_sample) {
+            return Err(JpeglsError::InvalidArgumentBitsPerSample);
+        }
+        if self.interleave_mode != InterleaveMode::None
+            && frame_info.component_count > MAXIMUM_COMPONENT_COUNT_IN_SCAN
+        {
+            return Err(JpeglsError::InvalidArgumentInterleaveMode);
+        }
+        validate_near_lossless(self.near_lossless, frame_info.bits_per_sample)
+            .map_err(|_| JpeglsError::InvalidArgumentNearLossless)?;
+        if let Some(pc_parameters) = &self.pc_parameters {
+            validate_preset_coding_parameters(
+                pc_parameters,
+                frame_info.bits_per_sample,
+                self.near_lossless,
+            )
+            .map_err(|error| match error {
+                JpeglsError::InvalidParameterNearLossless => {
+                    JpeglsError::InvalidArgumentNearLossless
+                }
+                _ => JpeglsError::InvalidArgumentJpeglsPcParameters,
+            })?;
+        }
+        if !color_transform::is_supported(self.color_transformation, frame_info) {
+            return Err(JpeglsError::InvalidArgumentColorTransformation);
+        }
+        Ok(())
+    }
+}
+
+/// Checks NEAR against the range allowed for samples of `bits_per_sample` bits.
+pub(crate) fn validate_near_lossless(
+    near_lossless: i32,
+    bits_per_sample: i32,
+) -> Result<(), JpeglsError> {
+    if !(2..=16).contains(&bits_per_sample) {
+        return Err(JpeglsError::InvalidParameterBitsPerSample);
+    }
+    let maximum = compute_maximum_near_lossless((1 << bits_per_sample) - 1);
+    if !(0..=maximum).contains(&near_lossless) {
+        return Err(JpeglsError::InvalidParameterNearLossless);
+    }
+    Ok(())
+}
+
+/// Checks preset coding parameters against ISO/IEC 14495-1, Table C.1 and returns them with
+/// every field that was left at 0 replaced by its default value.
+pub(crate) fn validate_preset_coding_parameters(
+    pc_parameters: &JpeglsPcParameters,
+    bits_per_sample: i32,
+    near_lossless: i32,
+) -> Result<JpeglsPcParameters, JpeglsError> {
+    validate_near_lossless(near_lossless, bits_per_sample)?;
+    let maximum_component_value = (1 << bits_per_sample) - 1;
+    let maximum_sample_value = pc_parameters.maximum_sample_value;
+    if (1..=maximum_component_value).contains(&maximum_sample_value)
+        && near_lossless > compute_maximum_near_lossless(maximum_sample_value)
+    {
+        return Err(JpeglsError::InvalidParameterNearLossless);
+    }
+
+    is_valid(pc_parameters, maximum_component_value, near_lossless)
+        .map_err(|_| JpeglsError::InvalidParameterJpeglsPresetParameters)
 }
@@ -1,3 +1,7 @@
+//! Lookup table for decoding short Golomb codes a byte at a time, as CharLS does.
+
+/// A Golomb code found at the start of a byte: the mapped error value it codes and its
+/// length in bits. A `bit_count` of 0 means the code is longer than 8 bits.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct GolombCodeMatch {
     pub error_value: i16,
@@ -16,7 +20,9 @@ const fn countl_zero_u8(mut x: u8) -> i8 {
     count
 }
 
-pub const GOLOMB_LUT: [[GolombCodeMatch; 256]; 32] = {
+/// The code at the start of every byte value, for every Golomb parameter `k` below 32. The
+/// scan decoder looks codes up here before reading them bit by bit.
+pub static GOLOMB_LUT: [[GolombCodeMatch; 256]; 32] = {
     let mut lut = [[GolombCodeMatch {
         error_value: 0,
         bit_count: 0,
@@ -44,3 +50,38 @@ pub const GOLOMB_LUT: [[GolombCodeMatch; 256]; 32] = {
     }
     lut
 };
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads the Golomb code with parameter `k` at the start of `byte` bit by bit.
+    fn decode(byte: u8, k: u32) -> Option<GolombCodeMatch> {
+        let unary = byte.leading_zeros();
+        let length = unary + 1 + k;
+        if length > 8 {
+            return None;
+        }
+        let remainder = (byte as u32 >> (8 - length)) & ((1 << k) - 1);
+        Some(GolombCodeMatch {
+            error_value: ((unary << k) + remainder) as i16,
+            bit_count: length as i8,
+        })
+    }
+
+    #[test]
+    fn test_table_matches_bit_by_bit_decoding() {
+        for k in 0..32 {
+            for byte in 0..=255u8 {
+                let expected = decode(byte, k).unwrap_or(GolombCodeMatch {
+                    error_value: 0,
+                    bit_count: 0,
+                });
+                assert_eq!(
+                    GOLOMB_LUT[k as usize][byte as usize], expected,
+                    "k={k} byte={byte}"
+                );
+            }
+        }
+    }
+}
@@ -9,7 +9,10 @@
 //! This module provides:
 //! - `JpeglsEncoder`: Support for encoding images with custom LSE parameters.
 //! - `JpeglsDecoder`: Capability to decode scans with multiple interleave modes.
+//! - `JpeglsTranscoder`: Conversion between planar and line-interleaved streams.
 //! - `SPIFF`: Full support for the Still Picture Interchange File Format header.
+//! - `MappingTable`: Mapping tables, in the image stream or in an abbreviated table
+//!   specification stream that the image data refers to.
 //!
 //! ## Supported Image Types
 //!
@@ -17,54 +20,43 @@
 //! |------------|----------|----------|-------|
 //! | Grayscale 8-bit | ✅ Lossless | ✅ Lossless | Fully supported |
 //! | Grayscale 16-bit | ✅ Lossless | ✅ Lossless | Fully supported |
-//! | RGB (Sample interleave) | ❌ | ❌ | See limitations below |
-//! | RGB (Line interleave) | ❌ | ⚠️ Partial | Single-component path only |
-//! | RGB (Non-interleaved) | ❌ | ⚠️ Partial | Single-component path only |
+//! | RGB (Sample interleave) | ✅ Lossless | ✅ Lossless | Up to 4 components per scan |
+//! | RGB (Line interleave) | ✅ Lossless | ✅ Lossless | Run index kept per component |
+//! | RGB (Non-interleaved) | ✅ Lossless | ✅ Lossless | One scan per component |
+//! | RGB with HP1/HP2/HP3 transform | ✅ 8/16-bit | ✅ 8/16-bit | APP8 "mrfx" segment |
 //!
-//! ## Current Limitations
+//! ## Sample-Interleaved Scans
 //!
-//! ### Multi-component / RGB Images
+//! In sample interleave mode (`InterleaveMode::Sample`) all components of a pixel are
+//! processed together, as CharLS does with its `triplet<sample_type>`/`quad<sample_type>`
+//! types:
 //!
-//! RGB and other multi-component images are **not yet fully supported**. The main blocker
-//! is that CharLS (the reference implementation) and the JPEG-LS standard use a specialized
-//! approach for sample-interleaved multi-component images:
-//!
-//! - **Sample interleave mode** (`InterleaveMode::Sample`): Requires processing pixels as
-//!   tuples (e.g., `triplet<sample_type>` in CharLS) where all components of a pixel are
-//!   processed together. This enables cross-component prediction and context modeling.
-//!   
-//! - The current implementation processes components independently, which works for grayscale
-//!   but produces incorrect results for sample-interleaved RGB data where CharLS uses
-//!   specialized triplet prediction.
-//!
-//! ### Technical Details
-//!
-//! CharLS implements sample-interleaved processing in `process_line<strategy, triplet<sample_type>>`
-//! where each pixel's RGB components are handled as a unit. Key differences include:
-//!
-//! 1. **Prediction**: Uses component-wise median prediction within the triplet structure
-//! 2. **Context**: May use cross-component correlation for better compression
-//! 3. **Run mode**: Entire triplets must match for run encoding
-//!
-//! To add full RGB support, the encoder and decoder would need to:
-//! 1. Implement `triplet<T>` or equivalent structure for sample-interleaved processing
-//! 2. Modify `decode_sample_line` / `encode_sample_line` to handle component tuples
-//! 3. Adjust run mode detection to compare full triplets, not individual samples
+//! 1. **Context**: Each component computes its own context from its own neighbours, but all
+//!    components share one set of regular and run mode contexts.
+//! 2. **Run mode**: A run is only entered when the gradients of every component are zero,
+//!    and continues while the whole pixel matches the pixel to its left. There is a single
+//!    run index for the scan.
+//! 3. **Run interruption**: Every component of the interruption pixel is coded relative to
+//!    its Rb sample using run interruption context 0.
 
 pub mod coding_parameters;
+pub mod color_transform;
 pub mod decoder;
 pub mod encoder;
 pub mod golomb_lut;
 pub mod regular_mode_context;
 pub mod run_mode_context;
+pub(crate) mod sample_layout;
 pub mod scan_decoder;
 pub mod scan_encoder;
 pub mod traits;
+pub mod transcoder;
 pub mod validate_spiff_header;
 
 pub use coding_parameters::{CodingParameters, JpeglsPcParameters};
 pub use decoder::JpeglsDecoder;
-pub use encoder::JpeglsEncoder;
+pub use encoder::{JpeglsEncoder, JpeglsEncoderBuilder};
+pub use transcoder::JpeglsTranscoder;
 
 use crate::error::JpeglsError;
 
@@ -130,6 +122,33 @@ pub enum CompressedDataFormat {
     AbbreviatedTableSpecification = 3,
 }
 
+/// Mapping table defined by a JPEG-LS preset parameters segment (ISO/IEC 14495-1, C.2.4.1.2).
+///
+/// A scan selects a table per component; the decoded samples of that component are then
+/// indices into the table, which the application maps to the output values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MappingTable {
+    /// Table ID (1 to 255) that scans refer to.
+    pub table_id: u8,
+    /// Size in bytes of every entry (1 to 255).
+    pub entry_size: u8,
+    /// The entries, `entry_size` bytes each.
+    pub data: Vec<u8>,
+}
+
+impl MappingTable {
+    /// Number of entries in the table.
+    pub fn entry_count(&self) -> usize {
+        self.data.len() / self.entry_size.max(1) as usize
+    }
+
+    /// The bytes of entry `index`, or `None` past the end of the table.
+    pub fn entry(&self, index: usize) -> Option<&[u8]> {
+        let size = self.entry_size as usize;
+        self.data.get(index.checked_mul(size)?..)?.get(..size)
+    }
+}
+
 /// SPIFF profile identifier.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SpiffProfileId {
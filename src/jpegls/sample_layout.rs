@@ -0,0 +1,53 @@
+//! Addressing of samples in the buffers handed to the scan coders.
+
+/// Describes where the sample of pixel `(x, y)` of component `c` lives in a flat buffer,
+/// so scans can be coded straight from pixel-interleaved or planar memory.
+///
+/// All distances are in samples, not bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleLayout {
+    /// Distance between the first samples of two consecutive rows.
+    pub row_stride: usize,
+    /// Distance between two horizontally adjacent samples of one component.
+    pub pixel_stride: usize,
+    /// Distance between the first samples of two consecutive components.
+    pub component_stride: usize,
+}
+
+impl SampleLayout {
+    /// All components of a pixel are adjacent (RGBRGB...).
+    pub fn interleaved(component_count: usize, row_stride: usize) -> Self {
+        Self {
+            row_stride,
+            pixel_stride: component_count,
+            component_stride: 1,
+        }
+    }
+
+    /// Each component is a separate `width` x `height` plane.
+    pub fn planar(width: usize, height: usize) -> Self {
+        Self::planar_with_stride(width, height)
+    }
+
+    /// Each component is a separate plane of `height` rows that are `row_stride` apart.
+    pub fn planar_with_stride(row_stride: usize, height: usize) -> Self {
+        Self {
+            row_stride,
+            pixel_stride: 1,
+            component_stride: row_stride * height,
+        }
+    }
+
+    #[inline]
+    pub fn index(&self, x: usize, y: usize, component: usize) -> usize {
+        y * self.row_stride + x * self.pixel_stride + component * self.component_stride
+    }
+
+    /// Number of samples a buffer needs to hold the given image.
+    pub fn required_len(&self, width: usize, height: usize, component_count: usize) -> usize {
+        if width == 0 || height == 0 || component_count == 0 {
+            return 0;
+        }
+        self.index(width - 1, height - 1, component_count - 1) + 1
+    }
+}
@@ -1,17 +1,22 @@
 use crate::FrameInfo;
+use crate::constants::MAXIMUM_COMPONENT_COUNT_IN_SCAN;
 use crate::error::JpeglsError;
-use crate::jpeg_marker_code::JPEG_MARKER_START_BYTE;
+use crate::jpeg_marker_code::{
+    JPEG_MARKER_START_BYTE, JPEG_RESTART_MARKER_BASE, JPEG_RESTART_MARKER_RANGE,
+};
+use crate::jpegls::golomb_lut::GOLOMB_LUT;
 use crate::jpegls::regular_mode_context::RegularModeContext;
 use crate::jpegls::run_mode_context::RunModeContext;
+use crate::jpegls::sample_layout::SampleLayout;
+use crate::jpegls::traits::CodingTraits;
 use crate::jpegls::{CodingParameters, InterleaveMode, JpeglsPcParameters};
+use crate::mem_profiling::{track_elements, BufferGuard};
 
-// Debug logging support
+// Per-sample tracing through the `log` crate; compiled out of release builds.
 #[cfg(debug_assertions)]
 macro_rules! debug_log {
     ($($arg:tt)*) => {
-        if std::env::var("JPEGLS_DEBUG").is_ok() {
-            eprintln!($($arg)*);
-        }
+        log::trace!($($arg)*)
     };
 }
 
@@ -35,15 +40,17 @@ pub struct ScanDecoder<'a> {
 
     // Scan state
     run_index: usize,
+    /// Lines of every component of the scan decoded so far.
+    lines_decoded: usize,
 
     // LUTs and Constants
-    t1: i32,
-    t2: i32,
-    t3: i32,
     reset_threshold: i32,
     _limit: i32,
-    _quantized_bits_per_sample: i32,
-    _quantization_lut: Vec<i32>,
+    traits: CodingTraits,
+    /// Quantized gradient of every difference `-MAXVAL..=MAXVAL`, offset by MAXVAL.
+    quantization_lut: Vec<i8>,
+    _quantization_lut_memory: BufferGuard,
+    _contexts_memory: BufferGuard,
     
     // Debug tracking
     #[cfg(debug_assertions)]
@@ -59,14 +66,12 @@ impl<'a> ScanDecoder<'a> {
         coding_parameters: CodingParameters,
         source: &'a [u8],
     ) -> Result<Self, JpeglsError> {
-        let (t1, t2, t3, reset) = (
-            pc_parameters.threshold1,
-            pc_parameters.threshold2,
-            pc_parameters.threshold3,
-            pc_parameters.reset_value,
+        let traits = CodingTraits::new(
+            pc_parameters.maximum_sample_value,
+            coding_parameters.near_lossless,
         );
-
-        let range = pc_parameters.maximum_sample_value + 1;
+        let range = traits.range;
+        let quantization_lut = quantization_lut(&pc_parameters, coding_parameters.near_lossless);
         let regular_mode_contexts = vec![RegularModeContext::new(range); 365];
         let run_mode_contexts = vec![RunModeContext::new(0, range), RunModeContext::new(1, range)];
 
@@ -81,13 +86,13 @@ impl<'a> ScanDecoder<'a> {
             regular_mode_contexts,
             run_mode_contexts,
             run_index: 0,
-            t1,
-            t2,
-            t3,
-            reset_threshold: reset,
+            lines_decoded: 0,
+            reset_threshold: pc_parameters.reset_value,
             _limit: coding_parameters.limit,
-            _quantized_bits_per_sample: frame_info.bits_per_sample,
-            _quantization_lut: Vec::new(),
+            traits,
+            _quantization_lut_memory: track_elements::<i8>(quantization_lut.len()),
+            quantization_lut,
+            _contexts_memory: track_elements::<RegularModeContext>(365),
             #[cfg(debug_assertions)]
             bits_consumed: 0,
             #[cfg(debug_assertions)]
@@ -107,83 +112,269 @@ impl<'a> ScanDecoder<'a> {
         Ok(decoder)
     }
 
+    /// Decodes the scan into `destination`, writing the components of the scan pixel
+    /// interleaved with `stride` bytes per row. Returns the number of bytes consumed from the
+    /// source, up to the marker that follows the entropy coded data.
     pub fn decode_scan(
         &mut self,
         destination: &mut [u8],
         stride: usize,
     ) -> Result<usize, JpeglsError> {
+        let components = self.components_in_scan()?;
+        self.decode_scan_into(destination, stride, components, 0)
+    }
+
+    /// Like [`decode_scan`](Self::decode_scan), but for a destination whose pixels hold
+    /// `pixel_components` samples, of which this scan fills the ones starting at
+    /// `first_component`. Used to assemble the separate scans of a non-interleaved image.
+    pub(crate) fn decode_scan_into(
+        &mut self,
+        destination: &mut [u8],
+        stride: usize,
+        pixel_components: usize,
+        first_component: usize,
+    ) -> Result<usize, JpeglsError> {
+        if first_component + self.components_in_scan()? > pixel_components {
+            return Err(JpeglsError::InvalidData);
+        }
+
         let bit_depth = self.frame_info.bits_per_sample;
         if bit_depth <= 8 {
-            self.decode_scan_typed::<u8>(destination, stride)
+            self.decode_scan_typed::<u8>(destination, stride, pixel_components, first_component)
         } else if bit_depth <= 16 {
-            self.decode_scan_typed::<u16>(destination, stride)
+            self.decode_scan_typed::<u16>(destination, stride, pixel_components, first_component)
         } else {
             Err(JpeglsError::ParameterValueNotSupported)
         }
     }
 
+    /// Decodes the scan straight into a typed sample buffer. Component `c` of the scan is
+    /// stored at `layout.index(x, y, c)`.
+    pub(crate) fn decode_samples<T: crate::jpegls::traits::JpeglsSample>(
+        &mut self,
+        destination: &mut [T],
+        layout: SampleLayout,
+    ) -> Result<usize, JpeglsError> {
+        let width = self.frame_info.width as usize;
+        let height = self.frame_info.height as usize;
+        let components = self.components_in_scan()?;
+        let needed = layout.required_len(width, height, components);
+        if destination.len() < needed {
+            return Err(JpeglsError::DestinationTooSmall { needed });
+        }
+
+        self.decode_lines::<T, _>(|line, first_component, pixels, components| {
+            let start = layout.index(0, line, first_component);
+            if layout.pixel_stride == 1 && components == 1 {
+                // A line of a plane is stored as is.
+                destination[start..start + pixels.len()].copy_from_slice(pixels);
+                return Ok(());
+            }
+            for (x, pixel) in pixels.chunks_exact(components).enumerate() {
+                let index = start + x * layout.pixel_stride;
+                for (c, &sample) in pixel.iter().enumerate() {
+                    destination[index + c * layout.component_stride] = sample;
+                }
+            }
+            Ok(())
+        })?;
+        self.end_scan()?;
+        Ok(self.position)
+    }
+
     fn decode_scan_typed<T: crate::jpegls::traits::JpeglsSample>(
         &mut self,
         destination: &mut [u8],
         stride: usize,
+        components: usize,
+        scan_offset: usize,
     ) -> Result<usize, JpeglsError> {
-        self.decode_lines::<T>(destination, stride)?;
+        let width = self.frame_info.width as usize;
+        let height = self.frame_info.height as usize;
+        let sample_size = std::mem::size_of::<T>();
+        let row_bytes = width * components * sample_size;
+        let needed = match height {
+            0 => 0,
+            _ => (height - 1) * stride + row_bytes,
+        };
+        if destination.len() < needed {
+            return Err(JpeglsError::DestinationTooSmall { needed });
+        }
+
+        self.decode_lines::<T, _>(|line, first_component, pixels, pixel_components| {
+            let row = &mut destination[line * stride..line * stride + row_bytes];
+            store_line(
+                row,
+                pixels,
+                pixel_components,
+                components,
+                scan_offset + first_component,
+            );
+            Ok(())
+        })?;
         self.end_scan()?;
         Ok(self.position)
     }
 
-    fn decode_lines<T: crate::jpegls::traits::JpeglsSample>(
+    /// Decodes a scan that holds every component of the image into `batch`, as many
+    /// pixel-interleaved rows at a time as it has room for. Every time the batch is full,
+    /// and after the last row, `handler` gets the index of the first row in the batch and
+    /// the rows; an error it returns stops decoding. Returns the number of bytes consumed
+    /// from the source, as [`decode_scan`](Self::decode_scan) does.
+    pub(crate) fn decode_row_batches(
         &mut self,
-        destination: &mut [u8],
-        stride: usize,
-    ) -> Result<(), JpeglsError> {
+        batch: &mut [u8],
+        handler: impl FnMut(usize, &mut [u8]) -> Result<(), JpeglsError>,
+    ) -> Result<usize, JpeglsError> {
+        let bit_depth = self.frame_info.bits_per_sample;
+        if bit_depth <= 8 {
+            self.decode_row_batches_typed::<u8>(batch, handler)
+        } else if bit_depth <= 16 {
+            self.decode_row_batches_typed::<u16>(batch, handler)
+        } else {
+            Err(JpeglsError::ParameterValueNotSupported)
+        }
+    }
+
+    fn decode_row_batches_typed<T: crate::jpegls::traits::JpeglsSample>(
+        &mut self,
+        batch: &mut [u8],
+        mut handler: impl FnMut(usize, &mut [u8]) -> Result<(), JpeglsError>,
+    ) -> Result<usize, JpeglsError> {
+        let components = self.components_in_scan()?;
+        let height = self.frame_info.height as usize;
+        let row_bytes = self.frame_info.width as usize * components * std::mem::size_of::<T>();
+        let rows = batch.len() / row_bytes.max(1);
+        if rows == 0 {
+            return Err(JpeglsError::DestinationTooSmall { needed: row_bytes });
+        }
+
+        self.decode_lines::<T, _>(|line, first_component, pixels, pixel_components| {
+            let filled = line % rows + 1;
+            let row = &mut batch[(filled - 1) * row_bytes..filled * row_bytes];
+            store_line(row, pixels, pixel_components, components, first_component);
+            let row_complete = first_component + pixel_components == components;
+            if row_complete && (filled == rows || line + 1 == height) {
+                handler(line + 1 - filled, &mut batch[..filled * row_bytes])?;
+            }
+            Ok(())
+        })?;
+        self.end_scan()?;
+        Ok(self.position)
+    }
+
+    /// Lines of every component of the scan that were decoded, e.g. before the data ran out.
+    pub(crate) fn lines_decoded(&self) -> usize {
+        self.lines_decoded
+    }
+
+    /// Offset in the source of the next byte to read.
+    pub(crate) fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Number of components coded in this scan.
+    fn components_in_scan(&self) -> Result<usize, JpeglsError> {
+        match self.coding_parameters.interleave_mode {
+            InterleaveMode::None => Ok(1),
+            InterleaveMode::Line | InterleaveMode::Sample => {
+                Ok(self.frame_info.component_count as usize)
+            }
+        }
+    }
+
+    /// Decodes all lines of the scan. Every decoded line is handed to `sink` as
+    /// `(line, first_component, pixels, components_per_pixel)`: non-interleaved and
+    /// line-interleaved scans deliver one component at a time, sample-interleaved scans
+    /// deliver all components of the line with the samples of a pixel adjacent. An error
+    /// returned by `sink` stops decoding.
+    fn decode_lines<T, F>(&mut self, sink: F) -> Result<(), JpeglsError>
+    where
+        T: crate::jpegls::traits::JpeglsSample,
+        F: FnMut(usize, usize, &[T], usize) -> Result<(), JpeglsError>,
+    {
+        let components = self.components_in_scan()?;
+        if self.coding_parameters.interleave_mode == InterleaveMode::Sample {
+            if components > MAXIMUM_COMPONENT_COUNT_IN_SCAN as usize {
+                return Err(JpeglsError::InvalidData);
+            }
+            self.decode_lines_sample_interleaved(components, sink)
+        } else {
+            self.decode_lines_by_component(components, sink)
+        }
+    }
+
+    /// Decodes a non-interleaved scan (one component) or a line-interleaved scan (one line
+    /// of every component in turn).
+    fn decode_lines_by_component<T, F>(
+        &mut self,
+        components: usize,
+        mut sink: F,
+    ) -> Result<(), JpeglsError>
+    where
+        T: crate::jpegls::traits::JpeglsSample,
+        F: FnMut(usize, usize, &[T], usize) -> Result<(), JpeglsError>,
+    {
         let width = self.frame_info.width as usize;
         let height = self.frame_info.height as usize;
         let pixel_stride = width + 2;
-        let components = if self.coding_parameters.interleave_mode == InterleaveMode::Line {
-            self.frame_info.component_count as usize
-        } else {
-            1
-        };
 
         debug_log!("=== Starting decode_lines ===");
         debug_log!("  Image: {}x{}, components: {}, pixel_stride: {}", 
                   width, height, components, pixel_stride);
 
-        // Initialize line buffer with 2 lines
-        // Per ITU-T T.87 specification, boundary pixels (outside the image) are
-        // initialized to ZERO for unsigned samples. This is required for proper
-        // synchronization between encoder and decoder.
+        // Two lines (previous and current) per component. Per ITU-T T.87 specification,
+        // boundary pixels (outside the image) are initialized to ZERO for unsigned samples.
+        // This is required for proper synchronization between encoder and decoder.
         let init_value = T::from_i32(0);
         let mut line_buffer: Vec<T> = vec![init_value; components * pixel_stride * 2];
+        let _line_buffer_memory = track_elements::<T>(line_buffer.len());
+
+        // The contexts are shared by all components of a line-interleaved scan, but each
+        // component keeps its own run index (ITU-T T.87, A.2.1).
+        let mut run_index = vec![0usize; components];
 
         for line in 0..height {
+            if self.is_restart_line(line) {
+                self.read_restart_marker(line)?;
+                line_buffer.fill(init_value);
+                run_index.fill(0);
+            }
+
             #[cfg(debug_assertions)]
             let line_start_pos = self.position;
             #[cfg(debug_assertions)]
             let line_start_bits = self.bits_consumed;
             
-            let (prev_line_slice, curr_line_slice) =
-                line_buffer.split_at_mut(components * pixel_stride);
-            let (prev, curr) = if (line & 1) == 1 {
-                (curr_line_slice, prev_line_slice)
+            let (first, second) = line_buffer.split_at_mut(components * pixel_stride);
+            let (prev_lines, curr_lines) = if (line & 1) == 1 {
+                (second, first)
             } else {
-                (prev_line_slice, curr_line_slice)
+                (first, second)
             };
 
-            let prev_line = &mut prev[0..pixel_stride];
-            let curr_line = &mut curr[0..pixel_stride];
+            for (component, run_index) in run_index.iter_mut().enumerate() {
+                let offset = component * pixel_stride;
+                let prev_line = &mut prev_lines[offset..offset + pixel_stride];
+                let curr_line = &mut curr_lines[offset..offset + pixel_stride];
+
+                // Initialize edge pixels per CharLS/ITU-T.87
+                // Left edge: current_line[0] = previous_line[1]
+                // Right edge: previous_line[width+1] = previous_line[width]
+                curr_line[0] = prev_line[1];
+                prev_line[width + 1] = prev_line[width];  // Right edge extension
+
+                self.run_index = *run_index;
+                self.decode_sample_line::<T, 1>(prev_line, curr_line, width)?;
+                *run_index = self.run_index;
 
-            // Initialize edge pixels per CharLS/ITU-T.87
-            // Left edge: current_line[0] = previous_line[1]
-            // Right edge: previous_line[width+1] = previous_line[width]
-            curr_line[0] = prev_line[1];
-            prev_line[width + 1] = prev_line[width];  // Right edge extension
-            self.decode_sample_line::<T>(prev_line, curr_line, width, line == 0)?;
+                sink(line, component, &curr_line[1..=width], 1)?;
+            }
+            self.lines_decoded = line + 1;
             
             #[cfg(debug_assertions)]
             {
-                self.pixels_decoded += width;
+                self.pixels_decoded += width * components;
                 let bits_for_line = self.bits_consumed - line_start_bits;
                 if line % 8 == 0 || line == height - 1 {
                     debug_log!("  Line {}/{}: pos {} → {}, {} bits consumed (total: {}), {} pixels decoded", 
@@ -191,101 +382,115 @@ impl<'a> ScanDecoder<'a> {
                               bits_for_line, self.bits_consumed, self.pixels_decoded);
                 }
             }
+        }
+        Ok(())
+    }
+
+    /// Decodes a sample-interleaved scan, in which all components of a pixel are coded
+    /// together and share a single run index.
+    fn decode_lines_sample_interleaved<T, F>(
+        &mut self,
+        components: usize,
+        mut sink: F,
+    ) -> Result<(), JpeglsError>
+    where
+        T: crate::jpegls::traits::JpeglsSample,
+        F: FnMut(usize, usize, &[T], usize) -> Result<(), JpeglsError>,
+    {
+        let width = self.frame_info.width as usize;
+        let height = self.frame_info.height as usize;
+        let line_length = (width + 2) * components;
+        let mut line_buffer: Vec<T> = vec![T::from_i32(0); line_length * 2];
+        let _line_buffer_memory = track_elements::<T>(line_buffer.len());
 
-            // Copy decoded samples from curr_line to destination
-            // curr_line has decoded samples at indices 1..=width
-            // TODO: This implementation needs review for multi-component/interleaved modes
-            // Currently assumes components=1 (grayscale/planar mode)
-            // Verify this assumption
-            if components != 1 {
-                // Multi-component handling not fully implemented in this code path
-                // For now, only single component (grayscale) is properly supported
-                // Multi-component images should use InterleaveMode::Line or be split into planar scans
-                return Err(JpeglsError::InvalidOperation);
+        for line in 0..height {
+            if self.is_restart_line(line) {
+                self.read_restart_marker(line)?;
+                line_buffer.fill(T::from_i32(0));
             }
-            
-            let dest_start = line * stride;
-            let dest_end = dest_start + width * components * std::mem::size_of::<T>();
-            let destination_row = &mut destination[dest_start..dest_end];
-            
-            // Convert T samples to bytes and write to destination
-            // For grayscale: pixel_stride = width + 2, so curr_line[1..=width] accesses indices 1 through width
-            // The slice has exactly 'width' elements starting at index 1
-            // We need curr_line.len() >= width + 1 to access curr_line[width]
-            if curr_line.len() < width + 1 {
-                return Err(JpeglsError::InvalidData);
+
+            let (first, second) = line_buffer.split_at_mut(line_length);
+            let (prev, curr) = if (line & 1) == 1 {
+                (second, first)
+            } else {
+                (first, second)
+            };
+
+            // Same edge initialization as above, applied to whole pixels.
+            let last = width * components;
+            prev.copy_within(last..last + components, last + components);
+            curr[..components].copy_from_slice(&prev[components..2 * components]);
+
+            match components {
+                1 => self.decode_sample_line::<T, 1>(prev, curr, width)?,
+                2 => self.decode_sample_line::<T, 2>(prev, curr, width)?,
+                3 => self.decode_sample_line::<T, 3>(prev, curr, width)?,
+                _ => self.decode_sample_line::<T, 4>(prev, curr, width)?,
             }
-            let samples_slice = &curr_line[1..=width];
-            let bytes_ptr = samples_slice.as_ptr() as *const u8;
-            let bytes_len = width * std::mem::size_of::<T>();
-            // SAFETY: We're converting T samples to bytes. This assumes T is a simple type (u8/u16)
-            // as guaranteed by the JpeglsSample trait which is only implemented for u8 and u16.
-            // These types have no padding and can be safely reinterpreted as bytes.
-            // The destination buffer is pre-allocated with sufficient size.
-            // For grayscale (components=1), we copy exactly width*sizeof(T) bytes.
-            unsafe {
-                std::ptr::copy_nonoverlapping(
-                    bytes_ptr,
-                    destination_row.as_mut_ptr(),
-                    bytes_len,
-                );
+
+            #[cfg(debug_assertions)]
+            {
+                self.pixels_decoded += width * components;
             }
+
+            sink(line, 0, &curr[components..(width + 1) * components], components)?;
+            self.lines_decoded = line + 1;
         }
         Ok(())
     }
 
-    fn decode_sample_line<T: crate::jpegls::traits::JpeglsSample>(
+    /// Decodes one line of `COMPONENTS` samples per pixel, compiled once for every count a
+    /// scan can have. Pixel `x` (0-based) of component `c` is stored at
+    /// `(x + 1) * COMPONENTS + c` in both line buffers; the edge pixels must be initialized.
+    fn decode_sample_line<T: crate::jpegls::traits::JpeglsSample, const COMPONENTS: usize>(
         &mut self,
-        prev_line: &mut [T],
+        prev_line: &[T],
         curr_line: &mut [T],
         width: usize,
-        _is_first_line: bool,
     ) -> Result<(), JpeglsError> {
-        let mut index = 1;
-        let mut rb = prev_line[0].to_i32();
-        let mut rd = prev_line[1].to_i32();
-
-        while index <= width {
-            let ra = curr_line[index - 1].to_i32();
-            let rc = rb;
-            rb = rd;
-            rd = prev_line[index + 1].to_i32();
-
-            let d1 = rd - rb;
-            let d2 = rb - rc;
-            let d3 = rc - ra;
-
-            let q1 = self.quantize_gradient(d1);
-            let q2 = self.quantize_gradient(d2);
-            let q3 = self.quantize_gradient(d3);
-
-            let qs = self.compute_context_id(q1, q2, q3);
-
-            // Per CharLS: use run mode when qs == 0, regular mode otherwise.
-            // No special case for first pixel - CharLS always uses run mode when qs=0.
-            if qs != 0 {
-                debug_log!("    Regular mode: index={}, qs={}", index, qs);
-                let predicted = self.compute_predicted_value(ra, rb, rc);
-                let error_value = self.decode_regular::<T>(qs, predicted)?;
-                curr_line[index] = T::from_i32(error_value);
-                index += 1;
+        let components = COMPONENTS;
+        let mut qs = [0i32; COMPONENTS];
+        let mut pixel_idx = 0;
+
+        while pixel_idx < width {
+            let base = (pixel_idx + 1) * components;
+            for (c, qs) in qs.iter_mut().enumerate() {
+                let idx = base + c;
+                let ra = curr_line[idx - components].to_i32();
+                let rc = prev_line[idx - components].to_i32();
+                let rb = prev_line[idx].to_i32();
+                let rd = prev_line[idx + components].to_i32();
+
+                *qs = self.compute_context_id(
+                    self.quantize_gradient(rd - rb),
+                    self.quantize_gradient(rb - rc),
+                    self.quantize_gradient(rc - ra),
+                );
+            }
+
+            // Per CharLS: use run mode when qs == 0 for every component of the pixel,
+            // regular mode otherwise. There is no special case for the first pixel.
+            if qs.iter().all(|&q| q == 0) {
+                debug_log!("    Run mode: pixel={}", pixel_idx);
+                pixel_idx +=
+                    self.decode_run_mode::<T>(pixel_idx, prev_line, curr_line, width, components)?;
             } else {
-                debug_log!("    Run mode: index={}", index);
-                index += self.decode_run_mode::<T>(index, prev_line, curr_line, width)?;
-                if index <= width {
-                    rb = prev_line[index - 1].to_i32();
-                    rd = prev_line[index].to_i32();
+                for (c, &qs) in qs.iter().enumerate() {
+                    let idx = base + c;
+                    let ra = curr_line[idx - components].to_i32();
+                    let rc = prev_line[idx - components].to_i32();
+                    let rb = prev_line[idx].to_i32();
+                    debug_log!("    Regular mode: pixel={}, component={}, qs={}", pixel_idx, c, qs);
+                    let predicted = self.compute_predicted_value(ra, rb, rc);
+                    curr_line[idx] = T::from_i32(self.decode_regular(qs, predicted)?);
                 }
+                pixel_idx += 1;
             }
         }
         Ok(())
     }
 
-    fn decode_regular<T: crate::jpegls::traits::JpeglsSample>(
-        &mut self,
-        qs: i32,
-        predicted: i32,
-    ) -> Result<i32, JpeglsError> {
+    fn decode_regular(&mut self, qs: i32, predicted: i32) -> Result<i32, JpeglsError> {
         let sign = Self::bit_wise_sign(qs);
         let ctx_index = Self::apply_sign_for_index(qs, sign);
 
@@ -301,7 +506,7 @@ impl<'a> ScanDecoder<'a> {
 
         // Apply context bias C to prediction (per CharLS/ITU-T.87)
         // corrected_prediction = correct_prediction(predicted + apply_sign(C, sign))
-        let corrected_prediction = T::correct_prediction(predicted + Self::apply_sign(context_c, sign));
+        let corrected_prediction = self.traits.correct_prediction(predicted + Self::apply_sign(context_c, sign));
 
         let map_val = self.decode_mapped_error_value(k)?;
         let mut error_value = self.unmap_error_value(map_val);
@@ -316,7 +521,7 @@ impl<'a> ScanDecoder<'a> {
         }
 
         error_value = Self::apply_sign(error_value, sign);
-        let reconstructed = T::compute_reconstructed_sample(corrected_prediction, error_value);
+        let reconstructed = self.traits.compute_reconstructed_sample(corrected_prediction, error_value);
         debug_log!("      Reconstructed: predicted={}, corrected={}, error={}, result={}", 
                   predicted, corrected_prediction, error_value, reconstructed);
         Ok(reconstructed)
@@ -326,38 +531,94 @@ impl<'a> ScanDecoder<'a> {
         self.decode_mapped_error_value_with_limit(k, self._limit)
     }
 
+    #[inline]
     fn decode_mapped_error_value_with_limit(&mut self, k: i32, limit: i32) -> Result<i32, JpeglsError> {
-        let mut value = 0;
-        let mut bit_count = 0;
-        
         // Limited-length Golomb code threshold
-        let qbpp = self._quantized_bits_per_sample;
-        let limit_threshold = limit - qbpp - 1;
+        let limit_threshold = limit - self.traits.quantized_bits_per_sample - 1;
 
         debug_log!("      decode_mapped_error_value: k={}, cache=0x{:016X}, valid_bits={}, pos={}, limit_threshold={}", 
                   k, self.read_cache, self.valid_bits, self.position, limit_threshold);
 
-        // Read unary code (count zeros until we hit a 1)
-        while self.peek_bits(1)? == 0 {
-            value += 1;
-            bit_count += 1;
-            self.skip_bits(1)?;
-            
+        // Codes of up to 8 bits are looked up from the next byte (per CharLS).
+        if self.valid_bits < 8 {
+            self.fill_read_cache()?;
+        }
+        if self.valid_bits >= 8 {
+            if let Some(codes) = GOLOMB_LUT.get(k as usize) {
+                let code = codes[self.read_cache >> (usize::BITS - 8)];
+                if code.bit_count != 0 && (code.error_value as i32 >> k) < limit_threshold {
+                    self.skip_bits(code.bit_count as i32)?;
+                    debug_log!(
+                        "    Golomb decode (LUT): k={}, result={}",
+                        k,
+                        code.error_value
+                    );
+                    return Ok(code.error_value as i32);
+                }
+            }
+        }
+        self.decode_long_mapped_error_value(k, limit_threshold)
+    }
+
+    /// Decodes a Golomb code too long for [`GOLOMB_LUT`], or an escape sequence.
+    fn decode_long_mapped_error_value(
+        &mut self,
+        k: i32,
+        limit_threshold: i32,
+    ) -> Result<i32, JpeglsError> {
+        let qbpp = self.traits.quantized_bits_per_sample;
+
+        // A code that is in the cache whole is read in one go.
+        if self.valid_bits < 32 {
+            self.fill_read_cache()?;
+        }
+        let zeros = self.read_cache.leading_zeros() as i32;
+        let length = zeros + 1 + k;
+        if zeros < limit_threshold && length <= self.valid_bits {
+            let remainder = match k {
+                0 => 0,
+                _ => ((self.read_cache << (zeros + 1)) >> (usize::BITS as i32 - k)) as i32,
+            };
+            self.skip_bits(length)?;
+            debug_log!(
+                "    Golomb decode: k={}, unary={}, remainder={}",
+                k,
+                zeros,
+                remainder
+            );
+            return Ok((zeros << k) | remainder);
+        }
+
+        // Read unary code (count zeros until we hit a 1), as many at a time as the cache
+        // holds. The first zero past the limit threshold starts an escape sequence.
+        let threshold = limit_threshold.max(1);
+        let mut value = 0;
+        loop {
+            if self.valid_bits <= 0 {
+                self.fill_read_cache()?;
+                if self.valid_bits <= 0 {
+                    return Err(JpeglsError::InvalidData);
+                }
+            }
+            let zeros = (self.read_cache.leading_zeros() as i32)
+                .min(self.valid_bits)
+                .min(threshold - value);
+            self.skip_bits(zeros)?;
+            value += zeros;
+
             // Check if we've reached the limit threshold (escape mode)
-            if bit_count >= limit_threshold {
+            if value == threshold {
                 // This is an escape sequence - read the terminating 1 and then qbpp bits
                 // Per CharLS: encoder writes (mapped_error - 1), decoder reads value + 1
                 self.skip_bits(1)?;  // Skip the terminating 1
                 let escape_value = self.read_bits(qbpp)?;
-                value = escape_value + 1;  // CharLS encodes as (MErrval - 1)
                 debug_log!("    Golomb decode (escape): unary={}, escape_value={}, result={}", 
-                          bit_count, escape_value, value);
-                return Ok(value);
+                          value, escape_value, escape_value + 1);
+                return Ok(escape_value + 1);  // CharLS encodes as (MErrval - 1)
             }
-            
-            if bit_count > 32 {
-                debug_log!("    Golomb: unary code too long (>32 zeros)");
-                return Err(JpeglsError::InvalidData);
+            // Zeros up to the end of the cache may go on past it.
+            if self.valid_bits > 0 {
+                break;
             }
         }
         self.skip_bits(1)?;  // Skip the terminating 1
@@ -365,11 +626,11 @@ impl<'a> ScanDecoder<'a> {
         // Read fixed-length remainder
         if k > 0 {
             let remainder = self.read_bits(k)?;
-            value = (value << k) | remainder;
             debug_log!("    Golomb decode: k={}, unary={}, remainder={}, result={}", 
-                      k, bit_count, remainder, value);
+                      k, value, remainder, (value << k) | remainder);
+            value = (value << k) | remainder;
         } else {
-            debug_log!("    Golomb decode: k=0, unary={}, result={}", bit_count, value);
+            debug_log!("    Golomb decode: k=0, unary={}, result={}", value, value);
         }
         
         Ok(value)
@@ -383,11 +644,18 @@ impl<'a> ScanDecoder<'a> {
         }
     }
 
-    #[allow(dead_code)]
+    /// Moves `position` to the marker that terminates the entropy coded data: a 0xFF followed
+    /// by a byte with the high bit set (a 0xFF followed by a clear bit is bit stuffing).
     fn find_jpeg_marker_start_byte(&mut self) {
-        while self.position < self.source.len()
-            && self.source[self.position] != JPEG_MARKER_START_BYTE
-        {
+        while self.position < self.source.len() {
+            if self.source[self.position] == JPEG_MARKER_START_BYTE
+                && self
+                    .source
+                    .get(self.position + 1)
+                    .is_some_and(|&next| next & 0x80 != 0)
+            {
+                break;
+            }
             self.position += 1;
         }
     }
@@ -496,35 +764,47 @@ impl<'a> ScanDecoder<'a> {
     }
 
     fn end_scan(&mut self) -> Result<(), JpeglsError> {
+        // The bits left in the cache are padding; the scan ends at the next marker.
+        self.valid_bits = 0;
+        self.read_cache = 0;
+        self.find_jpeg_marker_start_byte();
         Ok(())
     }
 
-    fn quantize_gradient(&self, di: i32) -> i32 {
-        if di <= -self.t3 {
-            return -4;
-        }
-        if di <= -self.t2 {
-            return -3;
-        }
-        if di <= -self.t1 {
-            return -2;
-        }
-        if di < -self.coding_parameters.near_lossless {
-            return -1;
-        }
-        if di <= self.coding_parameters.near_lossless {
-            return 0;
-        }
-        if di < self.t1 {
-            return 1;
-        }
-        if di < self.t2 {
-            return 2;
+    /// Whether a restart interval ends before `line`.
+    fn is_restart_line(&self, line: usize) -> bool {
+        let interval = self.coding_parameters.restart_interval as usize;
+        interval != 0 && line != 0 && line.is_multiple_of(interval)
+    }
+
+    /// Skips the padding and the RSTm marker that end the restart interval before `line` and
+    /// resets the coding state, as the encoder did when it wrote the marker. Fill bytes
+    /// (0xFF) in front of the marker are allowed.
+    fn read_restart_marker(&mut self, line: usize) -> Result<(), JpeglsError> {
+        self.end_scan()?;
+        let mut marker_position = self.position + 1;
+        while self.source.get(marker_position) == Some(&JPEG_MARKER_START_BYTE) {
+            marker_position += 1;
         }
-        if di < self.t3 {
-            return 3;
+        let index = line / self.coding_parameters.restart_interval as usize - 1;
+        let expected =
+            JPEG_RESTART_MARKER_BASE + (index % JPEG_RESTART_MARKER_RANGE as usize) as u8;
+        if self.source.get(marker_position) != Some(&expected) {
+            return Err(JpeglsError::RestartMarkerNotFound);
         }
-        4
+        self.position = marker_position + 1;
+
+        let range = self.traits.range;
+        self.regular_mode_contexts
+            .fill(RegularModeContext::new(range));
+        self.run_mode_contexts = vec![RunModeContext::new(0, range), RunModeContext::new(1, range)];
+        self.run_index = 0;
+        self.fill_read_cache()
+    }
+
+    #[inline]
+    fn quantize_gradient(&self, di: i32) -> i32 {
+        self.quantization_lut[(di + self.traits.maximum_sample_value) as usize] as i32
     }
 
     fn compute_context_id(&self, q1: i32, q2: i32, q3: i32) -> i32 {
@@ -541,14 +821,7 @@ impl<'a> ScanDecoder<'a> {
             ra + rb - rc
         };
 
-        let max_val = (1 << self.frame_info.bits_per_sample) - 1;
-        if predicted < 0 {
-            0
-        } else if predicted > max_val {
-            max_val
-        } else {
-            predicted
-        }
+        self.traits.correct_prediction(predicted)
     }
 
     fn bit_wise_sign(val: i32) -> i32 {
@@ -563,19 +836,22 @@ impl<'a> ScanDecoder<'a> {
         crate::jpegls::traits::apply_sign_for_index(val, sign)
     }
 
+    /// Decodes a run starting at pixel `start_pixel_idx`, followed by the run interruption
+    /// pixel if the run ends before the end of the line. Returns the number of pixels decoded.
     fn decode_run_mode<T: crate::jpegls::traits::JpeglsSample>(
         &mut self,
-        start_index: usize,
+        start_pixel_idx: usize,
         prev_line: &[T],
         curr_line: &mut [T],
         width: usize,
+        components: usize,
     ) -> Result<usize, JpeglsError> {
         let mut run_length = 0;
         // pixel_count is the number of remaining pixels to potentially fill with the run
-        // This is from start_index to width (inclusive), so width - start_index + 1
-        let pixel_count = width - start_index + 1;
-        debug_log!("    decode_run_mode: start_index={}, width={}, pixel_count={}", 
-                  start_index, width, pixel_count);
+        let pixel_count = width - start_pixel_idx;
+        let start = (start_pixel_idx + 1) * components;
+        debug_log!("    decode_run_mode: start_pixel={}, width={}, pixel_count={}", 
+                  start_pixel_idx, width, pixel_count);
         
         // Decode run pixels (CharLS-compatible algorithm)
         loop {
@@ -587,27 +863,17 @@ impl<'a> ScanDecoder<'a> {
             debug_log!("      [bit {}] run_index={}, J={}, bit={}, run_length={}", 
                       bits_before, self.run_index, run_index_val, bit, run_length);
             
-            if bit == 1 {
+            let count = if bit == 1 {
                 // Full run segment
                 let max_run = 1usize << run_index_val;
                 let count = std::cmp::min(max_run, pixel_count - run_length);
                 debug_log!("      → Full run segment: max={}, count={}", max_run, count);
                 
-                // Fill pixels
-                for i in 0..count {
-                    curr_line[start_index + run_length + i] = curr_line[start_index - 1];
-                }
-                run_length += count;
-                
                 // Only increment run_index if we used the full run length
                 if count == max_run && self.run_index < 31 {
                     self.run_index += 1;
                 }
-                
-                // Break if we've filled all remaining pixels
-                if run_length == pixel_count {
-                    break;
-                }
+                count
             } else {
                 // Incomplete run - read remainder bits
                 let remainder = if run_index_val > 0 { 
@@ -616,15 +882,19 @@ impl<'a> ScanDecoder<'a> {
                     0 
                 };
                 debug_log!("      → Partial run of {} pixels", remainder);
-                
-                // Fill pixels
-                let count = std::cmp::min(remainder, pixel_count - run_length);
-                for i in 0..count {
-                    curr_line[start_index + run_length + i] = curr_line[start_index - 1];
-                }
-                run_length += count;
-                
-                // Don't decrement run_index here - will be done after interruption
+                std::cmp::min(remainder, pixel_count - run_length)
+            };
+
+            // Fill the run with copies of the pixel to the left of the run (Ra).
+            for i in 0..count * components {
+                let idx = start + run_length * components + i;
+                curr_line[idx] = curr_line[idx - components];
+            }
+            run_length += count;
+
+            // Stop after a partial segment (run_index is decremented after the interruption)
+            // or once all remaining pixels are filled.
+            if bit == 0 || run_length == pixel_count {
                 break;
             }
         }
@@ -633,12 +903,25 @@ impl<'a> ScanDecoder<'a> {
 
         // Only decode interruption if run didn't consume all remaining pixels
         if run_length < pixel_count {
-            let rb = prev_line[start_index + run_length].to_i32();
-            let ra = curr_line[start_index + run_length - 1].to_i32();
-            debug_log!("    Run interruption pixel at index {}, ra={}, rb={}", 
-                      start_index + run_length, ra, rb);
-            let x = self.decode_run_interruption_pixel::<T>(ra, rb)?;
-            curr_line[start_index + run_length] = T::from_i32(x);
+            let idx = start + run_length * components;
+            if components == 1 {
+                let rb = prev_line[idx].to_i32();
+                let ra = curr_line[idx - 1].to_i32();
+                debug_log!("    Run interruption pixel at index {}, ra={}, rb={}", idx, ra, rb);
+                let x = self.decode_run_interruption_pixel(ra, rb)?;
+                curr_line[idx] = T::from_i32(x);
+            } else {
+                // In sample interleaved mode all components of the interruption pixel are
+                // coded relative to Rb with run interruption context 0.
+                for c in 0..components {
+                    let ra = curr_line[idx - components + c].to_i32();
+                    let rb = prev_line[idx + c].to_i32();
+                    let error_value = self.decode_run_interruption_error(0)?;
+                    let sign = if rb - ra < 0 { -1 } else { 1 };
+                    curr_line[idx + c] =
+                        T::from_i32(self.traits.compute_reconstructed_sample(rb, error_value * sign));
+                }
+            }
             run_length += 1;
             
             // Decrement run_index after run interruption (per CharLS)
@@ -650,18 +933,32 @@ impl<'a> ScanDecoder<'a> {
         Ok(run_length)
     }
 
-    fn decode_run_interruption_pixel<T: crate::jpegls::traits::JpeglsSample>(
+    fn decode_run_interruption_pixel(
         &mut self,
         ra: i32,
         rb: i32,
     ) -> Result<i32, JpeglsError> {
-        let near_lossless = self.coding_parameters.near_lossless;
-        let (context_index, sign) = if (ra - rb).abs() <= near_lossless {
+        let (context_index, sign) = if self.traits.is_near(ra, rb) {
             (1, 1)
         } else {
             (0, Self::bit_wise_sign(rb - ra))
         };
 
+        let error_value = self.decode_run_interruption_error(context_index)?;
+
+        let reconstructed = if context_index == 1 {
+            self.traits.compute_reconstructed_sample(ra, error_value)
+        } else {
+            self.traits.compute_reconstructed_sample(rb, error_value * sign)
+        };
+
+        debug_log!("    Run interruption: ra={}, rb={}, ctx={}, sign={}, error={}, reconstructed={}", 
+                  ra, rb, context_index, sign, error_value, reconstructed);
+
+        Ok(reconstructed)
+    }
+
+    fn decode_run_interruption_error(&mut self, context_index: usize) -> Result<i32, JpeglsError> {
         debug_log!("    Run interruption context[{}]: a={}, n={}, nn={}", 
                   context_index,
                   self.run_mode_contexts[context_index].a(),
@@ -685,16 +982,77 @@ impl<'a> ScanDecoder<'a> {
             e_mapped_error,
             reset_threshold,
         );
+        Ok(error_value)
+    }
+}
 
-        let reconstructed = if context_index == 1 {
-            T::compute_reconstructed_sample(ra, error_value)
-        } else {
-            T::compute_reconstructed_sample(rb, error_value * sign)
-        };
-
-        debug_log!("    Run interruption: ra={}, rb={}, ctx={}, sign={}, error={}, reconstructed={}", 
-                  ra, rb, context_index, sign, error_value, reconstructed);
-
-        Ok(reconstructed)
+/// Stores a decoded line of `pixels`, `pixel_components` samples per pixel, in the bytes of
+/// `row`, whose pixels hold `components` samples, starting with sample `component`.
+fn store_line<T: crate::jpegls::traits::JpeglsSample>(
+    row: &mut [u8],
+    pixels: &[T],
+    pixel_components: usize,
+    components: usize,
+    component: usize,
+) {
+    // SAFETY (both copies): JpeglsSample is only implemented for u8 and u16, which have no
+    // padding; the target ranges lie within `row`, which is bounds checked.
+    if pixel_components == components {
+        // Whole pixels: the line is stored as is.
+        let bytes = &mut row[..std::mem::size_of_val(pixels)];
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                pixels.as_ptr() as *const u8,
+                bytes.as_mut_ptr(),
+                bytes.len(),
+            );
+        }
+        return;
     }
+    let sample_size = std::mem::size_of::<T>();
+    let pixel_bytes = pixel_components * sample_size;
+    for (x, pixel) in pixels.chunks_exact(pixel_components).enumerate() {
+        let offset = (x * components + component) * sample_size;
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                pixel.as_ptr() as *const u8,
+                row[offset..offset + pixel_bytes].as_mut_ptr(),
+                pixel_bytes,
+            );
+        }
+    }
+}
+
+/// Quantizes every local gradient a scan can hold, `-MAXVAL..=MAXVAL` (ITU-T T.87, A.3.3),
+/// so the per-sample quantization is a table lookup. Mirrors the quantization LUT of CharLS.
+fn quantization_lut(pc_parameters: &JpeglsPcParameters, near_lossless: i32) -> Vec<i8> {
+    let (t1, t2, t3) = (
+        pc_parameters.threshold1,
+        pc_parameters.threshold2,
+        pc_parameters.threshold3,
+    );
+    let maximum_sample_value = pc_parameters.maximum_sample_value;
+    (-maximum_sample_value..=maximum_sample_value)
+        .map(|di| {
+            if di <= -t3 {
+                -4
+            } else if di <= -t2 {
+                -3
+            } else if di <= -t1 {
+                -2
+            } else if di < -near_lossless {
+                -1
+            } else if di <= near_lossless {
+                0
+            } else if di < t1 {
+                1
+            } else if di < t2 {
+                2
+            } else if di < t3 {
+                3
+            } else {
+                4
+            }
+        })
+        .collect()
 }
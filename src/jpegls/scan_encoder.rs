@@ -1,81 +1,76 @@
-use crate::FrameInfo;
+use crate::constants::MAXIMUM_COMPONENT_COUNT_IN_SCAN;
 use crate::error::JpeglsError;
-use crate::jpeg_marker_code::JPEG_MARKER_START_BYTE;
-use crate::jpegls::JpeglsPcParameters;
+use crate::jpeg_marker_code::{
+    JPEG_MARKER_START_BYTE, JPEG_RESTART_MARKER_BASE, JPEG_RESTART_MARKER_RANGE,
+};
+use crate::jpeg_stream_writer::Sink;
 use crate::jpegls::coding_parameters::CodingParameters;
 use crate::jpegls::regular_mode_context::RegularModeContext;
 use crate::jpegls::run_mode_context::RunModeContext;
-use crate::jpegls::traits::JpeglsSample;
+use crate::jpegls::sample_layout::SampleLayout;
+use crate::jpegls::traits::{CodingTraits, JpeglsSample};
 use crate::jpegls::InterleaveMode;
+use crate::jpegls::JpeglsPcParameters;
+use crate::mem_profiling::{track_elements, BufferGuard};
+use crate::FrameInfo;
 
-pub struct ScanEncoder<'a> {
+pub struct ScanEncoder<S: Sink> {
     frame_info: FrameInfo,
-    pc_parameters: JpeglsPcParameters,
     coding_parameters: CodingParameters,
-    destination: &'a mut [u8],
+    sink: S,
     position: usize,
     bit_buffer: u32,
     free_bit_count: i32,
     is_ff_written: bool,
+    // The first write the sink refused; nothing is written after it.
+    write_error: Option<JpeglsError>,
 
-    // Contexts (per component)
-    // regular_mode_contexts[component_index][context_id]
-    regular_mode_contexts: Vec<Vec<RegularModeContext>>,
-    // run_mode_contexts[component_index][0..1]
-    run_mode_contexts: Vec<Vec<RunModeContext>>,
-    // run_index[component_index]
-    run_index: Vec<usize>,
+    // Contexts are shared by all components of a scan (ITU-T T.87, A.2).
+    regular_mode_contexts: Vec<RegularModeContext>,
+    run_mode_contexts: [RunModeContext; 2],
+    // Run index of the component line currently being encoded.
+    run_index: usize,
+    _contexts_memory: BufferGuard,
 
     // Parameters
     t1: i32,
     t2: i32,
     t3: i32,
     reset_threshold: i32,
+    traits: CodingTraits,
 }
 
-impl<'a> ScanEncoder<'a> {
+impl<S: Sink> ScanEncoder<S> {
     pub fn new(
         frame_info: FrameInfo,
         pc_parameters: JpeglsPcParameters,
         coding_parameters: CodingParameters,
-        destination: &'a mut [u8],
+        sink: S,
     ) -> Self {
-        let range = pc_parameters.maximum_sample_value + 1;
-        let num_components = if coding_parameters.interleave_mode == InterleaveMode::None {
-            1
-        } else {
-            frame_info.component_count as usize
-        };
-
-        let mut regular_mode_contexts = Vec::with_capacity(num_components);
-        let mut run_mode_contexts = Vec::with_capacity(num_components);
-        let mut run_index = Vec::with_capacity(num_components);
-
-        for _ in 0..num_components {
-            regular_mode_contexts.push(vec![RegularModeContext::new(range); 365]);
-            run_mode_contexts.push(vec![
-                RunModeContext::new(0, range),
-                RunModeContext::new(1, range),
-            ]);
-            run_index.push(0);
-        }
+        let traits = CodingTraits::new(
+            pc_parameters.maximum_sample_value,
+            coding_parameters.near_lossless,
+        );
+        let range = traits.range;
 
         Self {
             frame_info,
-            pc_parameters,
             coding_parameters,
-            destination,
+            sink,
             position: 0,
             bit_buffer: 0,
             free_bit_count: 32,
             is_ff_written: false,
-            regular_mode_contexts,
-            run_mode_contexts,
-            run_index,
+            write_error: None,
+            regular_mode_contexts: vec![RegularModeContext::new(range); 365],
+            run_mode_contexts: [RunModeContext::new(0, range), RunModeContext::new(1, range)],
+            run_index: 0,
+            _contexts_memory: track_elements::<RegularModeContext>(365),
             t1: pc_parameters.threshold1,
             t2: pc_parameters.threshold2,
             t3: pc_parameters.threshold3,
             reset_threshold: pc_parameters.reset_value,
+            traits,
         }
     }
 
@@ -87,14 +82,19 @@ impl<'a> ScanEncoder<'a> {
         crate::jpegls::traits::bit_wise_sign(val)
     }
 
+    /// Encodes the scan. `source` holds the samples of all components in the scan,
+    /// arranged as described by `layout`.
     pub fn encode_scan<T: JpeglsSample>(
         &mut self,
         source: &[T],
-        stride: usize,
+        layout: SampleLayout,
     ) -> Result<usize, JpeglsError> {
         self.initialize();
-        self.encode_lines(source, stride)?;
+        self.encode_lines(source, layout)?;
         self.end_scan();
+        if let Some(error) = self.write_error {
+            return Err(error);
+        }
         Ok(self.get_length())
     }
 
@@ -102,101 +102,105 @@ impl<'a> ScanEncoder<'a> {
         self.bit_buffer = 0;
         self.free_bit_count = 32;
         self.is_ff_written = false;
+        self.write_error = None;
     }
 
     fn append_to_bit_stream(&mut self, bits: u32, bit_count: i32) {
+        debug_assert!((0..32).contains(&bit_count));
         if bit_count == 0 {
             return;
         }
-        let bit_count = bit_count.clamp(0, 31);
-        if self.free_bit_count < bit_count {
-            let bits_that_fit = self.free_bit_count.max(0);
-            if bits_that_fit > 0 {
-                let shift = bit_count - bits_that_fit;
-                let mask = ((1u32 << bit_count) - 1) >> shift;
-                let high_bits = (bits >> shift) & mask;
-                self.bit_buffer |= high_bits << (self.free_bit_count - bits_that_fit);
-                self.free_bit_count -= bits_that_fit;
-            }
+        self.free_bit_count -= bit_count;
+        if self.free_bit_count >= 0 {
+            self.bit_buffer |= bits << self.free_bit_count;
+        } else {
+            // Add as many bits as fit in the remaining space and flush.
+            self.bit_buffer |= bits >> -self.free_bit_count;
             self.flush();
-            let remaining = bit_count - bits_that_fit;
-            if remaining > 0 && self.free_bit_count >= remaining {
-                let low_mask = (1u32 << remaining) - 1;
-                let low_bits = bits & low_mask;
-                self.bit_buffer |= low_bits << (self.free_bit_count - remaining);
-                self.free_bit_count -= remaining;
+
+            // A second flush may be required if extra marker detect bits were needed and not all
+            // bits could be written.
+            if self.free_bit_count < 0 {
+                self.bit_buffer |= bits >> -self.free_bit_count;
+                self.flush();
+            }
+            debug_assert!(self.free_bit_count >= 0);
+            if self.free_bit_count < 32 {
+                self.bit_buffer |= bits << self.free_bit_count;
             }
-        } else {
-            self.bit_buffer |= bits << (self.free_bit_count - bit_count);
-            self.free_bit_count -= bit_count;
         }
     }
 
     fn flush(&mut self) {
-        // JPEG-LS bit stuffing: after writing 0xFF, the next byte only holds 7 bits
-        // (with the MSB always 0 to distinguish from markers)
-        while self.free_bit_count <= 24 {
-            if self.position >= self.destination.len() {
+        for _ in 0..4 {
+            if self.free_bit_count >= 32 {
+                self.free_bit_count = 32;
                 break;
             }
-            
-            if self.is_ff_written {
-                // After 0xFF, output only 7 bits (with MSB = 0 for bit stuffing)
-                let byte_val = (self.bit_buffer >> 25) as u8;  // Take 7 bits
-                self.destination[self.position] = byte_val;
-                self.position += 1;
+
+            // JPEG-LS bit stuffing (ITU-T T.87, A.1): after writing 0xFF, the next byte only
+            // holds 7 bits (with the MSB always 0 to distinguish it from a marker).
+            let byte_val = if self.is_ff_written {
+                let byte_val = (self.bit_buffer >> 25) as u8;
                 self.bit_buffer <<= 7;
                 self.free_bit_count += 7;
+                byte_val
             } else {
-                // Normal case: output 8 bits
                 let byte_val = (self.bit_buffer >> 24) as u8;
-                self.destination[self.position] = byte_val;
-                self.position += 1;
                 self.bit_buffer <<= 8;
                 self.free_bit_count += 8;
-            }
-            
-            // Check if we just wrote 0xFF
-            self.is_ff_written = self.position > 0 && 
-                self.destination[self.position - 1] == JPEG_MARKER_START_BYTE;
+                byte_val
+            };
+
+            self.write_byte(byte_val);
+            self.is_ff_written = byte_val == JPEG_MARKER_START_BYTE;
+        }
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        if self.write_error.is_some() {
+            return;
+        }
+        match self.sink.write(&[byte]) {
+            Ok(()) => self.position += 1,
+            Err(error) => self.write_error = Some(error),
         }
     }
 
     fn end_scan(&mut self) {
-        // First flush any pending data
         self.flush();
-        
-        // If a 0xFF was written as the last byte, we MUST output one more byte
-        // with the high bit = 0 (the bit stuffing byte)
+
+        // If a 0xFF was written, more than one 0 bit has to be written to ensure the 0xFF is
+        // followed by a stuffed 0 bit.
         if self.is_ff_written {
-            // Force output of a stuffing byte with any remaining bits
-            // The stuffing byte must have high bit = 0
-            if self.position < self.destination.len() {
-                // Output 7 bits (with high bit implicitly 0)
-                let byte_val = (self.bit_buffer >> 25) as u8;
-                self.destination[self.position] = byte_val;
-                self.position += 1;
-                self.bit_buffer <<= 7;
-                self.free_bit_count += 7;
-                self.is_ff_written = byte_val == JPEG_MARKER_START_BYTE;
-            }
-        }
-        
-        // Output any remaining bits that weren't flushed (pad to byte boundary)
-        let remaining_bits = 32 - self.free_bit_count;
-        if remaining_bits > 0 && self.position < self.destination.len() {
-            // Pad to 8 bits and output
-            let byte_val = (self.bit_buffer >> 24) as u8;
-            self.destination[self.position] = byte_val;
-            self.position += 1;
-            self.is_ff_written = byte_val == JPEG_MARKER_START_BYTE;
-        }
-        
-        // If we ended with 0xFF, output a stuffing byte
-        if self.is_ff_written && self.position < self.destination.len() {
-            self.destination[self.position] = 0x00;
-            self.position += 1;
+            self.append_to_bit_stream(0, (self.free_bit_count - 1) % 8);
         }
+        self.flush();
+    }
+
+    /// Ends the restart interval before `line` with its RSTm marker and resets the coding
+    /// state, so that the next interval can be decoded on its own.
+    fn write_restart_marker(&mut self, line: usize) {
+        self.end_scan();
+        let index = line / self.coding_parameters.restart_interval as usize - 1;
+        let marker = JPEG_RESTART_MARKER_BASE + (index % JPEG_RESTART_MARKER_RANGE as usize) as u8;
+        self.write_byte(JPEG_MARKER_START_BYTE);
+        self.write_byte(marker);
+        self.bit_buffer = 0;
+        self.free_bit_count = 32;
+        self.is_ff_written = false;
+
+        let range = self.traits.range;
+        self.regular_mode_contexts
+            .fill(RegularModeContext::new(range));
+        self.run_mode_contexts = [RunModeContext::new(0, range), RunModeContext::new(1, range)];
+        self.run_index = 0;
+    }
+
+    /// Whether a restart interval ends before `line`.
+    fn is_restart_line(&self, line: usize) -> bool {
+        let interval = self.coding_parameters.restart_interval as usize;
+        interval != 0 && line != 0 && line.is_multiple_of(interval)
     }
 
     fn get_length(&self) -> usize {
@@ -206,161 +210,182 @@ impl<'a> ScanEncoder<'a> {
     fn encode_lines<T: JpeglsSample>(
         &mut self,
         source: &[T],
-        _stride: usize,
+        layout: SampleLayout,
     ) -> Result<(), JpeglsError> {
         let width = self.frame_info.width as usize;
         let height = self.frame_info.height as usize;
-        let interleave_mode = self.coding_parameters.interleave_mode;
+        let component_count = self.frame_info.component_count as usize;
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
 
-        let components = if interleave_mode == InterleaveMode::None {
-            1
+        if source.len() < layout.required_len(width, height, component_count) {
+            return Err(JpeglsError::InvalidArgumentSize);
+        }
+
+        if self.coding_parameters.interleave_mode == InterleaveMode::Sample {
+            if component_count > MAXIMUM_COMPONENT_COUNT_IN_SCAN as usize {
+                return Err(JpeglsError::InvalidArgumentComponentCount);
+            }
+            self.encode_lines_sample_interleaved(source, layout, width, height, component_count)
         } else {
-            self.frame_info.component_count as usize
-        };
+            self.encode_lines_by_component(source, layout, width, height, component_count)
+        }
+    }
 
-        let pixel_stride = width * components;
-        let buffer_width = (width + 1) * components;
+    /// Encodes a non-interleaved scan (one component) or a line-interleaved scan
+    /// (one line of every component in turn).
+    fn encode_lines_by_component<T: JpeglsSample>(
+        &mut self,
+        source: &[T],
+        layout: SampleLayout,
+        width: usize,
+        height: usize,
+        component_count: usize,
+    ) -> Result<(), JpeglsError> {
+        // Each component line holds the `width` samples at 1..=width plus the left (0) and
+        // right (width + 1) edge samples used for prediction.
+        let pixel_stride = width + 2;
+        let mut line_buffer: Vec<T> = vec![T::default(); component_count * pixel_stride * 2];
+        let _line_buffer_memory = track_elements::<T>(line_buffer.len());
+        let mut run_index = vec![0usize; component_count];
+
+        for line in 0..height {
+            if self.is_restart_line(line) {
+                self.write_restart_marker(line);
+                line_buffer.fill(T::default());
+                run_index.fill(0);
+            }
+
+            let (first, second) = line_buffer.split_at_mut(component_count * pixel_stride);
+            let (prev_lines, curr_lines) = if (line & 1) == 1 {
+                (second, first)
+            } else {
+                (first, second)
+            };
 
-        // Per ITU-T T.87 specification, boundary pixels (outside the image) are
-        // initialized to ZERO for unsigned samples. This is required for proper
-        // synchronization between encoder and decoder.
-        let init_value = T::from_i32(0);
-        let mut line_buffer: Vec<T> = vec![init_value; buffer_width * 2];
-        let mut source_idx = 0;
+            for (component, run_index) in run_index.iter_mut().enumerate() {
+                let offset = component * pixel_stride;
+                let prev = &mut prev_lines[offset..offset + pixel_stride];
+                let curr = &mut curr_lines[offset..offset + pixel_stride];
+
+                for (x, sample) in curr[1..=width].iter_mut().enumerate() {
+                    *sample = source[layout.index(x, line, component)];
+                }
+
+                // Initialize the edge pixels used for prediction (ITU-T T.87, A.2.1).
+                prev[width + 1] = prev[width];
+                curr[0] = prev[1];
+
+                self.run_index = *run_index;
+                self.encode_sample_line(prev, curr, width, 1)?;
+                *run_index = self.run_index;
+            }
+        }
+        Ok(())
+    }
+
+    /// Encodes a sample-interleaved scan, in which all components of a pixel are coded together.
+    fn encode_lines_sample_interleaved<T: JpeglsSample>(
+        &mut self,
+        source: &[T],
+        layout: SampleLayout,
+        width: usize,
+        height: usize,
+        component_count: usize,
+    ) -> Result<(), JpeglsError> {
+        let line_length = (width + 2) * component_count;
+        let mut line_buffer: Vec<T> = vec![T::default(); line_length * 2];
+        let _line_buffer_memory = track_elements::<T>(line_buffer.len());
 
         for line in 0..height {
-            let (prev_line_slice, curr_line_slice) =
-                line_buffer.split_at_mut(buffer_width);
+            if self.is_restart_line(line) {
+                self.write_restart_marker(line);
+                line_buffer.fill(T::default());
+            }
+
+            let (first, second) = line_buffer.split_at_mut(line_length);
             let (prev, curr) = if (line & 1) == 1 {
-                (curr_line_slice, prev_line_slice)
+                (second, first)
             } else {
-                (prev_line_slice, curr_line_slice)
+                (first, second)
             };
 
-            let current_source_row = &source[source_idx..source_idx + pixel_stride];
-            curr[components..buffer_width].copy_from_slice(current_source_row);
-
-            // Replicate boundary pixels for padding
-            for c in 0..components {
-                 curr[c] = prev[components + c];
+            let row = &mut curr[component_count..(width + 1) * component_count];
+            if layout == SampleLayout::interleaved(component_count, layout.row_stride) {
+                let start = layout.index(0, line, 0);
+                row.copy_from_slice(&source[start..start + row.len()]);
+            } else {
+                for (x, pixel) in row.chunks_exact_mut(component_count).enumerate() {
+                    for (component, sample) in pixel.iter_mut().enumerate() {
+                        *sample = source[layout.index(x, line, component)];
+                    }
+                }
             }
 
-            self.encode_sample_line(prev, curr, width, components, line == 0)?;
-            source_idx += pixel_stride;
+            let last = width * component_count;
+            prev.copy_within(last..last + component_count, last + component_count);
+            curr[..component_count].copy_from_slice(&prev[component_count..2 * component_count]);
+
+            self.encode_sample_line(prev, curr, width, component_count)?;
         }
         Ok(())
     }
 
+    /// Encodes one line. Pixel `x` (0-based) of component `c` is stored at
+    /// `(x + 1) * components + c` in both line buffers; the edge pixels must be initialized.
     fn encode_sample_line<T: JpeglsSample>(
         &mut self,
-        prev_line: &mut [T],
+        prev_line: &[T],
         curr_line: &mut [T],
         width: usize,
         components: usize,
-        _is_first_line: bool,
     ) -> Result<(), JpeglsError> {
+        let mut qs = [0i32; MAXIMUM_COMPONENT_COUNT_IN_SCAN as usize];
         let mut pixel_idx = 0;
-        let mut current_buf_idx = components;
-
-        let mut rb = vec![0i32; components];
-        let mut rd = vec![0i32; components];
-
-        for c in 0..components {
-            rb[c] = prev_line[c].to_i32();
-            rd[c] = prev_line[components + c].to_i32();
-        }
 
         while pixel_idx < width {
-            let mut all_qs_zero = true;
-            let mut component_qs = vec![0; components];
-            let mut component_pred = vec![0; components];
-
-            let is_last_pixel = pixel_idx == width - 1;
-
-            for c in 0..components {
-                let idx = current_buf_idx + c;
+            let base = (pixel_idx + 1) * components;
+            for (c, qs) in qs.iter_mut().enumerate().take(components) {
+                let idx = base + c;
                 let ra = curr_line[idx - components].to_i32();
-                let rc = rb[c];
-                rb[c] = rd[c];
-
-                if is_last_pixel {
-                    rd[c] = rb[c];
-                } else {
-                    rd[c] = prev_line[idx + components].to_i32();
-                }
-
-                let d1 = rd[c] - rb[c];
-                let d2 = rb[c] - rc;
-                let d3 = rc - ra;
-
-                let q1 = self.quantize_gradient(d1);
-                let q2 = self.quantize_gradient(d2);
-                let q3 = self.quantize_gradient(d3);
-
-                let qs = self.compute_context_id(q1, q2, q3);
-                component_qs[c] = qs;
-                if qs != 0 {
-                    all_qs_zero = false;
-                }
-
-                component_pred[c] = self.compute_predicted_value(ra, rb[c], rc);
+                let rc = prev_line[idx - components].to_i32();
+                let rb = prev_line[idx].to_i32();
+                let rd = prev_line[idx + components].to_i32();
+
+                *qs = self.compute_context_id(
+                    self.quantize_gradient(rd - rb),
+                    self.quantize_gradient(rb - rc),
+                    self.quantize_gradient(rc - ra),
+                );
             }
 
-            // Per CharLS: use run mode when all contexts are zero, including first pixel.
-            // For the first pixel, Ra=0 (boundary), so if pixel != 0, run length will be 0
-            // and run interruption will encode the value.
-            let use_run_mode = all_qs_zero;
-
-            if !use_run_mode {
-                for c in 0..components {
-                    let idx = current_buf_idx + c;
-                    let val = curr_line[idx].to_i32();
-                    self.encode_regular::<T>(component_qs[c], val, component_pred[c], c)?;
-                }
-                pixel_idx += 1;
-                current_buf_idx += components;
+            if qs[..components].iter().all(|&q| q == 0) {
+                pixel_idx +=
+                    self.encode_run_mode(pixel_idx, prev_line, curr_line, width, components)?;
             } else {
-                let start_pixel_idx = pixel_idx;
-
-                let encoded_len = self.encode_run_mode_interleaved(
-                     start_pixel_idx,
-                     prev_line,
-                     curr_line,
-                     width,
-                     components,
-                     &mut rb,
-                     &mut rd
-                )?;
-
-                pixel_idx += encoded_len;
-                current_buf_idx += encoded_len * components;
-
-                // Re-sync Rb/Rd
-                if pixel_idx < width {
-                     let is_last = pixel_idx == width - 1;
-                     for c in 0..components {
-                         let comp_offset = components + c;
-
-                         rb[c] = prev_line[(pixel_idx - 1) * components + comp_offset].to_i32();
-                         if is_last {
-                             rd[c] = rb[c];
-                         } else {
-                             rd[c] = prev_line[pixel_idx * components + comp_offset].to_i32();
-                         }
-                     }
+                for (c, &qs) in qs.iter().enumerate().take(components) {
+                    let idx = base + c;
+                    let ra = curr_line[idx - components].to_i32();
+                    let rc = prev_line[idx - components].to_i32();
+                    let rb = prev_line[idx].to_i32();
+                    let predicted = self.compute_predicted_value(ra, rb, rc);
+                    let reconstructed =
+                        self.encode_regular(qs, curr_line[idx].to_i32(), predicted)?;
+                    curr_line[idx] = T::from_i32(reconstructed);
                 }
+                pixel_idx += 1;
             }
         }
         Ok(())
     }
 
-    fn encode_regular<T: JpeglsSample>(
+    fn encode_regular(
         &mut self,
         qs: i32,
         x: i32,
         predicted: i32,
-        component_index: usize,
-    ) -> Result<(), JpeglsError> {
+    ) -> Result<i32, JpeglsError> {
         let sign = Self::bit_wise_sign(qs);
         let ctx_index = crate::jpegls::traits::apply_sign_for_index(qs, sign);
 
@@ -372,46 +397,24 @@ impl<'a> ScanEncoder<'a> {
         let correction: i32;
 
         {
-            let context = &mut self.regular_mode_contexts[component_index][ctx_index];
+            let context = &mut self.regular_mode_contexts[ctx_index];
             k = context.compute_golomb_coding_parameter(31)?;
             c_val = context.c();
             correction = context.get_error_correction(near_lossless | k);
         }
 
-        let predicted_value = T::correct_prediction(predicted + Self::apply_sign(c_val, sign));
-        let error_val = self.compute_error_value(Self::apply_sign(x - predicted_value, sign));
+        let predicted_value = self.traits.correct_prediction(predicted + Self::apply_sign(c_val, sign));
+        let error_val = self.traits.compute_error_value(Self::apply_sign(x - predicted_value, sign));
         let mapped_error = self.map_error_value(correction ^ error_val);
         self.encode_mapped_value(k, mapped_error, limit);
 
         let reset_threshold = self.reset_threshold;
-        let context = &mut self.regular_mode_contexts[component_index][ctx_index];
+        let context = &mut self.regular_mode_contexts[ctx_index];
         context.update_variables_and_bias(error_val, near_lossless, reset_threshold)?;
-        Ok(())
-    }
-
-    fn compute_error_value(&self, e: i32) -> i32 {
-        self.modulo_range(self.quantize(e))
-    }
-
-    fn quantize(&self, e: i32) -> i32 {
-        if e > 0 {
-            (e + self.coding_parameters.near_lossless)
-                / (2 * self.coding_parameters.near_lossless + 1)
-        } else {
-            -(self.coding_parameters.near_lossless - e)
-                / (2 * self.coding_parameters.near_lossless + 1)
-        }
-    }
-
-    fn modulo_range(&self, mut error_value: i32) -> i32 {
-        let range = self.pc_parameters.maximum_sample_value + 1;
-        if error_value < 0 {
-            error_value += range;
-        }
-        if error_value >= (range + 1) / 2 {
-            error_value -= range;
-        }
-        error_value
+        Ok(self.traits.compute_reconstructed_sample(
+            predicted_value,
+            Self::apply_sign(error_val, sign),
+        ))
     }
 
     fn map_error_value(&self, error_value: i32) -> i32 {
@@ -420,29 +423,27 @@ impl<'a> ScanEncoder<'a> {
     }
 
     fn encode_mapped_value(&mut self, k: i32, mapped_error: i32, limit: i32) {
-        let high_bits = mapped_error >> k;
-        let qbpp = self.coding_parameters.quantized_bits_per_sample;
+        let mut high_bits = mapped_error >> k;
+        let qbpp = self.traits.quantized_bits_per_sample;
 
         if high_bits < limit - qbpp - 1 {
-            for _ in 0..high_bits {
-                self.append_to_bit_stream(0, 1);
+            if high_bits + 1 > 31 {
+                self.append_to_bit_stream(0, high_bits / 2);
+                high_bits -= high_bits / 2;
             }
-            self.append_to_bit_stream(1, 1);
-            let k_clamped = k.min(31);
-            self.append_to_bit_stream((mapped_error & ((1i32 << k_clamped) - 1)) as u32, k_clamped);
+            self.append_to_bit_stream(1, high_bits + 1);
+            self.append_to_bit_stream((mapped_error & ((1i32 << k) - 1)) as u32, k);
         } else {
             // Escape mode: output (limit - qbpp - 1) zeros + 1, then (MErrval - 1) in qbpp bits
             // Per CharLS: encoder writes (mapped_error - 1)
             let remaining = limit - qbpp;
             if remaining > 31 {
                 self.append_to_bit_stream(0, 31);
-                let remaining_clamped = (remaining - 31).min(31);
-                self.append_to_bit_stream(1, remaining_clamped);
+                self.append_to_bit_stream(1, remaining - 31);
             } else {
-                self.append_to_bit_stream(1, remaining.min(31));
+                self.append_to_bit_stream(1, remaining);
             }
-            let qbpp_clamped = qbpp.min(31);
-            self.append_to_bit_stream(((mapped_error - 1) & ((1i32 << qbpp_clamped) - 1)) as u32, qbpp_clamped);
+            self.append_to_bit_stream(((mapped_error - 1) & ((1i32 << qbpp) - 1)) as u32, qbpp);
         }
     }
 
@@ -488,153 +489,78 @@ impl<'a> ScanEncoder<'a> {
             ra + rb - rc
         };
 
-        let max_val = (1 << self.frame_info.bits_per_sample) - 1;
-        if predicted < 0 {
-            0
-        } else if predicted > max_val {
-            max_val
-        } else {
-            predicted
-        }
+        self.traits.correct_prediction(predicted)
     }
 
-    // Updated for Interleaved
-    fn encode_run_mode_interleaved<T: JpeglsSample>(
+    /// Encodes a run starting at `start_pixel_idx`, followed by the run interruption pixel
+    /// if the run ends before the end of the line. Returns the number of pixels encoded.
+    fn encode_run_mode<T: JpeglsSample>(
         &mut self,
         start_pixel_idx: usize,
         prev_line: &[T],
         curr_line: &mut [T],
         width: usize,
         components: usize,
-        _rb: &mut [i32],
-        _rd: &mut [i32],
     ) -> Result<usize, JpeglsError> {
-        // Run length is number of PIXELS where all components match Ra
-        let mut run_length = 0;
         let count_type_remain = width - start_pixel_idx;
+        let start = (start_pixel_idx + 1) * components;
 
-        let base_offset = components;
-
-        // Capture Ra for all components (left neighbor)
-        let mut ra = vec![T::default(); components];
-        for c in 0..components {
-            let ra_idx = if start_pixel_idx > 0 {
-                base_offset + (start_pixel_idx - 1) * components + c
-            } else {
-                // First pixel: use boundary pixel at index c
-                c
-            };
-            ra[c] = curr_line[ra_idx];
+        // A run continues while all components of a pixel match Ra (the pixel to the left).
+        let mut ra = [0i32; MAXIMUM_COMPONENT_COUNT_IN_SCAN as usize];
+        for (c, ra) in ra.iter_mut().enumerate().take(components) {
+            *ra = curr_line[start - components + c].to_i32();
         }
 
+        let mut run_length = 0;
         while run_length < count_type_remain {
-            let mut all_match = true;
-            for c in 0..components {
-                let val = curr_line[base_offset + (start_pixel_idx + run_length) * components + c];
-                if !T::is_near(
-                    val.to_i32(),
-                    ra[c].to_i32(),
-                    self.coding_parameters.near_lossless,
-                ) {
-                    all_match = false;
-                    break;
-                }
-            }
-            if !all_match {
+            let idx = start + run_length * components;
+            if !(0..components)
+                .all(|c| self.traits.is_near(curr_line[idx + c].to_i32(), ra[c]))
+            {
                 break;
             }
-
-            for c in 0..components {
-                curr_line[base_offset + (start_pixel_idx + run_length) * components + c] = ra[c];
+            for (c, &ra) in ra.iter().enumerate().take(components) {
+                curr_line[idx + c] = T::from_i32(ra);
             }
             run_length += 1;
         }
 
-        // Use Component 0 run index for shared run
-        self.encode_run_pixels(run_length, run_length == count_type_remain, 0);
-
+        self.encode_run_pixels(run_length, run_length == count_type_remain);
         if run_length == count_type_remain {
             return Ok(run_length);
         }
 
-        // Interruption
-        let interruption_pixel_idx = start_pixel_idx + run_length;
-
-        let mut interruption_comp = 0;
-        let mut found_break = false;
-
-        for c in 0..components {
-            let val = curr_line[base_offset + interruption_pixel_idx * components + c];
-            if !T::is_near(
-                 val.to_i32(),
-                 ra[c].to_i32(),
-                 self.coding_parameters.near_lossless
-            ) {
-                 interruption_comp = c;
-                 found_break = true;
-                 break;
+        let idx = start + run_length * components;
+        if components == 1 {
+            let reconstructed = self.encode_run_interruption_pixel(
+                curr_line[idx].to_i32(),
+                ra[0],
+                prev_line[idx].to_i32(),
+            );
+            curr_line[idx] = T::from_i32(reconstructed);
+        } else {
+            // In sample interleaved mode all components of the interruption pixel are coded
+            // relative to Rb with run interruption context 0.
+            for (c, &ra) in ra.iter().enumerate().take(components) {
+                let rb = prev_line[idx + c].to_i32();
+                let sign = if rb - ra < 0 { -1 } else { 1 };
+                let error_value =
+                    self.traits.compute_error_value(sign * (curr_line[idx + c].to_i32() - rb));
+                self.encode_run_interruption_error(0, error_value);
+                curr_line[idx + c] =
+                    T::from_i32(self.traits.compute_reconstructed_sample(rb, error_value * sign));
             }
         }
 
-        if !found_break {
-             return Ok(run_length);
-        }
-
-        // Handle interruption component
-        let c = interruption_comp;
-        let up_val = prev_line[base_offset + interruption_pixel_idx * components + c];
-        let val = curr_line[base_offset + interruption_pixel_idx * components + c];
-
-        let interruption_val = self.encode_run_interruption_pixel::<T>(
-             val.to_i32(),
-             ra[c].to_i32(),
-             up_val.to_i32(),
-             c // Use component c context
-        );
-        curr_line[base_offset + interruption_pixel_idx * components + c] = T::from_i32(interruption_val);
-
-        self.decrement_run_index(0);
-
-        for next_c in (c + 1)..components {
-             let idx = base_offset + interruption_pixel_idx * components + next_c;
-
-             let r_a = curr_line[idx - components].to_i32();
-             let r_up = prev_line[idx].to_i32(); // Rb
-             let r_up_left = prev_line[idx - components].to_i32(); // Rc
-
-             let r_up_right = if interruption_pixel_idx == width - 1 {
-                 r_up // Rd = Rb at end of line
-             } else {
-                 prev_line[idx + components].to_i32() // Rd
-             };
-
-             let d1 = r_up_right - r_up;
-             let d2 = r_up - r_up_left;
-             let d3 = r_up_left - r_a;
-
-             let q1 = self.quantize_gradient(d1);
-             let q2 = self.quantize_gradient(d2);
-             let q3 = self.quantize_gradient(d3);
-
-             let qs = self.compute_context_id(q1, q2, q3);
-             let predicted = self.compute_predicted_value(r_a, r_up, r_up_left);
-
-             self.encode_regular::<T>(
-                 qs,
-                 curr_line[idx].to_i32(),
-                 predicted,
-                 next_c
-             )?;
-        }
-
+        self.decrement_run_index();
         Ok(run_length + 1)
     }
 
-    fn encode_run_pixels(&mut self, mut run_length: usize, end_of_line: bool, comp: usize) {
-        while run_length >= (1 << crate::constants::J[self.run_index[comp]]) {
+    fn encode_run_pixels(&mut self, mut run_length: usize, end_of_line: bool) {
+        while run_length >= (1 << crate::constants::J[self.run_index]) {
             self.append_ones_to_bit_stream(1);
-            run_length -= 1 << crate::constants::J[self.run_index[comp]];
-            self.increment_run_index(comp);
+            run_length -= 1 << crate::constants::J[self.run_index];
+            self.increment_run_index();
         }
 
         if end_of_line {
@@ -642,29 +568,26 @@ impl<'a> ScanEncoder<'a> {
                 self.append_ones_to_bit_stream(1);
             }
         } else {
-            self.append_to_bit_stream(run_length as u32, crate::constants::J[self.run_index[comp]] + 1);
+            self.append_to_bit_stream(run_length as u32, crate::constants::J[self.run_index] + 1);
         }
     }
 
-    fn encode_run_interruption_pixel<T: JpeglsSample>(
-        &mut self, x: i32, ra: i32, rb: i32, comp: usize
-    ) -> i32 {
-        let near_lossless = self.coding_parameters.near_lossless;
-        if (ra - rb).abs() <= near_lossless {
-            let error_value = self.compute_error_value(x - ra);
-            self.encode_run_interruption_error(1, error_value, comp);
-            T::compute_reconstructed_sample(ra, error_value)
+    fn encode_run_interruption_pixel(&mut self, x: i32, ra: i32, rb: i32) -> i32 {
+        if self.traits.is_near(ra, rb) {
+            let error_value = self.traits.compute_error_value(x - ra);
+            self.encode_run_interruption_error(1, error_value);
+            self.traits.compute_reconstructed_sample(ra, error_value)
         } else {
             let sign = Self::bit_wise_sign(rb - ra);
-            let error_value = self.compute_error_value((x - rb) * sign);
-            self.encode_run_interruption_error(0, error_value, comp);
-            T::compute_reconstructed_sample(rb, error_value * sign)
+            let error_value = self.traits.compute_error_value((x - rb) * sign);
+            self.encode_run_interruption_error(0, error_value);
+            self.traits.compute_reconstructed_sample(rb, error_value * sign)
         }
     }
 
-    fn encode_run_interruption_error(&mut self, context_index: usize, error_value: i32, comp: usize) {
+    fn encode_run_interruption_error(&mut self, context_index: usize, error_value: i32) {
         let (k, e_mapped_error_value) = {
-            let context = &self.run_mode_contexts[comp][context_index];
+            let context = &self.run_mode_contexts[context_index];
             let k = context.compute_golomb_coding_parameter();
             let map = context.compute_map(error_value, k);
             let ri_type = context.run_interruption_type();
@@ -675,23 +598,23 @@ impl<'a> ScanEncoder<'a> {
             (k, val)
         };
 
-        let limit = self.coding_parameters.limit - crate::constants::J[self.run_index[comp]] - 1;
+        let limit = self.coding_parameters.limit - crate::constants::J[self.run_index] - 1;
         self.encode_mapped_value(k, e_mapped_error_value, limit);
 
         let reset_threshold = self.reset_threshold;
-        let context = &mut self.run_mode_contexts[comp][context_index];
+        let context = &mut self.run_mode_contexts[context_index];
         context.update_variables(error_value, e_mapped_error_value, reset_threshold);
     }
 
-    fn increment_run_index(&mut self, comp: usize) {
-        if self.run_index[comp] < 31 {
-            self.run_index[comp] += 1;
+    fn increment_run_index(&mut self) {
+        if self.run_index < 31 {
+            self.run_index += 1;
         }
     }
 
-    fn decrement_run_index(&mut self, comp: usize) {
-        if self.run_index[comp] > 0 {
-            self.run_index[comp] -= 1;
+    fn decrement_run_index(&mut self) {
+        if self.run_index > 0 {
+            self.run_index -= 1;
         }
     }
 
@@ -13,30 +13,95 @@ pub trait JpeglsSample:
     + From<u8>
     + TryInto<u8>
     + TryInto<i32>
+    + Send
+    + Sync
 {
     const BITS: u32;
     const MAX_VALUE: i32;
 
     fn to_i32(self) -> i32;
     fn from_i32(val: i32) -> Self;
+}
+
+/// Sample arithmetic that depends on MAXVAL and NEAR rather than on the sample type
+/// (ITU-T T.87, A.2.1, A.4 and A.5). Mirrors the `default_traits` of CharLS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodingTraits {
+    pub maximum_sample_value: i32,
+    pub near_lossless: i32,
+    /// Number of distinct quantized prediction errors (RANGE).
+    pub range: i32,
+    /// Bits needed to code a quantized prediction error (qbpp).
+    pub quantized_bits_per_sample: i32,
+}
+
+impl CodingTraits {
+    pub fn new(maximum_sample_value: i32, near_lossless: i32) -> Self {
+        let range = (maximum_sample_value + 2 * near_lossless) / (2 * near_lossless + 1) + 1;
+        Self {
+            maximum_sample_value,
+            near_lossless,
+            range,
+            quantized_bits_per_sample: log2_ceil(range),
+        }
+    }
+
+    pub fn is_near(&self, lhs: i32, rhs: i32) -> bool {
+        (lhs - rhs).abs() <= self.near_lossless
+    }
+
+    pub fn correct_prediction(&self, predicted: i32) -> i32 {
+        predicted.clamp(0, self.maximum_sample_value)
+    }
+
+    /// Quantizes a prediction error and reduces it modulo RANGE.
+    pub fn compute_error_value(&self, error_value: i32) -> i32 {
+        self.modulo_range(self.quantize(error_value))
+    }
+
+    pub fn compute_reconstructed_sample(&self, predicted: i32, error_value: i32) -> i32 {
+        self.fix_reconstructed_value(predicted + self.dequantize(error_value))
+    }
 
-    fn is_near(lhs: i32, rhs: i32, near: i32) -> bool {
-        (lhs - rhs).abs() <= near
+    pub fn modulo_range(&self, mut error_value: i32) -> i32 {
+        if error_value < 0 {
+            error_value += self.range;
+        }
+        if error_value >= (self.range + 1) / 2 {
+            error_value -= self.range;
+        }
+        error_value
     }
 
-    fn correct_prediction(predicted: i32) -> i32 {
-        if predicted < 0 {
-            0
-        } else if predicted > Self::MAX_VALUE {
-            Self::MAX_VALUE
+    fn quantize(&self, error_value: i32) -> i32 {
+        if error_value > 0 {
+            (error_value + self.near_lossless) / (2 * self.near_lossless + 1)
         } else {
-            predicted
+            -(self.near_lossless - error_value) / (2 * self.near_lossless + 1)
         }
     }
 
-    fn compute_reconstructed_sample(predicted: i32, error_value: i32) -> i32 {
-        (predicted + error_value) & Self::MAX_VALUE
+    fn dequantize(&self, error_value: i32) -> i32 {
+        error_value * (2 * self.near_lossless + 1)
+    }
+
+    fn fix_reconstructed_value(&self, mut value: i32) -> i32 {
+        if value < -self.near_lossless {
+            value += self.range * (2 * self.near_lossless + 1);
+        } else if value > self.maximum_sample_value + self.near_lossless {
+            value -= self.range * (2 * self.near_lossless + 1);
+        }
+        self.correct_prediction(value)
+    }
+}
+
+/// Smallest `n` for which `2^n >= value`.
+fn log2_ceil(value: i32) -> i32 {
+    let mut bits = 0;
+    while (1i64 << bits) < value as i64 {
+        bits += 1;
     }
+    bits
 }
 
 impl JpeglsSample for u8 {
@@ -0,0 +1,174 @@
+//! Conversion of JPEG-LS streams between interleave modes.
+//!
+//! Archives frequently hold the same kind of image coded both as separate component
+//! scans (ILV=0) and as a single line-interleaved scan (ILV=1). [`JpeglsTranscoder`]
+//! normalizes such streams by entropy decoding the scans into component planes and
+//! re-coding them with the requested interleave mode. The samples are never converted
+//! into a pixel-interleaved image, color transformed, or copied into the caller's
+//! layout, which makes it considerably cheaper than a decode followed by an encode.
+
+use crate::error::JpeglsError;
+use crate::jpeg_marker_code::JpegMarkerCode;
+use crate::jpeg_stream_reader::JpegStreamReader;
+use crate::jpegls::decoder::{scan_components, scan_parameters};
+use crate::jpegls::sample_layout::SampleLayout;
+use crate::jpegls::scan_decoder::ScanDecoder;
+use crate::jpegls::traits::JpeglsSample;
+use crate::jpegls::{InterleaveMode, JpeglsEncoder, JpeglsPcParameters};
+use crate::mem_profiling::track_elements;
+
+/// Rewrites a JPEG-LS stream with a different interleave mode.
+///
+/// ```rust
+/// use jpegexp_rs::jpegls::{InterleaveMode, JpeglsEncoder, JpeglsTranscoder};
+/// use jpegexp_rs::FrameInfo;
+///
+/// let frame_info = FrameInfo { width: 8, height: 8, bits_per_sample: 8, component_count: 3 };
+/// let pixels = vec![128u8; 8 * 8 * 3];
+/// let mut planar = vec![0u8; 1024];
+/// let mut encoder = JpeglsEncoder::new(&mut planar);
+/// encoder.set_frame_info(frame_info).unwrap();
+/// let planar_len = encoder.encode(&pixels).unwrap();
+///
+/// let mut line_interleaved = vec![0u8; 1024];
+/// let mut transcoder = JpeglsTranscoder::new(&planar[..planar_len]);
+/// transcoder.set_interleave_mode(InterleaveMode::Line).unwrap();
+/// let length = transcoder.transcode(&mut line_interleaved).unwrap();
+/// assert!(length > 0);
+/// ```
+///
+/// Lossless streams are transcoded without any change to the samples. For near-lossless
+/// streams (NEAR > 0) the re-coded samples may differ from the source by up to NEAR,
+/// because the prediction errors are quantized again in the new scan order.
+///
+/// Only the frame, the color transformation, the preset coding parameters, the mapping
+/// tables with the selection of each component, and the scans are carried over; other
+/// segments such as a SPIFF header or application data are not copied. Transformed samples are re-coded as they are, without an inverse transform.
+pub struct JpeglsTranscoder<'a> {
+    source: &'a [u8],
+    interleave_mode: InterleaveMode,
+}
+
+impl<'a> JpeglsTranscoder<'a> {
+    pub fn new(source: &'a [u8]) -> Self {
+        Self {
+            source,
+            interleave_mode: InterleaveMode::Line,
+        }
+    }
+
+    /// Interleave mode of the produced stream. Defaults to [`InterleaveMode::Line`].
+    pub fn set_interleave_mode(
+        &mut self,
+        interleave_mode: InterleaveMode,
+    ) -> Result<(), JpeglsError> {
+        self.interleave_mode = interleave_mode;
+        Ok(())
+    }
+
+    /// Writes the transcoded stream to `destination` and returns its length in bytes.
+    pub fn transcode(&mut self, destination: &mut [u8]) -> Result<usize, JpeglsError> {
+        let mut reader = JpegStreamReader::new(self.source);
+        let mut spiff_header = None;
+        reader.read_header(&mut spiff_header)?;
+
+        if reader.frame_info().bits_per_sample <= 8 {
+            self.transcode_typed::<u8>(reader, destination)
+        } else {
+            self.transcode_typed::<u16>(reader, destination)
+        }
+    }
+
+    fn transcode_typed<T: JpeglsSample>(
+        &self,
+        mut reader: JpegStreamReader,
+        destination: &mut [u8],
+    ) -> Result<usize, JpeglsError> {
+        let frame_info = reader.frame_info();
+        let width = frame_info.width as usize;
+        let height = frame_info.height as usize;
+        let component_count = frame_info.component_count as usize;
+        if width == 0 || height == 0 || component_count == 0 {
+            return Err(JpeglsError::InvalidData);
+        }
+
+        let layout = SampleLayout::planar(width, height);
+        let mut planes = vec![T::default(); width * height * component_count];
+        let _planes_memory = track_elements::<T>(planes.len());
+        let mut decoded = vec![false; component_count];
+        let mut near_lossless = None;
+
+        loop {
+            match reader.peek_marker()? {
+                JpegMarkerCode::StartOfScan => {
+                    reader.read_start_of_scan_segment_jpegls()?;
+                    let (first, count) = scan_components(&reader, &decoded)?;
+
+                    let mut scan_frame_info = frame_info;
+                    scan_frame_info.component_count = count as i32;
+                    let (preset, coding_parameters) = scan_parameters(&reader, &scan_frame_info)?;
+                    if *near_lossless.get_or_insert(coding_parameters.near_lossless)
+                        != coding_parameters.near_lossless
+                    {
+                        return Err(JpeglsError::ParameterValueNotSupported);
+                    }
+
+                    let mut scan_decoder = ScanDecoder::new(
+                        scan_frame_info,
+                        preset,
+                        coding_parameters,
+                        reader.remaining_data(),
+                    )?;
+                    let bytes_read = scan_decoder
+                        .decode_samples(&mut planes[layout.index(0, 0, first)..], layout)?;
+                    reader.advance(bytes_read);
+
+                    decoded[first..first + count].fill(true);
+                }
+                JpegMarkerCode::EndOfImage => break,
+                JpegMarkerCode::JpeglsPresetParameters => {
+                    reader.read_marker()?;
+                    reader.read_jpegls_preset_parameters_segment()?;
+                }
+                JpegMarkerCode::DefineNumberOfLines => {
+                    reader.read_marker()?;
+                    reader.read_define_number_of_lines_segment()?;
+                }
+                JpegMarkerCode::DefineRestartInterval => {
+                    reader.read_marker()?;
+                    reader.read_dri_segment()?;
+                }
+                _ => {
+                    reader.read_marker()?;
+                    reader.skip_segment()?;
+                }
+            }
+        }
+
+        if decoded.iter().any(|&done| !done) {
+            return Err(JpeglsError::InvalidData);
+        }
+
+        let mut encoder = JpeglsEncoder::new(destination);
+        encoder.set_frame_info(frame_info)?;
+        encoder.set_near_lossless(near_lossless.unwrap_or(0))?;
+        encoder.set_interleave_mode(self.interleave_mode)?;
+        encoder.set_color_transformation(reader.parameters().transformation)?;
+        encoder.set_restart_interval(reader.restart_interval);
+        let preset = reader.preset_coding_parameters();
+        if !crate::jpegls::coding_parameters::is_default(&preset, &JpeglsPcParameters::default()) {
+            encoder.set_preset_coding_parameters(preset)?;
+        }
+        for table in reader.mapping_tables() {
+            encoder.write_mapping_table(
+                table.table_id as i32,
+                table.entry_size as i32,
+                &table.data,
+            )?;
+        }
+        for (index, component) in reader.components.iter().enumerate() {
+            encoder.set_mapping_table_id(index as i32, component.mapping_table_id as i32)?;
+        }
+        encoder.encode_samples(&planes, layout)
+    }
+}
@@ -44,11 +44,19 @@ See [COMPLIANCE.md](file:///c:/Users/aroja/CODE/jpegexp-rs/COMPLIANCE.md) for a
 This library is written in pure Rust with `#![forbid(unsafe_code)]` where possible, ensuring memory safety without sacrificing performance.
 */
 
+pub mod checksum;
+pub mod codec;
 pub mod constants;
+pub mod decoder;
+pub mod dump;
 pub mod error;
 pub mod jpeg_marker_code;
 pub mod jpeg_stream_reader;
 pub mod jpeg_stream_writer;
+pub mod mem_profiling;
+pub mod pixelops;
+pub mod resolution;
+pub mod suggest;
 
 pub mod jpeg1;
 pub mod jpeg2000;
@@ -60,7 +68,15 @@ pub mod wasm;
 
 pub mod ffi;
 
-pub use error::JpeglsError;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+
+pub use codec::Format;
+pub use decoder::{DecodedImage, Decoder, ImageMetadata};
+pub use error::{JpeglsError, Truncation};
+pub use mem_profiling::{DecodeStats, EncodeStats};
+pub use resolution::Resolution;
+pub use suggest::{suggest_codec, CodecSuggestion, Goal};
 
 /// Basic information about a compressed image frame.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
@@ -75,10 +91,79 @@ pub struct FrameInfo {
     pub component_count: i32,
 }
 
+/// Precision and sampling of one component of a stream. [`FrameInfo`] gives a single
+/// bit depth for the whole frame (the largest one); JPEG 2000 lets every component have
+/// its own precision, signedness and subsampling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ComponentInfo {
+    /// Bits per sample of the component.
+    pub bits_per_sample: i32,
+    /// Whether the samples are signed (only in JPEG 2000).
+    pub signed: bool,
+    /// Horizontal subsampling of the component relative to the full image width, e.g. 2
+    /// for the chroma components of 4:2:0 YCbCr.
+    pub horizontal_subsampling: u8,
+    /// Vertical subsampling of the component relative to the full image height.
+    pub vertical_subsampling: u8,
+}
+
+impl FrameInfo {
+    /// Size in bytes of the decoded frame in `layout`, with samples wider than 8 bits
+    /// stored as 16-bit values: the rows a decoder writes (one per image row when
+    /// interleaved, one per image row and component when planar) times their length.
+    ///
+    /// Returns [`JpeglsError::ParameterOutOfRange`] if the size does not fit in `usize`,
+    /// as a 65535 x 65535 RGB frame on a 32-bit target, or the component count or bits per
+    /// sample is negative.
+    pub fn decoded_size(&self, layout: OutputLayout) -> Result<usize, JpeglsError> {
+        let components =
+            usize::try_from(self.component_count).map_err(|_| JpeglsError::ParameterOutOfRange)?;
+        let bits_per_sample =
+            usize::try_from(self.bits_per_sample).map_err(|_| JpeglsError::ParameterOutOfRange)?;
+        let height = self.height as usize;
+        let (pixel_components, row_count) = match layout {
+            OutputLayout::Interleaved => (components, Some(height)),
+            OutputLayout::Planar => (1, height.checked_mul(components)),
+        };
+        let row_bytes = (self.width as usize)
+            .checked_mul(pixel_components)
+            .and_then(|n| n.checked_mul(bits_per_sample.div_ceil(8)));
+        row_count
+            .zip(row_bytes)
+            .and_then(|(rows, row_bytes)| rows.checked_mul(row_bytes))
+            .ok_or(JpeglsError::ParameterOutOfRange)
+    }
+}
+
+/// Arrangement of the components of a decoded multi-component image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputLayout {
+    /// All components of a pixel are adjacent (RGBRGB...).
+    #[default]
+    Interleaved,
+    /// Each component is a separate plane of `height` rows (RRR...GGG...BBB...), as in
+    /// DICOM planar configuration 1.
+    Planar,
+}
+
+/// Color conversion applied while decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorConversion {
+    /// The components are returned as decoded.
+    #[default]
+    None,
+    /// Three-component images are decoded to one luminance channel, the Y component of
+    /// JPEG 1 YCbCr images (their chroma is entropy decoded but not reconstructed) and
+    /// 0.299 R + 0.587 G + 0.114 B otherwise. Images with other component counts are
+    /// decoded unchanged.
+    ToGray,
+}
+
 #[cfg(test)]
 mod tests {
     use crate::jpeg_stream_reader::JpegStreamReader;
     use crate::jpeg2000::decoder::J2kDecoder;
+    use crate::{FrameInfo, JpeglsError, OutputLayout};
 
     #[test]
     fn smoke_test() {
@@ -87,6 +172,44 @@ mod tests {
         let _decoder = J2kDecoder::new(&mut JpegStreamReader::new(&[]));
     }
 
+    #[test]
+    fn test_codecs_are_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<JpegStreamReader<'static>>();
+        assert_send_sync::<crate::jpegls::JpeglsDecoder<'static>>();
+        assert_send_sync::<crate::jpegls::JpeglsEncoder<'static>>();
+        assert_send_sync::<crate::jpegls::JpeglsTranscoder<'static>>();
+        assert_send_sync::<crate::jpeg1::decoder::Jpeg1Decoder<'static>>();
+        assert_send_sync::<crate::jpeg1::encoder::Jpeg1Encoder>();
+        assert_send_sync::<J2kDecoder<'static, 'static>>();
+        assert_send_sync::<crate::jpeg2000::encoder::J2kEncoder>();
+        assert_send_sync::<crate::codec::Encoder>();
+        assert_send_sync::<crate::decoder::Decoder>();
+    }
+
+    #[test]
+    fn test_decoded_size() {
+        let frame_info = |width, height, bits_per_sample, component_count| FrameInfo {
+            width,
+            height,
+            bits_per_sample,
+            component_count,
+        };
+        for layout in [OutputLayout::Interleaved, OutputLayout::Planar] {
+            assert_eq!(frame_info(640, 480, 8, 3).decoded_size(layout), Ok(921_600));
+            assert_eq!(frame_info(640, 480, 12, 1).decoded_size(layout), Ok(614_400));
+            assert_eq!(frame_info(0, 480, 8, 3).decoded_size(layout), Ok(0));
+            assert_eq!(
+                frame_info(u32::MAX, u32::MAX, 16, i32::MAX).decoded_size(layout),
+                Err(JpeglsError::ParameterOutOfRange)
+            );
+            assert_eq!(
+                frame_info(640, 480, 8, -1).decoded_size(layout),
+                Err(JpeglsError::ParameterOutOfRange)
+            );
+        }
+    }
+
     #[test]
     fn test_decoder_htj2k_integration_final() {
         // Mock stream with SOC, CAP (HTJ2K), SIZ, COD, QCD, SOT, SOD, data, EOC
@@ -0,0 +1,182 @@
+//! Opt-in accounting of intermediate buffer memory.
+//!
+//! When the `mem-profiling` feature is enabled, every codec records the size of the
+//! heap buffers it allocates while decoding or encoding (line buffers, coefficient
+//! planes, context tables, ...) and keeps track of the high-water mark. The result
+//! is reported through [`DecodeStats`] and [`EncodeStats`], which can be queried
+//! from a decoder or encoder after the operation has completed:
+//!
+//! ```rust
+//! use jpegexp_rs::jpegls::{JpeglsDecoder, JpeglsEncoder};
+//! use jpegexp_rs::FrameInfo;
+//!
+//! let frame_info = FrameInfo { width: 64, height: 64, bits_per_sample: 8, component_count: 1 };
+//! let pixels = vec![0u8; 64 * 64];
+//! let mut encoded = vec![0u8; 16384];
+//! let mut encoder = JpeglsEncoder::new(&mut encoded);
+//! encoder.set_frame_info(frame_info).unwrap();
+//! encoder.encode(&pixels).unwrap();
+//! let stats = encoder.stats();
+//! println!("{:.0} bytes/MP", stats.bytes_per_megapixel());
+//! ```
+//!
+//! Without the feature the tracking calls compile to nothing and all reported
+//! byte counts are zero. The caller's input and output buffers are not counted;
+//! only memory the codec allocates on its own behalf is.
+
+/// Memory statistics gathered during a single decode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DecodeStats {
+    /// Peak number of bytes held in intermediate buffers at any one time.
+    pub peak_buffer_bytes: usize,
+    /// Number of pixels in the decoded frame.
+    pub pixel_count: u64,
+}
+
+/// Memory statistics gathered during a single encode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EncodeStats {
+    /// Peak number of bytes held in intermediate buffers at any one time.
+    pub peak_buffer_bytes: usize,
+    /// Number of pixels in the encoded frame.
+    pub pixel_count: u64,
+}
+
+impl DecodeStats {
+    /// Peak intermediate memory normalized to one million pixels.
+    pub fn bytes_per_megapixel(&self) -> f64 {
+        per_megapixel(self.peak_buffer_bytes, self.pixel_count)
+    }
+}
+
+impl EncodeStats {
+    /// Peak intermediate memory normalized to one million pixels.
+    pub fn bytes_per_megapixel(&self) -> f64 {
+        per_megapixel(self.peak_buffer_bytes, self.pixel_count)
+    }
+}
+
+fn per_megapixel(bytes: usize, pixel_count: u64) -> f64 {
+    if pixel_count == 0 {
+        return 0.0;
+    }
+    bytes as f64 * 1_000_000.0 / pixel_count as f64
+}
+
+/// Returns true when the crate was built with the `mem-profiling` feature.
+pub const fn is_enabled() -> bool {
+    cfg!(feature = "mem-profiling")
+}
+
+#[cfg(feature = "mem-profiling")]
+mod imp {
+    use std::cell::Cell;
+
+    thread_local! {
+        static CURRENT: Cell<usize> = const { Cell::new(0) };
+        static PEAK: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// Keeps a buffer's bytes accounted for until dropped.
+    pub(crate) struct BufferGuard {
+        bytes: usize,
+    }
+
+    impl Drop for BufferGuard {
+        fn drop(&mut self) {
+            CURRENT.with(|c| c.set(c.get().saturating_sub(self.bytes)));
+        }
+    }
+
+    pub(crate) fn track(bytes: usize) -> BufferGuard {
+        let current = CURRENT.with(|c| {
+            let value = c.get() + bytes;
+            c.set(value);
+            value
+        });
+        PEAK.with(|p| p.set(p.get().max(current)));
+        BufferGuard { bytes }
+    }
+
+    /// Measures the peak reached between `begin` and `finish`. Sessions nest, so a
+    /// transcode that decodes inside an encode still reports sensible numbers.
+    pub(crate) struct Session {
+        base: usize,
+        outer_peak: usize,
+    }
+
+    impl Session {
+        pub(crate) fn begin() -> Self {
+            let base = CURRENT.with(|c| c.get());
+            let outer_peak = PEAK.with(|p| p.replace(base));
+            Self { base, outer_peak }
+        }
+
+        pub(crate) fn finish(self) -> usize {
+            let peak = PEAK.with(|p| p.get());
+            PEAK.with(|p| p.set(self.outer_peak.max(peak)));
+            peak - self.base
+        }
+    }
+}
+
+#[cfg(not(feature = "mem-profiling"))]
+mod imp {
+    pub(crate) struct BufferGuard;
+
+    #[inline(always)]
+    pub(crate) fn track(_bytes: usize) -> BufferGuard {
+        BufferGuard
+    }
+
+    pub(crate) struct Session;
+
+    impl Session {
+        #[inline(always)]
+        pub(crate) fn begin() -> Self {
+            Session
+        }
+
+        #[inline(always)]
+        pub(crate) fn finish(self) -> usize {
+            0
+        }
+    }
+}
+
+pub(crate) use imp::{BufferGuard, Session};
+
+/// Accounts for `len` elements of `T` until the returned guard is dropped.
+#[inline(always)]
+pub(crate) fn track_elements<T>(len: usize) -> BufferGuard {
+    imp::track(len * std::mem::size_of::<T>())
+}
+
+#[cfg(all(test, feature = "mem-profiling"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_reports_peak_above_base() {
+        let _outer = track_elements::<u8>(100);
+        let session = Session::begin();
+        {
+            let _a = track_elements::<u32>(10);
+            let _b = track_elements::<u8>(5);
+        }
+        let _c = track_elements::<u8>(20);
+        assert_eq!(session.finish(), 45);
+    }
+
+    #[test]
+    fn nested_sessions_do_not_lose_outer_peak() {
+        let outer = Session::begin();
+        let _a = track_elements::<u8>(64);
+        let inner = Session::begin();
+        {
+            let _b = track_elements::<u8>(32);
+        }
+        assert_eq!(inner.finish(), 32);
+        assert_eq!(outer.finish(), 96);
+    }
+}
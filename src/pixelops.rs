@@ -0,0 +1,235 @@
+//! Pixel format conversions commonly needed after decoding: reordering channels, adding an
+//! alpha channel, mapping wide samples to 8 bits with a window and swapping the byte order
+//! of 16-bit samples.
+//!
+//! The functions work on interleaved samples (RGBRGB...), as returned by
+//! [`Decoder`](crate::Decoder) with the default [`OutputLayout`](crate::OutputLayout).
+
+use crate::error::JpeglsError;
+
+/// Number of pixels in `samples`, checking that it holds whole pixels of `channels`
+/// samples.
+fn pixel_count<T>(samples: &[T], channels: usize) -> Result<usize, JpeglsError> {
+    if channels == 0 || !samples.len().is_multiple_of(channels) {
+        return Err(JpeglsError::InvalidArgumentSize);
+    }
+    Ok(samples.len() / channels)
+}
+
+/// Reorders the channels of every pixel: channel `c` of an output pixel is channel
+/// `order[c]` of the input pixel, so `[2, 1, 0]` turns RGB into BGR and `[2, 1, 0, 3]`
+/// RGBA into BGRA. A channel may be repeated or dropped, e.g. `[0, 0, 0]` expands gray
+/// to RGB.
+///
+/// Returns [`JpeglsError::InvalidArgumentSize`] if `samples` does not hold whole pixels
+/// of `channels` samples and [`JpeglsError::InvalidArgument`] if `order` is empty or
+/// selects a channel past `channels`.
+pub fn swizzle<T: Copy>(
+    samples: &[T],
+    channels: usize,
+    order: &[usize],
+) -> Result<Vec<T>, JpeglsError> {
+    let pixels = pixel_count(samples, channels)?;
+    if order.is_empty() || order.iter().any(|&channel| channel >= channels) {
+        return Err(JpeglsError::InvalidArgument);
+    }
+
+    let mut output = Vec::with_capacity(pixels * order.len());
+    for pixel in samples.chunks_exact(channels) {
+        output.extend(order.iter().map(|&channel| pixel[channel]));
+    }
+    Ok(output)
+}
+
+/// Appends a channel holding `alpha` to every pixel, e.g. RGB to RGBA for display APIs
+/// that expect four bytes per pixel.
+///
+/// Returns [`JpeglsError::InvalidArgumentSize`] if `samples` does not hold whole pixels
+/// of `channels` samples.
+pub fn add_alpha<T: Copy>(samples: &[T], channels: usize, alpha: T) -> Result<Vec<T>, JpeglsError> {
+    let pixels = pixel_count(samples, channels)?;
+    let mut output = Vec::with_capacity(pixels * (channels + 1));
+    for pixel in samples.chunks_exact(channels) {
+        output.extend_from_slice(pixel);
+        output.push(alpha);
+    }
+    Ok(output)
+}
+
+/// Maps samples to 8 bits with the linear VOI LUT function of DICOM (PS3.3 C.11.2.1.2):
+/// samples up to the bottom of the window of `width` values around `center` become 0,
+/// samples above its top 255 and the samples in between are scaled linearly. CT images
+/// are typically shown with e.g. center 40 and width 400 (soft tissue), after the
+/// modality rescale.
+///
+/// Returns [`JpeglsError::InvalidArgument`] if `width` is less than 1 or `center` is not
+/// finite.
+pub fn window_level<T: Copy + Into<f64>>(
+    samples: &[T],
+    center: f64,
+    width: f64,
+) -> Result<Vec<u8>, JpeglsError> {
+    if !center.is_finite() || !width.is_finite() || width < 1.0 {
+        return Err(JpeglsError::InvalidArgument);
+    }
+
+    let bottom = center - 0.5 - (width - 1.0) / 2.0;
+    let top = center - 0.5 + (width - 1.0) / 2.0;
+    // A width of 1 leaves no samples between the bottom and the top.
+    let scale = 255.0 / (width - 1.0).max(1.0);
+    Ok(samples
+        .iter()
+        .map(|&sample| {
+            let x = sample.into();
+            if x <= bottom {
+                0
+            } else if x > top {
+                255
+            } else {
+                ((x - (center - 0.5)) * scale + 127.5).round() as u8
+            }
+        })
+        .collect())
+}
+
+/// Shrinks an image of `width` x `height` pixels of `channels` samples to `new_width` x
+/// `new_height` by averaging the pixels that each output pixel covers (a box filter).
+///
+/// Returns [`JpeglsError::InvalidArgumentSize`] if `samples` does not hold the image and
+/// [`JpeglsError::InvalidArgument`] if the new size is 0 or larger than the image.
+pub fn downscale_box<T: Copy + Into<i64> + TryFrom<i64>>(
+    samples: &[T],
+    width: usize,
+    height: usize,
+    channels: usize,
+    new_width: usize,
+    new_height: usize,
+) -> Result<Vec<T>, JpeglsError> {
+    if pixel_count(samples, channels)? != width * height {
+        return Err(JpeglsError::InvalidArgumentSize);
+    }
+    if !(1..=width).contains(&new_width) || !(1..=height).contains(&new_height) {
+        return Err(JpeglsError::InvalidArgument);
+    }
+
+    // Source columns (or rows) start..end covered by output column (or row) i.
+    let span =
+        |i: usize, size: usize, new_size: usize| (i * size / new_size, (i + 1) * size / new_size);
+    let mut output = Vec::with_capacity(new_width * new_height * channels);
+    let mut sums = vec![0i64; channels];
+    for oy in 0..new_height {
+        let (top, bottom) = span(oy, height, new_height);
+        for ox in 0..new_width {
+            let (left, right) = span(ox, width, new_width);
+            sums.fill(0);
+            for y in top..bottom {
+                let row = &samples[(y * width + left) * channels..(y * width + right) * channels];
+                for pixel in row.chunks_exact(channels) {
+                    for (sum, &sample) in sums.iter_mut().zip(pixel) {
+                        *sum += sample.into();
+                    }
+                }
+            }
+            let count = ((bottom - top) * (right - left)) as i64;
+            output.extend(sums.iter().map(|&sum| {
+                // The rounded average of samples lies in their range.
+                let average = (2 * sum + count).div_euclid(2 * count);
+                T::try_from(average).unwrap_or_else(|_| unreachable!())
+            }));
+        }
+    }
+    Ok(output)
+}
+
+/// Swaps the bytes of every 16-bit sample in place, e.g. to turn the little-endian samples
+/// of [`DecodedImage`](crate::DecodedImage) into big-endian ones.
+///
+/// Returns [`JpeglsError::InvalidArgumentSize`] if `bytes` has an odd length.
+pub fn swap_bytes_16(bytes: &mut [u8]) -> Result<(), JpeglsError> {
+    if !bytes.len().is_multiple_of(2) {
+        return Err(JpeglsError::InvalidArgumentSize);
+    }
+    for pair in bytes.chunks_exact_mut(2) {
+        pair.swap(0, 1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swizzle_and_add_alpha() {
+        let rgb = [1u8, 2, 3, 4, 5, 6];
+        assert_eq!(swizzle(&rgb, 3, &[2, 1, 0]).unwrap(), [3, 2, 1, 6, 5, 4]);
+        assert_eq!(
+            swizzle(&[7u16, 8], 1, &[0, 0, 0]).unwrap(),
+            [7, 7, 7, 8, 8, 8]
+        );
+        assert_eq!(swizzle(&rgb, 3, &[3]), Err(JpeglsError::InvalidArgument));
+        assert_eq!(
+            swizzle(&rgb, 4, &[0]),
+            Err(JpeglsError::InvalidArgumentSize)
+        );
+
+        assert_eq!(
+            add_alpha(&rgb, 3, 255).unwrap(),
+            [1, 2, 3, 255, 4, 5, 6, 255]
+        );
+        assert_eq!(
+            add_alpha(&rgb, 0, 255),
+            Err(JpeglsError::InvalidArgumentSize)
+        );
+    }
+
+    #[test]
+    fn test_window_level() {
+        // Center 40, width 400: -160 and below map to 0, 239 and above to 255.
+        let samples = [-1000i16, -160, -159, 40, 239, 240, 3000];
+        assert_eq!(
+            window_level(&samples, 40.0, 400.0).unwrap(),
+            [0, 0, 1, 128, 255, 255, 255]
+        );
+        assert_eq!(
+            window_level(&[99u16, 100, 101], 100.0, 1.0).unwrap(),
+            [0, 255, 255]
+        );
+        assert_eq!(
+            window_level(&[0u16], 100.0, 0.5),
+            Err(JpeglsError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn test_downscale_box() {
+        // 4x2 gray image to 2x1: every output pixel averages a 2x2 square.
+        let samples = [0u8, 10, 20, 30, 2, 12, 22, 33];
+        assert_eq!(downscale_box(&samples, 4, 2, 1, 2, 1).unwrap(), [6, 26]);
+        // 3x1 to 2x1 with two channels: the squares are 1 and 2 pixels wide.
+        let signed = [-10i16, 100, 0, 200, 10, 300];
+        assert_eq!(
+            downscale_box(&signed, 3, 1, 2, 2, 1).unwrap(),
+            [-10, 100, 5, 250]
+        );
+        assert_eq!(
+            downscale_box(&samples, 4, 2, 1, 5, 1),
+            Err(JpeglsError::InvalidArgument)
+        );
+        assert_eq!(
+            downscale_box(&samples, 4, 3, 1, 2, 1),
+            Err(JpeglsError::InvalidArgumentSize)
+        );
+    }
+
+    #[test]
+    fn test_swap_bytes_16() {
+        let mut bytes = [1, 2, 3, 4];
+        swap_bytes_16(&mut bytes).unwrap();
+        assert_eq!(bytes, [2, 1, 4, 3]);
+        assert_eq!(
+            swap_bytes_16(&mut [0; 3]),
+            Err(JpeglsError::InvalidArgumentSize)
+        );
+    }
+}
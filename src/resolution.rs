@@ -0,0 +1,186 @@
+//! Pixel density (DPI) of an image, in the same form for every format.
+//!
+//! JPEG 1 streams give it in the JFIF (APP0) segment, JPEG-LS streams in the SPIFF header
+//! and JP2 files in the capture (`resc`) and display (`resd`) resolution boxes; JPEG 2000
+//! codestreams have no field for it. [`read_resolution`] reads it from any of them
+//! without decoding, the decoders report it in
+//! [`ImageMetadata::resolution`](crate::ImageMetadata::resolution) and the encoders write
+//! it when set with [`Encoder::set_resolution`](crate::codec::Encoder::set_resolution):
+//!
+//! ```rust
+//! use jpegexp_rs::codec::{Encoder, Format};
+//! use jpegexp_rs::resolution::{read_resolution, Resolution};
+//! use jpegexp_rs::FrameInfo;
+//!
+//! let frame_info = FrameInfo { width: 8, height: 8, bits_per_sample: 8, component_count: 1 };
+//! let scan = Resolution { x_dpi: 300, y_dpi: 300 };
+//! let mut encoder = Encoder::for_format(Format::Jpeg1);
+//! encoder.set_resolution(scan);
+//! let encoded = encoder.encode(&[128; 64], &frame_info).unwrap();
+//! assert_eq!(read_resolution(&encoded), Some(scan));
+//! ```
+
+use crate::codec::{detect_format, Format};
+use crate::jpeg1::read_metadata;
+use crate::jpeg2000::jp2::Jp2Reader;
+use crate::jpeg_stream_reader::JpegStreamReader;
+use crate::jpegls::SpiffHeader;
+
+/// Horizontal and vertical pixel density in dots per inch, rounded to whole dots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resolution {
+    pub x_dpi: u32,
+    pub y_dpi: u32,
+}
+
+/// Centimetres per inch, for densities given per centimetre or metre.
+const CM_PER_INCH: f64 = 2.54;
+
+impl Resolution {
+    /// The density of a JFIF segment or SPIFF header, whose units are 1 for dots per inch
+    /// and 2 for dots per centimetre; `None` for 0, a pixel aspect ratio only, for other
+    /// units and for a zero density.
+    pub(crate) fn from_density(units: u8, x: u32, y: u32) -> Option<Self> {
+        let resolution = match units {
+            1 => Self { x_dpi: x, y_dpi: y },
+            2 => Self {
+                x_dpi: (x as f64 * CM_PER_INCH).round() as u32,
+                y_dpi: (y as f64 * CM_PER_INCH).round() as u32,
+            },
+            _ => return None,
+        };
+        (resolution.x_dpi > 0 && resolution.y_dpi > 0).then_some(resolution)
+    }
+
+    /// The density of a SPIFF header.
+    pub(crate) fn from_spiff(header: &SpiffHeader) -> Option<Self> {
+        Self::from_density(
+            header.resolution_units as u8,
+            header.horizontal_resolution,
+            header.vertical_resolution,
+        )
+    }
+
+    /// The density of a JP2 resolution box, in dots per metre.
+    pub(crate) fn from_dots_per_metre(x: f64, y: f64) -> Option<Self> {
+        let dpi = |v: f64| (v * CM_PER_INCH / 100.0).round();
+        let (x_dpi, y_dpi) = (dpi(x), dpi(y));
+        let valid = |v: f64| v >= 1.0 && v <= u32::MAX as f64;
+        (valid(x_dpi) && valid(y_dpi)).then_some(Self {
+            x_dpi: x_dpi as u32,
+            y_dpi: y_dpi as u32,
+        })
+    }
+
+    /// The density of the same image resized from `from` to `to` (width, height) pixels.
+    pub(crate) fn scaled(self, from: (u32, u32), to: (u32, u32)) -> Self {
+        let scale = |dpi: u32, old: u32, new: u32| {
+            ((dpi as u64 * new as u64 + old as u64 / 2) / old.max(1) as u64).max(1) as u32
+        };
+        Self {
+            x_dpi: scale(self.x_dpi, from.0, to.0),
+            y_dpi: scale(self.y_dpi, from.1, to.1),
+        }
+    }
+
+    /// JFIF (APP0) segment data: version 1.02, dots per inch as the unit, the density
+    /// capped at the 16-bit field and no thumbnail.
+    pub(crate) fn jfif_segment(&self) -> Vec<u8> {
+        let density = |v: u32| (v.min(u16::MAX as u32) as u16).to_be_bytes();
+        let mut data = b"JFIF\0\x01\x02\x01".to_vec();
+        data.extend_from_slice(&density(self.x_dpi));
+        data.extend_from_slice(&density(self.y_dpi));
+        data.extend_from_slice(&[0, 0]);
+        data
+    }
+}
+
+/// Reads the resolution of a JPEG 1 or JPEG-LS stream or a JP2 file without decoding it;
+/// see the [module documentation](self). JP2 files give their capture resolution, or the
+/// display resolution without one. `None` when the stream has none or its header cannot
+/// be read.
+pub fn read_resolution(data: &[u8]) -> Option<Resolution> {
+    match detect_format(data)? {
+        Format::Jpeg1 => read_metadata(data).ok()?.resolution,
+        Format::Jpegls => {
+            let mut reader = JpegStreamReader::new(data);
+            let mut spiff = None;
+            reader.read_header(&mut spiff).ok()?;
+            Resolution::from_spiff(&spiff?)
+        }
+        Format::Jpeg2000 => {
+            let mut reader = Jp2Reader::new(data);
+            reader
+                .find_capture_resolution()
+                .or_else(|| reader.find_display_resolution())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Encoder;
+    use crate::{Decoder, FrameInfo};
+
+    #[test]
+    fn test_every_format_keeps_resolution() {
+        let frame_info = FrameInfo {
+            width: 16,
+            height: 8,
+            bits_per_sample: 8,
+            component_count: 3,
+        };
+        let pixels = vec![90u8; 16 * 8 * 3];
+        let resolution = Resolution {
+            x_dpi: 600,
+            y_dpi: 300,
+        };
+        for format in [Format::Jpeg1, Format::Jpegls, Format::Jpeg2000] {
+            let encoded = Encoder::for_format(format)
+                .encode(&pixels, &frame_info)
+                .unwrap();
+            assert_eq!(read_resolution(&encoded), None, "{format:?}");
+
+            let mut encoder = Encoder::for_format(format);
+            encoder.set_resolution(resolution);
+            encoder.set_embed_checksum(true);
+            let encoded = encoder.encode(&pixels, &frame_info).unwrap();
+            assert_eq!(read_resolution(&encoded), Some(resolution), "{format:?}");
+            let image = Decoder::auto(&encoded).unwrap();
+            assert_eq!(image.metadata.resolution, Some(resolution), "{format:?}");
+            assert_eq!(image.verify_checksum(), Some(true), "{format:?}");
+
+            // A thumbnail of half the size has half the density.
+            let thumbnail = Decoder::new().decode_thumbnail(&encoded, 8).unwrap();
+            assert_eq!(
+                thumbnail.metadata.resolution,
+                Some(Resolution {
+                    x_dpi: 300,
+                    y_dpi: 150
+                }),
+                "{format:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_density_units() {
+        assert_eq!(Resolution::from_density(0, 1, 1), None);
+        assert_eq!(Resolution::from_density(1, 0, 72), None);
+        assert_eq!(
+            Resolution::from_density(2, 118, 118),
+            Some(Resolution {
+                x_dpi: 300,
+                y_dpi: 300
+            })
+        );
+        assert_eq!(
+            Resolution::from_dots_per_metre(11811.0, 3937.0),
+            Some(Resolution {
+                x_dpi: 300,
+                y_dpi: 100
+            })
+        );
+    }
+}
@@ -0,0 +1,349 @@
+//! Codec recommendation based on image statistics.
+//!
+//! [`suggest_codec`] measures how predictable an image is and combines that with the
+//! bit depth, component count and the intended use to pick one of the codecs in this
+//! crate, together with its parameters and a rough estimate of the compression ratio:
+//!
+//! ```rust
+//! use jpegexp_rs::suggest::{suggest_codec, Codec, Goal};
+//! use jpegexp_rs::FrameInfo;
+//!
+//! let frame_info = FrameInfo { width: 64, height: 64, bits_per_sample: 8, component_count: 1 };
+//! let pixels: Vec<u8> = (0..64 * 64).map(|i| (i % 64) as u8).collect();
+//! let suggestion = suggest_codec(&pixels, &frame_info, Goal::ArchivalLossless).unwrap();
+//! assert_eq!(suggestion.codec, Codec::Jpegls);
+//! assert_eq!(suggestion.near_lossless, Some(0));
+//! ```
+//!
+//! The statistics are based on the residuals of the JPEG-LS median edge detector
+//! (LOCO-I) predictor, whose zeroth-order entropy is a good estimate of the lossless
+//! bit rate. The expected ratios for the lossy codecs are heuristics and only meant to
+//! rank the options; actual results depend on the image content.
+
+use crate::error::JpeglsError;
+use crate::FrameInfo;
+
+/// What the encoded image will be used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Goal {
+    /// Long-term storage; the samples must be preserved exactly.
+    ArchivalLossless,
+    /// Display in browsers and general purpose viewers.
+    Web,
+    /// Transfer of diagnostic images over limited bandwidth; a small, bounded error is
+    /// acceptable.
+    Telerad,
+}
+
+/// Codec recommended by [`suggest_codec`], the [`Format`](crate::codec::Format) it writes.
+pub use crate::codec::Format as Codec;
+
+/// Statistics the recommendation is based on.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ImageStatistics {
+    /// Zeroth-order entropy of the sample values, in bits per sample.
+    pub sample_entropy: f64,
+    /// Zeroth-order entropy of the prediction residuals, in bits per sample.
+    pub residual_entropy: f64,
+    /// Mean absolute prediction residual.
+    pub mean_absolute_residual: f64,
+    /// Fraction of samples whose local gradients are all zero (JPEG-LS run mode).
+    pub flat_fraction: f64,
+}
+
+/// A recommended codec with its parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodecSuggestion {
+    pub codec: Codec,
+    /// Quality setting (1-100) for [`Codec::Jpeg1`] and [`Codec::Jpeg2000`].
+    pub quality: Option<u8>,
+    /// NEAR parameter for [`Codec::Jpegls`]; 0 is lossless.
+    pub near_lossless: Option<i32>,
+    /// Estimated ratio of uncompressed to compressed size.
+    pub expected_compression_ratio: f64,
+    pub statistics: ImageStatistics,
+    /// Short human readable explanation of the choice.
+    pub rationale: &'static str,
+}
+
+/// Images whose longest side reaches this size benefit from the resolution scalability
+/// of JPEG 2000 when viewed remotely.
+const PROGRESSIVE_VIEWING_SIZE: u32 = 2048;
+
+/// Images with at least this fraction of flat samples are treated as synthetic content
+/// (screenshots, overlays, segmentation masks) for which DCT coding is a poor fit.
+const SYNTHETIC_FLAT_FRACTION: f64 = 0.6;
+
+/// Recommends a codec for `pixels`, laid out as the encoders expect them: pixel
+/// interleaved, one byte per sample up to 8 bits and native-endian `u16` samples above.
+pub fn suggest_codec(
+    pixels: &[u8],
+    frame_info: &FrameInfo,
+    goal: Goal,
+) -> Result<CodecSuggestion, JpeglsError> {
+    if frame_info.width == 0 || frame_info.height == 0 {
+        return Err(JpeglsError::InvalidArgumentSize);
+    }
+    if !(1..=4).contains(&frame_info.component_count) {
+        return Err(JpeglsError::InvalidArgumentComponentCount);
+    }
+    if !(2..=16).contains(&frame_info.bits_per_sample) {
+        return Err(JpeglsError::ParameterValueNotSupported);
+    }
+
+    let statistics = analyze(pixels, frame_info)?;
+    let bits = frame_info.bits_per_sample;
+    let baseline_compatible = bits == 8 && matches!(frame_info.component_count, 1 | 3);
+    let synthetic = statistics.flat_fraction >= SYNTHETIC_FLAT_FRACTION;
+    let bits = bits as f64;
+
+    let suggestion = match goal {
+        Goal::ArchivalLossless => CodecSuggestion {
+            codec: Codec::Jpegls,
+            quality: None,
+            near_lossless: Some(0),
+            expected_compression_ratio: bits / lossless_bit_rate(&statistics),
+            statistics,
+            rationale: "JPEG-LS gives the best lossless ratio at every bit depth up to 16 bits",
+        },
+        Goal::Web if baseline_compatible && !synthetic => CodecSuggestion {
+            codec: Codec::Jpeg1,
+            quality: Some(85),
+            near_lossless: None,
+            expected_compression_ratio: bits / (0.3 * statistics.residual_entropy + 0.1),
+            statistics,
+            rationale: "baseline JPEG is decoded natively by every browser",
+        },
+        Goal::Web if baseline_compatible => CodecSuggestion {
+            codec: Codec::Jpegls,
+            quality: None,
+            near_lossless: Some(0),
+            expected_compression_ratio: bits / lossless_bit_rate(&statistics),
+            statistics,
+            rationale:
+                "mostly flat synthetic content codes smaller losslessly than with DCT ringing",
+        },
+        Goal::Web | Goal::Telerad => {
+            let large = frame_info.width.max(frame_info.height) >= PROGRESSIVE_VIEWING_SIZE;
+            if goal == Goal::Telerad && baseline_compatible && large {
+                let bit_rate = 0.25 * statistics.residual_entropy + 0.05;
+                CodecSuggestion {
+                    codec: Codec::Jpeg2000,
+                    quality: Some(90),
+                    near_lossless: None,
+                    expected_compression_ratio: bits / bit_rate,
+                    statistics,
+                    rationale: "JPEG 2000 lets large images be viewed at reduced resolution first",
+                }
+            } else {
+                let near_lossless = near_lossless_for(frame_info.bits_per_sample);
+                CodecSuggestion {
+                    codec: Codec::Jpegls,
+                    quality: None,
+                    near_lossless: Some(near_lossless),
+                    expected_compression_ratio: bits
+                        / near_lossless_bit_rate(&statistics, near_lossless),
+                    statistics,
+                    rationale: "near-lossless JPEG-LS bounds the error of every sample",
+                }
+            }
+        }
+    };
+    Ok(suggestion)
+}
+
+/// NEAR value that keeps the error a small fraction of the sample range.
+fn near_lossless_for(bits_per_sample: i32) -> i32 {
+    if bits_per_sample <= 8 {
+        2
+    } else {
+        (1 << (bits_per_sample - 10).max(0)).clamp(2, 16)
+    }
+}
+
+/// Estimated JPEG-LS bit rate: the residual entropy, but run mode codes flat areas with
+/// far less than one bit per sample.
+fn lossless_bit_rate(statistics: &ImageStatistics) -> f64 {
+    let coded = statistics.residual_entropy * (1.0 - statistics.flat_fraction);
+    coded.max(0.05) + 0.02
+}
+
+/// Quantizing the residuals with step `2 * NEAR + 1` removes about log2 of the step.
+fn near_lossless_bit_rate(statistics: &ImageStatistics, near_lossless: i32) -> f64 {
+    let saved = ((2 * near_lossless + 1) as f64).log2();
+    let coded = (statistics.residual_entropy - saved).max(0.0) * (1.0 - statistics.flat_fraction);
+    coded.max(0.05) + 0.02
+}
+
+fn analyze(pixels: &[u8], frame_info: &FrameInfo) -> Result<ImageStatistics, JpeglsError> {
+    let width = frame_info.width as usize;
+    let height = frame_info.height as usize;
+    let components = frame_info.component_count as usize;
+    let sample_count = width * height * components;
+    let bytes_per_sample = if frame_info.bits_per_sample <= 8 {
+        1
+    } else {
+        2
+    };
+    if pixels.len() < sample_count * bytes_per_sample {
+        return Err(JpeglsError::InvalidArgumentSize);
+    }
+
+    let max_value = (1i32 << frame_info.bits_per_sample) - 1;
+    let sample = |x: usize, y: usize, c: usize| -> i32 {
+        let index = (y * width + x) * components + c;
+        let value = if bytes_per_sample == 1 {
+            pixels[index] as i32
+        } else {
+            u16::from_ne_bytes([pixels[2 * index], pixels[2 * index + 1]]) as i32
+        };
+        value.min(max_value)
+    };
+
+    let mut sample_histogram = vec![0u64; max_value as usize + 1];
+    let mut residual_histogram = vec![0u64; 2 * max_value as usize + 1];
+    let mut absolute_residual_sum = 0u64;
+    let mut flat = 0u64;
+
+    for y in 0..height {
+        for x in 0..width {
+            for c in 0..components {
+                let value = sample(x, y, c);
+                // Neighbors outside the image are treated as in JPEG-LS: zero above the
+                // first line, and the sample above at the start of a line.
+                let rb = if y > 0 { sample(x, y - 1, c) } else { 0 };
+                let ra = if x > 0 { sample(x - 1, y, c) } else { rb };
+                let rc = match (x > 0, y > 0) {
+                    (true, true) => sample(x - 1, y - 1, c),
+                    (false, true) if y > 1 => sample(x, y - 2, c),
+                    _ => 0,
+                };
+                let rd = if y > 0 && x + 1 < width {
+                    sample(x + 1, y - 1, c)
+                } else {
+                    rb
+                };
+
+                let residual = value - med_predictor(ra, rb, rc);
+                sample_histogram[value as usize] += 1;
+                residual_histogram[(residual + max_value) as usize] += 1;
+                absolute_residual_sum += residual.unsigned_abs() as u64;
+                if rd == rb && rb == rc && rc == ra {
+                    flat += 1;
+                }
+            }
+        }
+    }
+
+    let total = sample_count as f64;
+    Ok(ImageStatistics {
+        sample_entropy: entropy(&sample_histogram, total),
+        residual_entropy: entropy(&residual_histogram, total),
+        mean_absolute_residual: absolute_residual_sum as f64 / total,
+        flat_fraction: flat as f64 / total,
+    })
+}
+
+/// The median edge detector of JPEG-LS (ITU-T T.87, A.4.1).
+fn med_predictor(ra: i32, rb: i32, rc: i32) -> i32 {
+    if rc >= ra.max(rb) {
+        ra.min(rb)
+    } else if rc <= ra.min(rb) {
+        ra.max(rb)
+    } else {
+        ra + rb - rc
+    }
+}
+
+fn entropy(histogram: &[u64], total: f64) -> f64 {
+    histogram
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(width: u32, height: u32, bits_per_sample: i32, component_count: i32) -> FrameInfo {
+        FrameInfo {
+            width,
+            height,
+            bits_per_sample,
+            component_count,
+        }
+    }
+
+    fn noise(len: usize) -> Vec<u8> {
+        let mut seed = 0x2545_f491u32;
+        (0..len)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                seed as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn smooth_images_compress_better_than_noise() {
+        let frame_info = frame(64, 64, 8, 1);
+        let gradient: Vec<u8> = (0..64 * 64).map(|i| ((i % 64) + (i / 64)) as u8).collect();
+        let smooth = suggest_codec(&gradient, &frame_info, Goal::ArchivalLossless).unwrap();
+        let noisy = suggest_codec(&noise(64 * 64), &frame_info, Goal::ArchivalLossless).unwrap();
+
+        assert!(smooth.statistics.residual_entropy < 1.0);
+        assert!(noisy.statistics.residual_entropy > 7.0);
+        assert!(smooth.expected_compression_ratio > 4.0);
+        assert!(noisy.expected_compression_ratio < 1.2);
+    }
+
+    #[test]
+    fn web_prefers_baseline_jpeg_for_photographic_content() {
+        let frame_info = frame(32, 32, 8, 3);
+        let suggestion = suggest_codec(&noise(32 * 32 * 3), &frame_info, Goal::Web).unwrap();
+        assert_eq!(suggestion.codec, Codec::Jpeg1);
+        assert_eq!(suggestion.quality, Some(85));
+
+        let flat = vec![200u8; 32 * 32 * 3];
+        let suggestion = suggest_codec(&flat, &frame_info, Goal::Web).unwrap();
+        assert_eq!(suggestion.codec, Codec::Jpegls);
+        assert_eq!(suggestion.near_lossless, Some(0));
+    }
+
+    #[test]
+    fn high_bit_depth_uses_near_lossless_jpegls() {
+        let frame_info = frame(16, 16, 12, 1);
+        let pixels: Vec<u8> = (0..16 * 16u16)
+            .flat_map(|i| (i * 13 % 4096).to_ne_bytes())
+            .collect();
+        for goal in [Goal::Web, Goal::Telerad] {
+            let suggestion = suggest_codec(&pixels, &frame_info, goal).unwrap();
+            assert_eq!(suggestion.codec, Codec::Jpegls);
+            assert_eq!(suggestion.near_lossless, Some(4));
+        }
+    }
+
+    #[test]
+    fn telerad_uses_jpeg2000_for_large_images() {
+        let frame_info = frame(2048, 8, 8, 1);
+        let pixels = noise(2048 * 8);
+        let suggestion = suggest_codec(&pixels, &frame_info, Goal::Telerad).unwrap();
+        assert_eq!(suggestion.codec, Codec::Jpeg2000);
+    }
+
+    #[test]
+    fn rejects_short_input() {
+        let frame_info = frame(8, 8, 16, 1);
+        assert_eq!(
+            suggest_codec(&[0u8; 8 * 8], &frame_info, Goal::Web),
+            Err(JpeglsError::InvalidArgumentSize)
+        );
+    }
+}
@@ -9,11 +9,15 @@ use wasm_bindgen::prelude::*;
 /// Image information returned from WASM API.
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
+#[derive(Debug, Clone, Copy)]
 pub struct ImageInfo {
     pub width: u32,
     pub height: u32,
     pub components: u32,
     pub bits_per_sample: u32,
+    /// Pixel density in dots per inch; 0 when the stream gives none.
+    pub x_dpi: u32,
+    pub y_dpi: u32,
 }
 
 /// Decode a JPEG 1 image to raw pixels.
@@ -33,7 +37,8 @@ pub fn decode_jpeg(data: &[u8]) -> Result<Vec<u8>, JsValue> {
         .map_err(|e| JsValue::from_str(&format!("Header error: {:?}", e)))?;
 
     let info = reader.frame_info();
-    let pixel_count = (info.width * info.height * info.component_count as u32) as usize;
+    let pixel_count =
+        eight_bit_size(info).map_err(|e| JsValue::from_str(&format!("Header error: {:?}", e)))?;
     let mut pixels = vec![0u8; pixel_count];
 
     let mut decoder = crate::jpeg1::decoder::Jpeg1Decoder::new(data);
@@ -56,9 +61,11 @@ pub fn decode_jpegls(data: &[u8]) -> Result<Vec<u8>, JsValue> {
         .read_header()
         .map_err(|e| JsValue::from_str(&format!("Header error: {:?}", e)))?;
 
-    let info = decoder.frame_info();
-    let pixel_count = (info.width * info.height * info.component_count as u32) as usize;
-    let mut pixels = vec![0u8; pixel_count];
+    let byte_count = decoder
+        .frame_info()
+        .decoded_size(crate::OutputLayout::Interleaved)
+        .map_err(|e| JsValue::from_str(&format!("Header error: {:?}", e)))?;
+    let mut pixels = vec![0u8; byte_count];
 
     decoder
         .decode(&mut pixels)
@@ -71,47 +78,7 @@ pub fn decode_jpegls(data: &[u8]) -> Result<Vec<u8>, JsValue> {
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
 pub fn get_image_info(data: &[u8]) -> Result<ImageInfo, JsValue> {
-    if data.starts_with(&[0xFF, 0xD8]) {
-        // JPEG 1
-        let mut reader = crate::jpeg_stream_reader::JpegStreamReader::new(data);
-        let mut spiff = None;
-        reader
-            .read_header(&mut spiff)
-            .map_err(|e| JsValue::from_str(&format!("{:?}", e)))?;
-        let info = reader.frame_info();
-        Ok(ImageInfo {
-            width: info.width,
-            height: info.height,
-            components: info.component_count as u32,
-            bits_per_sample: info.bits_per_sample as u32,
-        })
-    } else if data.starts_with(&[0xFF, 0x4F]) || data.starts_with(b"\x00\x00\x00\x0CjP") {
-        // JPEG 2000
-        let mut reader = crate::jpeg_stream_reader::JpegStreamReader::new(data);
-        let mut decoder = crate::jpeg2000::decoder::J2kDecoder::new(&mut reader);
-        let image = decoder
-            .decode()
-            .map_err(|e| JsValue::from_str(&format!("{:?}", e)))?;
-        Ok(ImageInfo {
-            width: image.width,
-            height: image.height,
-            components: image.component_count,
-            bits_per_sample: 8, // Assume 8-bit for now
-        })
-    } else {
-        // Assume JPEG-LS
-        let mut decoder = crate::jpegls::JpeglsDecoder::new(data);
-        decoder
-            .read_header()
-            .map_err(|e| JsValue::from_str(&format!("{:?}", e)))?;
-        let info = decoder.frame_info();
-        Ok(ImageInfo {
-            width: info.width,
-            height: info.height,
-            components: info.component_count as u32,
-            bits_per_sample: info.bits_per_sample as u32,
-        })
-    }
+    read_info(data).map_err(|e| JsValue::from_str(&format!("{:?}", e)))
 }
 
 /// Encode raw pixels to JPEG.
@@ -122,6 +89,50 @@ pub fn encode_jpeg(
     width: u32,
     height: u32,
     components: u32,
+) -> Result<Vec<u8>, JsValue> {
+    encode_jpeg_with_options(pixels, width, height, components, None)
+}
+
+/// Encode raw pixels to JPEG-LS.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub fn encode_jpegls(
+    pixels: &[u8],
+    width: u32,
+    height: u32,
+    components: u32,
+) -> Result<Vec<u8>, JsValue> {
+    encode_jpegls_with_options(pixels, width, height, components, None, None)
+}
+
+/// Decode a JPEG, JPEG-LS or JPEG 2000 image to interleaved raw pixels.
+///
+/// The format is detected from the stream. Samples wider than 8 bits are returned as
+/// little-endian 16-bit values; use `new Uint16Array(pixels.buffer)` to read them.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub fn decode(data: &[u8]) -> Result<Vec<u8>, JsValue> {
+    decode_any(data).map_err(|e| JsValue::from_str(&e))
+}
+
+/// Get image information without decoding: only the headers are read.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = getInfo)]
+pub fn get_info(data: &[u8]) -> Result<ImageInfo, JsValue> {
+    get_image_info(data)
+}
+
+/// Encode raw pixels to JPEG.
+///
+/// `quality` (1-100) defaults to the encoder's standard tables when omitted.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = encodeJpeg)]
+pub fn encode_jpeg_with_options(
+    pixels: &[u8],
+    width: u32,
+    height: u32,
+    components: u32,
+    quality: Option<u8>,
 ) -> Result<Vec<u8>, JsValue> {
     let frame_info = crate::FrameInfo {
         width,
@@ -130,43 +141,130 @@ pub fn encode_jpeg(
         component_count: components as i32,
     };
 
-    let mut dest = vec![0u8; pixels.len() * 2];
     let mut encoder = crate::jpeg1::encoder::Jpeg1Encoder::new();
+    if let Some(quality) = quality {
+        encoder.set_quality(quality);
+    }
+    let mut dest = vec![0u8; encoder.estimated_destination_size(&frame_info)];
     let len = encoder
         .encode(pixels, &frame_info, &mut dest)
-        .map_err(|e| JsValue::from_str(&format!("{:?}", e)))?;
+        .map_err(to_js_error)?;
     dest.truncate(len);
     Ok(dest)
 }
 
 /// Encode raw pixels to JPEG-LS.
+///
+/// `near_lossless` is the NEAR parameter (0, the default, is lossless). With
+/// `bits_per_sample` above 8 the pixels are little-endian 16-bit values, e.g. the bytes of
+/// a `Uint16Array`.
 #[cfg(target_arch = "wasm32")]
-#[wasm_bindgen]
-pub fn encode_jpegls(
+#[wasm_bindgen(js_name = encodeJpegLs)]
+pub fn encode_jpegls_with_options(
     pixels: &[u8],
     width: u32,
     height: u32,
     components: u32,
+    near_lossless: Option<i32>,
+    bits_per_sample: Option<i32>,
 ) -> Result<Vec<u8>, JsValue> {
     let frame_info = crate::FrameInfo {
         width,
         height,
-        bits_per_sample: 8,
+        bits_per_sample: bits_per_sample.unwrap_or(8),
         component_count: components as i32,
     };
 
-    let mut dest = vec![0u8; pixels.len() * 2];
+    let capacity = crate::jpegls::JpeglsEncoder::estimated_destination_size(
+        &frame_info,
+        crate::jpegls::InterleaveMode::None,
+    );
+    let mut dest = vec![0u8; capacity];
     let mut encoder = crate::jpegls::JpeglsEncoder::new(&mut dest);
+    encoder.set_frame_info(frame_info).map_err(to_js_error)?;
     encoder
-        .set_frame_info(frame_info)
-        .map_err(|e| JsValue::from_str(&format!("{:?}", e)))?;
-    let len = encoder
-        .encode(pixels)
-        .map_err(|e| JsValue::from_str(&format!("{:?}", e)))?;
+        .set_near_lossless(near_lossless.unwrap_or(0))
+        .map_err(to_js_error)?;
+    let len = encoder.encode(pixels).map_err(to_js_error)?;
     dest.truncate(len);
     Ok(dest)
 }
 
+/// Collects a compressed stream that arrives in chunks, such as the reads of a
+/// `fetch()` body, so the header is available before the whole file has been received.
+///
+/// ```js
+/// const decoder = new StreamDecoder();
+/// for await (const chunk of response.body) {
+///   decoder.push(chunk);
+///   const info = decoder.info();
+///   if (info) resizeCanvas(info.width, info.height);
+/// }
+/// const pixels = decoder.decode();
+/// ```
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct StreamDecoder {
+    data: Vec<u8>,
+    info: Option<ImageInfo>,
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+impl StreamDecoder {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append the next chunk of the stream.
+    pub fn push(&mut self, chunk: &[u8]) {
+        self.data.extend_from_slice(chunk);
+    }
+
+    /// Number of bytes received so far.
+    #[wasm_bindgen(getter, js_name = bytesReceived)]
+    pub fn bytes_received(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Whether the whole stream has been received: the end of image (or end of
+    /// codestream) marker, or for a JP2 file the last of its boxes, which may follow the
+    /// codestream box.
+    #[wasm_bindgen(js_name = isComplete)]
+    pub fn is_complete(&self) -> bool {
+        if is_jp2(&self.data) {
+            crate::jpeg2000::jp2::Jp2Reader::new(&self.data).is_complete()
+        } else {
+            self.data.ends_with(&[0xFF, 0xD9])
+        }
+    }
+
+    /// Image information, or `undefined` until enough of the header has arrived. For
+    /// JPEG 2000, that is the main header of the codestream.
+    pub fn info(&mut self) -> Option<ImageInfo> {
+        if self.info.is_none() {
+            self.info = read_info(&self.data).ok();
+        }
+        self.info
+    }
+
+    /// Decode the received stream to interleaved raw pixels, like `decode`.
+    pub fn decode(&self) -> Result<Vec<u8>, JsValue> {
+        if !self.is_complete() {
+            return Err(JsValue::from_str("Stream is incomplete"));
+        }
+        decode(&self.data)
+    }
+
+    /// Discard the received data to start on a new stream.
+    pub fn reset(&mut self) {
+        self.data.clear();
+        self.info = None;
+    }
+}
+
 /// Transcode between formats (decode + encode).
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
@@ -194,7 +292,7 @@ fn decode_jpeg_internal(data: &[u8]) -> Result<(Vec<u8>, u32, u32, u32), JsValue
     let height = info.height;
     let components = info.component_count as u32;
 
-    let pixel_count = (width * height * components) as usize;
+    let pixel_count = eight_bit_size(info).map_err(|e| JsValue::from_str(&format!("{:?}", e)))?;
     let mut pixels = vec![0u8; pixel_count];
 
     let mut decoder = crate::jpeg1::decoder::Jpeg1Decoder::new(data);
@@ -207,3 +305,95 @@ fn decode_jpeg_internal(data: &[u8]) -> Result<(Vec<u8>, u32, u32, u32), JsValue
 
     Ok((pixels, width, height, components))
 }
+
+/// Size of a JPEG 1 image as decoded: the JPEG 1 decoder writes 8-bit samples.
+#[cfg(target_arch = "wasm32")]
+fn eight_bit_size(info: crate::FrameInfo) -> Result<usize, crate::JpeglsError> {
+    crate::FrameInfo {
+        bits_per_sample: 8,
+        ..info
+    }
+    .decoded_size(crate::OutputLayout::Interleaved)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_info(data: &[u8]) -> Result<ImageInfo, crate::JpeglsError> {
+    let info = read_frame_info(data)?;
+    let resolution = crate::resolution::read_resolution(data);
+    Ok(ImageInfo {
+        width: info.width,
+        height: info.height,
+        components: info.component_count as u32,
+        bits_per_sample: info.bits_per_sample as u32,
+        x_dpi: resolution.map_or(0, |r| r.x_dpi),
+        y_dpi: resolution.map_or(0, |r| r.y_dpi),
+    })
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_frame_info(data: &[u8]) -> Result<crate::FrameInfo, crate::JpeglsError> {
+    Ok(if data.starts_with(&[0xFF, 0xD8]) {
+        // JPEG 1 and JPEG-LS
+        let mut reader = crate::jpeg_stream_reader::JpegStreamReader::new(data);
+        let mut spiff = None;
+        reader.read_header(&mut spiff)?;
+        reader.frame_info()
+    } else if is_jpeg2000(data) {
+        // Only the main header; the tiles are not read.
+        let mut reader = crate::jpeg_stream_reader::JpegStreamReader::new(data);
+        let mut parser = crate::jpeg2000::parser::J2kParser::new(&mut reader);
+        let image = parser.parse_info()?;
+        let (width, height) = image.size();
+        crate::FrameInfo {
+            width,
+            height,
+            bits_per_sample: image.channel_bits_per_sample() as i32,
+            component_count: image.channel_count() as i32,
+        }
+    } else {
+        // Assume JPEG-LS
+        let mut decoder = crate::jpegls::JpeglsDecoder::new(data);
+        decoder.read_header()?;
+        decoder.frame_info()
+    })
+}
+
+#[cfg(target_arch = "wasm32")]
+fn decode_any(data: &[u8]) -> Result<Vec<u8>, String> {
+    if is_jpeg2000(data) {
+        let mut reader = crate::jpeg_stream_reader::JpegStreamReader::new(data);
+        let mut decoder = crate::jpeg2000::decoder::J2kDecoder::new(&mut reader);
+        let image = decoder.decode().map_err(|e| format!("{:?}", e))?;
+        return image.reconstruct_pixels();
+    }
+
+    let byte_count = read_frame_info(data)
+        .and_then(|info| info.decoded_size(crate::OutputLayout::Interleaved))
+        .map_err(|e| format!("{:?}", e))?;
+    let mut pixels = vec![0u8; byte_count];
+    let result = if crate::jpeg_stream_reader::is_jpegls(data) || !data.starts_with(&[0xFF, 0xD8])
+    {
+        let mut decoder = crate::jpegls::JpeglsDecoder::new(data);
+        decoder.read_header().and_then(|_| decoder.decode(&mut pixels))
+    } else {
+        let mut decoder = crate::jpeg1::decoder::Jpeg1Decoder::new(data);
+        decoder.read_header().and_then(|_| decoder.decode(&mut pixels))
+    };
+    result.map_err(|e| format!("{:?}", e))?;
+    Ok(pixels)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn is_jpeg2000(data: &[u8]) -> bool {
+    data.starts_with(&[0xFF, 0x4F]) || is_jp2(data)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn is_jp2(data: &[u8]) -> bool {
+    data.starts_with(b"\x00\x00\x00\x0CjP")
+}
+
+#[cfg(target_arch = "wasm32")]
+fn to_js_error(e: impl std::fmt::Debug) -> JsValue {
+    JsValue::from_str(&format!("{:?}", e))
+}
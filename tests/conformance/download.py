@@ -0,0 +1,97 @@
+#!/usr/bin/env python3
+"""
+Download the conformance streams and reference images used by tests/conformance:
+
+- jpegls/:   the ITU-T T.87 test images, from the CharLS repository
+- jpeg2000/: the ISO/IEC 15444-4 class-0 profile-0 codestreams and their reference
+             PGX images, from the OpenJPEG test data repository
+
+Files already present are kept. Use --dest (or JPEGEXP_CONFORMANCE_DIR) to download
+elsewhere, and --jpegls-url / --jpeg2000-url to use a mirror.
+"""
+
+import argparse
+import os
+import sys
+import urllib.error
+import urllib.request
+from pathlib import Path
+
+JPEGLS_URL = "https://raw.githubusercontent.com/team-charls/charls/main/test/conformance"
+JPEG2000_URL = "https://raw.githubusercontent.com/uclouvain/openjpeg-data/master"
+
+JPEGLS_FILES = [
+    "TEST8.PPM",
+    "TEST8BS2.PGM",
+    "TEST16.PGM",
+    "T8C0E0.JLS",
+    "T8C1E0.JLS",
+    "T8C2E0.JLS",
+    "T8C0E3.JLS",
+    "T8C1E3.JLS",
+    "T8C2E3.JLS",
+    "T8NDE0.JLS",
+    "T8NDE3.JLS",
+    "T16E0.JLS",
+    "T16E3.JLS",
+]
+
+# Component count of each profile-0 codestream, which names its references.
+JPEG2000_COMPONENTS = [1, 1, 1, 3, 4, 4, 3, 3, 1, 3, 1, 1, 4, 3, 1, 1]
+
+
+def jpeg2000_files():
+    for number, components in enumerate(JPEG2000_COMPONENTS, start=1):
+        yield f"input/conformance/p0_{number:02}.j2k"
+        if components == 1:
+            yield f"baseline/conformance/c0p0_{number:02}.pgx"
+        else:
+            for c in range(components):
+                yield f"baseline/conformance/c0p0_{number:02}_{c}.pgx"
+
+
+def fetch(url, path):
+    """Download url to path unless it exists; returns False on failure."""
+    if path.exists():
+        return True
+    try:
+        with urllib.request.urlopen(url, timeout=60) as response:
+            data = response.read()
+    except urllib.error.URLError as e:
+        print(f"  failed: {url}: {e}", file=sys.stderr)
+        return False
+    path.write_bytes(data)
+    print(f"  {path.name} ({len(data)} bytes)")
+    return True
+
+
+def main():
+    default_dest = os.environ.get(
+        "JPEGEXP_CONFORMANCE_DIR", Path(__file__).resolve().parent / "data"
+    )
+    parser = argparse.ArgumentParser(description=__doc__.strip().splitlines()[0])
+    parser.add_argument("--dest", type=Path, default=Path(default_dest))
+    parser.add_argument("--jpegls-url", default=JPEGLS_URL)
+    parser.add_argument("--jpeg2000-url", default=JPEG2000_URL)
+    args = parser.parse_args()
+
+    failures = 0
+    jpegls = args.dest / "jpegls"
+    jpegls.mkdir(parents=True, exist_ok=True)
+    print(f"JPEG-LS -> {jpegls}")
+    for name in JPEGLS_FILES:
+        failures += not fetch(f"{args.jpegls_url}/{name}", jpegls / name)
+
+    jpeg2000 = args.dest / "jpeg2000"
+    jpeg2000.mkdir(parents=True, exist_ok=True)
+    print(f"JPEG 2000 -> {jpeg2000}")
+    for name in jpeg2000_files():
+        failures += not fetch(f"{args.jpeg2000_url}/{name}", jpeg2000 / Path(name).name)
+
+    if failures:
+        print(f"{failures} file(s) could not be downloaded; their tests will be skipped")
+    return 1 if failures else 0
+
+
+if __name__ == "__main__":
+    sys.exit(main())
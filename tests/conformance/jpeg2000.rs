@@ -0,0 +1,169 @@
+//! ISO/IEC 15444-4 class-0 conformance: the profile-0 codestreams, decoded at the
+//! resolution of their reference images, must stay within the peak and mean squared
+//! error allowed for each component (Table C.1).
+
+use crate::loader::{errors, find, load, parse_pgx, Reference};
+use jpegexp_rs::jpeg2000::decoder::J2kDecoder;
+use jpegexp_rs::jpeg2000::image::{J2kSamples, J2kUpsampling};
+use jpegexp_rs::jpeg_stream_reader::JpegStreamReader;
+
+/// Reads the reference of `component`: `c0p0_NN.pgx` for a single-component image,
+/// `c0p0_NN_<component>.pgx` otherwise.
+fn reference(number: u32, component: usize, components: usize) -> Option<Reference> {
+    let name = if components == 1 {
+        format!("c0p0_{:02}.pgx", number)
+    } else {
+        format!("c0p0_{:02}_{}.pgx", number, component)
+    };
+    match find("jpeg2000", &name) {
+        Some(data) => Some(parse_pgx(&data)),
+        None => {
+            eprintln!("skipped: reference {} not found", name);
+            None
+        }
+    }
+}
+
+fn samples(samples: J2kSamples) -> Vec<i32> {
+    match samples {
+        J2kSamples::U8(s) => s.into_iter().map(i32::from).collect(),
+        J2kSamples::I8(s) => s.into_iter().map(i32::from).collect(),
+        J2kSamples::U16(s) => s.into_iter().map(i32::from).collect(),
+        J2kSamples::I16(s) => s.into_iter().map(i32::from).collect(),
+    }
+}
+
+/// Decodes `p0_NN.j2k` with `reduce` resolution levels discarded and compares every
+/// component with its reference.
+fn check(number: u32, reduce: u8, peak: u32, mse: f64) {
+    let stream = format!("p0_{:02}.j2k", number);
+    let Some(encoded) = load("jpeg2000", &stream) else {
+        return;
+    };
+    let mut reader = JpegStreamReader::new(&encoded);
+    let mut decoder = J2kDecoder::new(&mut reader);
+    let image = decoder
+        .decode()
+        .unwrap_or_else(|e| panic!("{}: {:?}", stream, e));
+    let levels = image.cod.as_ref().map_or(0, |cod| cod.decomposition_levels);
+    let preview = image
+        .reconstruct_resolution(levels.saturating_sub(reduce), J2kUpsampling::Nearest)
+        .unwrap_or_else(|e| panic!("{}: {}", stream, e));
+    let (width, height) = (preview.width as usize, preview.height as usize);
+    let components = image.components.len();
+    let decoded = samples(preview.samples);
+
+    for (c, info) in image.components.iter().enumerate() {
+        let Some(expected) = reference(number, c, components) else {
+            return;
+        };
+        // Subsampled components come back on the full grid; take one sample per block.
+        let (dx, dy) = (info.dx.max(1) as usize, info.dy.max(1) as usize);
+        assert_eq!(
+            (expected.width, expected.height),
+            (width.div_ceil(dx), height.div_ceil(dy)),
+            "{} component {}: size",
+            stream,
+            c
+        );
+        let mut component = Vec::with_capacity(expected.samples.len());
+        for v in 0..expected.height {
+            for u in 0..expected.width {
+                let (x, y) = ((u * dx).min(width - 1), (v * dy).min(height - 1));
+                component.push(decoded[(y * width + x) * components + c]);
+            }
+        }
+
+        let (component_peak, component_mse) = errors(&component, &expected.samples);
+        assert!(
+            component_peak <= peak && component_mse <= mse,
+            "{} component {}: peak error {} (allowed {}), MSE {:.3} (allowed {})",
+            stream,
+            c,
+            component_peak,
+            peak,
+            component_mse,
+            mse
+        );
+    }
+}
+
+#[test]
+fn p0_01() {
+    check(1, 0, 0, 0.0);
+}
+
+#[test]
+fn p0_02() {
+    check(2, 0, 0, 0.0);
+}
+
+#[test]
+fn p0_03() {
+    check(3, 0, 0, 0.0);
+}
+
+#[test]
+fn p0_04() {
+    check(4, 3, 33, 55.8);
+}
+
+#[test]
+fn p0_05() {
+    check(5, 3, 54, 68.0);
+}
+
+#[test]
+fn p0_06() {
+    check(6, 3, 109, 743.0);
+}
+
+#[test]
+fn p0_07() {
+    check(7, 0, 10, 0.34);
+}
+
+#[test]
+fn p0_08() {
+    check(8, 5, 7, 6.72);
+}
+
+#[test]
+fn p0_09() {
+    check(9, 2, 4, 1.47);
+}
+
+#[test]
+fn p0_10() {
+    check(10, 0, 10, 2.84);
+}
+
+#[test]
+fn p0_11() {
+    check(11, 0, 0, 0.0);
+}
+
+#[test]
+fn p0_12() {
+    check(12, 0, 0, 0.0);
+}
+
+#[test]
+fn p0_13() {
+    check(13, 0, 0, 0.0);
+}
+
+#[test]
+fn p0_14() {
+    check(14, 2, 0, 0.0);
+}
+
+#[test]
+fn p0_15() {
+    check(15, 0, 0, 0.0);
+}
+
+#[test]
+fn p0_16() {
+    check(16, 0, 0, 0.0);
+}
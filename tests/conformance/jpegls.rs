@@ -0,0 +1,105 @@
+//! ITU-T T.87 Annex E: every test stream decodes to its source image, exactly for the
+//! lossless ones and within NEAR for the near-lossless ones.
+//!
+//! T8SSE0/T8SSE3 are left out: their components are subsampled, which the decoder does
+//! not support.
+
+use crate::loader::{errors, load, parse_pnm};
+use jpegexp_rs::jpegls::JpeglsDecoder;
+
+fn check(stream: &str, source: &str, near_lossless: u32) {
+    let (Some(encoded), Some(source)) = (load("jpegls", stream), load("jpegls", source)) else {
+        return;
+    };
+    let expected = parse_pnm(&source);
+
+    let mut decoder = JpeglsDecoder::new(&encoded);
+    decoder
+        .read_header()
+        .unwrap_or_else(|e| panic!("{}: {}", stream, e));
+    let frame_info = decoder.frame_info();
+    let size = (
+        frame_info.width,
+        frame_info.height,
+        frame_info.component_count,
+    );
+    assert_eq!(
+        (size.0 as usize, size.1 as usize, size.2 as usize),
+        (expected.width, expected.height, expected.components),
+        "{}: width, height and component count",
+        stream
+    );
+
+    let wide = frame_info.bits_per_sample > 8;
+    let mut decoded = vec![0u8; expected.samples.len() * if wide { 2 } else { 1 }];
+    decoder
+        .decode(&mut decoded)
+        .unwrap_or_else(|e| panic!("{}: {}", stream, e));
+    let decoded: Vec<i32> = if wide {
+        decoded
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]) as i32)
+            .collect()
+    } else {
+        decoded.into_iter().map(i32::from).collect()
+    };
+
+    let (peak, _) = errors(&decoded, &expected.samples);
+    assert!(
+        peak <= near_lossless,
+        "{}: peak error {} exceeds NEAR = {}",
+        stream,
+        peak,
+        near_lossless
+    );
+}
+
+#[test]
+fn t8c0e0() {
+    check("T8C0E0.JLS", "TEST8.PPM", 0);
+}
+
+#[test]
+fn t8c1e0() {
+    check("T8C1E0.JLS", "TEST8.PPM", 0);
+}
+
+#[test]
+fn t8c2e0() {
+    check("T8C2E0.JLS", "TEST8.PPM", 0);
+}
+
+#[test]
+fn t8c0e3() {
+    check("T8C0E3.JLS", "TEST8.PPM", 3);
+}
+
+#[test]
+fn t8c1e3() {
+    check("T8C1E3.JLS", "TEST8.PPM", 3);
+}
+
+#[test]
+fn t8c2e3() {
+    check("T8C2E3.JLS", "TEST8.PPM", 3);
+}
+
+#[test]
+fn t8nde0() {
+    check("T8NDE0.JLS", "TEST8BS2.PGM", 0);
+}
+
+#[test]
+fn t8nde3() {
+    check("T8NDE3.JLS", "TEST8BS2.PGM", 3);
+}
+
+#[test]
+fn t16e0() {
+    check("T16E0.JLS", "TEST16.PGM", 0);
+}
+
+#[test]
+fn t16e3() {
+    check("T16E3.JLS", "TEST16.PGM", 3);
+}
@@ -0,0 +1,156 @@
+//! Finds the conformance files and reads the reference images (PGM/PPM and PGX).
+
+use std::path::PathBuf;
+
+/// Directory holding the conformance files: `$JPEGEXP_CONFORMANCE_DIR`, or
+/// `tests/conformance/data` as filled by `download.py`.
+pub fn data_dir() -> PathBuf {
+    match std::env::var_os("JPEGEXP_CONFORMANCE_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/conformance/data"),
+    }
+}
+
+/// Reads `name` from `subdir` of the data directory, ignoring the case of the file name:
+/// the T.87 images are distributed in upper case but often copied in lower case.
+pub fn find(subdir: &str, name: &str) -> Option<Vec<u8>> {
+    let dir = data_dir().join(subdir);
+    let entry = std::fs::read_dir(&dir)
+        .ok()?
+        .flatten()
+        .find(|entry| entry.file_name().eq_ignore_ascii_case(name))?;
+    Some(std::fs::read(entry.path()).unwrap_or_else(|e| panic!("{}: {}", name, e)))
+}
+
+/// Reads `name` like [`find`], or reports it as skipped when it is absent.
+pub fn load(subdir: &str, name: &str) -> Option<Vec<u8>> {
+    let data = find(subdir, name);
+    if data.is_none() {
+        eprintln!(
+            "skipped: {}/{} not found in {} (run tests/conformance/download.py)",
+            subdir,
+            name,
+            data_dir().display()
+        );
+    }
+    data
+}
+
+/// A reference image: interleaved samples of one or more components.
+#[derive(Debug)]
+pub struct Reference {
+    pub width: usize,
+    pub height: usize,
+    pub components: usize,
+    pub samples: Vec<i32>,
+}
+
+/// Parses a binary PGM (P5) or PPM (P6) image; samples above 8 bits are big-endian.
+pub fn parse_pnm(data: &[u8]) -> Reference {
+    let components = match &data[..2] {
+        b"P5" => 1,
+        b"P6" => 3,
+        magic => panic!("not a binary PGM/PPM image: {:?}", magic),
+    };
+    let mut pos = 2;
+    let mut fields = [0usize; 3];
+    for field in &mut fields {
+        loop {
+            while data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if data[pos] != b'#' {
+                break;
+            }
+            while data[pos] != b'\n' {
+                pos += 1;
+            }
+        }
+        let start = pos;
+        while data[pos].is_ascii_digit() {
+            pos += 1;
+        }
+        *field = std::str::from_utf8(&data[start..pos])
+            .unwrap()
+            .parse()
+            .unwrap();
+    }
+    // A single whitespace character separates the header from the samples.
+    let [width, height, max_value] = fields;
+    let samples = sample_values(&data[pos + 1..], max_value > 255, true, false);
+    Reference {
+        width,
+        height,
+        components,
+        samples,
+    }
+}
+
+/// Parses a PGX image, the single-component format of the JPEG 2000 conformance suite:
+/// a `PG <ML|LM> [+|-] <depth> <width> <height>` line followed by the samples, in one
+/// byte up to 8 bits and two bytes up to 16 bits, big-endian for `ML`.
+pub fn parse_pgx(data: &[u8]) -> Reference {
+    let end = data.iter().position(|&b| b == b'\n').expect("PGX header");
+    let header = std::str::from_utf8(&data[..end]).unwrap();
+    // The sign may be attached to the depth ("-12") or missing altogether.
+    let header = header.replace('+', " + ").replace('-', " - ");
+    let mut tokens = header.split_whitespace();
+    assert_eq!(tokens.next(), Some("PG"), "not a PGX image");
+    let big_endian = match tokens.next() {
+        Some("ML") => true,
+        Some("LM") => false,
+        other => panic!("PGX byte order {:?}", other),
+    };
+    let mut tokens: Vec<&str> = tokens.collect();
+    let signed = tokens.first() == Some(&"-");
+    if matches!(tokens.first(), Some(&"-") | Some(&"+")) {
+        tokens.remove(0);
+    }
+    let number = |i: usize| tokens[i].parse::<usize>().unwrap();
+    let (depth, width, height) = (number(0), number(1), number(2));
+    assert!(depth <= 16, "{}-bit PGX images are not supported", depth);
+    let samples = sample_values(&data[end + 1..], depth > 8, big_endian, signed);
+    Reference {
+        width,
+        height,
+        components: 1,
+        samples,
+    }
+}
+
+fn sample_values(data: &[u8], wide: bool, big_endian: bool, signed: bool) -> Vec<i32> {
+    if !wide {
+        return data
+            .iter()
+            .map(|&b| if signed { b as i8 as i32 } else { b as i32 })
+            .collect();
+    }
+    data.chunks_exact(2)
+        .map(|pair| {
+            let bytes = [pair[0], pair[1]];
+            let value = if big_endian {
+                u16::from_be_bytes(bytes)
+            } else {
+                u16::from_le_bytes(bytes)
+            };
+            if signed {
+                value as i16 as i32
+            } else {
+                value as i32
+            }
+        })
+        .collect()
+}
+
+/// Peak absolute error and mean squared error between decoded and reference samples.
+pub fn errors(decoded: &[i32], reference: &[i32]) -> (u32, f64) {
+    assert_eq!(decoded.len(), reference.len(), "sample count");
+    let mut peak = 0;
+    let mut squares = 0u64;
+    for (&a, &b) in decoded.iter().zip(reference) {
+        let error = a.abs_diff(b);
+        peak = peak.max(error);
+        squares += error as u64 * error as u64;
+    }
+    (peak, squares as f64 / reference.len().max(1) as f64)
+}
@@ -0,0 +1,10 @@
+//! Conformance tests against the reference streams of the standards: the ITU-T T.87
+//! JPEG-LS test images and the ISO/IEC 15444-4 class-0 JPEG 2000 codestreams.
+//!
+//! The streams are not checked in. Fetch them with `tests/conformance/download.py`, then
+//! run `cargo test --features conformance --test conformance`. Without the `conformance`
+//! feature this target is not built; streams missing from the data directory are skipped.
+
+mod jpeg2000;
+mod jpegls;
+mod loader;
@@ -0,0 +1,166 @@
+//! Golden-file regression tests for the encoders.
+//!
+//! Each case encodes a synthetic image in deterministic mode and compares the stream byte
+//! for byte with `tests/golden/<name>`, so an unintended change to the encoded output,
+//! which would break deduplication by hash in an archive, fails here. After an intended
+//! change, regenerate the files with `JPEGEXP_BLESS=1 cargo test --test golden` and
+//! review them with the rest of the change.
+
+use std::path::PathBuf;
+
+use jpegexp_rs::codec::{Encoder, Format};
+use jpegexp_rs::jpegls::{InterleaveMode, JpeglsEncoderBuilder};
+use jpegexp_rs::FrameInfo;
+
+/// Smooth gradients with a noisy band, so that the encoders take both their run/flat and
+/// their detailed paths. Samples wider than 8 bits are 16-bit native-endian values.
+fn test_image(frame_info: &FrameInfo) -> Vec<u8> {
+    let (width, height) = (frame_info.width, frame_info.height);
+    let components = frame_info.component_count as u32;
+    let max_value = (1u32 << frame_info.bits_per_sample) - 1;
+    let mut seed = 0x2545_f491u32;
+    let mut samples = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            for c in 0..components {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                let value = if (y / 4) % 3 == 1 {
+                    (seed >> 8) % (max_value + 1)
+                } else {
+                    (x * 7 + y * 3 + c * 40) * max_value / (width * 7 + height * 3 + 80)
+                };
+                samples.push(value);
+            }
+        }
+    }
+    if frame_info.bits_per_sample <= 8 {
+        samples.iter().map(|&v| v as u8).collect()
+    } else {
+        samples
+            .iter()
+            .flat_map(|&v| (v as u16).to_ne_bytes())
+            .collect()
+    }
+}
+
+fn frame(width: u32, height: u32, bits_per_sample: i32, component_count: i32) -> FrameInfo {
+    FrameInfo {
+        width,
+        height,
+        bits_per_sample,
+        component_count,
+    }
+}
+
+fn check_golden(name: &str, encoded: &[u8]) {
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "golden", name]
+        .iter()
+        .collect();
+    if std::env::var_os("JPEGEXP_BLESS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, encoded).unwrap();
+        return;
+    }
+    let expected = std::fs::read(&path)
+        .unwrap_or_else(|e| panic!("{}: {e}; run with JPEGEXP_BLESS=1 to create it", name));
+    if encoded != expected {
+        let first_difference = encoded
+            .iter()
+            .zip(&expected)
+            .position(|(a, b)| a != b)
+            .unwrap_or(encoded.len().min(expected.len()));
+        panic!(
+            "{}: the stream differs from the golden file at byte {} ({} bytes, expected {})",
+            name,
+            first_difference,
+            encoded.len(),
+            expected.len()
+        );
+    }
+}
+
+fn encode(mut encoder: Encoder, frame_info: &FrameInfo) -> Vec<u8> {
+    encoder.set_encode_deterministic(true);
+    encoder.encode(&test_image(frame_info), frame_info).unwrap()
+}
+
+#[test]
+fn test_jpegls_golden() {
+    let gray = frame(40, 24, 8, 1);
+    check_golden(
+        "gray8.jls",
+        &encode(Encoder::for_format(Format::Jpegls), &gray),
+    );
+
+    let mut encoder = Encoder::for_format(Format::Jpegls);
+    encoder.set_near_lossless(2);
+    encoder.set_interleave_mode(InterleaveMode::Sample);
+    encoder.set_restart_interval(5);
+    check_golden("rgb8_near2.jls", &encode(encoder, &frame(40, 24, 8, 3)));
+
+    let mut encoder = Encoder::for_format(Format::Jpegls);
+    encoder.set_interleave_mode(InterleaveMode::Line);
+    check_golden("rgb12.jls", &encode(encoder, &frame(17, 11, 12, 3)));
+}
+
+#[test]
+fn test_jpeg1_golden() {
+    check_golden(
+        "gray8.jpg",
+        &encode(Encoder::for_format(Format::Jpeg1), &frame(40, 24, 8, 1)),
+    );
+
+    let mut encoder = Encoder::for_format(Format::Jpeg1);
+    encoder.set_quality(90);
+    encoder.set_restart_interval(2);
+    check_golden("rgb8_q90.jpg", &encode(encoder, &frame(40, 24, 8, 3)));
+
+    // 12-bit samples get Huffman tables optimized for the image.
+    check_golden(
+        "gray12.jpg",
+        &encode(Encoder::for_format(Format::Jpeg1), &frame(33, 20, 12, 1)),
+    );
+}
+
+#[test]
+fn test_jpeg2000_golden() {
+    check_golden(
+        "rgb8_97.j2k",
+        &encode(Encoder::for_format(Format::Jpeg2000), &frame(40, 24, 8, 3)),
+    );
+
+    let mut encoder = Encoder::for_format(Format::Jpeg2000);
+    encoder.set_irreversible(false);
+    encoder.set_decomposition_levels(3);
+    check_golden("gray8_53.j2k", &encode(encoder, &frame(40, 24, 8, 1)));
+
+    let mut encoder = Encoder::for_format(Format::Jpeg2000);
+    encoder.set_high_throughput(true);
+    encoder.set_quality(60);
+    check_golden("gray8_ht.j2k", &encode(encoder, &frame(40, 24, 8, 1)));
+}
+
+#[test]
+fn test_jpegls_thread_count_does_not_change_stream() {
+    let frame_info = frame(64, 48, 8, 3);
+    let pixels = test_image(&frame_info);
+    let encode = |thread_count: usize, restart_interval: u16| {
+        JpeglsEncoderBuilder::new(frame_info)
+            .interleave_mode(InterleaveMode::Sample)
+            .restart_interval(restart_interval)
+            .thread_count(thread_count)
+            .encode_deterministic(true)
+            .encode_to_vec(&pixels)
+            .unwrap()
+    };
+    for restart_interval in [0, 7] {
+        let single = encode(1, restart_interval);
+        for thread_count in [0, 2, 3, 8] {
+            assert_eq!(
+                encode(thread_count, restart_interval),
+                single,
+                "{thread_count} threads, restart interval {restart_interval}"
+            );
+        }
+    }
+}
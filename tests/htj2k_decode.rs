@@ -0,0 +1,51 @@
+//! Decoding of HTJ2K codestreams made by other encoders.
+
+use jpegexp_rs::codec::Decoder;
+use jpegexp_rs::JpeglsError;
+
+fn decode_file(name: &str) -> Vec<u8> {
+    let path = format!("tests/test_images/JPEG2000/{}", name);
+    let data = std::fs::read(&path).expect("Failed to read test file");
+    Decoder::auto(&data).expect("Failed to decode").pixels
+}
+
+/// `ojph-ht.j2c` is the image of `kakadu71.jp2` encoded by OpenJPH with the HT block
+/// coder, so both decode to nearly the same pixels.
+#[test]
+#[ignore = "the CxtVLC codewords of Annex C are not transcribed yet"]
+fn test_openjph_codestream() {
+    let ht = decode_file("ojph-ht.j2c");
+    let part1 = decode_file("kakadu71.jp2");
+    assert_eq!(ht.len(), part1.len());
+
+    let mse = ht
+        .iter()
+        .zip(&part1)
+        .map(|(&a, &b)| (a as f64 - b as f64).powi(2))
+        .sum::<f64>()
+        / ht.len() as f64;
+    let psnr = 10.0 * (255.0 * 255.0 / mse).log10();
+    assert!(psnr > 30.0, "PSNR {:.2} dB", psnr);
+}
+
+/// Until the CxtVLC codewords are transcribed, HT code-blocks with significant quads cannot
+/// be decoded; the decoder must say so rather than return a flat mid-grey image.
+#[test]
+fn test_ht_code_blocks_are_not_decoded_to_grey() {
+    for name in [
+        "oj-ht-byte.jph",
+        "oj-ht-byte_causal.jhc",
+        "tileoffset-ojph.jhc",
+        "grok-ht.j2c",
+        "ojph-ht.j2c",
+    ] {
+        let path = format!("tests/test_images/JPEG2000/{}", name);
+        let data = std::fs::read(&path).expect("Failed to read test file");
+        assert_eq!(
+            Decoder::auto(&data).map(|image| image.pixels.len()),
+            Err(JpeglsError::EncodingNotSupported),
+            "{}",
+            name
+        );
+    }
+}
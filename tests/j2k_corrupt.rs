@@ -0,0 +1,35 @@
+//! Decoding of corrupt JPEG 2000 files, which must fail instead of panicking or
+//! exhausting memory.
+
+use jpegexp_rs::codec::Decoder;
+
+fn decode_file(name: &str) -> Result<usize, jpegexp_rs::JpeglsError> {
+    let path = format!("tests/test_images/JPEG2000/{}", name);
+    let data = std::fs::read(&path).expect("Failed to read test file");
+    Decoder::auto(&data).map(|image| image.pixels.len())
+}
+
+/// The SIZ of these files declares an image of gigapixels in a file of a few kilobytes.
+#[test]
+fn test_corrupt_image_size() {
+    for name in [
+        "bitwiser-codestreamheader-corrupted-xsiz-10918.jp2",
+        "bitwiser-codestreamheader-corrupted-xsiz-10928.jp2",
+        "bitwiser-codestreamheader-corrupted-xsiz-10937.jp2",
+        "bitwiser-codestreamheader-corrupted-xsiz-10946.jp2",
+        "bitwiser-codestreamheader-corrupted-xsiz-10955.jp2",
+        "bitwiser-codestreamheader-corrupted-ysiz-11208.jp2",
+        "bitwiser-codestreamheader-corrupted-ysiz-11218.jp2",
+        "bitwiser-codestreamheader-corrupted-ysiz-11227.jp2",
+        "bitwiser-codestreamheader-corrupted-ysiz-11238.jp2",
+        "bitwiser-codestreamheader-corrupted-ysiz-11252.jp2",
+    ] {
+        assert!(decode_file(name).is_err(), "{}", name);
+    }
+}
+
+/// The COD of this file declares more decomposition levels than the standard allows.
+#[test]
+fn test_corrupt_decomposition_levels() {
+    assert!(decode_file("oj-tileindex-error-2.jp2").is_err());
+}
@@ -0,0 +1,33 @@
+//! Validation of the JP2 test files from the jpylyzer and OpenJPEG corpora.
+
+use jpegexp_rs::jpeg2000::validate;
+
+fn validate_file(name: &str) -> jpegexp_rs::jpeg2000::ValidationReport {
+    let path = format!("tests/test_images/JPEG2000/{}", name);
+    let data = std::fs::read(&path).expect("Failed to read test file");
+    validate(&data)
+}
+
+#[test]
+fn test_valid_files() {
+    for name in ["kakadu71.jp2", "aware.jp2", "graphicsMagick.jp2"] {
+        let report = validate_file(name);
+        assert!(report.is_valid(), "{}: {:?}", name, report.errors);
+    }
+}
+
+#[test]
+fn test_truncated_tile_part() {
+    let report = validate_file("data_missing_in_last_tilepart.jp2");
+    assert_eq!(report.errors.len(), 1);
+    assert!(report.errors[0].message.contains("past the end"));
+}
+
+#[test]
+fn test_image_header_mismatch() {
+    let report = validate_file("height_image_header_damaged.jp2");
+    assert_eq!(report.errors.len(), 1);
+    assert!(report.errors[0]
+        .message
+        .contains("differs from the codestream size"));
+}
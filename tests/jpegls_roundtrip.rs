@@ -0,0 +1,2081 @@
+//! JPEG-LS encode/decode round-trip tests.
+//!
+//! These tests encode synthetic images with `JpeglsEncoder` and verify that the produced
+//! streams are well formed and decode back to the original samples.
+
+use jpegexp_rs::jpegls::{
+    ColorTransformation, CompressedDataFormat, InterleaveMode, JpeglsDecoder, JpeglsEncoder,
+    JpeglsEncoderBuilder, JpeglsPcParameters, JpeglsTranscoder, MappingTable, SpiffColorSpace,
+    SpiffCompressionType, SpiffHeader, SpiffProfileId, SpiffResolutionUnits,
+};
+use jpegexp_rs::{FrameInfo, OutputLayout};
+
+/// Generates an image that mixes flat areas (run mode) with noisy areas (regular mode).
+fn test_pattern(width: usize, height: usize, components: usize, max_value: u32) -> Vec<u16> {
+    let mut seed = 0x1234_5678u32 ^ (width as u32 * 31 + height as u32);
+    let mut samples = Vec::with_capacity(width * height * components);
+    for y in 0..height {
+        for x in 0..width {
+            for c in 0..components {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                let value = if (x / 3 + y / 2 + c) % 3 == 0 {
+                    (seed >> 16) % (max_value + 1)
+                } else {
+                    ((y * 7 + c * 50) as u32) % (max_value + 1)
+                };
+                samples.push(value as u16);
+            }
+        }
+    }
+    samples
+}
+
+fn to_bytes(samples: &[u16], bits_per_sample: i32) -> Vec<u8> {
+    if bits_per_sample <= 8 {
+        samples.iter().map(|&s| s as u8).collect()
+    } else {
+        samples.iter().flat_map(|s| s.to_ne_bytes()).collect()
+    }
+}
+
+fn encode(source: &[u8], frame_info: FrameInfo, interleave_mode: InterleaveMode) -> Vec<u8> {
+    let mut destination = vec![0u8; 1024 + source.len() * 2];
+    let mut encoder = JpeglsEncoder::new(&mut destination);
+    encoder.set_frame_info(frame_info).unwrap();
+    encoder.set_interleave_mode(interleave_mode).unwrap();
+    let length = encoder.encode(source).unwrap_or_else(|e| {
+        panic!(
+            "encode failed for {}x{}x{} ({:?}): {}",
+            frame_info.width, frame_info.height, frame_info.component_count, interleave_mode, e
+        )
+    });
+    destination.truncate(length);
+    destination
+}
+
+/// Walks the marker segments of an encoded stream and checks that every entropy coded
+/// segment only contains stuffed 0xFF bytes. Returns the number of scans found.
+fn assert_well_formed(stream: &[u8]) -> usize {
+    assert_eq!(&stream[..2], &[0xFF, 0xD8], "missing SOI");
+    assert_eq!(&stream[stream.len() - 2..], &[0xFF, 0xD9], "missing EOI");
+
+    let mut scans = 0;
+    let mut position = 2;
+    while position < stream.len() - 2 {
+        assert_eq!(stream[position], 0xFF, "expected marker at {}", position);
+        let marker = stream[position + 1];
+        let length = u16::from_be_bytes([stream[position + 2], stream[position + 3]]) as usize;
+        position += 2 + length;
+        if marker == 0xDA {
+            scans += 1;
+            while !(stream[position] == 0xFF && stream[position + 1] >= 0x80) {
+                if stream[position] == 0xFF {
+                    assert!(
+                        stream[position + 1] < 0x80,
+                        "unstuffed 0xFF at {}",
+                        position
+                    );
+                }
+                position += 1;
+            }
+        }
+    }
+    scans
+}
+
+#[test]
+fn dimension_sweep_grayscale() {
+    for bits_per_sample in [8, 12, 16] {
+        let max_value = (1u32 << bits_per_sample) - 1;
+        for width in 1..=17usize {
+            for height in 1..=17usize {
+                let samples = test_pattern(width, height, 1, max_value);
+                let source = to_bytes(&samples, bits_per_sample);
+                let frame_info = FrameInfo {
+                    width: width as u32,
+                    height: height as u32,
+                    bits_per_sample,
+                    component_count: 1,
+                };
+
+                let encoded = encode(&source, frame_info, InterleaveMode::None);
+                assert_eq!(assert_well_formed(&encoded), 1);
+
+                let mut decoder = JpeglsDecoder::new(&encoded);
+                decoder.read_header().unwrap();
+                assert_eq!(decoder.frame_info(), frame_info);
+                let mut decoded = vec![0u8; source.len()];
+                decoder.decode(&mut decoded).unwrap_or_else(|e| {
+                    panic!("{}x{} @ {}: {}", width, height, bits_per_sample, e)
+                });
+                assert_eq!(
+                    decoded, source,
+                    "round trip mismatch for {}x{} @ {} bits",
+                    width, height, bits_per_sample
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn dimension_sweep_multi_component() {
+    for component_count in [2usize, 3, 4] {
+        for interleave_mode in [
+            InterleaveMode::None,
+            InterleaveMode::Line,
+            InterleaveMode::Sample,
+        ] {
+            for width in 1..=17usize {
+                for height in [1usize, 2, 7, 16, 17] {
+                    let samples = test_pattern(width, height, component_count, 255);
+                    let source = to_bytes(&samples, 8);
+                    let frame_info = FrameInfo {
+                        width: width as u32,
+                        height: height as u32,
+                        bits_per_sample: 8,
+                        component_count: component_count as i32,
+                    };
+
+                    let encoded = encode(&source, frame_info, interleave_mode);
+                    let expected_scans = if interleave_mode == InterleaveMode::None {
+                        component_count
+                    } else {
+                        1
+                    };
+                    assert_eq!(assert_well_formed(&encoded), expected_scans);
+
+                    let mut decoder = JpeglsDecoder::new(&encoded);
+                    decoder.read_header().unwrap();
+                    let mut decoded = vec![0u8; source.len()];
+                    decoder.decode(&mut decoded).unwrap();
+                    assert_eq!(
+                        decoded, source,
+                        "{}x{}x{} {:?}",
+                        width, height, component_count, interleave_mode
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn encode_rejects_short_source() {
+    let frame_info = FrameInfo {
+        width: 5,
+        height: 3,
+        bits_per_sample: 8,
+        component_count: 3,
+    };
+    let mut destination = vec![0u8; 1024];
+    let mut encoder = JpeglsEncoder::new(&mut destination);
+    encoder.set_frame_info(frame_info).unwrap();
+    encoder.set_interleave_mode(InterleaveMode::Sample).unwrap();
+    assert_eq!(
+        encoder.encode(&[0u8; 5 * 3 * 3 - 1]),
+        Err(jpegexp_rs::JpeglsError::InvalidArgumentSize)
+    );
+}
+
+#[test]
+fn encode_reports_destination_too_small() {
+    let samples = test_pattern(16, 16, 1, 255);
+    let source = to_bytes(&samples, 8);
+    let frame_info = FrameInfo {
+        width: 16,
+        height: 16,
+        bits_per_sample: 8,
+        component_count: 1,
+    };
+    let mut destination = vec![0u8; 64];
+    let mut encoder = JpeglsEncoder::new(&mut destination);
+    encoder.set_frame_info(frame_info).unwrap();
+    assert_eq!(
+        encoder.encode(&source),
+        Err(jpegexp_rs::JpeglsError::DestinationTooSmall {
+            needed: JpeglsEncoder::estimated_destination_size(&frame_info, InterleaveMode::None)
+        })
+    );
+}
+
+fn transcode(stream: &[u8], interleave_mode: InterleaveMode) -> Vec<u8> {
+    let mut destination = vec![0u8; 1024 + stream.len() * 2];
+    let mut transcoder = JpeglsTranscoder::new(stream);
+    transcoder.set_interleave_mode(interleave_mode).unwrap();
+    let length = transcoder.transcode(&mut destination).unwrap();
+    destination.truncate(length);
+    destination
+}
+
+#[test]
+fn transcode_between_planar_and_line_interleaved() {
+    for bits_per_sample in [8, 12, 16] {
+        let max_value = (1u32 << bits_per_sample) - 1;
+        for (width, height) in [(1usize, 1usize), (7, 5), (16, 16), (33, 9)] {
+            let samples = test_pattern(width, height, 3, max_value);
+            let source = to_bytes(&samples, bits_per_sample);
+            let frame_info = FrameInfo {
+                width: width as u32,
+                height: height as u32,
+                bits_per_sample,
+                component_count: 3,
+            };
+
+            let planar = encode(&source, frame_info, InterleaveMode::None);
+            let line = transcode(&planar, InterleaveMode::Line);
+            assert_eq!(assert_well_formed(&line), 1);
+            assert_eq!(
+                line,
+                encode(&source, frame_info, InterleaveMode::Line),
+                "{}x{} @ {} bits",
+                width,
+                height,
+                bits_per_sample
+            );
+
+            let mut decoder = JpeglsDecoder::new(&line);
+            decoder.read_header().unwrap();
+            let mut decoded = vec![0u8; source.len()];
+            decoder.decode(&mut decoded).unwrap();
+            assert_eq!(decoded, source);
+
+            let back = transcode(&line, InterleaveMode::None);
+            assert_eq!(assert_well_formed(&back), 3);
+            assert_eq!(back, planar);
+
+            let sample = encode(&source, frame_info, InterleaveMode::Sample);
+            assert_eq!(transcode(&sample, InterleaveMode::None), planar);
+        }
+    }
+}
+
+#[test]
+fn line_interleaved_16_bit_round_trip() {
+    for (width, height) in [(1usize, 1usize), (5, 3), (17, 16), (64, 4)] {
+        let samples = test_pattern(width, height, 3, u16::MAX as u32);
+        let source = to_bytes(&samples, 16);
+        let frame_info = FrameInfo {
+            width: width as u32,
+            height: height as u32,
+            bits_per_sample: 16,
+            component_count: 3,
+        };
+
+        let encoded = encode(&source, frame_info, InterleaveMode::Line);
+        let mut decoder = JpeglsDecoder::new(&encoded);
+        decoder.read_header().unwrap();
+        let mut decoded = vec![0u8; source.len()];
+        decoder.decode(&mut decoded).unwrap();
+        assert_eq!(decoded, source, "{}x{}", width, height);
+    }
+}
+
+#[test]
+fn decode_reports_destination_too_small() {
+    let samples = test_pattern(8, 8, 3, 255);
+    let source = to_bytes(&samples, 8);
+    let frame_info = FrameInfo {
+        width: 8,
+        height: 8,
+        bits_per_sample: 8,
+        component_count: 3,
+    };
+
+    let encoded = encode(&source, frame_info, InterleaveMode::Line);
+    let mut decoder = JpeglsDecoder::new(&encoded);
+    decoder.read_header().unwrap();
+    let mut decoded = vec![0u8; source.len() - 1];
+    assert_eq!(
+        decoder.decode(&mut decoded),
+        Err(jpegexp_rs::JpeglsError::DestinationTooSmall {
+            needed: source.len()
+        })
+    );
+}
+
+fn from_bytes(bytes: &[u8], bits_per_sample: i32) -> Vec<u16> {
+    if bits_per_sample <= 8 {
+        bytes.iter().map(|&b| b as u16).collect()
+    } else {
+        bytes
+            .chunks_exact(2)
+            .map(|b| u16::from_ne_bytes([b[0], b[1]]))
+            .collect()
+    }
+}
+
+#[test]
+fn near_lossless_round_trip_stays_within_near() {
+    for bits_per_sample in [8, 12, 16] {
+        let max_value = (1u32 << bits_per_sample) - 1;
+        for near_lossless in [1, 2, 3] {
+            for interleave_mode in [
+                InterleaveMode::None,
+                InterleaveMode::Line,
+                InterleaveMode::Sample,
+            ] {
+                let (width, height) = (19usize, 11usize);
+                let samples = test_pattern(width, height, 3, max_value);
+                let source = to_bytes(&samples, bits_per_sample);
+                let frame_info = FrameInfo {
+                    width: width as u32,
+                    height: height as u32,
+                    bits_per_sample,
+                    component_count: 3,
+                };
+
+                let mut encoded = vec![0u8; 1024 + source.len() * 2];
+                let mut encoder = JpeglsEncoder::new(&mut encoded);
+                encoder.set_frame_info(frame_info).unwrap();
+                encoder.set_near_lossless(near_lossless).unwrap();
+                encoder.set_interleave_mode(interleave_mode).unwrap();
+                let length = encoder.encode(&source).unwrap();
+                encoded.truncate(length);
+                assert_well_formed(&encoded);
+
+                let mut decoder = JpeglsDecoder::new(&encoded);
+                decoder.read_header().unwrap();
+                let mut decoded = vec![0u8; source.len()];
+                decoder.decode(&mut decoded).unwrap();
+
+                let decoded = from_bytes(&decoded, bits_per_sample);
+                for (i, (&actual, &expected)) in decoded.iter().zip(&samples).enumerate() {
+                    assert!(
+                        (actual as i32 - expected as i32).abs() <= near_lossless,
+                        "sample {} is {} instead of {} (NEAR {}, {} bits, {:?})",
+                        i,
+                        actual,
+                        expected,
+                        near_lossless,
+                        bits_per_sample,
+                        interleave_mode
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn near_lossless_is_written_to_start_of_scan() {
+    let frame_info = FrameInfo {
+        width: 4,
+        height: 4,
+        bits_per_sample: 8,
+        component_count: 1,
+    };
+    let source = vec![100u8; 16];
+    let mut encoded = vec![0u8; 256];
+    let mut encoder = JpeglsEncoder::new(&mut encoded);
+    encoder.set_frame_info(frame_info).unwrap();
+    encoder.set_near_lossless(5).unwrap();
+    let length = encoder.encode(&source).unwrap();
+
+    let sos = encoded[..length]
+        .windows(2)
+        .position(|w| w == [0xFF, 0xDA])
+        .unwrap();
+    // FF DA, Ls (2), Ns (1), Cs/Tm (2 per component), NEAR, ILV, Al/Ah
+    assert_eq!(encoded[sos + 2 + 2 + 1 + 2], 5);
+}
+
+#[test]
+fn set_near_lossless_rejects_out_of_range_values() {
+    let mut destination = vec![0u8; 256];
+    let mut encoder = JpeglsEncoder::new(&mut destination);
+    assert_eq!(
+        encoder.set_near_lossless(-1),
+        Err(jpegexp_rs::JpeglsError::InvalidParameterNearLossless)
+    );
+    assert_eq!(
+        encoder.set_near_lossless(256),
+        Err(jpegexp_rs::JpeglsError::InvalidParameterNearLossless)
+    );
+
+    encoder
+        .set_frame_info(FrameInfo {
+            width: 4,
+            height: 4,
+            bits_per_sample: 8,
+            component_count: 1,
+        })
+        .unwrap();
+    assert!(encoder.set_near_lossless(127).is_ok());
+    assert_eq!(
+        encoder.set_near_lossless(128),
+        Err(jpegexp_rs::JpeglsError::InvalidParameterNearLossless)
+    );
+}
+
+#[test]
+fn builder_rejects_invalid_configurations() {
+    use jpegexp_rs::JpeglsError;
+
+    let frame_info = |width, height, bits_per_sample, component_count| FrameInfo {
+        width,
+        height,
+        bits_per_sample,
+        component_count,
+    };
+    let build = |builder: JpeglsEncoderBuilder| {
+        let mut destination = vec![0u8; 256];
+        builder.build(&mut destination).map(|_| ())
+    };
+    let rgb = frame_info(4, 4, 8, 3);
+
+    assert_eq!(
+        build(JpeglsEncoderBuilder::new(frame_info(0, 4, 8, 1))),
+        Err(JpeglsError::InvalidArgumentWidth)
+    );
+    assert_eq!(
+        build(JpeglsEncoderBuilder::new(frame_info(4, 70_000, 8, 1))),
+        Err(JpeglsError::InvalidArgumentHeight)
+    );
+    assert_eq!(
+        build(JpeglsEncoderBuilder::new(frame_info(4, 4, 8, 256))),
+        Err(JpeglsError::InvalidArgumentComponentCount)
+    );
+    assert_eq!(
+        build(JpeglsEncoderBuilder::new(frame_info(4, 4, 17, 1))),
+        Err(JpeglsError::InvalidArgumentBitsPerSample)
+    );
+    assert_eq!(
+        build(
+            JpeglsEncoderBuilder::new(frame_info(4, 4, 8, 5))
+                .interleave_mode(InterleaveMode::Sample)
+        ),
+        Err(JpeglsError::InvalidArgumentInterleaveMode)
+    );
+    assert_eq!(
+        build(JpeglsEncoderBuilder::new(rgb).near_lossless(128)),
+        Err(JpeglsError::InvalidArgumentNearLossless)
+    );
+    assert_eq!(
+        build(
+            JpeglsEncoderBuilder::new(rgb).preset_coding_parameters(JpeglsPcParameters {
+                maximum_sample_value: 256,
+                ..Default::default()
+            })
+        ),
+        Err(JpeglsError::InvalidArgumentJpeglsPcParameters)
+    );
+    assert_eq!(
+        build(
+            JpeglsEncoderBuilder::new(frame_info(4, 4, 12, 3))
+                .color_transformation(ColorTransformation::Hp1)
+        ),
+        Err(JpeglsError::InvalidArgumentColorTransformation)
+    );
+    assert_eq!(
+        build(
+            JpeglsEncoderBuilder::new(rgb)
+                .near_lossless(3)
+                .interleave_mode(InterleaveMode::Line)
+                .color_transformation(ColorTransformation::Hp2)
+        ),
+        Ok(())
+    );
+}
+
+#[test]
+fn builder_encodes_like_the_setters() {
+    let frame_info = FrameInfo {
+        width: 16,
+        height: 8,
+        bits_per_sample: 8,
+        component_count: 3,
+    };
+    let source = to_bytes(&test_pattern(16, 8, 3, 255), 8);
+
+    let mut expected = vec![0u8; 1024];
+    let mut encoder = JpeglsEncoder::new(&mut expected);
+    encoder.set_frame_info(frame_info).unwrap();
+    encoder.set_near_lossless(2).unwrap();
+    encoder.set_interleave_mode(InterleaveMode::Sample).unwrap();
+    let expected_length = encoder.encode(&source).unwrap();
+
+    let mut encoded = vec![0u8; 1024];
+    let length = JpeglsEncoderBuilder::new(frame_info)
+        .near_lossless(2)
+        .interleave_mode(InterleaveMode::Sample)
+        .build(&mut encoded)
+        .unwrap()
+        .encode(&source)
+        .unwrap();
+    assert_eq!(encoded[..length], expected[..expected_length]);
+}
+
+#[test]
+fn builder_encode_to_vec_matches_encode() {
+    let frame_info = FrameInfo {
+        width: 16,
+        height: 8,
+        bits_per_sample: 12,
+        component_count: 3,
+    };
+    let source = to_bytes(&test_pattern(16, 8, 3, 4095), 12);
+    let builder = JpeglsEncoderBuilder::new(frame_info).interleave_mode(InterleaveMode::Line);
+
+    let encoded = builder.encode_to_vec(&source).unwrap();
+    assert_eq!(encoded, encode(&source, frame_info, InterleaveMode::Line));
+    assert_eq!(
+        JpeglsEncoderBuilder::new(FrameInfo {
+            width: 0,
+            ..frame_info
+        })
+        .encode_to_vec(&source),
+        Err(jpegexp_rs::JpeglsError::InvalidArgumentWidth)
+    );
+}
+
+#[test]
+fn noise_fits_estimated_destination_size() {
+    let mut seed = 0x9E37_79B9u32;
+    for (width, height, component_count, interleave_mode) in [
+        (1, 1, 255, InterleaveMode::None),
+        (4, 4, 255, InterleaveMode::None),
+        (16, 16, 4, InterleaveMode::Sample),
+        (1, 300, 1, InterleaveMode::None),
+        (256, 64, 3, InterleaveMode::Line),
+    ] {
+        for bits_per_sample in [2, 8, 12, 16] {
+            let frame_info = FrameInfo {
+                width,
+                height,
+                bits_per_sample,
+                component_count,
+            };
+            let samples: Vec<u16> = (0..width * height * component_count as u32)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                    (seed >> 16) as u16 & ((1 << bits_per_sample) - 1) as u16
+                })
+                .collect();
+            let source = to_bytes(&samples, bits_per_sample);
+            for restart_interval in [0, 1] {
+                let builder = JpeglsEncoderBuilder::new(frame_info)
+                    .interleave_mode(interleave_mode)
+                    .restart_interval(restart_interval);
+                let size = builder.estimated_destination_size();
+                let mut destination = vec![0u8; size];
+                builder
+                    .build(&mut destination)
+                    .unwrap()
+                    .encode(&source)
+                    .unwrap_or_else(|e| panic!("{:?} {:?}: {}", frame_info, interleave_mode, e));
+            }
+        }
+    }
+}
+
+/// Noise is coded with long Golomb codes and escape sequences, which the decoder reads past
+/// its byte-wide code table.
+#[test]
+fn noise_round_trip() {
+    let mut seed = 0x2545_F491u32;
+    for (component_count, interleave_mode) in [
+        (1, InterleaveMode::None),
+        (3, InterleaveMode::Line),
+        (3, InterleaveMode::Sample),
+        (4, InterleaveMode::Sample),
+    ] {
+        for bits_per_sample in [2, 8, 12, 16] {
+            let frame_info = FrameInfo {
+                width: 37,
+                height: 23,
+                bits_per_sample,
+                component_count,
+            };
+            let samples: Vec<u16> = (0..37 * 23 * component_count as u32)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                    (seed >> 16) as u16 & ((1u32 << bits_per_sample) - 1) as u16
+                })
+                .collect();
+            let source = to_bytes(&samples, bits_per_sample);
+            let encoded = encode(&source, frame_info, interleave_mode);
+
+            let mut decoder = JpeglsDecoder::new(&encoded);
+            decoder.read_header().unwrap();
+            let mut decoded = vec![0u8; source.len()];
+            decoder.decode(&mut decoded).unwrap();
+            assert_eq!(decoded, source, "{:?} {:?}", frame_info, interleave_mode);
+        }
+    }
+}
+
+fn encode_with_preset(
+    source: &[u8],
+    frame_info: FrameInfo,
+    interleave_mode: InterleaveMode,
+    preset: JpeglsPcParameters,
+) -> Vec<u8> {
+    let mut destination = vec![0u8; 1024 + source.len() * 2];
+    let mut encoder = JpeglsEncoder::new(&mut destination);
+    encoder.set_frame_info(frame_info).unwrap();
+    encoder.set_interleave_mode(interleave_mode).unwrap();
+    encoder.set_preset_coding_parameters(preset).unwrap();
+    let length = encoder.encode(source).unwrap();
+    destination.truncate(length);
+    destination
+}
+
+/// Returns the MAXVAL, T1, T2, T3 and RESET fields of the first LSE segment, if any.
+fn preset_segment(stream: &[u8]) -> Option<[u16; 5]> {
+    let position = stream.windows(2).position(|w| w == [0xFF, 0xF8])?;
+    assert_eq!(stream[position + 4], 1, "LSE type");
+    let field = |i: usize| {
+        let offset = position + 5 + i * 2;
+        u16::from_be_bytes([stream[offset], stream[offset + 1]])
+    };
+    Some([field(0), field(1), field(2), field(3), field(4)])
+}
+
+#[test]
+fn preset_coding_parameters_round_trip() {
+    let (width, height) = (23usize, 13usize);
+    let samples = test_pattern(width, height, 3, 255);
+    let source = to_bytes(&samples, 8);
+    let frame_info = FrameInfo {
+        width: width as u32,
+        height: height as u32,
+        bits_per_sample: 8,
+        component_count: 3,
+    };
+    let presets = [
+        JpeglsPcParameters {
+            maximum_sample_value: 255,
+            threshold1: 18,
+            threshold2: 27,
+            threshold3: 41,
+            reset_value: 32,
+        },
+        JpeglsPcParameters {
+            threshold1: 2,
+            ..Default::default()
+        },
+        JpeglsPcParameters {
+            reset_value: 255,
+            ..Default::default()
+        },
+    ];
+
+    for preset in presets {
+        for interleave_mode in [
+            InterleaveMode::None,
+            InterleaveMode::Line,
+            InterleaveMode::Sample,
+        ] {
+            let encoded = encode_with_preset(&source, frame_info, interleave_mode, preset);
+            assert_well_formed(&encoded);
+            assert_eq!(
+                preset_segment(&encoded),
+                Some([
+                    preset.maximum_sample_value as u16,
+                    preset.threshold1 as u16,
+                    preset.threshold2 as u16,
+                    preset.threshold3 as u16,
+                    preset.reset_value as u16,
+                ])
+            );
+            assert_ne!(encoded, encode(&source, frame_info, interleave_mode));
+
+            let mut decoder = JpeglsDecoder::new(&encoded);
+            decoder.read_header().unwrap();
+            let mut decoded = vec![0u8; source.len()];
+            decoder.decode(&mut decoded).unwrap();
+            assert_eq!(decoded, source, "{:?} {:?}", preset, interleave_mode);
+        }
+    }
+}
+
+#[test]
+fn preset_maximum_sample_value_below_bit_depth() {
+    let (width, height) = (17usize, 9usize);
+    let samples = test_pattern(width, height, 1, 4095);
+    let source = to_bytes(&samples, 16);
+    let frame_info = FrameInfo {
+        width: width as u32,
+        height: height as u32,
+        bits_per_sample: 16,
+        component_count: 1,
+    };
+    let preset = JpeglsPcParameters {
+        maximum_sample_value: 4095,
+        ..Default::default()
+    };
+
+    let encoded = encode_with_preset(&source, frame_info, InterleaveMode::None, preset);
+    assert_eq!(preset_segment(&encoded), Some([4095, 0, 0, 0, 0]));
+
+    let mut decoder = JpeglsDecoder::new(&encoded);
+    decoder.read_header().unwrap();
+    let mut decoded = vec![0u8; source.len()];
+    decoder.decode(&mut decoded).unwrap();
+    assert_eq!(decoded, source);
+}
+
+#[test]
+fn default_preset_coding_parameters_are_not_written() {
+    let source = vec![7u8; 64];
+    let frame_info = FrameInfo {
+        width: 8,
+        height: 8,
+        bits_per_sample: 8,
+        component_count: 1,
+    };
+    let defaults = JpeglsPcParameters {
+        maximum_sample_value: 255,
+        threshold1: 3,
+        threshold2: 7,
+        threshold3: 21,
+        reset_value: 64,
+    };
+    for preset in [JpeglsPcParameters::default(), defaults] {
+        let encoded = encode_with_preset(&source, frame_info, InterleaveMode::None, preset);
+        assert_eq!(preset_segment(&encoded), None);
+        assert_eq!(encoded, encode(&source, frame_info, InterleaveMode::None));
+    }
+}
+
+#[test]
+fn invalid_preset_coding_parameters_are_rejected() {
+    let frame_info = FrameInfo {
+        width: 8,
+        height: 8,
+        bits_per_sample: 8,
+        component_count: 1,
+    };
+    let invalid = [
+        JpeglsPcParameters {
+            maximum_sample_value: 256,
+            ..Default::default()
+        },
+        JpeglsPcParameters {
+            threshold1: 10,
+            threshold2: 9,
+            ..Default::default()
+        },
+        JpeglsPcParameters {
+            threshold2: 30,
+            threshold3: 29,
+            ..Default::default()
+        },
+        JpeglsPcParameters {
+            threshold3: 300,
+            ..Default::default()
+        },
+        JpeglsPcParameters {
+            reset_value: 2,
+            ..Default::default()
+        },
+    ];
+
+    for preset in invalid {
+        let mut destination = vec![0u8; 1024];
+        let mut encoder = JpeglsEncoder::new(&mut destination);
+        encoder.set_frame_info(frame_info).unwrap();
+        assert_eq!(
+            encoder.set_preset_coding_parameters(preset),
+            Err(jpegexp_rs::JpeglsError::InvalidParameterJpeglsPresetParameters),
+            "{:?}",
+            preset
+        );
+
+        // Parameters set before the frame info are checked when encoding.
+        let mut encoder = JpeglsEncoder::new(&mut destination);
+        encoder.set_preset_coding_parameters(preset).unwrap();
+        encoder.set_frame_info(frame_info).unwrap();
+        assert_eq!(
+            encoder.encode(&[0u8; 64]),
+            Err(jpegexp_rs::JpeglsError::InvalidParameterJpeglsPresetParameters)
+        );
+    }
+}
+
+fn encode_with_transformation(
+    source: &[u8],
+    frame_info: FrameInfo,
+    interleave_mode: InterleaveMode,
+    transformation: ColorTransformation,
+) -> Vec<u8> {
+    let mut destination = vec![0u8; 1024 + source.len() * 2];
+    let mut encoder = JpeglsEncoder::new(&mut destination);
+    encoder.set_frame_info(frame_info).unwrap();
+    encoder.set_interleave_mode(interleave_mode).unwrap();
+    encoder.set_color_transformation(transformation).unwrap();
+    let length = encoder.encode(source).unwrap();
+    destination.truncate(length);
+    destination
+}
+
+/// RGB image whose components follow each other closely, like most photographic content.
+fn correlated_rgb(width: usize, height: usize, max_value: u32) -> Vec<u16> {
+    let mut samples = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            let green = ((x * 5 + y * 3 + (x * y) % 7) as u32 * (max_value / 255)) % max_value;
+            let offset = max_value / 64 + 1;
+            samples.push(((green + offset) % (max_value + 1)) as u16);
+            samples.push(green as u16);
+            samples.push(green.saturating_sub(offset) as u16);
+        }
+    }
+    samples
+}
+
+/// Returns the transformation id of the APP8 "mrfx" segment, if any.
+fn color_transform_segment(stream: &[u8]) -> Option<u8> {
+    let position = stream.windows(2).position(|w| w == [0xFF, 0xE8])?;
+    assert_eq!(
+        &stream[position + 2..position + 8],
+        &[0, 7, b'm', b'r', b'f', b'x']
+    );
+    Some(stream[position + 8])
+}
+
+#[test]
+fn color_transformation_round_trip() {
+    for bits_per_sample in [8, 16] {
+        let max_value = (1u32 << bits_per_sample) - 1;
+        for transformation in [
+            ColorTransformation::Hp1,
+            ColorTransformation::Hp2,
+            ColorTransformation::Hp3,
+        ] {
+            for interleave_mode in [
+                InterleaveMode::None,
+                InterleaveMode::Line,
+                InterleaveMode::Sample,
+            ] {
+                let (width, height) = (21usize, 10usize);
+                let samples = test_pattern(width, height, 3, max_value);
+                let source = to_bytes(&samples, bits_per_sample);
+                let frame_info = FrameInfo {
+                    width: width as u32,
+                    height: height as u32,
+                    bits_per_sample,
+                    component_count: 3,
+                };
+
+                let encoded = encode_with_transformation(
+                    &source,
+                    frame_info,
+                    interleave_mode,
+                    transformation,
+                );
+                assert_well_formed(&encoded);
+                assert_eq!(
+                    color_transform_segment(&encoded),
+                    Some(transformation as u8)
+                );
+
+                let mut decoder = JpeglsDecoder::new(&encoded);
+                decoder.read_header().unwrap();
+                let mut decoded = vec![0u8; source.len()];
+                decoder.decode(&mut decoded).unwrap();
+                assert_eq!(
+                    decoded, source,
+                    "{:?} {:?} @ {} bits",
+                    transformation, interleave_mode, bits_per_sample
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn color_transformation_improves_correlated_rgb() {
+    let (width, height) = (64usize, 64usize);
+    let source = to_bytes(&correlated_rgb(width, height, 255), 8);
+    let frame_info = FrameInfo {
+        width: width as u32,
+        height: height as u32,
+        bits_per_sample: 8,
+        component_count: 3,
+    };
+
+    let plain = encode(&source, frame_info, InterleaveMode::Line);
+    assert_eq!(color_transform_segment(&plain), None);
+    for transformation in [
+        ColorTransformation::Hp1,
+        ColorTransformation::Hp2,
+        ColorTransformation::Hp3,
+    ] {
+        let transformed =
+            encode_with_transformation(&source, frame_info, InterleaveMode::Line, transformation);
+        assert!(
+            transformed.len() < plain.len(),
+            "{:?}: {} >= {}",
+            transformation,
+            transformed.len(),
+            plain.len()
+        );
+    }
+}
+
+#[test]
+fn transcode_keeps_color_transformation() {
+    let samples = test_pattern(12, 7, 3, 255);
+    let source = to_bytes(&samples, 8);
+    let frame_info = FrameInfo {
+        width: 12,
+        height: 7,
+        bits_per_sample: 8,
+        component_count: 3,
+    };
+
+    let line = encode_with_transformation(
+        &source,
+        frame_info,
+        InterleaveMode::Line,
+        ColorTransformation::Hp2,
+    );
+    let planar = transcode(&line, InterleaveMode::None);
+    assert_eq!(
+        color_transform_segment(&planar),
+        Some(ColorTransformation::Hp2 as u8)
+    );
+
+    let mut decoder = JpeglsDecoder::new(&planar);
+    decoder.read_header().unwrap();
+    let mut decoded = vec![0u8; source.len()];
+    decoder.decode(&mut decoded).unwrap();
+    assert_eq!(decoded, source);
+}
+
+#[test]
+fn color_transformation_requires_8_or_16_bit_rgb() {
+    for (bits_per_sample, component_count) in [(8, 1), (8, 4), (12, 3)] {
+        let frame_info = FrameInfo {
+            width: 4,
+            height: 4,
+            bits_per_sample,
+            component_count,
+        };
+        let source = vec![0u8; 4 * 4 * component_count as usize * 2];
+        let mut destination = vec![0u8; 1024];
+        let mut encoder = JpeglsEncoder::new(&mut destination);
+        encoder.set_frame_info(frame_info).unwrap();
+        encoder
+            .set_color_transformation(ColorTransformation::Hp1)
+            .unwrap();
+        assert_eq!(
+            encoder.encode(&source),
+            Err(jpegexp_rs::JpeglsError::InvalidArgumentColorTransformation)
+        );
+    }
+}
+
+#[test]
+fn standard_spiff_header_round_trip() {
+    let samples = test_pattern(10, 6, 3, 255);
+    let source = to_bytes(&samples, 8);
+    let frame_info = FrameInfo {
+        width: 10,
+        height: 6,
+        bits_per_sample: 8,
+        component_count: 3,
+    };
+
+    let mut encoded = vec![0u8; 1024];
+    let mut encoder = JpeglsEncoder::new(&mut encoded);
+    encoder.set_frame_info(frame_info).unwrap();
+    encoder.set_interleave_mode(InterleaveMode::Line).unwrap();
+    encoder
+        .write_standard_spiff_header(SpiffColorSpace::Rgb)
+        .unwrap();
+    let length = encoder.encode(&source).unwrap();
+    encoded.truncate(length);
+
+    // SOI, then the 32 byte SPIFF segment, then the end-of-directory entry that carries
+    // the SOI of the wrapped image.
+    assert_eq!(&encoded[..4], &[0xFF, 0xD8, 0xFF, 0xE8]);
+    assert_eq!(&encoded[6..12], b"SPIFF\0");
+    assert_eq!(
+        &encoded[36..46],
+        &[0xFF, 0xE8, 0x00, 0x08, 0x00, 0x00, 0x00, 0x01, 0xFF, 0xD8]
+    );
+    assert_eq!(&encoded[46..48], &[0xFF, 0xF7]);
+
+    let mut decoder = JpeglsDecoder::new(&encoded);
+    decoder.read_header().unwrap();
+    assert_eq!(
+        decoder.spiff_header(),
+        Some(SpiffHeader {
+            profile_id: SpiffProfileId::None,
+            component_count: 3,
+            height: 6,
+            width: 10,
+            color_space: SpiffColorSpace::Rgb,
+            bits_per_sample: 8,
+            compression_type: SpiffCompressionType::JpegLs,
+            resolution_units: SpiffResolutionUnits::AspectRatio,
+            vertical_resolution: 1,
+            horizontal_resolution: 1,
+        })
+    );
+    let mut decoded = vec![0u8; source.len()];
+    decoder.decode(&mut decoded).unwrap();
+    assert_eq!(decoded, source);
+}
+
+#[test]
+fn spiff_header_with_resolution_round_trip() {
+    let source = vec![42u8; 16 * 8];
+    let frame_info = FrameInfo {
+        width: 16,
+        height: 8,
+        bits_per_sample: 8,
+        component_count: 1,
+    };
+    let header = SpiffHeader {
+        profile_id: SpiffProfileId::None,
+        component_count: 1,
+        height: 8,
+        width: 16,
+        color_space: SpiffColorSpace::Grayscale,
+        bits_per_sample: 8,
+        compression_type: SpiffCompressionType::JpegLs,
+        resolution_units: SpiffResolutionUnits::DotsPerInch,
+        vertical_resolution: 300,
+        horizontal_resolution: 600,
+    };
+
+    let mut encoded = vec![0u8; 1024];
+    let mut encoder = JpeglsEncoder::new(&mut encoded);
+    encoder.write_spiff_header(&header).unwrap();
+    encoder.set_frame_info(frame_info).unwrap();
+    let length = encoder.encode(&source).unwrap();
+    encoded.truncate(length);
+    assert_well_formed(&encoded);
+
+    let mut decoder = JpeglsDecoder::new(&encoded);
+    decoder.read_header().unwrap();
+    assert_eq!(decoder.spiff_header(), Some(header));
+    assert_eq!(decoder.frame_info(), frame_info);
+    let mut decoded = vec![0u8; source.len()];
+    decoder.decode(&mut decoded).unwrap();
+    assert_eq!(decoded, source);
+}
+
+#[test]
+fn write_spiff_header_rejects_invalid_use() {
+    let mut destination = vec![0u8; 1024];
+    let mut encoder = JpeglsEncoder::new(&mut destination);
+    assert_eq!(
+        encoder.write_standard_spiff_header(SpiffColorSpace::Grayscale),
+        Err(jpegexp_rs::JpeglsError::InvalidOperation)
+    );
+
+    let frame_info = FrameInfo {
+        width: 4,
+        height: 0,
+        bits_per_sample: 8,
+        component_count: 1,
+    };
+    encoder.set_frame_info(frame_info).unwrap();
+    assert_eq!(
+        encoder.write_standard_spiff_header(SpiffColorSpace::Grayscale),
+        Err(jpegexp_rs::JpeglsError::InvalidArgumentHeight)
+    );
+
+    encoder
+        .set_frame_info(FrameInfo {
+            height: 4,
+            ..frame_info
+        })
+        .unwrap();
+    encoder
+        .write_standard_spiff_header(SpiffColorSpace::Grayscale)
+        .unwrap();
+    assert_eq!(
+        encoder.write_standard_spiff_header(SpiffColorSpace::Grayscale),
+        Err(jpegexp_rs::JpeglsError::InvalidOperation)
+    );
+}
+
+#[test]
+fn comment_and_application_data_round_trip() {
+    let samples = test_pattern(9, 5, 1, 255);
+    let source = to_bytes(&samples, 8);
+    let frame_info = FrameInfo {
+        width: 9,
+        height: 5,
+        bits_per_sample: 8,
+        component_count: 1,
+    };
+    let study_uid = b"1.2.840.113619.2.55.3.604688119";
+
+    for with_spiff_header in [false, true] {
+        let mut encoded = vec![0u8; 1024];
+        let mut encoder = JpeglsEncoder::new(&mut encoded);
+        encoder.set_frame_info(frame_info).unwrap();
+        if with_spiff_header {
+            encoder
+                .write_standard_spiff_header(SpiffColorSpace::Grayscale)
+                .unwrap();
+        }
+        encoder.write_comment(b"jpegexp-rs").unwrap();
+        encoder.write_application_data(8, study_uid).unwrap();
+        encoder.write_application_data(15, &[]).unwrap();
+        assert_eq!(
+            encoder.write_spiff_header(&SpiffHeader {
+                profile_id: SpiffProfileId::None,
+                component_count: 1,
+                height: 5,
+                width: 9,
+                color_space: SpiffColorSpace::Grayscale,
+                bits_per_sample: 8,
+                compression_type: SpiffCompressionType::JpegLs,
+                resolution_units: SpiffResolutionUnits::AspectRatio,
+                vertical_resolution: 1,
+                horizontal_resolution: 1,
+            }),
+            Err(jpegexp_rs::JpeglsError::InvalidOperation)
+        );
+        let length = encoder.encode(&source).unwrap();
+        encoded.truncate(length);
+        assert_well_formed(&encoded);
+
+        let mut comments = Vec::new();
+        let mut application_data = Vec::new();
+        let mut decoded = vec![0u8; source.len()];
+        {
+            let mut decoder = JpeglsDecoder::new(&encoded);
+            decoder.at_comment(|comment| {
+                comments.push(comment.to_vec());
+                Ok(())
+            });
+            decoder.at_application_data(|id, data| {
+                application_data.push((id, data.to_vec()));
+                Ok(())
+            });
+            decoder.read_header().unwrap();
+            assert_eq!(decoder.spiff_header().is_some(), with_spiff_header);
+            decoder.decode(&mut decoded).unwrap();
+        }
+        assert_eq!(decoded, source);
+        assert_eq!(comments, vec![b"jpegexp-rs".to_vec()]);
+        assert_eq!(
+            application_data,
+            vec![(8, study_uid.to_vec()), (15, Vec::new())]
+        );
+    }
+}
+
+#[test]
+fn comment_handler_error_stops_decoding() {
+    let source = vec![1u8; 16];
+    let frame_info = FrameInfo {
+        width: 4,
+        height: 4,
+        bits_per_sample: 8,
+        component_count: 1,
+    };
+    let mut encoded = vec![0u8; 256];
+    let mut encoder = JpeglsEncoder::new(&mut encoded);
+    encoder.set_frame_info(frame_info).unwrap();
+    encoder.write_comment(b"abort").unwrap();
+    let length = encoder.encode(&source).unwrap();
+
+    let mut decoder = JpeglsDecoder::new(&encoded[..length]);
+    decoder.at_comment(|_| Err(jpegexp_rs::JpeglsError::CallbackFailed));
+    assert_eq!(
+        decoder.read_header(),
+        Err(jpegexp_rs::JpeglsError::CallbackFailed)
+    );
+}
+
+/// A palette of `entries` RGB entries, as a mapping table with 3-byte entries.
+fn rgb_palette(entries: usize) -> Vec<u8> {
+    (0..entries)
+        .flat_map(|i| [i as u8, (i * 3) as u8, 255 - i as u8])
+        .collect()
+}
+
+/// Encodes a 1 component image of palette indices that selects mapping table 5 for it.
+/// `table` is written to the stream when given.
+fn encode_indexed(indices: &[u8], frame_info: FrameInfo, table: Option<&[u8]>) -> Vec<u8> {
+    let mut encoded = vec![0u8; 1024 + indices.len() * 2 + table.map_or(0, |t| t.len() + 64)];
+    let mut encoder = JpeglsEncoder::new(&mut encoded);
+    encoder.set_frame_info(frame_info).unwrap();
+    if let Some(table) = table {
+        encoder.write_mapping_table(5, 3, table).unwrap();
+    }
+    encoder.set_mapping_table_id(0, 5).unwrap();
+    let length = encoder.encode(indices).unwrap();
+    encoded.truncate(length);
+    encoded
+}
+
+#[test]
+fn mapping_table_round_trip() {
+    let frame_info = FrameInfo {
+        width: 16,
+        height: 8,
+        bits_per_sample: 8,
+        component_count: 1,
+    };
+    let indices = to_bytes(&test_pattern(16, 8, 1, 255), 8);
+    let palette = rgb_palette(256);
+    let encoded = encode_indexed(&indices, frame_info, Some(&palette));
+
+    let mut decoder = JpeglsDecoder::new(&encoded);
+    decoder.read_header().unwrap();
+    assert_eq!(
+        decoder.compressed_data_format(),
+        CompressedDataFormat::Interchange
+    );
+    let mut decoded = vec![0u8; indices.len()];
+    decoder.decode(&mut decoded).unwrap();
+    assert_eq!(decoded, indices);
+    assert_eq!(decoder.mapping_table_id(0), Some(5));
+    let table = decoder.mapping_table(5).unwrap();
+    assert_eq!(
+        table,
+        &MappingTable {
+            table_id: 5,
+            entry_size: 3,
+            data: palette.clone(),
+        }
+    );
+    assert_eq!(table.entry_count(), 256);
+    assert_eq!(table.entry(2), Some(&[2u8, 6, 253][..]));
+    assert_eq!(table.entry(256), None);
+    assert_eq!(decoder.mapping_table(6), None);
+    assert_eq!(decoder.mapping_table_count(), 1);
+    assert_eq!(decoder.mapping_table_data(5), Some(&palette[..]));
+    assert_eq!(decoder.mapping_table_data(6), None);
+
+    // Decoding through the generic decoder keeps the palette with the indices.
+    let image = jpegexp_rs::Decoder::auto(&encoded).unwrap();
+    assert_eq!(image.pixels, indices);
+    assert_eq!(image.metadata.mapping_table_ids, vec![5]);
+    assert_eq!(image.metadata.mapping_tables, vec![table.clone()]);
+
+    // The transcoder carries the table and the selection over.
+    let mut transcoded = vec![0u8; encoded.len() + 256];
+    let length = JpeglsTranscoder::new(&encoded)
+        .transcode(&mut transcoded)
+        .unwrap();
+    let mut decoder = JpeglsDecoder::new(&transcoded[..length]);
+    decoder.read_header().unwrap();
+    decoder.decode(&mut decoded).unwrap();
+    assert_eq!(decoder.mapping_table_id(0), Some(5));
+    assert_eq!(decoder.mapping_table(5).map(|t| &t.data), Some(&palette));
+}
+
+#[test]
+fn large_mapping_table_uses_continuation_segments() {
+    let frame_info = FrameInfo {
+        width: 8,
+        height: 8,
+        bits_per_sample: 16,
+        component_count: 1,
+    };
+    let indices = to_bytes(&test_pattern(8, 8, 1, 65535), 16);
+    // 30000 entries of 3 bytes need two segments of type 2 and 3.
+    let palette = rgb_palette(30_000);
+    let encoded = encode_indexed(&indices, frame_info, Some(&palette));
+    let mapping_table_segments = encoded
+        .windows(5)
+        .filter(|w| w[0] == 0xFF && w[1] == 0xF8 && (w[4] == 2 || w[4] == 3))
+        .count();
+    assert_eq!(mapping_table_segments, 2);
+
+    let mut decoder = JpeglsDecoder::new(&encoded);
+    decoder.read_header().unwrap();
+    let mut decoded = vec![0u8; indices.len()];
+    decoder.decode(&mut decoded).unwrap();
+    assert_eq!(decoded, indices);
+    assert_eq!(decoder.mapping_table(5).unwrap().data, palette);
+}
+
+#[test]
+fn abbreviated_image_data_uses_table_specification() {
+    let palette = rgb_palette(64);
+    let mut tables = vec![0u8; 512];
+    let mut encoder = JpeglsEncoder::new(&mut tables);
+    encoder.write_mapping_table(5, 3, &palette).unwrap();
+    let length = encoder.create_tables_only().unwrap();
+    tables.truncate(length);
+    assert_eq!(&tables[..2], &[0xFF, 0xD8]);
+    assert_eq!(&tables[length - 2..], &[0xFF, 0xD9]);
+
+    let mut decoder = JpeglsDecoder::new(&tables);
+    assert_eq!(
+        decoder.compressed_data_format(),
+        CompressedDataFormat::Unknown
+    );
+    decoder.read_header().unwrap();
+    assert_eq!(
+        decoder.compressed_data_format(),
+        CompressedDataFormat::AbbreviatedTableSpecification
+    );
+    assert_eq!(decoder.mapping_table(5).unwrap().data, palette);
+    assert_eq!(
+        decoder.decode(&mut [0u8; 16]),
+        Err(jpegexp_rs::JpeglsError::InvalidOperation)
+    );
+
+    let frame_info = FrameInfo {
+        width: 8,
+        height: 8,
+        bits_per_sample: 6,
+        component_count: 1,
+    };
+    let indices = to_bytes(&test_pattern(8, 8, 1, 63), 8);
+    let image = encode_indexed(&indices, frame_info, None);
+
+    let mut decoder = JpeglsDecoder::new(&image);
+    decoder.read_header().unwrap();
+    assert_eq!(
+        decoder.compressed_data_format(),
+        CompressedDataFormat::AbbreviatedImageData
+    );
+    assert_eq!(decoder.mapping_table(5), None);
+    assert_eq!(
+        decoder.read_table_specification(&tables),
+        Err(jpegexp_rs::JpeglsError::InvalidOperation)
+    );
+
+    let mut decoder = JpeglsDecoder::new(&image);
+    assert_eq!(
+        decoder.read_table_specification(&image),
+        Err(jpegexp_rs::JpeglsError::InvalidArgument)
+    );
+    decoder.read_table_specification(&tables).unwrap();
+    assert_eq!(decoder.mapping_table_count(), 1);
+    decoder.read_header().unwrap();
+    assert_eq!(
+        decoder.compressed_data_format(),
+        CompressedDataFormat::AbbreviatedImageData
+    );
+    let mut decoded = vec![0u8; indices.len()];
+    decoder.decode(&mut decoded).unwrap();
+    assert_eq!(decoded, indices);
+    assert_eq!(decoder.mapping_table_id(0), Some(5));
+    assert_eq!(decoder.mapping_table(5).unwrap().data, palette);
+
+    // A table that the image defines itself replaces the one of the table specification.
+    let own_palette = rgb_palette(8);
+    let image = encode_indexed(&indices, frame_info, Some(&own_palette));
+    let mut decoder = JpeglsDecoder::new(&image);
+    decoder.read_table_specification(&tables).unwrap();
+    decoder.read_header().unwrap();
+    assert_eq!(
+        decoder.compressed_data_format(),
+        CompressedDataFormat::Interchange
+    );
+    assert_eq!(decoder.mapping_table_count(), 1);
+    assert_eq!(decoder.mapping_table_data(5), Some(&own_palette[..]));
+}
+
+#[test]
+fn write_mapping_table_rejects_invalid_arguments() {
+    let mut destination = vec![0u8; 256];
+    let mut encoder = JpeglsEncoder::new(&mut destination);
+    assert_eq!(
+        encoder.write_mapping_table(0, 1, &[1]),
+        Err(jpegexp_rs::JpeglsError::InvalidArgument)
+    );
+    assert_eq!(
+        encoder.write_mapping_table(256, 1, &[1]),
+        Err(jpegexp_rs::JpeglsError::InvalidArgument)
+    );
+    assert_eq!(
+        encoder.write_mapping_table(1, 0, &[1]),
+        Err(jpegexp_rs::JpeglsError::InvalidArgument)
+    );
+    assert_eq!(
+        encoder.write_mapping_table(1, 2, &[1, 2, 3]),
+        Err(jpegexp_rs::JpeglsError::InvalidArgumentSize)
+    );
+    assert_eq!(
+        encoder.write_mapping_table(1, 1, &[]),
+        Err(jpegexp_rs::JpeglsError::InvalidArgumentSize)
+    );
+    assert_eq!(
+        encoder.set_mapping_table_id(255, 1),
+        Err(jpegexp_rs::JpeglsError::InvalidArgument)
+    );
+    assert_eq!(
+        encoder.set_mapping_table_id(0, 256),
+        Err(jpegexp_rs::JpeglsError::InvalidArgument)
+    );
+}
+
+/// Returns the offset of the first `0xFF, marker` pair in `encoded`.
+fn find_marker(encoded: &[u8], marker: u8) -> Option<usize> {
+    encoded.windows(2).position(|pair| pair == [0xFF, marker])
+}
+
+#[test]
+fn define_number_of_lines_round_trip() {
+    let frame_info = FrameInfo {
+        width: 12,
+        height: 9,
+        bits_per_sample: 8,
+        component_count: 3,
+    };
+    let source = to_bytes(&test_pattern(12, 9, 3, 255), 8);
+    for interleave_mode in [
+        InterleaveMode::None,
+        InterleaveMode::Line,
+        InterleaveMode::Sample,
+    ] {
+        let encoded = JpeglsEncoderBuilder::new(frame_info)
+            .interleave_mode(interleave_mode)
+            .define_number_of_lines(true)
+            .encode_to_vec(&source)
+            .unwrap();
+
+        // SOF55 declares 0 lines; the DNL segment after the first scan holds the height.
+        let sof = find_marker(&encoded, 0xF7).unwrap();
+        assert_eq!(&encoded[sof + 5..sof + 7], &[0, 0]);
+        let dnl = find_marker(&encoded, 0xDC).unwrap();
+        assert_eq!(&encoded[dnl + 2..dnl + 6], &[0, 4, 0, 9]);
+
+        let mut decoder = JpeglsDecoder::new(&encoded);
+        decoder.read_header().unwrap();
+        assert_eq!(decoder.frame_info(), frame_info);
+        let mut decoded = vec![0u8; source.len()];
+        decoder.decode(&mut decoded).unwrap();
+        assert_eq!(decoded, source, "{interleave_mode:?}");
+
+        let mut transcoded = vec![0u8; encoded.len() + 256];
+        let length = JpeglsTranscoder::new(&encoded)
+            .transcode(&mut transcoded)
+            .unwrap();
+        let mut decoder = JpeglsDecoder::new(&transcoded[..length]);
+        decoder.read_header().unwrap();
+        decoder.decode(&mut decoded).unwrap();
+        assert_eq!(decoded, source);
+    }
+}
+
+#[test]
+fn define_number_of_lines_errors() {
+    let frame_info = FrameInfo {
+        width: 8,
+        height: 4,
+        bits_per_sample: 8,
+        component_count: 1,
+    };
+    let source = [3u8; 32];
+    let mut encoded = JpeglsEncoderBuilder::new(frame_info)
+        .define_number_of_lines(true)
+        .encode_to_vec(&source)
+        .unwrap();
+
+    // A height of 0 without a DNL segment leaves the image size undefined.
+    let dnl = find_marker(&encoded, 0xDC).unwrap();
+    let without_dnl: Vec<u8> = [&encoded[..dnl], &encoded[dnl + 6..]].concat();
+    let mut decoder = JpeglsDecoder::new(&without_dnl);
+    assert_eq!(
+        decoder.read_header(),
+        Err(jpegexp_rs::JpeglsError::DefineNumberOfLinesMarkerNotFound)
+    );
+
+    // DNL must define at least one line.
+    encoded[dnl + 5] = 0;
+    let mut decoder = JpeglsDecoder::new(&encoded);
+    assert_eq!(
+        decoder.read_header(),
+        Err(jpegexp_rs::JpeglsError::InvalidParameterHeight)
+    );
+
+    // A frame header with a height may not be followed by DNL.
+    let frame_info = FrameInfo {
+        component_count: 3,
+        ..frame_info
+    };
+    let encoded = JpeglsEncoderBuilder::new(frame_info)
+        .encode_to_vec(&[3u8; 96])
+        .unwrap();
+    let first_scan = find_marker(&encoded, 0xDA).unwrap();
+    let second_scan = first_scan + 2 + find_marker(&encoded[first_scan + 2..], 0xDA).unwrap();
+    let with_dnl = [
+        &encoded[..second_scan],
+        &[0xFF, 0xDC, 0, 4, 0, 4],
+        &encoded[second_scan..],
+    ]
+    .concat();
+    let mut decoder = JpeglsDecoder::new(&with_dnl);
+    decoder.read_header().unwrap();
+    let mut decoded = [0u8; 96];
+    assert_eq!(
+        decoder.decode(&mut decoded),
+        Err(jpegexp_rs::JpeglsError::UnexpectedDefineNumberOfLinesMarker)
+    );
+}
+
+/// Returns the entropy coded data of the only scan of `encoded`, restart markers included.
+fn scan_data(encoded: &[u8]) -> &[u8] {
+    let sos = find_marker(encoded, 0xDA).unwrap();
+    let length = u16::from_be_bytes([encoded[sos + 2], encoded[sos + 3]]) as usize;
+    &encoded[sos + 2 + length..encoded.len() - 2]
+}
+
+#[test]
+fn restart_interval_round_trip() {
+    let frame_info = FrameInfo {
+        width: 13,
+        height: 20,
+        bits_per_sample: 12,
+        component_count: 3,
+    };
+    let source = to_bytes(&test_pattern(13, 20, 3, 4095), 12);
+    for interleave_mode in [
+        InterleaveMode::None,
+        InterleaveMode::Line,
+        InterleaveMode::Sample,
+    ] {
+        for restart_interval in [1u16, 3, 20, 1000] {
+            let encoded = JpeglsEncoderBuilder::new(frame_info)
+                .interleave_mode(interleave_mode)
+                .restart_interval(restart_interval)
+                .encode_to_vec(&source)
+                .unwrap();
+
+            let dri = find_marker(&encoded, 0xDD).unwrap();
+            assert_eq!(
+                &encoded[dri + 2..dri + 6],
+                &[0, 4, (restart_interval >> 8) as u8, restart_interval as u8]
+            );
+            // Every scan numbers its restart markers RST0-RST7 from 0, modulo 8.
+            let scans = if interleave_mode == InterleaveMode::None {
+                3
+            } else {
+                1
+            };
+            let markers: Vec<u8> = encoded
+                .windows(2)
+                .filter(|pair| pair[0] == 0xFF && (0xD0..=0xD7).contains(&pair[1]))
+                .map(|pair| pair[1])
+                .collect();
+            let per_scan = (20 - 1) / restart_interval as usize;
+            assert_eq!(markers.len(), scans * per_scan, "{interleave_mode:?}");
+            for (index, &marker) in markers.iter().enumerate() {
+                assert_eq!(marker, 0xD0 + (index % per_scan % 8) as u8);
+            }
+
+            let mut decoder = JpeglsDecoder::new(&encoded);
+            decoder.read_header().unwrap();
+            let mut decoded = vec![0u8; source.len()];
+            decoder.decode(&mut decoded).unwrap();
+            assert_eq!(decoded, source, "{interleave_mode:?} {restart_interval}");
+
+            let transcoded = transcode(&encoded, InterleaveMode::Sample);
+            assert!(find_marker(&transcoded, 0xDD).is_some());
+            let mut decoder = JpeglsDecoder::new(&transcoded);
+            decoder.read_header().unwrap();
+            decoder.decode(&mut decoded).unwrap();
+            assert_eq!(decoded, source);
+        }
+    }
+}
+
+#[test]
+fn restart_intervals_are_coded_independently() {
+    // Each restart interval is coded exactly like an image of its lines on its own, so
+    // horizontal stripes can be encoded separately and joined with RSTm markers.
+    let frame_info = FrameInfo {
+        width: 10,
+        height: 12,
+        bits_per_sample: 8,
+        component_count: 3,
+    };
+    let source = to_bytes(&test_pattern(10, 12, 3, 255), 8);
+    let stripe_info = FrameInfo {
+        height: 4,
+        ..frame_info
+    };
+    for interleave_mode in [InterleaveMode::Line, InterleaveMode::Sample] {
+        let encoded = JpeglsEncoderBuilder::new(frame_info)
+            .interleave_mode(interleave_mode)
+            .restart_interval(4)
+            .encode_to_vec(&source)
+            .unwrap();
+
+        let mut joined = Vec::new();
+        for (index, stripe) in source.chunks(source.len() / 3).enumerate() {
+            if index > 0 {
+                joined.extend_from_slice(&[0xFF, 0xD0 + index as u8 - 1]);
+            }
+            let stripe_encoded = JpeglsEncoderBuilder::new(stripe_info)
+                .interleave_mode(interleave_mode)
+                .encode_to_vec(stripe)
+                .unwrap();
+            joined.extend_from_slice(scan_data(&stripe_encoded));
+        }
+        assert_eq!(scan_data(&encoded), &joined[..], "{interleave_mode:?}");
+    }
+}
+
+#[test]
+fn parallel_stripes_match_single_threaded_encoding() {
+    for (component_count, interleave_mode, bits_per_sample) in [
+        (1, InterleaveMode::None, 16),
+        (3, InterleaveMode::None, 8),
+        (3, InterleaveMode::Line, 12),
+        (4, InterleaveMode::Sample, 8),
+    ] {
+        let frame_info = FrameInfo {
+            width: 21,
+            height: 50,
+            bits_per_sample,
+            component_count,
+        };
+        let max_value = (1u32 << bits_per_sample) - 1;
+        let source = to_bytes(
+            &test_pattern(21, 50, component_count as usize, max_value),
+            bits_per_sample,
+        );
+        for restart_interval in [1u16, 7, 50] {
+            let builder = JpeglsEncoderBuilder::new(frame_info)
+                .interleave_mode(interleave_mode)
+                .restart_interval(restart_interval);
+            let expected = builder.encode_to_vec(&source).unwrap();
+            for thread_count in [0, 2, 3, 64] {
+                let encoded = builder
+                    .thread_count(thread_count)
+                    .encode_to_vec(&source)
+                    .unwrap();
+                assert_eq!(
+                    encoded, expected,
+                    "{interleave_mode:?} {restart_interval} {thread_count}"
+                );
+            }
+        }
+
+        // Without a restart interval, the scan is encoded on one thread and no restart
+        // markers are written.
+        let builder = JpeglsEncoderBuilder::new(frame_info)
+            .interleave_mode(interleave_mode)
+            .thread_count(4);
+        let encoded = builder.encode_to_vec(&source).unwrap();
+        assert_eq!(find_marker(&encoded, 0xDD), None);
+
+        // Unless every thread is asked to encode one stripe.
+        let encoded = builder
+            .auto_restart_interval(true)
+            .encode_to_vec(&source)
+            .unwrap();
+        let dri = find_marker(&encoded, 0xDD).unwrap();
+        assert_eq!(&encoded[dri + 2..dri + 6], &[0, 4, 0, 13]);
+        let mut decoder = JpeglsDecoder::new(&encoded);
+        decoder.read_header().unwrap();
+        let mut decoded = vec![0u8; source.len()];
+        decoder.decode(&mut decoded).unwrap();
+        assert_eq!(decoded, source, "{interleave_mode:?}");
+    }
+}
+
+#[test]
+fn parallel_stripes_report_destination_too_small() {
+    let frame_info = FrameInfo {
+        width: 64,
+        height: 64,
+        bits_per_sample: 8,
+        component_count: 1,
+    };
+    let source = to_bytes(&test_pattern(64, 64, 1, 255), 8);
+    let builder = JpeglsEncoderBuilder::new(frame_info)
+        .restart_interval(8)
+        .thread_count(4);
+    let length = builder.encode_to_vec(&source).unwrap().len();
+    let mut destination = vec![0u8; length - 1];
+    let error = builder
+        .build(&mut destination)
+        .unwrap()
+        .encode(&source)
+        .unwrap_err();
+    assert!(
+        matches!(error, jpegexp_rs::JpeglsError::DestinationTooSmall { needed } if needed >= length),
+        "{error:?}"
+    );
+}
+
+#[test]
+fn missing_restart_marker_is_reported() {
+    let frame_info = FrameInfo {
+        width: 8,
+        height: 6,
+        bits_per_sample: 8,
+        component_count: 1,
+    };
+    let source = to_bytes(&test_pattern(8, 6, 1, 255), 8);
+    let encoded = JpeglsEncoderBuilder::new(frame_info)
+        .restart_interval(2)
+        .encode_to_vec(&source)
+        .unwrap();
+    let mut decoded = vec![0u8; source.len()];
+
+    // Fill bytes in front of a restart marker are skipped.
+    let rst1 = find_marker(&encoded, 0xD1).unwrap();
+    let filled = [&encoded[..rst1], &[0xFF, 0xFF], &encoded[rst1..]].concat();
+    let mut decoder = JpeglsDecoder::new(&filled);
+    decoder.read_header().unwrap();
+    decoder.decode(&mut decoded).unwrap();
+    assert_eq!(decoded, source);
+
+    // RST1 where RST0 is expected.
+    let rst0 = find_marker(&encoded, 0xD0).unwrap();
+    let mut renumbered = encoded.clone();
+    renumbered[rst0 + 1] = 0xD1;
+    let mut decoder = JpeglsDecoder::new(&renumbered);
+    decoder.read_header().unwrap();
+    assert_eq!(
+        decoder.decode(&mut decoded),
+        Err(jpegexp_rs::JpeglsError::RestartMarkerNotFound)
+    );
+
+    // Without the DRI segment, the scan ends at the first restart marker.
+    let dri = find_marker(&encoded, 0xDD).unwrap();
+    let without_dri = [&encoded[..dri], &encoded[dri + 6..]].concat();
+    let mut decoder = JpeglsDecoder::new(&without_dri);
+    decoder.read_header().unwrap();
+    assert!(decoder.decode(&mut decoded).is_err());
+}
+
+#[test]
+fn owned_decoder_decodes_on_another_thread() {
+    let frame_info = FrameInfo {
+        width: 10,
+        height: 6,
+        bits_per_sample: 12,
+        component_count: 3,
+    };
+    let source = to_bytes(&test_pattern(10, 6, 3, 4095), 12);
+    let encoded = JpeglsEncoderBuilder::new(frame_info)
+        .interleave_mode(InterleaveMode::Line)
+        .encode_to_vec(&source)
+        .unwrap();
+
+    let mut decoder = JpeglsDecoder::from_vec(encoded);
+    let size = source.len();
+    let decoded = std::thread::spawn(move || {
+        decoder.read_header().unwrap();
+        let mut decoded = vec![0u8; size];
+        decoder.decode(&mut decoded).unwrap();
+        decoded
+    })
+    .join()
+    .unwrap();
+    assert_eq!(decoded, source);
+}
+
+/// Copies packed rows into a buffer whose rows are `stride` bytes apart, filling the padding
+/// with `fill`. The last row is not padded.
+fn pad_rows(packed: &[u8], row_bytes: usize, stride: usize, fill: u8) -> Vec<u8> {
+    let height = packed.len() / row_bytes;
+    let mut padded = vec![fill; (height - 1) * stride + row_bytes];
+    for (y, row) in packed.chunks_exact(row_bytes).enumerate() {
+        padded[y * stride..y * stride + row_bytes].copy_from_slice(row);
+    }
+    padded
+}
+
+#[test]
+fn stride_round_trip() {
+    let (width, height) = (13usize, 7usize);
+    for bits_per_sample in [8, 16] {
+        let bytes_per_sample = if bits_per_sample <= 8 { 1 } else { 2 };
+        for (component_count, transformation) in [
+            (1, ColorTransformation::None),
+            (3, ColorTransformation::None),
+            (3, ColorTransformation::Hp2),
+        ] {
+            for interleave_mode in [
+                InterleaveMode::None,
+                InterleaveMode::Line,
+                InterleaveMode::Sample,
+            ] {
+                let max_value = (1u32 << bits_per_sample) - 1;
+                let samples = test_pattern(width, height, component_count, max_value);
+                let packed = to_bytes(&samples, bits_per_sample);
+                let row_bytes = width * component_count * bytes_per_sample;
+                let stride = row_bytes + 6;
+                let padded = pad_rows(&packed, row_bytes, stride, 0xAB);
+                let frame_info = FrameInfo {
+                    width: width as u32,
+                    height: height as u32,
+                    bits_per_sample,
+                    component_count: component_count as i32,
+                };
+
+                let expected = encode_with_transformation(
+                    &packed,
+                    frame_info,
+                    interleave_mode,
+                    transformation,
+                );
+                let mut encoded = vec![0u8; expected.len() + 1024];
+                let mut encoder = JpeglsEncoder::new(&mut encoded);
+                encoder.set_frame_info(frame_info).unwrap();
+                encoder.set_interleave_mode(interleave_mode).unwrap();
+                encoder.set_color_transformation(transformation).unwrap();
+                let length = encoder.encode_with_stride(&padded, stride).unwrap();
+                assert_eq!(
+                    &encoded[..length],
+                    &expected[..],
+                    "{} components {:?} {:?} @ {} bits",
+                    component_count,
+                    interleave_mode,
+                    transformation,
+                    bits_per_sample
+                );
+
+                let mut decoder = JpeglsDecoder::new(&encoded[..length]);
+                decoder.read_header().unwrap();
+                let mut decoded = vec![0xCDu8; height * stride];
+                decoder.decode_with_stride(&mut decoded, stride).unwrap();
+                let mut expected_rows = pad_rows(&packed, row_bytes, stride, 0xCD);
+                expected_rows.resize(height * stride, 0xCD);
+                assert_eq!(decoded, expected_rows);
+            }
+        }
+    }
+}
+
+#[test]
+fn invalid_stride_is_rejected() {
+    let frame_info = FrameInfo {
+        width: 5,
+        height: 3,
+        bits_per_sample: 16,
+        component_count: 1,
+    };
+    let source = vec![0u8; 128];
+    let mut destination = vec![0u8; 256];
+    let mut encoder = JpeglsEncoder::new(&mut destination);
+    encoder.set_frame_info(frame_info).unwrap();
+    assert_eq!(
+        encoder.encode_with_stride(&source, 9),
+        Err(jpegexp_rs::JpeglsError::InvalidArgumentStride)
+    );
+    assert_eq!(
+        encoder.encode_with_stride(&source, 13),
+        Err(jpegexp_rs::JpeglsError::InvalidArgumentStride)
+    );
+    assert_eq!(
+        encoder.encode_with_stride(&source[..9 + 2 * 32], 32),
+        Err(jpegexp_rs::JpeglsError::InvalidArgumentSize)
+    );
+
+    let encoded = encode(&source[..30], frame_info, InterleaveMode::None);
+    let mut decoder = JpeglsDecoder::new(&encoded);
+    decoder.read_header().unwrap();
+    let mut decoded = vec![0u8; 2 * 32 + 10];
+    assert_eq!(
+        decoder.decode_with_stride(&mut decoded, 8),
+        Err(jpegexp_rs::JpeglsError::InvalidArgumentStride)
+    );
+    assert_eq!(
+        decoder.decode_with_stride(&mut decoded[..2 * 32 + 9], 32),
+        Err(jpegexp_rs::JpeglsError::DestinationTooSmall {
+            needed: 2 * 32 + 10
+        })
+    );
+    decoder.decode_with_stride(&mut decoded, 32).unwrap();
+}
+
+#[test]
+fn planar_output_layout_round_trip() {
+    let (width, height) = (11usize, 6usize);
+    for bits_per_sample in [8, 12, 16] {
+        let bytes_per_sample = if bits_per_sample <= 8 { 1 } else { 2 };
+        let transformations: &[ColorTransformation] = if bits_per_sample == 12 {
+            &[ColorTransformation::None]
+        } else {
+            &[ColorTransformation::None, ColorTransformation::Hp3]
+        };
+        for &transformation in transformations {
+            for interleave_mode in [
+                InterleaveMode::None,
+                InterleaveMode::Line,
+                InterleaveMode::Sample,
+            ] {
+                let max_value = (1u32 << bits_per_sample) - 1;
+                let samples = test_pattern(width, height, 3, max_value);
+                let frame_info = FrameInfo {
+                    width: width as u32,
+                    height: height as u32,
+                    bits_per_sample,
+                    component_count: 3,
+                };
+                let encoded = encode_with_transformation(
+                    &to_bytes(&samples, bits_per_sample),
+                    frame_info,
+                    interleave_mode,
+                    transformation,
+                );
+
+                let mut planes = Vec::with_capacity(samples.len());
+                for c in 0..3 {
+                    planes.extend(samples.iter().skip(c).step_by(3));
+                }
+                let row_bytes = width * bytes_per_sample;
+                let stride = row_bytes + 4;
+                let mut expected =
+                    pad_rows(&to_bytes(&planes, bits_per_sample), row_bytes, stride, 0);
+                expected.resize(3 * height * stride, 0);
+
+                let mut decoder = JpeglsDecoder::new(&encoded);
+                decoder.read_header().unwrap();
+                decoder.set_output_layout(OutputLayout::Planar);
+                let mut decoded = vec![0u8; expected.len()];
+                decoder.decode_with_stride(&mut decoded, stride).unwrap();
+                assert_eq!(
+                    decoded, expected,
+                    "{:?} {:?} @ {} bits",
+                    interleave_mode, transformation, bits_per_sample
+                );
+
+                let mut decoder = JpeglsDecoder::new(&encoded);
+                decoder.read_header().unwrap();
+                decoder.set_output_layout(OutputLayout::Planar);
+                let mut decoded = vec![0u8; samples.len() * bytes_per_sample];
+                decoder.decode(&mut decoded).unwrap();
+                assert_eq!(decoded, to_bytes(&planes, bits_per_sample));
+            }
+        }
+    }
+}
+
+#[test]
+fn planar_output_layout_reports_destination_too_small() {
+    let frame_info = FrameInfo {
+        width: 4,
+        height: 4,
+        bits_per_sample: 8,
+        component_count: 3,
+    };
+    let encoded = encode(&[7u8; 48], frame_info, InterleaveMode::Sample);
+    let mut decoder = JpeglsDecoder::new(&encoded);
+    decoder.read_header().unwrap();
+    decoder.set_output_layout(OutputLayout::Planar);
+    let mut decoded = vec![0u8; 11 * 8 + 3];
+    assert_eq!(
+        decoder.decode_with_stride(&mut decoded, 8),
+        Err(jpegexp_rs::JpeglsError::DestinationTooSmall { needed: 11 * 8 + 4 })
+    );
+    let mut decoded = vec![0u8; 11 * 8 + 4];
+    decoder.decode_with_stride(&mut decoded, 8).unwrap();
+    assert!(decoded.chunks(8).all(|row| row[..4] == [7; 4]));
+}
+
+/// Decodes `encoded` with `decode_row_batches`, returning the joined rows and the first row
+/// and row count of every batch.
+fn decode_in_batches(
+    encoded: &[u8],
+    rows: u32,
+    tolerant: bool,
+) -> (Vec<u8>, Vec<(u32, usize)>, JpeglsDecoder<'_>) {
+    let mut decoder = JpeglsDecoder::new(encoded);
+    decoder.read_header().unwrap();
+    decoder.set_tolerant(tolerant);
+    let row_bytes = decoder
+        .frame_info()
+        .decoded_size(OutputLayout::Interleaved)
+        .unwrap()
+        / decoder.frame_info().height as usize;
+    let mut joined = Vec::new();
+    let mut batches = Vec::new();
+    decoder
+        .decode_row_batches(rows, |first_row, pixels| {
+            batches.push((first_row, pixels.len() / row_bytes));
+            joined.extend_from_slice(pixels);
+            Ok(())
+        })
+        .unwrap();
+    (joined, batches, decoder)
+}
+
+#[test]
+fn decode_row_batches_matches_decode() {
+    for bits_per_sample in [8, 12] {
+        let frame_info = FrameInfo {
+            width: 13,
+            height: 11,
+            bits_per_sample,
+            component_count: 3,
+        };
+        let max_value = (1u32 << bits_per_sample) - 1;
+        let source = to_bytes(&test_pattern(13, 11, 3, max_value), bits_per_sample);
+        for interleave_mode in [
+            InterleaveMode::None,
+            InterleaveMode::Line,
+            InterleaveMode::Sample,
+        ] {
+            // The color transformations are defined for 8 and 16-bit samples.
+            let transformations = match bits_per_sample {
+                8 => &[ColorTransformation::None, ColorTransformation::Hp2][..],
+                _ => &[ColorTransformation::None][..],
+            };
+            for &transformation in transformations {
+                let encoded = encode_with_transformation(
+                    &source,
+                    frame_info,
+                    interleave_mode,
+                    transformation,
+                );
+                let (joined, batches, decoder) = decode_in_batches(&encoded, 4, false);
+                assert_eq!(joined, source, "{interleave_mode:?} {transformation:?}");
+                assert_eq!(batches, [(0, 4), (4, 4), (8, 3)]);
+                assert_eq!(decoder.truncation(), None);
+            }
+        }
+    }
+}
+
+#[test]
+fn decode_row_batches_stops_on_handler_error() {
+    let frame_info = FrameInfo {
+        width: 13,
+        height: 11,
+        bits_per_sample: 8,
+        component_count: 1,
+    };
+    let source = to_bytes(&test_pattern(13, 11, 1, 255), 8);
+    let encoded = encode(&source, frame_info, InterleaveMode::None);
+    let mut decoder = JpeglsDecoder::new(&encoded);
+    decoder.read_header().unwrap();
+    let mut calls = 0;
+    let result = decoder.decode_row_batches(4, |_, _| {
+        calls += 1;
+        Err(jpegexp_rs::JpeglsError::InvalidOperation)
+    });
+    assert_eq!(result, Err(jpegexp_rs::JpeglsError::InvalidOperation));
+    assert_eq!(calls, 1);
+}
+
+#[test]
+fn decode_row_batches_of_truncated_stream() {
+    let frame_info = FrameInfo {
+        width: 13,
+        height: 20,
+        bits_per_sample: 8,
+        component_count: 3,
+    };
+    let source = to_bytes(&test_pattern(13, 20, 3, 255), 8);
+    for interleave_mode in [InterleaveMode::None, InterleaveMode::Sample] {
+        let encoded = encode(&source, frame_info, interleave_mode);
+        let cut = &encoded[..encoded.len() / 2];
+
+        let mut decoder = JpeglsDecoder::new(cut);
+        decoder.read_header().unwrap();
+        decoder.set_tolerant(true);
+        let mut expected = vec![0u8; source.len()];
+        decoder.decode(&mut expected).unwrap();
+
+        let (joined, batches, batch_decoder) = decode_in_batches(cut, 6, true);
+        assert_eq!(joined, expected, "{interleave_mode:?}");
+        assert_eq!(batches, [(0, 6), (6, 6), (12, 6), (18, 2)]);
+        assert_eq!(batch_decoder.truncation(), decoder.truncation());
+        assert!(batch_decoder.truncation().is_some());
+    }
+}
@@ -0,0 +1,87 @@
+use jpegexp_rs::jpegls::{JpeglsDecoder, JpeglsEncoder};
+use jpegexp_rs::{DecodeStats, EncodeStats, FrameInfo};
+
+fn gradient(width: u32, height: u32, components: u32) -> Vec<u8> {
+    (0..width * height * components)
+        .map(|i| (i % 251) as u8)
+        .collect()
+}
+
+fn jpegls_round_trip(width: u32, height: u32) -> (EncodeStats, DecodeStats) {
+    let frame_info = FrameInfo {
+        width,
+        height,
+        bits_per_sample: 8,
+        component_count: 1,
+    };
+    let pixels = gradient(width, height, 1);
+    let mut encoded = vec![0u8; pixels.len() * 2 + 1024];
+    let mut encoder = JpeglsEncoder::new(&mut encoded);
+    encoder.set_frame_info(frame_info).unwrap();
+    let len = encoder.encode(&pixels).unwrap();
+    let encode_stats = encoder.stats();
+
+    let mut decoder = JpeglsDecoder::new(&encoded[..len]);
+    decoder.read_header().unwrap();
+    let mut decoded = vec![0u8; pixels.len()];
+    decoder.decode(&mut decoded).unwrap();
+    assert_eq!(decoded, pixels);
+    (encode_stats, decoder.stats())
+}
+
+#[test]
+fn stats_report_pixel_count() {
+    let (encode_stats, decode_stats) = jpegls_round_trip(40, 30);
+    assert_eq!(encode_stats.pixel_count, 1200);
+    assert_eq!(decode_stats.pixel_count, 1200);
+}
+
+#[cfg(not(feature = "mem-profiling"))]
+#[test]
+fn stats_are_zero_without_feature() {
+    let (encode_stats, decode_stats) = jpegls_round_trip(40, 30);
+    assert_eq!(encode_stats.peak_buffer_bytes, 0);
+    assert_eq!(decode_stats.peak_buffer_bytes, 0);
+    assert_eq!(decode_stats.bytes_per_megapixel(), 0.0);
+}
+
+#[cfg(feature = "mem-profiling")]
+#[test]
+fn jpegls_peak_scales_with_line_width() {
+    let (narrow_encode, narrow_decode) = jpegls_round_trip(64, 64);
+    let (wide_encode, wide_decode) = jpegls_round_trip(1024, 64);
+    assert!(narrow_encode.peak_buffer_bytes > 0);
+    assert!(narrow_decode.peak_buffer_bytes > 0);
+    // Only line buffers depend on the width; contexts are a fixed cost.
+    assert!(wide_encode.peak_buffer_bytes > narrow_encode.peak_buffer_bytes);
+    assert!(wide_decode.peak_buffer_bytes > narrow_decode.peak_buffer_bytes);
+    assert!(wide_decode.bytes_per_megapixel() < narrow_decode.bytes_per_megapixel());
+}
+
+#[cfg(feature = "mem-profiling")]
+#[test]
+fn jpeg1_decode_accounts_for_coefficient_planes() {
+    use jpegexp_rs::jpeg1::{Jpeg1Decoder, Jpeg1Encoder};
+
+    let (width, height) = (64u32, 48u32);
+    let frame_info = FrameInfo {
+        width,
+        height,
+        bits_per_sample: 8,
+        component_count: 3,
+    };
+    let pixels = gradient(width, height, 3);
+    let mut encoded = vec![0u8; 65536];
+    let mut encoder = Jpeg1Encoder::new();
+    let len = encoder.encode(&pixels, &frame_info, &mut encoded).unwrap();
+    assert_eq!(encoder.stats().peak_buffer_bytes, 0);
+
+    let mut decoder = Jpeg1Decoder::new(&encoded[..len]);
+    decoder.read_header().unwrap();
+    let mut decoded = vec![0u8; pixels.len()];
+    decoder.decode(&mut decoded).unwrap();
+
+    // Every component keeps an i16 coefficient plane and an f32 sample plane.
+    let plane = (width * height) as usize;
+    assert!(decoder.stats().peak_buffer_bytes >= 3 * plane * (2 + 4));
+}